@@ -24,6 +24,10 @@ extern "C" CFeatureLibrary CFeatureLibraryGet() {
   return reinterpret_cast<CFeatureLibrary>(feature::PlatformFeatures::Get());
 }
 
+extern "C" void CFeatureLibraryShutdownForTesting() {
+  feature::PlatformFeatures::ShutdownForTesting();
+}
+
 extern "C" int CFeatureLibraryIsEnabledBlocking(
     CFeatureLibrary handle, const struct VariationsFeature* const feature) {
   auto* library = reinterpret_cast<feature::PlatformFeaturesInterface*>(handle);
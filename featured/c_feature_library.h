@@ -61,6 +61,10 @@ bool FEATURE_EXPORT CFeatureLibraryInitialize();
 // C wrapper for PlatformFeatures::Get()
 CFeatureLibrary FEATURE_EXPORT CFeatureLibraryGet();
 
+// C wrapper for PlatformFeatures::ShutdownForTesting()
+// Invalidates all handles returned by CFeatureLibraryGet().
+void FEATURE_EXPORT CFeatureLibraryShutdownForTesting();
+
 // C wrapper for PlatformFeatures::IsEnabled is NOT defined, since different
 // language thread runtimes will likely be incompatible with C++'s
 // SequencedTaskRunner.
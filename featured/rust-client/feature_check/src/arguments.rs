@@ -0,0 +1,186 @@
+// Copyright 2024 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+/// Errors from parsing the command line.
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("getopts error: {0}")]
+    GetOpts(#[from] getopts::Fail),
+    #[error("at least one --feature is required")]
+    MissingFeature,
+    #[error("--param requires exactly one --feature and cannot be used with --json")]
+    InvalidParam,
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Args {
+    /// Names of the features to check.
+    pub features: Vec<String>,
+    /// The default state used when featured does not know a feature.
+    pub default_enabled: bool,
+    /// The parameter to print if the feature is enabled.
+    pub param: Option<String>,
+    /// A JSON file of feature states to use instead of featured.
+    pub fake: Option<PathBuf>,
+    /// Dump the states and parameters of all the features as JSON.
+    pub json: bool,
+}
+
+impl Args {
+    /// Parses the command line. Returns `None` if help was requested.
+    pub fn parse<T: AsRef<str>>(args: &[T]) -> Result<Option<Self>> {
+        let program_name = args.first().map(|s| s.as_ref()).unwrap_or("feature_check");
+
+        let mut opts = getopts::Options::new();
+        opts.optmulti("f", "feature", "Name of the feature to check", "NAME")
+            .optflag(
+                "",
+                "default-enabled",
+                "Treat the features as enabled by default",
+            )
+            .optopt(
+                "p",
+                "param",
+                "Print the value of the parameter if the feature is enabled",
+                "KEY",
+            )
+            .optopt(
+                "",
+                "fake",
+                "Read the feature states from a JSON file instead of featured",
+                "PATH",
+            )
+            .optflag(
+                "",
+                "json",
+                "Print the states and parameters of the features as JSON",
+            )
+            .optflag("h", "help", "Print help message");
+
+        let matches = match opts.parse(args.iter().skip(1).map(|s| s.as_ref())) {
+            Ok(m) => m,
+            Err(e) => {
+                show_usage(program_name, &opts);
+                return Err(Error::GetOpts(e));
+            }
+        };
+        if matches.opt_present("h") {
+            show_usage(program_name, &opts);
+            return Ok(None);
+        }
+
+        let features = matches.opt_strs("feature");
+        if features.is_empty() {
+            return Err(Error::MissingFeature);
+        }
+        let json = matches.opt_present("json");
+        let param = matches.opt_str("param");
+        if param.is_some() && (features.len() != 1 || json) {
+            return Err(Error::InvalidParam);
+        }
+
+        Ok(Some(Args {
+            features,
+            default_enabled: matches.opt_present("default-enabled"),
+            param,
+            fake: matches.opt_str("fake").map(PathBuf::from),
+            json,
+        }))
+    }
+}
+
+fn show_usage(program_name: &str, opts: &getopts::Options) {
+    let brief = format!(
+        "Usage: {} --feature NAME [args]\n\n\
+         Exits with 0 if all the features are enabled, 1 if any of them is disabled and 2 on \
+         errors.",
+        program_name
+    );
+    eprint!("{}", opts.usage(&brief));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn feature() {
+        let args = Args::parse(&["feature_check", "--feature", "CrOSLateBootA"])
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            args,
+            Args {
+                features: vec!["CrOSLateBootA".to_string()],
+                default_enabled: false,
+                param: None,
+                fake: None,
+                json: false,
+            }
+        );
+
+        let args = Args::parse(&["feature_check", "-f", "CrOSLateBootA", "--feature=B"])
+            .unwrap()
+            .unwrap();
+        assert_eq!(args.features, vec!["CrOSLateBootA", "B"]);
+
+        assert!(matches!(
+            Args::parse(&["feature_check"]),
+            Err(Error::MissingFeature)
+        ));
+        assert!(matches!(
+            Args::parse(&["feature_check", "--feature"]),
+            Err(Error::GetOpts(_))
+        ));
+    }
+
+    #[test]
+    fn options() {
+        let args = Args::parse(&[
+            "feature_check",
+            "--feature",
+            "A",
+            "--default-enabled",
+            "--param",
+            "key",
+            "--fake",
+            "/tmp/enabled_features.json",
+        ])
+        .unwrap()
+        .unwrap();
+        assert!(args.default_enabled);
+        assert_eq!(args.param, Some("key".to_string()));
+        assert_eq!(args.fake, Some(PathBuf::from("/tmp/enabled_features.json")));
+        assert!(!args.json);
+
+        let args = Args::parse(&["feature_check", "-f", "A", "-f", "B", "--json"])
+            .unwrap()
+            .unwrap();
+        assert!(args.json);
+    }
+
+    #[test]
+    fn param_needs_single_feature() {
+        assert!(matches!(
+            Args::parse(&["feature_check", "-f", "A", "-f", "B", "--param", "key"]),
+            Err(Error::InvalidParam)
+        ));
+        assert!(matches!(
+            Args::parse(&["feature_check", "-f", "A", "--json", "--param", "key"]),
+            Err(Error::InvalidParam)
+        ));
+    }
+
+    #[test]
+    fn help() {
+        assert!(Args::parse(&["feature_check", "-h"]).unwrap().is_none());
+        assert!(Args::parse(&["feature_check", "--help"]).unwrap().is_none());
+    }
+}
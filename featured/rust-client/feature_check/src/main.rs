@@ -0,0 +1,236 @@
+// Copyright 2024 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Command-line utility for checking platform features from scripts and tests.
+//!
+//! Exits with 0 if all the requested features are enabled and with 1 if any of them is
+//! disabled. Any error, including invalid arguments, exits with 2.
+
+mod arguments;
+
+use std::collections::BTreeMap;
+use std::io;
+use std::io::Write;
+use std::process::exit;
+
+use featured::{
+    CheckFeature, FakeLoadError, FakePlatformFeatures, Feature, FeatureError, PlatformError,
+    PlatformFeatures,
+};
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::arguments::Args;
+
+const EXIT_ENABLED: i32 = 0;
+const EXIT_DISABLED: i32 = 1;
+const EXIT_ERROR: i32 = 2;
+
+#[derive(Error, Debug)]
+enum Error {
+    #[error("{0}")]
+    Arguments(#[from] arguments::Error),
+    #[error("{0}")]
+    Feature(#[from] FeatureError),
+    #[error("{0}")]
+    Platform(#[from] PlatformError),
+    #[error("{0}")]
+    FakeLoad(#[from] FakeLoadError),
+    #[error("failed to write output: {0}")]
+    Output(#[from] io::Error),
+    #[error("failed to serialize output: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// The state of a feature in the `--json` output. This is the same format as the `--fake`
+/// input so that a dump can be replayed.
+#[derive(Serialize)]
+struct FeatureState<'a> {
+    enabled: bool,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    params: BTreeMap<&'a str, &'a str>,
+}
+
+/// Checks the features in `args` and returns the exit code.
+fn run<C: CheckFeature>(args: &Args, client: &C, out: &mut dyn Write) -> Result<i32, Error> {
+    let features = args
+        .features
+        .iter()
+        .map(|name| Feature::new(name, args.default_enabled))
+        .collect::<Result<Vec<_>, _>>()?;
+    let feature_refs: Vec<_> = features.iter().collect();
+    let response = client.get_params_and_enabled(&feature_refs)?;
+
+    if args.json {
+        let states: BTreeMap<_, _> = features
+            .iter()
+            .map(|feature| {
+                let params = response
+                    .get_params(feature)
+                    .into_iter()
+                    .flatten()
+                    .map(|(key, value)| (key.as_str(), value.as_str()))
+                    .collect();
+                let state = FeatureState {
+                    enabled: response.is_enabled(feature),
+                    params,
+                };
+                (feature.name(), state)
+            })
+            .collect();
+        serde_json::to_writer_pretty(&mut *out, &states)?;
+        writeln!(out)?;
+    } else if let Some(key) = &args.param {
+        if let Some(value) = response.get_param(&features[0], key) {
+            writeln!(out, "{}", value)?;
+        }
+    }
+
+    if features.iter().all(|feature| response.is_enabled(feature)) {
+        Ok(EXIT_ENABLED)
+    } else {
+        Ok(EXIT_DISABLED)
+    }
+}
+
+fn check_features(args: &[String]) -> Result<i32, Error> {
+    let Some(args) = Args::parse(args)? else {
+        return Ok(EXIT_ENABLED);
+    };
+    let mut out = io::stdout().lock();
+    match &args.fake {
+        Some(path) => run(
+            &args,
+            &FakePlatformFeatures::from_json_file(path)?,
+            &mut out,
+        ),
+        None => run(&args, PlatformFeatures::get()?.as_ref(), &mut out),
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let code = check_features(&args).unwrap_or_else(|e| {
+        eprintln!("feature_check: {}", e);
+        EXIT_ERROR
+    });
+    exit(code);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FAKE_STATES: &str = r#"{
+        "CrOSLateBootEnabled": { "enabled": true, "params": { "key": "value" } },
+        "CrOSLateBootDisabled": { "enabled": false }
+    }"#;
+
+    fn check(args: &[&str]) -> (i32, String) {
+        let args = Args::parse(args).unwrap().unwrap();
+        let mut client = FakePlatformFeatures::new().unwrap();
+        client.load_json(FAKE_STATES).unwrap();
+        let mut out = Vec::new();
+        let code = run(&args, &client, &mut out).unwrap();
+        (code, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn exit_code() {
+        assert_eq!(
+            check(&["feature_check", "-f", "CrOSLateBootEnabled"]),
+            (EXIT_ENABLED, String::new())
+        );
+        assert_eq!(
+            check(&["feature_check", "-f", "CrOSLateBootDisabled"]).0,
+            EXIT_DISABLED
+        );
+        assert_eq!(
+            check(&[
+                "feature_check",
+                "-f",
+                "CrOSLateBootEnabled",
+                "-f",
+                "CrOSLateBootDisabled"
+            ])
+            .0,
+            EXIT_DISABLED
+        );
+    }
+
+    #[test]
+    fn default_state() {
+        assert_eq!(
+            check(&["feature_check", "-f", "CrOSLateBootUnknown"]).0,
+            EXIT_DISABLED
+        );
+        assert_eq!(
+            check(&[
+                "feature_check",
+                "-f",
+                "CrOSLateBootUnknown",
+                "--default-enabled"
+            ])
+            .0,
+            EXIT_ENABLED
+        );
+        // The default does not override an explicit state.
+        assert_eq!(
+            check(&[
+                "feature_check",
+                "-f",
+                "CrOSLateBootDisabled",
+                "--default-enabled"
+            ])
+            .0,
+            EXIT_DISABLED
+        );
+    }
+
+    #[test]
+    fn param() {
+        assert_eq!(
+            check(&["feature_check", "-f", "CrOSLateBootEnabled", "-p", "key"]),
+            (EXIT_ENABLED, "value\n".to_string())
+        );
+        assert_eq!(
+            check(&["feature_check", "-f", "CrOSLateBootEnabled", "-p", "other"]),
+            (EXIT_ENABLED, String::new())
+        );
+        assert_eq!(
+            check(&["feature_check", "-f", "CrOSLateBootDisabled", "-p", "key"]),
+            (EXIT_DISABLED, String::new())
+        );
+    }
+
+    #[test]
+    fn json() {
+        let (code, out) = check(&[
+            "feature_check",
+            "-f",
+            "CrOSLateBootEnabled",
+            "-f",
+            "CrOSLateBootDisabled",
+            "--json",
+        ]);
+        assert_eq!(code, EXIT_DISABLED);
+        let dump: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(
+            dump,
+            serde_json::json!({
+                "CrOSLateBootEnabled": { "enabled": true, "params": { "key": "value" } },
+                "CrOSLateBootDisabled": { "enabled": false },
+            })
+        );
+
+        // The dump can be loaded as fake states.
+        let mut client = FakePlatformFeatures::new().unwrap();
+        client.load_json(&out).unwrap();
+        let feature = Feature::new("CrOSLateBootEnabled", false).unwrap();
+        assert_eq!(
+            client.get_feature_params_blocking(&feature).unwrap(),
+            Some([("key".to_string(), "value".to_string())].into())
+        );
+    }
+}
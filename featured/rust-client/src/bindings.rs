@@ -42,6 +42,9 @@ extern "C" {
 extern "C" {
     pub fn CFeatureLibraryGet() -> CFeatureLibrary;
 }
+extern "C" {
+    pub fn CFeatureLibraryShutdownForTesting();
+}
 extern "C" {
     pub fn CFeatureLibraryIsEnabledBlocking(
         handle: CFeatureLibrary,
@@ -17,11 +17,15 @@ use crate::bindings::*;
 use dbus::channel::MatchingReceiver;
 use dbus::message::MatchRule;
 use dbus::nonblock::SyncConnection;
-use once_cell::sync::OnceCell;
 use std::collections::HashMap;
 use std::mem::ManuallyDrop;
 use std::pin::Pin;
+use std::sync::mpsc;
+use std::sync::mpsc::Receiver;
 use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::PoisonError;
+use std::time::Duration;
 use thiserror::Error;
 
 /// Errors which can occur during `Feature` creation and use.
@@ -48,6 +52,35 @@ pub enum PlatformError {
     BadResult(i32),
 }
 
+/// Errors that can occur when loading feature states for `FakePlatformFeatures` from JSON.
+#[cfg(feature = "fake_json")]
+#[derive(Error, Debug)]
+pub enum FakeLoadError {
+    /// The JSON file could not be read.
+    #[error("failed to read feature states: {0}")]
+    Io(#[from] std::io::Error),
+    /// The JSON is not a valid feature state map.
+    #[error("failed to parse feature states: {0}")]
+    Json(#[from] serde_json::Error),
+    /// A feature name or parameter contains a null byte.
+    #[error("invalid feature: {0}")]
+    Feature(#[from] FeatureError),
+    /// The fake C client could not be created.
+    #[error("failed to create fake client: {0}")]
+    Platform(#[from] PlatformError),
+}
+
+/// The state of a single feature in the JSON accepted by
+/// `FakePlatformFeatures::load_json`.
+#[cfg(feature = "fake_json")]
+#[derive(serde::Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct FakeFeatureState {
+    enabled: bool,
+    #[serde(default)]
+    params: HashMap<String, String>,
+}
+
 /// `CheckFeature` provides methods for requesting the feature status from
 /// the underlying `featured` service. These methods are inherently blocking,
 /// and should be assumed to be invalidated when Chrome restarts.
@@ -74,6 +107,27 @@ pub trait CheckFeature {
         &self,
         features: &[&Feature],
     ) -> Result<GetParamsAndEnabledResponse, PlatformError>;
+
+    /// Requests the parameters of a single feature.
+    ///
+    /// Returns `None` if the feature is disabled, and the (possibly empty) parameter
+    /// map if it is enabled. This is a convenience for callers that only care about
+    /// one feature and would otherwise index into the `get_params_and_enabled`
+    /// response immediately.
+    ///
+    /// # Errors
+    ///
+    /// If the underlying C calls do not proper fetch the feature status object,
+    /// an error string will be returned.
+    fn get_feature_params_blocking(
+        &self,
+        feature: &Feature,
+    ) -> Result<Option<HashMap<String, String>>, PlatformError> {
+        Ok(self
+            .get_params_and_enabled(&[feature])?
+            .get_params(feature)
+            .cloned())
+    }
 }
 
 /// A wrapper around the C implementation for `VariationsFeature`.
@@ -103,7 +157,24 @@ impl Feature {
     /// If the provided feature name is not a valid C string, an error will be returned.
     pub fn new(name: &str, enabled_by_default: bool) -> Result<Self, FeatureError> {
         let name = std::ffi::CString::new(name).map_err(FeatureError::InteriorNullByte)?;
+        Ok(Self::from_c_string(name, enabled_by_default))
+    }
+
+    /// Creates a feature like [`Feature::new`], but takes ownership of `name` so that its buffer
+    /// is reused for the `CString` instead of being copied.
+    ///
+    /// The `name` argument must be a valid null-terminated UTF-8 string in order to be properly
+    /// serialized into a `CString`.
+    ///
+    /// # Errors
+    ///
+    /// If the provided feature name is not a valid C string, an error will be returned.
+    pub fn from_string(name: String, enabled_by_default: bool) -> Result<Self, FeatureError> {
+        let name = std::ffi::CString::new(name).map_err(FeatureError::InteriorNullByte)?;
+        Ok(Self::from_c_string(name, enabled_by_default))
+    }
 
+    fn from_c_string(name: std::ffi::CString, enabled_by_default: bool) -> Self {
         let default_state = if enabled_by_default {
             FeatureState_FEATURE_ENABLED_BY_DEFAULT
         } else {
@@ -113,7 +184,7 @@ impl Feature {
             name: name.as_ptr(),
             default_state,
         }));
-        Ok(Feature { name, c_feature })
+        Feature { name, c_feature }
     }
 
     /// The name assigned to this feature.
@@ -121,7 +192,8 @@ impl Feature {
     /// This will be the name registered in `featured` and used to enable/disable the feature
     /// via Finch or flags.
     pub fn name(&self) -> &str {
-        // The validation check in `Feature::new` means this is guarateed to be `Ok`.
+        // The validation check in `Feature::new` and `Feature::from_string` means this is
+        // guarateed to be `Ok`.
         self.name.to_str().expect("Unreachable")
     }
 
@@ -322,6 +394,48 @@ impl SafeHandle {
 
         Ok(GetParamsAndEnabledResponse { status_map })
     }
+
+    fn get_feature_params_blocking(
+        &self,
+        feature: &Feature,
+    ) -> Result<Option<HashMap<String, String>>, PlatformError> {
+        // The C library has no single-feature entry point, so query it with a
+        // one-element array. Both the feature pointer and the response entry live
+        // on the stack, which avoids the allocations of the batched path.
+        let feature_ptr = &*feature.c_feature.as_ref() as *const VariationsFeature;
+        let mut response =
+            std::mem::MaybeUninit::<VariationsFeatureGetParamsResponseEntry>::zeroed();
+
+        // SAFETY: The C library will not invalidate the handle pointer, and `response` has
+        // room for exactly the one entry requested.
+        let result = unsafe {
+            CFeatureLibraryGetParamsAndEnabledBlocking(
+                self.handle,
+                &feature_ptr,
+                1,
+                response.as_mut_ptr(),
+            )
+        };
+
+        // Note: When the C library call fails, it will deallocate any allocated memory, which means
+        // there is no need to manually call `CFeatureLibraryFreeEntries` before this early return.
+        if result != 0 {
+            return Err(PlatformError::BadResult(result));
+        }
+
+        // SAFETY: The C library call succeeded, so the entry has been populated.
+        let mut response = unsafe { response.assume_init() };
+        let params = match parse_params(&response) {
+            FeatureStatus::Disabled => None,
+            FeatureStatus::Enabled(params) => Some(params),
+        };
+
+        // SAFETY: This call only frees the underlying data allocated by the C library, which has
+        // already been converted to Rust-owned values above. The entry itself lives on the stack.
+        unsafe { CFeatureLibraryFreeEntries(&mut response, 1) }
+
+        Ok(params)
+    }
 }
 
 impl Drop for SafeHandle {
@@ -353,12 +467,114 @@ pub async fn listen_for_refetch_needed<T: FnMut() + Send + 'static>(
     Ok(())
 }
 
+/// A callback invoked with the enablement status and, if enabled, the parameters of a feature.
+type FeatureCallback = Box<dyn FnMut(bool, Option<&HashMap<String, String>>) + Send>;
+
+struct ObservedFeature {
+    feature: Feature,
+    callback: FeatureCallback,
+    // The status seen by the last refetch. `None` until the first refetch.
+    last_status: Option<(bool, Option<HashMap<String, String>>)>,
+}
+
+/// Invokes per-feature callbacks when the status of observed features changes.
+///
+/// Each `FeatureObserver::refetch` queries all the observed features together and
+/// invokes the callbacks of the features whose enablement status or parameters differ
+/// from the previous refetch. The first refetch after a feature is observed always
+/// invokes its callback with the initial status.
+///
+/// Use `listen_for_feature_changes` to refetch whenever featured asks clients to.
+#[derive(Default)]
+pub struct FeatureObserver {
+    observed: Vec<ObservedFeature>,
+}
+
+impl FeatureObserver {
+    /// Creates an observer without any observed features.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `callback` to be invoked when the status of `feature` changes.
+    pub fn observe<F>(&mut self, feature: Feature, callback: F)
+    where
+        F: FnMut(bool, Option<&HashMap<String, String>>) + Send + 'static,
+    {
+        self.observed.push(ObservedFeature {
+            feature,
+            callback: Box::new(callback),
+            last_status: None,
+        });
+    }
+
+    /// Re-queries the observed features and invokes the callbacks of the changed ones.
+    ///
+    /// # Errors
+    ///
+    /// If the underlying C calls do not proper fetch the feature status object,
+    /// an error will be returned and no callback is invoked.
+    pub fn refetch<C: CheckFeature + ?Sized>(&mut self, client: &C) -> Result<(), PlatformError> {
+        let features: Vec<_> = self.observed.iter().map(|o| &o.feature).collect();
+        let response = client.get_params_and_enabled(&features)?;
+
+        for observed in &mut self.observed {
+            let status = (
+                response.is_enabled(&observed.feature),
+                response.get_params(&observed.feature).cloned(),
+            );
+            if observed.last_status.as_ref() != Some(&status) {
+                (observed.callback)(status.0, status.1.as_ref());
+                observed.last_status = Some(status);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Refetches the features of `observer` whenever featured signals that feature state must
+/// be refetched (that is, whenever chrome restarts).
+///
+/// Signals are debounced: the refetch runs on a background thread once no further signal
+/// has arrived for `delay`. A failed refetch is dropped and retried on the next signal.
+pub async fn listen_for_feature_changes<C: CheckFeature + Send + Sync + 'static>(
+    conn: &SyncConnection,
+    observer: Arc<Mutex<FeatureObserver>>,
+    client: Arc<C>,
+    delay: Duration,
+) -> Result<(), dbus::Error> {
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        run_debounced(receiver, delay, || {
+            let _ = observer
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .refetch(client.as_ref());
+        })
+    });
+    listen_for_refetch_needed(conn, move || {
+        // The receiver lives as long as the sender, so this cannot fail.
+        let _ = sender.send(());
+    })
+    .await
+}
+
+/// Runs `f` once for each burst of messages on `receiver`, after no message has arrived for
+/// `delay`. Returns when all the senders are dropped.
+fn run_debounced<F: FnMut()>(receiver: Receiver<()>, delay: Duration, mut f: F) {
+    while receiver.recv().is_ok() {
+        // Wait until the burst ends, or all the senders are dropped.
+        while receiver.recv_timeout(delay).is_ok() {}
+        f();
+    }
+}
+
 /// A platform specific featured client, used to communicate to featured via the
 /// wrapped C library.
 pub struct PlatformFeatures {
     handle: SafeHandle,
 }
-static FEATURE_LIBRARY: OnceCell<Arc<PlatformFeatures>> = OnceCell::new();
+static FEATURE_LIBRARY: Mutex<Option<Arc<PlatformFeatures>>> = Mutex::new(None);
 
 impl PlatformFeatures {
     /// Returns a client handle for requests to featured. Will also initialize the client handle on
@@ -369,31 +585,53 @@ impl PlatformFeatures {
     /// If the underlying C calls do not return a proper handle to
     /// the featured client, an error will be returned.
     pub fn get() -> Result<Arc<PlatformFeatures>, PlatformError> {
-        FEATURE_LIBRARY
-            .get_or_try_init(|| {
-                // SAFETY: The C library will initialize the handle to either a valid object pointer
-                // or a null pointer. A subsequent check is made to ensure the client is initialized
-                // properly.
-                let initialize = unsafe { CFeatureLibraryInitialize() };
-                if !initialize {
-                    return Err(PlatformError::NullHandle);
-                }
-
-                let cpp_handle = unsafe { CFeatureLibraryGet() };
-                if cpp_handle.is_null() {
-                    return Err(PlatformError::NullHandle);
-                }
-
-                let lib = Arc::new(PlatformFeatures {
-                    handle: SafeHandle {
-                        handle: cpp_handle,
-                        fake: false,
-                    },
-                });
-
-                Ok(lib)
-            })
-            .map(Arc::clone)
+        let mut library = FEATURE_LIBRARY
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Some(lib) = library.as_ref() {
+            return Ok(Arc::clone(lib));
+        }
+
+        // SAFETY: The C library will initialize the handle to either a valid object pointer
+        // or a null pointer. A subsequent check is made to ensure the client is initialized
+        // properly.
+        let initialize = unsafe { CFeatureLibraryInitialize() };
+        if !initialize {
+            return Err(PlatformError::NullHandle);
+        }
+
+        let cpp_handle = unsafe { CFeatureLibraryGet() };
+        if cpp_handle.is_null() {
+            return Err(PlatformError::NullHandle);
+        }
+
+        let lib = Arc::new(PlatformFeatures {
+            handle: SafeHandle {
+                handle: cpp_handle,
+                fake: false,
+            },
+        });
+        *library = Some(Arc::clone(&lib));
+
+        Ok(lib)
+    }
+
+    /// Shuts down the global client so that the next call to [`PlatformFeatures::get`]
+    /// initializes a fresh one. This allows tests to start from a clean library state.
+    ///
+    /// # Safety
+    ///
+    /// The underlying C library instance is destroyed, which invalidates every handle
+    /// previously returned by [`PlatformFeatures::get`]. The caller must ensure that all
+    /// such handles have been dropped and that no other thread is using the library.
+    #[cfg(any(test, feature = "testing"))]
+    pub unsafe fn reset_for_testing() {
+        let mut library = FEATURE_LIBRARY
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if library.take().is_some() {
+            CFeatureLibraryShutdownForTesting();
+        }
     }
 }
 
@@ -408,6 +646,13 @@ impl CheckFeature for PlatformFeatures {
     ) -> Result<GetParamsAndEnabledResponse, PlatformError> {
         self.handle.get_params_and_enabled_blocking(features)
     }
+
+    fn get_feature_params_blocking(
+        &self,
+        feature: &Feature,
+    ) -> Result<Option<HashMap<String, String>>, PlatformError> {
+        self.handle.get_feature_params_blocking(feature)
+    }
 }
 
 /// A fake featured client, used to mock communications to featured via the
@@ -500,6 +745,64 @@ impl FakePlatformFeatures {
         // since the underlying C structure contains a reference to these items.
         self.c_strings.remove(feature.name());
     }
+
+    /// Creates a new fake client whose in-memory store is populated from a JSON file.
+    ///
+    /// See `FakePlatformFeatures::load_json` for the format of the file.
+    ///
+    /// # Errors
+    ///
+    /// If the fake C client cannot be created, or the file cannot be read or parsed,
+    /// an error will be returned.
+    #[cfg(feature = "fake_json")]
+    pub fn from_json_file(path: &std::path::Path) -> Result<Self, FakeLoadError> {
+        let json = std::fs::read_to_string(path)?;
+        let mut features = FakePlatformFeatures::new()?;
+        features.load_json(&json)?;
+        Ok(features)
+    }
+
+    /// Sets the enablement status and parameters of features from a JSON object
+    /// mapping feature names to their states, e.g.
+    ///
+    /// ```json
+    /// {
+    ///   "CrOSLateBootMyAwesomeFeature": { "enabled": true, "params": { "key": "value" } },
+    ///   "CrOSLateBootMyOtherFeature": { "enabled": false }
+    /// }
+    /// ```
+    ///
+    /// Features which are not listed keep their current state.
+    ///
+    /// # Errors
+    ///
+    /// If the JSON is malformed, or a name contains a null byte, an error is returned and
+    /// the store is left unchanged.
+    #[cfg(feature = "fake_json")]
+    pub fn load_json(&mut self, json: &str) -> Result<(), FakeLoadError> {
+        let states: HashMap<String, FakeFeatureState> = serde_json::from_str(json)?;
+        // Validate everything before touching the store so that a bad entry does not leave
+        // it partially populated.
+        let states = states
+            .into_iter()
+            .map(|(name, state)| Ok((Feature::new(&name, false)?, state)))
+            .collect::<Result<Vec<_>, FeatureError>>()?;
+        for (_, state) in &states {
+            for (key, value) in &state.params {
+                std::ffi::CString::new(key.as_str()).map_err(FeatureError::InteriorNullByte)?;
+                std::ffi::CString::new(value.as_str()).map_err(FeatureError::InteriorNullByte)?;
+            }
+        }
+
+        for (feature, state) in &states {
+            self.clear_params(feature);
+            for (key, value) in &state.params {
+                self.set_param(feature, key, value);
+            }
+            self.set_feature_enabled(feature, state.enabled);
+        }
+        Ok(())
+    }
 }
 
 impl CheckFeature for FakePlatformFeatures {
@@ -513,6 +816,13 @@ impl CheckFeature for FakePlatformFeatures {
     ) -> Result<GetParamsAndEnabledResponse, PlatformError> {
         self.handle.get_params_and_enabled_blocking(features)
     }
+
+    fn get_feature_params_blocking(
+        &self,
+        feature: &Feature,
+    ) -> Result<Option<HashMap<String, String>>, PlatformError> {
+        self.handle.get_feature_params_blocking(feature)
+    }
 }
 
 fn parse_params(entry: &VariationsFeatureGetParamsResponseEntry) -> FeatureStatus {
@@ -567,14 +877,56 @@ mod tests {
         assert!(subject.is_err());
     }
 
+    #[test]
+    fn it_creates_the_same_feature_from_an_owned_string() {
+        let borrowed = Feature::new("some-valid-feature", true).unwrap();
+        let owned = Feature::from_string("some-valid-feature".to_string(), true).unwrap();
+        assert_eq!(borrowed.name(), owned.name());
+        assert_eq!(borrowed.enabled_by_default(), owned.enabled_by_default());
+
+        let owned = Feature::from_string("some-valid-feature".to_string(), false).unwrap();
+        assert!(!owned.enabled_by_default());
+    }
+
+    #[test]
+    fn it_rejects_an_invalid_feature_from_an_owned_string() {
+        let subject = Feature::from_string("some-bad\0-feature".to_string(), true);
+        assert!(matches!(subject, Err(FeatureError::InteriorNullByte(_))));
+    }
+
+    // Serializes tests which use the global library, since it can be reset.
+    static GLOBAL_LIBRARY_LOCK: Mutex<()> = Mutex::new(());
+
     #[test]
     fn it_initializes_and_returns_a_valid_library() {
+        let _guard = GLOBAL_LIBRARY_LOCK
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
         let first_init = PlatformFeatures::get();
         assert!(first_init.is_ok());
         let second_init = PlatformFeatures::get();
         assert!(second_init.is_ok())
     }
 
+    #[test]
+    fn it_reinitializes_the_library_after_reset() {
+        let _guard = GLOBAL_LIBRARY_LOCK
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let first_init = Arc::downgrade(&PlatformFeatures::get().unwrap());
+        assert!(first_init.upgrade().is_some());
+
+        // SAFETY: The only handle returned above has been dropped.
+        unsafe { PlatformFeatures::reset_for_testing() };
+        assert!(first_init.upgrade().is_none());
+
+        let second_init = PlatformFeatures::get().unwrap();
+        assert!(!std::ptr::eq(
+            first_init.as_ptr(),
+            Arc::as_ptr(&second_init)
+        ));
+    }
+
     #[test]
     fn it_properly_fakes_the_feature_library_for_is_enabled() {
         let mut subject = FakePlatformFeatures::new().unwrap();
@@ -645,4 +997,160 @@ mod tests {
         assert_eq!(actual.get_param(&feature_two, &param_one_key), None);
         assert_eq!(actual.get_param(&feature_two, &param_two_key), None);
     }
+
+    #[test]
+    fn it_returns_the_same_params_for_a_single_feature_as_for_a_batch() {
+        let mut subject = FakePlatformFeatures::new().unwrap();
+
+        let enabled = Feature::new("some-enabled-feature", false).unwrap();
+        let disabled = Feature::new("some-disabled-feature", true).unwrap();
+        let unknown = Feature::new("some-unknown-feature", false).unwrap();
+
+        subject.set_param(&enabled, "some-param", "some-value");
+        subject.set_feature_enabled(&enabled, true);
+        subject.set_feature_enabled(&disabled, false);
+
+        let batch = subject
+            .get_params_and_enabled(&[&enabled, &disabled, &unknown])
+            .unwrap();
+
+        for feature in [&enabled, &disabled, &unknown] {
+            let single = subject.get_feature_params_blocking(feature).unwrap();
+            assert_eq!(single.as_ref(), batch.get_params(feature));
+        }
+
+        let params = subject
+            .get_feature_params_blocking(&enabled)
+            .unwrap()
+            .unwrap();
+        assert_eq!(params.len(), 1);
+        assert_eq!(params.get("some-param"), Some(&"some-value".to_string()));
+        assert!(subject
+            .get_feature_params_blocking(&disabled)
+            .unwrap()
+            .is_none());
+        assert!(subject
+            .get_feature_params_blocking(&unknown)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn it_notifies_observers_of_changed_features_only() {
+        let mut subject = FakePlatformFeatures::new().unwrap();
+        let flipped = Feature::new("some-flipped-feature", false).unwrap();
+        let stable = Feature::new("some-stable-feature", true).unwrap();
+
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let mut observer = FeatureObserver::new();
+        for feature in [&flipped, &stable] {
+            let calls = Arc::clone(&calls);
+            let name = feature.name().to_string();
+            observer.observe(
+                Feature::new(feature.name(), feature.enabled_by_default()).unwrap(),
+                move |enabled, params| {
+                    calls
+                        .lock()
+                        .unwrap()
+                        .push((name.clone(), enabled, params.cloned()))
+                },
+            );
+        }
+
+        // The first refetch reports the initial status.
+        observer.refetch(&subject).unwrap();
+        assert_eq!(
+            std::mem::take(&mut *calls.lock().unwrap()),
+            vec![
+                ("some-flipped-feature".to_string(), false, None),
+                (
+                    "some-stable-feature".to_string(),
+                    true,
+                    Some(HashMap::new())
+                ),
+            ]
+        );
+
+        // Nothing changed.
+        observer.refetch(&subject).unwrap();
+        assert!(calls.lock().unwrap().is_empty());
+
+        subject.set_feature_enabled(&flipped, true);
+        subject.set_param(&flipped, "key", "value");
+        observer.refetch(&subject).unwrap();
+        assert_eq!(
+            std::mem::take(&mut *calls.lock().unwrap()),
+            vec![(
+                "some-flipped-feature".to_string(),
+                true,
+                Some(HashMap::from([("key".to_string(), "value".to_string())]))
+            )]
+        );
+
+        // A parameter change alone is reported too.
+        subject.set_param(&flipped, "key", "other-value");
+        observer.refetch(&subject).unwrap();
+        assert_eq!(calls.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn it_debounces_refetch_signals() {
+        let (sender, receiver) = mpsc::channel();
+        for _ in 0..3 {
+            sender.send(()).unwrap();
+        }
+        drop(sender);
+
+        let mut n_runs = 0;
+        run_debounced(receiver, Duration::from_millis(10), || n_runs += 1);
+        assert_eq!(n_runs, 1);
+    }
+
+    #[cfg(feature = "fake_json")]
+    #[test]
+    fn it_loads_fake_feature_states_from_json() {
+        let mut subject = FakePlatformFeatures::new().unwrap();
+        let enabled = Feature::new("some-enabled-feature", false).unwrap();
+        let disabled = Feature::new("some-disabled-feature", true).unwrap();
+        let unlisted = Feature::new("some-unlisted-feature", true).unwrap();
+
+        subject
+            .load_json(
+                r#"{
+                    "some-enabled-feature": { "enabled": true, "params": { "key": "value" } },
+                    "some-disabled-feature": { "enabled": false }
+                }"#,
+            )
+            .unwrap();
+
+        let status = subject
+            .get_params_and_enabled(&[&enabled, &disabled, &unlisted])
+            .unwrap();
+        assert!(status.is_enabled(&enabled));
+        assert_eq!(
+            status.get_param(&enabled, "key"),
+            Some(&"value".to_string())
+        );
+        assert!(!status.is_enabled(&disabled));
+        assert!(status.is_enabled(&unlisted));
+    }
+
+    #[cfg(feature = "fake_json")]
+    #[test]
+    fn it_rejects_malformed_fake_feature_states() {
+        let mut subject = FakePlatformFeatures::new().unwrap();
+        let feature = Feature::new("some-feature", false).unwrap();
+
+        for json in [
+            "[]",
+            r#"{ "some-feature": true }"#,
+            r#"{ "some-feature": { "params": {} } }"#,
+            r#"{ "some-feature": { "enabled": true, "unknown": 1 } }"#,
+            r#"{ "some-feature": { "enabled": true, "params": { "key\u0000": "value" } } }"#,
+        ] {
+            assert!(subject.load_json(json).is_err(), "{}", json);
+        }
+        // A failed load leaves the store unchanged.
+        assert!(!subject.is_feature_enabled_blocking(&feature));
+    }
 }
@@ -0,0 +1,612 @@
+// Copyright 2024 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use log::info;
+use serde::Deserialize;
+
+use crate::disk;
+use crate::lsblk::{self, LsBlkDevice};
+use crate::mount;
+
+/// Contents of the optional flex config that is placed next to the install
+/// payload on the data partition.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+pub struct FlexConfig {
+    /// Settings for unattended installs.
+    #[serde(default)]
+    pub install: Option<InstallSection>,
+}
+
+impl FlexConfig {
+    /// Parses and validates a flex config from its JSON representation.
+    pub fn parse(input: &[u8]) -> Result<Self> {
+        let config: Self = serde_json::from_slice(input).context("Malformed flex config")?;
+        if let Some(install) = &config.install {
+            install.validate()?;
+        }
+        Ok(config)
+    }
+}
+
+/// The `install` section of the flex config.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct InstallSection {
+    /// Selects the disk to install onto. If absent, the disk carrying the
+    /// install payload is used.
+    #[serde(default)]
+    pub target: Option<DiskSelector>,
+
+    /// What to do once the installation succeeded.
+    #[serde(default = "default_on_success")]
+    pub on_success: PowerAction,
+
+    /// What to do once the installation failed.
+    #[serde(default = "default_on_failure")]
+    pub on_failure: PowerAction,
+
+    /// Where the install payload comes from.
+    #[serde(default)]
+    pub image_source: ImageSource,
+}
+
+impl InstallSection {
+    /// Checks for values that deserialize fine but make no sense.
+    pub fn validate(&self) -> Result<()> {
+        if self.on_failure == PowerAction::Shutdown {
+            bail!("on_failure only supports \"reboot\" and \"wait\"");
+        }
+
+        match &self.target {
+            Some(DiskSelector::Model(model)) if model.trim().is_empty() => {
+                bail!("The model selector must not be empty");
+            }
+            Some(DiskSelector::Serial(serial)) if serial.trim().is_empty() => {
+                bail!("The serial selector must not be empty");
+            }
+            Some(DiskSelector::SizeRange {
+                min_bytes,
+                max_bytes,
+            }) => match (min_bytes, max_bytes) {
+                (None, None) => bail!("The size range selector needs at least one bound"),
+                (Some(min), Some(max)) if min > max => {
+                    bail!("The size range selector has min_bytes ({min}) > max_bytes ({max})")
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+
+        if let ImageSource::Url { url, sha256 } = &self.image_source {
+            let is_https = url
+                .get(..HTTPS_PREFIX.len())
+                .is_some_and(|scheme| scheme.eq_ignore_ascii_case(HTTPS_PREFIX));
+            if !is_https || url.len() == HTTPS_PREFIX.len() {
+                bail!("The image url must be an https url, got {url:?}");
+            }
+            if sha256.len() != 64 || !sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+                bail!("The image sha256 must be 64 hex digits, got {sha256:?}");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+const HTTPS_PREFIX: &str = "https://";
+
+fn default_on_success() -> PowerAction {
+    PowerAction::Reboot
+}
+
+fn default_on_failure() -> PowerAction {
+    PowerAction::Wait
+}
+
+/// What flexor does once it is done.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PowerAction {
+    /// Reboot the machine.
+    Reboot,
+    /// Power off the machine.
+    Shutdown,
+    /// Exit flexor and leave the machine running.
+    Wait,
+}
+
+/// Where flexor gets the install payload from.
+///
+/// In JSON this is either the string `"partition"` or an object with the key
+/// `url`.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum ImageSource {
+    /// The data partition of the payload disk.
+    #[default]
+    Partition,
+    /// Download the payload over the network.
+    Url {
+        /// The https url of the payload.
+        url: String,
+        /// The expected SHA-256 digest of the payload in hex.
+        sha256: String,
+    },
+}
+
+/// Selects the disk to install onto.
+///
+/// In JSON this is either the string `"largest"` or an object with exactly
+/// one of the keys `model`, `serial` or `size_range`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum DiskSelector {
+    /// The disk with the largest size.
+    Largest,
+    /// The disk with this model string.
+    Model(String),
+    /// The disk with this serial number.
+    Serial(String),
+    /// The disk with a size in bytes within the inclusive range.
+    SizeRange {
+        #[serde(default)]
+        min_bytes: Option<u64>,
+        #[serde(default)]
+        max_bytes: Option<u64>,
+    },
+}
+
+impl DiskSelector {
+    /// Selects exactly one disk from `devices`. Devices that are not disks
+    /// are ignored. It is an error if no or more than one disk matches.
+    pub fn select<'a>(&self, devices: &'a [LsBlkDevice]) -> Result<&'a LsBlkDevice> {
+        let disks = devices.iter().filter(|device| device.device_type == "disk");
+
+        let matches: Vec<&LsBlkDevice> = match self {
+            Self::Largest => {
+                let disks: Vec<&LsBlkDevice> = disks.collect();
+                let largest = disks.iter().map(|disk| disk.size).max().unwrap_or_default();
+                disks
+                    .into_iter()
+                    .filter(|disk| disk.size == largest)
+                    .collect()
+            }
+            Self::Model(model) => disks
+                .filter(|disk| matches_trimmed(&disk.model, model))
+                .collect(),
+            Self::Serial(serial) => disks
+                .filter(|disk| matches_trimmed(&disk.serial, serial))
+                .collect(),
+            Self::SizeRange {
+                min_bytes,
+                max_bytes,
+            } => disks
+                .filter(|disk| {
+                    (min_bytes.unwrap_or(0)..=max_bytes.unwrap_or(u64::MAX)).contains(&disk.size)
+                })
+                .collect(),
+        };
+
+        match matches.as_slice() {
+            [] => bail!("No disk matches the selector {self:?}"),
+            [disk] => Ok(disk),
+            candidates => {
+                let candidates: Vec<String> =
+                    candidates.iter().map(|disk| describe_disk(disk)).collect();
+                bail!(
+                    "The selector {self:?} matches more than one disk: {}",
+                    candidates.join(", ")
+                );
+            }
+        }
+    }
+}
+
+/// lsblk pads some of its string columns with whitespace, so ignore it
+/// when comparing against the config.
+fn matches_trimmed(value: &Option<String>, expected: &str) -> bool {
+    value.as_deref().map(str::trim) == Some(expected.trim())
+}
+
+fn describe_disk(disk: &LsBlkDevice) -> String {
+    format!(
+        "{} (size: {} bytes, model: {}, serial: {})",
+        disk.name,
+        disk.size,
+        disk.model.as_deref().unwrap_or("unknown"),
+        disk.serial.as_deref().unwrap_or("unknown"),
+    )
+}
+
+/// Everything that determines where and how flexor installs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InstallConfig {
+    /// The disk carrying the data partition, if there is one. Network installs
+    /// may run without.
+    pub payload_disk: Option<PathBuf>,
+    /// The disk ChromeOS Flex is installed onto.
+    pub target_disk: PathBuf,
+    /// Where the install payload comes from.
+    pub image_source: ImageSource,
+    /// What to do once the installation succeeded.
+    pub on_success: PowerAction,
+    /// What to do once the installation failed.
+    pub on_failure: PowerAction,
+}
+
+impl InstallConfig {
+    /// Locates the install payload and reads the flex config next to it, if
+    /// there is one. Without an `install` section the payload disk is
+    /// installed onto and the machine reboots on success.
+    ///
+    /// Network booted installers have no data partition, so a flex config
+    /// built into the installer ramfs takes precedence.
+    pub fn new() -> Result<Self> {
+        if let Some(flex_config) = read_flex_config_file(Path::new(RAMFS_FLEX_CONFIG_PATH))? {
+            info!("Using the flex config from {RAMFS_FLEX_CONFIG_PATH}");
+            return Self::from_install_section(None, flex_config.install, lsblk::get_lsblk_devices);
+        }
+
+        let payload_disk = disk::get_target_device()?;
+        let flex_config = read_flex_config(&payload_disk)?;
+
+        Self::from_install_section(
+            Some(payload_disk),
+            flex_config.and_then(|config| config.install),
+            lsblk::get_lsblk_devices,
+        )
+    }
+
+    fn from_install_section<F>(
+        payload_disk: Option<PathBuf>,
+        install: Option<InstallSection>,
+        get_devices: F,
+    ) -> Result<Self>
+    where
+        F: FnOnce() -> Result<Vec<LsBlkDevice>>,
+    {
+        let install = install.unwrap_or(InstallSection {
+            target: None,
+            on_success: default_on_success(),
+            on_failure: default_on_failure(),
+            image_source: ImageSource::Partition,
+        });
+
+        if install.image_source == ImageSource::Partition && payload_disk.is_none() {
+            bail!("Installing from the data partition needs a disk with a data partition");
+        }
+
+        let target_disk = match (&install.target, &payload_disk) {
+            (Some(selector), _) => {
+                let devices = get_devices().context("Unable to get block devices")?;
+                let disk = selector.select(&devices)?;
+                info!("Selected target disk {}", describe_disk(disk));
+                Path::new(&disk.name).into()
+            }
+            (None, Some(payload_disk)) => payload_disk.clone(),
+            (None, None) => bail!("A target disk selector is needed without a payload disk"),
+        };
+
+        Ok(Self {
+            payload_disk,
+            target_disk,
+            image_source: install.image_source,
+            on_success: install.on_success,
+            on_failure: install.on_failure,
+        })
+    }
+}
+
+/// Location of a flex config built into the installer ramfs.
+const RAMFS_FLEX_CONFIG_PATH: &str = "/etc/flex_config.json";
+
+/// Reads the flex config from the data partition on `disk_path`. Returns
+/// `None` if there is no config.
+fn read_flex_config(disk_path: &Path) -> Result<Option<FlexConfig>> {
+    let data_partition_path =
+        disk::get_data_partition(disk_path).context("Unable to find correct partition path")?;
+    let mount = mount::Mount::mount_by_path(&data_partition_path, mount::FsType::Vfat)
+        .context("Unable to mount data partition")?;
+
+    let config = read_flex_config_file(&mount.mount_path().join(crate::FLEX_CONFIG_FILENAME))?;
+    if config.is_none() {
+        info!("No flex config found, using defaults");
+    }
+    Ok(config)
+}
+
+/// Reads the flex config at `config_path`. Returns `None` if there is no
+/// config.
+fn read_flex_config_file(config_path: &Path) -> Result<Option<FlexConfig>> {
+    if !config_path
+        .try_exists()
+        .context("Unable to check for the flex config")?
+    {
+        return Ok(None);
+    }
+
+    let contents = std::fs::read(config_path).context("Unable to read the flex config")?;
+    FlexConfig::parse(&contents).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mkdisk(name: &str, size: u64, model: &str, serial: &str) -> LsBlkDevice {
+        LsBlkDevice {
+            name: name.into(),
+            device_type: "disk".into(),
+            size,
+            model: Some(model.into()),
+            serial: Some(serial.into()),
+        }
+    }
+
+    fn fake_devices() -> Vec<LsBlkDevice> {
+        vec![
+            mkdisk("/dev/sda", 32_000_000_000, "USB Stick       ", "AA01"),
+            LsBlkDevice {
+                name: "/dev/sda1".into(),
+                device_type: "part".into(),
+                size: 512_000_000_000,
+                model: None,
+                serial: None,
+            },
+            mkdisk("/dev/nvme0n1", 256_000_000_000, "FAST NVME", "BB02"),
+            mkdisk("/dev/sdb", 256_000_000_000, "SLOW SSD", "CC03"),
+            mkdisk("/dev/sdc", 128_000_000_000, "SLOW SSD", "DD04"),
+        ]
+    }
+
+    fn parse_install(input: &str) -> Result<InstallSection> {
+        Ok(FlexConfig::parse(input.as_bytes())?.install.unwrap())
+    }
+
+    #[test]
+    fn test_select_by_serial() {
+        let devices = fake_devices();
+        let selector = DiskSelector::Serial("CC03".into());
+        assert_eq!(selector.select(&devices).unwrap().name, "/dev/sdb");
+    }
+
+    #[test]
+    fn test_select_by_model_ignores_padding() {
+        let devices = fake_devices();
+        let selector = DiskSelector::Model("USB Stick".into());
+        assert_eq!(selector.select(&devices).unwrap().name, "/dev/sda");
+    }
+
+    #[test]
+    fn test_select_by_size_range() {
+        let devices = fake_devices();
+        let selector = DiskSelector::SizeRange {
+            min_bytes: Some(100_000_000_000),
+            max_bytes: Some(200_000_000_000),
+        };
+        assert_eq!(selector.select(&devices).unwrap().name, "/dev/sdc");
+
+        // Partitions are never selected.
+        let selector = DiskSelector::SizeRange {
+            min_bytes: Some(500_000_000_000),
+            max_bytes: None,
+        };
+        assert!(selector.select(&devices).is_err());
+    }
+
+    #[test]
+    fn test_select_ambiguous() {
+        let devices = fake_devices();
+
+        let err = DiskSelector::Model("SLOW SSD".into())
+            .select(&devices)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("/dev/sdb"));
+        assert!(err.contains("/dev/sdc"));
+
+        // Two disks share the largest size.
+        let err = DiskSelector::Largest
+            .select(&devices)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("/dev/nvme0n1"));
+        assert!(err.contains("/dev/sdb"));
+    }
+
+    #[test]
+    fn test_select_largest() {
+        let devices: Vec<LsBlkDevice> = fake_devices()
+            .into_iter()
+            .filter(|device| device.name != "/dev/sdb")
+            .collect();
+        let selected = DiskSelector::Largest.select(&devices).unwrap();
+        assert_eq!(selected.name, "/dev/nvme0n1");
+    }
+
+    #[test]
+    fn test_select_no_match() {
+        let devices = fake_devices();
+        let selector = DiskSelector::Serial("ZZ99".into());
+        assert!(selector.select(&devices).is_err());
+        assert!(DiskSelector::Largest.select(&[]).is_err());
+    }
+
+    #[test]
+    fn test_parse_install_section() {
+        let install = parse_install(
+            r#"{"install": {"target": {"serial": "CC03"}, "on_success": "shutdown"}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            install,
+            InstallSection {
+                target: Some(DiskSelector::Serial("CC03".into())),
+                on_success: PowerAction::Shutdown,
+                on_failure: PowerAction::Wait,
+                image_source: ImageSource::Partition,
+            }
+        );
+
+        let install = parse_install(r#"{"install": {"target": "largest"}}"#).unwrap();
+        assert_eq!(install.target, Some(DiskSelector::Largest));
+        assert_eq!(install.on_success, PowerAction::Reboot);
+
+        let install =
+            parse_install(r#"{"install": {"target": {"size_range": {"max_bytes": 10}}}}"#).unwrap();
+        assert_eq!(
+            install.target,
+            Some(DiskSelector::SizeRange {
+                min_bytes: None,
+                max_bytes: Some(10),
+            })
+        );
+
+        let config = FlexConfig::parse(b"{}").unwrap();
+        assert_eq!(config.install, None);
+    }
+
+    #[test]
+    fn test_parse_install_section_invalid() {
+        // Shutting down on failure is not supported.
+        assert!(parse_install(r#"{"install": {"on_failure": "shutdown"}}"#).is_err());
+        // Unknown power action.
+        assert!(parse_install(r#"{"install": {"on_success": "hibernate"}}"#).is_err());
+        // Unknown selector.
+        assert!(parse_install(r#"{"install": {"target": "smallest"}}"#).is_err());
+        // Empty selectors.
+        assert!(parse_install(r#"{"install": {"target": {"model": " "}}}"#).is_err());
+        assert!(parse_install(r#"{"install": {"target": {"size_range": {}}}}"#).is_err());
+        // Inverted size range.
+        assert!(parse_install(
+            r#"{"install": {"target": {"size_range": {"min_bytes": 2, "max_bytes": 1}}}}"#
+        )
+        .is_err());
+        // Typo in a field name.
+        assert!(parse_install(r#"{"install": {"on_sucess": "wait"}}"#).is_err());
+    }
+
+    #[test]
+    fn test_install_config_fallback() {
+        let config = InstallConfig::from_install_section(Some("/dev/sda".into()), None, || {
+            panic!("block devices should not be queried")
+        })
+        .unwrap();
+        assert_eq!(config.target_disk, Path::new("/dev/sda"));
+        assert_eq!(config.payload_disk.as_deref(), Some(Path::new("/dev/sda")));
+        assert_eq!(config.image_source, ImageSource::Partition);
+        assert_eq!(config.on_success, PowerAction::Reboot);
+        assert_eq!(config.on_failure, PowerAction::Wait);
+    }
+
+    #[test]
+    fn test_install_config_with_selector() {
+        let install = InstallSection {
+            target: Some(DiskSelector::Serial("BB02".into())),
+            on_success: PowerAction::Shutdown,
+            on_failure: PowerAction::Reboot,
+            image_source: ImageSource::Partition,
+        };
+        let config =
+            InstallConfig::from_install_section(Some("/dev/sda".into()), Some(install), || {
+                Ok(fake_devices())
+            })
+            .unwrap();
+        assert_eq!(config.payload_disk.as_deref(), Some(Path::new("/dev/sda")));
+        assert_eq!(config.target_disk, Path::new("/dev/nvme0n1"));
+        assert_eq!(config.on_success, PowerAction::Shutdown);
+        assert_eq!(config.on_failure, PowerAction::Reboot);
+    }
+
+    const SHA256: &str = "c96f06234f804d8c4ed88cf07a2d1e26ab3643a4575e997b0ae97bd5d646d6ad";
+
+    #[test]
+    fn test_parse_image_source() {
+        let install = parse_install(r#"{"install": {}}"#).unwrap();
+        assert_eq!(install.image_source, ImageSource::Partition);
+
+        let install = parse_install(r#"{"install": {"image_source": "partition"}}"#).unwrap();
+        assert_eq!(install.image_source, ImageSource::Partition);
+
+        let install = parse_install(&format!(
+            r#"{{"install": {{"image_source": {{"url": {{
+                "url": "https://example.com/flex_image.tar.xz",
+                "sha256": "{SHA256}"
+            }}}}}}}}"#
+        ))
+        .unwrap();
+        assert_eq!(
+            install.image_source,
+            ImageSource::Url {
+                url: "https://example.com/flex_image.tar.xz".into(),
+                sha256: SHA256.into(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_image_source_invalid() {
+        let parse_url = |url: &str, sha256: &str| {
+            parse_install(&format!(
+                r#"{{"install": {{"image_source": {{"url": {{"url": "{url}", "sha256": "{sha256}"}}}}}}}}"#
+            ))
+        };
+        assert!(parse_url("https://example.com/image", SHA256).is_ok());
+        // Only https is allowed.
+        assert!(parse_url("http://example.com/image", SHA256).is_err());
+        assert!(parse_url("https://", SHA256).is_err());
+        assert!(parse_url("example.com/image", SHA256).is_err());
+        // The digest must be a full SHA-256 in hex.
+        assert!(parse_url("https://example.com/image", &SHA256[1..]).is_err());
+        assert!(parse_url("https://example.com/image", &SHA256.replace('c', "x")).is_err());
+        // The digest is required.
+        assert!(parse_install(
+            r#"{"install": {"image_source": {"url": {"url": "https://example.com/image"}}}}"#
+        )
+        .is_err());
+        assert!(parse_install(r#"{"install": {"image_source": "usb"}}"#).is_err());
+    }
+
+    #[test]
+    fn test_install_config_without_payload_disk() {
+        let install = InstallSection {
+            target: Some(DiskSelector::Serial("BB02".into())),
+            on_success: PowerAction::Reboot,
+            on_failure: PowerAction::Wait,
+            image_source: ImageSource::Url {
+                url: "https://example.com/flex_image.tar.xz".into(),
+                sha256: SHA256.into(),
+            },
+        };
+        let config =
+            InstallConfig::from_install_section(None, Some(install.clone()), || Ok(fake_devices()))
+                .unwrap();
+        assert_eq!(config.payload_disk, None);
+        assert_eq!(config.target_disk, Path::new("/dev/nvme0n1"));
+
+        // Without a payload disk, the target has to be selected explicitly.
+        let no_target = InstallSection {
+            target: None,
+            ..install.clone()
+        };
+        assert!(
+            InstallConfig::from_install_section(None, Some(no_target), || Ok(fake_devices()))
+                .is_err()
+        );
+
+        // The data partition is needed to install from it.
+        let from_partition = InstallSection {
+            image_source: ImageSource::Partition,
+            ..install
+        };
+        assert!(
+            InstallConfig::from_install_section(None, Some(from_partition), || Ok(fake_devices()))
+                .is_err()
+        );
+        assert!(InstallConfig::from_install_section(None, None, || Ok(fake_devices())).is_err());
+    }
+}
@@ -16,8 +16,8 @@ use crate::mount;
 
 use crate::util::execute_command;
 
-/// Identifies the target device to install onto. The device
-/// is identified by having the remote install payload on it.
+/// Identifies the device carrying the remote install payload. Unless the
+/// flex config selects a different disk, this is also the install target.
 pub fn get_target_device() -> Result<PathBuf> {
     let disks = get_disks()?;
 
@@ -21,6 +21,18 @@ pub struct LsBlkDevice {
     /// Device type.
     #[serde(rename = "type")]
     pub device_type: String,
+
+    /// Device size in bytes.
+    #[serde(default)]
+    pub size: u64,
+
+    /// Device model, if reported by the kernel.
+    #[serde(default)]
+    pub model: Option<String>,
+
+    /// Device serial number, if reported by the kernel.
+    #[serde(default)]
+    pub serial: Option<String>,
 }
 
 #[derive(Clone, Debug, Deserialize, PartialEq)]
@@ -73,7 +85,9 @@ fn get_lsblk_output() -> Result<Vec<u8>> {
     command.args([
         // Select the fields to output
         "--output",
-        "NAME,TYPE",
+        "NAME,TYPE,SIZE,MODEL,SERIAL",
+        // Print sizes in bytes
+        "--bytes",
         // Format output as JSON
         "--json",
         // Print full device paths
@@ -126,6 +140,9 @@ mod tests {
         LsBlkDevice {
             name: name.into(),
             device_type: dtype.into(),
+            size: 0,
+            model: None,
+            serial: None,
         }
     }
 
@@ -163,4 +180,41 @@ mod tests {
         let output = LsBlkOutput::parse(input).unwrap();
         assert_eq!(output.flattened(), expected);
     }
+
+    #[test]
+    fn test_lsblk_deserialization_with_details() {
+        // This test input was generated by running this command in a VM
+        // with two disks attached:
+        //
+        //     lsblk --bytes --output NAME,TYPE,SIZE,MODEL,SERIAL \
+        //         --json --paths --exclude 2,11,253
+        let input = include_bytes!("test_lsblk_output_details.json");
+
+        let expected = vec![
+            LsBlkDevice {
+                name: "/dev/sdb".into(),
+                device_type: "disk".into(),
+                size: 64_424_509_440,
+                model: Some("QEMU HARDDISK".into()),
+                serial: Some("QM00003".into()),
+            },
+            LsBlkDevice {
+                name: "/dev/sda".into(),
+                device_type: "disk".into(),
+                size: 17_179_869_184,
+                model: Some("QEMU HARDDISK".into()),
+                serial: Some("QM00001".into()),
+            },
+            LsBlkDevice {
+                name: "/dev/sda1".into(),
+                device_type: "part".into(),
+                size: 4_294_967_296,
+                model: None,
+                serial: None,
+            },
+        ];
+
+        let output = LsBlkOutput::parse(input).unwrap();
+        assert_eq!(output.flattened(), expected);
+    }
 }
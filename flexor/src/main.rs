@@ -2,24 +2,30 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
-use std::{path::Path, process::ExitCode};
+use std::{path::Path, process::ExitCode, time::Duration};
 
 use anyhow::{bail, Context, Result};
 use gpt_disk_types::{guid, Guid};
-use libchromeos::{panic_handler, syslog};
+use libchromeos::{panic_handler, retry::retry_with_backoff, syslog};
 use log::{error, info};
-use nix::sys::reboot::reboot;
+use nix::sys::reboot::{reboot, RebootMode};
+
+use crate::config::{ImageSource, InstallConfig, PowerAction};
+use crate::network::{HttpClient, Network};
 
 mod cgpt;
 mod chromeos_install;
+mod config;
 mod disk;
 mod gpt;
 mod lsblk;
 mod mount;
+mod network;
 mod util;
 
 const FLEXOR_TAG: &str = "flexor";
 const FLEX_IMAGE_FILENAME: &str = "flex_image.tar.xz";
+const FLEX_CONFIG_FILENAME: &str = "flex_config.json";
 const FLEXOR_LOG_FILE: &str = "/var/log/messages";
 
 const FLEX_DEPLOY_PART_NUM_BLOCKS: u64 = 8_000_000_000 / 512;
@@ -29,8 +35,30 @@ const FLEX_DEPLOY_PART_NUM: u32 = 13;
 const STATEFUL_PARTITION_LABEL: &str = "STATE";
 const STATEFUL_PARTITION_NUM: u32 = 1;
 
+const INSTALL_ATTEMPTS: u32 = 3;
+const INSTALL_RETRY_DELAY: Duration = Duration::from_secs(1);
+
 const DATA_PART_GUID: Guid = guid!("e160967d-9493-4ba8-8153-f0dc8ac4f7b7");
 
+/// Downloads the ChromeOS Flex image to rootfs (residing in RAM), see
+/// [`copy_image_to_rootfs`]. A partial download from an earlier attempt is
+/// resumed.
+fn download_image_to_rootfs(
+    network_setup: &dyn Network,
+    client: &dyn HttpClient,
+    url: &str,
+    sha256: &str,
+) -> Result<()> {
+    network_setup.bring_up()?;
+    network::download(
+        client,
+        url,
+        sha256,
+        &Path::new("/root").join(FLEX_IMAGE_FILENAME),
+    )
+    .context("Unable to download the image to rootfs")
+}
+
 /// Copies the ChromeOS Flex image to rootfs (residing in RAM). This is done
 /// since we are about to repartition the disk and can't loose the image. Since
 /// the image size is about 2.5GB, we assume that much free space in RAM.
@@ -111,28 +139,59 @@ fn perform_installation(disk_path: &Path) -> Result<()> {
     disk::try_remove_thirteenth_partition(disk_path)
 }
 
+/// Performs the power action requested by the flex config once flexor is done.
+fn perform_power_action(action: PowerAction) -> Result<()> {
+    match action {
+        PowerAction::Reboot => {
+            info!("Rebooting, keep fingers crossed");
+            reboot(RebootMode::RB_AUTOBOOT).context("Unable to reboot")?;
+        }
+        PowerAction::Shutdown => {
+            info!("Powering off");
+            reboot(RebootMode::RB_POWER_OFF).context("Unable to power off")?;
+        }
+        PowerAction::Wait => {
+            info!("Leaving the machine running as requested");
+        }
+    }
+
+    Ok(())
+}
+
 /// Installs ChromeOS Flex and retries the actual installation steps at most three times.
-fn run(disk_path: &Path) -> Result<()> {
+fn run(config: &InstallConfig) -> Result<()> {
     info!("Start Flex-ing");
-    copy_image_to_rootfs(disk_path)?;
-
-    // Try installing on the device three times at most.
-    for _ in 0..3 {
-        match perform_installation(disk_path) {
-            Ok(_) => {
-                // On success we reboot and end execution.
-                info!("Rebooting into ChromeOS Flex, keep fingers crossed");
-                reboot(nix::sys::reboot::RebootMode::RB_AUTOBOOT)
-                    .context("Unable to reboot after successful installation")?;
-                return Ok(());
-            }
-            Err(err) => {
-                error!("Flexor couldn't complete due to error: {err}");
-            }
+    if let Some(payload_disk) = &config.payload_disk {
+        if config.image_source == ImageSource::Partition {
+            copy_image_to_rootfs(payload_disk)?;
         }
     }
 
-    Ok(())
+    // Try installing on the device three times at most. Network downloads are
+    // part of the attempts, so network failures are retried as well.
+    let mut image_downloaded = false;
+    retry_with_backoff(INSTALL_ATTEMPTS, INSTALL_RETRY_DELAY, |attempt| {
+        if let ImageSource::Url { url, sha256 } = &config.image_source {
+            if !image_downloaded {
+                download_image_to_rootfs(&network::Dhcpcd, &network::Curl, url, sha256).map_err(
+                    |err| {
+                        error!("Flexor couldn't download the image in attempt {attempt}: {err:#}");
+                        err
+                    },
+                )?;
+                image_downloaded = true;
+            }
+        }
+        perform_installation(&config.target_disk).map_err(|err| {
+            error!("Flexor couldn't complete attempt {attempt} due to error: {err}");
+            err
+        })
+    })
+    .context("Installation failed three times, giving up")?;
+
+    // On success we end execution with the requested power action.
+    info!("Successfully installed ChromeOS Flex");
+    perform_power_action(config.on_success)
 }
 
 /// Tries to save logs to the disk depending on what state the installation fails in.
@@ -194,21 +253,26 @@ fn main() -> ExitCode {
     }
 
     info!("Hello from Flexor!");
-    let disk_path = match disk::get_target_device().context("Error getting the target disk") {
-        Ok(path) => path,
+    let config = match InstallConfig::new().context("Error getting the install config") {
+        Ok(config) => config,
         Err(err) => {
-            error!("Error selecting the target disk: {err}");
+            error!("Error selecting the target disk: {err:#}");
             return ExitCode::FAILURE;
         }
     };
 
-    if let Err(err) = run(&disk_path) {
+    if let Err(err) = run(&config) {
         error!("Unable to perform installation due to error: {err}");
 
-        // If we weren't successful, try to save the logs.
-        if let Err(err) = try_safe_logs(&disk_path) {
+        // If we weren't successful, try to save the logs. If the target disk
+        // differs from the payload disk, the data partition is still intact.
+        let log_disk = config.payload_disk.as_ref().unwrap_or(&config.target_disk);
+        if let Err(err) = try_safe_logs(log_disk) {
             error!("Unable to save logs due to: {err}")
         }
+        if let Err(err) = perform_power_action(config.on_failure) {
+            error!("Unable to perform the failure action due to: {err}")
+        }
         // TODO(b/314965086): Add an error screen displaying the log.
         ExitCode::FAILURE
     } else {
@@ -0,0 +1,371 @@
+// Copyright 2024 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process::{Child, ChildStdout, Command, Stdio};
+
+use anyhow::{bail, Context, Result};
+use log::{info, warn};
+use sha2::{Digest, Sha256};
+
+use crate::util;
+
+/// How many bytes are read from the network before they are written out.
+const DOWNLOAD_CHUNK_SIZE: usize = 1 << 20;
+/// How often the download progress is logged, in bytes.
+const PROGRESS_LOG_INTERVAL: u64 = 256 << 20;
+/// Exit code of curl if the server doesn't support resuming a download.
+const CURL_RANGE_ERROR: i32 = 33;
+
+/// Brings up networking in the installer environment.
+pub trait Network {
+    fn bring_up(&self) -> Result<()>;
+}
+
+/// Configures the network using the dhcpcd binary of the installer environment.
+pub struct Dhcpcd;
+
+impl Network for Dhcpcd {
+    fn bring_up(&self) -> Result<()> {
+        let mut cmd = Command::new("dhcpcd");
+        // Exit once an address has been configured instead of staying around
+        // as a daemon, we only need the network for the download.
+        cmd.arg("--oneshot").arg("--waitip");
+        util::execute_command(cmd).context("Unable to configure the network")
+    }
+}
+
+/// A GET request for the contents of `url`, starting at `offset`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RangeRequest<'a> {
+    pub url: &'a str,
+    pub offset: u64,
+}
+
+/// Performs HTTP requests. This is a trait so that the download logic can be
+/// tested without a server.
+pub trait HttpClient {
+    /// Starts `request` and returns a reader for the response body. Reading
+    /// fails with [`io::ErrorKind::Unsupported`] if the server can't serve the
+    /// body from the requested offset.
+    fn get(&self, request: &RangeRequest) -> Result<Box<dyn Read>>;
+}
+
+/// Performs HTTP requests using the curl binary of the installer environment.
+pub struct Curl;
+
+impl Curl {
+    fn command(request: &RangeRequest) -> Command {
+        let mut cmd = Command::new("curl");
+        cmd.args(["--fail", "--silent", "--show-error", "--location"]);
+        // Never fall back to plain HTTP, also not on redirects.
+        cmd.args(["--proto", "=https", "--proto-redir", "=https"]);
+        cmd.args(["--connect-timeout", "30"]);
+        if request.offset > 0 {
+            // Unlike a plain range header, this makes curl fail if the server
+            // responds with the whole file.
+            cmd.arg("--continue-at").arg(request.offset.to_string());
+        }
+        cmd.arg(request.url);
+        cmd
+    }
+}
+
+impl HttpClient for Curl {
+    fn get(&self, request: &RangeRequest) -> Result<Box<dyn Read>> {
+        let mut cmd = Self::command(request);
+        info!("Executing command: {:?}", cmd);
+        let mut child = cmd
+            .stdout(Stdio::piped())
+            .spawn()
+            .context("Unable to execute curl")?;
+        let stdout = child
+            .stdout
+            .take()
+            .context("Unable to get the output of curl")?;
+        Ok(Box::new(CurlBody { child, stdout }))
+    }
+}
+
+/// The response body streamed from a curl process. The exit status of curl is
+/// checked once the body is exhausted, so truncated downloads are detected.
+struct CurlBody {
+    child: Child,
+    stdout: ChildStdout,
+}
+
+impl Read for CurlBody {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.stdout.read(buf)?;
+        if read == 0 && !buf.is_empty() {
+            let status = self.child.wait()?;
+            if status.code() == Some(CURL_RANGE_ERROR) {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "the server doesn't support resuming downloads",
+                ));
+            }
+            if !status.success() {
+                return Err(io::Error::other(format!("curl failed with {status}")));
+            }
+        }
+        Ok(read)
+    }
+}
+
+impl Drop for CurlBody {
+    fn drop(&mut self) {
+        // Only needed if the body wasn't read to the end, so errors are
+        // expected here.
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Downloads `url` to `dst` and checks that the SHA-256 digest of the
+/// contents is `sha256`. The digest is computed while the download streams
+/// to disk.
+///
+/// If `dst` already contains the start of the file from an earlier attempt,
+/// the download resumes from its end. A file failing the digest check is
+/// removed, so the next attempt starts over.
+pub fn download(client: &dyn HttpClient, url: &str, sha256: &str, dst: &Path) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(dst)
+        .with_context(|| format!("Unable to open {}", dst.display()))?;
+
+    // Hash what earlier attempts have downloaded already.
+    let mut hasher = Sha256::new();
+    let offset = io::copy(&mut file, &mut hasher).context("Unable to read the partial download")?;
+    if offset > 0 {
+        if to_hex(&hasher.clone().finalize()) == sha256.to_ascii_lowercase() {
+            info!("{} was downloaded completely before", dst.display());
+            return Ok(());
+        }
+        info!("Resuming the download at {offset} bytes");
+    }
+
+    match fetch(client, url, offset, &mut file, &mut hasher) {
+        Err(err) if offset > 0 && is_range_unsupported(&err) => {
+            warn!("Unable to resume the download, starting over: {err:#}");
+            file.set_len(0)
+                .context("Unable to discard the partial download")?;
+            hasher = Sha256::new();
+            fetch(client, url, 0, &mut file, &mut hasher)?;
+        }
+        result => result?,
+    }
+
+    let digest = to_hex(&hasher.finalize());
+    if digest != sha256.to_ascii_lowercase() {
+        drop(file);
+        if let Err(err) = std::fs::remove_file(dst) {
+            warn!("Unable to remove the corrupt download: {err}");
+        }
+        bail!("The downloaded image has SHA-256 {digest}, expected {sha256}");
+    }
+
+    info!("Downloaded and verified {}", dst.display());
+    Ok(())
+}
+
+/// Streams the body of `url` from `offset` into `file` and `hasher`.
+fn fetch(
+    client: &dyn HttpClient,
+    url: &str,
+    offset: u64,
+    file: &mut File,
+    hasher: &mut Sha256,
+) -> Result<()> {
+    info!("Downloading {url}");
+    let mut body = client.get(&RangeRequest { url, offset })?;
+
+    let mut buf = vec![0; DOWNLOAD_CHUNK_SIZE];
+    let mut downloaded = offset;
+    let mut next_progress_log = offset + PROGRESS_LOG_INTERVAL;
+    loop {
+        let read = match body.read(&mut buf) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err).context("Unable to download the image"),
+        };
+        file.write_all(&buf[..read])
+            .context("Unable to write the downloaded image")?;
+        hasher.update(&buf[..read]);
+
+        downloaded += read as u64;
+        if downloaded >= next_progress_log {
+            info!("Downloaded {} MiB", downloaded >> 20);
+            next_progress_log += PROGRESS_LOG_INTERVAL;
+        }
+    }
+    file.sync_all()
+        .context("Unable to sync the downloaded image")?;
+
+    info!("Download finished after {downloaded} bytes");
+    Ok(())
+}
+
+fn is_range_unsupported(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        matches!(cause.downcast_ref::<io::Error>(), Some(err) if err.kind() == io::ErrorKind::Unsupported)
+    })
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+
+    const CONTENTS: &[u8] = b"ChromeOS Flex image";
+    // SHA-256 of CONTENTS.
+    const CONTENTS_SHA256: &str =
+        "c96f06234f804d8c4ed88cf07a2d1e26ab3643a4575e997b0ae97bd5d646d6ad";
+    const URL: &str = "https://example.com/flex_image.tar.xz";
+
+    /// Serves CONTENTS and records the requests. A body can be cut short
+    /// after `fail_after` bytes to simulate a network error.
+    #[derive(Default)]
+    struct FakeHttpClient {
+        requests: RefCell<Vec<(String, u64)>>,
+        fail_after: Option<usize>,
+        supports_ranges: bool,
+    }
+
+    /// A body that returns an error once `data` is exhausted.
+    struct FailingBody {
+        data: io::Cursor<Vec<u8>>,
+        error: io::ErrorKind,
+    }
+
+    impl Read for FailingBody {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.data.read(buf)? {
+                0 => Err(io::Error::new(self.error, "connection reset")),
+                read => Ok(read),
+            }
+        }
+    }
+
+    impl HttpClient for FakeHttpClient {
+        fn get(&self, request: &RangeRequest) -> Result<Box<dyn Read>> {
+            self.requests
+                .borrow_mut()
+                .push((request.url.to_owned(), request.offset));
+            if request.offset > 0 && !self.supports_ranges {
+                return Ok(Box::new(FailingBody {
+                    data: io::Cursor::new(vec![]),
+                    error: io::ErrorKind::Unsupported,
+                }));
+            }
+
+            let data = CONTENTS[request.offset as usize..].to_vec();
+            match self.fail_after {
+                Some(len) => Ok(Box::new(FailingBody {
+                    data: io::Cursor::new(data[..len].to_vec()),
+                    error: io::ErrorKind::ConnectionReset,
+                })),
+                None => Ok(Box::new(io::Cursor::new(data))),
+            }
+        }
+    }
+
+    #[test]
+    fn test_download() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let dst = tempdir.path().join("image");
+        let client = FakeHttpClient::default();
+
+        download(&client, URL, CONTENTS_SHA256, &dst).unwrap();
+        assert_eq!(std::fs::read(&dst).unwrap(), CONTENTS);
+        assert_eq!(*client.requests.borrow(), vec![(URL.to_owned(), 0)]);
+
+        // A complete download is not fetched again.
+        download(&client, URL, &CONTENTS_SHA256.to_uppercase(), &dst).unwrap();
+        assert_eq!(client.requests.borrow().len(), 1);
+    }
+
+    #[test]
+    fn test_download_digest_mismatch() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let dst = tempdir.path().join("image");
+        let client = FakeHttpClient::default();
+
+        let digest = "0".repeat(64);
+        let err = download(&client, URL, &digest, &dst).unwrap_err();
+        assert!(err.to_string().contains(CONTENTS_SHA256));
+        // The next attempt has to start over.
+        assert!(!dst.exists());
+    }
+
+    #[test]
+    fn test_download_resume() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let dst = tempdir.path().join("image");
+
+        let client = FakeHttpClient {
+            fail_after: Some(5),
+            supports_ranges: true,
+            ..Default::default()
+        };
+        assert!(download(&client, URL, CONTENTS_SHA256, &dst).is_err());
+        assert_eq!(std::fs::read(&dst).unwrap(), &CONTENTS[..5]);
+
+        let client = FakeHttpClient {
+            supports_ranges: true,
+            ..Default::default()
+        };
+        download(&client, URL, CONTENTS_SHA256, &dst).unwrap();
+        assert_eq!(std::fs::read(&dst).unwrap(), CONTENTS);
+        assert_eq!(*client.requests.borrow(), vec![(URL.to_owned(), 5)]);
+    }
+
+    #[test]
+    fn test_download_resume_unsupported() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let dst = tempdir.path().join("image");
+        std::fs::write(&dst, &CONTENTS[..5]).unwrap();
+
+        let client = FakeHttpClient::default();
+        download(&client, URL, CONTENTS_SHA256, &dst).unwrap();
+        assert_eq!(std::fs::read(&dst).unwrap(), CONTENTS);
+        assert_eq!(
+            *client.requests.borrow(),
+            vec![(URL.to_owned(), 5), (URL.to_owned(), 0)]
+        );
+    }
+
+    #[test]
+    fn test_curl_command() {
+        let args = |offset| -> Vec<String> {
+            Curl::command(&RangeRequest { url: URL, offset })
+                .get_args()
+                .map(|arg| arg.to_str().unwrap().to_owned())
+                .collect()
+        };
+
+        let args_from_start = args(0);
+        assert!(!args_from_start.contains(&"--continue-at".to_owned()));
+        assert_eq!(args_from_start.last().unwrap(), URL);
+
+        let args_resumed = args(1234);
+        let pos = args_resumed
+            .iter()
+            .position(|arg| arg == "--continue-at")
+            .unwrap();
+        assert_eq!(args_resumed[pos + 1], "1234");
+        assert_eq!(args_resumed.last().unwrap(), URL);
+    }
+}
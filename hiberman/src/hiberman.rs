@@ -11,11 +11,13 @@ pub mod metrics;
 mod cryptohome;
 mod device_mapper;
 mod files;
+mod hibernate_dbus;
 mod hiberutil;
 mod ioctl;
 mod lvm;
 mod mmapbuf;
 mod powerd;
+mod readiness;
 mod resume;
 mod resume_dbus;
 mod resume_init;
@@ -23,9 +25,12 @@ mod snapdev;
 mod snapwatch;
 mod swap_management;
 mod suspend;
+mod throttle;
 mod update_engine;
 mod volume;
 
+use crate::hibernate_dbus::run_hibernate_service;
+use crate::hibernate_dbus::send_hibernate_request;
 use crate::resume_dbus::send_abort;
 
 pub use hiberutil::record_user_logout;
@@ -33,6 +38,7 @@ pub use hiberutil::AbortResumeOptions;
 pub use hiberutil::HibernateOptions;
 pub use hiberutil::ResumeInitOptions;
 pub use hiberutil::ResumeOptions;
+pub use throttle::IoPriorityClass;
 
 use crate::snapdev::SnapshotDevice;
 use crate::snapdev::SnapshotMode;
@@ -54,6 +60,20 @@ pub fn hibernate(options: HibernateOptions) -> Result<()> {
     conductor.hibernate(options)
 }
 
+/// Ask the hiberman service to hibernate the system. The request goes through
+/// the same preflight checks as requests from powerd, and hibernate is started
+/// asynchronously by the service.
+pub fn request_hibernate(options: HibernateOptions) -> Result<()> {
+    send_hibernate_request(&options)
+}
+
+/// Serve the hibernate D-Bus interface, which lets powerd check whether the
+/// system can hibernate and request hibernate. This does not return unless
+/// the D-Bus connection fails.
+pub fn serve() -> Result<()> {
+    run_hibernate_service()
+}
+
 /// Prepare the system for resume. This is run very early in boot (from
 /// chromeos_startup) before the stateful partition has been mounted. It checks
 /// the hibernate cookie and clears it. If the cookie was set, it sets up
@@ -0,0 +1,499 @@
+// Copyright 2024 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Handles the D-Bus interface powerd uses to check whether the system can
+//! hibernate and to request hibernate.
+
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::channel;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use dbus::blocking::Connection;
+use dbus::channel::MatchingReceiver; // For start_receive
+use dbus::channel::Sender as _; // For send
+use dbus::message::MatchRule;
+use dbus::Message;
+use dbus_crossroads::Crossroads;
+use dbus_crossroads::IfaceBuilder;
+use dbus_crossroads::MethodErr;
+use log::debug;
+use log::error;
+use log::info;
+
+use crate::hiberutil::HibernateOptions;
+use crate::readiness::check_readiness;
+use crate::readiness::describe_cookie;
+use crate::readiness::last_failure;
+use crate::readiness::record_hibernate_result;
+use crate::readiness::HibernateReadiness;
+use crate::readiness::ReadinessSource;
+use crate::readiness::SystemReadinessSource;
+use crate::throttle::IoPriorityClass;
+use crate::volume::VOLUME_MANAGER;
+
+/// The D-Bus name of the hibernate service. It differs from the name of the
+/// resume D-Bus server so that the resume interface is always reachable while
+/// both processes run.
+pub const HIBERMAN_HIBERNATE_DBUS_NAME: &str = "org.chromium.HibernateManager";
+const HIBERMAN_HIBERNATE_DBUS_PATH: &str = "/org/chromium/HibernateManager";
+const HIBERMAN_HIBERNATE_DBUS_INTERFACE: &str = "org.chromium.HibernateInterface";
+const HIBERNATE_PROGRESS_SIGNAL: &str = "HibernateProgress";
+
+/// How long the service waits for D-Bus messages while idle.
+const IDLE_PROCESS_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long the service waits for D-Bus messages while a hibernate is in
+/// progress, bounding the delay of the progress signals.
+const BUSY_PROCESS_TIMEOUT: Duration = Duration::from_millis(500);
+
+// Define the timeout to connect to the dbus system.
+const DEFAULT_DBUS_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Progress of a hibernate requested over D-Bus.
+#[derive(Debug, PartialEq, Eq)]
+pub enum HibernateProgress {
+    /// The preflight checks passed and hibernate started.
+    Started,
+    /// The system resumed from hibernate, or the dry run completed.
+    Resumed,
+    /// Hibernate failed with the given error.
+    Failed(String),
+}
+
+impl HibernateProgress {
+    fn stage(&self) -> &'static str {
+        match self {
+            Self::Started => "started",
+            Self::Resumed => "resumed",
+            Self::Failed(_) => "failed",
+        }
+    }
+
+    fn error(&self) -> &str {
+        match self {
+            Self::Failed(e) => e,
+            _ => "",
+        }
+    }
+}
+
+/// The system operations behind the D-Bus methods, abstracted for testing.
+pub trait HibernateBackend: Send + Sync + 'static {
+    /// Runs `f` with the source of the readiness inputs.
+    fn with_readiness_source<T>(&self, f: impl FnOnce(&dyn ReadinessSource) -> T) -> T;
+    /// Hibernates the system, returning after resume or upon failure.
+    fn hibernate(&self, options: HibernateOptions) -> Result<()>;
+}
+
+struct SystemBackend;
+
+impl HibernateBackend for SystemBackend {
+    fn with_readiness_source<T>(&self, f: impl FnOnce(&dyn ReadinessSource) -> T) -> T {
+        let volume_manager = VOLUME_MANAGER.read().unwrap();
+        f(&SystemReadinessSource::new(&volume_manager))
+    }
+
+    fn hibernate(&self, options: HibernateOptions) -> Result<()> {
+        crate::hibernate(options)?;
+        // The suspend conductor does not return its failures, it only records
+        // them.
+        match last_failure() {
+            Some(e) => Err(anyhow!(e)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Implements the methods of the hibernate D-Bus interface.
+pub struct HibernateRequestHandler<B: HibernateBackend> {
+    backend: Arc<B>,
+    in_progress: Arc<AtomicBool>,
+    progress_sender: Sender<HibernateProgress>,
+}
+
+impl<B: HibernateBackend> HibernateRequestHandler<B> {
+    pub fn new(backend: B, progress_sender: Sender<HibernateProgress>) -> Self {
+        Self {
+            backend: Arc::new(backend),
+            in_progress: Arc::new(AtomicBool::new(false)),
+            progress_sender,
+        }
+    }
+
+    /// Returns the flag which is set while a requested hibernate runs.
+    pub fn in_progress(&self) -> Arc<AtomicBool> {
+        self.in_progress.clone()
+    }
+
+    pub fn check_readiness(&self) -> Result<HibernateReadiness> {
+        self.backend
+            .with_readiness_source(|source| check_readiness(source))
+    }
+
+    /// Runs the preflight checks and starts hibernate on a separate thread if
+    /// they pass. The progress is reported through the progress sender.
+    pub fn request_hibernate(&self, options: HibernateOptions) -> Result<thread::JoinHandle<()>> {
+        if self.in_progress.swap(true, Ordering::SeqCst) {
+            bail!("Hibernate is already in progress");
+        }
+        let vetoed = match self.check_readiness() {
+            Ok(readiness) => readiness.veto().map(|veto| {
+                anyhow::Error::from(veto.to_error()).context(format!("Hibernate vetoed: {veto}"))
+            }),
+            Err(e) => Some(e),
+        };
+        if let Some(e) = vetoed {
+            self.in_progress.store(false, Ordering::SeqCst);
+            return Err(e);
+        }
+
+        let backend = self.backend.clone();
+        let in_progress = self.in_progress.clone();
+        let progress_sender = self.progress_sender.clone();
+        Ok(thread::spawn(move || {
+            // The receiver is gone only when the service is shutting down.
+            let _ = progress_sender.send(HibernateProgress::Started);
+            let result = backend.hibernate(options);
+            record_hibernate_result(&result);
+            let _ = progress_sender.send(match &result {
+                Ok(()) => HibernateProgress::Resumed,
+                Err(e) => HibernateProgress::Failed(format!("{e:#}")),
+            });
+            in_progress.store(false, Ordering::SeqCst);
+        }))
+    }
+}
+
+/// Parses the options of the RequestHibernate method.
+fn parse_hibernate_options(
+    dry_run: bool,
+    reboot: bool,
+    io_priority: &str,
+) -> Result<HibernateOptions> {
+    let io_priority = if io_priority.is_empty() {
+        None
+    } else {
+        Some(io_priority.parse::<IoPriorityClass>()?)
+    };
+    Ok(HibernateOptions {
+        dry_run,
+        reboot,
+        io_priority,
+    })
+}
+
+/// Serves the hibernate D-Bus interface until the connection fails.
+pub fn run_hibernate_service() -> Result<()> {
+    let conn = Connection::new_system().context("Failed to start local dbus connection")?;
+    conn.request_name(HIBERMAN_HIBERNATE_DBUS_NAME, false, false, true)
+        .context("Failed to request dbus name")?;
+
+    let (progress_sender, progress_receiver) = channel();
+    let handler = HibernateRequestHandler::new(SystemBackend, progress_sender);
+    let in_progress = handler.in_progress();
+
+    let mut crossroads = Crossroads::new();
+    let iface_token = crossroads.register(
+        HIBERMAN_HIBERNATE_DBUS_INTERFACE,
+        |b: &mut IfaceBuilder<HibernateRequestHandler<SystemBackend>>| {
+            b.method(
+                "CheckHibernateReadiness",
+                (),
+                (
+                    "ready",
+                    "veto_reason",
+                    "cookie",
+                    "hiberimage_exists",
+                    "space_reserved",
+                    "free_space_bytes",
+                    "required_space_bytes",
+                    "update_pending",
+                    "last_failure",
+                ),
+                move |_, handler, ()| {
+                    let readiness = handler.check_readiness().map_err(|e| {
+                        error!("Failed to check hibernate readiness: {e:?}");
+                        MethodErr::failed(&format!("{e:#}"))
+                    })?;
+                    let veto = readiness.veto();
+                    Ok((
+                        veto.is_none(),
+                        veto.map(|veto| veto.to_string()).unwrap_or_default(),
+                        describe_cookie(&readiness.cookie).to_string(),
+                        readiness.hiberimage_exists,
+                        readiness.hiberimage_exists && readiness.free_space.is_none(),
+                        readiness.free_space.unwrap_or_default(),
+                        readiness.required_space,
+                        readiness.update_pending,
+                        readiness.last_failure.unwrap_or_default(),
+                    ))
+                },
+            );
+            b.method(
+                "RequestHibernate",
+                ("dry_run", "reboot", "io_priority"),
+                (),
+                move |_, handler, (dry_run, reboot, io_priority): (bool, bool, String)| {
+                    let options = parse_hibernate_options(dry_run, reboot, &io_priority)
+                        .map_err(|e| MethodErr::invalid_arg(&format!("{e:#}")))?;
+                    match handler.request_hibernate(options) {
+                        Ok(_) => {
+                            info!("Hibernate requested over D-Bus");
+                            Ok(())
+                        }
+                        Err(e) => {
+                            info!("Rejected hibernate request: {e:#}");
+                            Err(MethodErr::failed(&format!("{e:#}")))
+                        }
+                    }
+                },
+            );
+            b.signal::<(String, String), _>(HIBERNATE_PROGRESS_SIGNAL, ("stage", "error"));
+        },
+    );
+    crossroads.insert(HIBERMAN_HIBERNATE_DBUS_PATH, &[iface_token], handler);
+
+    conn.start_receive(
+        MatchRule::new_method_call(),
+        Box::new(move |msg, conn| {
+            if let Err(e) = crossroads.handle_message(msg, conn) {
+                error!("Failed to handle message: {:?}", e);
+                false
+            } else {
+                true
+            }
+        }),
+    );
+
+    loop {
+        // Read the flag before draining the progress: the hibernate thread
+        // sends its last progress before clearing the flag, so it is never
+        // left behind for an idle wait.
+        let timeout = if in_progress.load(Ordering::SeqCst) {
+            BUSY_PROCESS_TIMEOUT
+        } else {
+            IDLE_PROCESS_TIMEOUT
+        };
+        for progress in progress_receiver.try_iter() {
+            debug!("Hibernate progress: {progress:?}");
+            let signal = Message::signal(
+                &HIBERMAN_HIBERNATE_DBUS_PATH.into(),
+                &HIBERMAN_HIBERNATE_DBUS_INTERFACE.into(),
+                &HIBERNATE_PROGRESS_SIGNAL.into(),
+            )
+            .append2(progress.stage(), progress.error());
+            if conn.send(signal).is_err() {
+                error!("Failed to send the hibernate progress {progress:?}");
+            }
+        }
+        conn.process(timeout)
+            .context("Failed to process dbus message")?;
+    }
+}
+
+/// Requests the hiberman service to hibernate over D-Bus, going through the
+/// same preflight checks as powerd does.
+pub fn send_hibernate_request(options: &HibernateOptions) -> Result<()> {
+    let conn = Connection::new_system().context("Failed to connect to dbus for hibernate")?;
+    let proxy = conn.with_proxy(
+        HIBERMAN_HIBERNATE_DBUS_NAME,
+        HIBERMAN_HIBERNATE_DBUS_PATH,
+        DEFAULT_DBUS_TIMEOUT,
+    );
+
+    let io_priority = options
+        .io_priority
+        .map(|class| class.as_str())
+        .unwrap_or("");
+    proxy
+        .method_call::<(), _, _, _>(
+            HIBERMAN_HIBERNATE_DBUS_INTERFACE,
+            "RequestHibernate",
+            (options.dry_run, options.reboot, io_priority),
+        )
+        .context("Failed to send hibernate request")?;
+    debug!("Sent RequestHibernate request");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::Receiver;
+    use std::sync::Mutex;
+
+    use system_api::update_engine::Operation;
+
+    use super::*;
+    use crate::readiness::tests::FakeSource;
+    use crate::readiness::VetoReason;
+    use crate::resume_dbus::HIBERMAN_DBUS_NAME;
+
+    struct FakeBackend {
+        operation: Operation,
+        hibernate_error: Option<&'static str>,
+        hibernate_calls: Mutex<Vec<(bool, bool)>>,
+    }
+
+    impl FakeBackend {
+        fn new(operation: Operation, hibernate_error: Option<&'static str>) -> Self {
+            Self {
+                operation,
+                hibernate_error,
+                hibernate_calls: Mutex::new(Vec::new()),
+            }
+        }
+    }
+
+    impl HibernateBackend for FakeBackend {
+        fn with_readiness_source<T>(&self, f: impl FnOnce(&dyn ReadinessSource) -> T) -> T {
+            f(&FakeSource {
+                operation: Ok(self.operation),
+                ..Default::default()
+            })
+        }
+
+        fn hibernate(&self, options: HibernateOptions) -> Result<()> {
+            self.hibernate_calls
+                .lock()
+                .unwrap()
+                .push((options.dry_run, options.reboot));
+            match self.hibernate_error {
+                Some(e) => Err(anyhow!(e)),
+                None => Ok(()),
+            }
+        }
+    }
+
+    fn new_handler(
+        backend: FakeBackend,
+    ) -> (
+        HibernateRequestHandler<FakeBackend>,
+        Receiver<HibernateProgress>,
+    ) {
+        let (sender, receiver) = channel();
+        (HibernateRequestHandler::new(backend, sender), receiver)
+    }
+
+    // Serializes the tests running hibernate, as they record the last failure
+    // of the process.
+    static HIBERNATE_LOCK: Mutex<()> = Mutex::new(());
+
+    fn dry_run() -> HibernateOptions {
+        HibernateOptions {
+            dry_run: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn request_hibernate() {
+        let _lock = HIBERNATE_LOCK.lock().unwrap();
+        let (handler, progress) = new_handler(FakeBackend::new(Operation::IDLE, None));
+        assert_eq!(handler.check_readiness().unwrap().veto(), None);
+
+        handler
+            .request_hibernate(dry_run())
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(
+            progress.try_iter().collect::<Vec<_>>(),
+            vec![HibernateProgress::Started, HibernateProgress::Resumed]
+        );
+        assert_eq!(
+            *handler.backend.hibernate_calls.lock().unwrap(),
+            vec![(true, false)]
+        );
+        assert!(!handler.in_progress().load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn request_hibernate_vetoed_by_update_engine() {
+        let (handler, progress) =
+            new_handler(FakeBackend::new(Operation::UPDATED_NEED_REBOOT, None));
+        let readiness = handler.check_readiness().unwrap();
+        assert!(readiness.update_pending);
+        assert_eq!(readiness.veto(), Some(VetoReason::UpdateEngineActive));
+
+        assert!(handler.request_hibernate(dry_run()).is_err());
+        assert!(progress.try_iter().next().is_none());
+        assert!(handler.backend.hibernate_calls.lock().unwrap().is_empty());
+        // A vetoed request does not block later ones.
+        assert!(!handler.in_progress().load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn request_hibernate_in_progress() {
+        let (handler, _progress) = new_handler(FakeBackend::new(Operation::IDLE, None));
+        handler.in_progress().store(true, Ordering::SeqCst);
+        assert!(handler.request_hibernate(dry_run()).is_err());
+        assert!(handler.backend.hibernate_calls.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn request_hibernate_failure() {
+        let _lock = HIBERNATE_LOCK.lock().unwrap();
+        let (handler, progress) =
+            new_handler(FakeBackend::new(Operation::IDLE, Some("snapshot failed")));
+        handler
+            .request_hibernate(dry_run())
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(
+            progress.try_iter().collect::<Vec<_>>(),
+            vec![
+                HibernateProgress::Started,
+                HibernateProgress::Failed("snapshot failed".to_string())
+            ]
+        );
+        assert_eq!(
+            handler.check_readiness().unwrap().last_failure,
+            Some("snapshot failed".to_string())
+        );
+
+        // A successful hibernate clears the failure.
+        let (handler, _progress) = new_handler(FakeBackend::new(Operation::IDLE, None));
+        handler
+            .request_hibernate(dry_run())
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(handler.check_readiness().unwrap().last_failure, None);
+    }
+
+    #[test]
+    fn hibernate_options() {
+        let options = parse_hibernate_options(true, true, "idle").unwrap();
+        assert!(options.dry_run);
+        assert!(options.reboot);
+        assert_eq!(options.io_priority, Some(IoPriorityClass::Idle));
+        assert_eq!(
+            parse_hibernate_options(false, false, "")
+                .unwrap()
+                .io_priority,
+            None
+        );
+        assert!(parse_hibernate_options(false, false, "realtime").is_err());
+    }
+
+    #[test]
+    fn dbus_name_not_shared_with_resume() {
+        // The resume D-Bus server must own its name whether or not the service
+        // runs, so the service must neither request it nor be allowed to.
+        assert_ne!(HIBERMAN_HIBERNATE_DBUS_NAME, HIBERMAN_DBUS_NAME);
+        let policy = include_str!("../dbus/org.chromium.HibernateManager.conf");
+        assert!(policy.contains(&format!("<allow own=\"{HIBERMAN_HIBERNATE_DBUS_NAME}\"/>")));
+        assert!(!policy.contains(&format!("\"{HIBERMAN_DBUS_NAME}\"")));
+        let resume_policy = include_str!("../dbus/org.chromium.Hibernate.conf");
+        assert!(!resume_policy.contains(HIBERMAN_HIBERNATE_DBUS_NAME));
+    }
+}
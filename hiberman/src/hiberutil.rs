@@ -45,6 +45,7 @@ use crate::hiberlog::redirect_log;
 use crate::hiberlog::HiberlogOut;
 use crate::metrics::METRICS_LOGGER;
 use crate::mmapbuf::MmapBuffer;
+use crate::throttle::IoPriorityClass;
 use crate::volume::ActiveMount;
 
 const KEYCTL_PATH: &str = "/bin/keyctl";
@@ -138,6 +139,9 @@ pub enum HibernateError {
 pub struct HibernateOptions {
     pub dry_run: bool,
     pub reboot: bool,
+    /// I/O priority class of the image writer. The writer is not throttled
+    /// if this is None.
+    pub io_priority: Option<IoPriorityClass>,
 }
 
 /// Options taken from the command line affecting resume-init.
@@ -9,6 +9,7 @@ use getopts::{self};
 use hiberman::cookie::HibernateCookieValue;
 use hiberman::AbortResumeOptions;
 use hiberman::HibernateOptions;
+use hiberman::IoPriorityClass;
 use hiberman::ResumeInitOptions;
 use hiberman::ResumeOptions;
 use hiberman::{self};
@@ -163,6 +164,17 @@ fn hiberman_hibernate(args: &mut std::env::Args) -> std::result::Result<(), ()>
         "reboot",
         "Reboot after creating the snapshot image instead of shutting down",
     );
+    opts.optopt(
+        "",
+        "io-priority",
+        "Lower the CPU and I/O priority of the image writer (options are idle or besteffort)",
+        "class",
+    );
+    opts.optflag(
+        "",
+        "dbus",
+        "Request hibernate from the hiberman service, going through the same checks as powerd",
+    );
     let args: Vec<String> = args.collect();
     let matches = match opts.parse(args) {
         Ok(m) => m,
@@ -178,11 +190,32 @@ fn hiberman_hibernate(args: &mut std::env::Args) -> std::result::Result<(), ()>
         return Ok(());
     }
 
+    let io_priority = match matches.opt_str("io-priority") {
+        Some(value) => match value.parse::<IoPriorityClass>() {
+            Ok(class) => Some(class),
+            Err(e) => {
+                error!("{}", e);
+                hibernate_usage(true, &opts);
+                return Err(());
+            }
+        },
+        None => None,
+    };
+
     let options = HibernateOptions {
         dry_run: matches.opt_present("n"),
         reboot: matches.opt_present("r"),
+        io_priority,
     };
 
+    if matches.opt_present("dbus") {
+        if let Err(e) = hiberman::request_hibernate(options) {
+            error!("Failed to request hibernate: {:?}", e);
+            return Err(());
+        }
+        return Ok(());
+    }
+
     if let Err(e) = hiberman::hibernate(options) {
         error!("Failed to hibernate: {:?}", e);
         return Err(());
@@ -365,6 +398,42 @@ fn hiberman_teardown_hiberimage(args: &mut std::env::Args) -> std::result::Resul
     Ok(())
 }
 
+fn service_usage(error: bool, options: &Options) {
+    let brief = r#"Usage: hiberman service
+Serve the D-Bus interface for checking hibernate readiness and requesting
+hibernate.
+"#;
+
+    print_usage(&options.usage(brief), error);
+}
+
+fn hiberman_service(args: &mut std::env::Args) -> std::result::Result<(), ()> {
+    init_logging()?;
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "Print this help text");
+    let args: Vec<String> = args.collect();
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            error!("Failed to parse arguments: {}", e);
+            service_usage(true, &opts);
+            return Err(());
+        }
+    };
+
+    if matches.opt_present("h") {
+        service_usage(false, &opts);
+        return Ok(());
+    }
+
+    if let Err(e) = hiberman::serve() {
+        error!("Failed to serve the hibernate D-Bus interface: {:?}", e);
+        return Err(());
+    }
+
+    Ok(())
+}
+
 fn app_usage(error: bool) {
     let usage_msg = r#"Usage: hiberman subcommand [options]
 This application coordinates suspend-to-disk activities. Try
@@ -377,6 +446,7 @@ Valid subcommands are:
     resume -- Resume the system now.
     abort-resume -- Send an abort request to an in-progress resume.
     cookie -- Read or write the hibernate cookie.
+    service -- Serve the hibernate D-Bus interface.
     teardown-hiberimage -- Tear the hiberimage device down if it exists.
 "#;
     print_usage(usage_msg, error);
@@ -407,6 +477,7 @@ fn hiberman_main() -> std::result::Result<(), ()> {
         "hibernate" => hiberman_hibernate(&mut args),
         "resume-init" => hiberman_resume_init(&mut args),
         "resume" => hiberman_resume(&mut args),
+        "service" => hiberman_service(&mut args),
         "teardown-hiberimage" => hiberman_teardown_hiberimage(&mut args),
         _ => {
             eprintln!("Unknown subcommand: {}", subcommand);
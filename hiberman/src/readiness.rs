@@ -0,0 +1,394 @@
+// Copyright 2024 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Aggregates the preflight checks deciding whether the system can hibernate.
+
+use std::fmt;
+use std::sync::Mutex;
+
+use anyhow::Result;
+use log::info;
+use log::warn;
+use system_api::update_engine::Operation;
+use system_api::update_engine::StatusResult;
+
+use crate::cookie::cookie_description;
+use crate::cookie::get_hibernate_cookie;
+use crate::cookie::HibernateCookieValue;
+use crate::hiberutil::get_ram_size;
+use crate::hiberutil::has_user_logged_out;
+use crate::hiberutil::HibernateError;
+use crate::update_engine;
+use crate::volume::VolumeManager;
+
+/// The error of the last hibernate attempt started by this process, if it
+/// failed.
+static LAST_FAILURE: Mutex<Option<String>> = Mutex::new(None);
+
+/// Reasons why hibernate must not be attempted right now.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VetoReason {
+    /// The hiberimage was never set up.
+    NoHiberimage,
+    /// The hiberimage was torn down because the user logged out.
+    PriorUserLogout,
+    /// The thinpool has not enough space for the hibernate image.
+    InsufficientDiskSpace,
+    /// The update engine is not idle. Hibernating while an update is pending
+    /// could resume into the wrong slot or corrupt the update state.
+    UpdateEngineActive,
+    /// The update engine status could not be queried, so an update might be
+    /// pending.
+    UpdateEngineUnavailable,
+}
+
+impl VetoReason {
+    /// The error reported when hibernate is aborted for this reason.
+    pub fn to_error(self) -> HibernateError {
+        match self {
+            Self::NoHiberimage | Self::PriorUserLogout => HibernateError::NoHiberimageError(),
+            Self::InsufficientDiskSpace => HibernateError::InsufficientDiskSpaceError(),
+            Self::UpdateEngineActive | Self::UpdateEngineUnavailable => {
+                HibernateError::UpdateEngineBusyError()
+            }
+        }
+    }
+}
+
+impl fmt::Display for VetoReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::NoHiberimage => "no hiberimage",
+            Self::PriorUserLogout => "hiberimage torn down by user logout",
+            Self::InsufficientDiskSpace => "insufficient disk space",
+            Self::UpdateEngineActive => "update engine active",
+            Self::UpdateEngineUnavailable => "update engine status unknown",
+        })
+    }
+}
+
+/// Snapshot of the state relevant for deciding whether to hibernate.
+#[derive(Debug, PartialEq, Eq)]
+pub struct HibernateReadiness {
+    /// The hibernate cookie, or None if it could not be read.
+    pub cookie: Option<HibernateCookieValue>,
+    /// Whether the hiberimage is set up.
+    pub hiberimage_exists: bool,
+    /// Whether the hiberimage was torn down because the user logged out.
+    pub prior_user_logout: bool,
+    /// Free space in the thinpool, or None if the hiberimage is missing or
+    /// already thickened, i.e. no more space is needed.
+    pub free_space: Option<u64>,
+    /// Space the hibernate image may need in the thinpool.
+    pub required_space: u64,
+    /// Whether the update engine is doing anything but idling.
+    pub update_pending: bool,
+    /// Whether the update engine status could be queried.
+    pub update_engine_available: bool,
+    /// The error of the last failed hibernate attempt of this process.
+    pub last_failure: Option<String>,
+}
+
+impl HibernateReadiness {
+    /// Returns why hibernate must not be attempted, if anything. The checks
+    /// are evaluated in the same order as hibernate has always done them.
+    pub fn veto(&self) -> Option<VetoReason> {
+        if !self.hiberimage_exists {
+            return Some(if self.prior_user_logout {
+                VetoReason::PriorUserLogout
+            } else {
+                VetoReason::NoHiberimage
+            });
+        }
+        if matches!(self.free_space, Some(free_space) if free_space < self.required_space) {
+            return Some(VetoReason::InsufficientDiskSpace);
+        }
+        if !self.update_engine_available {
+            return Some(VetoReason::UpdateEngineUnavailable);
+        }
+        if self.update_pending {
+            return Some(VetoReason::UpdateEngineActive);
+        }
+        None
+    }
+}
+
+/// The inputs of the readiness checks. This is a trait so that the checks can
+/// be tested without real volumes or an update engine.
+pub trait ReadinessSource {
+    fn hibernate_cookie(&self) -> Result<HibernateCookieValue>;
+    fn hiberimage_exists(&self) -> bool;
+    fn has_user_logged_out(&self) -> bool;
+    fn is_hiberimage_thickened(&self) -> Result<bool>;
+    fn free_thinpool_space(&self) -> Result<u64>;
+    fn ram_size(&self) -> u64;
+    fn update_engine_status(&self) -> Result<StatusResult>;
+}
+
+/// Reads the readiness inputs from the running system.
+pub struct SystemReadinessSource<'a> {
+    volume_manager: &'a VolumeManager,
+}
+
+impl<'a> SystemReadinessSource<'a> {
+    pub fn new(volume_manager: &'a VolumeManager) -> Self {
+        Self { volume_manager }
+    }
+}
+
+impl ReadinessSource for SystemReadinessSource<'_> {
+    fn hibernate_cookie(&self) -> Result<HibernateCookieValue> {
+        get_hibernate_cookie::<&str>(None)
+    }
+
+    fn hiberimage_exists(&self) -> bool {
+        self.volume_manager.hiberimage_exists()
+    }
+
+    fn has_user_logged_out(&self) -> bool {
+        has_user_logged_out()
+    }
+
+    fn is_hiberimage_thickened(&self) -> Result<bool> {
+        self.volume_manager.is_hiberimage_thickened()
+    }
+
+    fn free_thinpool_space(&self) -> Result<u64> {
+        self.volume_manager.get_free_thinpool_space()
+    }
+
+    fn ram_size(&self) -> u64 {
+        get_ram_size()
+    }
+
+    fn update_engine_status(&self) -> Result<StatusResult> {
+        update_engine::get_status()
+    }
+}
+
+/// Collects the readiness of the system to hibernate.
+///
+/// Failing to read the cookie is not fatal since hibernate does not depend on
+/// its current value. Failing to query the disk space is. Failing to query the
+/// update engine vetoes hibernate, as it is not safe to hibernate while an
+/// update might be pending.
+pub fn check_readiness(source: &(impl ReadinessSource + ?Sized)) -> Result<HibernateReadiness> {
+    let cookie = match source.hibernate_cookie() {
+        Ok(cookie) => Some(cookie),
+        Err(e) => {
+            warn!("Failed to read the hibernate cookie: {e:?}");
+            None
+        }
+    };
+
+    let hiberimage_exists = source.hiberimage_exists();
+    let free_space = if hiberimage_exists && !source.is_hiberimage_thickened()? {
+        Some(source.free_thinpool_space()?)
+    } else {
+        None
+    };
+    // The max image size is half of the system RAM, add a bit of margin.
+    let required_space = (source.ram_size() as f64 * 0.75) as u64;
+
+    // While an update is "pending reboot", the update engine might do further
+    // checks for updates it can apply. So no state except idle is safe.
+    let (update_pending, update_engine_available) = match source.update_engine_status() {
+        Ok(status) => {
+            let update_pending = status.current_operation.enum_value() != Ok(Operation::IDLE);
+            if update_pending {
+                info!("Update engine status is {:?}", status.current_operation);
+            }
+            (update_pending, true)
+        }
+        Err(e) => {
+            warn!("Failed to get update engine status, not ready to hibernate: {e:?}");
+            (false, false)
+        }
+    };
+
+    Ok(HibernateReadiness {
+        cookie,
+        hiberimage_exists,
+        prior_user_logout: !hiberimage_exists && source.has_user_logged_out(),
+        free_space,
+        required_space,
+        update_pending,
+        update_engine_available,
+        last_failure: last_failure(),
+    })
+}
+
+/// Returns a human readable description of the cookie in the report.
+pub fn describe_cookie(cookie: &Option<HibernateCookieValue>) -> &'static str {
+    match cookie {
+        Some(cookie) => cookie_description(cookie),
+        None => "Unknown",
+    }
+}
+
+/// Records the outcome of a hibernate attempt for later readiness reports.
+pub fn record_hibernate_result(result: &Result<()>) {
+    *LAST_FAILURE.lock().unwrap() = result.as_ref().err().map(|e| format!("{e:#}"));
+}
+
+/// Returns the error of the last failed hibernate attempt of this process.
+pub fn last_failure() -> Option<String> {
+    LAST_FAILURE.lock().unwrap().clone()
+}
+
+#[cfg(test)]
+pub mod tests {
+    use anyhow::anyhow;
+
+    use super::*;
+
+    const GIB: u64 = 1 << 30;
+
+    pub struct FakeSource {
+        pub hiberimage_exists: bool,
+        pub user_logged_out: bool,
+        pub thickened: bool,
+        pub free_space: u64,
+        pub ram_size: u64,
+        pub operation: Result<Operation>,
+    }
+
+    impl Default for FakeSource {
+        fn default() -> Self {
+            Self {
+                hiberimage_exists: true,
+                user_logged_out: false,
+                thickened: false,
+                free_space: 8 * GIB,
+                ram_size: 8 * GIB,
+                operation: Ok(Operation::IDLE),
+            }
+        }
+    }
+
+    impl ReadinessSource for FakeSource {
+        fn hibernate_cookie(&self) -> Result<HibernateCookieValue> {
+            Ok(HibernateCookieValue::NoResume)
+        }
+
+        fn hiberimage_exists(&self) -> bool {
+            self.hiberimage_exists
+        }
+
+        fn has_user_logged_out(&self) -> bool {
+            self.user_logged_out
+        }
+
+        fn is_hiberimage_thickened(&self) -> Result<bool> {
+            assert!(self.hiberimage_exists);
+            Ok(self.thickened)
+        }
+
+        fn free_thinpool_space(&self) -> Result<u64> {
+            Ok(self.free_space)
+        }
+
+        fn ram_size(&self) -> u64 {
+            self.ram_size
+        }
+
+        fn update_engine_status(&self) -> Result<StatusResult> {
+            let operation = self.operation.as_ref().map_err(|e| anyhow!("{e}"))?;
+            let mut status = StatusResult::new();
+            status.current_operation = (*operation).into();
+            Ok(status)
+        }
+    }
+
+    #[test]
+    fn ready() {
+        let readiness = check_readiness(&FakeSource::default()).unwrap();
+        assert_eq!(readiness.cookie, Some(HibernateCookieValue::NoResume));
+        assert!(readiness.hiberimage_exists);
+        assert_eq!(readiness.free_space, Some(8 * GIB));
+        assert_eq!(readiness.required_space, 6 * GIB);
+        assert!(!readiness.update_pending);
+        assert_eq!(readiness.veto(), None);
+    }
+
+    #[test]
+    fn update_pending() {
+        for operation in [
+            Operation::CHECKING_FOR_UPDATE,
+            Operation::DOWNLOADING,
+            Operation::FINALIZING,
+            Operation::UPDATED_NEED_REBOOT,
+        ] {
+            let readiness = check_readiness(&FakeSource {
+                operation: Ok(operation),
+                ..Default::default()
+            })
+            .unwrap();
+            assert!(readiness.update_pending);
+            assert_eq!(readiness.veto(), Some(VetoReason::UpdateEngineActive));
+        }
+
+        // Hibernate is not safe if the update engine state is unknown.
+        let readiness = check_readiness(&FakeSource {
+            operation: Err(anyhow!("update engine is not running")),
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(!readiness.update_engine_available);
+        assert_eq!(readiness.veto(), Some(VetoReason::UpdateEngineUnavailable));
+    }
+
+    #[test]
+    fn disk_space() {
+        let readiness = check_readiness(&FakeSource {
+            free_space: 5 * GIB,
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(readiness.veto(), Some(VetoReason::InsufficientDiskSpace));
+
+        // A thickened hiberimage does not need more space.
+        let readiness = check_readiness(&FakeSource {
+            free_space: 0,
+            thickened: true,
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(readiness.free_space, None);
+        assert_eq!(readiness.veto(), None);
+    }
+
+    #[test]
+    fn veto_order() {
+        // A missing hiberimage takes precedence over everything else.
+        let source = FakeSource {
+            hiberimage_exists: false,
+            operation: Ok(Operation::DOWNLOADING),
+            ..Default::default()
+        };
+        assert_eq!(
+            check_readiness(&source).unwrap().veto(),
+            Some(VetoReason::NoHiberimage)
+        );
+        let source = FakeSource {
+            user_logged_out: true,
+            ..source
+        };
+        assert_eq!(
+            check_readiness(&source).unwrap().veto(),
+            Some(VetoReason::PriorUserLogout)
+        );
+
+        // Disk space is checked before the update engine.
+        let source = FakeSource {
+            free_space: 0,
+            operation: Ok(Operation::DOWNLOADING),
+            ..Default::default()
+        };
+        assert_eq!(
+            check_readiness(&source).unwrap().veto(),
+            Some(VetoReason::InsufficientDiskSpace)
+        );
+    }
+}
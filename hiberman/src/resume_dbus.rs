@@ -16,8 +16,8 @@ use dbus_crossroads::Crossroads;
 use log::debug;
 use log::error;
 
-const HIBERMAN_DBUS_NAME: &str = "org.chromium.Hibernate";
-const HIBERMAN_DBUS_PATH: &str = "/org/chromium/Hibernate";
+pub const HIBERMAN_DBUS_NAME: &str = "org.chromium.Hibernate";
+pub const HIBERMAN_DBUS_PATH: &str = "/org/chromium/Hibernate";
 const HIBERMAN_RESUME_DBUS_INTERFACE: &str = "org.chromium.HibernateResumeInterface";
 
 pub enum DBusEvent {
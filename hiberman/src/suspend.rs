@@ -38,7 +38,6 @@ use crate::hiberlog::LogFile;
 use crate::hiberutil::checked_command_output;
 use crate::hiberutil::get_kernel_restore_time;
 use crate::hiberutil::get_page_size;
-use crate::hiberutil::get_ram_size;
 use crate::hiberutil::has_user_logged_out;
 use crate::hiberutil::path_to_stateful_block;
 use crate::hiberutil::prealloc_mem;
@@ -50,11 +49,15 @@ use crate::metrics::read_and_send_metrics;
 use crate::metrics::DurationMetricUnit;
 use crate::metrics::HibernateEvent;
 use crate::metrics::METRICS_LOGGER;
+use crate::readiness::check_readiness;
+use crate::readiness::record_hibernate_result;
+use crate::readiness::SystemReadinessSource;
+use crate::readiness::VetoReason;
 use crate::snapdev::FrozenUserspaceTicket;
 use crate::snapdev::SnapshotDevice;
 use crate::snapdev::SnapshotMode;
 use crate::swap_management::reclaim_all_processes;
-use crate::update_engine::is_update_engine_idle;
+use crate::throttle::run_throttled;
 use crate::volume::ActiveMount;
 use crate::volume::VolumeManager;
 use crate::volume::VOLUME_MANAGER;
@@ -103,7 +106,9 @@ impl SuspendConductor<'_> {
 
         log_metric_event(HibernateEvent::SuspendAttempt);
 
-        let success = self.hibernate_inner().is_ok();
+        let result = self.hibernate_inner();
+        record_hibernate_result(&result);
+        let success = result.is_ok();
 
         // Now send any remaining logs and future logs to syslog.
         redirect_log(HiberlogOut::Syslog);
@@ -148,28 +153,29 @@ impl SuspendConductor<'_> {
             return Err(HibernateError::NoHiberimageError().into());
         }
 
-        if !self.volume_manager.is_hiberimage_thickened()? {
-            let free_thinpool_space = self.volume_manager.get_free_thinpool_space()?;
-            // The max image size is half of the system RAM, add a bit of margin.
-            if free_thinpool_space < (get_ram_size() as f64 * 0.75) as u64 {
-                warn!(
-                    "Not enough space ({} MB) in the thinpool for writing the hibernate image",
-                    free_thinpool_space / (1024 * 1024)
-                );
-
-                Self::log_suspend_abort(SuspendAbortReason::InsufficientDiskSpace);
-                return Err(HibernateError::InsufficientDiskSpaceError().into());
-            }
-        }
-
-        // Don't hibernate if the update engine is up to something, as we would
-        // not want to hibernate if upon reboot the other slot gets booted.
-        // While an update is "pending reboot", the update engine might do
-        // further checks for updates it can apply. So no state except idle is
-        // safe.
-        if !is_update_engine_idle()? {
-            Self::log_suspend_abort(SuspendAbortReason::UpdateEngineActive);
-            return Err(HibernateError::UpdateEngineBusyError()).context("Update engine is active");
+        let readiness = check_readiness(&SystemReadinessSource::new(&self.volume_manager))?;
+        if let Some(veto) = readiness.veto() {
+            let reason = match veto {
+                // Only if the hiberimage was torn down since the check above.
+                VetoReason::NoHiberimage => SuspendAbortReason::NoHiberimage,
+                VetoReason::PriorUserLogout => SuspendAbortReason::PriorUserLogout,
+                VetoReason::InsufficientDiskSpace => {
+                    warn!(
+                        "Not enough space ({} MB) in the thinpool for writing the hibernate image",
+                        readiness.free_space.unwrap_or_default() / (1024 * 1024)
+                    );
+                    SuspendAbortReason::InsufficientDiskSpace
+                }
+                // Don't hibernate if the update engine is up to something, as
+                // we would not want to hibernate if upon reboot the other slot
+                // gets booted.
+                VetoReason::UpdateEngineActive => SuspendAbortReason::UpdateEngineActive,
+                // An unknown update engine state is as unsafe as an active
+                // update engine. The error was logged by check_readiness.
+                VetoReason::UpdateEngineUnavailable => SuspendAbortReason::UpdateEngineActive,
+            };
+            Self::log_suspend_abort(reason);
+            return Err(veto.to_error()).context(format!("Hibernate vetoed: {veto}"));
         }
 
         debug!("Syncing filesystems");
@@ -262,7 +268,13 @@ impl SuspendConductor<'_> {
 
             let start = Instant::now();
 
-            if let Err(e) = snap_dev.transfer_block_device() {
+            // Writing the image saturates the disk. With --io-priority it is
+            // written by a throttled worker thread, while this control thread
+            // keeps its priority.
+            let result = run_throttled(self.options.io_priority, || {
+                snap_dev.transfer_block_device()
+            });
+            if let Err(e) = result {
                 snap_dev.unfreeze_userspace()?;
                 return Err(e);
             }
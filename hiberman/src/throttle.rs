@@ -0,0 +1,332 @@
+// Copyright 2024 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Lowers the CPU and I/O priority of the threads writing the hibernate
+//! image, leaving the control thread interactive.
+
+use std::str::FromStr;
+use std::thread;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use log::debug;
+use log::warn;
+
+/// The I/O scheduling class shift, see include/uapi/linux/ioprio.h.
+const IOPRIO_CLASS_SHIFT: u32 = 13;
+/// Mask for the priority data within an ioprio value.
+const IOPRIO_PRIO_MASK: i32 = (1 << IOPRIO_CLASS_SHIFT) - 1;
+/// ioprio_set(2)/ioprio_get(2) target a single thread.
+const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+
+const IOPRIO_CLASS_BE: i32 = 2;
+const IOPRIO_CLASS_IDLE: i32 = 3;
+
+/// The lowest priority within the best-effort class.
+const IOPRIO_BE_LOWEST: i32 = 7;
+
+/// Nice value of a throttled thread in the best-effort I/O class.
+const BESTEFFORT_NICE: i32 = 10;
+/// Nice value of a throttled thread in the idle I/O class.
+const IDLE_NICE: i32 = 19;
+
+/// I/O scheduling class used for the hibernate image writer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IoPriorityClass {
+    /// Lowest priority of the best-effort class.
+    BestEffort,
+    /// Only get disk time when nobody else needs it.
+    Idle,
+}
+
+impl IoPriorityClass {
+    /// The ioprio value for this class.
+    fn ioprio(&self) -> i32 {
+        match self {
+            Self::BestEffort => ioprio_value(IOPRIO_CLASS_BE, IOPRIO_BE_LOWEST),
+            Self::Idle => ioprio_value(IOPRIO_CLASS_IDLE, 0),
+        }
+    }
+
+    /// The name of the class as accepted by [IoPriorityClass::from_str].
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::BestEffort => "besteffort",
+            Self::Idle => "idle",
+        }
+    }
+
+    /// The nice value applied along with the I/O class.
+    fn nice(&self) -> i32 {
+        match self {
+            Self::BestEffort => BESTEFFORT_NICE,
+            Self::Idle => IDLE_NICE,
+        }
+    }
+}
+
+impl FromStr for IoPriorityClass {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "besteffort" => Ok(Self::BestEffort),
+            "idle" => Ok(Self::Idle),
+            _ => Err(anyhow!("Invalid I/O priority class: {}", s)),
+        }
+    }
+}
+
+/// Pack an I/O scheduling class and its priority data into an ioprio value.
+fn ioprio_value(class: i32, data: i32) -> i32 {
+    (class << IOPRIO_CLASS_SHIFT) | (data & IOPRIO_PRIO_MASK)
+}
+
+/// Scheduling syscalls used for throttling, abstracted for testing.
+pub trait SchedulingSyscalls {
+    fn get_ioprio(&self, tid: libc::pid_t) -> Result<i32>;
+    fn set_ioprio(&self, tid: libc::pid_t, ioprio: i32) -> Result<()>;
+    fn get_nice(&self, tid: libc::pid_t) -> Result<i32>;
+    fn set_nice(&self, tid: libc::pid_t, nice: i32) -> Result<()>;
+}
+
+/// Scheduling syscalls backed by the kernel.
+pub struct KernelSyscalls;
+
+impl SchedulingSyscalls for KernelSyscalls {
+    fn get_ioprio(&self, tid: libc::pid_t) -> Result<i32> {
+        // This is safe because ioprio_get() does not modify memory.
+        let rc = unsafe { libc::syscall(libc::SYS_ioprio_get, IOPRIO_WHO_PROCESS, tid) };
+        if rc < 0 {
+            return Err(nix::Error::last()).context("Failed to get I/O priority");
+        }
+
+        Ok(rc as i32)
+    }
+
+    fn set_ioprio(&self, tid: libc::pid_t, ioprio: i32) -> Result<()> {
+        // This is safe because ioprio_set() does not modify memory.
+        let rc = unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, tid, ioprio) };
+        if rc < 0 {
+            return Err(nix::Error::last()).context("Failed to set I/O priority");
+        }
+
+        Ok(())
+    }
+
+    fn get_nice(&self, tid: libc::pid_t) -> Result<i32> {
+        // getpriority() can legitimately return -1, so errno has to be
+        // cleared beforehand to detect failures.
+        nix::errno::Errno::clear();
+        // This is safe because getpriority() does not modify memory.
+        let nice = unsafe { libc::getpriority(libc::PRIO_PROCESS, tid as libc::id_t) };
+        if nice == -1 && nix::errno::Errno::last() != nix::errno::Errno::UnknownErrno {
+            return Err(nix::Error::last()).context("Failed to get nice value");
+        }
+
+        Ok(nice)
+    }
+
+    fn set_nice(&self, tid: libc::pid_t, nice: i32) -> Result<()> {
+        // This is safe because setpriority() does not modify memory.
+        let rc = unsafe { libc::setpriority(libc::PRIO_PROCESS, tid as libc::id_t, nice) };
+        if rc < 0 {
+            return Err(nix::Error::last()).context("Failed to set nice value");
+        }
+
+        Ok(())
+    }
+}
+
+/// Get the id of the calling thread.
+pub fn current_thread_id() -> libc::pid_t {
+    // This is safe because gettid() has no side effects.
+    unsafe { libc::gettid() }
+}
+
+/// Token for a throttled thread. The original CPU and I/O priority of the
+/// thread are restored when the token is dropped, so hibernate paths that
+/// bail out leave the thread as interactive as it was before.
+pub struct ThrottledThread<S: SchedulingSyscalls> {
+    syscalls: S,
+    tid: libc::pid_t,
+    original_ioprio: i32,
+    original_nice: i32,
+}
+
+impl<S: SchedulingSyscalls> ThrottledThread<S> {
+    /// Lower the priority of the thread `tid` according to `class`.
+    pub fn new(syscalls: S, tid: libc::pid_t, class: IoPriorityClass) -> Result<Self> {
+        let original_ioprio = syscalls.get_ioprio(tid)?;
+        let original_nice = syscalls.get_nice(tid)?;
+
+        syscalls.set_ioprio(tid, class.ioprio())?;
+        // Construct the token before setting nice, so the I/O priority is
+        // restored if that fails.
+        let throttled = ThrottledThread {
+            syscalls,
+            tid,
+            original_ioprio,
+            original_nice,
+        };
+        throttled.syscalls.set_nice(tid, class.nice())?;
+
+        debug!("Throttled thread {} to {:?}", tid, class);
+        Ok(throttled)
+    }
+}
+
+impl<S: SchedulingSyscalls> Drop for ThrottledThread<S> {
+    fn drop(&mut self) {
+        if let Err(e) = self.syscalls.set_ioprio(self.tid, self.original_ioprio) {
+            warn!(
+                "Failed to restore I/O priority of thread {}: {:?}",
+                self.tid, e
+            );
+        }
+
+        if let Err(e) = self.syscalls.set_nice(self.tid, self.original_nice) {
+            warn!(
+                "Failed to restore nice value of thread {}: {:?}",
+                self.tid, e
+            );
+        }
+    }
+}
+
+/// Throttle the calling thread according to `class`. Failures are not fatal
+/// for hibernate, they are only logged.
+fn throttle_current_thread(class: IoPriorityClass) -> Option<ThrottledThread<KernelSyscalls>> {
+    match ThrottledThread::new(KernelSyscalls, current_thread_id(), class) {
+        Ok(throttled) => Some(throttled),
+        Err(e) => {
+            warn!("Failed to throttle hibernate worker thread: {:?}", e);
+            None
+        }
+    }
+}
+
+/// Run `f` on a worker thread throttled according to `class` and wait for
+/// its result, leaving the calling thread interactive. Without a class, `f`
+/// runs on the calling thread.
+pub fn run_throttled<T: Send>(class: Option<IoPriorityClass>, f: impl FnOnce() -> T + Send) -> T {
+    let Some(class) = class else {
+        return f();
+    };
+    thread::scope(|s| {
+        let worker = s.spawn(|| {
+            let _throttled = throttle_current_thread(class);
+            f()
+        });
+        match worker.join() {
+            Ok(result) => result,
+            Err(e) => std::panic::resume_unwind(e),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct FakeThread {
+        ioprio: i32,
+        nice: i32,
+        fail_set_nice: bool,
+    }
+
+    #[derive(Clone, Default)]
+    struct FakeSyscalls(Rc<RefCell<FakeThread>>);
+
+    impl SchedulingSyscalls for FakeSyscalls {
+        fn get_ioprio(&self, _tid: libc::pid_t) -> Result<i32> {
+            Ok(self.0.borrow().ioprio)
+        }
+
+        fn set_ioprio(&self, _tid: libc::pid_t, ioprio: i32) -> Result<()> {
+            self.0.borrow_mut().ioprio = ioprio;
+            Ok(())
+        }
+
+        fn get_nice(&self, _tid: libc::pid_t) -> Result<i32> {
+            Ok(self.0.borrow().nice)
+        }
+
+        fn set_nice(&self, _tid: libc::pid_t, nice: i32) -> Result<()> {
+            let mut thread = self.0.borrow_mut();
+            if thread.fail_set_nice && nice != 0 {
+                return Err(anyhow!("set_nice failed"));
+            }
+            thread.nice = nice;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn ioprio_packing() {
+        assert_eq!(ioprio_value(IOPRIO_CLASS_BE, 7), 0x4007);
+        assert_eq!(ioprio_value(IOPRIO_CLASS_IDLE, 0), 0x6000);
+        // Priority data must not leak into the class bits.
+        assert_eq!(
+            ioprio_value(IOPRIO_CLASS_BE, 1 << IOPRIO_CLASS_SHIFT),
+            0x4000
+        );
+        assert_eq!(IoPriorityClass::BestEffort.ioprio(), 0x4007);
+        assert_eq!(IoPriorityClass::Idle.ioprio(), 0x6000);
+    }
+
+    #[test]
+    fn parse_io_priority_class() {
+        assert_eq!(
+            "idle".parse::<IoPriorityClass>().unwrap(),
+            IoPriorityClass::Idle
+        );
+        assert_eq!(
+            "besteffort".parse::<IoPriorityClass>().unwrap(),
+            IoPriorityClass::BestEffort
+        );
+        assert!("realtime".parse::<IoPriorityClass>().is_err());
+    }
+
+    #[test]
+    fn throttle_and_revert() {
+        let syscalls = FakeSyscalls::default();
+        syscalls.0.borrow_mut().ioprio = ioprio_value(IOPRIO_CLASS_BE, 4);
+
+        let throttled = ThrottledThread::new(syscalls.clone(), 1, IoPriorityClass::Idle).unwrap();
+        assert_eq!(syscalls.0.borrow().ioprio, 0x6000);
+        assert_eq!(syscalls.0.borrow().nice, IDLE_NICE);
+
+        // Hibernate aborted, the thread must be interactive again.
+        drop(throttled);
+        assert_eq!(syscalls.0.borrow().ioprio, ioprio_value(IOPRIO_CLASS_BE, 4));
+        assert_eq!(syscalls.0.borrow().nice, 0);
+    }
+
+    #[test]
+    fn run_on_worker_thread() {
+        let caller = current_thread_id();
+        assert_eq!(run_throttled(None, current_thread_id), caller);
+        // Throttling may fail without CAP_SYS_NICE, the work runs anyway.
+        assert_ne!(
+            run_throttled(Some(IoPriorityClass::BestEffort), current_thread_id),
+            caller
+        );
+    }
+
+    #[test]
+    fn revert_ioprio_when_throttle_fails() {
+        let syscalls = FakeSyscalls::default();
+        syscalls.0.borrow_mut().fail_set_nice = true;
+
+        assert!(ThrottledThread::new(syscalls.clone(), 1, IoPriorityClass::BestEffort).is_err());
+        assert_eq!(syscalls.0.borrow().ioprio, 0);
+        assert_eq!(syscalls.0.borrow().nice, 0);
+    }
+}
@@ -9,9 +9,7 @@ use std::time::Duration;
 use anyhow::Context as AnyhowContext;
 use anyhow::Result;
 use dbus::blocking::Connection;
-use log::info;
 use protobuf::Message;
-use system_api::update_engine::Operation;
 use system_api::update_engine::StatusResult;
 use update_engine_dbus::client::OrgChromiumUpdateEngineInterface;
 
@@ -19,17 +17,8 @@ use update_engine_dbus::client::OrgChromiumUpdateEngineInterface;
 /// call responses.
 const UPDATE_ENGINE_DBUS_PROXY_TIMEOUT: Duration = Duration::from_secs(30);
 
-pub fn is_update_engine_idle() -> Result<bool> {
-    let status = get_status().context("Failed to get update engine status")?;
-    let current_operation = status.current_operation.enum_value();
-    if current_operation != Ok(Operation::IDLE) {
-        info!("Update engine status is {:?}", status.current_operation);
-    }
-
-    Ok(current_operation == Ok(Operation::IDLE))
-}
-
-fn get_status() -> Result<StatusResult> {
+/// Gets the current status of the update engine.
+pub fn get_status() -> Result<StatusResult> {
     // First open up a connection to the system bus.
     let conn = Connection::new_system().context("Failed to start system dbus connection")?;
 
@@ -40,6 +40,7 @@ pub struct Args {
     pub bus_device: Option<(u8, u8)>,
     pub unix_socket: Option<PathBuf>,
     pub upstart_mode: bool,
+    pub sd_notify: bool,
     pub verbose_log: bool,
 }
 
@@ -60,6 +61,11 @@ impl Args {
                 "upstart",
                 "Let upstart manage shutdown instead of immediately exiting after USB disconnect.",
             )
+            .optflag(
+                "",
+                "sd-notify",
+                "Notify systemd of readiness and liveness and accept a socket-activated listener.",
+            )
             .optflag("v", "verbose", "Enable verbose logging")
             .optflag("h", "help", "Print help message");
 
@@ -101,11 +107,13 @@ impl Args {
         let unix_socket = matches.opt_str("unix-socket").map(PathBuf::from);
         let verbose_log = matches.opt_present("v");
         let upstart_mode = matches.opt_present("upstart");
+        let sd_notify = matches.opt_present("sd-notify");
 
         Ok(Some(Args {
             bus_device,
             unix_socket,
             upstart_mode,
+            sd_notify,
             verbose_log,
         }))
     }
@@ -185,6 +193,19 @@ mod tests {
         assert!(args.verbose_log);
     }
 
+    #[test]
+    fn sd_notify() {
+        let args = Args::parse(&["ippusb-bridge"])
+            .expect("No args format should parse correctly")
+            .expect("Options struct should be returned");
+        assert!(!args.sd_notify);
+
+        let args = Args::parse(&["ippusb-bridge", "--sd-notify"])
+            .expect("sd-notify flag should parse correctly")
+            .expect("Options struct should be returned");
+        assert!(args.sd_notify);
+    }
+
     #[test]
     fn help() {
         let args =
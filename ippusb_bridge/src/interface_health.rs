@@ -0,0 +1,382 @@
+// Copyright 2024 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+use std::time::{Duration, Instant};
+
+use log::{error, info, warn};
+
+/// Number of consecutive failed requests after which an interface is considered suspect.
+const SUSPECT_THRESHOLD: u32 = 3;
+/// Quarantine period after the first failed recovery.  It doubles for every further failed
+/// recovery in a row, up to MAX_QUARANTINE.
+const BASE_QUARANTINE: Duration = Duration::from_secs(10);
+const MAX_QUARANTINE: Duration = Duration::from_secs(5 * 60);
+
+/// Counters describing the health history of an interface.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct InterfaceStats {
+    /// Requests that failed because of a timeout, babble or stall.
+    pub failures: u64,
+    /// Successful recoveries by resetting the interface or the device.
+    pub resets: u64,
+    /// Times the interface was taken out of rotation.
+    pub quarantines: u64,
+}
+
+/// The operations used to bring a suspect interface back into a working state.
+pub trait Recovery {
+    /// Clears halts on the interface endpoints and re-selects its alternate setting.
+    fn reset_interface(&mut self) -> bool;
+    /// Resets the whole USB device.
+    fn reset_device(&mut self) -> bool;
+}
+
+/// Tracks the health of a single IPP-USB interface.
+///
+/// Every request on the interface is recorded as a success or a failure.  After
+/// SUSPECT_THRESHOLD consecutive failures the interface is reset.  If the reset fails, or
+/// requests keep failing after it, the interface is quarantined for a backoff period so that
+/// requests are routed to the remaining interfaces.  The last interface that is not quarantined
+/// is never quarantined; the device is reset instead.
+#[derive(Debug)]
+pub struct InterfaceHealth {
+    interface_number: u8,
+    consecutive_failures: u32,
+    // The interface was reset and no request succeeded since.
+    reset_without_success: bool,
+    failed_recoveries: u32,
+    quarantined_until: Option<Instant>,
+    stats: InterfaceStats,
+}
+
+impl InterfaceHealth {
+    pub fn new(interface_number: u8) -> Self {
+        Self {
+            interface_number,
+            consecutive_failures: 0,
+            reset_without_success: false,
+            failed_recoveries: 0,
+            quarantined_until: None,
+            stats: InterfaceStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> InterfaceStats {
+        self.stats
+    }
+
+    /// Returns the end of the quarantine if the interface is quarantined at `now`.
+    pub fn quarantined_until(&self, now: Instant) -> Option<Instant> {
+        self.quarantined_until.filter(|until| *until > now)
+    }
+
+    pub fn is_available(&self, now: Instant) -> bool {
+        self.quarantined_until(now).is_none()
+    }
+
+    /// Records the outcome of a request on the interface and runs `recovery` if the interface
+    /// became suspect.  `is_last_available` tells whether every other interface is quarantined.
+    pub fn record_request<R: Recovery>(
+        &mut self,
+        succeeded: bool,
+        is_last_available: bool,
+        recovery: &mut R,
+        now: Instant,
+    ) {
+        if succeeded {
+            self.consecutive_failures = 0;
+            self.reset_without_success = false;
+            self.failed_recoveries = 0;
+            return;
+        }
+
+        self.stats.failures += 1;
+        self.consecutive_failures += 1;
+        if self.consecutive_failures < SUSPECT_THRESHOLD {
+            return;
+        }
+
+        self.consecutive_failures = 0;
+        if self.reset_without_success {
+            warn!(
+                "Interface {} keeps failing after a reset",
+                self.interface_number
+            );
+        } else {
+            warn!(
+                "Interface {} failed {} requests in a row, resetting it",
+                self.interface_number, SUSPECT_THRESHOLD
+            );
+            if recovery.reset_interface() {
+                self.stats.resets += 1;
+                self.reset_without_success = true;
+                return;
+            }
+        }
+
+        if is_last_available {
+            warn!(
+                "Interface {} is the last available interface, resetting the device",
+                self.interface_number
+            );
+            if recovery.reset_device() {
+                self.stats.resets += 1;
+            } else {
+                error!(
+                    "Failed to recover interface {}; requests will keep failing",
+                    self.interface_number
+                );
+            }
+            return;
+        }
+
+        let quarantine = BASE_QUARANTINE
+            .saturating_mul(1 << self.failed_recoveries.min(16))
+            .min(MAX_QUARANTINE);
+        self.failed_recoveries += 1;
+        self.quarantined_until = Some(now + quarantine);
+        self.stats.quarantines += 1;
+        info!(
+            "Quarantining interface {} for {}s",
+            self.interface_number,
+            quarantine.as_secs()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fake IPP-USB device whose interfaces fail every request while they are stalled.
+    /// Resetting an interface fails, or "succeeds" without unstalling it if
+    /// `interface_reset_succeeds` is set.  Resetting the device unstalls everything.
+    struct FakeDevice {
+        stalled: Vec<bool>,
+        interface_reset_succeeds: bool,
+        interface_resets: Vec<usize>,
+        device_resets: usize,
+    }
+
+    impl FakeDevice {
+        fn new(num_interfaces: usize) -> Self {
+            Self {
+                stalled: vec![false; num_interfaces],
+                interface_reset_succeeds: false,
+                interface_resets: Vec::new(),
+                device_resets: 0,
+            }
+        }
+    }
+
+    struct FakeRecovery<'a> {
+        device: &'a mut FakeDevice,
+        interface: usize,
+    }
+
+    impl Recovery for FakeRecovery<'_> {
+        fn reset_interface(&mut self) -> bool {
+            self.device.interface_resets.push(self.interface);
+            self.device.interface_reset_succeeds
+        }
+
+        fn reset_device(&mut self) -> bool {
+            self.device.device_resets += 1;
+            self.device
+                .stalled
+                .iter_mut()
+                .for_each(|stalled| *stalled = false);
+            true
+        }
+    }
+
+    /// Mirrors how InterfaceManager hands out interfaces: the first available one is used.
+    /// Returns the interface the request was sent to.
+    fn send_request(
+        device: &mut FakeDevice,
+        healths: &mut [InterfaceHealth],
+        now: Instant,
+    ) -> usize {
+        let interface = healths
+            .iter()
+            .position(|health| health.is_available(now))
+            .expect("no available interface");
+        let succeeded = !device.stalled[interface];
+        let is_last_available = healths
+            .iter()
+            .enumerate()
+            .all(|(i, health)| i == interface || !health.is_available(now));
+        let mut recovery = FakeRecovery { device, interface };
+        healths[interface].record_request(succeeded, is_last_available, &mut recovery, now);
+        interface
+    }
+
+    fn healths(num_interfaces: u8) -> Vec<InterfaceHealth> {
+        (0..num_interfaces).map(InterfaceHealth::new).collect()
+    }
+
+    #[test]
+    fn requests_migrate_away_from_stalled_interface() {
+        let mut device = FakeDevice::new(2);
+        let mut healths = healths(2);
+        let now = Instant::now();
+        device.stalled[0] = true;
+
+        for _ in 0..SUSPECT_THRESHOLD {
+            assert_eq!(send_request(&mut device, &mut healths, now), 0);
+        }
+        assert_eq!(device.interface_resets, vec![0]);
+        assert_eq!(
+            healths[0].stats(),
+            InterfaceStats {
+                failures: SUSPECT_THRESHOLD as u64,
+                resets: 0,
+                quarantines: 1,
+            }
+        );
+
+        // Requests now go to the healthy interface.
+        for _ in 0..5 {
+            assert_eq!(send_request(&mut device, &mut healths, now), 1);
+        }
+        assert_eq!(healths[1].stats(), InterfaceStats::default());
+
+        // The stalled interface is tried again once its quarantine ends.
+        let later = now + BASE_QUARANTINE;
+        assert_eq!(send_request(&mut device, &mut healths, later), 0);
+    }
+
+    #[test]
+    fn quarantine_backs_off() {
+        let mut device = FakeDevice::new(2);
+        let mut healths = healths(2);
+        let mut now = Instant::now();
+        device.stalled[0] = true;
+
+        let mut expected = BASE_QUARANTINE;
+        for _ in 0..3 {
+            for _ in 0..SUSPECT_THRESHOLD {
+                send_request(&mut device, &mut healths, now);
+            }
+            assert_eq!(healths[0].quarantined_until(now), Some(now + expected));
+            now += expected;
+            expected *= 2;
+        }
+        assert_eq!(healths[0].stats().quarantines, 3);
+    }
+
+    #[test]
+    fn success_resets_failure_count() {
+        let mut device = FakeDevice::new(2);
+        let mut healths = healths(2);
+        let now = Instant::now();
+
+        for _ in 0..SUSPECT_THRESHOLD - 1 {
+            device.stalled[0] = true;
+            send_request(&mut device, &mut healths, now);
+            device.stalled[0] = false;
+            send_request(&mut device, &mut healths, now);
+        }
+        assert!(device.interface_resets.is_empty());
+        assert!(healths[0].is_available(now));
+        assert_eq!(healths[0].stats().failures, (SUSPECT_THRESHOLD - 1) as u64);
+    }
+
+    #[test]
+    fn last_interface_escalates_to_device_reset() {
+        let mut device = FakeDevice::new(2);
+        let mut healths = healths(2);
+        let now = Instant::now();
+        device.stalled = vec![true, true];
+
+        // The first interface is quarantined, the second one is the last available.
+        for _ in 0..SUSPECT_THRESHOLD {
+            assert_eq!(send_request(&mut device, &mut healths, now), 0);
+        }
+        for _ in 0..SUSPECT_THRESHOLD {
+            assert_eq!(send_request(&mut device, &mut healths, now), 1);
+        }
+
+        assert_eq!(device.interface_resets, vec![0, 1]);
+        assert_eq!(device.device_resets, 1);
+        assert!(healths[1].is_available(now));
+        assert_eq!(healths[1].stats().resets, 1);
+        assert_eq!(healths[1].stats().quarantines, 0);
+
+        // The device reset fixed the interface.
+        assert_eq!(send_request(&mut device, &mut healths, now), 1);
+        assert_eq!(healths[1].stats().failures, SUSPECT_THRESHOLD as u64);
+    }
+
+    #[test]
+    fn quarantine_when_reset_does_not_help() {
+        let mut device = FakeDevice::new(2);
+        device.interface_reset_succeeds = true;
+        let mut healths = healths(2);
+        let now = Instant::now();
+        device.stalled[0] = true;
+
+        // The reset reports success, but the interface stays stalled.
+        for _ in 0..SUSPECT_THRESHOLD {
+            assert_eq!(send_request(&mut device, &mut healths, now), 0);
+        }
+        assert_eq!(device.interface_resets, vec![0]);
+        assert!(healths[0].is_available(now));
+
+        // The interface is quarantined instead of being reset over and over.
+        for _ in 0..SUSPECT_THRESHOLD {
+            assert_eq!(send_request(&mut device, &mut healths, now), 0);
+        }
+        assert_eq!(device.interface_resets, vec![0]);
+        assert_eq!(
+            healths[0].quarantined_until(now),
+            Some(now + BASE_QUARANTINE)
+        );
+        assert_eq!(
+            healths[0].stats(),
+            InterfaceStats {
+                failures: 2 * SUSPECT_THRESHOLD as u64,
+                resets: 1,
+                quarantines: 1,
+            }
+        );
+        assert_eq!(send_request(&mut device, &mut healths, now), 1);
+
+        // Still stalled after the quarantine: the quarantine backs off without another reset.
+        let later = now + BASE_QUARANTINE;
+        for _ in 0..SUSPECT_THRESHOLD {
+            assert_eq!(send_request(&mut device, &mut healths, later), 0);
+        }
+        assert_eq!(device.interface_resets, vec![0]);
+        assert_eq!(
+            healths[0].quarantined_until(later),
+            Some(later + 2 * BASE_QUARANTINE)
+        );
+    }
+
+    #[test]
+    fn last_interface_escalates_to_device_reset_when_reset_does_not_help() {
+        let mut device = FakeDevice::new(1);
+        device.interface_reset_succeeds = true;
+        let mut healths = healths(1);
+        let now = Instant::now();
+        device.stalled[0] = true;
+
+        for _ in 0..2 * SUSPECT_THRESHOLD {
+            assert_eq!(send_request(&mut device, &mut healths, now), 0);
+        }
+        assert_eq!(device.interface_resets, vec![0]);
+        assert_eq!(device.device_resets, 1);
+        assert!(healths[0].is_available(now));
+        assert_eq!(healths[0].stats().resets, 2);
+
+        // The device reset fixed the interface, a later stall is reset at the interface again.
+        assert_eq!(send_request(&mut device, &mut healths, now), 0);
+        device.stalled[0] = true;
+        for _ in 0..SUSPECT_THRESHOLD {
+            send_request(&mut device, &mut healths, now);
+        }
+        assert_eq!(device.interface_resets, vec![0, 0]);
+    }
+}
@@ -6,14 +6,14 @@ use std::fs;
 use std::io;
 use std::net::TcpListener;
 use std::ops::Deref;
-use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::io::{AsFd, BorrowedFd};
 use std::os::unix::net::UnixListener;
 
 use tiny_http::Stream;
 
 use log::error;
 
-pub trait Accept: AsRawFd {
+pub trait Accept: AsFd {
     fn accept(&self) -> io::Result<Stream>;
 }
 
@@ -32,9 +32,9 @@ impl Accept for UnixListener {
 /// Scopes a UnixListener such that on Drop, the local socket path, if any, is deleted.
 pub struct ScopedUnixListener(pub UnixListener);
 
-impl AsRawFd for ScopedUnixListener {
-    fn as_raw_fd(&self) -> RawFd {
-        self.0.as_raw_fd()
+impl AsFd for ScopedUnixListener {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.0.as_fd()
     }
 }
 
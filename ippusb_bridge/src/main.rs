@@ -4,8 +4,10 @@
 
 mod arguments;
 mod http;
+mod interface_health;
 mod io_adapters;
 mod listeners;
+mod sd_notify;
 mod usb_connector;
 mod util;
 
@@ -13,12 +15,14 @@ use std::fmt;
 use std::io;
 use std::net::TcpListener;
 use std::os::raw::c_int;
-use std::os::unix::io::{AsRawFd, IntoRawFd, RawFd};
+use std::os::unix::io::{AsFd, IntoRawFd};
 use std::os::unix::net::UnixListener;
 use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
-use std::time::Duration;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
-use libchromeos::deprecated::{EventFd, PollContext, PollToken};
+use libchromeos::events::{EventFd, PollToken, Poller};
 use libchromeos::signal::register_signal_handler;
 use libchromeos::syslog;
 use log::{debug, error, info};
@@ -28,10 +32,12 @@ use tiny_http::{ClientConnection, Stream};
 use crate::arguments::Args;
 use crate::http::handle_request;
 use crate::listeners::{Accept, ScopedUnixListener};
+use crate::sd_notify::{ActivatedListener, Notifier, NotifyState, StallDetector};
 use crate::usb_connector::{UnplugDetector, UsbConnector};
 
 #[derive(Debug)]
 pub enum Error {
+    ActivateSocket(io::Error),
     CreateSocket(io::Error),
     CreateUsbConnector(usb_connector::Error),
     EventFd(io::Error),
@@ -40,6 +46,7 @@ pub enum Error {
     RegisterHandler(nix::Error),
     Syslog(syslog::Error),
     SysUtil(nix::Error),
+    Watchdog(io::Error),
 }
 
 impl std::error::Error for Error {}
@@ -48,6 +55,7 @@ impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use Error::*;
         match self {
+            ActivateSocket(err) => write!(f, "Failed to adopt socket-activated listener: {}", err),
             CreateSocket(err) => write!(f, "Failed to create socket: {}", err),
             CreateUsbConnector(err) => write!(f, "Failed to create USB connector: {}", err),
             EventFd(err) => write!(f, "Failed to create/duplicate EventFd: {}", err),
@@ -56,6 +64,7 @@ impl fmt::Display for Error {
             RegisterHandler(err) => write!(f, "Registering SIGINT handler failed: {}", err),
             Syslog(err) => write!(f, "Failed to initalize syslog: {}", err),
             SysUtil(err) => write!(f, "Sysutil error: {}", err),
+            Watchdog(err) => write!(f, "Failed to start watchdog thread: {}", err),
         }
     }
 }
@@ -106,16 +115,6 @@ struct Daemon {
     usb: UsbConnector,
 }
 
-// Trivially allows a `RawFd` to be passed as a `&AsRawFd`.  Needed because
-// `Daemon` contains an `Accept` but needs to pass it to `PollContext` as a
-// `&AsRawFd`.
-struct WrapFd(RawFd);
-impl AsRawFd for WrapFd {
-    fn as_raw_fd(&self) -> RawFd {
-        self.0
-    }
-}
-
 impl Daemon {
     fn new(
         verbose_log: bool,
@@ -139,16 +138,15 @@ impl Daemon {
             ClientConnection,
         }
 
-        let listener_fd = WrapFd(self.listener.as_raw_fd());
-        let poll_ctx: PollContext<Token> = PollContext::build_with(&[
+        let poller: Poller<Token> = Poller::build_with(&[
             (&self.shutdown, Token::Shutdown),
-            (&listener_fd, Token::ClientConnection),
+            (&self.listener.as_fd(), Token::ClientConnection),
         ])
         .map_err(Error::SysUtil)?;
 
         'poll: loop {
             let timeout = Duration::new(i64::MAX as u64, 0);
-            let events = poll_ctx.wait_timeout(timeout).map_err(Error::PollEvents)?;
+            let events = poller.wait_timeout(timeout).map_err(Error::PollEvents)?;
             for event in &events {
                 match event.token() {
                     Token::Shutdown => break 'poll,
@@ -197,6 +195,25 @@ impl Daemon {
     }
 }
 
+/// Sends watchdog pings to systemd every `interval` as long as the bridge is not stalled.
+fn start_watchdog(notifier: Arc<Notifier>, interval: Duration, usb: UsbConnector) -> Result<()> {
+    thread::Builder::new()
+        .name("watchdog".into())
+        .spawn(move || {
+            let mut detector = StallDetector::new(Instant::now());
+            while !SHUTDOWN.load(Ordering::Relaxed) {
+                if detector.is_alive(usb.activity(), Instant::now()) {
+                    sd_notify::notify(Some(&notifier), &[NotifyState::Watchdog]);
+                } else {
+                    error!("No USB transfer completed for active connections, bridge is stalled");
+                }
+                thread::sleep(interval);
+            }
+        })
+        .map_err(Error::Watchdog)?;
+    Ok(())
+}
+
 fn run() -> Result<()> {
     syslog::init("ippusb_bridge".to_string(), true).map_err(Error::Syslog)?;
     let argv: Vec<String> = std::env::args().collect();
@@ -212,7 +229,20 @@ fn run() -> Result<()> {
     // Safe because the syscall doesn't touch any memory and always succeeds.
     unsafe { libc::umask(0o117) };
 
-    let listener: Box<dyn Accept> = if let Some(unix_socket_path) = args.unix_socket {
+    let activated_listener = if args.sd_notify {
+        sd_notify::listener_from_env().map_err(Error::ActivateSocket)?
+    } else {
+        None
+    };
+
+    let listener: Box<dyn Accept> = if let Some(activated_listener) = activated_listener {
+        info!("Listening on socket-activated listener");
+        // systemd owns the socket, so a unix socket path must not be removed on exit.
+        match activated_listener {
+            ActivatedListener::Tcp(listener) => Box::new(listener),
+            ActivatedListener::Unix(listener) => Box::new(listener),
+        }
+    } else if let Some(unix_socket_path) = args.unix_socket {
         info!("Listening on {}", unix_socket_path.display());
         Box::new(ScopedUnixListener(
             UnixListener::bind(unix_socket_path).map_err(Error::CreateSocket)?,
@@ -233,8 +263,30 @@ fn run() -> Result<()> {
         args.upstart_mode,
     );
 
-    let mut daemon = Daemon::new(args.verbose_log, shutdown_fd, listener, usb)?;
+    let notifier = if args.sd_notify {
+        Notifier::from_env().unwrap_or_else(|e| {
+            error!("Failed to create systemd notification socket: {}", e);
+            None
+        })
+    } else {
+        None
+    }
+    .map(Arc::new);
+    sd_notify::notify(notifier.as_deref(), &[NotifyState::Ready]);
+    if let (Some(notifier), Some(interval)) = (&notifier, sd_notify::watchdog_interval_from_env()) {
+        start_watchdog(notifier.clone(), interval, usb.clone())?;
+    }
+
+    let mut daemon = Daemon::new(args.verbose_log, shutdown_fd, listener, usb.clone())?;
     daemon.run()?;
+    sd_notify::notify(notifier.as_deref(), &[NotifyState::Stopping]);
+
+    for (interface_number, stats) in usb.interface_stats() {
+        info!(
+            "Interface {}: {} failures, {} resets, {} quarantines",
+            interface_number, stats.failures, stats.resets, stats.quarantines
+        );
+    }
 
     info!("Shutting down.");
     Ok(())
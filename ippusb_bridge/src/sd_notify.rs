@@ -0,0 +1,450 @@
+// Copyright 2024 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Minimal implementation of the systemd service notification and socket activation protocols
+//! (see sd_notify(3) and sd_listen_fds(3)).  Everything here is a no-op unless systemd sets the
+//! corresponding environment variables.
+
+use std::ffi::OsStr;
+use std::io;
+use std::mem::size_of;
+use std::net::TcpListener;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::os::unix::net::{SocketAddr, UnixDatagram, UnixListener};
+use std::time::{Duration, Instant};
+
+use log::error;
+
+/// The first file descriptor passed by systemd for socket activation.
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+/// A bridge with active connections that completes no USB transfer for this long is considered
+/// stalled.  Every transfer ends within its 60 second timeout, so this leaves ample margin.
+const STALL_TIMEOUT: Duration = Duration::from_secs(150);
+
+/// The service state changes sent to systemd.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NotifyState {
+    /// Startup is finished: the listener and the USB device are up.
+    Ready,
+    /// Keep-alive ping for the service watchdog.
+    Watchdog,
+    /// Shutdown has started.
+    Stopping,
+}
+
+impl NotifyState {
+    fn assignment(&self) -> &'static str {
+        match self {
+            NotifyState::Ready => "READY=1",
+            NotifyState::Watchdog => "WATCHDOG=1",
+            NotifyState::Stopping => "STOPPING=1",
+        }
+    }
+}
+
+/// Formats `states` as a notification message, one assignment per line.
+pub fn format_message(states: &[NotifyState]) -> String {
+    states
+        .iter()
+        .map(|state| state.assignment())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Parses the value of NOTIFY_SOCKET.  systemd passes either an absolute path or an abstract
+/// socket name prefixed with '@'.
+fn parse_notify_socket(notify_socket: &OsStr) -> Option<SocketAddr> {
+    let bytes = notify_socket.as_bytes();
+    match bytes.first() {
+        Some(b'/') => SocketAddr::from_pathname(notify_socket).ok(),
+        Some(b'@') => SocketAddr::from_abstract_name(&bytes[1..]).ok(),
+        _ => None,
+    }
+}
+
+/// Sends notifications to the socket in NOTIFY_SOCKET.
+pub struct Notifier {
+    socket: UnixDatagram,
+    addr: SocketAddr,
+}
+
+impl Notifier {
+    /// Returns None if `notify_socket`, the value of NOTIFY_SOCKET, is absent or invalid.
+    pub fn new(notify_socket: Option<&OsStr>) -> io::Result<Option<Self>> {
+        let addr = match notify_socket.and_then(parse_notify_socket) {
+            Some(addr) => addr,
+            None => return Ok(None),
+        };
+        Ok(Some(Self {
+            socket: UnixDatagram::unbound()?,
+            addr,
+        }))
+    }
+
+    pub fn from_env() -> io::Result<Option<Self>> {
+        Self::new(std::env::var_os("NOTIFY_SOCKET").as_deref())
+    }
+
+    pub fn notify(&self, states: &[NotifyState]) -> io::Result<()> {
+        self.socket
+            .send_to_addr(format_message(states).as_bytes(), &self.addr)
+            .map(|_| ())
+    }
+}
+
+/// Sends `states` to systemd if `notifier` is set.  Failures are only logged because systemd
+/// handles a missing notification on its own.
+pub fn notify(notifier: Option<&Notifier>, states: &[NotifyState]) {
+    if let Some(notifier) = notifier {
+        if let Err(e) = notifier.notify(states) {
+            error!("Failed to notify {}: {}", format_message(states), e);
+        }
+    }
+}
+
+/// Returns the interval to send watchdog pings at, given the values of WATCHDOG_USEC and
+/// WATCHDOG_PID.  The pings are sent twice per watchdog period as sd_watchdog_enabled(3)
+/// recommends.
+pub fn watchdog_interval(
+    watchdog_usec: Option<&str>,
+    watchdog_pid: Option<&str>,
+    pid: u32,
+) -> Option<Duration> {
+    if let Some(watchdog_pid) = watchdog_pid {
+        if watchdog_pid.parse::<u32>().ok()? != pid {
+            return None;
+        }
+    }
+    match watchdog_usec?.parse::<u64>().ok()? {
+        0 => None,
+        usec => Some(Duration::from_micros(usec) / 2),
+    }
+}
+
+pub fn watchdog_interval_from_env() -> Option<Duration> {
+    let watchdog_usec = std::env::var("WATCHDOG_USEC").ok();
+    let watchdog_pid = std::env::var("WATCHDOG_PID").ok();
+    watchdog_interval(
+        watchdog_usec.as_deref(),
+        watchdog_pid.as_deref(),
+        std::process::id(),
+    )
+}
+
+/// Snapshot of the bridge counters used to tell whether it is stalled.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BridgeActivity {
+    /// Connections currently holding an IPP-USB interface.
+    pub active_connections: usize,
+    /// USB transfers completed since startup, successful or not.
+    pub transfers: u64,
+}
+
+/// Decides whether watchdog pings should be sent.  The bridge is stalled when connections are
+/// active but no USB transfer completed for STALL_TIMEOUT.
+pub struct StallDetector {
+    transfers: u64,
+    last_progress: Instant,
+}
+
+impl StallDetector {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            transfers: 0,
+            last_progress: now,
+        }
+    }
+
+    /// Returns whether the bridge is alive at `now` given its current `activity`.
+    pub fn is_alive(&mut self, activity: BridgeActivity, now: Instant) -> bool {
+        if activity.active_connections == 0 || activity.transfers != self.transfers {
+            self.transfers = activity.transfers;
+            self.last_progress = now;
+            return true;
+        }
+        now.duration_since(self.last_progress) < STALL_TIMEOUT
+    }
+}
+
+/// A listener passed by systemd through socket activation.
+#[derive(Debug)]
+pub enum ActivatedListener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+/// Returns the listener fd passed by systemd, given the values of LISTEN_PID and LISTEN_FDS.
+/// Only the first fd is used if several are passed.
+pub fn listen_fd(listen_pid: Option<&str>, listen_fds: Option<&str>, pid: u32) -> Option<RawFd> {
+    if listen_pid?.parse::<u32>().ok()? != pid {
+        return None;
+    }
+    match listen_fds?.parse::<u32>().ok()? {
+        0 => None,
+        _ => Some(SD_LISTEN_FDS_START),
+    }
+}
+
+/// Takes ownership of `fd`, which must be a listening stream socket.
+///
+/// # Safety
+///
+/// `fd` must be an open file descriptor that is not owned by anything else.
+pub unsafe fn adopt_listener(fd: RawFd) -> io::Result<ActivatedListener> {
+    let mut sock_type: libc::c_int = 0;
+    let mut len = size_of::<libc::c_int>() as libc::socklen_t;
+    // Safe because the kernel only writes up to `len` bytes to `sock_type`.
+    if libc::getsockopt(
+        fd,
+        libc::SOL_SOCKET,
+        libc::SO_TYPE,
+        &mut sock_type as *mut libc::c_int as *mut libc::c_void,
+        &mut len,
+    ) < 0
+    {
+        return Err(io::Error::last_os_error());
+    }
+    if sock_type != libc::SOCK_STREAM {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "socket activation fd is not a stream socket",
+        ));
+    }
+
+    let mut addr: libc::sockaddr_storage = std::mem::zeroed();
+    let mut len = size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    // Safe because the kernel only writes up to `len` bytes to `addr`.
+    if libc::getsockname(
+        fd,
+        &mut addr as *mut libc::sockaddr_storage as *mut libc::sockaddr,
+        &mut len,
+    ) < 0
+    {
+        return Err(io::Error::last_os_error());
+    }
+
+    // systemd does not set FD_CLOEXEC on the fds it passes.
+    if libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    match addr.ss_family as libc::c_int {
+        libc::AF_INET | libc::AF_INET6 => Ok(ActivatedListener::Tcp(TcpListener::from_raw_fd(fd))),
+        libc::AF_UNIX => Ok(ActivatedListener::Unix(UnixListener::from_raw_fd(fd))),
+        family => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("unsupported socket activation address family {}", family),
+        )),
+    }
+}
+
+/// Adopts the listener passed by systemd, if any.
+pub fn listener_from_env() -> io::Result<Option<ActivatedListener>> {
+    let listen_pid = std::env::var("LISTEN_PID").ok();
+    let listen_fds = std::env::var("LISTEN_FDS").ok();
+    match listen_fd(
+        listen_pid.as_deref(),
+        listen_fds.as_deref(),
+        std::process::id(),
+    ) {
+        // Safe because systemd hands the fd over to us and nothing else uses it.
+        Some(fd) => unsafe { adopt_listener(fd) }.map(Some),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Read;
+    use std::io::Write;
+    use std::net::TcpStream;
+    use std::os::unix::io::{AsRawFd, IntoRawFd};
+    use std::os::unix::net::UnixStream;
+    use std::path::PathBuf;
+
+    fn temp_socket_path(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("ippusb_bridge-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn message_format() {
+        assert_eq!(format_message(&[NotifyState::Ready]), "READY=1");
+        assert_eq!(format_message(&[NotifyState::Watchdog]), "WATCHDOG=1");
+        assert_eq!(format_message(&[NotifyState::Stopping]), "STOPPING=1");
+        assert_eq!(
+            format_message(&[NotifyState::Ready, NotifyState::Watchdog]),
+            "READY=1\nWATCHDOG=1"
+        );
+    }
+
+    #[test]
+    fn notify_socket_parsing() {
+        let addr = parse_notify_socket(OsStr::new("/run/systemd/notify")).unwrap();
+        assert_eq!(
+            addr.as_pathname(),
+            Some(PathBuf::from("/run/systemd/notify").as_path())
+        );
+
+        let addr = parse_notify_socket(OsStr::new("@/org/freedesktop/systemd1/notify")).unwrap();
+        assert_eq!(
+            addr.as_abstract_name(),
+            Some(&b"/org/freedesktop/systemd1/notify"[..])
+        );
+
+        assert!(parse_notify_socket(OsStr::new("")).is_none());
+        assert!(parse_notify_socket(OsStr::new("relative/notify")).is_none());
+    }
+
+    #[test]
+    fn notifier_absent() {
+        assert!(Notifier::new(None).unwrap().is_none());
+        assert!(Notifier::new(Some(OsStr::new(""))).unwrap().is_none());
+        // Without a notifier, notify() does nothing.
+        notify(None, &[NotifyState::Ready]);
+    }
+
+    #[test]
+    fn notifier_sends_messages() {
+        let path = temp_socket_path("notify");
+        let receiver = UnixDatagram::bind(&path).unwrap();
+        let notifier = Notifier::new(Some(path.as_os_str())).unwrap().unwrap();
+
+        notify(Some(&notifier), &[NotifyState::Ready]);
+        notify(Some(&notifier), &[NotifyState::Stopping]);
+        let mut buf = [0u8; 64];
+        let n = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"READY=1");
+        let n = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"STOPPING=1");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn watchdog_interval_parsing() {
+        assert_eq!(
+            watchdog_interval(Some("20000000"), None, 42),
+            Some(Duration::from_secs(10))
+        );
+        assert_eq!(
+            watchdog_interval(Some("20000000"), Some("42"), 42),
+            Some(Duration::from_secs(10))
+        );
+        // The watchdog is meant for another process.
+        assert_eq!(watchdog_interval(Some("20000000"), Some("43"), 42), None);
+        assert_eq!(watchdog_interval(None, Some("42"), 42), None);
+        assert_eq!(watchdog_interval(Some("0"), None, 42), None);
+        assert_eq!(watchdog_interval(Some("soon"), None, 42), None);
+    }
+
+    #[test]
+    fn stall_detection() {
+        let start = Instant::now();
+        let mut detector = StallDetector::new(start);
+
+        // Idle bridges are never stalled.
+        let idle = BridgeActivity {
+            active_connections: 0,
+            transfers: 0,
+        };
+        assert!(detector.is_alive(idle, start + STALL_TIMEOUT * 2));
+
+        // Active connections must keep transferring.
+        let busy = BridgeActivity {
+            active_connections: 1,
+            transfers: 5,
+        };
+        let now = start + STALL_TIMEOUT * 3;
+        assert!(detector.is_alive(busy, now));
+        assert!(detector.is_alive(busy, now + STALL_TIMEOUT / 2));
+        assert!(!detector.is_alive(busy, now + STALL_TIMEOUT));
+
+        let progress = BridgeActivity {
+            active_connections: 1,
+            transfers: 6,
+        };
+        assert!(detector.is_alive(progress, now + STALL_TIMEOUT * 2));
+    }
+
+    #[test]
+    fn listen_fds_parsing() {
+        assert_eq!(
+            listen_fd(Some("42"), Some("1"), 42),
+            Some(SD_LISTEN_FDS_START)
+        );
+        assert_eq!(
+            listen_fd(Some("42"), Some("2"), 42),
+            Some(SD_LISTEN_FDS_START)
+        );
+        // The fds are meant for another process.
+        assert_eq!(listen_fd(Some("43"), Some("1"), 42), None);
+        assert_eq!(listen_fd(None, Some("1"), 42), None);
+        assert_eq!(listen_fd(Some("42"), None, 42), None);
+        assert_eq!(listen_fd(Some("42"), Some("0"), 42), None);
+        assert_eq!(listen_fd(Some("42"), Some("one"), 42), None);
+    }
+
+    fn is_cloexec(fd: RawFd) -> bool {
+        // Safe because F_GETFD does not touch memory.
+        let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+        flags >= 0 && flags & libc::FD_CLOEXEC != 0
+    }
+
+    #[test]
+    fn adopt_tcp_listener() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let fd = listener.into_raw_fd();
+        // Clear FD_CLOEXEC like systemd does.
+        // Safe because F_SETFD does not touch memory.
+        assert_eq!(unsafe { libc::fcntl(fd, libc::F_SETFD, 0) }, 0);
+
+        // Safe because the fd was released by the listener above.
+        let listener = match unsafe { adopt_listener(fd) }.unwrap() {
+            ActivatedListener::Tcp(listener) => listener,
+            other => panic!("Unexpected listener {:?}", other),
+        };
+        assert!(is_cloexec(listener.as_raw_fd()));
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+        client.write_all(b"ping").unwrap();
+        let mut buf = [0u8; 4];
+        server.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+    }
+
+    #[test]
+    fn adopt_unix_listener() {
+        let path = temp_socket_path("listener");
+        let fd = UnixListener::bind(&path).unwrap().into_raw_fd();
+
+        // Safe because the fd was released by the listener above.
+        let listener = match unsafe { adopt_listener(fd) }.unwrap() {
+            ActivatedListener::Unix(listener) => listener,
+            other => panic!("Unexpected listener {:?}", other),
+        };
+        let _client = UnixStream::connect(&path).unwrap();
+        assert!(listener.accept().is_ok());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn adopt_datagram_socket() {
+        let fd = UnixDatagram::unbound().unwrap().into_raw_fd();
+        // Safe because the fd was released by the socket above.
+        assert!(unsafe { adopt_listener(fd) }.is_err());
+        // Safe because adopt_listener() does not take the fd on failure.
+        unsafe { libc::close(fd) };
+    }
+}
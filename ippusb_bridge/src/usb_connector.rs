@@ -2,20 +2,23 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, VecDeque};
 use std::fmt;
 use std::io::{self, Read, Write};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 use std::thread;
 use std::time::{Duration, Instant};
 use std::vec::Vec;
 
-use libchromeos::deprecated::{EventFd, PollContext};
+use libchromeos::events::{EventFd, Poller};
 use log::{debug, error, info};
 use rusb::{Direction, GlobalContext, Registration, TransferType, UsbContext};
 use std::sync::{Condvar, Mutex};
 
+use crate::interface_health::{InterfaceHealth, InterfaceStats, Recovery};
+use crate::sd_notify::BridgeActivity;
+
 const USB_TRANSFER_TIMEOUT: Duration = Duration::from_secs(60);
 const USB_CLEANUP_TIMEOUT: Duration = Duration::from_secs(2);
 
@@ -249,6 +252,7 @@ fn read_ippusb_device_info<T: UsbContext>(
 struct ClaimedInterface {
     handle: rusb::DeviceHandle<GlobalContext>,
     descriptor: IppusbDescriptor,
+    health: InterfaceHealth,
 }
 
 impl ClaimedInterface {
@@ -273,10 +277,59 @@ impl ClaimedInterface {
     }
 }
 
+/// Recovers a suspect interface by clearing its endpoint halts or by resetting the device.
+///
+/// The device is reset through the handle of the interface, so recovery does not need the
+/// InterfaceManager state.
+struct UsbRecovery<'a> {
+    handle: &'a mut rusb::DeviceHandle<GlobalContext>,
+    descriptor: IppusbDescriptor,
+}
+
+impl Recovery for UsbRecovery<'_> {
+    fn reset_interface(&mut self) -> bool {
+        let descriptor = self.descriptor;
+        let result = self
+            .handle
+            .clear_halt(descriptor.in_endpoint)
+            .and_then(|_| self.handle.clear_halt(descriptor.out_endpoint))
+            .and_then(|_| {
+                self.handle.set_alternate_setting(
+                    descriptor.interface_number,
+                    descriptor.alternate_setting,
+                )
+            });
+        match result {
+            Ok(()) => true,
+            Err(e) => {
+                error!(
+                    "Failed to reset interface {}: {}",
+                    descriptor.interface_number, e
+                );
+                false
+            }
+        }
+    }
+
+    fn reset_device(&mut self) -> bool {
+        // If the device re-enumerates, this fails with NotFound and the UnplugDetector shuts us
+        // down.
+        match self.handle.reset() {
+            Ok(()) => true,
+            Err(e) => {
+                error!("Failed to reset device: {}", e);
+                false
+            }
+        }
+    }
+}
+
 /// InterfaceManagerState contains the internal state of InterfaceManager.  It is intended to
 /// be shared across InterfaceManager instances and protected by a mutex.
 struct InterfaceManagerState {
     interfaces: VecDeque<ClaimedInterface>,
+    num_interfaces: usize,
+    stats: BTreeMap<u8, InterfaceStats>,
     handle: rusb::DeviceHandle<GlobalContext>,
     usb_config: u8,
     active: usize,
@@ -328,6 +381,10 @@ impl InterfaceManagerState {
 /// If no interfaces are currently available, requesting an interface will block
 /// until an interface is freed by another thread.
 ///
+/// InterfaceManager also tracks the health of each interface.  Interfaces that keep
+/// failing are reset and, if that does not help, quarantined for a while so that
+/// requests are sent to the remaining interfaces.  See InterfaceHealth for details.
+///
 /// InterfaceManager maintains the invariant that interfaces are claimed when
 /// handed out.  It expects newly-inserted interfaces to be claimed by libusb and
 /// it ensures that they are still claimed when retrieved.  Internally it releases
@@ -337,6 +394,8 @@ impl InterfaceManagerState {
 pub struct InterfaceManager {
     interface_available: Arc<Condvar>,
     state: Arc<Mutex<InterfaceManagerState>>,
+    // USB transfers completed on any interface, used to detect a stalled bridge.
+    transfers: Arc<AtomicU64>,
 }
 
 impl InterfaceManager {
@@ -345,6 +404,16 @@ impl InterfaceManager {
         usb_config: u8,
         interfaces: Vec<ClaimedInterface>,
     ) -> Self {
+        let num_interfaces = interfaces.len();
+        let stats = interfaces
+            .iter()
+            .map(|interface| {
+                (
+                    interface.descriptor.interface_number,
+                    interface.health.stats(),
+                )
+            })
+            .collect();
         let mut deque: VecDeque<ClaimedInterface> = interfaces.into();
         for interface in &mut deque {
             interface.release().unwrap_or_else(|e| {
@@ -358,12 +427,15 @@ impl InterfaceManager {
             interface_available: Arc::new(Condvar::new()),
             state: Arc::new(Mutex::new(InterfaceManagerState {
                 interfaces: deque,
+                num_interfaces,
+                stats,
                 handle,
                 usb_config,
                 active: 0,
                 pending_cleanup: false,
                 next_cleanup: Instant::now(),
             })),
+            transfers: Arc::new(AtomicU64::new(0)),
         }
     }
 
@@ -465,7 +537,12 @@ impl InterfaceManager {
         state.active += 1;
 
         loop {
-            if let Some(interface) = state.interfaces.pop_front() {
+            let now = Instant::now();
+            let available = state
+                .interfaces
+                .iter()
+                .position(|interface| interface.health.is_available(now));
+            if let Some(interface) = available.and_then(|i| state.interfaces.remove(i)) {
                 debug!(
                     "* Using interface {}",
                     interface.descriptor.interface_number
@@ -473,17 +550,61 @@ impl InterfaceManager {
                 return Ok(interface);
             }
 
-            state = self.interface_available.wait(state).unwrap();
+            // If only quarantined interfaces are left, wake up when the first quarantine ends
+            // in case no other interface is returned before.
+            let quarantine_end = state
+                .interfaces
+                .iter()
+                .filter_map(|interface| interface.health.quarantined_until(now))
+                .min();
+            state = match quarantine_end {
+                Some(end) => {
+                    self.interface_available
+                        .wait_timeout(state, end - now)
+                        .unwrap()
+                        .0
+                }
+                None => self.interface_available.wait(state).unwrap(),
+            };
         }
     }
 
     /// Return an interface to the pool of interfaces.
-    fn free_interface(&mut self, interface: ClaimedInterface) {
+    ///
+    /// `succeeded` is the outcome of the request the interface was used for, or None if
+    /// the request did not transfer any data.
+    fn free_interface(&mut self, mut interface: ClaimedInterface, succeeded: Option<bool>) {
         debug!(
             "* Returning interface {}",
             interface.descriptor.interface_number
         );
+        if let Some(succeeded) = succeeded {
+            let now = Instant::now();
+            let is_last_available = {
+                let state = self.state.lock().unwrap();
+                let num_quarantined = state
+                    .interfaces
+                    .iter()
+                    .filter(|interface| !interface.health.is_available(now))
+                    .count();
+                num_quarantined + 1 >= state.num_interfaces
+            };
+            // Recovery does control transfers and may reset the device, which can take a
+            // while.  Run it without the lock so that other interfaces can still be handed out
+            // and returned.
+            let mut recovery = UsbRecovery {
+                handle: &mut interface.handle,
+                descriptor: interface.descriptor,
+            };
+            interface
+                .health
+                .record_request(succeeded, is_last_available, &mut recovery, now);
+        }
         let mut state = self.state.lock().unwrap();
+        state.stats.insert(
+            interface.descriptor.interface_number,
+            interface.health.stats(),
+        );
         state.interfaces.push_back(interface);
         state.next_cleanup = Instant::now() + USB_CLEANUP_TIMEOUT;
         state.pending_cleanup = true;
@@ -493,6 +614,24 @@ impl InterfaceManager {
         // waiting on this condition variable.
         self.interface_available.notify_all();
     }
+
+    /// Returns the health counters of every interface, ordered by interface number.
+    fn interface_stats(&self) -> Vec<(u8, InterfaceStats)> {
+        let state = self.state.lock().unwrap();
+        state
+            .stats
+            .iter()
+            .map(|(number, stats)| (*number, *stats))
+            .collect()
+    }
+
+    fn activity(&self) -> BridgeActivity {
+        let state = self.state.lock().unwrap();
+        BridgeActivity {
+            active_connections: state.active,
+            transfers: self.transfers.load(Ordering::Relaxed),
+        }
+    }
 }
 
 pub struct UnplugDetector {
@@ -596,9 +735,9 @@ impl CallbackHandler {
 
         info!("Waiting for shutdown signal");
         let timeout = Duration::from_secs(2);
-        let ctx: PollContext<u32> = PollContext::new().map_err(Error::Poll)?;
-        ctx.add(&self.shutdown_fd, 1).map_err(Error::Poll)?;
-        ctx.wait_timeout(timeout).map_err(Error::Poll)?;
+        let poller: Poller<u32> = Poller::new().map_err(Error::Poll)?;
+        poller.add(&self.shutdown_fd, 1).map_err(Error::Poll)?;
+        poller.wait_timeout(timeout).map_err(Error::Poll)?;
         Ok(())
     }
 }
@@ -685,6 +824,7 @@ impl UsbConnector {
             connections.push(ClaimedInterface {
                 handle: interface_handle,
                 descriptor,
+                health: InterfaceHealth::new(descriptor.interface_number),
             });
         }
 
@@ -703,6 +843,17 @@ impl UsbConnector {
         self.handle.device()
     }
 
+    /// Returns the health counters of every IPP-USB interface, ordered by interface number.
+    pub fn interface_stats(&self) -> Vec<(u8, InterfaceStats)> {
+        self.manager.interface_stats()
+    }
+
+    /// Returns the counters used to tell whether the bridge is stalled.  Blocks if the interface
+    /// manager is deadlocked, which also stops the watchdog pings.
+    pub fn activity(&self) -> BridgeActivity {
+        self.manager.activity()
+    }
+
     pub fn get_connection(&mut self) -> Result<UsbConnection> {
         let interface = self.manager.request_interface()?;
         Ok(UsbConnection::new(
@@ -721,6 +872,9 @@ pub struct UsbConnection {
     // `interface` is never None until the UsbConnection is dropped, at which point the
     // ClaimedInterface is returned to the pool of connections in InterfaceManager.
     interface: Option<ClaimedInterface>,
+    // Whether any transfer succeeded or failed in a way that hints at a wedged interface.
+    transfer_succeeded: AtomicBool,
+    transfer_failed: AtomicBool,
 }
 
 impl UsbConnection {
@@ -729,6 +883,21 @@ impl UsbConnection {
             verbose_log,
             manager,
             interface: Some(interface),
+            transfer_succeeded: AtomicBool::new(false),
+            transfer_failed: AtomicBool::new(false),
+        }
+    }
+
+    /// Records the outcome of a transfer for the interface health tracking.  Timeouts,
+    /// babble and stalls count as failures; other errors are not caused by the interface.
+    fn record_transfer<T>(&self, result: &rusb::Result<T>) {
+        self.manager.transfers.fetch_add(1, Ordering::Relaxed);
+        match result {
+            Ok(_) => self.transfer_succeeded.store(true, Ordering::Relaxed),
+            Err(rusb::Error::Timeout | rusb::Error::Overflow | rusb::Error::Pipe) => {
+                self.transfer_failed.store(true, Ordering::Relaxed)
+            }
+            Err(_) => {}
         }
     }
 }
@@ -737,7 +906,14 @@ impl Drop for UsbConnection {
     fn drop(&mut self) {
         // Unwrap because interface only becomes None at drop.
         let interface = self.interface.take().unwrap();
-        self.manager.free_interface(interface);
+        let succeeded = if self.transfer_failed.load(Ordering::Relaxed) {
+            Some(false)
+        } else if self.transfer_succeeded.load(Ordering::Relaxed) {
+            Some(true)
+        } else {
+            None
+        };
+        self.manager.free_interface(interface, succeeded);
     }
 }
 
@@ -758,10 +934,11 @@ impl Write for &UsbConnection {
         // Unwrap because interface only becomes None at drop.
         let interface = self.interface.as_ref().unwrap();
         let endpoint = interface.descriptor.out_endpoint;
-        let written = interface
+        let result = interface
             .handle
-            .write_bulk(endpoint, buf, USB_TRANSFER_TIMEOUT)
-            .map_err(to_io_error)?;
+            .write_bulk(endpoint, buf, USB_TRANSFER_TIMEOUT);
+        self.record_transfer(&result);
+        let written = result.map_err(to_io_error)?;
 
         if self.verbose_log {
             let mut output = String::new();
@@ -792,10 +969,14 @@ impl Read for &UsbConnection {
         let interface = self.interface.as_ref().unwrap();
         let endpoint = interface.descriptor.in_endpoint;
         let start = Instant::now();
-        let mut result = interface
-            .handle
-            .read_bulk(endpoint, buf, USB_TRANSFER_TIMEOUT)
-            .map_err(to_io_error);
+        let mut read = || {
+            let result = interface
+                .handle
+                .read_bulk(endpoint, buf, USB_TRANSFER_TIMEOUT);
+            self.record_transfer(&result);
+            result.map_err(to_io_error)
+        };
+        let mut result = read();
         let mut zero_reads = 0;
 
         // USB reads cannot hit EOF. We will retry after a short delay so that higher-level
@@ -803,6 +984,7 @@ impl Read for &UsbConnection {
         while let Ok(0) = result {
             zero_reads += 1;
             if start.elapsed() > USB_TRANSFER_TIMEOUT {
+                self.transfer_failed.store(true, Ordering::Relaxed);
                 result = Err(io::Error::new(
                     io::ErrorKind::TimedOut,
                     "Timed out waiting for non-zero USB read",
@@ -810,10 +992,7 @@ impl Read for &UsbConnection {
                 break;
             }
             thread::sleep(Duration::from_millis(10));
-            result = interface
-                .handle
-                .read_bulk(endpoint, buf, USB_TRANSFER_TIMEOUT)
-                .map_err(to_io_error);
+            result = read();
         }
 
         if zero_reads > 0 {
@@ -10,7 +10,7 @@ use std::{
     time::{Duration, Instant},
 };
 
-use crate::deprecated::EventFd;
+use crate::events::EventFd;
 
 #[derive(Debug, Copy, Clone)]
 pub struct Clock(Instant);
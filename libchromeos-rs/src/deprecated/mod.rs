@@ -5,6 +5,9 @@
 //! Modules brought over from sys_util before it was reworked into crosvm-base that are no longer
 //! maintained. Please do not use these for any new code.
 
+// The modules still use the deprecated items among themselves until they are removed.
+#![allow(deprecated)]
+
 mod clock;
 mod eventfd;
 mod linux;
@@ -19,3 +22,11 @@ pub use poll::*;
 pub use poll_token_derive::*;
 pub use scoped_event_macro::*;
 pub use timerfd::*;
+
+pub use crate::events::{EventReadResult, PollToken, WatchingEvents};
+
+#[deprecated(note = "use libchromeos::events::EventFd instead")]
+pub type EventFd = crate::events::EventFd;
+
+#[deprecated(note = "use libchromeos::events::Timer instead")]
+pub type TimerFd = crate::events::Timer;
@@ -14,6 +14,7 @@ use std::{
     time::Duration,
 };
 
+use crate::events::{PollToken, WatchingEvents};
 use crate::handle_eintr_errno;
 use libc::{
     c_int, epoll_create1, epoll_ctl, epoll_event, epoll_wait, EPOLLHUP, EPOLLIN, EPOLLOUT,
@@ -42,81 +43,6 @@ impl Default for EpollEvents {
     }
 }
 
-/// Trait for a token that can be associated with an `fd` in a `PollContext`.
-///
-/// Simple enums that have no or primitive variant data data can use the `#[derive(PollToken)]`
-/// custom derive to implement this trait. See
-/// [poll_token_derive::poll_token](../poll_token_derive/fn.poll_token.html) for details.
-pub trait PollToken {
-    /// Converts this token into a u64 that can be turned back into a token via `from_raw_token`.
-    fn as_raw_token(&self) -> u64;
-
-    /// Converts a raw token as returned from `as_raw_token` back into a token.
-    ///
-    /// It is invalid to give a raw token that was not returned via `as_raw_token` from the same
-    /// `Self`. The implementation can expect that this will never happen as a result of its usage
-    /// in `PollContext`.
-    fn from_raw_token(data: u64) -> Self;
-}
-
-impl PollToken for usize {
-    fn as_raw_token(&self) -> u64 {
-        *self as u64
-    }
-
-    fn from_raw_token(data: u64) -> Self {
-        data as Self
-    }
-}
-
-impl PollToken for u64 {
-    fn as_raw_token(&self) -> u64 {
-        *self
-    }
-
-    fn from_raw_token(data: u64) -> Self {
-        data as Self
-    }
-}
-
-impl PollToken for u32 {
-    fn as_raw_token(&self) -> u64 {
-        u64::from(*self)
-    }
-
-    fn from_raw_token(data: u64) -> Self {
-        data as Self
-    }
-}
-
-impl PollToken for u16 {
-    fn as_raw_token(&self) -> u64 {
-        u64::from(*self)
-    }
-
-    fn from_raw_token(data: u64) -> Self {
-        data as Self
-    }
-}
-
-impl PollToken for u8 {
-    fn as_raw_token(&self) -> u64 {
-        u64::from(*self)
-    }
-
-    fn from_raw_token(data: u64) -> Self {
-        data as Self
-    }
-}
-
-impl PollToken for () {
-    fn as_raw_token(&self) -> u64 {
-        0
-    }
-
-    fn from_raw_token(_data: u64) -> Self {}
-}
-
 /// An event returned by `PollContext::wait`.
 pub struct PollEvent<'a, T> {
     event: &'a epoll_event,
@@ -254,44 +180,11 @@ impl<T: PollToken> PollEventsOwned<T> {
     }
 }
 
-/// Watching events taken by PollContext.
-pub struct WatchingEvents(u32);
-
-impl WatchingEvents {
-    /// Returns empty Events.
-    #[inline(always)]
-    pub fn empty() -> WatchingEvents {
-        WatchingEvents(0)
-    }
-
-    /// Build Events from raw epoll events (defined in epoll_ctl(2)).
-    #[inline(always)]
-    pub fn new(raw: u32) -> WatchingEvents {
-        WatchingEvents(raw)
-    }
-
-    /// Set read events.
-    #[inline(always)]
-    pub fn set_read(self) -> WatchingEvents {
-        WatchingEvents(self.0 | EPOLLIN as u32)
-    }
-
-    /// Set write events.
-    #[inline(always)]
-    pub fn set_write(self) -> WatchingEvents {
-        WatchingEvents(self.0 | EPOLLOUT as u32)
-    }
-
-    /// Get the underlying epoll events.
-    pub fn get_raw(&self) -> u32 {
-        self.0
-    }
-}
-
 /// EpollContext wraps linux epoll. It provides similar interface to PollContext.
 /// It is thread safe while PollContext is not. It requires user to pass in a reference of
 /// EpollEvents while PollContext does not. Always use PollContext if you don't need to access the
 /// same epoll from different threads.
+#[deprecated(note = "use libchromeos::events::Poller instead")]
 pub struct EpollContext<T> {
     epoll_ctx: File,
     // Needed to satisfy usage of T
@@ -381,7 +274,7 @@ impl<T: PollToken> EpollContext<T> {
     /// `events` and the token associated with it will be replaced with the given `token`.
     pub fn modify(&self, fd: &dyn AsRawFd, events: WatchingEvents, token: T) -> Result<()> {
         let mut evt = epoll_event {
-            events: events.0,
+            events: events.get_raw(),
             u64: token.as_raw_token(),
         };
         // Safe because we give a valid epoll FD and FD to modify, as well as a valid epoll_event
@@ -518,6 +411,7 @@ impl<T: PollToken> IntoRawFd for EpollContext<T> {
 /// #   Ok(())
 /// # }
 /// ```
+#[deprecated(note = "use libchromeos::events::Poller instead")]
 pub struct PollContext<T> {
     epoll_ctx: EpollContext<T>,
 
@@ -694,7 +588,8 @@ impl<T: PollToken> IntoRawFd for PollContext<T> {
 
 #[cfg(test)]
 mod tests {
-    use super::{super::EventFd, *};
+    use super::*;
+    use crate::events::EventFd;
     use poll_token_derive::PollToken;
     use std::{os::unix::net::UnixStream, time::Instant};
 
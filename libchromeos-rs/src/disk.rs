@@ -5,7 +5,12 @@
 //! Utilities for interacting with the disk.
 
 use std::{
-    os::unix::prelude::OsStrExt,
+    fs::{File, OpenOptions},
+    io,
+    os::unix::{
+        fs::{FileExt, OpenOptionsExt},
+        prelude::OsStrExt,
+    },
     path::{Path, PathBuf},
 };
 
@@ -60,9 +65,120 @@ fn get_partition_device_by_path_or_id<P: AsRef<Path>>(disk_device: P, num: u32)
     PathBuf::from(buf)
 }
 
+/// Size of the tag identifying a [`RawBlockCookie`] on disk.
+pub const COOKIE_TAG_SIZE: usize = 8;
+
+const COOKIE_CRC_SIZE: usize = 4;
+
+/// A small fixed-size value stored directly on a block device, outside of any
+/// file system.
+///
+/// This is useful for flags that need to be read before file systems are
+/// mounted, or that decide how they are mounted, e.g. in space left unused by
+/// the partition table. The value is stored at `offset` as:
+///
+/// | tag (8 bytes) | value (N bytes) | CRC-32 of tag and value (4 bytes, LE) |
+///
+/// The tag identifies the owner of the cookie, so that a cookie is never read
+/// from, and ideally never written over, data belonging to something else.
+pub struct RawBlockCookie<const N: usize> {
+    file: File,
+    offset: u64,
+    tag: [u8; COOKIE_TAG_SIZE],
+}
+
+impl<const N: usize> RawBlockCookie<N> {
+    /// Size of the cookie on disk, in bytes.
+    pub const DISK_SIZE: usize = COOKIE_TAG_SIZE + N + COOKIE_CRC_SIZE;
+
+    /// Opens the block device (or file) at `path` for reading and writing the
+    /// cookie. Writes are synchronous.
+    pub fn open<P: AsRef<Path>>(
+        path: P,
+        offset: u64,
+        tag: [u8; COOKIE_TAG_SIZE],
+    ) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_SYNC)
+            .open(path)?;
+        Ok(Self::new(file, offset, tag))
+    }
+
+    /// Uses an already opened block device (or file) for the cookie.
+    pub fn new(file: File, offset: u64, tag: [u8; COOKIE_TAG_SIZE]) -> Self {
+        RawBlockCookie { file, offset, tag }
+    }
+
+    /// Reads the cookie value.
+    ///
+    /// Returns `None` if the tag is not found at the offset, i.e. the cookie was
+    /// never written or was cleared. Returns an `InvalidData` error if the tag
+    /// is found but the CRC does not match.
+    pub fn read(&self) -> io::Result<Option<[u8; N]>> {
+        let mut buf = vec![0u8; Self::DISK_SIZE];
+        self.file.read_exact_at(&mut buf, self.offset)?;
+        if buf[..COOKIE_TAG_SIZE] != self.tag {
+            return Ok(None);
+        }
+
+        let (data, crc) = buf.split_at(COOKIE_TAG_SIZE + N);
+        let mut expected_crc = [0u8; COOKIE_CRC_SIZE];
+        expected_crc.copy_from_slice(crc);
+        if crc32(data) != u32::from_le_bytes(expected_crc) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "cookie CRC mismatch",
+            ));
+        }
+
+        let mut value = [0u8; N];
+        value.copy_from_slice(&data[COOKIE_TAG_SIZE..]);
+        Ok(Some(value))
+    }
+
+    /// Writes the cookie value. Only the bytes of the cookie are modified.
+    pub fn write(&self, value: &[u8; N]) -> io::Result<()> {
+        let mut buf = Vec::with_capacity(Self::DISK_SIZE);
+        buf.extend_from_slice(&self.tag);
+        buf.extend_from_slice(value);
+        let crc = crc32(&buf);
+        buf.extend_from_slice(&crc.to_le_bytes());
+        self.write_raw(&buf)
+    }
+
+    /// Clears the cookie so that subsequent reads return `None`.
+    pub fn clear(&self) -> io::Result<()> {
+        self.write_raw(&vec![0u8; Self::DISK_SIZE])
+    }
+
+    fn write_raw(&self, buf: &[u8]) -> io::Result<()> {
+        self.file.write_all_at(buf, self.offset)?;
+        self.file.sync_data()
+    }
+}
+
+/// Computes the CRC-32 (IEEE 802.3) checksum of the data.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::scoped_path::{get_temp_path, ScopedPath};
 
     #[test]
     fn test_get_partition_device() {
@@ -118,4 +234,89 @@ mod tests {
             result.unwrap()
         );
     }
+
+    const TAG: [u8; COOKIE_TAG_SIZE] = *b"TestTag!";
+    const BLOCK_SIZE: u64 = 512;
+
+    /// Creates a 4 block file filled with 0xaa standing in for a block device.
+    fn fake_block_device(dir: &Path) -> PathBuf {
+        let path = dir.join("blockdev");
+        std::fs::write(&path, vec![0xaau8; 4 * BLOCK_SIZE as usize]).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_raw_block_cookie() {
+        let dir = ScopedPath::create(get_temp_path(Some("test_raw_block_cookie"))).unwrap();
+        let path = fake_block_device(&dir);
+        let offset = 2 * BLOCK_SIZE + 100;
+
+        let cookie = RawBlockCookie::<16>::open(&path, offset, TAG).unwrap();
+        assert_eq!(cookie.read().unwrap(), None);
+
+        cookie.write(b"InstallComplete!").unwrap();
+        assert_eq!(cookie.read().unwrap(), Some(*b"InstallComplete!"));
+        // Reopening reads the value back from the device.
+        let cookie = RawBlockCookie::<16>::open(&path, offset, TAG).unwrap();
+        assert_eq!(cookie.read().unwrap(), Some(*b"InstallComplete!"));
+        cookie.write(b"InstallStarted..").unwrap();
+        assert_eq!(cookie.read().unwrap(), Some(*b"InstallStarted.."));
+
+        // The bytes around the cookie are untouched.
+        let contents = std::fs::read(&path).unwrap();
+        let start = offset as usize;
+        let end = start + RawBlockCookie::<16>::DISK_SIZE;
+        assert!(contents[..start].iter().all(|b| *b == 0xaa));
+        assert!(contents[end..].iter().all(|b| *b == 0xaa));
+
+        cookie.clear().unwrap();
+        assert_eq!(cookie.read().unwrap(), None);
+    }
+
+    #[test]
+    fn test_raw_block_cookie_tag_mismatch() {
+        let dir = ScopedPath::create(get_temp_path(Some("test_raw_block_cookie_tag"))).unwrap();
+        let path = fake_block_device(&dir);
+
+        RawBlockCookie::<4>::open(&path, BLOCK_SIZE, TAG)
+            .unwrap()
+            .write(b"data")
+            .unwrap();
+        let other = RawBlockCookie::<4>::open(&path, BLOCK_SIZE, *b"OtherTag").unwrap();
+        assert_eq!(other.read().unwrap(), None);
+    }
+
+    #[test]
+    fn test_raw_block_cookie_corrupt() {
+        let dir = ScopedPath::create(get_temp_path(Some("test_raw_block_cookie_corrupt"))).unwrap();
+        let path = fake_block_device(&dir);
+
+        let cookie = RawBlockCookie::<4>::open(&path, 0, TAG).unwrap();
+        cookie.write(b"data").unwrap();
+        // Flip a bit of the value.
+        let mut contents = std::fs::read(&path).unwrap();
+        contents[COOKIE_TAG_SIZE] ^= 1;
+        std::fs::write(&path, contents).unwrap();
+
+        let err = cookie.read().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_raw_block_cookie_out_of_range() {
+        let dir = ScopedPath::create(get_temp_path(Some("test_raw_block_cookie_range"))).unwrap();
+        let path = fake_block_device(&dir);
+
+        let cookie = RawBlockCookie::<16>::open(&path, 4 * BLOCK_SIZE - 8, TAG).unwrap();
+        assert_eq!(
+            cookie.read().unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+    }
 }
@@ -0,0 +1,208 @@
+// Copyright 2024 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+use std::io;
+use std::mem::size_of;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, OwnedFd, RawFd};
+use std::ptr;
+use std::time::Duration;
+
+use libc::c_void;
+use nix::{Error, Result};
+
+use super::duration_to_timespec;
+use crate::handle_eintr_errno;
+
+/// A safe wrapper around a Linux eventfd (man 2 eventfd).
+///
+/// The eventfd holds a 64-bit counter: writes add to it and reads return it and reset it to zero.
+/// It is created with `EFD_CLOEXEC` and can be polled like any other file descriptor.
+#[derive(Debug)]
+pub struct EventFd {
+    fd: OwnedFd,
+}
+
+/// The result of `EventFd::read_timeout`: either the counter of the eventfd or a timeout while
+/// waiting for it to become non-zero.
+#[derive(Debug, PartialEq, Eq)]
+pub enum EventReadResult {
+    Count(u64),
+    Timeout,
+}
+
+impl EventFd {
+    /// Creates a new blocking eventfd with a counter of 0.
+    pub fn new() -> Result<EventFd> {
+        // SAFETY: eventfd(2) only allocates a new file descriptor and the error case is checked.
+        let ret = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC) };
+        if ret < 0 {
+            return Err(Error::last());
+        }
+        // SAFETY: The kernel gave us a new file descriptor that nothing else owns.
+        Ok(EventFd {
+            fd: unsafe { OwnedFd::from_raw_fd(ret) },
+        })
+    }
+
+    /// Adds `v` to the counter, blocking until this won't overflow the counter.
+    ///
+    /// Fails with `EINVAL` if `v` is `u64::MAX`.
+    pub fn write(&self, v: u64) -> Result<()> {
+        let ret = handle_eintr_errno!(
+            // SAFETY: The buffer is a u64 that outlives the call and its size is passed properly.
+            unsafe {
+                libc::write(
+                    self.fd.as_raw_fd(),
+                    &v as *const u64 as *const c_void,
+                    size_of::<u64>(),
+                )
+            }
+        );
+        if ret < 0 {
+            return Err(Error::last());
+        }
+        Ok(())
+    }
+
+    /// Blocks until the counter is non-zero, then returns it and resets it to zero.
+    pub fn read(&self) -> Result<u64> {
+        let mut count: u64 = 0;
+        let ret = handle_eintr_errno!(
+            // SAFETY: The buffer is a u64 that outlives the call and its size is passed properly.
+            unsafe {
+                libc::read(
+                    self.fd.as_raw_fd(),
+                    &mut count as *mut u64 as *mut c_void,
+                    size_of::<u64>(),
+                )
+            }
+        );
+        if ret < 0 {
+            return Err(Error::last());
+        }
+        Ok(count)
+    }
+
+    /// Like `read` except it only blocks for a maximum of `timeout`.
+    pub fn read_timeout(&self, timeout: Duration) -> Result<EventReadResult> {
+        let mut pfd = libc::pollfd {
+            fd: self.fd.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout = duration_to_timespec(timeout);
+        // SAFETY: ppoll(2) only modifies `pfd`, which outlives the call, and the return value is
+        // checked.
+        let ret = handle_eintr_errno!(unsafe { libc::ppoll(&mut pfd, 1, &timeout, ptr::null()) });
+        if ret < 0 {
+            return Err(Error::last());
+        }
+        // No returned events means the timeout expired.
+        if pfd.revents == 0 {
+            return Ok(EventReadResult::Timeout);
+        }
+        Ok(EventReadResult::Count(self.read()?))
+    }
+
+    /// Creates a new file descriptor sharing the counter of this eventfd.
+    pub fn try_clone(&self) -> io::Result<EventFd> {
+        self.fd.try_clone().map(|fd| EventFd { fd })
+    }
+}
+
+impl AsFd for EventFd {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+impl AsRawFd for EventFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+impl From<OwnedFd> for EventFd {
+    fn from(fd: OwnedFd) -> Self {
+        EventFd { fd }
+    }
+}
+
+impl From<EventFd> for OwnedFd {
+    fn from(event_fd: EventFd) -> Self {
+        event_fd.fd
+    }
+}
+
+impl FromRawFd for EventFd {
+    unsafe fn from_raw_fd(fd: RawFd) -> Self {
+        EventFd {
+            fd: OwnedFd::from_raw_fd(fd),
+        }
+    }
+}
+
+impl IntoRawFd for EventFd {
+    fn into_raw_fd(self) -> RawFd {
+        self.fd.into_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nix::errno::Errno;
+    use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+
+    use super::*;
+
+    #[test]
+    fn close_on_exec() {
+        let evt = EventFd::new().unwrap();
+        let flags = FdFlag::from_bits_truncate(fcntl(evt.as_raw_fd(), FcntlArg::F_GETFD).unwrap());
+        assert!(flags.contains(FdFlag::FD_CLOEXEC));
+    }
+
+    #[test]
+    fn counter() {
+        let evt = EventFd::new().unwrap();
+        // Writes add up until the counter is read.
+        evt.write(1).unwrap();
+        evt.write(54).unwrap();
+        assert_eq!(evt.read(), Ok(55));
+        // Reading resets the counter.
+        assert_eq!(
+            evt.read_timeout(Duration::from_millis(1)),
+            Ok(EventReadResult::Timeout)
+        );
+        evt.write(3).unwrap();
+        assert_eq!(
+            evt.read_timeout(Duration::from_millis(1)),
+            Ok(EventReadResult::Count(3))
+        );
+    }
+
+    #[test]
+    fn invalid_write() {
+        let evt = EventFd::new().unwrap();
+        assert_eq!(evt.write(u64::MAX), Err(Errno::EINVAL));
+    }
+
+    #[test]
+    fn clone() {
+        let evt = EventFd::new().unwrap();
+        let evt_clone = evt.try_clone().unwrap();
+        assert_ne!(evt.as_raw_fd(), evt_clone.as_raw_fd());
+        evt.write(923).unwrap();
+        assert_eq!(evt_clone.read(), Ok(923));
+    }
+
+    #[test]
+    fn owned_fd_conversion() {
+        let evt = EventFd::new().unwrap();
+        evt.write(7).unwrap();
+        let fd: OwnedFd = evt.into();
+        let evt = EventFd::from(fd);
+        assert_eq!(evt.read(), Ok(7));
+    }
+}
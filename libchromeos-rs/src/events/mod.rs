@@ -0,0 +1,32 @@
+// Copyright 2024 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Safe wrappers around the Linux event primitives: eventfd, epoll and timerfd.
+//!
+//! These replace the equivalents in the `deprecated` module. The APIs are close enough that
+//! migrating is mostly a matter of changing the imports: `deprecated::EventFd` is `EventFd`,
+//! `deprecated::TimerFd` is `Timer` and `deprecated::PollContext` is `Poller`, which takes `AsFd`
+//! sources instead of `AsRawFd` ones.
+
+mod eventfd;
+mod poll;
+mod timer;
+
+use std::time::Duration;
+
+pub use eventfd::*;
+pub use poll::*;
+pub use poll_token_derive::*;
+pub use timer::*;
+
+/// Converts `duration` to a timespec.
+#[allow(clippy::useless_conversion)]
+pub(crate) fn duration_to_timespec(duration: Duration) -> libc::timespec {
+    // nsec always fits in i32 because subsec_nanos is defined to be less than one billion.
+    let nsec = duration.subsec_nanos() as i32;
+    libc::timespec {
+        tv_sec: duration.as_secs() as libc::time_t,
+        tv_nsec: nsec.into(),
+    }
+}
@@ -0,0 +1,574 @@
+// Copyright 2024 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+use std::cell::{Ref, RefCell};
+use std::marker::PhantomData;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd};
+use std::ptr;
+use std::slice;
+use std::time::Duration;
+
+use libc::{epoll_event, EPOLLET, EPOLLHUP, EPOLLIN, EPOLLOUT, EPOLLRDHUP};
+use nix::{Error, Result};
+
+use crate::handle_eintr_errno;
+
+const POLLER_MAX_EVENTS: usize = 16;
+
+/// Trait for a token that can be associated with a file descriptor in a `Poller`.
+///
+/// Simple enums that have no or primitive variant data data can use the `#[derive(PollToken)]`
+/// custom derive to implement this trait. See
+/// [poll_token_derive::poll_token](../poll_token_derive/fn.poll_token.html) for details.
+pub trait PollToken {
+    /// Converts this token into a u64 that can be turned back into a token via `from_raw_token`.
+    fn as_raw_token(&self) -> u64;
+
+    /// Converts a raw token as returned from `as_raw_token` back into a token.
+    ///
+    /// It is invalid to give a raw token that was not returned via `as_raw_token` from the same
+    /// `Self`. The implementation can expect that this will never happen as a result of its usage
+    /// in `Poller`.
+    fn from_raw_token(data: u64) -> Self;
+}
+
+impl PollToken for usize {
+    fn as_raw_token(&self) -> u64 {
+        *self as u64
+    }
+
+    fn from_raw_token(data: u64) -> Self {
+        data as Self
+    }
+}
+
+impl PollToken for u64 {
+    fn as_raw_token(&self) -> u64 {
+        *self
+    }
+
+    fn from_raw_token(data: u64) -> Self {
+        data as Self
+    }
+}
+
+impl PollToken for u32 {
+    fn as_raw_token(&self) -> u64 {
+        u64::from(*self)
+    }
+
+    fn from_raw_token(data: u64) -> Self {
+        data as Self
+    }
+}
+
+impl PollToken for u16 {
+    fn as_raw_token(&self) -> u64 {
+        u64::from(*self)
+    }
+
+    fn from_raw_token(data: u64) -> Self {
+        data as Self
+    }
+}
+
+impl PollToken for u8 {
+    fn as_raw_token(&self) -> u64 {
+        u64::from(*self)
+    }
+
+    fn from_raw_token(data: u64) -> Self {
+        data as Self
+    }
+}
+
+impl PollToken for () {
+    fn as_raw_token(&self) -> u64 {
+        0
+    }
+
+    fn from_raw_token(_data: u64) -> Self {}
+}
+
+/// The events to watch for on a file descriptor added to a `Poller`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WatchingEvents(u32);
+
+impl WatchingEvents {
+    /// Returns empty Events.
+    #[inline(always)]
+    pub fn empty() -> WatchingEvents {
+        WatchingEvents(0)
+    }
+
+    /// Build Events from raw epoll events (defined in epoll_ctl(2)).
+    #[inline(always)]
+    pub fn new(raw: u32) -> WatchingEvents {
+        WatchingEvents(raw)
+    }
+
+    /// Set read events.
+    #[inline(always)]
+    pub fn set_read(self) -> WatchingEvents {
+        WatchingEvents(self.0 | EPOLLIN as u32)
+    }
+
+    /// Set write events.
+    #[inline(always)]
+    pub fn set_write(self) -> WatchingEvents {
+        WatchingEvents(self.0 | EPOLLOUT as u32)
+    }
+
+    /// Report the events only when they occur instead of as long as they are pending.
+    ///
+    /// With edge-triggered events, the file descriptor should be non-blocking and drained until
+    /// it returns `EAGAIN` when it is reported.
+    #[inline(always)]
+    pub fn set_edge_triggered(self) -> WatchingEvents {
+        WatchingEvents(self.0 | EPOLLET as u32)
+    }
+
+    /// Get the underlying epoll events.
+    pub fn get_raw(&self) -> u32 {
+        self.0
+    }
+}
+
+/// An event returned by `Poller::wait`.
+pub struct PollEvent<'a, T> {
+    event: &'a epoll_event,
+    token: PhantomData<T>, // Needed to satisfy usage of T
+}
+
+impl<'a, T: PollToken> PollEvent<'a, T> {
+    /// Gets the token associated in `Poller::add` with this event.
+    pub fn token(&self) -> T {
+        T::from_raw_token(self.event.u64)
+    }
+
+    /// True if the file descriptor associated with this event is readable.
+    pub fn readable(&self) -> bool {
+        self.event.events & (EPOLLIN as u32) != 0
+    }
+
+    /// True if the file descriptor associated with this event is writable.
+    pub fn writable(&self) -> bool {
+        self.event.events & (EPOLLOUT as u32) != 0
+    }
+
+    /// True if the file descriptor associated with this event has been hungup on.
+    pub fn hungup(&self) -> bool {
+        self.event.events & ((EPOLLHUP | EPOLLRDHUP) as u32) != 0
+    }
+}
+
+/// An iterator over some (sub)set of events returned by `Poller::wait`.
+pub struct PollEventIter<'a, T> {
+    mask: u32,
+    iter: slice::Iter<'a, epoll_event>,
+    tokens: PhantomData<[T]>, // Needed to satisfy usage of T
+}
+
+impl<'a, T: PollToken> Iterator for PollEventIter<'a, T> {
+    type Item = PollEvent<'a, T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mask = self.mask;
+        self.iter
+            .find(|event| (event.events & mask) != 0)
+            .map(|event| PollEvent {
+                event,
+                token: PhantomData,
+            })
+    }
+}
+
+/// The list of events returned by `Poller::wait`.
+///
+/// The events borrow the buffer of the `Poller`, so they must be dropped before the next call to
+/// `wait`. File descriptors can be added to and deleted from the `Poller` while iterating.
+pub struct PollEvents<'a, T> {
+    count: usize,
+    events: Ref<'a, [epoll_event; POLLER_MAX_EVENTS]>,
+    tokens: PhantomData<[T]>, // Needed to satisfy usage of T
+}
+
+impl<'a, T: PollToken> PollEvents<'a, T> {
+    fn iter_mask(&self, mask: u32) -> PollEventIter<'_, T> {
+        PollEventIter {
+            mask,
+            iter: self.events[..self.count].iter(),
+            tokens: PhantomData,
+        }
+    }
+
+    /// Returns the number of events.
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    /// True if no event occurred before the timeout.
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Iterates over each event.
+    pub fn iter(&self) -> PollEventIter<'_, T> {
+        self.iter_mask(0xffff_ffff)
+    }
+
+    /// Iterates over each readable event.
+    pub fn iter_readable(&self) -> PollEventIter<'_, T> {
+        self.iter_mask(EPOLLIN as u32)
+    }
+
+    /// Iterates over each writable event.
+    pub fn iter_writable(&self) -> PollEventIter<'_, T> {
+        self.iter_mask(EPOLLOUT as u32)
+    }
+
+    /// Iterates over each hungup event.
+    pub fn iter_hungup(&self) -> PollEventIter<'_, T> {
+        self.iter_mask((EPOLLHUP | EPOLLRDHUP) as u32)
+    }
+}
+
+impl<'a, T: PollToken> IntoIterator for &'a PollEvents<'_, T> {
+    type Item = PollEvent<'a, T>;
+    type IntoIter = PollEventIter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Polls file descriptors with epoll and reports their events with typed tokens.
+///
+/// # Example
+///
+/// ```
+/// # use libchromeos::events::{EventFd, PollEvents, Poller};
+/// # use nix::Result;
+/// # fn test() -> Result<()> {
+///     let evt1 = EventFd::new()?;
+///     let evt2 = EventFd::new()?;
+///     evt2.write(1)?;
+///
+///     let poller: Poller<u32> = Poller::new()?;
+///     poller.add(&evt1, 1)?;
+///     poller.add(&evt2, 2)?;
+///
+///     let events: PollEvents<u32> = poller.wait()?;
+///     let tokens: Vec<u32> = events.iter_readable().map(|e| e.token()).collect();
+///     assert_eq!(&tokens[..], &[2]);
+/// #   Ok(())
+/// # }
+/// ```
+pub struct Poller<T> {
+    epoll: OwnedFd,
+    // The buffer is in a RefCell so that `wait` only needs an immutable reference, which lets the
+    // caller add and delete file descriptors while handling the returned events.
+    events: RefCell<[epoll_event; POLLER_MAX_EVENTS]>,
+    tokens: PhantomData<[T]>, // Needed to satisfy usage of T
+}
+
+impl<T: PollToken> Poller<T> {
+    /// Creates a new `Poller`.
+    pub fn new() -> Result<Poller<T>> {
+        // SAFETY: epoll_create1(2) only allocates a new file descriptor and the error case is
+        // checked.
+        let ret = unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) };
+        if ret < 0 {
+            return Err(Error::last());
+        }
+        Ok(Poller {
+            // SAFETY: The kernel gave us a new file descriptor that nothing else owns.
+            epoll: unsafe { OwnedFd::from_raw_fd(ret) },
+            events: RefCell::new([epoll_event { events: 0, u64: 0 }; POLLER_MAX_EVENTS]),
+            tokens: PhantomData,
+        })
+    }
+
+    /// Creates a new `Poller` and adds the slice of `fd` and `token` tuples to it.
+    ///
+    /// This is equivalent to calling `new` followed by `add_many`.
+    pub fn build_with(fd_tokens: &[(&dyn AsFd, T)]) -> Result<Poller<T>> {
+        let poller = Poller::new()?;
+        poller.add_many(fd_tokens)?;
+        Ok(poller)
+    }
+
+    /// Adds the given slice of `fd` and `token` tuples to this `Poller`.
+    ///
+    /// This is equivalent to calling `add` with each `fd` and `token`. If there are any errors,
+    /// this method will stop adding `fd`s and return the first error, leaving the `fd`s added
+    /// so far registered.
+    pub fn add_many(&self, fd_tokens: &[(&dyn AsFd, T)]) -> Result<()> {
+        for (fd, token) in fd_tokens {
+            self.add(*fd, T::from_raw_token(token.as_raw_token()))?;
+        }
+        Ok(())
+    }
+
+    /// Adds the given `fd` to this `Poller` and associates the given `token` with the `fd`'s
+    /// level-triggered readable events.
+    ///
+    /// A `fd` can only be added once and does not need to be kept open. If the `fd` is closed and
+    /// there were no duplicates of it, events will not be reported by `wait` anymore.
+    pub fn add(&self, fd: &dyn AsFd, token: T) -> Result<()> {
+        self.add_fd_with_events(fd, WatchingEvents::empty().set_read(), token)
+    }
+
+    /// Adds the given `fd` to this `Poller`, watching for the specified events and associates the
+    /// given `token` with those events.
+    pub fn add_fd_with_events(
+        &self,
+        fd: &dyn AsFd,
+        events: WatchingEvents,
+        token: T,
+    ) -> Result<()> {
+        self.ctl(libc::EPOLL_CTL_ADD, fd.as_fd(), Some((events, token)))
+    }
+
+    /// If `fd` was previously added to this `Poller`, the watched events will be replaced with
+    /// `events` and the token associated with it will be replaced with the given `token`.
+    pub fn modify(&self, fd: &dyn AsFd, events: WatchingEvents, token: T) -> Result<()> {
+        self.ctl(libc::EPOLL_CTL_MOD, fd.as_fd(), Some((events, token)))
+    }
+
+    /// Deletes the given `fd` from this `Poller`.
+    ///
+    /// A hungup `fd` must be deleted or closed, otherwise `wait` keeps returning immediately with
+    /// its hangup event.
+    pub fn delete(&self, fd: &dyn AsFd) -> Result<()> {
+        self.ctl(libc::EPOLL_CTL_DEL, fd.as_fd(), None)
+    }
+
+    fn ctl(
+        &self,
+        op: libc::c_int,
+        fd: BorrowedFd,
+        event: Option<(WatchingEvents, T)>,
+    ) -> Result<()> {
+        let mut event = event.map(|(events, token)| epoll_event {
+            events: events.get_raw(),
+            u64: token.as_raw_token(),
+        });
+        let event_ptr = event
+            .as_mut()
+            .map_or(ptr::null_mut(), |event| event as *mut _);
+        // SAFETY: Both file descriptors are valid and the event, if any, outlives the call. The
+        // return value is checked.
+        let ret = unsafe { libc::epoll_ctl(self.epoll.as_raw_fd(), op, fd.as_raw_fd(), event_ptr) };
+        if ret < 0 {
+            return Err(Error::last());
+        }
+        Ok(())
+    }
+
+    /// Waits for any events to occur in file descriptors that were previously added.
+    ///
+    /// # Panics
+    /// Panics if the returned `PollEvents` is not dropped before subsequent `wait` calls.
+    pub fn wait(&self) -> Result<PollEvents<'_, T>> {
+        self.wait_millis(-1)
+    }
+
+    /// Like `wait` except will only block for a maximum of the given `timeout`.
+    ///
+    /// Timeouts longer than `i32::MAX` milliseconds (~24.8 days) are truncated.
+    pub fn wait_timeout(&self, timeout: Duration) -> Result<PollEvents<'_, T>> {
+        let timeout_millis = timeout.as_millis().min(i32::MAX as u128) as i32;
+        self.wait_millis(timeout_millis)
+    }
+
+    fn wait_millis(&self, timeout_millis: i32) -> Result<PollEvents<'_, T>> {
+        let count = {
+            let mut events = self.events.borrow_mut();
+            let ret = handle_eintr_errno!(
+                // SAFETY: The events buffer outlives the call and its length is passed properly.
+                unsafe {
+                    libc::epoll_wait(
+                        self.epoll.as_raw_fd(),
+                        events.as_mut_ptr(),
+                        events.len() as libc::c_int,
+                        timeout_millis,
+                    )
+                }
+            );
+            if ret < 0 {
+                return Err(Error::last());
+            }
+            ret as usize
+        };
+        Ok(PollEvents {
+            count,
+            events: self.events.borrow(),
+            tokens: PhantomData,
+        })
+    }
+}
+
+impl<T> AsFd for Poller<T> {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.epoll.as_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixStream;
+    use std::time::Instant;
+
+    use poll_token_derive::PollToken;
+
+    use super::super::EventFd;
+    use super::*;
+
+    #[derive(Debug, PartialEq, Eq, PollToken)]
+    enum Token {
+        Event(usize),
+        Stream,
+    }
+
+    #[test]
+    fn readable_tokens() {
+        let evt1 = EventFd::new().unwrap();
+        let evt2 = EventFd::new().unwrap();
+        let poller: Poller<Token> =
+            Poller::build_with(&[(&evt1, Token::Event(1)), (&evt2, Token::Event(2))]).unwrap();
+
+        evt2.write(1).unwrap();
+        let events = poller.wait().unwrap();
+        let tokens: Vec<Token> = events.iter_readable().map(|e| e.token()).collect();
+        assert_eq!(tokens, vec![Token::Event(2)]);
+    }
+
+    #[test]
+    fn add_and_delete_while_iterating() {
+        let evts: Vec<EventFd> = (0..3).map(|_| EventFd::new().unwrap()).collect();
+        let poller: Poller<usize> = Poller::new().unwrap();
+        poller.add(&evts[0], 0).unwrap();
+        poller.add(&evts[1], 1).unwrap();
+        evts[0].write(1).unwrap();
+        evts[1].write(1).unwrap();
+        evts[2].write(1).unwrap();
+
+        let mut seen = Vec::new();
+        for event in &poller.wait().unwrap() {
+            let index = event.token();
+            seen.push(index);
+            evts[index].read().unwrap();
+            // Replace both registered eventfds with the third one.
+            if seen.len() == 1 {
+                poller.delete(&evts[0]).unwrap();
+                poller.delete(&evts[1]).unwrap();
+                poller.add(&evts[2], 2).unwrap();
+            }
+        }
+        // The events returned by the call to wait are all reported.
+        seen.sort();
+        assert_eq!(seen, vec![0, 1]);
+
+        // The deleted eventfds are not reported anymore, the added one is.
+        evts[0].write(1).unwrap();
+        evts[1].write(1).unwrap();
+        let events = poller.wait_timeout(Duration::ZERO).unwrap();
+        let tokens: Vec<usize> = events.iter().map(|e| e.token()).collect();
+        assert_eq!(tokens, vec![2]);
+    }
+
+    #[test]
+    fn modify_token() {
+        let evt = EventFd::new().unwrap();
+        let poller: Poller<u32> = Poller::build_with(&[(&evt, 1)]).unwrap();
+        poller
+            .modify(&evt, WatchingEvents::empty().set_read(), 2)
+            .unwrap();
+
+        evt.write(1).unwrap();
+        let events = poller.wait().unwrap();
+        let tokens: Vec<u32> = events.iter().map(|e| e.token()).collect();
+        assert_eq!(tokens, vec![2]);
+    }
+
+    #[test]
+    fn level_triggered() {
+        let evt = EventFd::new().unwrap();
+        let poller: Poller<u32> = Poller::build_with(&[(&evt, 1)]).unwrap();
+        evt.write(1).unwrap();
+
+        // The event is reported until the eventfd is read.
+        assert_eq!(poller.wait_timeout(Duration::ZERO).unwrap().len(), 1);
+        assert_eq!(poller.wait_timeout(Duration::ZERO).unwrap().len(), 1);
+        evt.read().unwrap();
+        assert!(poller.wait_timeout(Duration::ZERO).unwrap().is_empty());
+    }
+
+    #[test]
+    fn edge_triggered() {
+        let evt = EventFd::new().unwrap();
+        let poller: Poller<u32> = Poller::new().unwrap();
+        poller
+            .add_fd_with_events(
+                &evt,
+                WatchingEvents::empty().set_read().set_edge_triggered(),
+                1,
+            )
+            .unwrap();
+        evt.write(1).unwrap();
+
+        // The event is reported once even though the eventfd is not read.
+        assert_eq!(poller.wait_timeout(Duration::ZERO).unwrap().len(), 1);
+        assert!(poller.wait_timeout(Duration::ZERO).unwrap().is_empty());
+        // A new write is a new edge.
+        evt.write(1).unwrap();
+        assert_eq!(poller.wait_timeout(Duration::ZERO).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn more_events_than_buffer() {
+        const EVT_COUNT: usize = POLLER_MAX_EVENTS * 2 + 1;
+        let poller: Poller<usize> = Poller::new().unwrap();
+        let mut evts = Vec::with_capacity(EVT_COUNT);
+        for i in 0..EVT_COUNT {
+            let evt = EventFd::new().unwrap();
+            evt.write(1).unwrap();
+            poller.add(&evt, i).unwrap();
+            evts.push(evt);
+        }
+        let mut evt_count = 0;
+        while evt_count < EVT_COUNT {
+            for event in poller.wait().unwrap().iter_readable() {
+                evts[event.token()].read().unwrap();
+                evt_count += 1;
+            }
+        }
+        assert!(poller.wait_timeout(Duration::ZERO).unwrap().is_empty());
+    }
+
+    #[test]
+    fn hungup() {
+        let (s1, s2) = UnixStream::pair().unwrap();
+        let poller: Poller<Token> = Poller::build_with(&[(&s1, Token::Stream)]).unwrap();
+        drop(s2);
+
+        let events = poller.wait().unwrap();
+        let tokens: Vec<Token> = events.iter_hungup().map(|e| e.token()).collect();
+        assert_eq!(tokens, vec![Token::Stream]);
+        drop(events);
+
+        poller.delete(&s1).unwrap();
+        assert!(poller.wait_timeout(Duration::ZERO).unwrap().is_empty());
+    }
+
+    #[test]
+    fn timeout() {
+        let poller: Poller<u32> = Poller::new().unwrap();
+        let dur = Duration::from_millis(10);
+        let start = Instant::now();
+        assert!(poller.wait_timeout(dur).unwrap().is_empty());
+        assert!(start.elapsed() >= dur);
+    }
+}
@@ -0,0 +1,192 @@
+// Copyright 2024 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+use std::io;
+use std::mem::{size_of, zeroed};
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, OwnedFd, RawFd};
+use std::ptr;
+use std::time::Duration;
+
+use libc::c_void;
+use nix::{Error, Result};
+
+use super::duration_to_timespec;
+use crate::handle_eintr_errno;
+
+/// A safe wrapper around a Linux timerfd (man 2 timerfd_create) on `CLOCK_MONOTONIC`.
+///
+/// The timerfd becomes readable when the timer expires, so it can be added to a `Poller`.
+#[derive(Debug)]
+pub struct Timer {
+    fd: OwnedFd,
+}
+
+impl Timer {
+    /// Creates a new timer. The timer is initially disarmed and must be armed by calling `reset`.
+    pub fn new() -> Result<Timer> {
+        // SAFETY: timerfd_create(2) only allocates a new file descriptor and the error case is
+        // checked.
+        let ret = unsafe { libc::timerfd_create(libc::CLOCK_MONOTONIC, libc::TFD_CLOEXEC) };
+        if ret < 0 {
+            return Err(Error::last());
+        }
+        // SAFETY: The kernel gave us a new file descriptor that nothing else owns.
+        Ok(Timer {
+            fd: unsafe { OwnedFd::from_raw_fd(ret) },
+        })
+    }
+
+    /// Creates a new file descriptor sharing the timer.
+    pub fn try_clone(&self) -> io::Result<Timer> {
+        self.fd.try_clone().map(|fd| Timer { fd })
+    }
+
+    /// Sets the timer to expire after `dur`. If `interval` is not `None`, the timer then expires
+    /// periodically every `interval`. Otherwise it expires just once. Cancels any existing
+    /// expiration. A zero `dur` disarms the timer.
+    pub fn reset(&self, dur: Duration, interval: Option<Duration>) -> Result<()> {
+        let spec = libc::itimerspec {
+            it_value: duration_to_timespec(dur),
+            it_interval: duration_to_timespec(interval.unwrap_or_default()),
+        };
+        self.set_time(&spec)
+    }
+
+    /// Disarms the timer.
+    pub fn clear(&self) -> Result<()> {
+        // SAFETY: itimerspec only has primitive fields, for which zero is valid.
+        let spec: libc::itimerspec = unsafe { zeroed() };
+        self.set_time(&spec)
+    }
+
+    fn set_time(&self, spec: &libc::itimerspec) -> Result<()> {
+        // SAFETY: timerfd_settime(2) does not modify memory as the old value is not requested and
+        // the return value is checked.
+        let ret = unsafe { libc::timerfd_settime(self.fd.as_raw_fd(), 0, spec, ptr::null_mut()) };
+        if ret < 0 {
+            return Err(Error::last());
+        }
+        Ok(())
+    }
+
+    /// Waits until the timer expires. Returns the number of expirations since the last call to
+    /// `wait`, blocking until the next one if there was none.
+    pub fn wait(&self) -> Result<u64> {
+        let mut count: u64 = 0;
+        let ret = handle_eintr_errno!(
+            // SAFETY: The buffer is a u64 that outlives the call and its size is passed properly.
+            unsafe {
+                libc::read(
+                    self.fd.as_raw_fd(),
+                    &mut count as *mut u64 as *mut c_void,
+                    size_of::<u64>(),
+                )
+            }
+        );
+        if ret < 0 {
+            return Err(Error::last());
+        }
+        Ok(count)
+    }
+
+    /// Returns the resolution of timers on the host.
+    pub fn resolution() -> Result<Duration> {
+        // SAFETY: timespec only has primitive fields, for which zero is valid.
+        let mut res: libc::timespec = unsafe { zeroed() };
+        // SAFETY: clock_getres(2) only modifies `res` and the return value is checked.
+        let ret = unsafe { libc::clock_getres(libc::CLOCK_MONOTONIC, &mut res) };
+        if ret < 0 {
+            return Err(Error::last());
+        }
+        Ok(Duration::new(res.tv_sec as u64, res.tv_nsec as u32))
+    }
+}
+
+impl AsFd for Timer {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+impl AsRawFd for Timer {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+impl FromRawFd for Timer {
+    unsafe fn from_raw_fd(fd: RawFd) -> Self {
+        Timer {
+            fd: OwnedFd::from_raw_fd(fd),
+        }
+    }
+}
+
+impl IntoRawFd for Timer {
+    fn into_raw_fd(self) -> RawFd {
+        self.fd.into_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread::sleep;
+    use std::time::Instant;
+
+    use super::super::Poller;
+    use super::*;
+
+    #[test]
+    fn one_shot() {
+        let timer = Timer::new().unwrap();
+        let dur = Duration::from_millis(50);
+        let start = Instant::now();
+        timer.reset(dur, None).unwrap();
+
+        assert_eq!(timer.wait(), Ok(1));
+        assert!(start.elapsed() >= dur);
+    }
+
+    #[test]
+    fn repeating() {
+        let timer = Timer::new().unwrap();
+        let dur = Duration::from_millis(100);
+        let interval = Duration::from_millis(50);
+        timer.reset(dur, Some(interval)).unwrap();
+
+        // Expirations at 100, 150, 200, 250 and 300ms.
+        sleep(dur * 3);
+        let count = timer.wait().unwrap();
+        assert!(count >= 5, "count = {}", count);
+
+        // Reading resets the number of expirations.
+        let count = timer.wait().unwrap();
+        assert!((1..=2).contains(&count), "count = {}", count);
+    }
+
+    #[test]
+    fn clear() {
+        let timer = Timer::new().unwrap();
+        let poller: Poller<u32> = Poller::build_with(&[(&timer, 1)]).unwrap();
+
+        timer.reset(Duration::from_millis(10), None).unwrap();
+        timer.clear().unwrap();
+        assert!(poller
+            .wait_timeout(Duration::from_millis(50))
+            .unwrap()
+            .is_empty());
+
+        // A zero duration also disarms the timer.
+        timer.reset(Duration::ZERO, None).unwrap();
+        assert!(poller
+            .wait_timeout(Duration::from_millis(50))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn resolution() {
+        assert!(Timer::resolution().unwrap() > Duration::ZERO);
+    }
+}
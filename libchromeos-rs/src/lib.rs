@@ -40,8 +40,14 @@ pub mod chromeos {
 
 pub mod deprecated;
 pub mod disk;
+pub mod events;
+pub mod mount;
 pub mod panic_handler;
+pub mod privileges;
+pub mod proc;
 pub mod rand;
+pub mod retry;
+pub mod sandbox;
 pub mod scoped_path;
 pub mod secure_blob;
 pub mod signal;
@@ -0,0 +1,411 @@
+// Copyright 2024 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Helpers for performing mounts in a private mount namespace, so that they are never visible to
+//! the rest of the system and go away together with the namespace.
+//!
+//! A mount namespace belongs to a thread (more precisely to its filesystem information), so the
+//! helpers never change the namespace of the calling thread:
+//!
+//! * [`with_private_namespace`] runs a closure in a forked child. The child is single-threaded
+//!   even if the caller is not, and its namespace goes away when it exits, also if the closure
+//!   panics. Like any code running after a fork in a multi-threaded process, the closure must not
+//!   depend on locks held by other threads of the caller. The helper itself does not allocate in
+//!   the child, so only the closure and the serialization of its result need a fork-safe
+//!   allocator if they allocate. glibc's malloc is fork-safe.
+//! * [`PrivateNamespace`] keeps a namespace alive for as long as the handle exists and runs
+//!   closures in it on dedicated threads. Use it if the mounts need to outlive a single closure or
+//!   the closure has to share memory with the caller.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::Path;
+use std::thread;
+
+use nix::errno::Errno;
+use nix::mount::{mount, MsFlags};
+use nix::sched::{setns, unshare, CloneFlags};
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::{fork, ForkResult};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use thiserror::Error as ThisError;
+
+#[derive(ThisError, Debug)]
+pub enum Error {
+    #[error("failed to create a mount namespace: {0}")]
+    Unshare(nix::Error),
+    #[error("failed to make the mounts private: {0}")]
+    MakePrivate(nix::Error),
+    #[error("failed to open the mount namespace: {0}")]
+    OpenNamespace(io::Error),
+    #[error("failed to enter the mount namespace: {0}")]
+    EnterNamespace(nix::Error),
+    #[error("failed to create a pipe: {0}")]
+    Pipe(nix::Error),
+    #[error("failed to fork: {0}")]
+    Fork(nix::Error),
+    #[error("failed to wait for the child: {0}")]
+    Wait(nix::Error),
+    #[error("failed to spawn a thread: {0}")]
+    SpawnThread(io::Error),
+    #[error("failed to read the result: {0}")]
+    ReadResult(io::Error),
+    #[error("failed to deserialize the result: {0}")]
+    DeserializeResult(serde_json::Error),
+    #[error("the child exited without a result: {0:?}")]
+    ChildExited(WaitStatus),
+    #[error("the closure panicked")]
+    Panicked,
+}
+
+pub type Result<R> = std::result::Result<R, Error>;
+
+/// What the forked child of [`with_private_namespace`] reports to the parent. It is serialized
+/// straight into the pipe, and errors are reported as errnos, so that reporting does not allocate.
+#[derive(Serialize, Deserialize)]
+enum ChildResult<T> {
+    Ok(T),
+    UnshareFailed(i32),
+    MakePrivateFailed(i32),
+    Panicked,
+}
+
+/// Runs `f` in a new mount namespace in which all mounts are private and returns its result.
+///
+/// `f` runs in a forked child, and its result is serialized back to the caller over a pipe. Mounts
+/// made by `f` are never visible to the caller and are gone once this returns, also if `f` panics.
+/// Since `f` runs in another process, changes to memory made by `f` are not visible to the
+/// caller either. See the module documentation for the restrictions in multi-threaded callers,
+/// including on allocations.
+///
+/// Creating a mount namespace needs `CAP_SYS_ADMIN`.
+pub fn with_private_namespace<F, T>(f: F) -> Result<T>
+where
+    F: FnOnce() -> T,
+    T: Serialize + DeserializeOwned,
+{
+    let (mut reader, mut writer) = crate::pipe(true).map_err(Error::Pipe)?;
+
+    // SAFETY: The child only runs `f` and exits without returning to the caller.
+    match unsafe { fork() }.map_err(Error::Fork)? {
+        ForkResult::Child => {
+            drop(reader);
+            let result = if let Err(e) = unshare(CloneFlags::CLONE_NEWNS) {
+                ChildResult::UnshareFailed(e as i32)
+            } else if let Err(e) = make_mounts_private() {
+                ChildResult::MakePrivateFailed(e as i32)
+            } else {
+                match catch_unwind(AssertUnwindSafe(f)) {
+                    Ok(value) => ChildResult::Ok(value),
+                    Err(_) => ChildResult::Panicked,
+                }
+            };
+            // The pipe is unbuffered, so serde_json writes the tokens as they are serialized
+            // without an intermediate buffer.
+            let written = serde_json::to_writer(&mut writer, &result).is_ok();
+            // SAFETY: _exit(2) terminates the child without running anything else of the caller,
+            // e.g. destructors or atexit handlers.
+            unsafe { libc::_exit(if written { 0 } else { 1 }) };
+        }
+        ForkResult::Parent { child } => {
+            drop(writer);
+            let mut output = Vec::new();
+            let read = reader.read_to_end(&mut output);
+            let status = waitpid(child, None).map_err(Error::Wait)?;
+            read.map_err(Error::ReadResult)?;
+            if output.is_empty() {
+                return Err(Error::ChildExited(status));
+            }
+            match serde_json::from_slice(&output).map_err(Error::DeserializeResult)? {
+                ChildResult::Ok(value) => Ok(value),
+                ChildResult::UnshareFailed(errno) => Err(Error::Unshare(Errno::from_i32(errno))),
+                ChildResult::MakePrivateFailed(errno) => {
+                    Err(Error::MakePrivate(Errno::from_i32(errno)))
+                }
+                ChildResult::Panicked => Err(Error::Panicked),
+            }
+        }
+    }
+}
+
+/// A private mount namespace that stays alive as long as this handle exists.
+///
+/// The handle holds a file descriptor of the namespace, as found in `/proc/<pid>/ns/mnt`. Once it
+/// is dropped and no thread runs in the namespace anymore, the kernel removes the namespace and
+/// unmounts everything mounted in it.
+pub struct PrivateNamespace {
+    fd: File,
+}
+
+impl PrivateNamespace {
+    /// Creates a new mount namespace in which all mounts are private. The namespace of the calling
+    /// thread is not changed.
+    ///
+    /// This needs `CAP_SYS_ADMIN`.
+    pub fn new() -> Result<Self> {
+        let fd = run_on_thread(|| {
+            enter_private_namespace()?;
+            File::open("/proc/thread-self/ns/mnt").map_err(Error::OpenNamespace)
+        })??;
+        Ok(PrivateNamespace { fd })
+    }
+
+    /// Runs `f` in the namespace and returns its result. `f` runs on a new thread, so the
+    /// namespace of the calling thread is not changed. If `f` panics, [`Error::Panicked`] is
+    /// returned and the mounts it made so far stay in the namespace.
+    pub fn run<F, T>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce() -> T + Send,
+        T: Send,
+    {
+        let fd = self.fd.as_raw_fd();
+        run_on_thread(move || {
+            enter_namespace(fd)?;
+            Ok(f())
+        })?
+    }
+}
+
+impl AsRawFd for PrivateNamespace {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+/// Runs `f` on a new thread. The thread is joined before this returns, so `f` may borrow from
+/// the caller.
+fn run_on_thread<F, T>(f: F) -> Result<T>
+where
+    F: FnOnce() -> T + Send,
+    T: Send,
+{
+    thread::scope(|scope| {
+        thread::Builder::new()
+            .spawn_scoped(scope, f)
+            .map_err(Error::SpawnThread)?
+            .join()
+            .map_err(|_| Error::Panicked)
+    })
+}
+
+/// Moves the calling thread into a new mount namespace and makes all mounts in it private, so
+/// that mount events do not propagate back to the original namespace.
+fn enter_private_namespace() -> Result<()> {
+    unshare(CloneFlags::CLONE_NEWNS).map_err(Error::Unshare)?;
+    make_mounts_private().map_err(Error::MakePrivate)
+}
+
+/// Makes all mounts in the mount namespace of the calling thread private.
+fn make_mounts_private() -> nix::Result<()> {
+    mount(
+        None::<&Path>,
+        "/",
+        None::<&Path>,
+        MsFlags::MS_REC | MsFlags::MS_PRIVATE,
+        None::<&Path>,
+    )
+}
+
+/// Moves the calling thread into the mount namespace `fd`.
+fn enter_namespace(fd: RawFd) -> Result<()> {
+    // Threads share their filesystem information, which includes the mount namespace, and
+    // setns(2) refuses to change a shared one.
+    unshare(CloneFlags::CLONE_FS).map_err(Error::EnterNamespace)?;
+    setns(fd, CloneFlags::CLONE_NEWNS).map_err(Error::EnterNamespace)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+    use std::fs::read_to_string;
+    use std::os::unix::fs::MetadataExt;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::mpsc::channel;
+
+    use nix::unistd::Uid;
+
+    use crate::scoped_path::{get_temp_path, ScopedPath};
+
+    const MARKER: &str = "marker";
+
+    /// The exit code of a forked child which allocated while [`FORBID_CHILD_ALLOCATIONS`] was set.
+    const ALLOCATED_IN_CHILD: i32 = 42;
+
+    static TEST_PROCESS_ID: AtomicU32 = AtomicU32::new(0);
+
+    thread_local! {
+        // Set on the thread which forks. The child inherits it, while the other threads of the
+        // test process, e.g. other tests, are not affected.
+        static FORBID_CHILD_ALLOCATIONS: Cell<bool> = const { Cell::new(false) };
+    }
+
+    /// Makes a forked child exit with [`ALLOCATED_IN_CHILD`] if it allocates while
+    /// [`FORBID_CHILD_ALLOCATIONS`] is set. This checks that [`with_private_namespace`] does not
+    /// rely on the allocator in the child, which may be unusable if another thread of the caller
+    /// held an allocator lock during the fork.
+    struct ChildAllocationChecker;
+
+    // SAFETY: All the allocations are forwarded to the system allocator.
+    unsafe impl GlobalAlloc for ChildAllocationChecker {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let forbidden = FORBID_CHILD_ALLOCATIONS
+                .try_with(|forbidden| forbidden.get())
+                .unwrap_or(false);
+            if forbidden && std::process::id() != TEST_PROCESS_ID.load(Ordering::Relaxed) {
+                // SAFETY: _exit(2) terminates the child without running anything else.
+                unsafe { libc::_exit(ALLOCATED_IN_CHILD) };
+            }
+            // SAFETY: The caller upholds the contract of GlobalAlloc::alloc.
+            unsafe { System.alloc(layout) }
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            // SAFETY: The caller upholds the contract of GlobalAlloc::dealloc.
+            unsafe { System.dealloc(ptr, layout) }
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: ChildAllocationChecker = ChildAllocationChecker;
+
+    fn forbid_child_allocations(forbid: bool) {
+        FORBID_CHILD_ALLOCATIONS.with(|forbidden| forbidden.set(forbid));
+    }
+
+    fn mount_tmpfs(path: &Path) {
+        mount(
+            Some("tmpfs"),
+            path,
+            Some("tmpfs"),
+            MsFlags::empty(),
+            None::<&Path>,
+        )
+        .unwrap();
+    }
+
+    /// Returns whether `path` is a mount point in the namespace of the calling thread.
+    fn is_mounted(path: &Path) -> bool {
+        let mountinfo = read_to_string("/proc/thread-self/mountinfo").unwrap();
+        let path = path.to_str().unwrap();
+        mountinfo
+            .lines()
+            .any(|line| line.split(' ').nth(4) == Some(path))
+    }
+
+    fn namespace_inode() -> u64 {
+        std::fs::metadata("/proc/thread-self/ns/mnt").unwrap().ino()
+    }
+
+    #[test]
+    fn mount_invisible_outside() {
+        if !Uid::effective().is_root() {
+            return;
+        }
+        let dir = ScopedPath::create(get_temp_path(Some("test_private_namespace"))).unwrap();
+        let namespace = namespace_inode();
+
+        let inside = with_private_namespace(|| {
+            mount_tmpfs(&dir);
+            std::fs::write(dir.join(MARKER), b"").unwrap();
+            (is_mounted(&dir), namespace_inode() != namespace)
+        })
+        .unwrap();
+        assert_eq!(inside, (true, true));
+
+        assert!(!is_mounted(&dir));
+        assert!(!dir.join(MARKER).exists());
+        assert_eq!(namespace_inode(), namespace);
+    }
+
+    #[test]
+    fn panic_does_not_leak_mounts() {
+        if !Uid::effective().is_root() {
+            return;
+        }
+        let dir = ScopedPath::create(get_temp_path(Some("test_private_namespace_panic"))).unwrap();
+
+        let result = with_private_namespace::<_, ()>(|| {
+            mount_tmpfs(&dir);
+            panic!("failed after mounting");
+        });
+        assert!(matches!(result, Err(Error::Panicked)));
+        assert!(!is_mounted(&dir));
+    }
+
+    #[test]
+    fn multi_threaded_caller() {
+        if !Uid::effective().is_root() {
+            return;
+        }
+        let dir =
+            ScopedPath::create(get_temp_path(Some("test_private_namespace_threads"))).unwrap();
+
+        // Another thread of the caller keeps running during the fork and stays in the original
+        // namespace.
+        let (sender, receiver) = channel::<()>();
+        let other = thread::spawn(move || {
+            let namespace = namespace_inode();
+            receiver.recv().unwrap();
+            namespace_inode() == namespace
+        });
+
+        // Only the closure may allocate in the child, not the helper around it.
+        TEST_PROCESS_ID.store(std::process::id(), Ordering::Relaxed);
+        forbid_child_allocations(true);
+        let result = with_private_namespace(|| {
+            forbid_child_allocations(false);
+            mount_tmpfs(&dir);
+            let mounted = is_mounted(&dir);
+            forbid_child_allocations(true);
+            mounted
+        });
+        forbid_child_allocations(false);
+        assert!(result.unwrap());
+
+        sender.send(()).unwrap();
+        assert!(other.join().unwrap());
+        assert!(!is_mounted(&dir));
+    }
+
+    #[test]
+    fn private_namespace_handle() {
+        if !Uid::effective().is_root() {
+            return;
+        }
+        let dir = ScopedPath::create(get_temp_path(Some("test_private_namespace_handle"))).unwrap();
+        let namespace = namespace_inode();
+
+        let private = PrivateNamespace::new().unwrap();
+        assert_eq!(namespace_inode(), namespace);
+
+        private
+            .run(|| {
+                mount_tmpfs(&dir);
+                std::fs::write(dir.join(MARKER), b"").unwrap();
+            })
+            .unwrap();
+        assert!(!is_mounted(&dir));
+        assert!(!dir.join(MARKER).exists());
+
+        // The mount outlives the closure that made it.
+        assert!(private
+            .run(|| is_mounted(&dir) && dir.join(MARKER).exists())
+            .unwrap());
+        assert!(matches!(
+            private.run::<_, ()>(|| panic!("failed in the namespace")),
+            Err(Error::Panicked)
+        ));
+        assert!(private.run(|| is_mounted(&dir)).unwrap());
+
+        drop(private);
+        assert!(!is_mounted(&dir));
+        assert_eq!(namespace_inode(), namespace);
+    }
+}
@@ -0,0 +1,114 @@
+// Copyright 2024 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Helpers for running as an unprivileged user.
+
+use nix::errno::Errno;
+use nix::unistd::{
+    getgroups, getresgid, getresuid, setgroups, setresgid, setresuid, Gid, ResGid, ResUid, Uid,
+};
+use nix::Result;
+
+/// Switches the process to `uid` and `gid` with the `supplementary` groups.
+///
+/// The supplementary groups are set first, then the real, effective and saved gids and finally
+/// the uids, because changing the uid away from root drops the capability to change the groups.
+/// The ids are read back afterwards and `EPERM` is returned if any of them did not change.
+///
+/// If the process already runs as `uid` and `gid`, nothing is changed. This lets an unprivileged
+/// process call this unconditionally, e.g. when it is started without the privileges in tests.
+pub fn drop_privileges(uid: u32, gid: u32, supplementary: &[u32]) -> Result<()> {
+    let uid = Uid::from_raw(uid);
+    let gid = Gid::from_raw(gid);
+    if has_ids(uid, gid)? {
+        return Ok(());
+    }
+
+    let groups: Vec<Gid> = supplementary.iter().copied().map(Gid::from_raw).collect();
+    setgroups(&groups)?;
+    setresgid(gid, gid, gid)?;
+    setresuid(uid, uid, uid)?;
+
+    if !has_ids(uid, gid)? || !has_groups(&groups)? {
+        return Err(Errno::EPERM);
+    }
+    Ok(())
+}
+
+/// Returns whether the real, effective and saved ids are all `uid` and `gid`.
+fn has_ids(uid: Uid, gid: Gid) -> Result<bool> {
+    let ResUid {
+        real,
+        effective,
+        saved,
+    } = getresuid()?;
+    if [real, effective, saved].iter().any(|id| *id != uid) {
+        return Ok(false);
+    }
+    let ResGid {
+        real,
+        effective,
+        saved,
+    } = getresgid()?;
+    Ok([real, effective, saved].iter().all(|id| *id == gid))
+}
+
+fn has_groups(groups: &[Gid]) -> Result<bool> {
+    let mut expected = groups.to_vec();
+    expected.sort_by_key(|gid| gid.as_raw());
+    expected.dedup();
+    let mut current = getgroups()?;
+    current.sort_by_key(|gid| gid.as_raw());
+    current.dedup();
+    Ok(current == expected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The nobody user and group.
+    const NOBODY: u32 = 65534;
+
+    #[test]
+    fn already_unprivileged() {
+        let uid = Uid::current();
+        if uid.is_root() {
+            return;
+        }
+        let gid = Gid::current();
+        // A no-op, even though the groups cannot be set without privileges.
+        drop_privileges(uid.as_raw(), gid.as_raw(), &[NOBODY]).unwrap();
+        // Switching to another user needs privileges.
+        if uid.as_raw() != NOBODY {
+            assert_eq!(drop_privileges(NOBODY, NOBODY, &[]), Err(Errno::EPERM));
+        }
+    }
+
+    #[test]
+    fn drop_root() {
+        if !Uid::effective().is_root() {
+            return;
+        }
+        // Drop the privileges in a child process so that the other tests keep running as root.
+        // SAFETY: The child only changes its own credentials and exits right away.
+        let pid = unsafe { libc::fork() };
+        assert!(pid >= 0);
+        if pid == 0 {
+            let dropped = drop_privileges(NOBODY, NOBODY, &[NOBODY]).is_ok()
+                && Uid::effective().as_raw() == NOBODY
+                && Gid::effective().as_raw() == NOBODY
+                && getgroups().ok() == Some(vec![Gid::from_raw(NOBODY)])
+                // The privileges cannot be regained.
+                && setresuid(Uid::from_raw(0), Uid::from_raw(0), Uid::from_raw(0)).is_err();
+            // SAFETY: _exit(2) terminates the child without running anything else.
+            unsafe { libc::_exit(if dropped { 0 } else { 1 }) };
+        }
+        let mut status = 0;
+        // SAFETY: waitpid(2) only writes to `status`.
+        assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+        assert!(libc::WIFEXITED(status));
+        assert_eq!(libc::WEXITSTATUS(status), 0);
+    }
+}
@@ -0,0 +1,191 @@
+// Copyright 2024 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Helpers for reading process information from procfs.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// Returns the start time of the process in clock ticks after system boot.
+///
+/// The start time, i.e. the 22nd field of /proc/pid/stat, identifies a process instance: a new
+/// process reusing the pid has a different start time. The error kind is `NotFound` if the
+/// process does not exist and `InvalidData` if the stat file cannot be parsed.
+pub fn process_start_time(pid: u32) -> io::Result<u64> {
+    read_start_time(Path::new(&format!("/proc/{}/stat", pid)))
+}
+
+/// Returns the start time of the thread in clock ticks after system boot.
+///
+/// See [process_start_time].
+pub fn thread_start_time(pid: u32, tid: u32) -> io::Result<u64> {
+    read_start_time(Path::new(&format!("/proc/{}/task/{}/stat", pid, tid)))
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_start_time(path: &Path) -> io::Result<u64> {
+    let mut stat_file = File::open(path).map_err(|e| {
+        // Opening a file of an exiting task may fail with ESRCH.
+        if e.raw_os_error() == Some(libc::ESRCH) {
+            io::Error::new(io::ErrorKind::NotFound, e)
+        } else {
+            e
+        }
+    })?;
+    // starttime is the 22th column in /proc/pid/stat. Each numeric column in /proc/pid/stat has at
+    // most 21 bytes. (1 byte for sign + 19 bytes for u64 + 1 byte space). The 2nd column (comm) is
+    // at most 67 bytes including the wrapping parenthesis (proc_task_name() of kernel uses 64 bytes
+    // buffer `tcomm`). 512 bytes is enough to hold the 22 columns (i.e. 512 >= 21 * 21 + 67 = 508).
+    let mut buf = [0; 512];
+    let n = stat_file.read(&mut buf)?;
+    parse_start_time(&buf, n)
+}
+
+/// Parses the start time from the first `n` bytes of `buf`. The rest of `buf` must be zeroed.
+fn parse_start_time(buf: &[u8; 512], n: usize) -> io::Result<u64> {
+    // Since threads can set comm by writing to /proc/self/task/tid/comm, it can contain any byte
+    // sequence. This means we need to exclusively look at kernel controlled bytes to determine
+    // where comm ends. To do this, we can scan backwards to look for the closing parentheses
+    // emitted by the kernel.
+    // The longest possible tail offset of comm is 88 bytes (= 21 bytes for pid column + 1 byte for
+    // space + 66 bytes for comm column). This works even if the stat file size is less than 88
+    // bytes because the space after the stat file content are zeroed.
+    let i_comm_tail = buf[..88]
+        .iter()
+        .rposition(|c| *c == b')')
+        .ok_or_else(|| invalid_data("no comm in stat file"))?;
+
+    let mut prev_space = i_comm_tail + 1;
+    let mut starttime = None;
+    // `pid` and `comm` columns are consumed.
+    let mut column_idx = 2;
+    for (i, c) in buf[..n].iter().enumerate().skip(prev_space + 1) {
+        if *c == b' ' {
+            if column_idx == 21 {
+                // starttime is at 22th column.
+                starttime = Some(prev_space + 1..i);
+                break;
+            }
+            prev_space = i;
+            column_idx += 1;
+        }
+    }
+    let starttime = starttime.ok_or_else(|| invalid_data("no starttime in stat file"))?;
+    std::str::from_utf8(&buf[starttime])
+        .ok()
+        .and_then(|starttime| starttime.parse().ok())
+        .ok_or_else(|| invalid_data("invalid starttime in stat file"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+    use std::thread;
+
+    use super::*;
+
+    fn gettid() -> u32 {
+        // SAFETY: gettid(2) has no side effects.
+        unsafe { libc::syscall(libc::SYS_gettid) as u32 }
+    }
+
+    #[test]
+    fn current_process_start_time() {
+        let pid = std::process::id();
+        let start_time = process_start_time(pid).unwrap();
+        assert!(start_time > 0);
+        // The main thread has the same start time as the process.
+        assert_eq!(thread_start_time(pid, pid).unwrap(), start_time);
+
+        let (tid_sender, tid_receiver) = channel();
+        let (exit_sender, exit_receiver) = channel::<()>();
+        let handle = thread::spawn(move || {
+            tid_sender.send(gettid()).unwrap();
+            // Keep the thread alive until the main thread is done.
+            let _ = exit_receiver.recv();
+        });
+        let tid = tid_receiver.recv().unwrap();
+        assert!(thread_start_time(pid, tid).unwrap() >= start_time);
+        drop(exit_sender);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn missing_process() {
+        // pid_max is at most 2^22.
+        let err = process_start_time(u32::MAX).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        let err = thread_start_time(std::process::id(), u32::MAX).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    fn read_start_time_from(content: &[u8]) -> io::Result<u64> {
+        let mut buf = [0; 512];
+        buf[..content.len()].copy_from_slice(content);
+        parse_start_time(&buf, content.len())
+    }
+
+    #[test]
+    fn parse_stat_file() {
+        for (stat_file_content, starttime) in [
+            (
+                "9345 (resourced) S 1 9344 9344 0 -1 1077936384 599 0 0 0 2851 2468 0 0 20 0 1 0 \
+            41329081 19865600 2719 18446744073709551615 101386084483072 101386086716560 \
+            140736188509360 0 0 0 0 4096 1088 1 0 0 17 0 0 0 0 0 0 101386086981672 101386086982232 \
+            101386110365696 140736188513963 140736188513982 140736188513982 140736188514277 0",
+                41329081,
+            ),
+            // Shortest stat file.
+            ("1 (a) 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 ", 2),
+            // Longest pid + comm stat file.
+            (
+                "1234567890123456789 \
+            (1234567890123456789012345678901234567890123456789012345678901234) 3 4 5 6 7 8 9 0 1 2 \
+            3 4 5 6 7 8 9 0 1 123 ",
+                123,
+            ),
+            // comm contains spaces and parenthesis.
+            (
+                "1 ( a ( b ) c ) ) 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 456 ",
+                456,
+            ),
+        ]
+        .iter()
+        {
+            assert_eq!(
+                read_start_time_from(stat_file_content.as_bytes()).unwrap(),
+                *starttime,
+                "{}",
+                stat_file_content
+            );
+        }
+    }
+
+    #[test]
+    fn parse_corrupt_stat_file() {
+        for stat_file_content in [
+            // no comm parenthesis.
+            &b"1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2"[..],
+            // starttime is not a number.
+            b"1 (a) 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 a ",
+            // no 22th space
+            b"1 (a) 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2",
+            // non utf-8
+            b"1 (a) 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2\xff ",
+        ]
+        .iter()
+        {
+            assert_eq!(
+                read_start_time_from(stat_file_content).unwrap_err().kind(),
+                io::ErrorKind::InvalidData,
+                "{:?}",
+                stat_file_content
+            );
+        }
+    }
+}
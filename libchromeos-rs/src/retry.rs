@@ -0,0 +1,106 @@
+// Copyright 2024 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Helpers for retrying fallible operations.
+
+use std::thread::sleep;
+use std::time::Duration;
+
+/// Calls `f` until it returns `Ok` or `max_attempts` calls have failed.
+///
+/// `f` is passed the attempt number, starting at 1. After the n-th failed attempt this sleeps for
+/// `base_delay * 2^(n-1)` before trying again. There is no sleep after the last attempt, whose
+/// error is returned. `f` is always called at least once, even if `max_attempts` is 0.
+pub fn retry_with_backoff<T, E, F>(max_attempts: u32, base_delay: Duration, f: F) -> Result<T, E>
+where
+    F: FnMut(u32) -> Result<T, E>,
+{
+    retry_with_backoff_and_sleep(max_attempts, base_delay, f, sleep)
+}
+
+fn retry_with_backoff_and_sleep<T, E, F, S>(
+    max_attempts: u32,
+    base_delay: Duration,
+    mut f: F,
+    mut sleep: S,
+) -> Result<T, E>
+where
+    F: FnMut(u32) -> Result<T, E>,
+    S: FnMut(Duration),
+{
+    let mut attempt = 1;
+    loop {
+        match f(attempt) {
+            Ok(value) => return Ok(value),
+            Err(err) if attempt >= max_attempts => return Err(err),
+            Err(_) => {
+                let factor = 2u32.saturating_pow(attempt - 1);
+                sleep(base_delay.saturating_mul(factor));
+                attempt += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE_DELAY: Duration = Duration::from_millis(10);
+
+    #[test]
+    fn succeeds_on_third_attempt() {
+        let mut attempts = Vec::new();
+        let mut delays = Vec::new();
+        let result = retry_with_backoff_and_sleep(
+            5,
+            BASE_DELAY,
+            |attempt| {
+                attempts.push(attempt);
+                if attempt < 3 {
+                    Err(attempt)
+                } else {
+                    Ok("done")
+                }
+            },
+            |delay| delays.push(delay),
+        );
+
+        assert_eq!(result, Ok("done"));
+        assert_eq!(attempts, vec![1, 2, 3]);
+        assert_eq!(delays, vec![BASE_DELAY, BASE_DELAY * 2]);
+    }
+
+    #[test]
+    fn gives_up_after_max_attempts() {
+        let mut attempts = Vec::new();
+        let mut delays = Vec::new();
+        let result: Result<(), u32> = retry_with_backoff_and_sleep(
+            4,
+            BASE_DELAY,
+            |attempt| {
+                attempts.push(attempt);
+                Err(attempt)
+            },
+            |delay| delays.push(delay),
+        );
+
+        // The error of the last attempt is returned.
+        assert_eq!(result, Err(4));
+        assert_eq!(attempts, vec![1, 2, 3, 4]);
+        assert_eq!(delays, vec![BASE_DELAY, BASE_DELAY * 2, BASE_DELAY * 4]);
+    }
+
+    #[test]
+    fn zero_attempts_calls_once() {
+        let mut calls = 0;
+        let result: Result<(), ()> = retry_with_backoff(0, Duration::ZERO, |_| {
+            calls += 1;
+            Err(())
+        });
+
+        assert_eq!(result, Err(()));
+        assert_eq!(calls, 1);
+    }
+}
@@ -0,0 +1,100 @@
+// Copyright 2024 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Helpers for daemons restricting themselves before serving, without minijail.
+//!
+//! The helpers are independent of each other, so a daemon can pick the ones it needs. Combine
+//! them with [`crate::privileges::drop_privileges`], which has to come last since the helpers
+//! here may need privileges.
+
+use std::io;
+use std::os::unix::fs::chroot;
+use std::path::Path;
+
+use nix::sys::resource::setrlimit;
+pub use nix::sys::resource::Resource;
+use nix::Result;
+
+/// Sets the `soft` and `hard` limits of `resource` for the process.
+///
+/// Raising the hard limit needs `CAP_SYS_RESOURCE`, and `EINVAL` is returned if `soft` exceeds
+/// `hard`.
+pub fn set_rlimit(resource: Resource, soft: u64, hard: u64) -> Result<()> {
+    setrlimit(resource, soft, hard)
+}
+
+/// Changes the root directory of the process to `path` and moves into it, so that no directory
+/// outside of `path` stays reachable through the working directory.
+///
+/// This needs `CAP_SYS_CHROOT`. Open file descriptors are not affected and still refer to files
+/// outside of `path`.
+pub fn enter_chroot(path: &Path) -> io::Result<()> {
+    chroot(path)?;
+    std::env::set_current_dir("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use nix::errno::Errno;
+    use nix::sys::resource::getrlimit;
+    use nix::unistd::Uid;
+
+    use crate::scoped_path::{get_temp_path, ScopedPath};
+
+    /// Runs `f` in a child process, so that changes to the process do not affect other tests, and
+    /// returns whether it succeeded.
+    fn run_in_child(f: impl FnOnce() -> bool) -> bool {
+        // SAFETY: The child only changes its own state and exits right away.
+        let pid = unsafe { libc::fork() };
+        assert!(pid >= 0);
+        if pid == 0 {
+            let ok = f();
+            // SAFETY: _exit(2) terminates the child without running anything else.
+            unsafe { libc::_exit(if ok { 0 } else { 1 }) };
+        }
+        let mut status = 0;
+        // SAFETY: waitpid(2) only writes to `status`.
+        assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+        libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0
+    }
+
+    #[test]
+    fn set_rlimit_nofile() {
+        let (soft, hard) = getrlimit(Resource::RLIMIT_NOFILE).unwrap();
+        let new_soft = soft.min(hard).min(64);
+        assert!(run_in_child(|| {
+            // Lowering the limits needs no privileges.
+            set_rlimit(Resource::RLIMIT_NOFILE, new_soft - 1, new_soft).is_ok()
+                && getrlimit(Resource::RLIMIT_NOFILE) == Ok((new_soft - 1, new_soft))
+        }));
+        // The limits of this process did not change.
+        assert_eq!(getrlimit(Resource::RLIMIT_NOFILE), Ok((soft, hard)));
+    }
+
+    #[test]
+    fn set_rlimit_invalid() {
+        let (soft, hard) = getrlimit(Resource::RLIMIT_NOFILE).unwrap();
+        assert_eq!(
+            set_rlimit(Resource::RLIMIT_NOFILE, hard, soft.min(hard) - 1),
+            Err(Errno::EINVAL)
+        );
+        assert_eq!(getrlimit(Resource::RLIMIT_NOFILE), Ok((soft, hard)));
+    }
+
+    #[test]
+    fn enter_chroot_as_root() {
+        if !Uid::effective().is_root() {
+            return;
+        }
+        let dir = ScopedPath::create(get_temp_path(Some("test_enter_chroot"))).unwrap();
+        std::fs::write(dir.as_ref().join("marker"), b"").unwrap();
+        assert!(run_in_child(|| {
+            enter_chroot(dir.as_ref()).is_ok()
+                && Path::new("/marker").exists()
+                && std::env::current_dir().ok().as_deref() == Some(Path::new("/"))
+        }));
+    }
+}
@@ -3,6 +3,13 @@
 // found in the LICENSE file.
 
 //! Utilities for working with signal handlers
+#[cfg(feature = "signal-stream")]
+use std::convert::TryFrom;
+#[cfg(feature = "signal-stream")]
+use std::io;
+
+#[cfg(feature = "signal-stream")]
+use futures::stream::Stream;
 use libc::c_int;
 use nix::sys::signal::pthread_sigmask;
 use nix::sys::signal::sigaction;
@@ -12,6 +19,12 @@ use nix::sys::signal::SigHandler;
 use nix::sys::signal::SigSet;
 use nix::sys::signal::SigmaskHow;
 use nix::sys::signal::Signal;
+#[cfg(feature = "signal-stream")]
+use nix::sys::signalfd::SfdFlags;
+#[cfg(feature = "signal-stream")]
+use nix::sys::signalfd::SignalFd;
+#[cfg(feature = "signal-stream")]
+use tokio::io::unix::AsyncFd;
 
 /// Registers `handler` as the signal handler of signum `num`.
 ///
@@ -59,3 +72,75 @@ pub fn unblock_signal(num: Signal) -> nix::Result<()> {
     sigset.add(num);
     pthread_sigmask(SigmaskHow::SIG_UNBLOCK, Some(&sigset), None)
 }
+
+/// Returns a stream of the given signals, backed by a `signalfd` registered with the tokio reactor.
+///
+/// The signals are blocked in the calling thread so that they are only delivered through the
+/// stream. Threads spawned before this call keep their signal mask, so this should be called early
+/// from the main thread, before spawning other threads. Must be called within a tokio runtime.
+///
+/// The stream ends if reading the `signalfd` fails.
+#[cfg(feature = "signal-stream")]
+pub fn signal_stream(signals: &[Signal]) -> io::Result<impl Stream<Item = Signal>> {
+    let mut mask = SigSet::empty();
+    for signal in signals {
+        mask.add(*signal);
+    }
+    pthread_sigmask(SigmaskHow::SIG_BLOCK, Some(&mask), None)?;
+
+    let signal_fd = SignalFd::with_flags(&mask, SfdFlags::SFD_NONBLOCK | SfdFlags::SFD_CLOEXEC)?;
+    let async_fd = AsyncFd::new(signal_fd)?;
+
+    Ok(futures::stream::unfold(async_fd, next_signal))
+}
+
+#[cfg(feature = "signal-stream")]
+async fn next_signal(mut async_fd: AsyncFd<SignalFd>) -> Option<(Signal, AsyncFd<SignalFd>)> {
+    match read_signal(&mut async_fd).await {
+        Ok(signal) => Some((signal, async_fd)),
+        Err(e) => {
+            log::error!("failed to read signalfd: {}", e);
+            None
+        }
+    }
+}
+
+#[cfg(feature = "signal-stream")]
+async fn read_signal(async_fd: &mut AsyncFd<SignalFd>) -> io::Result<Signal> {
+    loop {
+        let mut guard = async_fd.readable_mut().await?;
+        let result = guard.try_io(|inner| match inner.get_mut().read_signal() {
+            Ok(Some(siginfo)) => Ok(siginfo),
+            // read_signal() returns None on EAGAIN.
+            Ok(None) => Err(io::ErrorKind::WouldBlock.into()),
+            Err(e) => Err(e.into()),
+        });
+        match result {
+            Ok(Ok(siginfo)) => {
+                return Signal::try_from(siginfo.ssi_signo as c_int).map_err(io::Error::from);
+            }
+            Ok(Err(e)) => return Err(e),
+            // Spurious wakeup, wait for the next readiness event.
+            Err(_would_block) => continue,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "signal-stream"))]
+mod tests {
+    use futures::StreamExt;
+    use nix::sys::signal::raise;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn signal_stream_receives_signal() {
+        let mut stream = Box::pin(signal_stream(&[Signal::SIGUSR1]).unwrap());
+
+        // raise() directs the signal to the calling thread, which has it blocked now. The
+        // current-thread runtime reads the signalfd from the same thread.
+        raise(Signal::SIGUSR1).unwrap();
+
+        assert_eq!(stream.next().await, Some(Signal::SIGUSR1));
+    }
+}
@@ -3,23 +3,233 @@
 // found in the LICENSE file.
 
 use std::env;
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::RwLock;
+use std::thread;
+use std::thread::JoinHandle;
 
 pub use log::LevelFilter;
-use log::SetLoggerError;
+use log::{Log, Metadata, Record, SetLoggerError};
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
 use nix::unistd::getpid;
 use stderrlog::StdErrLog;
 use syslog::{BasicLogger, Facility, Formatter3164};
 
+/// The name of the per-module log level config file in `/run/<ident>`.
+pub const MODULE_LEVEL_CONFIG_FILE_NAME: &str = "log-levels.conf";
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("unix socket syslog setup failed: {0}")]
     SyslogUnix(#[source] syslog::Error),
     #[error("failed to set logger: {0}")]
     SetLoggerError(#[source] SetLoggerError),
+    #[error("failed to read log level config: {0}")]
+    ReadLevelConfig(#[source] io::Error),
+    #[error("invalid log level config at line {0}: {1}")]
+    InvalidLevelConfig(usize, String),
+    #[error("failed to watch log level config: {0}")]
+    WatchLevelConfig(#[source] nix::Error),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Max log levels of the process, with overrides for module path prefixes.
+struct ModuleLevels {
+    default_level: LevelFilter,
+    // Sorted by prefix for binary search.
+    overrides: Vec<(String, LevelFilter)>,
+}
+
+impl ModuleLevels {
+    const fn new(default_level: LevelFilter) -> Self {
+        ModuleLevels {
+            default_level,
+            overrides: Vec::new(),
+        }
+    }
+
+    /// Returns the level of the longest prefix matching `target`.
+    ///
+    /// A prefix matches if it is equal to `target` or to one of its parent modules, e.g.
+    /// "resourced::memory" matches "resourced::memory::pressure" but not "resourced::memory2".
+    fn level_for(&self, target: &str) -> LevelFilter {
+        if self.overrides.is_empty() {
+            return self.default_level;
+        }
+        let mut prefix = target;
+        loop {
+            if let Ok(i) = self
+                .overrides
+                .binary_search_by(|(p, _)| p.as_str().cmp(prefix))
+            {
+                return self.overrides[i].1;
+            }
+            match prefix.rfind("::") {
+                Some(end) => prefix = &prefix[..end],
+                None => return self.default_level,
+            }
+        }
+    }
+
+    fn set(&mut self, module_path_prefix: &str, level: LevelFilter) {
+        match self
+            .overrides
+            .binary_search_by(|(p, _)| p.as_str().cmp(module_path_prefix))
+        {
+            Ok(i) => self.overrides[i].1 = level,
+            Err(i) => self
+                .overrides
+                .insert(i, (module_path_prefix.to_string(), level)),
+        }
+    }
+
+    fn replace(&mut self, mut overrides: Vec<(String, LevelFilter)>) {
+        overrides.sort_by(|(a, _), (b, _)| a.cmp(b));
+        // The last entry of duplicated prefixes wins.
+        overrides.reverse();
+        overrides.dedup_by(|(a, _), (b, _)| a == b);
+        overrides.reverse();
+        self.overrides = overrides;
+    }
+
+    /// The most verbose level, which is the max level the `log` macros have to let through.
+    fn max_level(&self) -> LevelFilter {
+        self.overrides
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.default_level, Ord::max)
+    }
+}
+
+static MODULE_LEVELS: RwLock<ModuleLevels> = RwLock::new(ModuleLevels::new(LevelFilter::Info));
+
+/// Filters records by [ModuleLevels] before passing them to the inner logger.
+struct ModuleLevelLogger {
+    inner: Box<dyn Log>,
+    levels: &'static RwLock<ModuleLevels>,
+}
+
+impl Log for ModuleLevelLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        let levels = self.levels.read().unwrap_or_else(|e| e.into_inner());
+        metadata.level() <= levels.level_for(metadata.target()) && self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            self.inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+fn update_module_levels<F: FnOnce(&mut ModuleLevels)>(f: F) {
+    let mut levels = MODULE_LEVELS.write().unwrap_or_else(|e| e.into_inner());
+    f(&mut levels);
+    log::set_max_level(levels.max_level());
+}
+
+/// Overrides the max log level of the modules under `module_path_prefix`.
+///
+/// The longest matching prefix wins. Takes effect immediately for loggers set up by
+/// `init(...)` or `init_with_level(...)`.
+pub fn set_module_level(module_path_prefix: &str, level: LevelFilter) {
+    update_module_levels(|levels| levels.set(module_path_prefix, level));
+}
+
+/// Replaces all the per-module log level overrides with `overrides`.
+pub fn set_module_levels(overrides: Vec<(String, LevelFilter)>) {
+    update_module_levels(|levels| levels.replace(overrides));
+}
+
+/// Parses per-module log level overrides.
+///
+/// Each line is `prefix=level`, e.g. `resourced::memory=debug`. Empty lines and lines starting
+/// with `#` are ignored.
+pub fn parse_module_levels(config: &str) -> Result<Vec<(String, LevelFilter)>> {
+    let mut overrides = Vec::new();
+    for (i, line) in config.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (prefix, level) = line
+            .split_once('=')
+            .ok_or_else(|| Error::InvalidLevelConfig(i + 1, "missing '='".to_string()))?;
+        let prefix = prefix.trim();
+        if prefix.is_empty() {
+            return Err(Error::InvalidLevelConfig(i + 1, "empty prefix".to_string()));
+        }
+        let level = level.trim().parse().map_err(|_| {
+            Error::InvalidLevelConfig(i + 1, format!("invalid level {:?}", level.trim()))
+        })?;
+        overrides.push((prefix.to_string(), level));
+    }
+    Ok(overrides)
+}
+
+/// Get the path of the per-module log level config of the daemon `ident`.
+pub fn get_module_level_config_path(ident: &str) -> PathBuf {
+    Path::new("/run")
+        .join(ident)
+        .join(MODULE_LEVEL_CONFIG_FILE_NAME)
+}
+
+/// Applies the per-module log level config at `path`.
+///
+/// A missing file clears all the overrides. Daemons can call this from their SIGHUP handler.
+pub fn load_module_level_config(path: &Path) -> Result<()> {
+    let overrides = match fs::read_to_string(path) {
+        Ok(config) => parse_module_levels(&config)?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(Error::ReadLevelConfig(e)),
+    };
+    set_module_levels(overrides);
+    Ok(())
+}
+
+/// Applies the per-module log level config at `path` and re-applies it whenever the file is
+/// written, replaced or removed.
+///
+/// The parent directory of `path` must exist. The config is watched by a background thread for
+/// the lifetime of the process.
+pub fn watch_module_level_config(path: PathBuf) -> Result<JoinHandle<()>> {
+    let dir = path.parent().unwrap_or_else(|| Path::new("/"));
+    let file_name = path.file_name().map(OsStr::to_os_string);
+    let inotify = Inotify::init(InitFlags::IN_CLOEXEC).map_err(Error::WatchLevelConfig)?;
+    inotify
+        .add_watch(
+            dir,
+            AddWatchFlags::IN_CLOSE_WRITE | AddWatchFlags::IN_MOVED_TO | AddWatchFlags::IN_DELETE,
+        )
+        .map_err(Error::WatchLevelConfig)?;
+    load_module_level_config(&path)?;
+
+    Ok(thread::spawn(move || loop {
+        let events = match inotify.read_events() {
+            Ok(events) => events,
+            Err(nix::errno::Errno::EINTR) => continue,
+            Err(e) => {
+                log::error!("stop watching log level config: {}", e);
+                return;
+            }
+        };
+        if events.iter().any(|event| event.name == file_name) {
+            if let Err(e) = load_module_level_config(&path) {
+                log::error!("failed to apply log level config: {}", e);
+            }
+        }
+    }))
+}
+
 /// Get an identifier to be used with init(...) from the current process.
 pub fn get_ident_from_process() -> Option<String> {
     env::current_exe()
@@ -49,22 +259,216 @@ pub fn init(ident: String, log_to_stderr: bool) -> Result<()> {
     init_with_level(ident, log_to_stderr, LevelFilter::Info)
 }
 
+/// Initialize logging with `max_level` as the level of the modules without overrides.
+///
+/// See [set_module_level] and [watch_module_level_config] for raising or lowering the level of
+/// specific modules at runtime.
 pub fn init_with_level(ident: String, log_to_stderr: bool, max_level: LevelFilter) -> Result<()> {
     let syslog_logger = Box::new(get_syslog_logger(ident)?);
 
-    if log_to_stderr {
+    let inner: Box<dyn Log> = if log_to_stderr {
         let mut stderr_logger = StdErrLog::new();
-        stderr_logger.verbosity(max_level as usize);
-
-        if let Some(level) = max_level.to_level() {
-            multi_log::MultiLogger::init(vec![Box::new(stderr_logger), syslog_logger], level)
-        } else {
-            // When max_level is LevelFilter::Off, max_level.to_level() returns
-            // None. In this case, we don't need to initialize MultiLogger.
-            Ok(())
-        }
+        // Levels are filtered by ModuleLevelLogger, which allows overrides above max_level.
+        stderr_logger.verbosity(log::Level::Trace as usize);
+        Box::new(multi_log::MultiLogger::new(vec![
+            Box::new(stderr_logger),
+            syslog_logger,
+        ]))
     } else {
-        log::set_boxed_logger(syslog_logger).map(|()| log::set_max_level(max_level))
+        syslog_logger
+    };
+
+    log::set_boxed_logger(Box::new(ModuleLevelLogger {
+        inner,
+        levels: &MODULE_LEVELS,
+    }))
+    .map_err(Error::SetLoggerError)?;
+    update_module_levels(|levels| levels.default_level = max_level);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    use log::Level;
+
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct CaptureLogger(Arc<Mutex<Vec<String>>>);
+
+    impl Log for CaptureLogger {
+        fn enabled(&self, _metadata: &Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &Record) {
+            self.0.lock().unwrap().push(record.target().to_string());
+        }
+
+        fn flush(&self) {}
+    }
+
+    fn levels(overrides: &[(&str, LevelFilter)]) -> ModuleLevels {
+        let mut levels = ModuleLevels::new(LevelFilter::Info);
+        for (prefix, level) in overrides {
+            levels.set(prefix, *level);
+        }
+        levels
+    }
+
+    fn log_to(logger: &ModuleLevelLogger, target: &str, level: Level) {
+        logger.log(
+            &Record::builder()
+                .target(target)
+                .level(level)
+                .args(format_args!("message"))
+                .build(),
+        );
+    }
+
+    #[test]
+    fn level_for_prefix() {
+        let levels = levels(&[
+            ("resourced", LevelFilter::Warn),
+            ("resourced::memory", LevelFilter::Debug),
+            ("resourced::memory::psi", LevelFilter::Off),
+        ]);
+        assert_eq!(levels.level_for("resourced"), LevelFilter::Warn);
+        assert_eq!(levels.level_for("resourced::dbus"), LevelFilter::Warn);
+        assert_eq!(levels.level_for("resourced::memory"), LevelFilter::Debug);
+        assert_eq!(
+            levels.level_for("resourced::memory::margins"),
+            LevelFilter::Debug
+        );
+        assert_eq!(
+            levels.level_for("resourced::memory::psi::monitor"),
+            LevelFilter::Off
+        );
+        // Prefixes only match whole module names.
+        assert_eq!(levels.level_for("resourced2"), LevelFilter::Info);
+        assert_eq!(levels.level_for("resourced::memory2"), LevelFilter::Warn);
+        assert_eq!(levels.level_for("other"), LevelFilter::Info);
+    }
+
+    #[test]
+    fn max_level_includes_overrides() {
+        assert_eq!(levels(&[]).max_level(), LevelFilter::Info);
+        assert_eq!(
+            levels(&[("a", LevelFilter::Error)]).max_level(),
+            LevelFilter::Info
+        );
+        assert_eq!(
+            levels(&[("a", LevelFilter::Error), ("b", LevelFilter::Trace)]).max_level(),
+            LevelFilter::Trace
+        );
+    }
+
+    #[test]
+    fn replace_keeps_last_duplicate() {
+        let mut levels = levels(&[("a", LevelFilter::Trace)]);
+        levels.replace(vec![
+            ("b".to_string(), LevelFilter::Debug),
+            ("c".to_string(), LevelFilter::Warn),
+            ("b".to_string(), LevelFilter::Error),
+        ]);
+        assert_eq!(levels.level_for("a"), LevelFilter::Info);
+        assert_eq!(levels.level_for("b"), LevelFilter::Error);
+        assert_eq!(levels.level_for("c"), LevelFilter::Warn);
+    }
+
+    #[test]
+    fn parse_config() {
+        let overrides = parse_module_levels(
+            "# comment\n\nresourced::memory = debug\n  hiberman=OFF\nresourced::memory=trace\n",
+        )
+        .unwrap();
+        assert_eq!(
+            overrides,
+            vec![
+                ("resourced::memory".to_string(), LevelFilter::Debug),
+                ("hiberman".to_string(), LevelFilter::Off),
+                ("resourced::memory".to_string(), LevelFilter::Trace),
+            ]
+        );
+        assert!(parse_module_levels("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn parse_invalid_config() {
+        assert!(matches!(
+            parse_module_levels("a=debug\nb"),
+            Err(Error::InvalidLevelConfig(2, _))
+        ));
+        assert!(matches!(
+            parse_module_levels("=debug"),
+            Err(Error::InvalidLevelConfig(1, _))
+        ));
+        assert!(matches!(
+            parse_module_levels("a=verbose"),
+            Err(Error::InvalidLevelConfig(1, _))
+        ));
+    }
+
+    #[test]
+    fn dynamic_update() {
+        let levels: &'static RwLock<ModuleLevels> =
+            Box::leak(Box::new(RwLock::new(ModuleLevels::new(LevelFilter::Info))));
+        let capture = CaptureLogger::default();
+        let logger = ModuleLevelLogger {
+            inner: Box::new(capture.clone()),
+            levels,
+        };
+
+        log_to(&logger, "daemon::memory", Level::Debug);
+        log_to(&logger, "daemon::memory", Level::Info);
+        assert_eq!(*capture.0.lock().unwrap(), vec!["daemon::memory"]);
+
+        levels
+            .write()
+            .unwrap()
+            .set("daemon::memory", LevelFilter::Debug);
+        log_to(&logger, "daemon::memory", Level::Debug);
+        log_to(&logger, "daemon::dbus", Level::Debug);
+        assert_eq!(
+            *capture.0.lock().unwrap(),
+            vec!["daemon::memory", "daemon::memory"]
+        );
+
+        levels.write().unwrap().replace(Vec::new());
+        log_to(&logger, "daemon::memory", Level::Debug);
+        assert_eq!(capture.0.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn load_config_file() {
+        let dir = std::env::temp_dir().join(format!("syslog_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(MODULE_LEVEL_CONFIG_FILE_NAME);
+
+        fs::write(&path, "libchromeos::test_module=trace\n").unwrap();
+        load_module_level_config(&path).unwrap();
+        assert_eq!(
+            MODULE_LEVELS
+                .read()
+                .unwrap()
+                .level_for("libchromeos::test_module"),
+            LevelFilter::Trace
+        );
+        assert_eq!(log::max_level(), LevelFilter::Trace);
+
+        // Removing the file clears the overrides.
+        fs::remove_file(&path).unwrap();
+        load_module_level_config(&path).unwrap();
+        assert_eq!(
+            MODULE_LEVELS
+                .read()
+                .unwrap()
+                .level_for("libchromeos::test_module"),
+            LevelFilter::Info
+        );
+        fs::remove_dir(&dir).unwrap();
     }
-    .map_err(Error::SetLoggerError)
 }
@@ -0,0 +1,290 @@
+// Copyright 2024 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Watches the metrics consent file and reports changes of the consent state.
+
+use std::ffi::OsString;
+use std::io::{Error, ErrorKind};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use nix::errno::Errno;
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
+
+/// The consent file consulted by libmetrics, see kConsentFile in metrics_library.cc.
+pub const CONSENT_FILE: &str = "/home/chronos/Consent To Send Stats";
+
+/// How long the consent file has to stay unchanged before the consent state is evaluated again.
+pub const CONSENT_DEBOUNCE: Duration = Duration::from_millis(500);
+
+pub type ConsentCallback = Box<dyn Fn(bool) + Send>;
+
+/// What ended a wait for changes of the consent file.
+enum Wake {
+    Changed,
+    TimedOut,
+    Stopped,
+}
+
+/// Watches a consent file on a background thread.
+///
+/// Once the file has been created, written, replaced or removed and then stayed unchanged for the
+/// debounce duration, the consent state is evaluated again and the callbacks are invoked if it
+/// changed. The thread is stopped and joined when the watcher is dropped.
+pub struct ConsentWatcher {
+    state: Arc<AtomicBool>,
+    callbacks: Arc<Mutex<Vec<ConsentCallback>>>,
+    stop: Option<UnixStream>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ConsentWatcher {
+    /// Starts watching `path`, whose parent directory must exist. `check` evaluates the consent
+    /// state; it is called once before this returns and then on the background thread.
+    pub fn new<F>(path: &Path, debounce: Duration, mut check: F) -> Result<Self, Error>
+    where
+        F: FnMut() -> bool + Send + 'static,
+    {
+        let dir = path.parent().unwrap_or_else(|| Path::new("/"));
+        let file_name = path
+            .file_name()
+            .map(OsString::from)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "consent file has no name"))?;
+        let inotify = Inotify::init(InitFlags::IN_CLOEXEC | InitFlags::IN_NONBLOCK)?;
+        inotify.add_watch(
+            dir,
+            AddWatchFlags::IN_CREATE
+                | AddWatchFlags::IN_MODIFY
+                | AddWatchFlags::IN_CLOSE_WRITE
+                | AddWatchFlags::IN_MOVED_FROM
+                | AddWatchFlags::IN_MOVED_TO
+                | AddWatchFlags::IN_DELETE,
+        )?;
+        // The watch is added first, so that no change after the initial check is missed.
+        let state = Arc::new(AtomicBool::new(check()));
+        let callbacks = Arc::new(Mutex::new(Vec::<ConsentCallback>::new()));
+        let (stop, stopped) = UnixStream::pair()?;
+
+        let thread = {
+            let state = state.clone();
+            let callbacks = callbacks.clone();
+            thread::Builder::new()
+                .name("consent-watcher".to_string())
+                .spawn(move || loop {
+                    match wait_for_change(&inotify, &stopped, &file_name, None) {
+                        Wake::Changed => {}
+                        Wake::TimedOut => continue,
+                        Wake::Stopped => return,
+                    }
+                    // Wait until the file settles, so that a burst of writes results in a single
+                    // evaluation.
+                    loop {
+                        match wait_for_change(&inotify, &stopped, &file_name, Some(debounce)) {
+                            Wake::Changed => {}
+                            Wake::TimedOut => break,
+                            Wake::Stopped => return,
+                        }
+                    }
+                    let enabled = check();
+                    if state.swap(enabled, Ordering::SeqCst) != enabled {
+                        for callback in callbacks.lock().unwrap().iter() {
+                            callback(enabled);
+                        }
+                    }
+                })?
+        };
+
+        Ok(ConsentWatcher {
+            state,
+            callbacks,
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+
+    /// Adds `callback`, which is invoked on the background thread with the new consent state
+    /// whenever it changes.
+    pub fn subscribe(&self, callback: ConsentCallback) {
+        self.callbacks.lock().unwrap().push(callback);
+    }
+
+    /// Returns the last observed consent state.
+    pub fn state(&self) -> bool {
+        self.state.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for ConsentWatcher {
+    fn drop(&mut self) {
+        // Closing our end of the socket pair wakes up the thread.
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                log::error!("consent watcher thread panicked");
+            }
+        }
+    }
+}
+
+/// Waits until `file_name` changes, `timeout` passes or `stopped` is closed by the other end.
+fn wait_for_change(
+    inotify: &Inotify,
+    stopped: &UnixStream,
+    file_name: &OsString,
+    timeout: Option<Duration>,
+) -> Wake {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    loop {
+        let timeout_ms = match deadline {
+            Some(deadline) => {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                // Round up, so that the deadline has passed once poll(2) times out.
+                remaining
+                    .as_micros()
+                    .div_ceil(1000)
+                    .try_into()
+                    .unwrap_or(i32::MAX)
+            }
+            None => -1,
+        };
+        let mut fds = [
+            PollFd::new(inotify.as_raw_fd(), PollFlags::POLLIN),
+            PollFd::new(stopped.as_raw_fd(), PollFlags::POLLIN),
+        ];
+        match poll(&mut fds, timeout_ms) {
+            Ok(0) => return Wake::TimedOut,
+            Ok(_) => {}
+            Err(Errno::EINTR) => continue,
+            Err(e) => {
+                log::error!("stop watching the consent file: {}", e);
+                return Wake::Stopped;
+            }
+        }
+        if fds[1].revents().is_some_and(|revents| !revents.is_empty()) {
+            return Wake::Stopped;
+        }
+        match inotify.read_events() {
+            Ok(events) => {
+                if events
+                    .iter()
+                    .any(|event| event.name.as_ref() == Some(file_name))
+                {
+                    return Wake::Changed;
+                }
+            }
+            Err(Errno::EAGAIN) | Err(Errno::EINTR) => {}
+            Err(e) => {
+                log::error!("stop watching the consent file: {}", e);
+                return Wake::Stopped;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+
+    use tempfile::TempDir;
+
+    const DEBOUNCE: Duration = Duration::from_millis(100);
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Starts a watcher treating "1" in the consent file as consent and returns it together with
+    /// the path of the consent file and a receiver of the callback invocations.
+    fn start_watcher(dir: &TempDir) -> (ConsentWatcher, PathBuf, Receiver<bool>) {
+        let path = dir.path().join("consent");
+        let check_path = path.clone();
+        let watcher = ConsentWatcher::new(&path, DEBOUNCE, move || {
+            fs::read_to_string(&check_path).is_ok_and(|content| content == "1")
+        })
+        .unwrap();
+        let (sender, receiver) = channel();
+        watcher.subscribe(Box::new(move |enabled| sender.send(enabled).unwrap()));
+        (watcher, path, receiver)
+    }
+
+    #[test]
+    fn test_create_modify_delete() {
+        let dir = TempDir::new().unwrap();
+        let (watcher, path, receiver) = start_watcher(&dir);
+        assert!(!watcher.state());
+
+        fs::write(&path, "1").unwrap();
+        assert_eq!(receiver.recv_timeout(TIMEOUT), Ok(true));
+        assert!(watcher.state());
+
+        fs::write(&path, "0").unwrap();
+        assert_eq!(receiver.recv_timeout(TIMEOUT), Ok(false));
+        assert!(!watcher.state());
+
+        fs::write(&path, "1").unwrap();
+        assert_eq!(receiver.recv_timeout(TIMEOUT), Ok(true));
+
+        fs::remove_file(&path).unwrap();
+        assert_eq!(receiver.recv_timeout(TIMEOUT), Ok(false));
+        assert!(!watcher.state());
+    }
+
+    #[test]
+    fn test_initial_state() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("consent"), "1").unwrap();
+        let (watcher, _path, receiver) = start_watcher(&dir);
+        assert!(watcher.state());
+        assert!(receiver.recv_timeout(DEBOUNCE * 3).is_err());
+    }
+
+    #[test]
+    fn test_debounce() {
+        let dir = TempDir::new().unwrap();
+        let (watcher, path, receiver) = start_watcher(&dir);
+
+        // A burst of writes is evaluated once, after the last one.
+        for content in ["1", "0", "1", "0", "1"] {
+            fs::write(&path, content).unwrap();
+        }
+        assert_eq!(receiver.recv_timeout(TIMEOUT), Ok(true));
+        assert!(receiver.recv_timeout(DEBOUNCE * 3).is_err());
+        assert!(watcher.state());
+
+        // A burst that ends in the original state does not invoke the callbacks.
+        fs::write(&path, "0").unwrap();
+        fs::write(&path, "1").unwrap();
+        assert!(receiver.recv_timeout(DEBOUNCE * 3).is_err());
+        assert!(watcher.state());
+    }
+
+    #[test]
+    fn test_unrelated_file() {
+        let dir = TempDir::new().unwrap();
+        let (watcher, _path, receiver) = start_watcher(&dir);
+        fs::write(dir.path().join("other"), "1").unwrap();
+        assert!(receiver.recv_timeout(DEBOUNCE * 3).is_err());
+        assert!(!watcher.state());
+    }
+
+    #[test]
+    fn test_drop_stops_thread() {
+        let dir = TempDir::new().unwrap();
+        let (watcher, path, receiver) = start_watcher(&dir);
+        drop(watcher);
+        // The callbacks, and with them the sender, are gone once the thread is joined.
+        fs::write(&path, "1").unwrap();
+        assert_eq!(
+            receiver.recv_timeout(TIMEOUT),
+            Err(RecvTimeoutError::Disconnected)
+        );
+    }
+}
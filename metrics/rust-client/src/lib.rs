@@ -5,14 +5,18 @@
 mod bindings {
     include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
 }
+mod consent;
 
 use std::io::{Error, ErrorKind};
+use std::path::Path;
 use std::sync::{Arc, Mutex, OnceLock};
 
 use crate::bindings::*;
+use crate::consent::{ConsentWatcher, CONSENT_DEBOUNCE, CONSENT_FILE};
 
 pub struct MetricsLibrary {
     handle: CMetricsLibrary,
+    consent_watcher: Option<ConsentWatcher>,
 }
 
 // The thread safety issue with metrics library is that it is not safe to have
@@ -31,18 +35,23 @@ impl MetricsLibrary {
     pub fn get() -> Option<Arc<Mutex<Self>>> {
         static METRICS_LIBRARY: OnceLock<Option<Arc<Mutex<MetricsLibrary>>>> = OnceLock::new();
         METRICS_LIBRARY
-            .get_or_init(|| {
-                // Safety: Calls a C function.
-                let handle = unsafe { CMetricsLibraryNew() };
-                if handle.is_null() {
-                    None
-                } else {
-                    Some(Arc::new(Mutex::new(MetricsLibrary { handle })))
-                }
-            })
+            .get_or_init(|| MetricsLibrary::new().map(|library| Arc::new(Mutex::new(library))))
             .clone()
     }
 
+    fn new() -> Option<Self> {
+        // Safety: Calls a C function.
+        let handle = unsafe { CMetricsLibraryNew() };
+        if handle.is_null() {
+            None
+        } else {
+            Some(MetricsLibrary {
+                handle,
+                consent_watcher: None,
+            })
+        }
+    }
+
     pub fn send_to_uma(
         &mut self,
         name: &str,
@@ -191,6 +200,39 @@ impl MetricsLibrary {
         // Safety: Calls a C function. The argument type is checked.
         (unsafe { CMetricsLibraryAreMetricsEnabled(self.handle) }) != 0
     }
+
+    // Invokes `callback` with the new result of are_metrics_enabled() whenever the consent file
+    // changes in a way that changes the result. The result is evaluated again once the file
+    // stayed unchanged for a while, so that a burst of writes is reported once. Changes that do
+    // not touch the consent file, e.g. of the device policy, are not noticed.
+    // `callback` runs on a background thread, which is stopped when this instance is dropped.
+    pub fn subscribe_consent_changes(
+        &mut self,
+        callback: Box<dyn Fn(bool) + Send>,
+    ) -> Result<(), Error> {
+        if self.consent_watcher.is_none() {
+            // The background thread queries its own instance, so that it never calls into the C
+            // library at the same time as the user of this one.
+            let mut library =
+                MetricsLibrary::new().ok_or_else(|| Error::other("CMetricsLibraryNew failed"))?;
+            self.consent_watcher = Some(ConsentWatcher::new(
+                Path::new(CONSENT_FILE),
+                CONSENT_DEBOUNCE,
+                move || library.are_metrics_enabled(),
+            )?);
+        }
+        if let Some(watcher) = &self.consent_watcher {
+            watcher.subscribe(callback);
+        }
+        Ok(())
+    }
+
+    // Returns the consent state last observed by the watcher started by
+    // subscribe_consent_changes() without calling into the C library, or None if no watcher was
+    // started.
+    pub fn consent_state_cached(&self) -> Option<bool> {
+        self.consent_watcher.as_ref().map(ConsentWatcher::state)
+    }
 }
 
 impl Drop for MetricsLibrary {
@@ -6,17 +6,29 @@ use std::fmt::Display;
 use std::fs::File;
 use std::io;
 use std::io::Write;
-use std::path::Path;
+use std::os::fd::AsRawFd;
 use std::path::PathBuf;
 
 use crate::ProcessId;
 use crate::ThreadId;
 
-const CGROUP_CPU_PATH: &str = "/sys/fs/cgroup/cpu";
-const CGROUP_CPUSET_PATH: &str = "/sys/fs/cgroup/cpuset";
+const CGROUP_ROOT_PATH: &str = "/sys/fs/cgroup";
+const CPU_CONTROLLER: &str = "cpu";
+const CPUSET_CONTROLLER: &str = "cpuset";
+const MEMORY_CONTROLLER: &str = "memory";
 const CPU_SHARE_FILE: &str = "cpu.shares";
+const CPU_WEIGHT_FILE: &str = "cpu.weight";
+const MEMORY_SWAPPINESS_FILE: &str = "memory.swappiness";
 const CGROUP_PROCESSES_FILE: &str = "cgroup.procs";
 const CGROUP_THREADS_FILE: &str = "tasks";
+const CGROUP_V2_THREADS_FILE: &str = "cgroup.threads";
+/// ChromeOS mounts the v1 cpuset hierarchy with noprefix.
+const CPUSET_CPUS_FILE: &str = "cpus";
+const CPUSET_V2_CPUS_FILE: &str = "cpuset.cpus";
+/// cpu.shares which the kernel treats as the default cpu.weight of 100.
+const CPU_SHARES_PER_DEFAULT_WEIGHT: u32 = 1024;
+const CPU_WEIGHT_DEFAULT: u32 = 100;
+const CPU_WEIGHT_MAX: u32 = 10000;
 
 /// Error while setting up cgroups
 #[derive(Debug)]
@@ -40,48 +52,190 @@ impl Display for CgroupSetupError {
     }
 }
 
-/// Setup cpu cgroup
-///
-/// Cpu cgroups are used to control cpu share of managed processes.
-///
-/// This creates the cgroup if not exist.
+/// Version of the cgroup hierarchy
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CgroupVersion {
+    /// One hierarchy per controller, e.g. "/sys/fs/cgroup/cpu/<name>".
+    #[default]
+    V1,
+    /// The unified hierarchy, e.g. "/sys/fs/cgroup/<name>" for all controllers.
+    V2,
+}
+
+/// The cgroup hierarchy in which [CgroupContext] cgroups are set up.
 ///
-/// This returns an opened [File] of cgroup.procs of the cgroup.
+/// The version decides which files are used:
 ///
-/// TODO(kawasin): Write unit test. The test requires an environment to execute
-/// with cpu cgroup submodule enabled.
-pub fn setup_cpu_cgroup(name: &str, cpu_shares: u16) -> CgroupSetupResult {
-    let cgroup_path = Path::new(CGROUP_CPU_PATH).join(name);
+/// * cpu cgroups: "cpu.shares" on v1 and "cpu.weight" on v2.
+/// * cpuset cgroups: threads are moved via "tasks" on v1 and via "cgroup.threads" on v2.
+/// * memory cgroups: v2 has no per-cgroup "memory.swappiness".
+#[derive(Clone, Debug)]
+pub struct CgroupHierarchy {
+    version: CgroupVersion,
+    root: PathBuf,
+}
+
+impl CgroupHierarchy {
+    /// The hierarchy mounted at "/sys/fs/cgroup".
+    pub fn new(version: CgroupVersion) -> Self {
+        Self::with_root(version, CGROUP_ROOT_PATH)
+    }
+
+    /// The hierarchy mounted at `root`.
+    pub fn with_root(version: CgroupVersion, root: impl Into<PathBuf>) -> Self {
+        Self {
+            version,
+            root: root.into(),
+        }
+    }
+
+    pub fn version(&self) -> CgroupVersion {
+        self.version
+    }
+
+    fn cgroup_path(&self, controller: &str, name: &str) -> PathBuf {
+        match self.version {
+            CgroupVersion::V1 => self.root.join(controller).join(name),
+            CgroupVersion::V2 => self.root.join(name),
+        }
+    }
+
+    /// Setup cpu cgroup
+    ///
+    /// Cpu cgroups are used to control cpu share of managed processes. On v2, `cpu_shares` is
+    /// converted to the equivalent cpu.weight.
+    ///
+    /// This creates the cgroup if not exist.
+    ///
+    /// This returns an opened [File] of cgroup.procs of the cgroup.
+    pub fn setup_cpu_cgroup(&self, name: &str, cpu_shares: u16) -> CgroupSetupResult {
+        let cgroup_path = create_cgroup(self.cgroup_path(CPU_CONTROLLER, name))?;
+        self.set_cpu_shares(name, cpu_shares)?;
+        open_cgroup_file(cgroup_path.join(CGROUP_PROCESSES_FILE))
+    }
+
+    /// Changes the cpu share of the existing cpu cgroup. On v2, `cpu_shares` is converted to the
+    /// equivalent cpu.weight.
+    pub fn set_cpu_shares(
+        &self,
+        name: &str,
+        cpu_shares: u16,
+    ) -> std::result::Result<(), CgroupSetupError> {
+        let cgroup_path = self.cgroup_path(CPU_CONTROLLER, name);
+        let (share_file, value) = match self.version {
+            CgroupVersion::V1 => (cgroup_path.join(CPU_SHARE_FILE), cpu_shares as u32),
+            CgroupVersion::V2 => (
+                cgroup_path.join(CPU_WEIGHT_FILE),
+                cpu_shares_to_weight(cpu_shares),
+            ),
+        };
+        std::fs::write(&share_file, value.to_string()).map_err(|e| CgroupSetupError(share_file, e))
+    }
+
+    /// Setup memory cgroup
+    ///
+    /// Memory cgroups are used to control memory reclaim of managed processes.
+    ///
+    /// This creates the cgroup if not exist. If `swappiness` is [None], the cgroup keeps the
+    /// swappiness inherited from its parent. `swappiness` is ignored on v2, which has no
+    /// per-cgroup swappiness.
+    ///
+    /// This returns an opened [File] of cgroup.procs of the cgroup.
+    pub fn setup_memory_cgroup(&self, name: &str, swappiness: Option<u32>) -> CgroupSetupResult {
+        let cgroup_path = create_cgroup(self.cgroup_path(MEMORY_CONTROLLER, name))?;
+        if let (CgroupVersion::V1, Some(swappiness)) = (self.version, swappiness) {
+            let swappiness_file = cgroup_path.join(MEMORY_SWAPPINESS_FILE);
+            std::fs::write(&swappiness_file, swappiness.to_string())
+                .map_err(|e| CgroupSetupError(swappiness_file, e))?;
+        }
+        open_cgroup_file(cgroup_path.join(CGROUP_PROCESSES_FILE))
+    }
+
+    /// Opens the file to move processes to the existing memory cgroup
+    ///
+    /// Unlike [Self::setup_memory_cgroup], this neither creates the cgroup nor changes its
+    /// settings, so the cgroup can be prepared by a privileged init script.
+    pub fn open_memory_cgroup(&self, name: &str) -> CgroupSetupResult {
+        open_cgroup_file(
+            self.cgroup_path(MEMORY_CONTROLLER, name)
+                .join(CGROUP_PROCESSES_FILE),
+        )
+    }
+
+    /// Opens the file to move threads to the existing cpuset cgroup
+    ///
+    /// The cpuset cgroup must be configured. On v2, it must be a threaded cgroup in the threaded
+    /// subtree of the cpu cgroup the processes of the threads are in, see
+    /// [CgroupContext::cpuset_background_all].
+    ///
+    /// TODO(kawasin): Move cpuset setup into resourced
+    pub fn open_cpuset_cgroup(&self, name: &str) -> CgroupSetupResult {
+        let threads_file = match self.version {
+            CgroupVersion::V1 => CGROUP_THREADS_FILE,
+            CgroupVersion::V2 => CGROUP_V2_THREADS_FILE,
+        };
+        open_cgroup_file(self.cgroup_path(CPUSET_CONTROLLER, name).join(threads_file))
+    }
+
+    /// Changes the CPUs of the existing cpuset cgroup, e.g. "0-3" or "0,2".
+    pub fn set_cpuset_cpus(
+        &self,
+        name: &str,
+        cpus: &str,
+    ) -> std::result::Result<(), CgroupSetupError> {
+        let cpus_file = self
+            .cgroup_path(CPUSET_CONTROLLER, name)
+            .join(match self.version {
+                CgroupVersion::V1 => CPUSET_CPUS_FILE,
+                CgroupVersion::V2 => CPUSET_V2_CPUS_FILE,
+            });
+        std::fs::write(&cpus_file, cpus).map_err(|e| CgroupSetupError(cpus_file, e))
+    }
+}
+
+/// Converts cpu.shares to the cpu.weight the kernel schedules the same, i.e. 1024 shares are the
+/// default weight of 100.
+fn cpu_shares_to_weight(cpu_shares: u16) -> u32 {
+    let weight = (cpu_shares as u32 * CPU_WEIGHT_DEFAULT + CPU_SHARES_PER_DEFAULT_WEIGHT / 2)
+        / CPU_SHARES_PER_DEFAULT_WEIGHT;
+    weight.clamp(1, CPU_WEIGHT_MAX)
+}
+
+fn create_cgroup(cgroup_path: PathBuf) -> std::result::Result<PathBuf, CgroupSetupError> {
     if !cgroup_path.exists() {
         if let Err(e) = std::fs::create_dir_all(&cgroup_path) {
             return Err(CgroupSetupError(cgroup_path, e));
         }
     }
-    let share_file = cgroup_path.join(CPU_SHARE_FILE);
-    std::fs::write(&share_file, cpu_shares.to_string())
-        .map_err(|e| CgroupSetupError(share_file, e))?;
-    let cgroup_file = cgroup_path.join(CGROUP_PROCESSES_FILE);
+    Ok(cgroup_path)
+}
+
+fn open_cgroup_file(cgroup_file: PathBuf) -> CgroupSetupResult {
     std::fs::OpenOptions::new()
         .write(true)
         .open(&cgroup_file)
         .map_err(|e| CgroupSetupError(cgroup_file, e))
 }
 
-/// Opens tasks file of the existing cpuset cgroup
+/// Setup cpu cgroup in the cgroup v1 hierarchy
 ///
-/// The cpuset cgroup must be configured.
+/// See [CgroupHierarchy::setup_cpu_cgroup].
+pub fn setup_cpu_cgroup(name: &str, cpu_shares: u16) -> CgroupSetupResult {
+    CgroupHierarchy::new(CgroupVersion::V1).setup_cpu_cgroup(name, cpu_shares)
+}
+
+/// Opens cgroup.procs of the existing memory cgroup in the cgroup v1 hierarchy
 ///
-/// TODO(kawasin): Move cpuset setup into resourced
-/// TODO(kawasin): Write unit test. The test requires an environment to execute
-/// with cpu cgroup submodule enabled.
+/// See [CgroupHierarchy::open_memory_cgroup].
+pub fn open_memory_cgroup(name: &str) -> CgroupSetupResult {
+    CgroupHierarchy::new(CgroupVersion::V1).open_memory_cgroup(name)
+}
+
+/// Opens tasks file of the existing cpuset cgroup in the cgroup v1 hierarchy
+///
+/// See [CgroupHierarchy::open_cpuset_cgroup].
 pub fn open_cpuset_cgroup(name: &str) -> CgroupSetupResult {
-    let cgroup_path = Path::new(CGROUP_CPUSET_PATH)
-        .join(name)
-        .join(CGROUP_THREADS_FILE);
-    std::fs::OpenOptions::new()
-        .write(true)
-        .open(&cgroup_path)
-        .map_err(|e| CgroupSetupError(cgroup_path, e))
+    CgroupHierarchy::new(CgroupVersion::V1).open_cpuset_cgroup(name)
 }
 
 /// Set of cgroups used for scheduler settings.
@@ -90,7 +244,21 @@ pub fn open_cpuset_cgroup(name: &str) -> CgroupSetupResult {
 /// file of each cpu cgroup.
 ///
 /// cpuset cgroups are used for [CpusetCgroup]. The files must points "tasks"
-/// file of each cpuset cgroup.
+/// file of each cpuset cgroup, or "cgroup.threads" on cgroup v2.
+///
+/// On cgroup v2, a thread can only move to the threaded cgroups nested in the domain cgroup of its
+/// process, i.e. the cpu cgroup. The cpuset cgroups are threaded children of each cpu cgroup, e.g.
+/// "normal/all" and "background/all". `cpuset_all` and `cpuset_efficient` are the ones nested in
+/// the normal cpu cgroup and `cpuset_background_all` and `cpuset_background_efficient` the ones
+/// nested in the background cpu cgroup.
+///
+/// memory cgroups are used for [MemCgroup]. The files must points "cgroup.procs"
+/// file of each memory cgroup. They are optional and only processes in states whose
+/// [crate::ProcessStateConfig::memcg] is set are moved to them.
+///
+/// If `verify_writes` is true, the members of the cgroup are re-read after each write and the
+/// write fails if the process/thread is not listed. This is for debugging migrations which
+/// silently fail on some kernels and costs a read of the whole member list per write.
 #[derive(Debug)]
 pub struct CgroupContext {
     /// cgroup.procs file of cpu cgroup for normal processes
@@ -101,6 +269,21 @@ pub struct CgroupContext {
     pub cpuset_all: File,
     /// tasks file of cpuset cgroup for threads using efficient CPU cores only
     pub cpuset_efficient: File,
+    /// cgroup.threads file of cpuset cgroup for threads of background processes using all CPU
+    /// cores on cgroup v2. None if `cpuset_all` is used for all processes as on cgroup v1.
+    pub cpuset_background_all: Option<File>,
+    /// cgroup.threads file of cpuset cgroup for threads of background processes using efficient
+    /// CPU cores only on cgroup v2. None if `cpuset_efficient` is used for all processes as on
+    /// cgroup v1.
+    pub cpuset_background_efficient: Option<File>,
+    /// cgroup.procs file of memory cgroup for normal processes. None if memory cgroups are not
+    /// used.
+    pub memory_normal: Option<File>,
+    /// cgroup.procs file of memory cgroup for background processes. None if memory cgroups are
+    /// not used.
+    pub memory_background: Option<File>,
+    /// Verify that each write is reflected in the member list of the cgroup
+    pub verify_writes: bool,
 }
 
 impl CgroupContext {
@@ -114,25 +297,86 @@ impl CgroupContext {
             CpuCgroup::Background => &mut self.cpu_background,
         };
 
-        let _ = cgroup_file.write(process_id.0.to_string().as_bytes())?;
-        Ok(())
+        write_cgroup_member(cgroup_file, process_id.0, self.verify_writes)
+    }
+
+    /// Whether the cpuset cgroups are nested in the cpu cgroups as on cgroup v2.
+    ///
+    /// Moving a process to a cpu cgroup then moves all of its threads out of the cpuset cgroups.
+    pub(crate) fn has_nested_cpusets(&self) -> bool {
+        self.cpuset_background_all.is_some() || self.cpuset_background_efficient.is_some()
     }
 
+    /// Moves the thread to the cpuset cgroup nested in `cpu_cgroup`, the cpu cgroup of the process
+    /// of the thread.
     pub(crate) fn set_cpuset_cgroup(
         &mut self,
         thread_id: ThreadId,
+        cpu_cgroup: CpuCgroup,
         cpuset_cgroup: CpusetCgroup,
     ) -> io::Result<()> {
-        let cgroup_file = match cpuset_cgroup {
-            CpusetCgroup::All => &mut self.cpuset_all,
-            CpusetCgroup::Efficient => &mut self.cpuset_efficient,
+        let (cgroup_file, background_cgroup_file) = match cpuset_cgroup {
+            CpusetCgroup::All => (&mut self.cpuset_all, &mut self.cpuset_background_all),
+            CpusetCgroup::Efficient => (
+                &mut self.cpuset_efficient,
+                &mut self.cpuset_background_efficient,
+            ),
+        };
+        let cgroup_file = match (cpu_cgroup, background_cgroup_file) {
+            (CpuCgroup::Background, Some(background_cgroup_file)) => background_cgroup_file,
+            _ => cgroup_file,
+        };
+
+        write_cgroup_member(cgroup_file, thread_id.0, self.verify_writes)
+    }
+
+    /// Whether the file of the memory cgroup is set.
+    pub(crate) fn has_memory_cgroup(&self, memory_cgroup: MemCgroup) -> bool {
+        match memory_cgroup {
+            MemCgroup::Normal => self.memory_normal.is_some(),
+            MemCgroup::Background => self.memory_background.is_some(),
+        }
+    }
+
+    pub(crate) fn set_memory_cgroup(
+        &mut self,
+        process_id: ProcessId,
+        memory_cgroup: MemCgroup,
+    ) -> io::Result<()> {
+        let cgroup_file = match memory_cgroup {
+            MemCgroup::Normal => &mut self.memory_normal,
+            MemCgroup::Background => &mut self.memory_background,
+        };
+        let Some(cgroup_file) = cgroup_file else {
+            return Err(io::Error::other("memory cgroup is not set"));
         };
 
-        let _ = cgroup_file.write(thread_id.0.to_string().as_bytes())?;
-        Ok(())
+        write_cgroup_member(cgroup_file, process_id.0, self.verify_writes)
     }
 }
 
+/// Move the process/thread `id` to the cgroup.
+///
+/// If `verify` is true, this fails if `id` is not listed in the cgroup after the write.
+fn write_cgroup_member(cgroup_file: &mut File, id: u32, verify: bool) -> io::Result<()> {
+    let _ = cgroup_file.write(id.to_string().as_bytes())?;
+    if verify {
+        // The files in CgroupContext are write only. Reopen the file via procfs to read it.
+        let members =
+            std::fs::read_to_string(format!("/proc/self/fd/{}", cgroup_file.as_raw_fd()))?;
+        if !members
+            .split_whitespace()
+            .any(|member| member.parse() == Ok(id))
+        {
+            return Err(io::Error::other(format!(
+                "{} is not a member of the cgroup after write",
+                id
+            )));
+        }
+    }
+    Ok(())
+}
+
 /// Cpu cgroups
 #[derive(Clone, Copy, Debug)]
 pub enum CpuCgroup {
@@ -167,8 +411,27 @@ impl CpusetCgroup {
     }
 }
 
+/// Memory cgroups
+#[derive(Clone, Copy, Debug)]
+pub enum MemCgroup {
+    Normal,
+    Background,
+}
+
+impl MemCgroup {
+    /// Name to display
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Normal => "memory.normal",
+            Self::Background => "memory.background",
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use std::path::Path;
+
     use super::*;
     use crate::test_utils::*;
 
@@ -194,17 +457,258 @@ mod tests {
     fn test_set_cpuset_cgroup() {
         let (mut ctx, mut files) = create_fake_cgroup_context_pair();
 
-        ctx.set_cpuset_cgroup(ThreadId(123), CpusetCgroup::All)
+        ctx.set_cpuset_cgroup(ThreadId(123), CpuCgroup::Normal, CpusetCgroup::All)
             .unwrap();
         assert_eq!(read_number(&mut files.cpuset_all), Some(123));
 
-        ctx.set_cpuset_cgroup(ThreadId(456), CpusetCgroup::All)
+        ctx.set_cpuset_cgroup(ThreadId(456), CpuCgroup::Normal, CpusetCgroup::All)
             .unwrap();
         assert_eq!(read_number(&mut files.cpuset_all), Some(456));
 
-        ctx.set_cpuset_cgroup(ThreadId(789), CpusetCgroup::Efficient)
+        ctx.set_cpuset_cgroup(ThreadId(789), CpuCgroup::Normal, CpusetCgroup::Efficient)
             .unwrap();
         assert_eq!(read_number(&mut files.cpuset_all), None);
         assert_eq!(read_number(&mut files.cpuset_efficient), Some(789));
+
+        // The cpuset cgroups are shared by background processes unless they are nested.
+        ctx.set_cpuset_cgroup(ThreadId(123), CpuCgroup::Background, CpusetCgroup::All)
+            .unwrap();
+        assert_eq!(read_number(&mut files.cpuset_all), Some(123));
+    }
+
+    #[test]
+    fn test_set_nested_cpuset_cgroup() {
+        let (mut ctx, mut files) = create_fake_nested_cgroup_context_pair();
+        assert!(ctx.has_nested_cpusets());
+
+        ctx.set_cpuset_cgroup(ThreadId(123), CpuCgroup::Normal, CpusetCgroup::All)
+            .unwrap();
+        ctx.set_cpuset_cgroup(ThreadId(456), CpuCgroup::Background, CpusetCgroup::All)
+            .unwrap();
+        ctx.set_cpuset_cgroup(
+            ThreadId(789),
+            CpuCgroup::Background,
+            CpusetCgroup::Efficient,
+        )
+        .unwrap();
+        assert_eq!(read_number(&mut files.cpuset_all), Some(123));
+        assert_eq!(read_number(&mut files.cpuset_background_all), Some(456));
+        assert_eq!(read_number(&mut files.cpuset_efficient), None);
+        assert_eq!(
+            read_number(&mut files.cpuset_background_efficient),
+            Some(789)
+        );
+    }
+
+    #[test]
+    fn test_set_memory_cgroup() {
+        let (mut ctx, mut files) = create_fake_cgroup_context_pair();
+
+        ctx.set_memory_cgroup(ProcessId(123), MemCgroup::Normal)
+            .unwrap();
+        assert_eq!(read_number(&mut files.memory_normal), Some(123));
+
+        ctx.set_memory_cgroup(ProcessId(456), MemCgroup::Background)
+            .unwrap();
+        assert_eq!(read_number(&mut files.memory_normal), None);
+        assert_eq!(read_number(&mut files.memory_background), Some(456));
+
+        ctx.memory_background = None;
+        assert!(ctx
+            .set_memory_cgroup(ProcessId(456), MemCgroup::Background)
+            .is_err());
+        assert_eq!(read_number(&mut files.memory_background), None);
+    }
+
+    #[test]
+    fn test_verify_writes() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut ctx, _files) = create_fake_cgroup_context_pair();
+        ctx.verify_writes = true;
+        // A cgroup which lists the written id.
+        ctx.cpu_normal = File::create(dir.path().join("cgroup.procs")).unwrap();
+        // A cgroup which silently drops the write.
+        ctx.cpu_background = std::fs::OpenOptions::new()
+            .write(true)
+            .open("/dev/null")
+            .unwrap();
+
+        ctx.set_cpu_cgroup(ProcessId(123), CpuCgroup::Normal)
+            .unwrap();
+        let err = ctx
+            .set_cpu_cgroup(ProcessId(123), CpuCgroup::Background)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+
+        // The write is not verified unless verify_writes is set.
+        ctx.verify_writes = false;
+        ctx.set_cpu_cgroup(ProcessId(123), CpuCgroup::Background)
+            .unwrap();
+    }
+
+    /// Creates the cgroup files of a fake hierarchy under `root` and sets up a [CgroupContext]
+    /// from it.
+    ///
+    /// The cpuset cgroups are "all" and "efficient" on v1 and nested in the cpu cgroups, e.g.
+    /// "normal/all", on v2.
+    fn setup_fake_hierarchy(hierarchy: &CgroupHierarchy, root: &Path) -> CgroupContext {
+        let cpuset_names: &[&str] = match hierarchy.version() {
+            CgroupVersion::V1 => &["all", "efficient"],
+            CgroupVersion::V2 => &[
+                "normal/all",
+                "normal/efficient",
+                "background/all",
+                "background/efficient",
+            ],
+        };
+        let mut cgroups = vec![
+            (CPU_CONTROLLER, "normal", CGROUP_PROCESSES_FILE),
+            (CPU_CONTROLLER, "background", CGROUP_PROCESSES_FILE),
+            (MEMORY_CONTROLLER, "normal", CGROUP_PROCESSES_FILE),
+            (MEMORY_CONTROLLER, "background", CGROUP_PROCESSES_FILE),
+        ];
+        for name in cpuset_names {
+            cgroups.push((CPUSET_CONTROLLER, name, CGROUP_THREADS_FILE));
+            cgroups.push((CPUSET_CONTROLLER, name, CGROUP_V2_THREADS_FILE));
+        }
+        for (controller, name, file) in cgroups {
+            let dir = match hierarchy.version() {
+                CgroupVersion::V1 => root.join(controller).join(name),
+                CgroupVersion::V2 => root.join(name),
+            };
+            std::fs::create_dir_all(&dir).unwrap();
+            File::create(dir.join(file)).unwrap();
+        }
+        let (cpuset_all, cpuset_efficient, cpuset_background_all, cpuset_background_efficient) =
+            match hierarchy.version() {
+                CgroupVersion::V1 => (
+                    hierarchy.open_cpuset_cgroup("all").unwrap(),
+                    hierarchy.open_cpuset_cgroup("efficient").unwrap(),
+                    None,
+                    None,
+                ),
+                CgroupVersion::V2 => (
+                    hierarchy.open_cpuset_cgroup("normal/all").unwrap(),
+                    hierarchy.open_cpuset_cgroup("normal/efficient").unwrap(),
+                    Some(hierarchy.open_cpuset_cgroup("background/all").unwrap()),
+                    Some(
+                        hierarchy
+                            .open_cpuset_cgroup("background/efficient")
+                            .unwrap(),
+                    ),
+                ),
+            };
+        CgroupContext {
+            cpu_normal: hierarchy.setup_cpu_cgroup("normal", 1024).unwrap(),
+            cpu_background: hierarchy.setup_cpu_cgroup("background", 10).unwrap(),
+            cpuset_all,
+            cpuset_efficient,
+            cpuset_background_all,
+            cpuset_background_efficient,
+            memory_normal: Some(hierarchy.setup_memory_cgroup("normal", None).unwrap()),
+            memory_background: Some(
+                hierarchy
+                    .setup_memory_cgroup("background", Some(100))
+                    .unwrap(),
+            ),
+            verify_writes: true,
+        }
+    }
+
+    fn read(path: PathBuf) -> String {
+        std::fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn test_cgroup_v1_hierarchy() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+        let hierarchy = CgroupHierarchy::with_root(CgroupVersion::V1, root);
+        let mut ctx = setup_fake_hierarchy(&hierarchy, root);
+
+        assert_eq!(read(root.join("cpu/normal/cpu.shares")), "1024");
+        assert_eq!(read(root.join("cpu/background/cpu.shares")), "10");
+        assert!(!root.join("cpu/normal/cpu.weight").exists());
+        assert_eq!(
+            read(root.join("memory/background/memory.swappiness")),
+            "100"
+        );
+        assert!(!root.join("memory/normal/memory.swappiness").exists());
+        hierarchy.open_memory_cgroup("background").unwrap();
+        assert!(hierarchy.open_memory_cgroup("missing").is_err());
+
+        ctx.set_cpu_cgroup(ProcessId(123), CpuCgroup::Normal)
+            .unwrap();
+        ctx.set_cpu_cgroup(ProcessId(456), CpuCgroup::Background)
+            .unwrap();
+        assert_eq!(read(root.join("cpu/normal/cgroup.procs")), "123");
+        assert_eq!(read(root.join("cpu/background/cgroup.procs")), "456");
+
+        ctx.set_cpuset_cgroup(ThreadId(789), CpuCgroup::Normal, CpusetCgroup::Efficient)
+            .unwrap();
+        assert_eq!(read(root.join("cpuset/efficient/tasks")), "789");
+        assert_eq!(read(root.join("cpuset/efficient/cgroup.threads")), "");
+
+        hierarchy.set_cpuset_cpus("efficient", "0-3").unwrap();
+        assert_eq!(read(root.join("cpuset/efficient/cpus")), "0-3");
+    }
+
+    #[test]
+    fn test_cgroup_v2_hierarchy() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+        let hierarchy = CgroupHierarchy::with_root(CgroupVersion::V2, root);
+        let mut ctx = setup_fake_hierarchy(&hierarchy, root);
+
+        assert_eq!(read(root.join("normal/cpu.weight")), "100");
+        assert_eq!(read(root.join("background/cpu.weight")), "1");
+        assert!(!root.join("normal/cpu.shares").exists());
+        assert!(!root.join("background/cpu.shares").exists());
+        assert!(!root.join("background/memory.swappiness").exists());
+        hierarchy.set_cpu_shares("background", 2048).unwrap();
+        assert_eq!(read(root.join("background/cpu.weight")), "200");
+
+        ctx.set_cpu_cgroup(ProcessId(123), CpuCgroup::Normal)
+            .unwrap();
+        assert_eq!(read(root.join("normal/cgroup.procs")), "123");
+        ctx.set_cpu_cgroup(ProcessId(456), CpuCgroup::Background)
+            .unwrap();
+        assert_eq!(read(root.join("background/cgroup.procs")), "456");
+
+        // The threads are moved to the cpuset cgroups nested in the cpu cgroup of their process.
+        ctx.set_cpuset_cgroup(ThreadId(789), CpuCgroup::Normal, CpusetCgroup::Efficient)
+            .unwrap();
+        assert_eq!(read(root.join("normal/efficient/cgroup.threads")), "789");
+        assert_eq!(read(root.join("normal/efficient/tasks")), "");
+        ctx.set_cpuset_cgroup(ThreadId(790), CpuCgroup::Background, CpusetCgroup::All)
+            .unwrap();
+        assert_eq!(read(root.join("background/all/cgroup.threads")), "790");
+        ctx.set_cpuset_cgroup(
+            ThreadId(791),
+            CpuCgroup::Background,
+            CpusetCgroup::Efficient,
+        )
+        .unwrap();
+        assert_eq!(
+            read(root.join("background/efficient/cgroup.threads")),
+            "791"
+        );
+        assert_eq!(read(root.join("normal/all/cgroup.threads")), "");
+
+        hierarchy
+            .set_cpuset_cpus("background/efficient", "0-3")
+            .unwrap();
+        assert_eq!(read(root.join("background/efficient/cpuset.cpus")), "0-3");
+        assert!(!root.join("background/efficient/cpus").exists());
+    }
+
+    #[test]
+    fn test_cpu_shares_to_weight() {
+        assert_eq!(cpu_shares_to_weight(1024), 100);
+        assert_eq!(cpu_shares_to_weight(2048), 200);
+        assert_eq!(cpu_shares_to_weight(10), 1);
+        assert_eq!(cpu_shares_to_weight(2), 1);
+        assert_eq!(cpu_shares_to_weight(512), 50);
+        assert_eq!(cpu_shares_to_weight(u16::MAX), 6400);
     }
 }
@@ -13,23 +13,37 @@ mod storage;
 #[cfg(test)]
 mod test_utils;
 
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
 use std::fmt::Display;
 use std::io;
 use std::path::Path;
+use std::path::PathBuf;
 
 pub use cgroups::CgroupContext;
 pub use cgroups::CpuCgroup;
 pub use cgroups::CpusetCgroup;
+pub use cgroups::MemCgroup;
+use proc::load_process_euid;
+use proc::load_process_nice;
 use proc::load_process_timestamp;
+use proc::load_thread_ids;
 use proc::load_thread_timestamp;
+use proc::ProcessFd;
 use proc::ThreadChecker;
+pub use sched_attr::SavedSchedAttr;
 use sched_attr::SchedAttrContext;
 use sched_attr::UCLAMP_BOOSTED_MIN;
 pub use sched_attr::UCLAMP_MAX;
+use serde::Serialize;
+pub use storage::restorable::GrowthConfig;
 use storage::restorable::RestorableProcessMap;
 use storage::simple::SimpleProcessMap;
 use storage::ProcessContext;
 use storage::ProcessMap;
+pub use storage::StorageStats;
 use storage::ThreadMap;
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -37,6 +51,19 @@ pub type Result<T> = std::result::Result<T, Error>;
 const NUM_PROCESS_STATES: usize = ProcessState::Background as usize + 1;
 const NUM_THREAD_STATES: usize = ThreadState::Background as usize + 1;
 
+/// The range of the priority of SCHED_FIFO.
+const RT_PRIORITY_MIN: u32 = 1;
+const RT_PRIORITY_MAX: u32 = 99;
+/// The range of the nice value.
+const NICE_MIN: i32 = -20;
+const NICE_MAX: i32 = 19;
+
+/// The maximum number of scans of the threads in
+/// [SchedQosContext::set_process_state_with_default_threads].
+const MAX_THREAD_SCANS: usize = 5;
+/// The maximum number of [RtDenial]s kept until [SchedQosContext::take_rt_denials].
+const MAX_RT_DENIALS: usize = 64;
+
 /// Errors from schedqos crate.
 #[derive(Debug)]
 pub enum Error {
@@ -91,7 +118,7 @@ impl From<proc::Error> for Error {
 
 /// Scheduler QoS states of a process.
 #[repr(u8)]
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize)]
 pub enum ProcessState {
     Normal = 0,
     Background = 1,
@@ -111,7 +138,7 @@ impl TryFrom<u8> for ProcessState {
 
 /// Scheduler QoS states of a thread.
 #[repr(u8)]
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize)]
 pub enum ThreadState {
     UrgentBursty = 0,
     Urgent = 1,
@@ -146,6 +173,17 @@ pub struct Config {
     pub process_configs: [ProcessStateConfig; NUM_PROCESS_STATES],
     /// ThreadStateConfig for each thread QoS state
     pub thread_configs: [ThreadStateConfig; NUM_THREAD_STATES],
+    /// How to handle [ThreadStateConfig::rt_priority] out of the SCHED_FIFO range.
+    pub rt_priority_policy: RtPriorityPolicy,
+}
+
+/// Policy for [ThreadStateConfig::rt_priority] out of the SCHED_FIFO range (1..=99).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RtPriorityPolicy {
+    /// Fail the config validation.
+    Reject,
+    /// Clamp the priority into the range.
+    Clamp,
 }
 
 impl Config {
@@ -154,14 +192,18 @@ impl Config {
             // ProcessState::Normal
             ProcessStateConfig {
                 cpu_cgroup: CpuCgroup::Normal,
+                memcg: None,
                 allow_rt: true,
                 allow_all_cores: true,
+                default_thread_config: ThreadStateConfig::default(),
             },
             // Process:State::Background
             ProcessStateConfig {
                 cpu_cgroup: CpuCgroup::Background,
+                memcg: None,
                 allow_rt: false,
                 allow_all_cores: false,
+                default_thread_config: ThreadStateConfig::default(),
             },
         ]
     }
@@ -172,6 +214,7 @@ impl Config {
             ThreadStateConfig {
                 rt_priority: Some(8),
                 nice: -8,
+                nice_relative: false,
                 uclamp_min: UCLAMP_BOOSTED_MIN,
                 cpuset_cgroup: CpusetCgroup::All,
                 latency_sensitive: true,
@@ -179,6 +222,7 @@ impl Config {
             // ThreadState::Urgent
             ThreadStateConfig {
                 nice: -8,
+                nice_relative: false,
                 uclamp_min: UCLAMP_BOOSTED_MIN,
                 cpuset_cgroup: CpusetCgroup::All,
                 latency_sensitive: true,
@@ -216,10 +260,18 @@ impl Config {
 pub struct ProcessStateConfig {
     /// The cpu cgroup
     pub cpu_cgroup: CpuCgroup,
+    /// The memory cgroup. If this is None, the process stays in its current memory cgroup.
+    ///
+    /// Memory cgroups are opt-in, i.e. None by default. Setting this requires the file of the
+    /// memory cgroup in [CgroupContext].
+    pub memcg: Option<MemCgroup>,
     /// If RT is not allowed, threads with [ThreadStateConfig::rt_priority] use SCHED_OTHER.
     pub allow_rt: bool,
     /// If all core is not allowed, move all threads to the efficient cpuset cgroup.
     pub allow_all_cores: bool,
+    /// The settings a thread is reverted to when it stops being managed by
+    /// [SchedQosContext::remove_thread]. Threads which were never managed are not touched.
+    pub default_thread_config: ThreadStateConfig,
 }
 
 /// Detailed scheduler settings for a thread QoS state.
@@ -229,6 +281,12 @@ pub struct ThreadStateConfig {
     pub rt_priority: Option<u32>,
     /// The nice value
     pub nice: i32,
+    /// If true, [Self::nice] is added to the nice value of the main thread of the process instead
+    /// of being applied as is. The result is clamped to the valid nice range.
+    ///
+    /// The main thread itself should not use a relative nice, otherwise the base drifts every time
+    /// the state is applied.
+    pub nice_relative: bool,
     /// sched_attr.sched_util_min
     ///
     /// This must be smaller than or equal to 1024.
@@ -244,17 +302,45 @@ pub struct ThreadStateConfig {
 }
 
 impl ThreadStateConfig {
-    fn validate(&self) -> std::result::Result<(), &'static str> {
+    fn validate(
+        &mut self,
+        rt_priority_policy: RtPriorityPolicy,
+    ) -> std::result::Result<(), &'static str> {
         if self.uclamp_min > UCLAMP_MAX {
             return Err("uclamp_min is too big");
         }
+        if let Some(rt_priority) = self.rt_priority.as_mut() {
+            if !(RT_PRIORITY_MIN..=RT_PRIORITY_MAX).contains(rt_priority) {
+                match rt_priority_policy {
+                    RtPriorityPolicy::Reject => return Err("rt_priority is out of range"),
+                    RtPriorityPolicy::Clamp => {
+                        *rt_priority = (*rt_priority).clamp(RT_PRIORITY_MIN, RT_PRIORITY_MAX)
+                    }
+                }
+            }
+        }
         Ok(())
     }
 
+    /// Returns the config with [Self::nice] resolved to the absolute nice value for the threads of
+    /// the process.
+    fn resolve_nice(&self, process_id: ProcessId) -> proc::Result<Cow<'_, Self>> {
+        if !self.nice_relative {
+            return Ok(Cow::Borrowed(self));
+        }
+        let base_nice = load_process_nice(process_id)?;
+        Ok(Cow::Owned(ThreadStateConfig {
+            nice: (base_nice + self.nice).clamp(NICE_MIN, NICE_MAX),
+            nice_relative: false,
+            ..self.clone()
+        }))
+    }
+
     const fn default() -> Self {
         ThreadStateConfig {
             rt_priority: None,
             nice: 0,
+            nice_relative: false,
             uclamp_min: 0,
             cpuset_cgroup: CpusetCgroup::All,
             latency_sensitive: false,
@@ -262,6 +348,57 @@ impl ThreadStateConfig {
     }
 }
 
+/// The scheduling policy of a thread.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SchedPolicy {
+    /// SCHED_OTHER
+    Other,
+    /// SCHED_FIFO
+    Fifo,
+}
+
+/// The scheduler settings [SchedQosContext] applies to a managed thread, resolved from the states
+/// of the thread and its process and the config.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EffectiveThreadSettings {
+    pub process_state: ProcessState,
+    pub thread_state: ThreadState,
+    pub policy: SchedPolicy,
+    /// The RT priority. This is None unless [Self::policy] is [SchedPolicy::Fifo].
+    pub rt_priority: Option<u32>,
+    /// The nice value, with a relative nice resolved against the nice value of the process.
+    pub nice: i32,
+    pub uclamp_min: u32,
+    pub cpuset_cgroup: CpusetCgroup,
+    /// Whether the thread prefers idle cpus, including [SchedQosContext::set_prefer_idle_override].
+    pub prefer_idle: bool,
+}
+
+/// Restricts RT (SCHED_FIFO) to specific processes regardless of the config.
+///
+/// This is independent of [ProcessStateConfig::allow_rt]. A thread gets RT only if both its
+/// process state allows RT and its process is in the allowlist. The allowlist is only evaluated
+/// for threads requesting RT.
+///
+/// There is intentionally no allowlist by command name: any process can change its own comm with
+/// prctl(PR_SET_NAME), and the kernel truncates it to 15 bytes, so longer names never match.
+pub enum RtAllowlist {
+    /// Processes whose effective uid, as in "/proc/<pid>/status", is in the set. Unlike the comm,
+    /// an unprivileged process cannot change its uid to another one.
+    Uid(HashSet<u32>),
+    /// Processes for which the predicate returns true, e.g. for checking a pidfd.
+    Predicate(Box<dyn Fn(ProcessId) -> bool + Send>),
+}
+
+/// A thread which requested RT but got SCHED_OTHER because its process is not in the
+/// [RtAllowlist].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RtDenial {
+    pub process_id: ProcessId,
+    pub thread_id: ThreadId,
+    pub thread_state: ThreadState,
+}
+
 /// Wrap u32 PID with [ProcessId].
 ///
 /// Using u32 for both process id and thread id is confusing in this library.
@@ -275,6 +412,12 @@ impl From<u32> for ProcessId {
     }
 }
 
+impl From<ProcessId> for u32 {
+    fn from(process_id: ProcessId) -> Self {
+        process_id.0
+    }
+}
+
 /// Wrap u32 TID with [ThreadId].
 ///
 /// See [ProcessId] for the reason.
@@ -290,11 +433,75 @@ impl From<u32> for ThreadId {
     }
 }
 
+impl From<ThreadId> for u32 {
+    fn from(thread_id: ThreadId) -> Self {
+        thread_id.0
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
 pub struct ProcessKey {
     process_id: ProcessId,
     timestamp: u64,
 }
 
+impl ProcessKey {
+    pub fn process_id(&self) -> ProcessId {
+        self.process_id
+    }
+}
+
+/// States restored by [RestorableSchedQosContext::restore_from_file].
+#[derive(Default)]
+pub struct RestoreResult {
+    /// The processes whose states are re-applied.
+    ///
+    /// As with the [ProcessKey] returned by [SchedQosContext::set_process_state], the caller
+    /// should call [SchedQosContext::remove_process] when the process exits.
+    pub processes: Vec<ProcessKey>,
+    /// The number of threads whose states are re-applied.
+    pub n_threads: usize,
+    /// The number of processes and threads dropped because they are dead or their states failed
+    /// to be re-applied.
+    pub n_pruned: usize,
+}
+
+/// A process managed by [SchedQosContext] and the threads registered to it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProcessRegistration {
+    pub process_id: ProcessId,
+    pub state: ProcessState,
+    pub threads: Vec<(ThreadId, ThreadState)>,
+}
+
+/// A snapshot of the whole state table of [SchedQosContext].
+///
+/// Unlike the storage of the context, this is a stable format meant for crash reports and offline
+/// analysis. Processes and threads are sorted by id.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct QosTableSnapshot {
+    pub processes: Vec<ProcessSnapshot>,
+}
+
+/// A process in [QosTableSnapshot].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ProcessSnapshot {
+    pub process_id: u32,
+    /// The starttime of the process when it was registered.
+    pub timestamp: u64,
+    pub state: ProcessState,
+    pub threads: Vec<ThreadSnapshot>,
+}
+
+/// A thread in [ProcessSnapshot].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ThreadSnapshot {
+    pub thread_id: u32,
+    /// The starttime of the thread when it was registered.
+    pub timestamp: u64,
+    pub state: ThreadState,
+}
+
 pub type SimpleSchedQosContext = SchedQosContext<SimpleProcessMap>;
 pub type RestorableSchedQosContext = SchedQosContext<RestorableProcessMap>;
 
@@ -302,6 +509,23 @@ pub struct SchedQosContext<PM: ProcessMap> {
     config: Config,
     sched_attr_context: SchedAttrContext,
     process_map: PM,
+    /// Frozen registered processes and their states before freezing.
+    ///
+    /// This is not persisted to the process map storage.
+    frozen_processes: HashMap<ProcessId, ProcessState>,
+    /// Overrides [ThreadStateConfig::latency_sensitive] of every thread state if set.
+    prefer_idle_override: Option<bool>,
+    /// Only processes in the allowlist get RT if set.
+    rt_allowlist: Option<RtAllowlist>,
+    /// Threads refused RT by [Self::rt_allowlist] since the last [Self::take_rt_denials].
+    rt_denials: VecDeque<RtDenial>,
+    /// The procfs directory containing the latency_sensitive files. Tests replace this with a
+    /// fake directory.
+    proc_root: PathBuf,
+    /// Called between loading the process timestamp and writing the cpu cgroup in
+    /// [Self::set_process_state]. Tests use this to reuse the pid of the process in between.
+    #[cfg(test)]
+    before_cgroup_write: Option<Box<dyn FnMut(ProcessId) + Send>>,
 }
 
 impl SimpleSchedQosContext {
@@ -312,21 +536,73 @@ impl SimpleSchedQosContext {
 
 impl RestorableSchedQosContext {
     pub fn new_file(config: Config, path: &Path) -> Result<Self> {
-        let storage = RestorableProcessMap::new(path).map_err(Error::Storage)?;
+        Self::new_file_with_config(config, path, GrowthConfig::default())
+    }
+
+    pub fn new_file_with_config(
+        config: Config,
+        path: &Path,
+        growth_config: GrowthConfig,
+    ) -> Result<Self> {
+        let storage =
+            RestorableProcessMap::new_with_config(path, growth_config).map_err(Error::Storage)?;
         Self::new(config, storage)
     }
 
     pub fn load_from_file(config: Config, path: &Path) -> Result<Self> {
-        let storage = RestorableProcessMap::load(path).map_err(Error::Storage)?;
+        Self::load_from_file_with_config(config, path, GrowthConfig::default())
+    }
+
+    pub fn load_from_file_with_config(
+        config: Config,
+        path: &Path,
+        growth_config: GrowthConfig,
+    ) -> Result<Self> {
+        let storage =
+            RestorableProcessMap::load_with_config(path, growth_config).map_err(Error::Storage)?;
         Self::new(config, storage)
     }
+
+    /// Load the file and re-apply the loaded states to the processes and threads.
+    ///
+    /// This is for restarting the owner of the context. The kernel settings of the processes
+    /// and threads which are still alive are made consistent with the loaded map again.
+    pub fn restore_from_file(config: Config, path: &Path) -> Result<(Self, RestoreResult)> {
+        Self::restore_from_file_with_config(config, path, GrowthConfig::default())
+    }
+
+    /// [Self::restore_from_file] with the storage growing and shrinking as `growth_config`.
+    pub fn restore_from_file_with_config(
+        config: Config,
+        path: &Path,
+        growth_config: GrowthConfig,
+    ) -> Result<(Self, RestoreResult)> {
+        let mut ctx = Self::load_from_file_with_config(config, path, growth_config)?;
+        let mut result = ctx.reapply_states();
+        result.n_pruned += ctx.process_map.n_pruned_on_load();
+        Ok((ctx, result))
+    }
 }
 
 impl<PM: ProcessMap> SchedQosContext<PM> {
-    fn new(config: Config, process_map: PM) -> Result<Self> {
-        for thread_config in &config.thread_configs {
+    fn new(mut config: Config, process_map: PM) -> Result<Self> {
+        for process_config in &mut config.process_configs {
+            process_config
+                .default_thread_config
+                .validate(config.rt_priority_policy)
+                .map_err(|e| Error::Config("process validation", e))?;
+            if let Some(memcg) = process_config.memcg {
+                if !config.cgroup_context.has_memory_cgroup(memcg) {
+                    return Err(Error::Config(
+                        "process validation",
+                        "memcg is set without its cgroup",
+                    ));
+                }
+            }
+        }
+        for thread_config in &mut config.thread_configs {
             thread_config
-                .validate()
+                .validate(config.rt_priority_policy)
                 .map_err(|e| Error::Config("thread validation", e))?;
         }
 
@@ -334,29 +610,157 @@ impl<PM: ProcessMap> SchedQosContext<PM> {
             config,
             sched_attr_context: SchedAttrContext::new().map_err(Error::SchedAttr)?,
             process_map,
+            frozen_processes: HashMap::new(),
+            prefer_idle_override: None,
+            rt_allowlist: None,
+            rt_denials: VecDeque::new(),
+            proc_root: PathBuf::from("/proc"),
+            #[cfg(test)]
+            before_cgroup_write: None,
         })
     }
 
+    /// Set the state of the process.
+    ///
+    /// If the process is frozen, the state is recorded and applied on [Self::on_thaw] instead and
+    /// the process stays in [ProcessState::Background].
     pub fn set_process_state(
         &mut self,
         process_id: ProcessId,
         process_state: ProcessState,
     ) -> Result<Option<ProcessKey>> {
-        let process_config = &self.config.process_configs[process_state as usize];
+        let process_state = self.state_to_apply(process_id, process_state);
+        self.apply_process_state(process_id, process_state)
+    }
+
+    /// Set the state of all the registered processes, e.g. for a global policy change.
+    ///
+    /// Dead processes are dropped from the map. The result of each process is returned in the
+    /// same manner as [Self::set_process_state].
+    pub fn set_all_processes_state(
+        &mut self,
+        process_state: ProcessState,
+    ) -> Vec<(ProcessId, Result<()>)> {
+        let mut results = Vec::new();
+        for process_id in self.process_map.process_ids() {
+            let state = self.state_to_apply(process_id, process_state);
+            let result = self
+                .apply_process_state_without_compaction(process_id, state)
+                .map(|_| ());
+            results.push((process_id, result));
+        }
+        // Compact the map once after all the processes are updated.
+        self.process_map.compact();
+        results
+    }
+
+    /// Returns the state to apply to the process.
+    ///
+    /// If the process is frozen, `process_state` is recorded to be applied on [Self::on_thaw]
+    /// instead and [ProcessState::Background] is returned.
+    fn state_to_apply(
+        &mut self,
+        process_id: ProcessId,
+        process_state: ProcessState,
+    ) -> ProcessState {
+        if let Some(pre_freeze_state) = self.frozen_processes.get_mut(&process_id) {
+            *pre_freeze_state = process_state;
+            ProcessState::Background
+        } else {
+            process_state
+        }
+    }
+
+    /// Move the process to [ProcessState::Background] because it is frozen.
+    ///
+    /// The state of the process before freezing is recorded and restored by [Self::on_thaw].
+    /// Freezing an already frozen process keeps the originally recorded state. Processes which
+    /// are not registered are not managed, so freezing them does nothing.
+    pub fn on_freeze(&mut self, process_id: ProcessId) -> Result<Option<ProcessKey>> {
+        if !self.frozen_processes.contains_key(&process_id) {
+            let Some(process) = self.process_map.get_process(process_id) else {
+                return Ok(None);
+            };
+            let pre_freeze_state = process.state();
+            drop(process);
+            self.frozen_processes.insert(process_id, pre_freeze_state);
+        }
+        self.apply_process_state(process_id, ProcessState::Background)
+    }
+
+    /// Restore the state of the process recorded by [Self::on_freeze].
+    ///
+    /// `restore` is applied if no state was recorded but the process is registered, e.g. the
+    /// context was restored from a file after the process was frozen. Thawing a process which is
+    /// not registered does nothing.
+    pub fn on_thaw(
+        &mut self,
+        process_id: ProcessId,
+        restore: ProcessState,
+    ) -> Result<Option<ProcessKey>> {
+        let process_state = match self.frozen_processes.remove(&process_id) {
+            Some(pre_freeze_state) => pre_freeze_state,
+            None if self.process_map.get_process(process_id).is_some() => restore,
+            None => return Ok(None),
+        };
+        self.apply_process_state(process_id, process_state)
+    }
+
+    fn apply_process_state(
+        &mut self,
+        process_id: ProcessId,
+        process_state: ProcessState,
+    ) -> Result<Option<ProcessKey>> {
+        let result = self.apply_process_state_without_compaction(process_id, process_state);
+        self.process_map.compact();
+        result
+    }
 
-        let timestamp = match load_process_timestamp(process_id) {
+    fn apply_process_state_without_compaction(
+        &mut self,
+        process_id: ProcessId,
+        process_state: ProcessState,
+    ) -> Result<Option<ProcessKey>> {
+        // Open the pidfd before loading the timestamp so that both refer to the same process.
+        let (process_fd, timestamp) = match ProcessFd::open(process_id)
+            .and_then(|process_fd| Ok((process_fd, load_process_timestamp(process_id)?)))
+        {
             Err(proc::Error::NotFound) => {
-                self.process_map.remove_process(process_id, None);
-                self.process_map.compact();
+                self.forget_process(process_id);
                 return Err(Error::ProcessNotFound);
             }
             other => other?,
         };
 
-        self.config
-            .cgroup_context
-            .set_cpu_cgroup(process_id, process_config.cpu_cgroup)
-            .map_err(|e| Error::Cgroup(process_config.cpu_cgroup.name(), e))?;
+        if self.process_map.is_full() && self.process_map.get_process(process_id).is_none() {
+            return Err(Error::Storage(storage::restorable::Error::CapacityExceeded));
+        }
+
+        #[cfg(test)]
+        if let Some(hook) = self.before_cgroup_write.as_mut() {
+            hook(process_id);
+        }
+
+        // The process may have died and its pid been reused since the timestamp was loaded.
+        // Re-verify the process right before the cgroup write not to move another process. The
+        // timestamp is compared if pidfd is not supported, but it can miss a pid reused within a
+        // clock tick.
+        let is_same_process = match process_fd {
+            Some(process_fd) => process_fd.is_alive(),
+            None => {
+                matches!(load_process_timestamp(process_id), Ok(current) if current == timestamp)
+            }
+        };
+        if !is_same_process {
+            self.forget_process(process_id);
+            return Err(Error::ProcessNotFound);
+        }
+
+        self.apply_process_cgroups(process_id, process_state)?;
+
+        let process_config = &self.config.process_configs[process_state as usize];
+        // Evaluated lazily for the first thread requesting RT.
+        let mut is_rt_allowlisted = None;
 
         // Update the timestamp to the latest one. Even if there are obsolete threads in the
         // process context, those will be drained below.
@@ -392,18 +796,37 @@ impl<PM: ProcessMap> SchedQosContext<PM> {
             }
             let thread_config = &self.config.thread_configs[thread.state as usize];
             if thread_config.rt_priority.is_some() {
+                let mut allow_rt = process_config.allow_rt;
+                if allow_rt
+                    && !*is_rt_allowlisted.get_or_insert_with(|| {
+                        is_in_rt_allowlist(&self.rt_allowlist, &self.proc_root, process_id)
+                    })
+                {
+                    record_rt_denial(&mut self.rt_denials, process_id, *thread_id, thread.state);
+                    allow_rt = false;
+                }
                 // Ignore the error. There is rare cases that the thread die after the
                 // timestamp check above.
-                if let Err(e) = self.sched_attr_context.set_thread_sched_attr(
-                    *thread_id,
-                    thread_config,
-                    process_config.allow_rt,
-                ) {
-                    result = Err(Error::SchedAttr(e));
+                match thread_config.resolve_nice(process_id) {
+                    Ok(thread_config) => {
+                        if let Err(e) = self.sched_attr_context.set_thread_sched_attr(
+                            *thread_id,
+                            &thread_config,
+                            allow_rt,
+                        ) {
+                            result = Err(Error::SchedAttr(e));
+                        }
+                    }
+                    Err(proc::Error::NotFound) => {}
+                    Err(e) => result = Err(Error::Proc(e)),
                 }
             }
 
-            if thread_config.cpuset_cgroup != CpusetCgroup::Efficient {
+            // Threads in the efficient cpuset cgroup stay there unless moving the process to its cpu
+            // cgroup moved them out of the nested cpuset cgroups.
+            if thread_config.cpuset_cgroup != CpusetCgroup::Efficient
+                || self.config.cgroup_context.has_nested_cpusets()
+            {
                 let cpuset_cgroup = if process_config.allow_all_cores {
                     thread_config.cpuset_cgroup
                 } else {
@@ -411,65 +834,163 @@ impl<PM: ProcessMap> SchedQosContext<PM> {
                 };
                 // Ignore the error. There is rare cases that the thread die after the
                 // timestamp check above.
-                if let Err(e) = self
-                    .config
-                    .cgroup_context
-                    .set_cpuset_cgroup(*thread_id, cpuset_cgroup)
-                {
+                if let Err(e) = self.config.cgroup_context.set_cpuset_cgroup(
+                    *thread_id,
+                    process_config.cpu_cgroup,
+                    cpuset_cgroup,
+                ) {
                     result = Err(Error::Cgroup(cpuset_cgroup.name(), e));
                 }
             }
             true
         });
 
-        drop(process);
-        self.process_map.compact();
+        result
+    }
+
+    /// Drop the process whose pid is dead or reused from the process map.
+    ///
+    /// The map is not compacted.
+    fn forget_process(&mut self, process_id: ProcessId) {
+        self.process_map.remove_process(process_id, None);
+        self.frozen_processes.remove(&process_id);
+    }
+
+    fn apply_process_cgroups(
+        &mut self,
+        process_id: ProcessId,
+        process_state: ProcessState,
+    ) -> Result<()> {
+        let process_config = &self.config.process_configs[process_state as usize];
+
+        self.config
+            .cgroup_context
+            .set_cpu_cgroup(process_id, process_config.cpu_cgroup)
+            .map_err(|e| Error::Cgroup(process_config.cpu_cgroup.name(), e))?;
+
+        if let Some(memcg) = process_config.memcg {
+            self.config
+                .cgroup_context
+                .set_memory_cgroup(process_id, memcg)
+                .map_err(|e| Error::Cgroup(memcg.name(), e))?;
+        }
+
+        Ok(())
+    }
+
+    /// Re-apply the states in the process map to the processes and threads.
+    ///
+    /// Processes and threads which are dead or whose states fail to be applied are removed from
+    /// the map.
+    fn reapply_states(&mut self) -> RestoreResult {
+        let mut result = RestoreResult::default();
+        for registration in self.registrations() {
+            let process_id = registration.process_id;
+            let Some(process) = self.process_map.get_process(process_id) else {
+                continue;
+            };
+            let timestamp = process.timestamp();
+            drop(process);
+
+            let is_alive = matches!(
+                load_process_timestamp(process_id),
+                Ok(current) if current == timestamp
+            );
+            if !is_alive
+                || self
+                    .apply_process_cgroups(process_id, registration.state)
+                    .is_err()
+            {
+                self.process_map.remove_process(process_id, Some(timestamp));
+                result.n_pruned += registration.threads.len() + 1;
+                continue;
+            }
 
+            for (thread_id, thread_state) in registration.threads {
+                if self
+                    .apply_thread_state(process_id, thread_id, registration.state, thread_state)
+                    .is_ok()
+                {
+                    result.n_threads += 1;
+                } else {
+                    if let Some(mut process) = self.process_map.get_process(process_id) {
+                        process.thread_map().remove_thread(thread_id);
+                    }
+                    result.n_pruned += 1;
+                }
+            }
+            result.processes.push(ProcessKey {
+                process_id,
+                timestamp,
+            });
+        }
+        self.process_map.compact();
         result
     }
 
+    /// Returns the [ProcessKey] of the registered process.
+    ///
+    /// This is for whoever manages the process on behalf of another party and needs to stop
+    /// managing it before it exits, in addition to the owner of the [ProcessKey] returned by
+    /// [Self::set_process_state].
+    pub fn process_key(&mut self, process_id: ProcessId) -> Option<ProcessKey> {
+        let process = self.process_map.get_process(process_id)?;
+        Some(ProcessKey {
+            process_id,
+            timestamp: process.timestamp(),
+        })
+    }
+
     /// Stop managing QoS state associated with the given [ProcessKey].
     pub fn remove_process(&mut self, process_key: ProcessKey) {
         self.process_map
             .remove_process(process_key.process_id, Some(process_key.timestamp));
         self.process_map.compact();
+        // Keep the record if the key is stale and a new process reuses the id.
+        if self
+            .process_map
+            .get_process(process_key.process_id)
+            .is_none()
+        {
+            self.frozen_processes.remove(&process_key.process_id);
+        }
     }
 
-    pub fn set_thread_state(
-        &mut self,
-        process_id: ProcessId,
-        thread_id: ThreadId,
-        thread_state: ThreadState,
-    ) -> Result<()> {
+    /// Stop managing QoS state of the thread.
+    ///
+    /// If the thread is still alive, its scheduler settings, cpuset and latency_sensitive are
+    /// reverted to [ProcessStateConfig::default_thread_config] of the process state. Returns
+    /// [Error::ThreadNotFound] without touching the thread if it is not managed.
+    pub fn remove_thread(&mut self, process_id: ProcessId, thread_id: ThreadId) -> Result<()> {
         let Some(mut process) = self.process_map.get_process(process_id) else {
             return Err(Error::ProcessNotRegistered);
         };
+        if !process.thread_map().contains_thread(thread_id) {
+            return Err(Error::ThreadNotFound);
+        }
         let process_state = process.state();
+        process.thread_map().remove_thread(thread_id);
+        drop(process);
+        self.process_map.compact();
 
-        let timestamp = match load_thread_timestamp(process_id, thread_id) {
-            Err(proc::Error::NotFound) => {
-                process.thread_map().remove_thread(thread_id);
-                drop(process);
-                self.process_map.compact();
-                return Err(Error::ThreadNotFound);
-            }
+        match load_thread_timestamp(process_id, thread_id) {
+            Err(proc::Error::NotFound) => return Ok(()),
             other => other?,
         };
 
-        let mut thread_checker = ThreadChecker::new(process_id);
-        process
-            .thread_map()
-            .insert_or_update(thread_id, timestamp, thread_state, |thread_id| {
-                thread_checker.thread_exists(*thread_id)
-            });
-        drop(process);
-        self.process_map.compact();
-
         let process_config = &self.config.process_configs[process_state as usize];
-        let thread_config = &self.config.thread_configs[thread_state as usize];
+        let thread_config = match process_config
+            .default_thread_config
+            .resolve_nice(process_id)
+        {
+            Err(proc::Error::NotFound) => return Ok(()),
+            other => other?,
+        };
 
+        let allow_rt = process_config.allow_rt
+            && (thread_config.rt_priority.is_none() || self.is_rt_allowlisted(process_id));
         self.sched_attr_context
-            .set_thread_sched_attr(thread_id, thread_config, process_config.allow_rt)
+            .set_thread_sched_attr(thread_id, &thread_config, allow_rt)
             .map_err(Error::SchedAttr)?;
 
         let cpuset_cgroup = if process_config.allow_all_cores {
@@ -479,58 +1000,475 @@ impl<PM: ProcessMap> SchedQosContext<PM> {
         };
         self.config
             .cgroup_context
-            .set_cpuset_cgroup(thread_id, cpuset_cgroup)
+            .set_cpuset_cgroup(thread_id, process_config.cpu_cgroup, cpuset_cgroup)
             .map_err(|e| Error::Cgroup(cpuset_cgroup.name(), e))?;
 
-        // Apply latency sensitive. Latency_sensitive will prefer idle cores.
-        // This is a patch not yet in upstream(http://crrev/c/2981472)
-        let latency_sensitive_file = format!(
-            "/proc/{}/task/{}/latency_sensitive",
-            process_id.0, thread_id.0
-        );
-        if std::path::Path::new(&latency_sensitive_file).exists() {
-            let value = if thread_config.latency_sensitive {
-                b"1"
-            } else {
-                b"0"
+        self.write_latency_sensitive(process_id, thread_id, thread_config.latency_sensitive)
+    }
+
+    /// Returns the processes and threads currently managed by the context.
+    pub fn registrations(&mut self) -> Vec<ProcessRegistration> {
+        let mut registrations = Vec::new();
+        for process_id in self.process_map.process_ids() {
+            let Some(mut process) = self.process_map.get_process(process_id) else {
+                continue;
             };
-            std::fs::write(&latency_sensitive_file, value).map_err(Error::LatencySensitive)?;
+            let mut threads = Vec::new();
+            process.thread_map().retain_threads(|thread_id, thread| {
+                threads.push((*thread_id, thread.state));
+                true
+            });
+            registrations.push(ProcessRegistration {
+                process_id,
+                state: process.state(),
+                threads,
+            });
         }
+        registrations
+    }
 
-        Ok(())
+    /// Returns a snapshot of the processes and threads currently managed by the context, including
+    /// their timestamps.
+    pub fn export_table(&self) -> QosTableSnapshot {
+        let mut processes = Vec::new();
+        for process_id in self.process_map.process_ids() {
+            let Some(process) = self.process_map.get_process_entry(process_id) else {
+                continue;
+            };
+            let mut threads = Vec::new();
+            self.process_map
+                .for_each_thread(process_id, |thread_id, thread| {
+                    threads.push(ThreadSnapshot {
+                        thread_id: thread_id.0,
+                        timestamp: thread.timestamp,
+                        state: thread.state,
+                    });
+                });
+            threads.sort_by_key(|thread| thread.thread_id);
+            processes.push(ProcessSnapshot {
+                process_id: process_id.0,
+                timestamp: process.timestamp,
+                state: process.state,
+                threads,
+            });
+        }
+        processes.sort_by_key(|process| process.process_id);
+        QosTableSnapshot { processes }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use std::collections::HashSet;
+    /// Returns the usage of the process map storage.
+    pub fn storage_stats(&self) -> StorageStats {
+        self.process_map.stats()
+    }
 
-    use super::*;
-    use crate::test_utils::*;
+    /// Compact the process map storage regardless of its compaction threshold.
+    ///
+    /// e.g. this shrinks the storage file before the owner of the context shuts down.
+    pub fn force_compact(&mut self) {
+        self.process_map.force_compact();
+    }
 
-    #[test]
-    fn test_process_state_conversion() {
-        for state in [ProcessState::Normal, ProcessState::Background] {
-            assert_eq!(state, ProcessState::try_from(state as u8).unwrap());
+    /// Override [ThreadStateConfig::latency_sensitive] of every thread state.
+    ///
+    /// e.g. this allows to stop preferring idle cpus while on battery. `None` restores the
+    /// per-state configs. The new value is re-applied to all the managed threads. Errors do not
+    /// stop updating the other threads and the last error is returned.
+    pub fn set_prefer_idle_override(&mut self, value: Option<bool>) -> Result<()> {
+        if self.prefer_idle_override == value {
+            return Ok(());
         }
+        self.prefer_idle_override = value;
 
-        assert!(ProcessState::try_from(NUM_PROCESS_STATES as u8).is_err());
+        let mut result = Ok(());
+        for registration in self.registrations() {
+            for (thread_id, thread_state) in registration.threads {
+                if let Err(e) =
+                    self.apply_latency_sensitive(registration.process_id, thread_id, thread_state)
+                {
+                    result = Err(e);
+                }
+            }
+        }
+        result
     }
 
-    #[test]
-    fn test_thread_state_conversion() {
-        for state in [
-            ThreadState::UrgentBursty,
-            ThreadState::Urgent,
-            ThreadState::Balanced,
-            ThreadState::Eco,
-            ThreadState::Utility,
-            ThreadState::Background,
-        ] {
-            assert_eq!(state, ThreadState::try_from(state as u8).unwrap());
+    /// Restrict RT to the processes in `allowlist`, or lift the restriction with `None`.
+    ///
+    /// The new allowlist is re-applied to all the managed threads. Errors do not stop updating
+    /// the other threads and the last error is returned.
+    pub fn set_rt_allowlist(&mut self, allowlist: Option<RtAllowlist>) -> Result<()> {
+        self.rt_allowlist = allowlist;
+
+        let mut result = Ok(());
+        for registration in self.registrations() {
+            for (thread_id, thread_state) in registration.threads {
+                if let Err(e) = self.apply_thread_state(
+                    registration.process_id,
+                    thread_id,
+                    registration.state,
+                    thread_state,
+                ) {
+                    result = Err(e);
+                }
+            }
         }
+        result
+    }
 
-        assert!(ThreadState::try_from(NUM_THREAD_STATES as u8).is_err());
+    /// Returns the threads refused RT by the [RtAllowlist] since the last call, oldest first.
+    ///
+    /// Only the latest denials are kept if there are many.
+    pub fn take_rt_denials(&mut self) -> Vec<RtDenial> {
+        self.rt_denials.drain(..).collect()
+    }
+
+    fn is_rt_allowlisted(&self, process_id: ProcessId) -> bool {
+        is_in_rt_allowlist(&self.rt_allowlist, &self.proc_root, process_id)
+    }
+
+    /// Returns the scheduler settings applied to the thread, computed from the recorded states and
+    /// the config instead of reading them back from the kernel.
+    ///
+    /// Returns None if the thread is not managed or the process has exited.
+    pub fn effective_thread_settings(
+        &self,
+        process_id: ProcessId,
+        thread_id: ThreadId,
+    ) -> Option<EffectiveThreadSettings> {
+        let process_state = self.process_map.get_process_entry(process_id)?.state;
+        let mut thread_state = None;
+        self.process_map.for_each_thread(process_id, |id, thread| {
+            if *id == thread_id {
+                thread_state = Some(thread.state);
+            }
+        });
+        let thread_state = thread_state?;
+
+        let process_config = &self.config.process_configs[process_state as usize];
+        let thread_config = self.config.thread_configs[thread_state as usize]
+            .resolve_nice(process_id)
+            .ok()?;
+        let rt_priority = thread_config
+            .rt_priority
+            .filter(|_| process_config.allow_rt && self.is_rt_allowlisted(process_id));
+        let cpuset_cgroup = if process_config.allow_all_cores {
+            thread_config.cpuset_cgroup
+        } else {
+            CpusetCgroup::Efficient
+        };
+        Some(EffectiveThreadSettings {
+            process_state,
+            thread_state,
+            policy: if rt_priority.is_some() {
+                SchedPolicy::Fifo
+            } else {
+                SchedPolicy::Other
+            },
+            rt_priority,
+            nice: thread_config.nice,
+            uclamp_min: thread_config.uclamp_min,
+            cpuset_cgroup,
+            prefer_idle: self
+                .prefer_idle_override
+                .unwrap_or(thread_config.latency_sensitive),
+        })
+    }
+
+    /// Applies the scheduler settings of the thread state to a thread which is not managed by the
+    /// context.
+    ///
+    /// Unlike [Self::set_thread_state], the thread is not registered and neither the thread nor
+    /// its process is moved between cgroups. Real-time priority is never applied. Returns the
+    /// previous settings to revert by [Self::restore_unmanaged_thread_sched_attr].
+    pub fn set_unmanaged_thread_sched_attr(
+        &self,
+        process_id: ProcessId,
+        thread_id: ThreadId,
+        thread_state: ThreadState,
+    ) -> Result<SavedSchedAttr> {
+        let thread_config = self.thread_config(thread_state).resolve_nice(process_id)?;
+        let saved = self
+            .sched_attr_context
+            .save_thread_sched_attr(thread_id)
+            .map_err(Error::SchedAttr)?;
+        self.sched_attr_context
+            .set_thread_sched_attr(thread_id, &thread_config, false)
+            .map_err(Error::SchedAttr)?;
+        Ok(saved)
+    }
+
+    /// Reverts the settings saved by [Self::set_unmanaged_thread_sched_attr].
+    pub fn restore_unmanaged_thread_sched_attr(
+        &self,
+        thread_id: ThreadId,
+        saved: &SavedSchedAttr,
+    ) -> Result<()> {
+        self.sched_attr_context
+            .restore_thread_sched_attr(thread_id, saved)
+            .map_err(Error::SchedAttr)
+    }
+
+    /// Returns the config of the thread state.
+    pub fn thread_config(&self, thread_state: ThreadState) -> &ThreadStateConfig {
+        &self.config.thread_configs[thread_state as usize]
+    }
+
+    /// Replace the config of the thread state at runtime.
+    ///
+    /// The new config is re-applied to all the managed threads in the state. Errors do not stop
+    /// updating the other threads and the last error is returned.
+    pub fn set_thread_config(
+        &mut self,
+        thread_state: ThreadState,
+        mut thread_config: ThreadStateConfig,
+    ) -> Result<()> {
+        thread_config
+            .validate(self.config.rt_priority_policy)
+            .map_err(|e| Error::Config("thread validation", e))?;
+        self.config.thread_configs[thread_state as usize] = thread_config;
+
+        let mut result = Ok(());
+        for registration in self.registrations() {
+            for (thread_id, state) in registration.threads {
+                if state != thread_state {
+                    continue;
+                }
+                if let Err(e) = self.apply_thread_state(
+                    registration.process_id,
+                    thread_id,
+                    registration.state,
+                    state,
+                ) {
+                    result = Err(e);
+                }
+            }
+        }
+        result
+    }
+
+    pub fn set_thread_state(
+        &mut self,
+        process_id: ProcessId,
+        thread_id: ThreadId,
+        thread_state: ThreadState,
+    ) -> Result<()> {
+        let is_full = self.process_map.is_full();
+        let Some(mut process) = self.process_map.get_process(process_id) else {
+            return Err(Error::ProcessNotRegistered);
+        };
+        let process_state = process.state();
+
+        let timestamp = match load_thread_timestamp(process_id, thread_id) {
+            Err(proc::Error::NotFound) => {
+                process.thread_map().remove_thread(thread_id);
+                drop(process);
+                self.process_map.compact();
+                return Err(Error::ThreadNotFound);
+            }
+            other => other?,
+        };
+
+        if is_full && !process.thread_map().contains_thread(thread_id) {
+            return Err(Error::Storage(storage::restorable::Error::CapacityExceeded));
+        }
+
+        let mut thread_checker = ThreadChecker::new(process_id);
+        process
+            .thread_map()
+            .insert_or_update(thread_id, timestamp, thread_state, |thread_id| {
+                thread_checker.thread_exists(*thread_id)
+            });
+        drop(process);
+        self.process_map.compact();
+
+        self.apply_thread_state(process_id, thread_id, process_state, thread_state)
+    }
+
+    /// Set the state of the process and manage all of its threads which are not managed yet with
+    /// `default_thread_state`.
+    ///
+    /// This is for processes which are registered after their threads are spawned. The threads
+    /// are listed from "/proc/<pid>/task", which is scanned again while new threads show up
+    /// because threads spawned during the scan may be missed. The number of scans is bounded so
+    /// that a process spawning threads endlessly does not block this.
+    pub fn set_process_state_with_default_threads(
+        &mut self,
+        process_id: ProcessId,
+        process_state: ProcessState,
+        default_thread_state: ThreadState,
+    ) -> Result<Option<ProcessKey>> {
+        let process_key = self.set_process_state(process_id, process_state)?;
+
+        let mut visited_threads = HashSet::new();
+        let mut result = Ok(process_key);
+        for _ in 0..MAX_THREAD_SCANS {
+            let thread_ids = match load_thread_ids(process_id) {
+                Err(proc::Error::NotFound) => return Err(Error::ProcessNotFound),
+                other => other?,
+            };
+            let new_threads: Vec<_> = thread_ids
+                .into_iter()
+                .filter(|thread_id| visited_threads.insert(*thread_id))
+                .collect();
+            if new_threads.is_empty() {
+                break;
+            }
+            for thread_id in new_threads {
+                let is_managed = self
+                    .process_map
+                    .get_process(process_id)
+                    .map(|mut process| process.thread_map().contains_thread(thread_id))
+                    .unwrap_or(false);
+                if is_managed {
+                    continue;
+                }
+                match self.set_thread_state(process_id, thread_id, default_thread_state) {
+                    // The thread has exited after the scan.
+                    Ok(()) | Err(Error::ThreadNotFound) => {}
+                    Err(e) => result = Err(e),
+                }
+            }
+        }
+        result
+    }
+
+    fn apply_thread_state(
+        &mut self,
+        process_id: ProcessId,
+        thread_id: ThreadId,
+        process_state: ProcessState,
+        thread_state: ThreadState,
+    ) -> Result<()> {
+        let process_config = &self.config.process_configs[process_state as usize];
+        let thread_config =
+            match self.config.thread_configs[thread_state as usize].resolve_nice(process_id) {
+                Err(proc::Error::NotFound) => return Err(Error::ProcessNotFound),
+                other => other?,
+            };
+
+        let mut allow_rt = process_config.allow_rt;
+        if allow_rt && thread_config.rt_priority.is_some() && !self.is_rt_allowlisted(process_id) {
+            record_rt_denial(&mut self.rt_denials, process_id, thread_id, thread_state);
+            allow_rt = false;
+        }
+        self.sched_attr_context
+            .set_thread_sched_attr(thread_id, &thread_config, allow_rt)
+            .map_err(Error::SchedAttr)?;
+
+        let cpuset_cgroup = if process_config.allow_all_cores {
+            thread_config.cpuset_cgroup
+        } else {
+            CpusetCgroup::Efficient
+        };
+        self.config
+            .cgroup_context
+            .set_cpuset_cgroup(thread_id, process_config.cpu_cgroup, cpuset_cgroup)
+            .map_err(|e| Error::Cgroup(cpuset_cgroup.name(), e))?;
+
+        self.apply_latency_sensitive(process_id, thread_id, thread_state)
+    }
+
+    fn apply_latency_sensitive(
+        &self,
+        process_id: ProcessId,
+        thread_id: ThreadId,
+        thread_state: ThreadState,
+    ) -> Result<()> {
+        let latency_sensitive = self
+            .prefer_idle_override
+            .unwrap_or(self.config.thread_configs[thread_state as usize].latency_sensitive);
+        self.write_latency_sensitive(process_id, thread_id, latency_sensitive)
+    }
+
+    fn write_latency_sensitive(
+        &self,
+        process_id: ProcessId,
+        thread_id: ThreadId,
+        latency_sensitive: bool,
+    ) -> Result<()> {
+        // Apply latency sensitive. Latency_sensitive will prefer idle cores.
+        // This is a patch not yet in upstream(http://crrev/c/2981472)
+        let latency_sensitive_file = self
+            .proc_root
+            .join(process_id.0.to_string())
+            .join("task")
+            .join(thread_id.0.to_string())
+            .join("latency_sensitive");
+        if latency_sensitive_file.exists() {
+            let value = if latency_sensitive { b"1" } else { b"0" };
+            std::fs::write(&latency_sensitive_file, value).map_err(Error::LatencySensitive)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns whether the process is in `allowlist`, or true if there is no allowlist.
+///
+/// A process whose uid cannot be read from `proc_root` is not in the allowlist.
+fn is_in_rt_allowlist(
+    allowlist: &Option<RtAllowlist>,
+    proc_root: &Path,
+    process_id: ProcessId,
+) -> bool {
+    match allowlist {
+        None => true,
+        Some(RtAllowlist::Uid(uids)) => {
+            matches!(load_process_euid(proc_root, process_id), Ok(uid) if uids.contains(&uid))
+        }
+        Some(RtAllowlist::Predicate(predicate)) => predicate(process_id),
+    }
+}
+
+/// Records that the thread was refused RT, dropping the oldest denial if there are too many.
+fn record_rt_denial(
+    rt_denials: &mut VecDeque<RtDenial>,
+    process_id: ProcessId,
+    thread_id: ThreadId,
+    thread_state: ThreadState,
+) {
+    if rt_denials.len() >= MAX_RT_DENIALS {
+        rt_denials.pop_front();
+    }
+    rt_denials.push_back(RtDenial {
+        process_id,
+        thread_id,
+        thread_state,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn test_process_state_conversion() {
+        for state in [ProcessState::Normal, ProcessState::Background] {
+            assert_eq!(state, ProcessState::try_from(state as u8).unwrap());
+        }
+
+        assert!(ProcessState::try_from(NUM_PROCESS_STATES as u8).is_err());
+    }
+
+    #[test]
+    fn test_thread_state_conversion() {
+        for state in [
+            ThreadState::UrgentBursty,
+            ThreadState::Urgent,
+            ThreadState::Balanced,
+            ThreadState::Eco,
+            ThreadState::Utility,
+            ThreadState::Background,
+        ] {
+            assert_eq!(state, ThreadState::try_from(state as u8).unwrap());
+        }
+
+        assert!(ThreadState::try_from(NUM_THREAD_STATES as u8).is_err());
     }
 
     #[test]
@@ -542,17 +1480,22 @@ mod tests {
                 // ProcessState::Normal
                 ProcessStateConfig {
                     cpu_cgroup: CpuCgroup::Normal,
+                    memcg: None,
                     allow_rt: true,
                     allow_all_cores: true,
+                    default_thread_config: ThreadStateConfig::default(),
                 },
                 // Process:State::Background
                 ProcessStateConfig {
                     cpu_cgroup: CpuCgroup::Background,
+                    memcg: None,
                     allow_rt: false,
                     allow_all_cores: false,
+                    default_thread_config: ThreadStateConfig::default(),
                 },
             ],
             thread_configs: Config::default_thread_config(),
+            rt_priority_policy: RtPriorityPolicy::Reject,
         })
         .unwrap();
 
@@ -570,6 +1513,56 @@ mod tests {
             read_number(&mut cgroup_files.cpu_background),
             Some(process_id.0)
         );
+        // The memory cgroup is not changed if memcg is None.
+        assert_eq!(read_number(&mut cgroup_files.memory_normal), None);
+        assert_eq!(read_number(&mut cgroup_files.memory_background), None);
+    }
+
+    #[test]
+    fn test_set_process_state_memcg() {
+        let (cgroup_context, mut cgroup_files) = create_fake_cgroup_context_pair();
+        let mut process_configs = Config::default_process_config();
+        process_configs[ProcessState::Normal as usize].memcg = Some(MemCgroup::Normal);
+        process_configs[ProcessState::Background as usize].memcg = Some(MemCgroup::Background);
+        let mut ctx = SchedQosContext::new_simple(Config {
+            cgroup_context,
+            process_configs,
+            thread_configs: Config::default_thread_config(),
+            rt_priority_policy: RtPriorityPolicy::Reject,
+        })
+        .unwrap();
+
+        let process_id = ProcessId(std::process::id());
+        ctx.set_process_state(process_id, ProcessState::Normal)
+            .unwrap();
+        assert_eq!(
+            read_number(&mut cgroup_files.memory_normal),
+            Some(process_id.0)
+        );
+        assert_eq!(read_number(&mut cgroup_files.memory_background), None);
+
+        ctx.set_process_state(process_id, ProcessState::Background)
+            .unwrap();
+        assert_eq!(read_number(&mut cgroup_files.memory_normal), None);
+        assert_eq!(
+            read_number(&mut cgroup_files.memory_background),
+            Some(process_id.0)
+        );
+    }
+
+    #[test]
+    fn test_memcg_without_cgroup() {
+        let (mut cgroup_context, _files) = create_fake_cgroup_context_pair();
+        cgroup_context.memory_background = None;
+        let mut process_configs = Config::default_process_config();
+        process_configs[ProcessState::Background as usize].memcg = Some(MemCgroup::Background);
+        let ctx = SchedQosContext::new_simple(Config {
+            cgroup_context,
+            process_configs,
+            thread_configs: Config::default_thread_config(),
+            rt_priority_policy: RtPriorityPolicy::Reject,
+        });
+        assert!(matches!(ctx, Err(Error::Config(_, _))));
     }
 
     #[test]
@@ -604,17 +1597,22 @@ mod tests {
                 // ProcessState::Normal
                 ProcessStateConfig {
                     cpu_cgroup: CpuCgroup::Normal,
+                    memcg: None,
                     allow_rt: true,
                     allow_all_cores: true,
+                    default_thread_config: ThreadStateConfig::default(),
                 },
                 // Process:State::Background
                 ProcessStateConfig {
                     cpu_cgroup: CpuCgroup::Background,
+                    memcg: None,
                     allow_rt: false,
                     allow_all_cores: false,
+                    default_thread_config: ThreadStateConfig::default(),
                 },
             ],
             thread_configs,
+            rt_priority_policy: RtPriorityPolicy::Reject,
         })
         .unwrap();
 
@@ -688,6 +1686,52 @@ mod tests {
         assert!(!sched_attr_unmanaged.is_changed());
     }
 
+    #[test]
+    fn test_set_process_state_nested_cpusets() {
+        let (cgroup_context, mut cgroup_files) = create_fake_nested_cgroup_context_pair();
+        let mut thread_configs = Config::default_thread_config();
+        thread_configs[ThreadState::Utility as usize].cpuset_cgroup = CpusetCgroup::Efficient;
+        let mut ctx = SchedQosContext::new_simple(Config {
+            cgroup_context,
+            process_configs: Config::default_process_config(),
+            thread_configs,
+            rt_priority_policy: RtPriorityPolicy::Reject,
+        })
+        .unwrap();
+
+        let process_id = ProcessId(std::process::id());
+        ctx.set_process_state(process_id, ProcessState::Normal)
+            .unwrap();
+        let (thread_id, _thread) = spawn_thread_for_test();
+        ctx.set_thread_state(process_id, thread_id, ThreadState::Utility)
+            .unwrap();
+        assert_eq!(
+            read_number(&mut cgroup_files.cpuset_efficient),
+            Some(thread_id.0)
+        );
+
+        // Moving the process to another cpu cgroup moves the threads out of the nested cpuset
+        // cgroups. Threads in the efficient cpuset cgroup are moved back as well.
+        ctx.set_process_state(process_id, ProcessState::Background)
+            .unwrap();
+        assert_eq!(
+            read_number(&mut cgroup_files.cpuset_background_efficient),
+            Some(thread_id.0)
+        );
+        assert_eq!(read_number(&mut cgroup_files.cpuset_efficient), None);
+
+        ctx.set_process_state(process_id, ProcessState::Normal)
+            .unwrap();
+        assert_eq!(
+            read_number(&mut cgroup_files.cpuset_efficient),
+            Some(thread_id.0)
+        );
+        assert_eq!(
+            read_number(&mut cgroup_files.cpuset_background_efficient),
+            None
+        );
+    }
+
     #[test]
     fn test_set_process_state_invalid_process() {
         let (cgroup_context, _files) = create_fake_cgroup_context_pair();
@@ -695,6 +1739,7 @@ mod tests {
             cgroup_context,
             process_configs: Config::default_process_config(),
             thread_configs: Config::default_thread_config(),
+            rt_priority_policy: RtPriorityPolicy::Reject,
         })
         .unwrap();
 
@@ -709,25 +1754,120 @@ mod tests {
     }
 
     #[test]
-    fn test_set_process_state_for_new_process() {
-        let (cgroup_context, _files) = create_fake_cgroup_context_pair();
+    fn test_set_all_processes_state() {
+        let (cgroup_context, mut cgroup_files) = create_fake_cgroup_context_pair();
         let mut ctx = SchedQosContext::new_simple(Config {
             cgroup_context,
             process_configs: Config::default_process_config(),
             thread_configs: Config::default_thread_config(),
+            rt_priority_policy: RtPriorityPolicy::Reject,
         })
         .unwrap();
 
-        let process_id = ProcessId(std::process::id());
+        let (process_id1, _, _process1) = fork_process_for_test();
+        let (process_id2, _, _process2) = fork_process_for_test();
+        let (process_id_dead, _, process_dead) = fork_process_for_test();
+        for process_id in [process_id1, process_id2, process_id_dead] {
+            ctx.set_process_state(process_id, ProcessState::Normal)
+                .unwrap();
+        }
+        drain_file(&mut cgroup_files.cpu_normal);
+        drop(process_dead);
+
+        let results: HashMap<_, _> = ctx
+            .set_all_processes_state(ProcessState::Background)
+            .into_iter()
+            .collect();
+        assert_eq!(results.len(), 3);
+        assert!(results[&process_id1].is_ok());
+        assert!(results[&process_id2].is_ok());
+        assert!(matches!(
+            results[&process_id_dead],
+            Err(Error::ProcessNotFound)
+        ));
 
-        // First set_process_state() creates a new process context.
-        let process_key = ctx
-            .set_process_state(process_id, ProcessState::Normal)
-            .unwrap();
-        assert!(process_key.is_some());
-        let process_key = process_key.unwrap();
-        assert_eq!(process_key.process_id, process_id);
-        assert_eq!(ctx.process_map.len(), 1);
+        assert_eq!(
+            read_numbers(&mut cgroup_files.cpu_background).collect::<HashSet<_>>(),
+            HashSet::from([process_id1.0, process_id2.0])
+        );
+        assert_eq!(read_number(&mut cgroup_files.cpu_normal), None);
+
+        // The dead process is reaped.
+        let mut process_ids = ctx.process_map.process_ids();
+        process_ids.sort_by_key(|process_id| process_id.0);
+        let mut expected_process_ids = vec![process_id1, process_id2];
+        expected_process_ids.sort_by_key(|process_id| process_id.0);
+        assert_eq!(process_ids, expected_process_ids);
+        for process_id in expected_process_ids {
+            assert_eq!(
+                ctx.process_map.get_process(process_id).unwrap().state(),
+                ProcessState::Background
+            );
+        }
+    }
+
+    #[test]
+    fn test_set_process_state_pid_reused() {
+        let (cgroup_context, mut cgroup_files) = create_fake_cgroup_context_pair();
+        let mut ctx = SchedQosContext::new_simple(Config {
+            cgroup_context,
+            process_configs: Config::default_process_config(),
+            thread_configs: Config::default_thread_config(),
+            rt_priority_policy: RtPriorityPolicy::Reject,
+        })
+        .unwrap();
+
+        let (process_id, _, process) = fork_process_for_test();
+        ctx.set_process_state(process_id, ProcessState::Normal)
+            .unwrap();
+        assert_eq!(
+            read_number(&mut cgroup_files.cpu_normal),
+            Some(process_id.0)
+        );
+
+        // The process exits and another process takes over its pid after the timestamp is loaded.
+        let mut process = Some(process);
+        let reused_process = Arc::new(Mutex::new(None));
+        let reused_process_clone = reused_process.clone();
+        ctx.before_cgroup_write = Some(Box::new(move |process_id| {
+            drop(process.take());
+            *reused_process_clone.lock().unwrap() =
+                Some(fork_process_reusing_pid_for_test(process_id));
+        }));
+        assert!(matches!(
+            ctx.set_process_state(process_id, ProcessState::Background)
+                .err()
+                .unwrap(),
+            Error::ProcessNotFound
+        ));
+        assert!(reused_process.lock().unwrap().is_some());
+
+        // The new process is not moved and the dead one is dropped.
+        assert_eq!(read_number(&mut cgroup_files.cpu_background), None);
+        assert!(ctx.process_map.get_process(process_id).is_none());
+    }
+
+    #[test]
+    fn test_set_process_state_for_new_process() {
+        let (cgroup_context, _files) = create_fake_cgroup_context_pair();
+        let mut ctx = SchedQosContext::new_simple(Config {
+            cgroup_context,
+            process_configs: Config::default_process_config(),
+            thread_configs: Config::default_thread_config(),
+            rt_priority_policy: RtPriorityPolicy::Reject,
+        })
+        .unwrap();
+
+        let process_id = ProcessId(std::process::id());
+
+        // First set_process_state() creates a new process context.
+        let process_key = ctx
+            .set_process_state(process_id, ProcessState::Normal)
+            .unwrap();
+        assert!(process_key.is_some());
+        let process_key = process_key.unwrap();
+        assert_eq!(process_key.process_id, process_id);
+        assert_eq!(ctx.process_map.len(), 1);
 
         let process_key = ctx
             .set_process_state(process_id, ProcessState::Background)
@@ -758,6 +1898,7 @@ mod tests {
                 cgroup_context,
                 process_configs: Config::default_process_config(),
                 thread_configs: Config::default_thread_config(),
+                rt_priority_policy: RtPriorityPolicy::Reject,
             },
             &file_path,
         )
@@ -794,6 +1935,7 @@ mod tests {
             cgroup_context,
             process_configs: Config::default_process_config(),
             thread_configs: Config::default_thread_config(),
+            rt_priority_policy: RtPriorityPolicy::Reject,
         })
         .unwrap();
 
@@ -828,6 +1970,36 @@ mod tests {
         assert_eq!(ctx.process_map.len(), 3);
     }
 
+    #[test]
+    fn test_process_key() {
+        let (cgroup_context, _files) = create_fake_cgroup_context_pair();
+        let mut ctx = SchedQosContext::new_simple(Config {
+            cgroup_context,
+            process_configs: Config::default_process_config(),
+            thread_configs: Config::default_thread_config(),
+            rt_priority_policy: RtPriorityPolicy::Reject,
+        })
+        .unwrap();
+
+        let (process_id, _, process) = fork_process_for_test();
+        assert!(ctx.process_key(process_id).is_none());
+        let process_key = ctx
+            .set_process_state(process_id, ProcessState::Normal)
+            .unwrap()
+            .unwrap();
+        let another_process_key = ctx.process_key(process_id).unwrap();
+        assert_eq!(another_process_key, process_key);
+
+        ctx.remove_process(another_process_key);
+        assert_eq!(ctx.process_map.len(), 0);
+        assert!(ctx.process_key(process_id).is_none());
+
+        // The original key is stale after the process is removed.
+        drop(process);
+        ctx.remove_process(process_key);
+        assert_eq!(ctx.process_map.len(), 0);
+    }
+
     #[test]
     fn test_remove_process_compact() {
         let dir = tempfile::tempdir().unwrap();
@@ -838,6 +2010,7 @@ mod tests {
                 cgroup_context,
                 process_configs: Config::default_process_config(),
                 thread_configs: Config::default_thread_config(),
+                rt_priority_policy: RtPriorityPolicy::Reject,
             },
             &file_path,
         )
@@ -867,6 +2040,7 @@ mod tests {
             ThreadStateConfig {
                 rt_priority: Some(8),
                 nice: -8,
+                nice_relative: false,
                 uclamp_min: UCLAMP_BOOSTED_MIN,
                 cpuset_cgroup: CpusetCgroup::All,
                 latency_sensitive: true,
@@ -874,6 +2048,7 @@ mod tests {
             // ThreadState::Urgent
             ThreadStateConfig {
                 nice: -8,
+                nice_relative: false,
                 uclamp_min: UCLAMP_BOOSTED_MIN,
                 cpuset_cgroup: CpusetCgroup::All,
                 latency_sensitive: true,
@@ -909,17 +2084,22 @@ mod tests {
                 // ProcessState::Normal
                 ProcessStateConfig {
                     cpu_cgroup: CpuCgroup::Normal,
+                    memcg: None,
                     allow_rt: true,
                     allow_all_cores: true,
+                    default_thread_config: ThreadStateConfig::default(),
                 },
                 // Process:State::Background
                 ProcessStateConfig {
                     cpu_cgroup: CpuCgroup::Background,
+                    memcg: None,
                     allow_rt: false,
                     allow_all_cores: false,
+                    default_thread_config: ThreadStateConfig::default(),
                 },
             ],
             thread_configs: thread_configs.clone(),
+            rt_priority_policy: RtPriorityPolicy::Reject,
         })
         .unwrap();
 
@@ -980,6 +2160,238 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_effective_thread_settings() {
+        let (cgroup_context, mut cgroup_files) = create_fake_cgroup_context_pair();
+        let thread_configs = Config::default_thread_config();
+        let mut ctx = SchedQosContext::new_simple(Config {
+            cgroup_context,
+            process_configs: Config::default_process_config(),
+            thread_configs: thread_configs.clone(),
+            rt_priority_policy: RtPriorityPolicy::Reject,
+        })
+        .unwrap();
+        let sched_ctx = SchedAttrContext::new().unwrap();
+        let (process_id, _, _process) = fork_process_for_test();
+        let (unmanaged_thread_id, _thread) = spawn_thread_for_test();
+        assert_eq!(
+            ctx.effective_thread_settings(process_id, ThreadId(process_id.0)),
+            None
+        );
+
+        for process_state in [ProcessState::Normal, ProcessState::Background] {
+            ctx.set_process_state(process_id, process_state).unwrap();
+            drain_file(&mut cgroup_files.cpuset_all);
+            drain_file(&mut cgroup_files.cpuset_efficient);
+            for thread_state in [
+                ThreadState::UrgentBursty,
+                ThreadState::Urgent,
+                ThreadState::Balanced,
+                ThreadState::Eco,
+                ThreadState::Utility,
+                ThreadState::Background,
+            ] {
+                let thread_id = ThreadId(process_id.0);
+                ctx.set_thread_state(process_id, thread_id, thread_state)
+                    .unwrap();
+                let settings = ctx
+                    .effective_thread_settings(process_id, thread_id)
+                    .unwrap();
+
+                assert_eq!(settings.process_state, process_state);
+                assert_eq!(settings.thread_state, thread_state);
+                assert_eq!(
+                    settings.policy == SchedPolicy::Fifo,
+                    settings.rt_priority.is_some()
+                );
+                assert_eq!(
+                    settings.prefer_idle,
+                    thread_configs[thread_state as usize].latency_sensitive
+                );
+                // The settings match what was applied to the thread.
+                let applied_cpuset = match settings.cpuset_cgroup {
+                    CpusetCgroup::All => read_number(&mut cgroup_files.cpuset_all),
+                    CpusetCgroup::Efficient => read_number(&mut cgroup_files.cpuset_efficient),
+                };
+                assert_eq!(applied_cpuset, Some(thread_id.0));
+                assert_sched_attr(
+                    &sched_ctx,
+                    thread_id,
+                    &ThreadStateConfig {
+                        rt_priority: settings.rt_priority,
+                        nice: settings.nice,
+                        nice_relative: false,
+                        uclamp_min: settings.uclamp_min,
+                        cpuset_cgroup: settings.cpuset_cgroup,
+                        latency_sensitive: settings.prefer_idle,
+                    },
+                    true,
+                );
+            }
+        }
+
+        ctx.set_prefer_idle_override(Some(false)).unwrap();
+        ctx.set_thread_state(process_id, ThreadId(process_id.0), ThreadState::Urgent)
+            .unwrap();
+        assert!(
+            !ctx.effective_thread_settings(process_id, ThreadId(process_id.0))
+                .unwrap()
+                .prefer_idle
+        );
+
+        // Threads of other processes are not reported.
+        assert_eq!(
+            ctx.effective_thread_settings(process_id, unmanaged_thread_id),
+            None
+        );
+    }
+
+    #[test]
+    fn test_set_thread_state_relative_nice() {
+        let (cgroup_context, _cgroup_files) = create_fake_cgroup_context_pair();
+        let mut thread_configs = Config::default_thread_config();
+        thread_configs[ThreadState::Utility as usize] = ThreadStateConfig {
+            nice: 2,
+            nice_relative: true,
+            ..ThreadStateConfig::default()
+        };
+        thread_configs[ThreadState::Background as usize] = ThreadStateConfig {
+            nice: 30,
+            nice_relative: true,
+            ..ThreadStateConfig::default()
+        };
+        let mut ctx = SchedQosContext::new_simple(Config {
+            cgroup_context,
+            process_configs: Config::default_process_config(),
+            thread_configs,
+            rt_priority_policy: RtPriorityPolicy::Reject,
+        })
+        .unwrap();
+        let (process_id, threads, _process) = fork_process_with_threads_for_test(2);
+        let threads: Vec<ThreadId> = threads
+            .into_iter()
+            .filter(|thread_id| thread_id.0 != process_id.0)
+            .collect();
+        // Raising the nice value does not need privileges.
+        // SAFETY: setpriority(2) only changes the nice value of the main thread of the child.
+        let ret = unsafe { libc::setpriority(libc::PRIO_PROCESS, process_id.0, 5) };
+        assert_eq!(ret, 0);
+
+        ctx.set_process_state(process_id, ProcessState::Normal)
+            .unwrap();
+        ctx.set_thread_state(process_id, threads[0], ThreadState::Utility)
+            .unwrap();
+        ctx.set_thread_state(process_id, threads[1], ThreadState::Background)
+            .unwrap();
+
+        let sched_ctx = SchedAttrContext::new().unwrap();
+        assert_sched_attr(
+            &sched_ctx,
+            threads[0],
+            &ThreadStateConfig {
+                nice: 7,
+                ..ThreadStateConfig::default()
+            },
+            true,
+        );
+        // The result is clamped.
+        assert_sched_attr(
+            &sched_ctx,
+            threads[1],
+            &ThreadStateConfig {
+                nice: NICE_MAX,
+                ..ThreadStateConfig::default()
+            },
+            true,
+        );
+    }
+
+    #[test]
+    fn test_set_process_state_with_default_threads() {
+        let (cgroup_context, mut cgroup_files) = create_fake_cgroup_context_pair();
+        let mut ctx = SchedQosContext::new_simple(Config {
+            cgroup_context,
+            process_configs: Config::default_process_config(),
+            thread_configs: Config::default_thread_config(),
+            rt_priority_policy: RtPriorityPolicy::Reject,
+        })
+        .unwrap();
+        let sched_ctx = SchedAttrContext::new().unwrap();
+
+        let (process_id, thread_ids, _process) = fork_process_with_threads_for_test(3);
+        // The main thread is already managed and keeps its state.
+        let main_thread_id = ThreadId(process_id.0);
+        ctx.set_process_state(process_id, ProcessState::Normal)
+            .unwrap();
+        ctx.set_thread_state(process_id, main_thread_id, ThreadState::Balanced)
+            .unwrap();
+        drain_file(&mut cgroup_files.cpuset_all);
+
+        ctx.set_process_state_with_default_threads(
+            process_id,
+            ProcessState::Normal,
+            ThreadState::Utility,
+        )
+        .unwrap();
+
+        let thread_config = &Config::default_thread_config()[ThreadState::Utility as usize];
+        let mut cpuset_thread_ids: Vec<_> = read_numbers(&mut cgroup_files.cpuset_efficient)
+            .map(ThreadId)
+            .collect();
+        cpuset_thread_ids.sort_by_key(|thread_id| thread_id.0);
+        let mut expected_thread_ids: Vec<_> = thread_ids
+            .iter()
+            .copied()
+            .filter(|thread_id| *thread_id != main_thread_id)
+            .collect();
+        expected_thread_ids.sort_by_key(|thread_id| thread_id.0);
+        assert_eq!(cpuset_thread_ids, expected_thread_ids);
+        for thread_id in &expected_thread_ids {
+            assert_sched_attr(&sched_ctx, *thread_id, thread_config, true);
+        }
+
+        let mut threads = ctx
+            .registrations()
+            .into_iter()
+            .find(|registration| registration.process_id == process_id)
+            .unwrap()
+            .threads;
+        threads.sort_by_key(|(thread_id, _)| thread_id.0);
+        let mut expected_threads: Vec<_> = expected_thread_ids
+            .into_iter()
+            .map(|thread_id| (thread_id, ThreadState::Utility))
+            .collect();
+        expected_threads.push((main_thread_id, ThreadState::Balanced));
+        expected_threads.sort_by_key(|(thread_id, _)| thread_id.0);
+        assert_eq!(threads, expected_threads);
+    }
+
+    #[test]
+    fn test_set_process_state_with_default_threads_process_not_found() {
+        let (cgroup_context, _files) = create_fake_cgroup_context_pair();
+        let mut ctx = SchedQosContext::new_simple(Config {
+            cgroup_context,
+            process_configs: Config::default_process_config(),
+            thread_configs: Config::default_thread_config(),
+            rt_priority_policy: RtPriorityPolicy::Reject,
+        })
+        .unwrap();
+
+        let (process_id, _, process) = fork_process_with_threads_for_test(1);
+        drop(process);
+
+        assert!(matches!(
+            ctx.set_process_state_with_default_threads(
+                process_id,
+                ProcessState::Normal,
+                ThreadState::Utility
+            )
+            .err()
+            .unwrap(),
+            Error::ProcessNotFound
+        ));
+    }
+
     #[test]
     fn test_set_thread_state_without_process() {
         let process_id = ProcessId(std::process::id());
@@ -988,6 +2400,7 @@ mod tests {
             cgroup_context,
             process_configs: Config::default_process_config(),
             thread_configs: Config::default_thread_config(),
+            rt_priority_policy: RtPriorityPolicy::Reject,
         })
         .unwrap();
 
@@ -1009,6 +2422,7 @@ mod tests {
             cgroup_context,
             process_configs: Config::default_process_config(),
             thread_configs: Config::default_thread_config(),
+            rt_priority_policy: RtPriorityPolicy::Reject,
         })
         .unwrap();
         let (_, child_process_thread_id, _process) = fork_process_for_test();
@@ -1057,6 +2471,7 @@ mod tests {
             cgroup_context,
             process_configs: Config::default_process_config(),
             thread_configs: Config::default_thread_config(),
+            rt_priority_policy: RtPriorityPolicy::Reject,
         })
         .unwrap();
 
@@ -1100,6 +2515,7 @@ mod tests {
                 cgroup_context,
                 process_configs: Config::default_process_config(),
                 thread_configs: Config::default_thread_config(),
+                rt_priority_policy: RtPriorityPolicy::Reject,
             },
             &file_path,
         )
@@ -1131,58 +2547,890 @@ mod tests {
     }
 
     #[test]
-    fn test_restart() {
-        let dir = tempfile::tempdir().unwrap();
-        let file_path = dir.path().join("states");
-        let (cgroup_context, _files) = create_fake_cgroup_context_pair();
-        let mut ctx = SchedQosContext::new_file(
-            Config {
-                cgroup_context,
-                process_configs: Config::default_process_config(),
-                thread_configs: Config::default_thread_config(),
-            },
-            &file_path,
-        )
+    fn test_remove_thread() {
+        let (cgroup_context, mut cgroup_files) = create_fake_cgroup_context_pair();
+        let sched_ctx = SchedAttrContext::new().unwrap();
+        let default_thread_config = ThreadStateConfig {
+            nice: 3,
+            cpuset_cgroup: CpusetCgroup::Efficient,
+            ..ThreadStateConfig::default()
+        };
+        let mut process_configs = Config::default_process_config();
+        process_configs[ProcessState::Normal as usize].default_thread_config =
+            default_thread_config.clone();
+        let mut ctx = SchedQosContext::new_simple(Config {
+            cgroup_context,
+            process_configs,
+            thread_configs: Config::default_thread_config(),
+            rt_priority_policy: RtPriorityPolicy::Reject,
+        })
         .unwrap();
 
         let process_id = ProcessId(std::process::id());
         ctx.set_process_state(process_id, ProcessState::Normal)
             .unwrap();
         let (thread_id1, _thread1) = spawn_thread_for_test();
-        ctx.set_thread_state(process_id, thread_id1, ThreadState::UrgentBursty)
-            .unwrap();
-
-        let (process_id2, thread_id2, _process) = fork_process_for_test();
-        ctx.set_process_state(process_id2, ProcessState::Background)
-            .unwrap()
+        let (thread_id2, _thread2) = spawn_thread_for_test();
+        ctx.set_thread_state(process_id, thread_id1, ThreadState::Urgent)
             .unwrap();
-        ctx.set_thread_state(process_id2, thread_id2, ThreadState::Background)
+        ctx.set_thread_state(process_id, thread_id2, ThreadState::Urgent)
             .unwrap();
+        drain_file(&mut cgroup_files.cpuset_all);
+        drain_file(&mut cgroup_files.cpuset_efficient);
 
-        let (cgroup_context, mut files) = create_fake_cgroup_context_pair();
-        let mut ctx = SchedQosContext::load_from_file(
-            Config {
-                cgroup_context,
-                process_configs: Config::default_process_config(),
-                thread_configs: Config::default_thread_config(),
-            },
-            &file_path,
-        )
-        .unwrap();
+        ctx.remove_thread(process_id, thread_id1).unwrap();
+        let mut process_ctx = ctx.process_map.get_process(process_id).unwrap();
+        assert_eq!(process_ctx.thread_map().len(), 1);
+        // The removed thread is reverted to the default thread config of the process state.
+        assert_sched_attr(&sched_ctx, thread_id1, &default_thread_config, true);
+        assert_eq!(read_number(&mut cgroup_files.cpuset_all), None);
+        assert_eq!(
+            read_number(&mut cgroup_files.cpuset_efficient),
+            Some(thread_id1.0)
+        );
 
+        let sched_attr_removed = SchedAttrChecker::new(thread_id1);
         ctx.set_process_state(process_id, ProcessState::Background)
             .unwrap();
+        assert!(!sched_attr_removed.is_changed());
         assert_eq!(
-            read_number(&mut files.cpuset_efficient).unwrap(),
-            thread_id1.0
+            read_numbers(&mut cgroup_files.cpuset_efficient).collect::<Vec<_>>(),
+            vec![thread_id2.0]
         );
-        assert!(read_number(&mut files.cpuset_efficient).is_none());
+    }
 
-        ctx.set_thread_state(process_id2, thread_id2, ThreadState::UrgentBursty)
+    #[test]
+    fn test_remove_thread_latency_sensitive() {
+        let dir = tempfile::tempdir().unwrap();
+        let (cgroup_context, _files) = create_fake_cgroup_context_pair();
+        let mut ctx = SchedQosContext::new_simple(Config {
+            cgroup_context,
+            process_configs: Config::default_process_config(),
+            thread_configs: Config::default_thread_config(),
+            rt_priority_policy: RtPriorityPolicy::Reject,
+        })
+        .unwrap();
+        ctx.proc_root = dir.path().to_path_buf();
+
+        let process_id = ProcessId(std::process::id());
+        ctx.set_process_state(process_id, ProcessState::Normal)
+            .unwrap();
+        let (thread_id, _thread) = spawn_thread_for_test();
+        let task_dir = dir
+            .path()
+            .join(process_id.0.to_string())
+            .join("task")
+            .join(thread_id.0.to_string());
+        std::fs::create_dir_all(&task_dir).unwrap();
+        let latency_sensitive_file = task_dir.join("latency_sensitive");
+        std::fs::write(&latency_sensitive_file, "").unwrap();
+
+        ctx.set_thread_state(process_id, thread_id, ThreadState::Urgent)
             .unwrap();
         assert_eq!(
-            read_number(&mut files.cpuset_efficient).unwrap(),
-            thread_id2.0
+            std::fs::read_to_string(&latency_sensitive_file).unwrap(),
+            "1"
         );
+
+        ctx.remove_thread(process_id, thread_id).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&latency_sensitive_file).unwrap(),
+            "0"
+        );
+    }
+
+    #[test]
+    fn test_remove_thread_not_managed() {
+        let (cgroup_context, mut cgroup_files) = create_fake_cgroup_context_pair();
+        let mut ctx = SchedQosContext::new_simple(Config {
+            cgroup_context,
+            process_configs: Config::default_process_config(),
+            thread_configs: Config::default_thread_config(),
+            rt_priority_policy: RtPriorityPolicy::Reject,
+        })
+        .unwrap();
+
+        let process_id = ProcessId(std::process::id());
+        ctx.set_process_state(process_id, ProcessState::Normal)
+            .unwrap();
+        drain_file(&mut cgroup_files.cpuset_all);
+        drain_file(&mut cgroup_files.cpuset_efficient);
+        let (thread_id, _thread) = spawn_thread_for_test();
+        let sched_attr = SchedAttrChecker::new(thread_id);
+
+        // The settings of a thread which is not managed are not touched.
+        assert!(matches!(
+            ctx.remove_thread(process_id, thread_id),
+            Err(Error::ThreadNotFound)
+        ));
+        assert!(!sched_attr.is_changed());
+        assert_eq!(read_number(&mut cgroup_files.cpuset_all), None);
+        assert_eq!(read_number(&mut cgroup_files.cpuset_efficient), None);
+    }
+
+    #[test]
+    fn test_unmanaged_thread_sched_attr() {
+        let (cgroup_context, mut cgroup_files) = create_fake_cgroup_context_pair();
+        let sched_ctx = SchedAttrContext::new().unwrap();
+        let mut ctx = SchedQosContext::new_simple(Config {
+            cgroup_context,
+            process_configs: Config::default_process_config(),
+            thread_configs: Config::default_thread_config(),
+            rt_priority_policy: RtPriorityPolicy::Reject,
+        })
+        .unwrap();
+
+        let process_id = ProcessId(std::process::id());
+        let (thread_id, _thread) = spawn_thread_for_test();
+        let sched_attr = SchedAttrChecker::new(thread_id);
+
+        let saved = ctx
+            .set_unmanaged_thread_sched_attr(process_id, thread_id, ThreadState::Urgent)
+            .unwrap();
+        assert_sched_attr(
+            &sched_ctx,
+            thread_id,
+            &Config::default_thread_config()[ThreadState::Urgent as usize],
+            false,
+        );
+        // Neither the thread nor the process is registered nor moved between cgroups.
+        assert!(ctx.process_map.get_process(process_id).is_none());
+        assert_eq!(read_number(&mut cgroup_files.cpu_normal), None);
+        assert_eq!(read_number(&mut cgroup_files.cpuset_all), None);
+        assert_eq!(read_number(&mut cgroup_files.cpuset_efficient), None);
+
+        ctx.restore_unmanaged_thread_sched_attr(thread_id, &saved)
+            .unwrap();
+        assert!(!sched_attr.is_changed());
+    }
+
+    #[test]
+    fn test_remove_thread_compact() {
+        let dir = tempfile::tempdir().unwrap();
+        let file_path = dir.path().join("states");
+        let (cgroup_context, _files) = create_fake_cgroup_context_pair();
+        let mut ctx = SchedQosContext::new_file(
+            Config {
+                cgroup_context,
+                process_configs: Config::default_process_config(),
+                thread_configs: Config::default_thread_config(),
+                rt_priority_policy: RtPriorityPolicy::Reject,
+            },
+            &file_path,
+        )
+        .unwrap();
+
+        let process_id = ProcessId(std::process::id());
+        ctx.set_process_state(process_id, ProcessState::Normal)
+            .unwrap();
+        let (thread_id1, dead_thread1) = spawn_thread_for_test();
+        ctx.set_thread_state(process_id, thread_id1, ThreadState::Balanced)
+            .unwrap();
+        let (thread_id2, _thread2) = spawn_thread_for_test();
+        ctx.set_thread_state(process_id, thread_id2, ThreadState::Balanced)
+            .unwrap();
+        assert_eq!(ctx.process_map.n_cells(), 3);
+
+        ctx.remove_thread(process_id, thread_id2).unwrap();
+        assert_eq!(ctx.process_map.n_cells(), 2);
+
+        // Removing a dead thread succeeds without touching its settings.
+        drop(dead_thread1);
+        assert!(wait_for_thread_removed(process_id, thread_id1));
+        ctx.remove_thread(process_id, thread_id1).unwrap();
+        assert_eq!(ctx.process_map.n_cells(), 1);
+    }
+
+    #[test]
+    fn test_remove_thread_without_process() {
+        let process_id = ProcessId(std::process::id());
+        let (cgroup_context, _files) = create_fake_cgroup_context_pair();
+        let mut ctx = SchedQosContext::new_simple(Config {
+            cgroup_context,
+            process_configs: Config::default_process_config(),
+            thread_configs: Config::default_thread_config(),
+            rt_priority_policy: RtPriorityPolicy::Reject,
+        })
+        .unwrap();
+
+        let (thread_id, _thread) = spawn_thread_for_test();
+
+        assert!(matches!(
+            ctx.remove_thread(process_id, thread_id).err().unwrap(),
+            Error::ProcessNotRegistered
+        ));
+    }
+
+    #[test]
+    fn test_registrations() {
+        let (cgroup_context, _files) = create_fake_cgroup_context_pair();
+        let mut ctx = SchedQosContext::new_simple(Config {
+            cgroup_context,
+            process_configs: Config::default_process_config(),
+            thread_configs: Config::default_thread_config(),
+            rt_priority_policy: RtPriorityPolicy::Reject,
+        })
+        .unwrap();
+        assert!(ctx.registrations().is_empty());
+
+        let process_id = ProcessId(std::process::id());
+        ctx.set_process_state(process_id, ProcessState::Normal)
+            .unwrap();
+        let (thread_id, _thread) = spawn_thread_for_test();
+        ctx.set_thread_state(process_id, thread_id, ThreadState::Eco)
+            .unwrap();
+        let (child_process_id, _child_thread_id, _process) = fork_process_for_test();
+        ctx.set_process_state(child_process_id, ProcessState::Background)
+            .unwrap();
+
+        let mut registrations = ctx.registrations();
+        registrations.sort_by_key(|registration| registration.process_id.0);
+        let mut expected = vec![
+            ProcessRegistration {
+                process_id,
+                state: ProcessState::Normal,
+                threads: vec![(thread_id, ThreadState::Eco)],
+            },
+            ProcessRegistration {
+                process_id: child_process_id,
+                state: ProcessState::Background,
+                threads: Vec::new(),
+            },
+        ];
+        expected.sort_by_key(|registration| registration.process_id.0);
+        assert_eq!(registrations, expected);
+    }
+
+    #[test]
+    fn test_export_table() {
+        let (cgroup_context, _files) = create_fake_cgroup_context_pair();
+        let mut ctx = SchedQosContext::new_simple(Config {
+            cgroup_context,
+            process_configs: Config::default_process_config(),
+            thread_configs: Config::default_thread_config(),
+            rt_priority_policy: RtPriorityPolicy::Reject,
+        })
+        .unwrap();
+        assert!(ctx.export_table().processes.is_empty());
+
+        let process_id = ProcessId(std::process::id());
+        ctx.set_process_state(process_id, ProcessState::Normal)
+            .unwrap();
+        let (thread_id1, _thread1) = spawn_thread_for_test();
+        let (thread_id2, _thread2) = spawn_thread_for_test();
+        ctx.set_thread_state(process_id, thread_id1, ThreadState::Eco)
+            .unwrap();
+        ctx.set_thread_state(process_id, thread_id2, ThreadState::Urgent)
+            .unwrap();
+        let (child_process_id, _child_thread_id, _process) = fork_process_for_test();
+        ctx.set_process_state(child_process_id, ProcessState::Background)
+            .unwrap();
+        // Updates are reflected.
+        ctx.set_thread_state(process_id, thread_id1, ThreadState::Utility)
+            .unwrap();
+
+        let mut expected = vec![
+            ProcessSnapshot {
+                process_id: process_id.0,
+                timestamp: load_process_timestamp(process_id).unwrap(),
+                state: ProcessState::Normal,
+                threads: vec![
+                    ThreadSnapshot {
+                        thread_id: thread_id1.0,
+                        timestamp: load_thread_timestamp(process_id, thread_id1).unwrap(),
+                        state: ThreadState::Utility,
+                    },
+                    ThreadSnapshot {
+                        thread_id: thread_id2.0,
+                        timestamp: load_thread_timestamp(process_id, thread_id2).unwrap(),
+                        state: ThreadState::Urgent,
+                    },
+                ],
+            },
+            ProcessSnapshot {
+                process_id: child_process_id.0,
+                timestamp: load_process_timestamp(child_process_id).unwrap(),
+                state: ProcessState::Background,
+                threads: Vec::new(),
+            },
+        ];
+        expected[0].threads.sort_by_key(|thread| thread.thread_id);
+        expected.sort_by_key(|process| process.process_id);
+        assert_eq!(
+            ctx.export_table(),
+            QosTableSnapshot {
+                processes: expected
+            }
+        );
+
+        ctx.remove_thread(process_id, thread_id2).unwrap();
+        let snapshot = ctx.export_table();
+        let process = snapshot
+            .processes
+            .iter()
+            .find(|process| process.process_id == process_id.0)
+            .unwrap();
+        assert_eq!(process.threads.len(), 1);
+        assert_eq!(process.threads[0].thread_id, thread_id1.0);
+    }
+
+    #[test]
+    fn test_freeze_and_thaw() {
+        let (cgroup_context, mut cgroup_files) = create_fake_cgroup_context_pair();
+        let mut ctx = SchedQosContext::new_simple(Config {
+            cgroup_context,
+            process_configs: Config::default_process_config(),
+            thread_configs: Config::default_thread_config(),
+            rt_priority_policy: RtPriorityPolicy::Reject,
+        })
+        .unwrap();
+
+        let process_id = ProcessId(std::process::id());
+        ctx.set_process_state(process_id, ProcessState::Normal)
+            .unwrap();
+        assert_eq!(
+            read_number(&mut cgroup_files.cpu_normal),
+            Some(process_id.0)
+        );
+
+        assert!(ctx.on_freeze(process_id).unwrap().is_none());
+        assert_eq!(
+            read_number(&mut cgroup_files.cpu_background),
+            Some(process_id.0)
+        );
+        // Freezing again does not overwrite the recorded state.
+        ctx.on_freeze(process_id).unwrap();
+        assert_eq!(
+            read_number(&mut cgroup_files.cpu_background),
+            Some(process_id.0)
+        );
+
+        // The recorded state wins over the fallback.
+        assert!(ctx
+            .on_thaw(process_id, ProcessState::Background)
+            .unwrap()
+            .is_none());
+        assert_eq!(
+            read_number(&mut cgroup_files.cpu_normal),
+            Some(process_id.0)
+        );
+        assert_eq!(ctx.registrations()[0].state, ProcessState::Normal);
+    }
+
+    #[test]
+    fn test_set_process_state_while_frozen() {
+        let (cgroup_context, mut cgroup_files) = create_fake_cgroup_context_pair();
+        let mut ctx = SchedQosContext::new_simple(Config {
+            cgroup_context,
+            process_configs: Config::default_process_config(),
+            thread_configs: Config::default_thread_config(),
+            rt_priority_policy: RtPriorityPolicy::Reject,
+        })
+        .unwrap();
+
+        let process_id = ProcessId(std::process::id());
+        ctx.set_process_state(process_id, ProcessState::Background)
+            .unwrap();
+        ctx.on_freeze(process_id).unwrap();
+        read_number(&mut cgroup_files.cpu_background);
+
+        // The process stays in background while frozen.
+        ctx.set_process_state(process_id, ProcessState::Normal)
+            .unwrap();
+        assert_eq!(
+            read_number(&mut cgroup_files.cpu_background),
+            Some(process_id.0)
+        );
+        assert_eq!(read_number(&mut cgroup_files.cpu_normal), None);
+
+        ctx.on_thaw(process_id, ProcessState::Background).unwrap();
+        assert_eq!(
+            read_number(&mut cgroup_files.cpu_normal),
+            Some(process_id.0)
+        );
+    }
+
+    #[test]
+    fn test_freeze_unregistered_process() {
+        let (cgroup_context, mut cgroup_files) = create_fake_cgroup_context_pair();
+        let mut ctx = SchedQosContext::new_simple(Config {
+            cgroup_context,
+            process_configs: Config::default_process_config(),
+            thread_configs: Config::default_thread_config(),
+            rt_priority_policy: RtPriorityPolicy::Reject,
+        })
+        .unwrap();
+
+        let (process_id, _, _process) = fork_process_for_test();
+        assert!(ctx.on_freeze(process_id).unwrap().is_none());
+        assert_eq!(read_number(&mut cgroup_files.cpu_background), None);
+        assert!(ctx.registrations().is_empty());
+
+        assert!(ctx
+            .on_thaw(process_id, ProcessState::Normal)
+            .unwrap()
+            .is_none());
+        assert_eq!(read_number(&mut cgroup_files.cpu_normal), None);
+        assert!(ctx.registrations().is_empty());
+    }
+
+    #[test]
+    fn test_thaw_without_recorded_state() {
+        let (cgroup_context, mut cgroup_files) = create_fake_cgroup_context_pair();
+        let mut ctx = SchedQosContext::new_simple(Config {
+            cgroup_context,
+            process_configs: Config::default_process_config(),
+            thread_configs: Config::default_thread_config(),
+            rt_priority_policy: RtPriorityPolicy::Reject,
+        })
+        .unwrap();
+
+        let (process_id, _, _process) = fork_process_for_test();
+        ctx.set_process_state(process_id, ProcessState::Background)
+            .unwrap();
+        read_number(&mut cgroup_files.cpu_background);
+
+        // No state was recorded, e.g. after a restart, so the fallback is applied.
+        ctx.on_thaw(process_id, ProcessState::Normal).unwrap();
+        assert_eq!(
+            read_number(&mut cgroup_files.cpu_normal),
+            Some(process_id.0)
+        );
+    }
+
+    #[test]
+    fn test_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let file_path = dir.path().join("states");
+        let (cgroup_context, _files) = create_fake_cgroup_context_pair();
+        let mut ctx = SchedQosContext::new_file(
+            Config {
+                cgroup_context,
+                process_configs: Config::default_process_config(),
+                thread_configs: Config::default_thread_config(),
+                rt_priority_policy: RtPriorityPolicy::Reject,
+            },
+            &file_path,
+        )
+        .unwrap();
+
+        let process_id = ProcessId(std::process::id());
+        ctx.set_process_state(process_id, ProcessState::Normal)
+            .unwrap();
+        let (thread_id1, _thread1) = spawn_thread_for_test();
+        ctx.set_thread_state(process_id, thread_id1, ThreadState::UrgentBursty)
+            .unwrap();
+
+        let (process_id2, thread_id2, _process) = fork_process_for_test();
+        ctx.set_process_state(process_id2, ProcessState::Background)
+            .unwrap()
+            .unwrap();
+        ctx.set_thread_state(process_id2, thread_id2, ThreadState::Background)
+            .unwrap();
+
+        let (cgroup_context, mut files) = create_fake_cgroup_context_pair();
+        let mut ctx = SchedQosContext::load_from_file(
+            Config {
+                cgroup_context,
+                process_configs: Config::default_process_config(),
+                thread_configs: Config::default_thread_config(),
+                rt_priority_policy: RtPriorityPolicy::Reject,
+            },
+            &file_path,
+        )
+        .unwrap();
+
+        ctx.set_process_state(process_id, ProcessState::Background)
+            .unwrap();
+        assert_eq!(
+            read_number(&mut files.cpuset_efficient).unwrap(),
+            thread_id1.0
+        );
+        assert!(read_number(&mut files.cpuset_efficient).is_none());
+
+        ctx.set_thread_state(process_id2, thread_id2, ThreadState::UrgentBursty)
+            .unwrap();
+        assert_eq!(
+            read_number(&mut files.cpuset_efficient).unwrap(),
+            thread_id2.0
+        );
+    }
+
+    #[test]
+    fn test_restore_from_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let file_path = dir.path().join("states");
+        let (cgroup_context, _files) = create_fake_cgroup_context_pair();
+        let mut ctx = SchedQosContext::new_file(
+            Config {
+                cgroup_context,
+                process_configs: Config::default_process_config(),
+                thread_configs: Config::default_thread_config(),
+                rt_priority_policy: RtPriorityPolicy::Reject,
+            },
+            &file_path,
+        )
+        .unwrap();
+
+        let process_id = ProcessId(std::process::id());
+        ctx.set_process_state(process_id, ProcessState::Normal)
+            .unwrap();
+        let (thread_id, _thread) = spawn_thread_for_test();
+        ctx.set_thread_state(process_id, thread_id, ThreadState::Balanced)
+            .unwrap();
+
+        let (dead_process_id, dead_thread_id, process) = fork_process_for_test();
+        ctx.set_process_state(dead_process_id, ProcessState::Background)
+            .unwrap();
+        ctx.set_thread_state(dead_process_id, dead_thread_id, ThreadState::Background)
+            .unwrap();
+        drop(process);
+        drop(ctx);
+
+        let (cgroup_context, mut files) = create_fake_cgroup_context_pair();
+        let (mut ctx, result) = SchedQosContext::restore_from_file(
+            Config {
+                cgroup_context,
+                process_configs: Config::default_process_config(),
+                thread_configs: Config::default_thread_config(),
+                rt_priority_policy: RtPriorityPolicy::Reject,
+            },
+            &file_path,
+        )
+        .unwrap();
+
+        assert_eq!(result.processes.len(), 1);
+        assert_eq!(result.processes[0].process_id(), process_id);
+        assert_eq!(result.n_threads, 1);
+        // The dead process and its thread.
+        assert_eq!(result.n_pruned, 2);
+
+        // The states are re-applied without any new request.
+        assert_eq!(read_number(&mut files.cpu_normal), Some(process_id.0));
+        assert_eq!(read_number(&mut files.cpu_background), None);
+        assert_eq!(read_number(&mut files.cpuset_all), Some(thread_id.0));
+        assert_eq!(read_number(&mut files.cpuset_efficient), None);
+
+        assert_eq!(
+            ctx.registrations(),
+            vec![ProcessRegistration {
+                process_id,
+                state: ProcessState::Normal,
+                threads: vec![(thread_id, ThreadState::Balanced)],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_set_prefer_idle_override() {
+        let dir = tempfile::tempdir().unwrap();
+        let (cgroup_context, _files) = create_fake_cgroup_context_pair();
+        let mut ctx = SchedQosContext::new_simple(Config {
+            cgroup_context,
+            process_configs: Config::default_process_config(),
+            thread_configs: Config::default_thread_config(),
+            rt_priority_policy: RtPriorityPolicy::Reject,
+        })
+        .unwrap();
+        ctx.proc_root = dir.path().to_path_buf();
+
+        let process_id = ProcessId(std::process::id());
+        ctx.set_process_state(process_id, ProcessState::Normal)
+            .unwrap();
+        let (balanced_thread_id, _balanced_thread) = spawn_thread_for_test();
+        let (eco_thread_id, _eco_thread) = spawn_thread_for_test();
+        let latency_sensitive_file = |thread_id: ThreadId| {
+            let task_dir = dir
+                .path()
+                .join(process_id.0.to_string())
+                .join("task")
+                .join(thread_id.0.to_string());
+            std::fs::create_dir_all(&task_dir).unwrap();
+            task_dir.join("latency_sensitive")
+        };
+        let balanced_file = latency_sensitive_file(balanced_thread_id);
+        let eco_file = latency_sensitive_file(eco_thread_id);
+        std::fs::write(&balanced_file, "").unwrap();
+        std::fs::write(&eco_file, "").unwrap();
+        let read = |path: &Path| std::fs::read_to_string(path).unwrap();
+
+        ctx.set_thread_state(process_id, balanced_thread_id, ThreadState::Balanced)
+            .unwrap();
+        ctx.set_thread_state(process_id, eco_thread_id, ThreadState::Eco)
+            .unwrap();
+        assert_eq!(read(&balanced_file), "1");
+        assert_eq!(read(&eco_file), "0");
+
+        // The override is re-applied to the managed threads.
+        ctx.set_prefer_idle_override(Some(false)).unwrap();
+        assert_eq!(read(&balanced_file), "0");
+        assert_eq!(read(&eco_file), "0");
+
+        ctx.set_prefer_idle_override(Some(true)).unwrap();
+        assert_eq!(read(&balanced_file), "1");
+        assert_eq!(read(&eco_file), "1");
+
+        // The override is also used for newly set states.
+        ctx.set_thread_state(process_id, balanced_thread_id, ThreadState::Utility)
+            .unwrap();
+        assert_eq!(read(&balanced_file), "1");
+
+        // None restores the per-state configs.
+        ctx.set_prefer_idle_override(None).unwrap();
+        assert_eq!(read(&balanced_file), "0");
+        assert_eq!(read(&eco_file), "0");
+        ctx.set_thread_state(process_id, balanced_thread_id, ThreadState::Urgent)
+            .unwrap();
+        assert_eq!(read(&balanced_file), "1");
+    }
+
+    #[test]
+    fn test_rt_allowlist() {
+        let dir = tempfile::tempdir().unwrap();
+        let (cgroup_context, _files) = create_fake_cgroup_context_pair();
+        let thread_configs = Config::default_thread_config();
+        let mut ctx = SchedQosContext::new_simple(Config {
+            cgroup_context,
+            process_configs: Config::default_process_config(),
+            thread_configs: thread_configs.clone(),
+            rt_priority_policy: RtPriorityPolicy::Reject,
+        })
+        .unwrap();
+        ctx.proc_root = dir.path().to_path_buf();
+        let sched_ctx = SchedAttrContext::new().unwrap();
+        let process_id = ProcessId(std::process::id());
+        let process_dir = dir.path().join(process_id.0.to_string());
+        std::fs::create_dir(&process_dir).unwrap();
+        std::fs::write(process_dir.join("status"), "Uid:\t1000\t1000\t1000\t1000\n").unwrap();
+        ctx.set_process_state(process_id, ProcessState::Normal)
+            .unwrap();
+        let urgent_bursty_config = &thread_configs[ThreadState::UrgentBursty as usize];
+        assert!(urgent_bursty_config.rt_priority.is_some());
+        let policy = |ctx: &mut SimpleSchedQosContext, thread_id| {
+            ctx.effective_thread_settings(process_id, thread_id)
+                .unwrap()
+                .policy
+        };
+
+        // The process is not in the allowlist.
+        ctx.set_rt_allowlist(Some(RtAllowlist::Uid(HashSet::from([20104]))))
+            .unwrap();
+        let (thread_id, _thread) = spawn_thread_for_test();
+        ctx.set_thread_state(process_id, thread_id, ThreadState::UrgentBursty)
+            .unwrap();
+        assert_sched_attr(&sched_ctx, thread_id, urgent_bursty_config, false);
+        assert_eq!(policy(&mut ctx, thread_id), SchedPolicy::Other);
+        assert_eq!(
+            ctx.take_rt_denials(),
+            vec![RtDenial {
+                process_id,
+                thread_id,
+                thread_state: ThreadState::UrgentBursty,
+            }]
+        );
+        assert!(ctx.take_rt_denials().is_empty());
+
+        // Threads not requesting RT are not denied.
+        let (balanced_thread_id, _balanced_thread) = spawn_thread_for_test();
+        ctx.set_thread_state(process_id, balanced_thread_id, ThreadState::Balanced)
+            .unwrap();
+        assert!(ctx.take_rt_denials().is_empty());
+
+        // The new allowlist is re-applied to the managed threads.
+        ctx.set_rt_allowlist(Some(RtAllowlist::Uid(HashSet::from([1000]))))
+            .unwrap();
+        assert_sched_attr(&sched_ctx, thread_id, urgent_bursty_config, true);
+        assert_eq!(policy(&mut ctx, thread_id), SchedPolicy::Fifo);
+        assert!(ctx.take_rt_denials().is_empty());
+
+        // A process whose uid cannot be read is not in the allowlist.
+        std::fs::remove_file(process_dir.join("status")).unwrap();
+        ctx.set_process_state(process_id, ProcessState::Normal)
+            .unwrap();
+        assert_sched_attr(&sched_ctx, thread_id, urgent_bursty_config, false);
+        assert_eq!(ctx.take_rt_denials().len(), 1);
+
+        ctx.set_rt_allowlist(Some(RtAllowlist::Predicate(Box::new(move |id| {
+            id != process_id
+        }))))
+        .unwrap();
+        assert_sched_attr(&sched_ctx, thread_id, urgent_bursty_config, false);
+        assert_eq!(ctx.take_rt_denials().len(), 1);
+
+        ctx.set_rt_allowlist(None).unwrap();
+        assert_sched_attr(&sched_ctx, thread_id, urgent_bursty_config, true);
+        assert_eq!(policy(&mut ctx, thread_id), SchedPolicy::Fifo);
+        assert!(ctx.take_rt_denials().is_empty());
+    }
+
+    #[test]
+    fn test_rt_allowlist_only_for_rt_threads() {
+        let (cgroup_context, _files) = create_fake_cgroup_context_pair();
+        let mut ctx = SchedQosContext::new_simple(Config {
+            cgroup_context,
+            process_configs: Config::default_process_config(),
+            thread_configs: Config::default_thread_config(),
+            rt_priority_policy: RtPriorityPolicy::Reject,
+        })
+        .unwrap();
+        let n_checks = Arc::new(Mutex::new(0));
+        let n_checks_clone = n_checks.clone();
+        ctx.set_rt_allowlist(Some(RtAllowlist::Predicate(Box::new(move |_| {
+            *n_checks_clone.lock().unwrap() += 1;
+            true
+        }))))
+        .unwrap();
+
+        let process_id = ProcessId(std::process::id());
+        ctx.set_process_state(process_id, ProcessState::Normal)
+            .unwrap();
+        let (thread_id, _thread) = spawn_thread_for_test();
+        ctx.set_thread_state(process_id, thread_id, ThreadState::Balanced)
+            .unwrap();
+        ctx.set_process_state(process_id, ProcessState::Normal)
+            .unwrap();
+        assert_eq!(*n_checks.lock().unwrap(), 0);
+
+        // The allowlist is evaluated once per process state change.
+        let (rt_thread_id1, _rt_thread1) = spawn_thread_for_test();
+        let (rt_thread_id2, _rt_thread2) = spawn_thread_for_test();
+        ctx.set_thread_state(process_id, rt_thread_id1, ThreadState::UrgentBursty)
+            .unwrap();
+        ctx.set_thread_state(process_id, rt_thread_id2, ThreadState::UrgentBursty)
+            .unwrap();
+        *n_checks.lock().unwrap() = 0;
+        ctx.set_process_state(process_id, ProcessState::Normal)
+            .unwrap();
+        assert_eq!(*n_checks.lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_set_thread_config() {
+        let (cgroup_context, _files) = create_fake_cgroup_context_pair();
+        let mut ctx = SchedQosContext::new_simple(Config {
+            cgroup_context,
+            process_configs: Config::default_process_config(),
+            thread_configs: Config::default_thread_config(),
+            rt_priority_policy: RtPriorityPolicy::Reject,
+        })
+        .unwrap();
+        let sched_ctx = SchedAttrContext::new().unwrap();
+
+        let process_id = ProcessId(std::process::id());
+        ctx.set_process_state(process_id, ProcessState::Normal)
+            .unwrap();
+        let (urgent_thread_id, _urgent_thread) = spawn_thread_for_test();
+        let (balanced_thread_id, _balanced_thread) = spawn_thread_for_test();
+        ctx.set_thread_state(process_id, urgent_thread_id, ThreadState::Urgent)
+            .unwrap();
+        ctx.set_thread_state(process_id, balanced_thread_id, ThreadState::Balanced)
+            .unwrap();
+
+        let invalid_config = ThreadStateConfig {
+            uclamp_min: UCLAMP_MAX + 1,
+            ..ctx.thread_config(ThreadState::Urgent).clone()
+        };
+        assert!(ctx
+            .set_thread_config(ThreadState::Urgent, invalid_config)
+            .is_err());
+
+        let new_config = ThreadStateConfig {
+            uclamp_min: UCLAMP_BOOSTED_MIN / 2,
+            ..ctx.thread_config(ThreadState::Urgent).clone()
+        };
+        ctx.set_thread_config(ThreadState::Urgent, new_config.clone())
+            .unwrap();
+        assert_eq!(
+            ctx.thread_config(ThreadState::Urgent).uclamp_min,
+            UCLAMP_BOOSTED_MIN / 2
+        );
+        // Only the threads in the updated state are re-applied.
+        assert_sched_attr(&sched_ctx, urgent_thread_id, &new_config, true);
+        assert_sched_attr(
+            &sched_ctx,
+            balanced_thread_id,
+            ctx.thread_config(ThreadState::Balanced),
+            true,
+        );
+
+        // The new config is used for newly set states.
+        ctx.set_thread_state(process_id, balanced_thread_id, ThreadState::Urgent)
+            .unwrap();
+        assert_sched_attr(&sched_ctx, balanced_thread_id, &new_config, true);
+    }
+
+    #[test]
+    fn test_rt_priority_validation() {
+        for (rt_priority, policy, expected) in [
+            (0, RtPriorityPolicy::Reject, None),
+            (100, RtPriorityPolicy::Reject, None),
+            (50, RtPriorityPolicy::Reject, Some(50)),
+            (0, RtPriorityPolicy::Clamp, Some(RT_PRIORITY_MIN)),
+            (100, RtPriorityPolicy::Clamp, Some(RT_PRIORITY_MAX)),
+            (50, RtPriorityPolicy::Clamp, Some(50)),
+        ] {
+            let (cgroup_context, _files) = create_fake_cgroup_context_pair();
+            let mut thread_configs = Config::default_thread_config();
+            thread_configs[ThreadState::UrgentBursty as usize].rt_priority = Some(rt_priority);
+            let ctx = SchedQosContext::new_simple(Config {
+                cgroup_context,
+                process_configs: Config::default_process_config(),
+                thread_configs,
+                rt_priority_policy: policy,
+            });
+            match expected {
+                Some(expected) => assert_eq!(
+                    ctx.unwrap()
+                        .thread_config(ThreadState::UrgentBursty)
+                        .rt_priority,
+                    Some(expected),
+                    "{rt_priority} {policy:?}"
+                ),
+                None => assert!(
+                    matches!(ctx, Err(Error::Config(_, _))),
+                    "{rt_priority} {policy:?}"
+                ),
+            }
+        }
+    }
+
+    #[test]
+    fn test_set_thread_config_rt_priority() {
+        for (policy, expected) in [
+            (RtPriorityPolicy::Reject, None),
+            (RtPriorityPolicy::Clamp, Some(RT_PRIORITY_MAX)),
+        ] {
+            let (cgroup_context, _files) = create_fake_cgroup_context_pair();
+            let mut ctx = SchedQosContext::new_simple(Config {
+                cgroup_context,
+                process_configs: Config::default_process_config(),
+                thread_configs: Config::default_thread_config(),
+                rt_priority_policy: policy,
+            })
+            .unwrap();
+
+            let new_config = ThreadStateConfig {
+                rt_priority: Some(100),
+                ..ctx.thread_config(ThreadState::UrgentBursty).clone()
+            };
+            let result = ctx.set_thread_config(ThreadState::UrgentBursty, new_config);
+            match expected {
+                Some(expected) => {
+                    result.unwrap();
+                    assert_eq!(
+                        ctx.thread_config(ThreadState::UrgentBursty).rt_priority,
+                        Some(expected)
+                    );
+                }
+                None => {
+                    assert!(matches!(result, Err(Error::Config(_, _))));
+                    // The config is not changed.
+                    assert_eq!(
+                        ctx.thread_config(ThreadState::UrgentBursty).rt_priority,
+                        Some(8)
+                    );
+                }
+            }
+        }
     }
 }
@@ -50,10 +50,10 @@ impl Mmap {
                 self.ptr as *mut libc::c_void,
                 self.size.get(),
                 new_size.get(),
-                libc::MAP_SHARED,
+                libc::MREMAP_MAYMOVE,
             )
         } as *mut u8;
-        if ptr.is_null() {
+        if ptr == libc::MAP_FAILED as *mut u8 {
             return Err(io::Error::last_os_error());
         }
         self.ptr = ptr;
@@ -7,9 +7,14 @@ use std::fs::File;
 use std::io;
 use std::io::BufRead;
 use std::io::BufReader;
-use std::io::Read;
+use std::os::fd::AsRawFd;
+use std::os::fd::FromRawFd;
+use std::os::fd::OwnedFd;
 use std::path::Path;
 
+use libchromeos::proc::process_start_time;
+use libchromeos::proc::thread_start_time;
+
 use crate::ProcessId;
 use crate::ThreadId;
 
@@ -45,6 +50,8 @@ impl From<io::Error> for Error {
     fn from(e: io::Error) -> Self {
         if e.kind() == io::ErrorKind::NotFound || e.raw_os_error() == Some(libc::ESRCH) {
             Self::NotFound
+        } else if e.kind() == io::ErrorKind::InvalidData {
+            Self::FormatCorrupt
         } else {
             Self::Io(e)
         }
@@ -52,65 +59,91 @@ impl From<io::Error> for Error {
 }
 
 pub fn load_process_timestamp(process_id: ProcessId) -> Result<u64> {
-    load_starttime(Path::new(&format!("/proc/{}/stat", process_id.0)))
+    Ok(process_start_time(process_id.0)?)
+}
+
+/// A pidfd (man 2 pidfd_open) of a process. Unlike the pid, the pidfd keeps referring to the same
+/// process after the pid is reused.
+pub struct ProcessFd(OwnedFd);
+
+impl ProcessFd {
+    /// Returns [None] if the kernel does not support pidfd.
+    pub fn open(process_id: ProcessId) -> Result<Option<Self>> {
+        // SAFETY: pidfd_open(2) only allocates a new file descriptor and the error is checked.
+        let ret = unsafe { libc::syscall(libc::SYS_pidfd_open, process_id.0 as libc::pid_t, 0) };
+        if ret < 0 {
+            let e = io::Error::last_os_error();
+            if e.raw_os_error() == Some(libc::ENOSYS) {
+                return Ok(None);
+            }
+            return Err(e.into());
+        }
+        // SAFETY: The file descriptor is new and not owned by anything else.
+        Ok(Some(Self(unsafe { OwnedFd::from_raw_fd(ret as i32) })))
+    }
+
+    /// Whether the process has not exited yet, i.e. its pid is not reused.
+    pub fn is_alive(&self) -> bool {
+        // A pidfd becomes readable once the process exits. poll(2) is used instead of
+        // pidfd_send_signal(2) since the seccomp policy of resourced allows it already.
+        let mut pollfd = libc::pollfd {
+            fd: self.0.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // SAFETY: poll(2) only writes to `pollfd` and the timeout 0 does not block.
+        let ret = unsafe { libc::poll(&mut pollfd, 1, 0) };
+        ret == 0
+    }
 }
 
 pub fn load_thread_timestamp(process_id: ProcessId, thread_id: ThreadId) -> Result<u64> {
-    load_starttime(Path::new(&format!(
-        "/proc/{}/task/{}/stat",
-        process_id.0, thread_id.0
-    )))
+    Ok(thread_start_time(process_id.0, thread_id.0)?)
 }
 
-fn load_starttime(path: &Path) -> Result<u64> {
-    let mut stat_file = File::open(path)?;
-    // starttime is the 22th column in /proc/pid/stat. Each numeric column in /proc/pid/stat has at
-    // most 21 bytes. (1 byte for sign + 19 bytes for u64 + 1 byte space). The 2nd column (comm) is
-    // at most 67 bytes including the wrapping parenthesis (proc_task_name() of kernel uses 64 bytes
-    // buffer `tcomm`). 512 bytes is enough to hold the 22 columns (i.e. 512 >= 21 * 21 + 67 = 508).
-    let mut buf = [0; 512];
-    let n = stat_file.read(&mut buf)?;
-
-    // Since threads can set comm by writing to /proc/self/task/tid/comm, it can contain any byte
-    // sequence. This means we need to exclusively look at kernel controlled bytes to determine
-    // where comm ends. To do this, we can scan backwards to look for the closing parentheses
-    // emitted by the kernel.
-    // The longest possible tail offset of comm is 88 bytes (= 21 bytes for pid column + 1 byte for
-    // space + 66 bytes for comm column). This works even if the stat file size is less than 88
-    // bytes because the space after the stat file content are zeroed.
-    let mut i_comm_tail = None;
-    for (i, c) in buf[..88].iter().enumerate().rev() {
-        if *c == b')' {
-            i_comm_tail = Some(i);
-            break;
+/// Loads the nice value of the main thread of the process from "/proc/<pid>/stat".
+pub fn load_process_nice(process_id: ProcessId) -> Result<i32> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", process_id.0))?;
+    // The comm field may contain spaces and parentheses. The fields after the last ')' start from
+    // the 3rd field "state" and nice is the 19th field.
+    let (_, fields) = stat.rsplit_once(')').ok_or(Error::FormatCorrupt)?;
+    fields
+        .split_ascii_whitespace()
+        .nth(19 - 3)
+        .and_then(|nice| nice.parse().ok())
+        .ok_or(Error::FormatCorrupt)
+}
+
+/// Loads the effective uid of the process from "<proc_root>/<pid>/status".
+pub fn load_process_euid(proc_root: &Path, process_id: ProcessId) -> Result<u32> {
+    let file = File::open(proc_root.join(process_id.0.to_string()).join("status"))?;
+    let r = BufReader::with_capacity(1024, file);
+    for line in r.lines() {
+        let line = line.map_err(Error::Io)?;
+        // The real, effective, saved set and filesystem uids follow the tag.
+        const UID_TAG: &str = "Uid:";
+        if let Some(uids) = line.strip_prefix(UID_TAG) {
+            return uids
+                .split_ascii_whitespace()
+                .nth(1)
+                .and_then(|uid| uid.parse().ok())
+                .ok_or(Error::FormatCorrupt);
         }
     }
-    let Some(i_comm_tail) = i_comm_tail else {
-        return Err(Error::FormatCorrupt);
-    };
-
-    let mut prev_space = i_comm_tail + 1;
-    let mut starttime = None;
-    // `pid` and `comm` columns are consumed.
-    let mut column_idx = 2;
-    for (i, c) in buf[..n].iter().enumerate().skip(prev_space + 1) {
-        if *c == b' ' {
-            if column_idx == 21 {
-                // starttime is at 22th column.
-                starttime = Some(prev_space + 1..i);
-                break;
-            }
-            prev_space = i;
-            column_idx += 1;
+    Err(Error::FormatCorrupt)
+}
+
+/// Lists the threads of the process from "/proc/<pid>/task".
+pub fn load_thread_ids(process_id: ProcessId) -> Result<Vec<ThreadId>> {
+    let mut thread_ids = Vec::new();
+    for entry in std::fs::read_dir(format!("/proc/{}/task", process_id.0))? {
+        let entry = entry?;
+        // Skip entries which are not a thread id, if any.
+        if let Some(thread_id) = entry.file_name().to_str().and_then(|s| s.parse().ok()) {
+            thread_ids.push(ThreadId(thread_id));
         }
     }
-    let Some(starttime) = starttime else {
-        return Err(Error::FormatCorrupt);
-    };
-    let starttime = std::str::from_utf8(&buf[starttime]).map_err(|_| Error::FormatCorrupt)?;
-    let starttime = starttime.parse().map_err(|_| Error::FormatCorrupt)?;
-
-    Ok(starttime)
+    Ok(thread_ids)
 }
 
 pub fn load_tgid(thread_id: ThreadId) -> Result<ProcessId> {
@@ -152,11 +185,37 @@ impl ThreadChecker {
 
 #[cfg(test)]
 mod tests {
-    use std::io::Write;
-
     use super::*;
     use crate::test_utils::*;
 
+    #[test]
+    fn test_load_process_euid() {
+        let process_id = ProcessId(std::process::id());
+        // SAFETY: geteuid(2) has no side effects.
+        let euid = unsafe { libc::geteuid() };
+        assert_eq!(
+            load_process_euid(Path::new("/proc"), process_id).unwrap(),
+            euid
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let process_dir = dir.path().join("123");
+        std::fs::create_dir(&process_dir).unwrap();
+        std::fs::write(
+            process_dir.join("status"),
+            "Name:\tfoo\nUid:\t1000\t20104\t1000\t1000\n",
+        )
+        .unwrap();
+        assert_eq!(
+            load_process_euid(dir.path(), ProcessId(123)).unwrap(),
+            20104
+        );
+        assert!(matches!(
+            load_process_euid(dir.path(), ProcessId(456)).err().unwrap(),
+            Error::NotFound
+        ));
+    }
+
     #[test]
     fn test_load_process_timestamp() {
         let process_id = ProcessId(std::process::id());
@@ -176,6 +235,27 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn test_process_fd() {
+        let (process_id, _, process) = fork_process_for_test();
+        let Some(process_fd) = ProcessFd::open(process_id).unwrap() else {
+            // pidfd is not supported by the kernel.
+            return;
+        };
+        assert!(process_fd.is_alive());
+
+        drop(process);
+        assert!(!process_fd.is_alive());
+        assert!(matches!(
+            ProcessFd::open(process_id).err().unwrap(),
+            Error::NotFound
+        ));
+
+        // The pidfd keeps referring to the dead process after its pid is reused.
+        let (_, _, _process) = fork_process_reusing_pid_for_test(process_id);
+        assert!(!process_fd.is_alive());
+    }
+
     #[test]
     fn test_load_thread_timestamp() {
         let process_id = ProcessId(std::process::id());
@@ -210,71 +290,18 @@ mod tests {
     }
 
     #[test]
-    fn test_load_starttime() {
-        for (stat_file_content, starttime) in [
-            (
-                "9345 (resourced) S 1 9344 9344 0 -1 1077936384 599 0 0 0 2851 2468 0 0 20 0 1 0 \
-            41329081 19865600 2719 18446744073709551615 101386084483072 101386086716560 \
-            140736188509360 0 0 0 0 4096 1088 1 0 0 17 0 0 0 0 0 0 101386086981672 101386086982232 \
-            101386110365696 140736188513963 140736188513982 140736188513982 140736188514277 0",
-                41329081,
-            ),
-            // Shortest stat file.
-            ("1 (a) 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 ", 2),
-            // Longest pid + comm stat file.
-            (
-                "1234567890123456789 \
-            (1234567890123456789012345678901234567890123456789012345678901234) 3 4 5 6 7 8 9 0 1 2 \
-            3 4 5 6 7 8 9 0 1 123 ",
-                123,
-            ),
-            // comm contains spaces and parenthesis.
-            (
-                "1 ( a ( b ) c ) ) 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 456 ",
-                456,
-            ),
-        ] {
-            let mut file = tempfile::NamedTempFile::new().unwrap();
-            file.write_all(stat_file_content.as_bytes()).unwrap();
-            assert_eq!(
-                load_starttime(file.path()).unwrap(),
-                starttime,
-                "{}",
-                stat_file_content
-            );
-        }
-    }
-
-    #[test]
-    fn test_load_starttime_corrupt_format() {
-        for stat_file_content in [
-            // no comm parenthesis.
-            "1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2",
-            // starttime is not a number.
-            "1 (a) 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 a ",
-            // no 22th space
-            "1 (a) 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2",
-        ] {
-            let mut file = tempfile::NamedTempFile::new().unwrap();
-            file.write_all(stat_file_content.as_bytes()).unwrap();
-            assert!(
-                matches!(
-                    load_starttime(file.path()).err().unwrap(),
-                    Error::FormatCorrupt
-                ),
-                "{}",
-                stat_file_content
-            );
-        }
+    fn test_load_process_nice() {
+        let (process_id, _, process) = fork_process_for_test();
+        assert_eq!(load_process_nice(process_id).unwrap(), 0);
+        // SAFETY: setpriority(2) only changes the nice value of the child process.
+        let ret = unsafe { libc::setpriority(libc::PRIO_PROCESS, process_id.0, 5) };
+        assert_eq!(ret, 0);
+        assert_eq!(load_process_nice(process_id).unwrap(), 5);
 
-        let mut file = tempfile::NamedTempFile::new().unwrap();
-        file.write_all(b"1 (a) 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2")
-            .unwrap();
-        // non utf-8
-        file.write_all(&[255, b' ']).unwrap();
+        drop(process);
         assert!(matches!(
-            load_starttime(file.path()).err().unwrap(),
-            Error::FormatCorrupt
+            load_process_nice(process_id),
+            Err(Error::NotFound)
         ));
     }
 
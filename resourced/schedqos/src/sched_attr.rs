@@ -60,13 +60,39 @@ impl SchedAttrContext {
 
         sched_setattr(thread_id, &mut attr)
     }
+
+    /// Saves the current sched_attr of the thread to restore it later by
+    /// [Self::restore_thread_sched_attr].
+    pub fn save_thread_sched_attr(&self, thread_id: ThreadId) -> io::Result<SavedSchedAttr> {
+        let mut attr = sched_attr::default();
+        sched_getattr(thread_id, &mut attr)?;
+        Ok(SavedSchedAttr(attr))
+    }
+
+    pub fn restore_thread_sched_attr(
+        &self,
+        thread_id: ThreadId,
+        saved: &SavedSchedAttr,
+    ) -> io::Result<()> {
+        let mut attr = saved.0;
+        // sched_getattr(2) reports uclamp values without the flags. The flags
+        // are required to write them back.
+        if self.uclamp_support {
+            attr.sched_flags |= SCHED_FLAG_UTIL_CLAMP_MIN | SCHED_FLAG_UTIL_CLAMP_MAX;
+        }
+        sched_setattr(thread_id, &mut attr)
+    }
 }
 
+/// The sched_attr of a thread saved by [SchedAttrContext::save_thread_sched_attr].
+#[derive(Debug)]
+pub struct SavedSchedAttr(sched_attr);
+
 /// sched_attr defined in Linux.
 ///
 /// See `/include/uapi/linux/sched/types.h` of the Linux kernel.
 #[repr(C)]
-#[derive(PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 struct sched_attr {
     size: u32,
 
@@ -256,6 +282,7 @@ mod tests {
             let thread_config = ThreadStateConfig {
                 rt_priority: None,
                 nice,
+                nice_relative: false,
                 uclamp_min,
                 cpuset_cgroup: CpusetCgroup::All,
                 latency_sensitive: false,
@@ -298,6 +325,7 @@ mod tests {
             let thread_config = ThreadStateConfig {
                 rt_priority: Some(rt_priority),
                 nice,
+                nice_relative: false,
                 uclamp_min,
                 cpuset_cgroup: CpusetCgroup::All,
                 latency_sensitive: false,
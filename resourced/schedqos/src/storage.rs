@@ -15,6 +15,8 @@ pub trait ProcessContext {
     where
         Self: 'a;
     fn state(&self) -> ProcessState;
+    /// The starttime of the process when it was registered.
+    fn timestamp(&self) -> u64;
     fn thread_map(&mut self) -> Self::TM<'_>;
 }
 
@@ -34,18 +36,57 @@ pub trait ProcessMap {
         state: ProcessState,
     ) -> Option<Self::P<'_>>;
     fn get_process(&mut self, process_id: ProcessId) -> Option<Self::P<'_>>;
+    /// The timestamp and the state of a process, without borrowing the map mutably.
+    fn get_process_entry(&self, process_id: ProcessId) -> Option<ProcessEntry>;
+    /// Calls `f` with each thread of a process, without borrowing the map mutably.
+    ///
+    /// Does nothing if the process is not in the map.
+    fn for_each_thread<F>(&self, process_id: ProcessId, f: F)
+    where
+        F: FnMut(&ThreadId, &ThreadEntry);
+    /// Ids of all the processes in the map.
+    fn process_ids(&self) -> Vec<ProcessId>;
     /// Remove a process.
     ///
     /// `timestamp` is used to identify the process with `process_id` if it is `Option::Some`.
     /// Otherwise this does not check the stored timestamp in the map.
     fn remove_process(&mut self, process_id: ProcessId, timestamp: Option<u64>);
-    /// Reduce storage size by compacting holes left by deleted processes and threads.
+    /// Whether the map reached its capacity. A new process or thread must not be inserted while
+    /// the map is full.
+    fn is_full(&self) -> bool;
+    /// Reduce storage size by compacting holes left by deleted processes and threads if the
+    /// storage is fragmented beyond its compaction threshold.
     ///
     /// NOTE: compact() should be called on every process/thread context update. It still works
     /// without compact()ing, but next compact() will take longer time for accumulating removed
     /// contexts which will cause inconsistent latency of the process/thread context update latency
     /// and performance degradation. [The Tail at Scale](https://research.google/pubs/pub40801/).
     fn compact(&mut self);
+    /// Compact the storage regardless of the compaction threshold. e.g. on shutdown.
+    fn force_compact(&mut self);
+    /// The current usage of the storage.
+    fn stats(&self) -> StorageStats;
+}
+
+/// Usage of the cells of a [ProcessMap] storage.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StorageStats {
+    /// The number of cells holding live processes and threads.
+    pub n_used: usize,
+    /// The number of cells freed and not compacted yet.
+    pub n_freed: usize,
+}
+
+impl StorageStats {
+    /// The fraction of the cells which are freed. 0 if there is no cell.
+    pub fn fragmentation(&self) -> f64 {
+        let n_cells = self.n_used + self.n_freed;
+        if n_cells == 0 {
+            0.0
+        } else {
+            self.n_freed as f64 / n_cells as f64
+        }
+    }
 }
 
 pub trait ThreadMap {
@@ -67,6 +108,12 @@ pub trait ThreadMap {
     where
         F: FnMut(&ThreadId, &ThreadEntry) -> bool;
     fn remove_thread(&mut self, thread_id: ThreadId);
+    fn contains_thread(&self, thread_id: ThreadId) -> bool;
+}
+
+pub struct ProcessEntry {
+    pub timestamp: u64,
+    pub state: ProcessState,
 }
 
 pub struct ThreadEntry {
@@ -17,7 +17,9 @@ use crate::proc::load_process_timestamp;
 use crate::proc::load_tgid;
 use crate::proc::load_thread_timestamp;
 use crate::storage::ProcessContext;
+use crate::storage::ProcessEntry;
 use crate::storage::ProcessMap;
+use crate::storage::StorageStats;
 use crate::storage::ThreadEntry;
 use crate::storage::ThreadMap;
 use crate::ProcessId;
@@ -33,10 +35,14 @@ const STATE_OFFSET: usize = 4;
 const TYPE_OFFSET: usize = 5;
 const TIMESTAMP_OFFSET: usize = 8;
 
+/// The default upper bound of cells. The file grows up to 1 MiB including the header.
+const DEFAULT_MAX_CELLS: usize = 1024 * 1024 / CELL_SIZE - 1;
+
 #[derive(Debug)]
 pub enum Error {
     Io(io::Error),
     MalformedFile,
+    CapacityExceeded,
 }
 
 impl std::error::Error for Error {
@@ -44,6 +50,7 @@ impl std::error::Error for Error {
         match self {
             Self::Io(e) => Some(e),
             Self::MalformedFile => None,
+            Self::CapacityExceeded => None,
         }
     }
 }
@@ -53,6 +60,7 @@ impl Display for Error {
         match self {
             Self::Io(e) => f.write_fmt(format_args!("io: {e}")),
             Self::MalformedFile => f.write_str("file is malformed"),
+            Self::CapacityExceeded => f.write_str("storage capacity is exceeded"),
         }
     }
 }
@@ -65,6 +73,30 @@ impl From<io::Error> for Error {
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// How the file of [RestorableProcessMap] grows and shrinks.
+#[derive(Clone, Copy, Debug)]
+pub struct GrowthConfig {
+    /// The number of pages the file is extended by when all the cells are in use.
+    pub growth_pages: NonZeroUsize,
+    /// The maximum number of cells (i.e. processes and threads) in the file. This prevents a
+    /// misbehaving client from growing the file unboundedly.
+    pub max_cells: usize,
+    /// [ProcessMap::compact] only compacts the file when the fraction of freed cells exceeds
+    /// this. Freed cells are reused by new processes and threads in the meantime. 0 compacts
+    /// whenever a cell is freed.
+    pub compaction_threshold: f64,
+}
+
+impl Default for GrowthConfig {
+    fn default() -> Self {
+        Self {
+            growth_pages: NonZeroUsize::new(1).unwrap(),
+            max_cells: DEFAULT_MAX_CELLS,
+            compaction_threshold: 0.0,
+        }
+    }
+}
+
 #[inline]
 fn offset_to_cell_idx(offset: usize) -> usize {
     (offset / CELL_SIZE) - 1
@@ -106,13 +138,6 @@ pub struct RestorableProcessContext<'a> {
     entry: OccupiedEntry<'a, ProcessId, RestorableProcessEntry>,
 }
 
-#[cfg(test)]
-impl RestorableProcessContext<'_> {
-    fn timestamp(&self) -> u64 {
-        self.entry.get().cell.timestamp(self.storage)
-    }
-}
-
 impl<'a> ProcessContext for RestorableProcessContext<'a> {
     type TM<'b> = RestorableThreadMap<'b>  where Self: 'b;
     fn state(&self) -> ProcessState {
@@ -124,6 +149,10 @@ impl<'a> ProcessContext for RestorableProcessContext<'a> {
             .expect("invalid process state")
     }
 
+    fn timestamp(&self) -> u64 {
+        self.entry.get().cell.timestamp(self.storage)
+    }
+
     fn thread_map(&mut self) -> RestorableThreadMap {
         RestorableThreadMap {
             storage: self.storage,
@@ -136,11 +165,17 @@ impl<'a> ProcessContext for RestorableProcessContext<'a> {
 pub struct RestorableProcessMap {
     storage: RestorableStateStorage,
     map: HashMap<ProcessId, RestorableProcessEntry>,
+    n_pruned_on_load: usize,
 }
 
 impl RestorableProcessMap {
     /// Creates an empty [RestorableProcessMap].
     pub fn new(path: &Path) -> Result<Self> {
+        Self::new_with_config(path, GrowthConfig::default())
+    }
+
+    /// Creates an empty [RestorableProcessMap] which grows as `config`.
+    pub fn new_with_config(path: &Path, config: GrowthConfig) -> Result<Self> {
         let file = OpenOptions::new()
             .read(true)
             .write(true)
@@ -149,13 +184,23 @@ impl RestorableProcessMap {
         let size = NonZeroUsize::new(PAGE_SIZE).unwrap();
 
         Ok(Self {
-            storage: RestorableStateStorage::new(file, size)?,
+            storage: RestorableStateStorage::new(file, size, config)?,
             map: HashMap::new(),
+            n_pruned_on_load: 0,
         })
     }
 
     /// Load the file and creates [RestorableProcessMap].
     pub fn load(path: &Path) -> Result<Self> {
+        Self::load_with_config(path, GrowthConfig::default())
+    }
+
+    /// Load the file and creates [RestorableProcessMap] which grows as `config`.
+    ///
+    /// The number of cells in the header is validated against the file size. The file is always
+    /// extended before the header is updated, so a crash while growing leaves a file larger than
+    /// the header needs which is still valid.
+    pub fn load_with_config(path: &Path, config: GrowthConfig) -> Result<Self> {
         let file = OpenOptions::new().read(true).write(true).open(path)?;
         let mut size = file.metadata()?.len() as usize;
         if size % PAGE_SIZE != 0 {
@@ -166,7 +211,7 @@ impl RestorableProcessMap {
         }
         let size = NonZeroUsize::new(size).unwrap();
 
-        let mut storage = RestorableStateStorage::new(file, size)?;
+        let mut storage = RestorableStateStorage::new(file, size, config)?;
 
         let n_cells = storage.n_cells();
         if (n_cells + 1) * CELL_SIZE > storage.memory.len() {
@@ -247,19 +292,44 @@ impl RestorableProcessMap {
             }
         }
 
-        let mut process_map = RestorableProcessMap { storage, map };
-        process_map.compact();
+        let n_pruned_on_load = storage.freed_cells.len();
+        let mut process_map = RestorableProcessMap {
+            storage,
+            map,
+            n_pruned_on_load,
+        };
+        process_map.force_compact();
 
         Ok(process_map)
     }
 
+    /// The number of dead processes and threads dropped by [RestorableProcessMap::load].
+    pub fn n_pruned_on_load(&self) -> usize {
+        self.n_pruned_on_load
+    }
+
+    /// The number of processes and threads stored in the file.
+    pub fn len(&self) -> usize {
+        self.storage.n_cells() - self.storage.freed_cells.len()
+    }
+
+    /// Whether no process or thread is stored in the file.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The number of processes and threads the file can store without growing.
+    pub fn capacity(&self) -> usize {
+        self.storage.capacity()
+    }
+
     #[cfg(test)]
     pub fn n_cells(&self) -> usize {
         self.storage.n_cells()
     }
 
     #[cfg(test)]
-    pub fn len(&self) -> usize {
+    pub fn n_processes(&self) -> usize {
         self.map.len()
     }
 }
@@ -312,6 +382,43 @@ impl ProcessMap for RestorableProcessMap {
         }
     }
 
+    fn get_process_entry(&self, process_id: ProcessId) -> Option<ProcessEntry> {
+        self.map.get(&process_id).map(|process| ProcessEntry {
+            timestamp: process.cell.timestamp(&self.storage),
+            state: process
+                .cell
+                .state(&self.storage)
+                .try_into()
+                .expect("invalid process state"),
+        })
+    }
+
+    fn for_each_thread<F>(&self, process_id: ProcessId, mut f: F)
+    where
+        F: FnMut(&ThreadId, &ThreadEntry),
+    {
+        let Some(process) = self.map.get(&process_id) else {
+            return;
+        };
+        for (thread_id, thread) in &process.thread_map {
+            f(
+                thread_id,
+                &ThreadEntry {
+                    timestamp: thread.cell.timestamp(&self.storage),
+                    state: thread
+                        .cell
+                        .state(&self.storage)
+                        .try_into()
+                        .expect("invalid thread state"),
+                },
+            );
+        }
+    }
+
+    fn process_ids(&self) -> Vec<ProcessId> {
+        self.map.keys().copied().collect()
+    }
+
     fn remove_process(&mut self, process_id: ProcessId, timestamp: Option<u64>) {
         if let Entry::Occupied(entry) = self.map.entry(process_id) {
             if timestamp.is_none()
@@ -326,7 +433,17 @@ impl ProcessMap for RestorableProcessMap {
         }
     }
 
+    fn is_full(&self) -> bool {
+        self.len() >= self.storage.config.max_cells
+    }
+
     fn compact(&mut self) {
+        if self.stats().fragmentation() > self.storage.config.compaction_threshold {
+            self.force_compact();
+        }
+    }
+
+    fn force_compact(&mut self) {
         self.storage.freed_cells.sort_unstable();
         let mut n_cells = self.storage.n_cells();
         let mut i_head = 0;
@@ -376,8 +493,9 @@ impl ProcessMap for RestorableProcessMap {
         self.storage.process_ids.truncate(n_cells);
         self.storage.freed_cells.drain(..i_head);
 
-        // If there are more than 1 page unused, shrink the mmap.
-        let memory_size_threshold = (n_cells + 1) * CELL_SIZE + PAGE_SIZE;
+        // If there are more unused pages than a growth increment, shrink the mmap.
+        let memory_size_threshold =
+            (n_cells + 1) * CELL_SIZE + self.storage.config.growth_pages.get() * PAGE_SIZE;
         if memory_size_threshold < self.storage.memory.len() {
             let n_pages = memory_size_threshold / PAGE_SIZE;
             let new_size = NonZeroUsize::new(n_pages * PAGE_SIZE).unwrap();
@@ -388,6 +506,13 @@ impl ProcessMap for RestorableProcessMap {
                 .expect("failed to resize");
         }
     }
+
+    fn stats(&self) -> StorageStats {
+        StorageStats {
+            n_used: self.len(),
+            n_freed: self.storage.freed_cells.len(),
+        }
+    }
 }
 
 pub struct RestorableThreadMap<'a> {
@@ -468,6 +593,10 @@ impl ThreadMap for RestorableThreadMap<'_> {
             self.storage.free_cell(thread.cell.offset);
         }
     }
+
+    fn contains_thread(&self, thread_id: ThreadId) -> bool {
+        self.map.contains_key(&thread_id)
+    }
 }
 
 /// [RestorableStateStorage] stores each process/thread state in a file mmap(2)ed.
@@ -501,19 +630,26 @@ struct RestorableStateStorage {
     /// on `compact()`.
     process_ids: Vec<ProcessId>,
     freed_cells: Vec<usize>,
+    config: GrowthConfig,
 }
 
 impl RestorableStateStorage {
-    fn new(file: File, size: NonZeroUsize) -> Result<Self> {
+    fn new(file: File, size: NonZeroUsize, config: GrowthConfig) -> Result<Self> {
         let memory = Mmap::new(file, size)?;
 
         Ok(Self {
             memory,
             process_ids: Vec::new(),
             freed_cells: Vec::new(),
+            config,
         })
     }
 
+    fn capacity(&self) -> usize {
+        // The first entry is the header.
+        self.memory.len() / CELL_SIZE - 1
+    }
+
     fn n_cells(&self) -> usize {
         u64::from_ne_bytes(self.memory[0..8].try_into().unwrap()) as usize
     }
@@ -528,14 +664,23 @@ impl RestorableStateStorage {
             offset
         } else {
             let n_cells = self.n_cells() + 1;
-            self.set_n_cells(n_cells as u64);
+            debug_assert!(
+                n_cells <= self.config.max_cells,
+                "storage capacity is exceeded"
+            );
             let offset = n_cells * CELL_SIZE;
 
+            // Extend the file before updating the header so that the header never points beyond
+            // the end of the file even if the process crashes in the middle.
             if offset + CELL_SIZE > self.memory.len() {
-                let new_size = NonZeroUsize::new(self.memory.len() + PAGE_SIZE).unwrap();
+                let new_size = NonZeroUsize::new(
+                    self.memory.len() + self.config.growth_pages.get() * PAGE_SIZE,
+                )
+                .unwrap();
                 // The file is expected to be on tmpfs. truncate(2) and mremap(2) must not fail.
                 self.memory.resize(new_size).expect("failed to resize");
             }
+            self.set_n_cells(n_cells as u64);
 
             self.process_ids.push(process_id);
 
@@ -846,6 +991,33 @@ mod tests {
         assert_eq!(thread_map.len(), 0);
     }
 
+    #[test]
+    fn test_read_without_borrowing_mutably() {
+        let dir = tempfile::tempdir().unwrap();
+        let file_path = dir.path().join("states");
+        let mut map = RestorableProcessMap::new(&file_path).unwrap();
+        map.insert_or_update(ProcessId(1000), 12345, ProcessState::Normal);
+        let mut process = map.get_process(ProcessId(1000)).unwrap();
+        let mut thread_map = process.thread_map();
+        thread_map.insert_or_update(ThreadId(1000), 12345, ThreadState::Balanced, |_| true);
+        thread_map.insert_or_update(ThreadId(1001), 23456, ThreadState::Urgent, |_| true);
+
+        let process = map.get_process_entry(ProcessId(1000)).unwrap();
+        assert_eq!(process.timestamp, 12345);
+        assert_eq!(process.state, ProcessState::Normal);
+        assert!(map.get_process_entry(ProcessId(1001)).is_none());
+
+        let mut threads = Vec::new();
+        map.for_each_thread(ProcessId(1000), |thread_id, thread| {
+            threads.push((*thread_id, thread.state, thread.timestamp));
+        });
+        assert_eq!(threads.len(), 2);
+        assert!(threads.contains(&(ThreadId(1000), ThreadState::Balanced, 12345)));
+        assert!(threads.contains(&(ThreadId(1001), ThreadState::Urgent, 23456)));
+
+        map.for_each_thread(ProcessId(1001), |_, _| panic!("unknown process"));
+    }
+
     #[test]
     fn test_compact() {
         let dir = tempfile::tempdir().unwrap();
@@ -1067,7 +1239,7 @@ mod tests {
         let mut map = RestorableProcessMap::load(&file_path).unwrap();
 
         assert_eq!(map.n_cells(), 4);
-        assert_eq!(map.len(), 2);
+        assert_eq!(map.n_processes(), 2);
         let mut process = map.get_process(process_id).unwrap();
         assert_eq!(process.state(), ProcessState::Normal);
         assert_eq!(
@@ -1223,7 +1395,7 @@ mod tests {
         let mut map = RestorableProcessMap::load(&file_path).unwrap();
 
         assert_eq!(map.n_cells(), 4);
-        assert_eq!(map.len(), 2);
+        assert_eq!(map.n_processes(), 2);
         let mut process = map.get_process(process_id).unwrap();
         assert_eq!(process.state(), ProcessState::Normal);
         assert_eq!(
@@ -1292,4 +1464,150 @@ mod tests {
         assert_eq!(map.n_cells(), 0);
         assert!(map.map.is_empty());
     }
+
+    #[test]
+    fn test_grow_beyond_initial_capacity() {
+        let dir = tempfile::tempdir().unwrap();
+        let file_path = dir.path().join("states");
+        let config = GrowthConfig {
+            growth_pages: NonZeroUsize::new(2).unwrap(),
+            ..Default::default()
+        };
+        let mut map = RestorableProcessMap::new_with_config(&file_path, config).unwrap();
+        // The first entry is header.
+        assert_eq!(map.capacity(), PAGE_SIZE / CELL_SIZE - 1);
+
+        let process_id = ProcessId(std::process::id());
+        map.insert_or_update(
+            process_id,
+            load_process_timestamp(process_id).unwrap(),
+            ProcessState::Normal,
+        );
+        let threads: Vec<_> = (0..PAGE_SIZE / CELL_SIZE)
+            .map(|_| spawn_thread_for_test())
+            .collect();
+        for (thread_id, _) in &threads {
+            map.get_process(process_id)
+                .unwrap()
+                .thread_map()
+                .insert_or_update(
+                    *thread_id,
+                    load_thread_timestamp(process_id, *thread_id).unwrap(),
+                    ThreadState::Balanced,
+                    |_| true,
+                );
+            map.compact();
+        }
+        assert_eq!(map.len(), threads.len() + 1);
+        // The file is extended by 2 pages.
+        assert_eq!(map.capacity(), 3 * PAGE_SIZE / CELL_SIZE - 1);
+        assert_eq!(file_path.metadata().unwrap().len(), 3 * PAGE_SIZE as u64);
+        drop(map);
+
+        let mut map = RestorableProcessMap::load_with_config(&file_path, config).unwrap();
+        assert_eq!(map.n_pruned_on_load(), 0);
+        assert_eq!(map.len(), threads.len() + 1);
+        let mut process = map.get_process(process_id).unwrap();
+        let thread_map = process.thread_map();
+        assert_eq!(thread_map.len(), threads.len());
+        for (thread_id, _) in &threads {
+            let thread = thread_map.map.get(thread_id).unwrap();
+            assert_eq!(
+                thread.cell.state(thread_map.storage),
+                ThreadState::Balanced as u8
+            );
+            assert_eq!(
+                thread.cell.timestamp(thread_map.storage),
+                load_thread_timestamp(process_id, *thread_id).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_is_full() {
+        let dir = tempfile::tempdir().unwrap();
+        let file_path = dir.path().join("states");
+        let config = GrowthConfig {
+            max_cells: 3,
+            ..Default::default()
+        };
+        let mut map = RestorableProcessMap::new_with_config(&file_path, config).unwrap();
+
+        map.insert_or_update(ProcessId(1000), 12345, ProcessState::Normal);
+        map.insert_or_update(ProcessId(1001), 23456, ProcessState::Normal);
+        assert!(!map.is_full());
+        map.insert_or_update(ProcessId(1002), 34567, ProcessState::Normal);
+        assert!(map.is_full());
+        assert_eq!(map.len(), 3);
+
+        // Removed cells are available again.
+        map.remove_process(ProcessId(1000), None);
+        assert!(!map.is_full());
+        map.compact();
+        assert!(!map.is_full());
+        assert_eq!(map.len(), 2);
+    }
+
+    #[test]
+    fn test_compaction_threshold() {
+        let dir = tempfile::tempdir().unwrap();
+        let file_path = dir.path().join("states");
+        let config = GrowthConfig {
+            compaction_threshold: 0.25,
+            ..Default::default()
+        };
+        let mut map = RestorableProcessMap::new_with_config(&file_path, config).unwrap();
+        for i in 0..8 {
+            map.insert_or_update(ProcessId(1000 + i), 12345, ProcessState::Normal);
+        }
+        assert_eq!((map.stats().n_used, map.stats().n_freed), (8, 0));
+
+        // A freed cell is reused by the next process instead of being compacted.
+        for i in 8..100 {
+            map.remove_process(ProcessId(1000 + i - 8), None);
+            map.compact();
+            assert_eq!(map.n_cells(), 8);
+            assert_eq!((map.stats().n_used, map.stats().n_freed), (7, 1));
+            map.insert_or_update(ProcessId(1000 + i), 12345, ProcessState::Normal);
+            map.compact();
+            assert_eq!((map.stats().n_used, map.stats().n_freed), (8, 0));
+        }
+
+        map.remove_process(ProcessId(1092), None);
+        map.remove_process(ProcessId(1093), None);
+        map.compact();
+        assert_eq!(map.n_cells(), 8);
+        assert_eq!(map.stats().fragmentation(), 0.25);
+
+        // The third freed cell trips the threshold.
+        map.remove_process(ProcessId(1094), None);
+        map.compact();
+        assert_eq!(map.n_cells(), 5);
+        assert_eq!((map.stats().n_used, map.stats().n_freed), (5, 0));
+        for i in 95..100 {
+            assert!(map.get_process(ProcessId(1000 + i)).is_some());
+        }
+
+        map.remove_process(ProcessId(1095), None);
+        map.compact();
+        assert_eq!(map.n_cells(), 5);
+        map.force_compact();
+        assert_eq!(map.n_cells(), 4);
+        assert_eq!(map.stats().fragmentation(), 0.0);
+    }
+
+    #[test]
+    fn test_load_header_beyond_file_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let file_path = dir.path().join("states");
+        let mut map = RestorableProcessMap::new(&file_path).unwrap();
+        // Emulate a crash after the header is updated but before the file is extended.
+        map.storage.set_n_cells((PAGE_SIZE / CELL_SIZE) as u64);
+        drop(map);
+
+        assert!(matches!(
+            RestorableProcessMap::load(&file_path),
+            Err(Error::MalformedFile)
+        ));
+    }
 }
@@ -7,7 +7,9 @@ use std::collections::hash_map::OccupiedEntry;
 use std::collections::HashMap;
 
 use super::ProcessContext;
+use crate::storage::ProcessEntry;
 use crate::storage::ProcessMap;
+use crate::storage::StorageStats;
 use crate::storage::ThreadEntry;
 use crate::storage::ThreadMap;
 use crate::ProcessId;
@@ -32,6 +34,10 @@ impl<'a> ProcessContext for SimpleProcessContext<'a> {
         self.get().state
     }
 
+    fn timestamp(&self) -> u64 {
+        self.get().timestamp
+    }
+
     fn thread_map(&mut self) -> SimpleThreadMap {
         &mut self.get_mut().thread_map
     }
@@ -71,6 +77,29 @@ impl ProcessMap for SimpleProcessMap {
         }
     }
 
+    fn get_process_entry(&self, process_id: ProcessId) -> Option<ProcessEntry> {
+        self.get(&process_id).map(|process| ProcessEntry {
+            timestamp: process.timestamp,
+            state: process.state,
+        })
+    }
+
+    fn for_each_thread<F>(&self, process_id: ProcessId, mut f: F)
+    where
+        F: FnMut(&ThreadId, &ThreadEntry),
+    {
+        let Some(process) = self.get(&process_id) else {
+            return;
+        };
+        for (thread_id, thread) in &process.thread_map {
+            f(thread_id, thread);
+        }
+    }
+
+    fn process_ids(&self) -> Vec<ProcessId> {
+        self.keys().copied().collect()
+    }
+
     fn remove_process(&mut self, process_id: ProcessId, timestamp: Option<u64>) {
         if let Entry::Occupied(entry) = self.entry(process_id) {
             if timestamp.is_none() || entry.get().timestamp == timestamp.unwrap() {
@@ -79,9 +108,27 @@ impl ProcessMap for SimpleProcessMap {
         }
     }
 
+    fn is_full(&self) -> bool {
+        false
+    }
+
     fn compact(&mut self) {
         // No-op.
     }
+
+    fn force_compact(&mut self) {
+        // No-op.
+    }
+
+    fn stats(&self) -> StorageStats {
+        StorageStats {
+            n_used: self
+                .values()
+                .map(|process| 1 + process.thread_map.len())
+                .sum(),
+            n_freed: 0,
+        }
+    }
 }
 
 impl ThreadMap for SimpleThreadMap<'_> {
@@ -113,6 +160,10 @@ impl ThreadMap for SimpleThreadMap<'_> {
     fn remove_thread(&mut self, thread_id: ThreadId) {
         self.remove(&thread_id);
     }
+
+    fn contains_thread(&self, thread_id: ThreadId) -> bool {
+        self.contains_key(&thread_id)
+    }
 }
 
 #[cfg(test)]
@@ -287,4 +338,29 @@ mod tests {
         assert!(threads.contains(&(ThreadId(1002), ThreadState::UrgentBursty, 34567)));
         assert_eq!(thread_map.len(), 0);
     }
+
+    #[test]
+    fn test_read_without_borrowing_mutably() {
+        let mut map = SimpleProcessMap::new();
+        map.insert_or_update(ProcessId(1000), 12345, ProcessState::Normal);
+        let mut process = map.get_process(ProcessId(1000)).unwrap();
+        let mut thread_map = process.thread_map();
+        thread_map.insert_or_update(ThreadId(1000), 12345, ThreadState::Balanced, |_| true);
+        thread_map.insert_or_update(ThreadId(1001), 23456, ThreadState::Urgent, |_| true);
+
+        let process = map.get_process_entry(ProcessId(1000)).unwrap();
+        assert_eq!(process.timestamp, 12345);
+        assert_eq!(process.state, ProcessState::Normal);
+        assert!(map.get_process_entry(ProcessId(1001)).is_none());
+
+        let mut threads = Vec::new();
+        map.for_each_thread(ProcessId(1000), |thread_id, thread| {
+            threads.push((*thread_id, thread.state, thread.timestamp));
+        });
+        assert_eq!(threads.len(), 2);
+        assert!(threads.contains(&(ThreadId(1000), ThreadState::Balanced, 12345)));
+        assert!(threads.contains(&(ThreadId(1001), ThreadState::Urgent, 23456)));
+
+        map.for_each_thread(ProcessId(1001), |_, _| panic!("unknown process"));
+    }
 }
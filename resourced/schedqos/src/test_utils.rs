@@ -13,6 +13,7 @@ use std::thread::sleep;
 use std::thread::JoinHandle;
 use std::time::Duration;
 
+use crate::proc::load_thread_ids;
 use crate::proc::ThreadChecker;
 pub use crate::sched_attr::assert_sched_attr;
 pub use crate::sched_attr::SchedAttrChecker;
@@ -26,6 +27,10 @@ pub struct FakeCgroupFiles {
     pub cpu_background: File,
     pub cpuset_all: File,
     pub cpuset_efficient: File,
+    pub cpuset_background_all: File,
+    pub cpuset_background_efficient: File,
+    pub memory_normal: File,
+    pub memory_background: File,
 }
 
 fn create_fake_file_pair() -> (File, File) {
@@ -44,22 +49,45 @@ fn create_fake_file_pair() -> (File, File) {
 /// [FakeCgroupFiles] must be retained while [CgroupContext] is used. Otherwise writes fail as
 /// `ECONNREFUSED`.
 pub fn create_fake_cgroup_context_pair() -> (CgroupContext, FakeCgroupFiles) {
+    create_fake_cgroup_context_pair_with(false)
+}
+
+/// Like [create_fake_cgroup_context_pair] but with the cpuset cgroups nested in the cpu cgroups as
+/// on cgroup v2.
+pub fn create_fake_nested_cgroup_context_pair() -> (CgroupContext, FakeCgroupFiles) {
+    create_fake_cgroup_context_pair_with(true)
+}
+
+fn create_fake_cgroup_context_pair_with(nested_cpusets: bool) -> (CgroupContext, FakeCgroupFiles) {
     let cpu_normal = create_fake_file_pair();
     let cpu_background = create_fake_file_pair();
     let cpuset_all = create_fake_file_pair();
     let cpuset_efficient = create_fake_file_pair();
+    let cpuset_background_all = create_fake_file_pair();
+    let cpuset_background_efficient = create_fake_file_pair();
+    let memory_normal = create_fake_file_pair();
+    let memory_background = create_fake_file_pair();
     (
         CgroupContext {
             cpu_normal: cpu_normal.0,
             cpu_background: cpu_background.0,
             cpuset_all: cpuset_all.0,
             cpuset_efficient: cpuset_efficient.0,
+            cpuset_background_all: nested_cpusets.then_some(cpuset_background_all.0),
+            cpuset_background_efficient: nested_cpusets.then_some(cpuset_background_efficient.0),
+            memory_normal: Some(memory_normal.0),
+            memory_background: Some(memory_background.0),
+            verify_writes: false,
         },
         FakeCgroupFiles {
             cpu_normal: cpu_normal.1,
             cpu_background: cpu_background.1,
             cpuset_all: cpuset_all.1,
             cpuset_efficient: cpuset_efficient.1,
+            cpuset_background_all: cpuset_background_all.1,
+            cpuset_background_efficient: cpuset_background_efficient.1,
+            memory_normal: memory_normal.1,
+            memory_background: memory_background.1,
         },
     )
 }
@@ -178,3 +206,82 @@ pub fn fork_process_for_test() -> (ProcessId, ThreadId, ProcessForTest) {
         },
     )
 }
+
+/// Forks a process which reuses the pid of the dead `process_id`.
+///
+/// The next pid is set through ns_last_pid, which needs CAP_SYS_ADMIN. Without it the new process
+/// gets an arbitrary pid.
+pub fn fork_process_reusing_pid_for_test(
+    process_id: ProcessId,
+) -> (ProcessId, ThreadId, ProcessForTest) {
+    let _ = std::fs::write(
+        "/proc/sys/kernel/ns_last_pid",
+        (process_id.0 - 1).to_string(),
+    );
+    fork_process_for_test()
+}
+
+const THREAD_STACK_SIZE: usize = 64 * 1024;
+
+extern "C" fn sleep_forever(_: *mut libc::c_void) -> libc::c_int {
+    loop {
+        // The thread is not a pthread and has no TLS of its own, so use the raw syscall.
+        // SAFETY: pause(2) only waits for a signal.
+        unsafe { libc::syscall(libc::SYS_pause) };
+    }
+}
+
+/// Forks a process with `num_threads` threads in addition to its main thread.
+///
+/// Returns the ids of all the threads of the process including the main thread.
+pub fn fork_process_with_threads_for_test(
+    num_threads: usize,
+) -> (ProcessId, Vec<ThreadId>, ProcessForTest) {
+    // Tests run in parallel, so other threads may hold the allocator lock or other locks during
+    // the fork. The child must not use them, so the stacks of its threads are allocated here.
+    let mut stacks: Vec<Vec<u8>> = (0..num_threads)
+        .map(|_| vec![0; THREAD_STACK_SIZE])
+        .collect();
+    // SAFETY: The child only calls async-signal-safe functions. Its threads are created by
+    // clone(2) instead of std::thread::spawn(), which allocates.
+    let child_process_id = unsafe { libc::fork() };
+    if child_process_id == 0 {
+        for stack in &mut stacks {
+            // SAFETY: The thread only runs sleep_forever() on its own stack, which is never freed
+            // in the child.
+            let thread_id = unsafe {
+                libc::clone(
+                    sleep_forever,
+                    stack.as_mut_ptr_range().end.cast(),
+                    libc::CLONE_VM
+                        | libc::CLONE_FS
+                        | libc::CLONE_FILES
+                        | libc::CLONE_SIGHAND
+                        | libc::CLONE_THREAD
+                        | libc::CLONE_SYSVSEM,
+                    std::ptr::null_mut(),
+                )
+            };
+            if thread_id < 0 {
+                // SAFETY: _exit(2) terminates the child without running anything else.
+                unsafe { libc::_exit(1) };
+            }
+        }
+        loop {
+            std::thread::sleep(Duration::from_secs(1));
+        }
+    }
+    assert!(child_process_id > 0);
+    let child_process_id = ProcessId(child_process_id as u32);
+    let process = ProcessForTest {
+        process_id: child_process_id,
+    };
+    for _ in 0..1000 {
+        let thread_ids = load_thread_ids(child_process_id).unwrap();
+        if thread_ids.len() == num_threads + 1 {
+            return (child_process_id, thread_ids, process);
+        }
+        sleep(Duration::from_millis(1));
+    }
+    panic!("threads are not spawned");
+}
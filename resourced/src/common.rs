@@ -9,6 +9,8 @@ use std::io::BufReader;
 use std::path::Path;
 use std::path::PathBuf;
 use std::sync::Mutex;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
 
 use anyhow::bail;
 use anyhow::Context;
@@ -65,6 +67,57 @@ pub fn read_file_to_u64<P: AsRef<Path>>(filename: P) -> Result<u64> {
     parse_file_to_u64(reader)
 }
 
+pub const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// Source of the current time, replaceable in tests.
+pub trait Clock {
+    /// Milliseconds of CLOCK_MONOTONIC.
+    fn now_ms(&self) -> i64;
+    /// Wall clock time in seconds since the unix epoch.
+    fn now(&self) -> i64;
+    /// Ordinal of the local calendar day containing `time`, in seconds since the unix epoch. Later
+    /// days have larger ordinals.
+    fn local_day(&self, time: i64) -> Result<i64>;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_ms(&self) -> i64 {
+        let mut ts = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        // SAFETY: Safe because the only side-effects of this function are modifications via the
+        // passed pointer, and we pass a pointer of the proper type.
+        let result =
+            unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts as *mut libc::timespec) };
+        if result != 0 {
+            error!("Failed to get current time.");
+        }
+        ts.tv_sec * 1000 + ts.tv_nsec / 1_000_000
+    }
+
+    fn now(&self) -> i64 {
+        match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(duration) => duration.as_secs() as i64,
+            Err(e) => -(e.duration().as_secs() as i64),
+        }
+    }
+
+    fn local_day(&self, time: i64) -> Result<i64> {
+        let time = time as libc::time_t;
+        // SAFETY: libc::tm is plain old data, so all zeros is a valid value.
+        let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+        // SAFETY: localtime_r only writes to `tm` which outlives the call.
+        if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+            bail!("localtime_r failed for {}", time);
+        }
+        // The UTC offset accounts for the timezone and daylight saving time at `time`.
+        Ok((time as i64 + tm.tm_gmtoff as i64).div_euclid(SECONDS_PER_DAY))
+    }
+}
+
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum GameMode {
     // Game mode is off.
@@ -630,7 +683,6 @@ mod tests {
 
     #[test]
     fn test_initialize_feature_in_default_state() {
-
         feature::init_for_test();
         assert!(feature::initialize_feature("FakeFeatureDisabled", false).is_ok());
         assert!(!feature::is_feature_enabled("FakeFeatureDisabled").unwrap());
@@ -179,7 +179,7 @@ pub enum PowerPreferencesType {
 }
 
 impl PowerPreferencesType {
-    fn dir_name(&self) -> &'static str {
+    pub fn dir_name(&self) -> &'static str {
         match self {
             PowerPreferencesType::Default => "default-power-preferences",
             PowerPreferencesType::WebRTC => "web-rtc-power-preferences",
@@ -199,7 +199,7 @@ pub enum PowerSourceType {
 }
 
 impl PowerSourceType {
-    fn dir_name(&self) -> &'static str {
+    pub fn dir_name(&self) -> &'static str {
         match self {
             PowerSourceType::AC => "ac",
             PowerSourceType::DC => "dc",
@@ -7,14 +7,20 @@ use std::io::BufReader;
 use std::path::Path;
 use std::path::PathBuf;
 use std::str;
+use std::str::FromStr;
+use std::sync::Mutex;
 
 use anyhow::bail;
+use anyhow::Context;
 use anyhow::Result;
 use glob::glob;
 use log::info;
+use once_cell::sync::Lazy;
 use regex::Regex;
 
 use crate::common;
+use crate::config::EnergyPerformancePreference;
+use crate::config::PowerSourceType;
 
 /// Base path for power_limit relative to rootdir.
 const DEVICE_POWER_LIMIT_PATH: &str = "sys/class/powercap/intel-rapl:0";
@@ -22,6 +28,12 @@ const DEVICE_POWER_LIMIT_PATH: &str = "sys/class/powercap/intel-rapl:0";
 /// Base path for cpufreq relative to rootdir.
 const DEVICE_CPUFREQ_PATH: &str = "sys/devices/system/cpu/cpufreq";
 
+/// Base path for the intel_pstate global tunables relative to rootdir.
+const INTEL_PSTATE_PATH: &str = "sys/devices/system/cpu/intel_pstate";
+
+/// The min performance of the performance profile on intel_pstate, in percent of the max.
+const INTEL_PERFORMANCE_MIN_PERF_PCT: u32 = 50;
+
 /// The threshold divsor for the minimum difference between min and max freq
 const CPU_DIFF_THRESHOLD_DIVISOR: i32 = 4;
 
@@ -441,14 +453,310 @@ impl DeviceCpuStatus {
     }
 }
 
+/// The cpufreq scaling drivers that have dedicated tunables for the [CpuScalingProfile]s.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CpuScalingDriver {
+    /// intel_pstate in active mode, which drives the Intel HWP (Hardware P-states).
+    IntelPstate,
+    /// amd-pstate or amd-pstate-epp, which drive the AMD CPPC (Collaborative Processor
+    /// Performance Control).
+    AmdPstate,
+    /// Any other driver, e.g. acpi-cpufreq or intel_cpufreq (intel_pstate in passive mode).
+    Generic,
+}
+
+impl CpuScalingDriver {
+    /// Detects the scaling driver from the `scaling_driver` of policy0.
+    pub fn detect(backend: &dyn CpuScalingBackend) -> Result<CpuScalingDriver> {
+        let path = Path::new(DEVICE_CPUFREQ_PATH).join("policy0/scaling_driver");
+        let driver = backend
+            .read(&path)
+            .context("Failed to read the cpufreq scaling driver")?;
+        Ok(match driver.trim_end_matches('\n') {
+            "intel_pstate" => CpuScalingDriver::IntelPstate,
+            "amd-pstate" | "amd-pstate-epp" => CpuScalingDriver::AmdPstate,
+            _ => CpuScalingDriver::Generic,
+        })
+    }
+}
+
+/// Named CPU scaling profiles, applied by the power preferences manager.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CpuScalingProfile {
+    Performance,
+    Balanced,
+    PowerSaver,
+}
+
+impl CpuScalingProfile {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CpuScalingProfile::Performance => "performance",
+            CpuScalingProfile::Balanced => "balanced",
+            CpuScalingProfile::PowerSaver => "power-saver",
+        }
+    }
+
+    /// The profile applied when the system switches to `power_source`.
+    pub fn for_power_source(power_source: PowerSourceType) -> CpuScalingProfile {
+        match power_source {
+            PowerSourceType::AC => CpuScalingProfile::Balanced,
+            PowerSourceType::DC => CpuScalingProfile::PowerSaver,
+        }
+    }
+
+    // Balanced and power-saver use the same EPP values as the power preferences on AC and on DC
+    // with video playback.
+    pub fn epp(&self) -> EnergyPerformancePreference {
+        match self {
+            CpuScalingProfile::Performance => EnergyPerformancePreference::Performance,
+            CpuScalingProfile::Balanced => EnergyPerformancePreference::BalancePerformance,
+            CpuScalingProfile::PowerSaver => EnergyPerformancePreference::BalancePower,
+        }
+    }
+
+    // The min and max performance in percent of the max HWP performance.
+    fn hwp_perf_pct(&self) -> (u32, u32) {
+        match self {
+            CpuScalingProfile::Performance => (INTEL_PERFORMANCE_MIN_PERF_PCT, 100),
+            CpuScalingProfile::Balanced | CpuScalingProfile::PowerSaver => (0, 100),
+        }
+    }
+
+    fn governor(&self) -> &'static str {
+        match self {
+            CpuScalingProfile::Performance => "performance",
+            CpuScalingProfile::Balanced => "schedutil",
+            CpuScalingProfile::PowerSaver => "powersave",
+        }
+    }
+}
+
+impl FromStr for CpuScalingProfile {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "performance" => Ok(CpuScalingProfile::Performance),
+            "balanced" => Ok(CpuScalingProfile::Balanced),
+            "power-saver" => Ok(CpuScalingProfile::PowerSaver),
+            _ => bail!("Unknown cpu scaling profile: {}", s),
+        }
+    }
+}
+
+/// Access to the sysfs nodes written by the [CpuScalingProfile]s. All the paths are relative to
+/// the root so that unit tests can verify the writes without touching the system.
+pub trait CpuScalingBackend {
+    fn read(&self, path: &Path) -> Result<String>;
+    fn write(&self, path: &Path, value: &str) -> Result<()>;
+    fn exists(&self, path: &Path) -> bool;
+    /// Returns the cpufreq policy directories.
+    fn policies(&self) -> Result<Vec<PathBuf>>;
+}
+
+pub struct SysfsCpuScalingBackend {
+    root: PathBuf,
+}
+
+impl SysfsCpuScalingBackend {
+    pub fn new(root: &Path) -> Self {
+        SysfsCpuScalingBackend {
+            root: root.to_path_buf(),
+        }
+    }
+}
+
+impl CpuScalingBackend for SysfsCpuScalingBackend {
+    fn read(&self, path: &Path) -> Result<String> {
+        let path = self.root.join(path);
+        std::fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))
+    }
+
+    fn write(&self, path: &Path, value: &str) -> Result<()> {
+        // Skip writing the current value to avoid permission errors on nodes that are not owned
+        // by resourced.
+        if let Ok(current) = self.read(path) {
+            if current.trim_end_matches('\n') == value {
+                return Ok(());
+            }
+        }
+        let path = self.root.join(path);
+        std::fs::write(&path, value)
+            .with_context(|| format!("Failed to write {} to {}", value, path.display()))
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.root.join(path).exists()
+    }
+
+    fn policies(&self) -> Result<Vec<PathBuf>> {
+        let pattern = self.root.join(DEVICE_CPUFREQ_PATH).join("policy*");
+        let pattern = pattern
+            .to_str()
+            .context("Cannot convert cpufreq policy path to string")?;
+        let mut policies = Vec::new();
+        for entry in glob(pattern)? {
+            policies.push(entry?.strip_prefix(&self.root)?.to_path_buf());
+        }
+        Ok(policies)
+    }
+}
+
+fn read_policy_freq(backend: &dyn CpuScalingBackend, policy: &Path, attr: &str) -> Result<u64> {
+    let value = backend.read(&policy.join(attr))?;
+    value
+        .trim_end_matches('\n')
+        .parse::<u64>()
+        .with_context(|| format!("Failed to parse {} of {}", attr, policy.display()))
+}
+
+fn write_policy_epp(
+    backend: &dyn CpuScalingBackend,
+    policy: &Path,
+    epp: EnergyPerformancePreference,
+) -> Result<()> {
+    let epp_path = policy.join("energy_performance_preference");
+    // The EPP is only available with HWP on Intel and with amd-pstate-epp on AMD.
+    if backend.exists(&epp_path) {
+        backend.write(&epp_path, epp.name())?;
+    }
+    Ok(())
+}
+
+fn apply_intel_hwp_profile(
+    backend: &dyn CpuScalingBackend,
+    profile: CpuScalingProfile,
+    epp: EnergyPerformancePreference,
+) -> Result<()> {
+    let (min_perf_pct, max_perf_pct) = profile.hwp_perf_pct();
+    let intel_pstate = Path::new(INTEL_PSTATE_PATH);
+    // Raise the max first, the driver clamps min_perf_pct to max_perf_pct.
+    backend.write(
+        &intel_pstate.join("max_perf_pct"),
+        &max_perf_pct.to_string(),
+    )?;
+    backend.write(
+        &intel_pstate.join("min_perf_pct"),
+        &min_perf_pct.to_string(),
+    )?;
+    for policy in backend.policies()? {
+        write_policy_epp(backend, &policy, epp)?;
+    }
+    Ok(())
+}
+
+fn apply_amd_cppc_profile(
+    backend: &dyn CpuScalingBackend,
+    profile: CpuScalingProfile,
+    epp: EnergyPerformancePreference,
+) -> Result<()> {
+    for policy in backend.policies()? {
+        let min_freq = match profile {
+            // The lowest nonlinear frequency is the most power efficient one, below it the
+            // performance drops faster than the power.
+            CpuScalingProfile::Performance => {
+                read_policy_freq(backend, &policy, "amd_pstate_lowest_nonlinear_freq")?
+            }
+            CpuScalingProfile::Balanced | CpuScalingProfile::PowerSaver => {
+                read_policy_freq(backend, &policy, "cpuinfo_min_freq")?
+            }
+        };
+        let max_freq = read_policy_freq(backend, &policy, "cpuinfo_max_freq")?;
+        backend.write(&policy.join("scaling_max_freq"), &max_freq.to_string())?;
+        backend.write(&policy.join("scaling_min_freq"), &min_freq.to_string())?;
+        write_policy_epp(backend, &policy, epp)?;
+    }
+    Ok(())
+}
+
+fn apply_governor_profile(
+    backend: &dyn CpuScalingBackend,
+    profile: CpuScalingProfile,
+) -> Result<()> {
+    for policy in backend.policies()? {
+        backend.write(&policy.join("scaling_governor"), profile.governor())?;
+    }
+    Ok(())
+}
+
+/// Applies `profile` with the tunables of the detected scaling driver, using `epp` as the energy
+/// performance preference. Unknown drivers fall back to the cpufreq scaling governors.
+pub fn apply_cpu_scaling_profile(
+    backend: &dyn CpuScalingBackend,
+    profile: CpuScalingProfile,
+    epp: EnergyPerformancePreference,
+) -> Result<CpuScalingDriver> {
+    let driver = CpuScalingDriver::detect(backend)?;
+    info!(
+        "Applying cpu scaling profile {} with {:?}",
+        profile.name(),
+        driver
+    );
+    match driver {
+        CpuScalingDriver::IntelPstate => apply_intel_hwp_profile(backend, profile, epp)?,
+        CpuScalingDriver::AmdPstate => apply_amd_cppc_profile(backend, profile, epp)?,
+        CpuScalingDriver::Generic => apply_governor_profile(backend, profile)?,
+    }
+    Ok(driver)
+}
+
+// The profile set with `set_cpu_scaling_profile_override`, which takes precedence over the power
+// source profiles.
+static PROFILE_OVERRIDE: Lazy<Mutex<Option<CpuScalingProfile>>> = Lazy::new(|| Mutex::new(None));
+
+/// Applies the profile of `power_source` unless it is overridden. `epp` replaces the energy
+/// performance preference of the profile if set.
+///
+/// On generic drivers the scaling governor follows the power preferences of the config instead,
+/// so the power source profile is only applied when it is overridden. Returns whether the EPP was
+/// applied, which is only the case with the HWP and CPPC drivers.
+pub fn apply_power_source_profile(
+    backend: &dyn CpuScalingBackend,
+    power_source: PowerSourceType,
+    epp: Option<EnergyPerformancePreference>,
+) -> Result<bool> {
+    // Without cpufreq there is nothing to scale.
+    if !backend.exists(&Path::new(DEVICE_CPUFREQ_PATH).join("policy0/scaling_driver")) {
+        return Ok(false);
+    }
+    let Ok(profile_override) = PROFILE_OVERRIDE.lock() else {
+        bail!("Failed to get cpu scaling profile override");
+    };
+    let profile = match *profile_override {
+        Some(profile) => profile,
+        None => {
+            if CpuScalingDriver::detect(backend)? == CpuScalingDriver::Generic {
+                return Ok(false);
+            }
+            CpuScalingProfile::for_power_source(power_source)
+        }
+    };
+    let driver = apply_cpu_scaling_profile(backend, profile, epp.unwrap_or(profile.epp()))?;
+    Ok(driver != CpuScalingDriver::Generic)
+}
+
+/// Overrides the power source profiles with `profile`, or restores them if `profile` is None.
+/// The profile is applied on the next update of the power preferences.
+pub fn set_cpu_scaling_profile_override(profile: Option<CpuScalingProfile>) -> Result<()> {
+    match PROFILE_OVERRIDE.lock() {
+        Ok(mut profile_override) => *profile_override = profile,
+        Err(_) => bail!("Failed to set cpu scaling profile override"),
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
+    use std::cell::RefCell;
+    use std::collections::HashMap;
     use std::collections::HashSet;
 
     use anyhow::Result;
     use tempfile::tempdir;
 
     use super::*;
+    use crate::test_utils::DEVICE_CPUFREQ_PATH;
     use crate::test_utils::*;
 
     const MOCK_NUM_CPU: i32 = 16;
@@ -586,4 +894,313 @@ mod tests {
 
         Ok(())
     }
+
+    /// Records the writes instead of touching sysfs.
+    struct FakeCpuScalingBackend {
+        files: HashMap<PathBuf, String>,
+        num_policies: u32,
+        writes: RefCell<Vec<(PathBuf, String)>>,
+    }
+
+    impl FakeCpuScalingBackend {
+        fn new(driver: &str, num_policies: u32) -> Self {
+            let mut files = HashMap::new();
+            files.insert(
+                Path::new(DEVICE_CPUFREQ_PATH).join("policy0/scaling_driver"),
+                format!("{}\n", driver),
+            );
+            FakeCpuScalingBackend {
+                files,
+                num_policies,
+                writes: RefCell::new(Vec::new()),
+            }
+        }
+
+        fn add_policy_file(&mut self, attr: &str, value: &str) {
+            for policy in self.policies().unwrap() {
+                self.files.insert(policy.join(attr), format!("{}\n", value));
+            }
+        }
+
+        fn take_writes(&self) -> Vec<(String, String)> {
+            self.writes
+                .take()
+                .into_iter()
+                .map(|(path, value)| (path.display().to_string(), value))
+                .collect()
+        }
+    }
+
+    impl CpuScalingBackend for FakeCpuScalingBackend {
+        fn read(&self, path: &Path) -> Result<String> {
+            self.files
+                .get(path)
+                .cloned()
+                .with_context(|| format!("{} does not exist", path.display()))
+        }
+
+        fn write(&self, path: &Path, value: &str) -> Result<()> {
+            self.writes
+                .borrow_mut()
+                .push((path.to_path_buf(), value.to_string()));
+            Ok(())
+        }
+
+        fn exists(&self, path: &Path) -> bool {
+            self.files.contains_key(path)
+        }
+
+        fn policies(&self) -> Result<Vec<PathBuf>> {
+            Ok((0..self.num_policies)
+                .map(|i| Path::new(DEVICE_CPUFREQ_PATH).join(format!("policy{}", i)))
+                .collect())
+        }
+    }
+
+    fn writes(expected: &[(&str, &str)]) -> Vec<(String, String)> {
+        expected
+            .iter()
+            .map(|(path, value)| (path.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_cpu_scaling_driver_detect() {
+        let tests = [
+            ("intel_pstate", CpuScalingDriver::IntelPstate),
+            ("amd-pstate", CpuScalingDriver::AmdPstate),
+            ("amd-pstate-epp", CpuScalingDriver::AmdPstate),
+            ("intel_cpufreq", CpuScalingDriver::Generic),
+            ("acpi-cpufreq", CpuScalingDriver::Generic),
+            ("", CpuScalingDriver::Generic),
+        ];
+        for (driver, expected) in tests {
+            let backend = FakeCpuScalingBackend::new(driver, 1);
+            assert_eq!(CpuScalingDriver::detect(&backend).unwrap(), expected);
+        }
+
+        let root = tempdir().unwrap();
+        let backend = SysfsCpuScalingBackend::new(root.path());
+        assert!(CpuScalingDriver::detect(&backend).is_err());
+
+        let policy0 = root.path().join(DEVICE_CPUFREQ_PATH).join("policy0");
+        std::fs::create_dir_all(&policy0).unwrap();
+        std::fs::write(policy0.join("scaling_driver"), "amd-pstate-epp\n").unwrap();
+        assert_eq!(
+            CpuScalingDriver::detect(&backend).unwrap(),
+            CpuScalingDriver::AmdPstate
+        );
+    }
+
+    #[test]
+    fn test_cpu_scaling_profile_parse() {
+        for profile in [
+            CpuScalingProfile::Performance,
+            CpuScalingProfile::Balanced,
+            CpuScalingProfile::PowerSaver,
+        ] {
+            assert_eq!(
+                profile.name().parse::<CpuScalingProfile>().unwrap(),
+                profile
+            );
+        }
+        assert!("powersave".parse::<CpuScalingProfile>().is_err());
+    }
+
+    #[test]
+    fn test_cpu_scaling_profile_intel_hwp() {
+        let mut backend = FakeCpuScalingBackend::new("intel_pstate", 2);
+        backend.add_policy_file("energy_performance_preference", "balance_performance");
+        let tests = [
+            (CpuScalingProfile::Performance, "50", "performance"),
+            (CpuScalingProfile::Balanced, "0", "balance_performance"),
+            (CpuScalingProfile::PowerSaver, "0", "balance_power"),
+        ];
+        for (profile, min_perf_pct, epp) in tests {
+            assert_eq!(
+                apply_cpu_scaling_profile(&backend, profile, profile.epp()).unwrap(),
+                CpuScalingDriver::IntelPstate
+            );
+            assert_eq!(
+                backend.take_writes(),
+                writes(&[
+                    ("sys/devices/system/cpu/intel_pstate/max_perf_pct", "100"),
+                    (
+                        "sys/devices/system/cpu/intel_pstate/min_perf_pct",
+                        min_perf_pct
+                    ),
+                    (
+                        "sys/devices/system/cpu/cpufreq/policy0/energy_performance_preference",
+                        epp
+                    ),
+                    (
+                        "sys/devices/system/cpu/cpufreq/policy1/energy_performance_preference",
+                        epp
+                    ),
+                ])
+            );
+        }
+
+        // Without HWP, there is no EPP.
+        let backend = FakeCpuScalingBackend::new("intel_pstate", 2);
+        apply_cpu_scaling_profile(
+            &backend,
+            CpuScalingProfile::PowerSaver,
+            EnergyPerformancePreference::BalancePower,
+        )
+        .unwrap();
+        assert_eq!(
+            backend.take_writes(),
+            writes(&[
+                ("sys/devices/system/cpu/intel_pstate/max_perf_pct", "100"),
+                ("sys/devices/system/cpu/intel_pstate/min_perf_pct", "0"),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_cpu_scaling_profile_amd_cppc() {
+        for driver in ["amd-pstate", "amd-pstate-epp"] {
+            let mut backend = FakeCpuScalingBackend::new(driver, 2);
+            backend.add_policy_file("cpuinfo_min_freq", "400000");
+            backend.add_policy_file("cpuinfo_max_freq", "4500000");
+            backend.add_policy_file("amd_pstate_lowest_nonlinear_freq", "1600000");
+            if driver == "amd-pstate-epp" {
+                backend.add_policy_file("energy_performance_preference", "balance_performance");
+            }
+            let tests = [
+                (CpuScalingProfile::Performance, "1600000", "performance"),
+                (CpuScalingProfile::Balanced, "400000", "balance_performance"),
+                (CpuScalingProfile::PowerSaver, "400000", "balance_power"),
+            ];
+            for (profile, min_freq, epp) in tests {
+                assert_eq!(
+                    apply_cpu_scaling_profile(&backend, profile, profile.epp()).unwrap(),
+                    CpuScalingDriver::AmdPstate
+                );
+                let mut expected = Vec::new();
+                for policy in ["policy0", "policy1"] {
+                    let policy = format!("sys/devices/system/cpu/cpufreq/{}", policy);
+                    expected.push((format!("{}/scaling_max_freq", policy), "4500000"));
+                    expected.push((format!("{}/scaling_min_freq", policy), min_freq));
+                    if driver == "amd-pstate-epp" {
+                        expected.push((format!("{}/energy_performance_preference", policy), epp));
+                    }
+                }
+                let expected: Vec<(&str, &str)> = expected
+                    .iter()
+                    .map(|(path, value)| (path.as_str(), *value))
+                    .collect();
+                assert_eq!(backend.take_writes(), writes(&expected));
+            }
+        }
+    }
+
+    #[test]
+    fn test_cpu_scaling_profile_generic() {
+        let backend = FakeCpuScalingBackend::new("acpi-cpufreq", 2);
+        let tests = [
+            (CpuScalingProfile::Performance, "performance"),
+            (CpuScalingProfile::Balanced, "schedutil"),
+            (CpuScalingProfile::PowerSaver, "powersave"),
+        ];
+        for (profile, governor) in tests {
+            assert_eq!(
+                apply_cpu_scaling_profile(&backend, profile, profile.epp()).unwrap(),
+                CpuScalingDriver::Generic
+            );
+            assert_eq!(
+                backend.take_writes(),
+                writes(&[
+                    (
+                        "sys/devices/system/cpu/cpufreq/policy0/scaling_governor",
+                        governor
+                    ),
+                    (
+                        "sys/devices/system/cpu/cpufreq/policy1/scaling_governor",
+                        governor
+                    ),
+                ])
+            );
+        }
+    }
+
+    #[test]
+    fn test_cpu_scaling_profile_for_power_source() {
+        assert_eq!(
+            CpuScalingProfile::for_power_source(PowerSourceType::AC),
+            CpuScalingProfile::Balanced
+        );
+        assert_eq!(
+            CpuScalingProfile::for_power_source(PowerSourceType::DC),
+            CpuScalingProfile::PowerSaver
+        );
+    }
+
+    #[test]
+    fn test_apply_power_source_profile() {
+        let mut backend = FakeCpuScalingBackend::new("intel_pstate", 1);
+        backend.add_policy_file("energy_performance_preference", "balance_performance");
+        let epp_path = "sys/devices/system/cpu/cpufreq/policy0/energy_performance_preference";
+
+        assert!(apply_power_source_profile(&backend, PowerSourceType::DC, None).unwrap());
+        assert_eq!(
+            backend.take_writes(),
+            writes(&[
+                ("sys/devices/system/cpu/intel_pstate/max_perf_pct", "100"),
+                ("sys/devices/system/cpu/intel_pstate/min_perf_pct", "0"),
+                (epp_path, "balance_power"),
+            ])
+        );
+
+        // The EPP of the power preferences replaces the one of the profile.
+        assert!(apply_power_source_profile(
+            &backend,
+            PowerSourceType::AC,
+            Some(EnergyPerformancePreference::BalancePower)
+        )
+        .unwrap());
+        assert_eq!(
+            backend.take_writes()[2],
+            writes(&[(epp_path, "balance_power")])[0]
+        );
+
+        // Generic drivers keep the governor of the power preferences.
+        let backend = FakeCpuScalingBackend::new("acpi-cpufreq", 1);
+        assert!(!apply_power_source_profile(&backend, PowerSourceType::AC, None).unwrap());
+        assert!(backend.take_writes().is_empty());
+
+        // Nothing is applied without cpufreq.
+        let root = tempdir().unwrap();
+        let backend = SysfsCpuScalingBackend::new(root.path());
+        assert!(!apply_power_source_profile(&backend, PowerSourceType::AC, None).unwrap());
+    }
+
+    #[test]
+    fn test_sysfs_cpu_scaling_backend() -> Result<()> {
+        let root = tempdir()?;
+        let backend = SysfsCpuScalingBackend::new(root.path());
+        let cpufreq = root.path().join(DEVICE_CPUFREQ_PATH);
+        for policy in ["policy0", "policy4"] {
+            std::fs::create_dir_all(cpufreq.join(policy))?;
+            std::fs::write(cpufreq.join(policy).join("scaling_governor"), "schedutil\n")?;
+        }
+
+        let mut policies = backend.policies()?;
+        policies.sort();
+        assert_eq!(
+            policies,
+            vec![
+                Path::new(DEVICE_CPUFREQ_PATH).join("policy0"),
+                Path::new(DEVICE_CPUFREQ_PATH).join("policy4")
+            ]
+        );
+
+        let governor = Path::new(DEVICE_CPUFREQ_PATH).join("policy4/scaling_governor");
+        assert!(backend.exists(&governor));
+        backend.write(&governor, "powersave")?;
+        assert_eq!(backend.read(&governor)?, "powersave");
+
+        Ok(())
+    }
 }
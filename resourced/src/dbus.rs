@@ -28,13 +28,29 @@ use dbus_crossroads::IfaceToken;
 use dbus_crossroads::MethodErr;
 use dbus_tokio::connection;
 use log::error;
+use log::info;
 use log::LevelFilter;
 use system_api::battery_saver::BatterySaverModeState;
+use system_api::concierge_service::VmStartedSignal;
+use system_api::concierge_service::VmStoppedSignal;
+use tokio::sync::watch;
 
 use crate::common;
 use crate::config::ConfigProvider;
+#[cfg(target_arch = "x86_64")]
+use crate::cpu_scaling;
+use crate::dbus_clients;
+use crate::dbus_clients::ClientRegistry;
+use crate::dbus_clients::SystemClientRegistry;
+use crate::dbus_clients::SystemClientResources;
+use crate::discard;
+use crate::dump;
 use crate::feature;
+use crate::game_mode;
+use crate::game_mode::GameModeController;
+use crate::game_mode::SystemGameModeSubsystems;
 use crate::memory;
+use crate::metrics;
 use crate::power;
 use crate::proc::load_euid;
 use crate::psi;
@@ -42,6 +58,8 @@ use crate::qos;
 use crate::qos::set_process_state;
 use crate::qos::set_thread_state;
 use crate::qos::SchedQosContext;
+use crate::thermal;
+use crate::thermal::ThermalState;
 use crate::vm_memory_management_client::VmMemoryManagementClient;
 
 const SERVICE_NAME: &str = "org.chromium.ResourceManager";
@@ -49,6 +67,7 @@ const PATH_NAME: &str = "/org/chromium/ResourceManager";
 const INTERFACE_NAME: &str = SERVICE_NAME;
 
 const VMCONCIEGE_INTERFACE_NAME: &str = "org.chromium.VmConcierge";
+const BOREALIS_VM_NAME: &str = "borealis";
 const POWERD_INTERFACE_NAME: &str = "org.chromium.PowerManager";
 const POWERD_PATH_NAME: &str = "/org/chromium/PowerManager";
 
@@ -75,6 +94,17 @@ struct DbusContext {
     reset_vm_boot_mode_timer_id: Arc<AtomicUsize>,
 
     scheduler_context: Option<Arc<Mutex<SchedQosContext>>>,
+    // None if the schedqos states were not restored on startup.
+    qos_restore_stats: Option<qos::RestoreStats>,
+
+    thermal_state: watch::Receiver<ThermalState>,
+
+    game_mode: Arc<Mutex<GameModeController<SystemGameModeSubsystems>>>,
+
+    // The registrations made by each D-Bus client, reverted when the client disconnects.
+    clients: Arc<Mutex<SystemClientRegistry>>,
+
+    vmms_client: Arc<VmMemoryManagementClient>,
 }
 
 fn send_pressure_signal(
@@ -226,13 +256,20 @@ fn register_interface(cr: &mut Crossroads, conn: Arc<SyncConnection>) -> IfaceTo
                 Ok((result,))
             },
         );
+        let conn_clone = conn.clone();
         b.method(
             "SetMemoryMarginsBps",
             ("critical_bps", "moderate_bps"),
             ("critical", "moderate"),
-            move |_, _, (critical_bps, moderate_bps): (u32, u32)| {
+            move |ctx, context, (critical_bps, moderate_bps): (u32, u32)| {
                 match memory::set_memory_margins_bps(critical_bps, moderate_bps) {
                     Ok(()) => {
+                        dbus_clients::track_registration(
+                            &conn_clone,
+                            &context.clients,
+                            ctx.message().sender().map(|s| s.to_string()),
+                            |clients, sender| clients.set_memory_margins_owner(sender),
+                        );
                         let margins = memory::get_memory_margins_kb();
                         Ok((margins.0, margins.1))
                     }
@@ -405,6 +442,64 @@ fn register_interface(cr: &mut Crossroads, conn: Arc<SyncConnection>) -> IfaceTo
                 }
             }
         });
+        b.method(
+            "StartGameSession",
+            ("vm_pid", "vcpu_thread_ids"),
+            (),
+            move |_, context, (vm_pid, vcpu_thread_ids): (u32, Vec<u32>)| {
+                let mut game_mode = context.game_mode.lock().expect("lock game mode");
+                match game_mode.on_session_start(vm_pid, vcpu_thread_ids) {
+                    Ok(()) => Ok(()),
+                    Err(e) => {
+                        error!("start game session failed: {:#}", e);
+                        Err(MethodErr::failed("Failed to start game session"))
+                    }
+                }
+            },
+        );
+        b.method("EndGameSession", (), (), move |_, context, ()| {
+            let mut game_mode = context.game_mode.lock().expect("lock game mode");
+            match game_mode.on_session_end() {
+                Ok(()) => Ok(()),
+                Err(e) => {
+                    error!("end game session failed: {:#}", e);
+                    Err(MethodErr::failed("Failed to end game session"))
+                }
+            }
+        });
+        // The profile is applied until it is cleared with an empty string. For tests only, so it
+        // is only available in developer mode.
+        #[cfg(target_arch = "x86_64")]
+        b.method(
+            "SetCpuScalingProfileOverride",
+            ("profile",),
+            (),
+            move |_, context, (profile,): (String,)| {
+                if !feature::is_dev_mode() {
+                    return Err(MethodErr::failed(
+                        "Cpu scaling profile override requires developer mode",
+                    ));
+                }
+                let profile = if profile.is_empty() {
+                    None
+                } else {
+                    Some(
+                        profile
+                            .parse::<cpu_scaling::CpuScalingProfile>()
+                            .map_err(|_| MethodErr::failed("Unsupported cpu scaling profile"))?,
+                    )
+                };
+                match cpu_scaling::set_cpu_scaling_profile_override(profile).and_then(|()| {
+                    common::update_power_preferences(context.power_preferences_manager.as_ref())
+                }) {
+                    Ok(()) => Ok(()),
+                    Err(e) => {
+                        error!("set_cpu_scaling_profile_override failed: {:#}", e);
+                        Err(MethodErr::failed("Failed to set cpu scaling profile"))
+                    }
+                }
+            },
+        );
         b.method(
             "SetLogLevel",
             ("level",),
@@ -430,11 +525,13 @@ fn register_interface(cr: &mut Crossroads, conn: Arc<SyncConnection>) -> IfaceTo
             (),
             move |mut sender_context, cr, (process_id, process_state): (u32, u8)| {
                 let context: Option<&mut DbusContext> = cr.data_mut(sender_context.path());
+                let clients = context.as_ref().map(|ctx| ctx.clients.clone());
                 let sched_ctx = context.and_then(|ctx| ctx.scheduler_context.clone());
                 let sender_bus_name = sender_context.message().sender().map(|s| s.to_string());
-                let sender_euid = get_sender_euid(conn_clone.clone(), sender_bus_name);
+                let sender_euid = get_sender_euid(conn_clone.clone(), sender_bus_name.clone());
+                let conn = conn_clone.clone();
                 async move {
-                    let Some(sched_ctx) = sched_ctx else {
+                    let (Some(sched_ctx), Some(clients)) = (sched_ctx, clients) else {
                         return sender_context.reply(Err(MethodErr::failed("no schedqos context")));
                     };
 
@@ -448,7 +545,18 @@ fn register_interface(cr: &mut Crossroads, conn: Arc<SyncConnection>) -> IfaceTo
                     };
 
                     match set_process_state(sched_ctx, process_id, process_state, sender_euid) {
-                        Ok(_) => sender_context.reply(Ok(())),
+                        Ok(join_handle) => {
+                            // The process is owned by the client which registered it first.
+                            if join_handle.is_some() {
+                                dbus_clients::track_registration(
+                                    &conn,
+                                    &clients,
+                                    sender_bus_name,
+                                    |clients, sender| clients.add_process(sender, process_id),
+                                );
+                            }
+                            sender_context.reply(Ok(()))
+                        }
                         Err(e) => {
                             error!("change_process_state failed: {:#}, pid={}", e, process_id);
                             sender_context.reply(Err(e.to_dbus_error()))
@@ -497,11 +605,69 @@ fn register_interface(cr: &mut Crossroads, conn: Arc<SyncConnection>) -> IfaceTo
                 }
             },
         );
+        b.method(
+            "GetSchedQosRestoreStats",
+            (),
+            ("restored", "restored_entries", "pruned_entries"),
+            move |_, context, ()| {
+                let stats = context.qos_restore_stats.unwrap_or_default();
+                Ok((
+                    context.qos_restore_stats.is_some(),
+                    stats.restored,
+                    stats.pruned,
+                ))
+            },
+        );
+        b.method_with_cr_async(
+            "GetDebugDump",
+            ("redact",),
+            ("dump",),
+            move |mut sender_context, cr, (redact,): (bool,)| {
+                let context: Option<&mut DbusContext> = cr.data_mut(sender_context.path());
+                let context = context.cloned();
+                async move {
+                    let Some(context) = context else {
+                        return sender_context.reply(Err(MethodErr::failed("no context")));
+                    };
+                    let balloon_sizes = context.vmms_client.get_balloon_sizes().await;
+                    let config_provider = ConfigProvider::from_root(Path::new("/"));
+                    let thermal_state = *context.thermal_state.borrow();
+                    let dump = dump::collect_debug_dump(
+                        &config_provider,
+                        context.scheduler_context.as_deref(),
+                        thermal_state,
+                        balloon_sizes,
+                        redact,
+                    );
+                    sender_context.reply(Ok((dump.to_json(),)))
+                }
+            },
+        );
+        b.method(
+            "ListFeatureOverrides",
+            (),
+            ("allowed", "overrides"),
+            move |_, _, ()| {
+                let dev_overrides = feature::get_dev_overrides().map_err(|e| {
+                    error!("Failed to get feature overrides: {:#}", e);
+                    MethodErr::failed("Couldn't get feature overrides")
+                })?;
+                let overrides: Vec<(String, bool, HashMap<String, String>)> = dev_overrides
+                    .overrides
+                    .into_iter()
+                    .map(|(name, feature_override)| {
+                        (name, feature_override.enabled, feature_override.params)
+                    })
+                    .collect();
+                Ok((dev_overrides.allowed, overrides))
+            },
+        );
+        let conn_clone = conn.clone();
         b.method(
             "ReportBackgroundProcesses",
             ("raw_bytes",),
             (),
-            move |_, _, (raw_bytes,): (Vec<u8>,)| {
+            move |ctx, context, (raw_bytes,): (Vec<u8>,)| {
                 use system_api::resource_manager::report_background_processes::Component;
 
                 let report_bk_processes: system_api::resource_manager::ReportBackgroundProcesses =
@@ -532,14 +698,59 @@ fn register_interface(cr: &mut Crossroads, conn: Arc<SyncConnection>) -> IfaceTo
                         }
                     };
                 memory::set_background_processes(browser_type, report_bk_processes.pids);
+                dbus_clients::track_registration(
+                    &conn_clone,
+                    &context.clients,
+                    ctx.message().sender().map(|s| s.to_string()),
+                    |clients, sender| clients.set_browser_processes_owner(sender, browser_type),
+                );
                 Ok(())
             },
         );
+        b.method_with_cr_async(
+            "GetDiscardCandidatesHint",
+            ("candidates",),
+            ("hints",),
+            move |mut sender_context, _, (candidates,): (Vec<(i64, i32, i64)>,)| {
+                let candidates: Vec<discard::DiscardCandidate> = candidates
+                    .into_iter()
+                    .map(|(id, pid, last_active_ms)| discard::DiscardCandidate {
+                        id,
+                        pid,
+                        last_active_ms,
+                    })
+                    .collect();
+                async move {
+                    // Sampling smaps_rollup of the renderers can take a while, so it does not run
+                    // on the D-Bus dispatcher.
+                    match tokio::task::spawn_blocking(move || {
+                        discard::get_discard_candidates_hint(&candidates)
+                    })
+                    .await
+                    {
+                        Ok(hints) => {
+                            let hints: Vec<(i64, i32, u64)> = hints
+                                .into_iter()
+                                .map(|hint| (hint.id, hint.pid, hint.reclaimable_kb))
+                                .collect();
+                            sender_context.reply(Ok((hints,)))
+                        }
+                        Err(e) => {
+                            error!("Failed to get discard candidates hint: {}", e);
+                            sender_context.reply(Err(MethodErr::failed(
+                                "failed to get discard candidates hint",
+                            )))
+                        }
+                    }
+                }
+            },
+        );
+        let conn_clone = conn.clone();
         b.method(
             "ReportBrowserProcesses",
             ("raw_bytes",),
             (),
-            move |_, _, (raw_bytes,): (Vec<u8>,)| {
+            move |ctx, context, (raw_bytes,): (Vec<u8>,)| {
                 use system_api::resource_manager::BrowserType;
 
                 let report_browser_processes: system_api::resource_manager::ReportBrowserProcesses =
@@ -580,6 +791,12 @@ fn register_interface(cr: &mut Crossroads, conn: Arc<SyncConnection>) -> IfaceTo
                     }
                 }
                 memory::set_browser_processes(browser_type, background_pids, protected_pids);
+                dbus_clients::track_registration(
+                    &conn_clone,
+                    &context.clients,
+                    ctx.message().sender().map(|s| s.to_string()),
+                    |clients, sender| clients.set_browser_processes_owner(sender, browser_type),
+                );
                 Ok(())
             },
         );
@@ -646,6 +863,30 @@ fn on_battery_saver_mode_change(context: DbusContext, raw_bytes: Vec<u8>) -> Res
     Ok(())
 }
 
+fn on_vm_started_signal(context: &DbusContext, msg: &Message) -> Result<()> {
+    let signal: VmStartedSignal = protobuf::Message::parse_from_bytes(&msg.read1::<Vec<u8>>()?)?;
+    if signal.name != BOREALIS_VM_NAME {
+        return Ok(());
+    }
+    context
+        .game_mode
+        .lock()
+        .expect("lock game mode")
+        .on_vm_started(signal.vm_info.pid as u32)
+}
+
+fn on_vm_stopped_signal(context: &DbusContext, msg: &Message) -> Result<()> {
+    let signal: VmStoppedSignal = protobuf::Message::parse_from_bytes(&msg.read1::<Vec<u8>>()?)?;
+    if signal.name != BOREALIS_VM_NAME {
+        return Ok(());
+    }
+    context
+        .game_mode
+        .lock()
+        .expect("lock game mode")
+        .on_vm_stopped()
+}
+
 async fn init_battery_saver_mode(context: DbusContext, conn: Arc<SyncConnection>) -> Result<()> {
     let powerd_proxy = Proxy::new(
         POWERD_INTERFACE_NAME,
@@ -661,7 +902,10 @@ async fn init_battery_saver_mode(context: DbusContext, conn: Arc<SyncConnection>
     on_battery_saver_mode_change(context.clone(), powerd_response)
 }
 
-async fn memory_checker_wait(pressure_result: &Result<memory::PressureStatus>) {
+async fn memory_checker_wait(
+    pressure_result: &Result<memory::PressureStatus>,
+    psi_watcher: &mut Option<psi::PsiMemoryWatcher>,
+) {
     const MEMORY_USAGE_POLL_INTERVAL: u64 = 1000;
 
     match feature::is_feature_enabled(VARIABLE_TIME_MEMORY_SIGNAL_FEATURE_NAME) {
@@ -688,18 +932,36 @@ async fn memory_checker_wait(pressure_result: &Result<memory::PressureStatus>) {
                 }
             };
 
+            if psi_watcher.is_none() {
+                match psi::PsiMemoryWatcher::new(Path::new("/"), STALL_MS, WINDOW_MS) {
+                    Ok(watcher) => {
+                        info!("PSI memory watcher uses {:?}", watcher.mechanism());
+                        if let Err(e) = report_psi_mechanism(watcher.mechanism()) {
+                            error!("Failed to report PSI mechanism: {:#}", e);
+                        }
+                        *psi_watcher = Some(watcher);
+                    }
+                    Err(e) => error!("Failed to create PSI memory watcher: {:?}", e),
+                }
+            }
+
             // Waiting for certain range of duration. Interrupt if PSI memory stall exceeds the
             // threshold.
-            let wait_result = psi::wait_psi_monitor_memory_event(
-                STALL_MS,
-                WINDOW_MS,
-                MIN_WAITING_MS,
-                max_waiting_ms,
-            )
-            .await;
+            let wait_result = match psi_watcher {
+                Some(watcher) => watcher.wait(MIN_WAITING_MS, max_waiting_ms).await,
+                None => {
+                    psi::wait_psi_monitor_memory_event(
+                        STALL_MS,
+                        WINDOW_MS,
+                        MIN_WAITING_MS,
+                        max_waiting_ms,
+                    )
+                    .await
+                }
+            };
             if wait_result.is_err() {
                 error!(
-                    "wait_psi_monitor_memory_event returns error: {:?}",
+                    "Waiting for PSI memory event returns error: {:?}",
                     wait_result
                 );
                 // Fallback to 1 second waiting.
@@ -712,6 +974,21 @@ async fn memory_checker_wait(pressure_result: &Result<memory::PressureStatus>) {
     }
 }
 
+fn report_psi_mechanism(mechanism: psi::PsiMechanism) -> Result<()> {
+    let metrics = metrics_rs::MetricsLibrary::get().context("MetricsLibrary::get() failed")?;
+
+    // Shall panic on poisoned mutex.
+    metrics
+        .lock()
+        .expect("Lock MetricsLibrary object failed")
+        .send_enum_to_uma(
+            "Platform.Resourced.PsiMemoryMechanism",
+            mechanism as i32,
+            psi::PsiMechanism::UMA_MAX,
+        )?;
+    Ok(())
+}
+
 fn report_notification_count(notification_count: i32) -> Result<()> {
     let metrics = metrics_rs::MetricsLibrary::get().context("MetricsLibrary::get() failed")?;
 
@@ -729,28 +1006,78 @@ fn report_notification_count(notification_count: i32) -> Result<()> {
     Ok(())
 }
 
+// Requests the debug dump from the running resourced.
+pub fn get_debug_dump_blocking(redact: bool) -> Result<String> {
+    let conn = dbus::blocking::Connection::new_system().context("connect to system bus")?;
+    let proxy = conn.with_proxy(SERVICE_NAME, PATH_NAME, DEFAULT_DBUS_TIMEOUT);
+    let (dump,): (String,) = proxy
+        .method_call(INTERFACE_NAME, "GetDebugDump", (redact,))
+        .context("call GetDebugDump")?;
+    Ok(dump)
+}
+
 pub async fn service_main() -> Result<()> {
     let root = Path::new("/");
     let config_provider = ConfigProvider::from_root(root);
-    let scheduler_context = match qos::create_schedqos_context() {
-        Ok(ctx) => Some(Arc::new(Mutex::new(ctx))),
-        Err(e) => {
-            error!("failed to initialize schedqos context: {e}");
-            None
+    let (scheduler_context, qos_restore_stats, restored_processes) =
+        match qos::create_schedqos_context() {
+            Ok((ctx, restore_result)) => {
+                let ctx = Arc::new(Mutex::new(ctx));
+                let restored_processes: Option<Vec<u32>> = restore_result.as_ref().map(|result| {
+                    result
+                        .processes
+                        .iter()
+                        .map(|process_key| process_key.process_id().into())
+                        .collect()
+                });
+                let qos_restore_stats = restore_result
+                    .map(|result| qos::monitor_restored_processes(ctx.clone(), result).0);
+                (Some(ctx), qos_restore_stats, restored_processes)
+            }
+            Err(e) => {
+                error!("failed to initialize schedqos context: {e}");
+                (None, None, None)
+            }
+        };
+
+    let client_resources = SystemClientResources::new(scheduler_context.clone());
+    let owners_file = Path::new(dbus_clients::OWNERS_FILE_PATH);
+    let (clients, restored_clients) = match restored_processes {
+        Some(restored_processes) => {
+            ClientRegistry::restore(client_resources, owners_file, restored_processes)
         }
+        None => (
+            ClientRegistry::new(client_resources, Some(owners_file)),
+            Vec::new(),
+        ),
     };
-    let context = DbusContext {
-        power_preferences_manager: Arc::new(power::new_directory_power_preferences_manager(
-            root,
-            config_provider,
-        )),
-        reset_game_mode_timer_id: Arc::new(AtomicUsize::new(0)),
-        reset_fullscreen_video_timer_id: Arc::new(AtomicUsize::new(0)),
-        reset_vm_boot_mode_timer_id: Arc::new(AtomicUsize::new(0)),
-        scheduler_context,
-    };
+    let clients = Arc::new(Mutex::new(clients));
+
+    let power_preferences_manager = Arc::new(power::new_directory_power_preferences_manager(
+        root,
+        config_provider,
+    ));
+
+    // Throttles the power and QoS policies while the system is thermally limited.
+    let mut thermal_monitor = thermal::ThermalMonitor::new(root);
+    thermal_monitor.register_hook(Box::new(power::CpuBoostThermalHook::new(
+        power_preferences_manager.clone(),
+    )));
+    if let Some(scheduler_context) = &scheduler_context {
+        thermal_monitor.register_hook(Box::new(qos::UclampThermalHook::new(
+            scheduler_context.clone(),
+        )));
+    }
+    let thermal_state = thermal_monitor.subscribe();
+    thermal_monitor.start();
+
+    let game_mode = Arc::new(Mutex::new(GameModeController::new(
+        SystemGameModeSubsystems::new(root, scheduler_context.clone()),
+    )));
 
     let (io_resource, conn) = connection::new_system_sync()?;
+    // Both the D-Bus service and client monitors receive NameOwnerChanged.
+    conn.set_signal_match_mode(true);
 
     // io_resource must be awaited to start receiving D-Bus message.
     let _handle = tokio::spawn(async {
@@ -762,12 +1089,36 @@ pub async fn service_main() -> Result<()> {
         .await
         .context("start feature monitoring")?;
 
-    let vmms_client = VmMemoryManagementClient::new(conn.clone())
-        .await
-        .context("create VmMemoryManagementClient")?;
+    if let Err(e) = feature::start_dev_overrides_monitoring(root) {
+        error!("Failed to start feature overrides monitoring: {:#}", e);
+    }
+
+    let vmms_client = Arc::new(
+        VmMemoryManagementClient::new(conn.clone())
+            .await
+            .context("create VmMemoryManagementClient")?,
+    );
+
+    let context = DbusContext {
+        power_preferences_manager,
+        reset_game_mode_timer_id: Arc::new(AtomicUsize::new(0)),
+        reset_fullscreen_video_timer_id: Arc::new(AtomicUsize::new(0)),
+        reset_vm_boot_mode_timer_id: Arc::new(AtomicUsize::new(0)),
+        scheduler_context,
+        qos_restore_stats,
+        thermal_state,
+        game_mode,
+        clients,
+        vmms_client: vmms_client.clone(),
+    };
 
     conn.request_name(SERVICE_NAME, false, true, false).await?;
 
+    // Clients which disconnected while resourced was not running are released right away.
+    for client in restored_clients {
+        dbus_clients::monitor_client(conn.clone(), context.clients.clone(), client);
+    }
+
     let mut cr = Crossroads::new();
 
     // Enable asynchronous methods. Incoming method calls are spawned as separate tasks if
@@ -835,6 +1186,43 @@ pub async fn service_main() -> Result<()> {
         );
     }
 
+    // Detects the game session from the Borealis VM lifecycle in case it is not reported.
+    let vm_started_rule = MatchRule::new_signal(VMCONCIEGE_INTERFACE_NAME, "VmStartedSignal");
+    conn.add_match_no_cb(&vm_started_rule.match_str()).await?;
+    let cb_context = context.clone();
+    conn.start_receive(
+        vm_started_rule,
+        Box::new(move |msg, _| {
+            if let Err(e) = on_vm_started_signal(&cb_context, &msg) {
+                error!("Failed to handle VmStartedSignal. {:#}", e);
+            }
+            true
+        }),
+    );
+    let vm_stopped_rule = MatchRule::new_signal(VMCONCIEGE_INTERFACE_NAME, "VmStoppedSignal");
+    conn.add_match_no_cb(&vm_stopped_rule.match_str()).await?;
+    let cb_context = context.clone();
+    conn.start_receive(
+        vm_stopped_rule,
+        Box::new(move |msg, _| {
+            if let Err(e) = on_vm_stopped_signal(&cb_context, &msg) {
+                error!("Failed to handle VmStoppedSignal. {:#}", e);
+            }
+            true
+        }),
+    );
+
+    // Reverts the game session if the VM exits without notifying the end of the session.
+    let game_mode = context.game_mode.clone();
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(game_mode::WATCHDOG_INTERVAL).await;
+            if let Err(e) = game_mode.lock().expect("lock game mode").check_vm() {
+                error!("Failed to revert game session. {:#}", e);
+            }
+        }
+    });
+
     if init_battery_saver_mode(context.clone(), conn.clone())
         .await
         .is_err()
@@ -886,6 +1274,13 @@ pub async fn service_main() -> Result<()> {
             VARIABLE_TIME_MEMORY_SIGNAL_FEATURE_NAME, err
         );
     }
+    if let Err(err) = feature::initialize_feature(discard::DISCARD_HINT_FEATURE_NAME, false) {
+        error!(
+            "Failed to update feature {}: {}",
+            discard::DISCARD_HINT_FEATURE_NAME,
+            err
+        );
+    }
 
     // Reports memory pressure notification count every 10 minutes.
     let notification_count = Arc::new(AtomicI32::new(0));
@@ -904,7 +1299,11 @@ pub async fn service_main() -> Result<()> {
         }
     });
 
+    // Reports CPU time by cgroup and memory pressure stall time once per day.
+    metrics::start_daily_resource_usage_reporting(root);
+
     // The memory checker loop.
+    let mut psi_watcher = None;
     loop {
         let pressure_result = memory::get_memory_pressure_status(&vmms_client).await;
 
@@ -936,6 +1335,6 @@ pub async fn service_main() -> Result<()> {
 
         notification_count.fetch_add(1, Ordering::Relaxed);
 
-        memory_checker_wait(&pressure_result).await;
+        memory_checker_wait(&pressure_result, &mut psi_watcher).await;
     }
 }
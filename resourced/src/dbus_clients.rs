@@ -0,0 +1,720 @@
+// Copyright 2024 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Reverts the registrations of a D-Bus client when it disconnects from the bus.
+//!
+//! Clients like Chrome register processes for QoS, report browser processes and override the
+//! memory margins. If a client crashes, these would linger until each process exits. Each of them
+//! is recorded with the unique bus name of its sender (e.g. ":1.42") and reverted when the name is
+//! released. The owners of the QoS registrations are saved next to the schedqos state file so that
+//! they are restored along with the registrations when resourced restarts. New registrations are
+//! saved with a delay so that a burst of them results in a single write.
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::Context;
+use anyhow::Result;
+use async_trait::async_trait;
+use dbus::nonblock::SyncConnection;
+use log::error;
+use log::info;
+
+use crate::dbus_ownership_listener::monitor_dbus_client;
+use crate::dbus_ownership_listener::DbusOwnershipChangeCallback;
+use crate::dump;
+use crate::memory;
+use crate::memory::BrowserType;
+use crate::qos::ProcessKey;
+use crate::qos::SchedQosContext;
+
+/// The owners of the QoS registrations, next to the schedqos state file.
+pub const OWNERS_FILE_PATH: &str = "/run/resourced/schedqos_owners";
+
+/// The delay of saving the owners after a new registration.
+const OWNERS_SAVE_DELAY: Duration = Duration::from_secs(1);
+
+/// The states changed on behalf of clients.
+pub trait ClientResources {
+    /// Returns the key of the process if it is registered for QoS.
+    fn process_key(&mut self, process_id: u32) -> Option<ProcessKey>;
+    fn remove_process(&mut self, process_key: ProcessKey);
+    fn clear_browser_processes(&mut self, browser_type: BrowserType);
+    fn reset_memory_margins(&mut self);
+}
+
+/// Tracks which client made each registration.
+pub struct ClientRegistry<R> {
+    resources: R,
+    // The owner of each process registered for QoS.
+    processes: HashMap<u32, (String, ProcessKey)>,
+    // The client which last reported the processes of each browser.
+    browser_processes: BTreeMap<BrowserType, String>,
+    // The client which last set the memory margins.
+    memory_margins: Option<String>,
+    // The clients monitored for disconnection.
+    clients: BTreeSet<String>,
+    owners_file: Option<PathBuf>,
+    // Whether the owners changed since they were saved.
+    owners_changed: bool,
+    // Whether a deferred save of the owners is scheduled.
+    save_scheduled: bool,
+}
+
+impl<R: ClientResources> ClientRegistry<R> {
+    /// The owners of the QoS registrations are saved to `owners_file` if it is given.
+    pub fn new(resources: R, owners_file: Option<&Path>) -> Self {
+        Self {
+            resources,
+            processes: HashMap::new(),
+            browser_processes: BTreeMap::new(),
+            memory_margins: None,
+            clients: BTreeSet::new(),
+            owners_file: owners_file.map(Path::to_path_buf),
+            owners_changed: false,
+            save_scheduled: false,
+        }
+    }
+
+    /// Restores the owners saved by the previous resourced instance for the processes whose QoS
+    /// states are restored. Processes without a known owner stay registered until they exit.
+    ///
+    /// The returned clients need to be monitored.
+    pub fn restore(
+        resources: R,
+        owners_file: &Path,
+        restored_processes: impl IntoIterator<Item = u32>,
+    ) -> (Self, Vec<String>) {
+        let mut registry = Self::new(resources, Some(owners_file));
+        let owners = match load_owners(owners_file) {
+            Ok(owners) => owners,
+            Err(e) => {
+                error!(
+                    "failed to load the owners of the QoS registrations: {:#}",
+                    e
+                );
+                HashMap::new()
+            }
+        };
+        for process_id in restored_processes {
+            let Some(owner) = owners.get(&process_id) else {
+                continue;
+            };
+            let Some(process_key) = registry.resources.process_key(process_id) else {
+                continue;
+            };
+            registry.clients.insert(owner.clone());
+            registry
+                .processes
+                .insert(process_id, (owner.clone(), process_key));
+        }
+        info!(
+            "Restored owners of {} processes from {} clients",
+            registry.processes.len(),
+            registry.clients.len()
+        );
+        registry.owners_changed = true;
+        registry.save_owners();
+        let clients = registry.clients.iter().cloned().collect();
+        (registry, clients)
+    }
+
+    /// Records that the client registered the process for QoS.
+    ///
+    /// The owners are not saved until [Self::save_owners] is called, see [Self::schedule_save].
+    ///
+    /// Returns true if the client is new and needs to be monitored.
+    pub fn add_process(&mut self, client: &str, process_id: u32) -> bool {
+        let Some(process_key) = self.resources.process_key(process_id) else {
+            return false;
+        };
+        let owner = (client.to_string(), process_key);
+        if self.processes.get(&process_id) != Some(&owner) {
+            self.processes.insert(process_id, owner);
+            self.owners_changed = true;
+        }
+        self.clients.insert(client.to_string())
+    }
+
+    /// Returns true if the owners changed and no deferred save is scheduled yet. The caller is
+    /// responsible for calling [Self::save_owners] later then.
+    pub fn schedule_save(&mut self) -> bool {
+        if !self.owners_changed || self.save_scheduled || self.owners_file.is_none() {
+            return false;
+        }
+        self.save_scheduled = true;
+        true
+    }
+
+    /// Records that the client reported the processes of the browser.
+    ///
+    /// Returns true if the client is new and needs to be monitored.
+    pub fn set_browser_processes_owner(&mut self, client: &str, browser_type: BrowserType) -> bool {
+        self.browser_processes
+            .insert(browser_type, client.to_string());
+        self.clients.insert(client.to_string())
+    }
+
+    /// Records that the client set the memory margins.
+    ///
+    /// Returns true if the client is new and needs to be monitored.
+    pub fn set_memory_margins_owner(&mut self, client: &str) -> bool {
+        self.memory_margins = Some(client.to_string());
+        self.clients.insert(client.to_string())
+    }
+
+    /// Reverts everything the client registered.
+    pub fn on_client_disconnected(&mut self, client: &str) {
+        if !self.clients.remove(client) {
+            return;
+        }
+        info!("Client {} disconnected", client);
+
+        self.prune_exited_processes();
+        let process_ids: Vec<u32> = self
+            .processes
+            .iter()
+            .filter(|(_, (owner, _))| owner == client)
+            .map(|(process_id, _)| *process_id)
+            .collect();
+        for process_id in process_ids {
+            if let Some((_, process_key)) = self.processes.remove(&process_id) {
+                // No-op if the process has exited already.
+                self.resources.remove_process(process_key);
+                self.owners_changed = true;
+            }
+        }
+        self.save_owners();
+
+        let browser_types: Vec<BrowserType> = self
+            .browser_processes
+            .iter()
+            .filter(|(_, owner)| *owner == client)
+            .map(|(browser_type, _)| *browser_type)
+            .collect();
+        for browser_type in browser_types {
+            self.browser_processes.remove(&browser_type);
+            self.resources.clear_browser_processes(browser_type);
+        }
+
+        if self.memory_margins.as_deref() == Some(client) {
+            self.memory_margins = None;
+            self.resources.reset_memory_margins();
+        }
+    }
+
+    // Drops the processes which exited or were registered again after exiting.
+    fn prune_exited_processes(&mut self) {
+        let resources = &mut self.resources;
+        let n_processes = self.processes.len();
+        self.processes.retain(|process_id, (_, process_key)| {
+            resources.process_key(*process_id).as_ref() == Some(process_key)
+        });
+        if self.processes.len() != n_processes {
+            self.owners_changed = true;
+        }
+    }
+
+    /// Saves the owners if they changed since the last save.
+    pub fn save_owners(&mut self) {
+        self.save_scheduled = false;
+        if !self.owners_changed {
+            return;
+        }
+        self.owners_changed = false;
+        let Some(owners_file) = &self.owners_file else {
+            return;
+        };
+        let mut owners: BTreeMap<&str, Vec<u32>> = BTreeMap::new();
+        for (process_id, (owner, _)) in &self.processes {
+            owners.entry(owner).or_default().push(*process_id);
+        }
+        if let Err(e) = save_owners(owners_file, &owners) {
+            error!(
+                "failed to save the owners of the QoS registrations: {:#}",
+                e
+            );
+        }
+    }
+}
+
+fn load_owners(owners_file: &Path) -> Result<HashMap<u32, String>> {
+    if !owners_file.exists() {
+        return Ok(HashMap::new());
+    }
+    let content = std::fs::read_to_string(owners_file)
+        .with_context(|| format!("read {}", owners_file.display()))?;
+    let owners: BTreeMap<String, Vec<u32>> =
+        serde_json::from_str(&content).context("parse owners")?;
+    let mut process_owners = HashMap::new();
+    for (owner, process_ids) in owners {
+        for process_id in process_ids {
+            process_owners.insert(process_id, owner.clone());
+        }
+    }
+    Ok(process_owners)
+}
+
+fn save_owners(owners_file: &Path, owners: &BTreeMap<&str, Vec<u32>>) -> Result<()> {
+    // Replace the file atomically so that a crash does not leave a corrupt file behind.
+    let tmp_file = owners_file.with_extension("tmp");
+    std::fs::write(&tmp_file, serde_json::to_string(owners)?)
+        .with_context(|| format!("write {}", tmp_file.display()))?;
+    std::fs::rename(&tmp_file, owners_file)
+        .with_context(|| format!("rename to {}", owners_file.display()))
+}
+
+/// Applies the cleanup of disconnected clients to the system.
+pub struct SystemClientResources {
+    sched_ctx: Option<Arc<Mutex<SchedQosContext>>>,
+}
+
+impl SystemClientResources {
+    pub fn new(sched_ctx: Option<Arc<Mutex<SchedQosContext>>>) -> Self {
+        Self { sched_ctx }
+    }
+}
+
+impl ClientResources for SystemClientResources {
+    fn process_key(&mut self, process_id: u32) -> Option<ProcessKey> {
+        self.sched_ctx
+            .as_ref()?
+            .lock()
+            .expect("lock schedqos context")
+            .process_key(process_id.into())
+    }
+
+    fn remove_process(&mut self, process_key: ProcessKey) {
+        let Some(sched_ctx) = &self.sched_ctx else {
+            return;
+        };
+        let process_id: u32 = process_key.process_id().into();
+        sched_ctx
+            .lock()
+            .expect("lock schedqos context")
+            .remove_process(process_key);
+        dump::record_decision(dump::Decision::ProcessReleased { process_id });
+    }
+
+    fn clear_browser_processes(&mut self, browser_type: BrowserType) {
+        memory::clear_browser_processes(browser_type);
+    }
+
+    fn reset_memory_margins(&mut self) {
+        memory::reset_memory_margins();
+    }
+}
+
+pub type SystemClientRegistry = ClientRegistry<SystemClientResources>;
+
+struct ClientDisconnectCallback {
+    clients: Arc<Mutex<SystemClientRegistry>>,
+    client: String,
+}
+
+#[async_trait]
+impl DbusOwnershipChangeCallback for ClientDisconnectCallback {
+    async fn on_ownership_change(&self, _old: String, _new: String) -> Result<()> {
+        self.clients
+            .lock()
+            .expect("lock client registry")
+            .on_client_disconnected(&self.client);
+        Ok(())
+    }
+}
+
+/// Reverts the registrations of the client when it disconnects from the bus.
+pub fn monitor_client(
+    conn: Arc<SyncConnection>,
+    clients: Arc<Mutex<SystemClientRegistry>>,
+    client: String,
+) {
+    tokio::spawn(async move {
+        let cb = ClientDisconnectCallback {
+            clients,
+            client: client.clone(),
+        };
+        if let Err(e) = monitor_dbus_client(&conn, client.clone(), cb).await {
+            error!("failed to monitor client {}: {:#}", client, e);
+        }
+    });
+}
+
+/// Records a registration made by the sender of a D-Bus method call and monitors the sender if it
+/// is new.
+pub fn track_registration(
+    conn: &Arc<SyncConnection>,
+    clients: &Arc<Mutex<SystemClientRegistry>>,
+    sender: Option<String>,
+    register: impl FnOnce(&mut SystemClientRegistry, &str) -> bool,
+) {
+    let Some(sender) = sender else {
+        return;
+    };
+    let (is_new, schedule_save) = {
+        let mut registry = clients.lock().expect("lock client registry");
+        let is_new = register(&mut registry, &sender);
+        (is_new, registry.schedule_save())
+    };
+    if schedule_save {
+        let clients = clients.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(OWNERS_SAVE_DELAY).await;
+            clients.lock().expect("lock client registry").save_owners();
+        });
+    }
+    if is_new {
+        monitor_client(conn.clone(), clients.clone(), sender);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use schedqos::CgroupContext;
+    use schedqos::Config;
+    use schedqos::RtPriorityPolicy;
+
+    use super::*;
+    use crate::qos::ProcessState;
+    use crate::test_utils::*;
+
+    #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+    enum Call {
+        RemoveProcess(u32),
+        ClearBrowserProcesses(BrowserType),
+        ResetMemoryMargins,
+    }
+
+    struct FakeResources {
+        sched_ctx: Arc<Mutex<SchedQosContext>>,
+        calls: Rc<RefCell<Vec<Call>>>,
+    }
+
+    impl ClientResources for FakeResources {
+        fn process_key(&mut self, process_id: u32) -> Option<ProcessKey> {
+            self.sched_ctx
+                .lock()
+                .unwrap()
+                .process_key(process_id.into())
+        }
+
+        fn remove_process(&mut self, process_key: ProcessKey) {
+            self.calls
+                .borrow_mut()
+                .push(Call::RemoveProcess(process_key.process_id().into()));
+            self.sched_ctx.lock().unwrap().remove_process(process_key);
+        }
+
+        fn clear_browser_processes(&mut self, browser_type: BrowserType) {
+            self.calls
+                .borrow_mut()
+                .push(Call::ClearBrowserProcesses(browser_type));
+        }
+
+        fn reset_memory_margins(&mut self) {
+            self.calls.borrow_mut().push(Call::ResetMemoryMargins);
+        }
+    }
+
+    fn create_config_for_test() -> Config {
+        Config {
+            cgroup_context: CgroupContext {
+                cpu_normal: tempfile::tempfile().unwrap(),
+                cpu_background: tempfile::tempfile().unwrap(),
+                cpuset_all: tempfile::tempfile().unwrap(),
+                cpuset_efficient: tempfile::tempfile().unwrap(),
+                cpuset_background_all: None,
+                cpuset_background_efficient: None,
+                memory_normal: None,
+                memory_background: None,
+                verify_writes: false,
+            },
+            process_configs: Config::default_process_config(),
+            thread_configs: Config::default_thread_config(),
+            rt_priority_policy: RtPriorityPolicy::Reject,
+        }
+    }
+
+    fn registered_process_ids(sched_ctx: &Mutex<SchedQosContext>) -> Vec<u32> {
+        let mut process_ids: Vec<u32> = sched_ctx
+            .lock()
+            .unwrap()
+            .registrations()
+            .iter()
+            .map(|registration| registration.process_id.into())
+            .collect();
+        process_ids.sort_unstable();
+        process_ids
+    }
+
+    // sched_getattr(2) is not supported on qemu-user which CQ uses to run tests for non-x86_64
+    // boards.
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_client_disconnected() {
+        let dir = tempfile::tempdir().unwrap();
+        let sched_ctx = Arc::new(Mutex::new(
+            SchedQosContext::new_file(create_config_for_test(), &dir.path().join("states"))
+                .unwrap(),
+        ));
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        let mut registry = ClientRegistry::new(
+            FakeResources {
+                sched_ctx: sched_ctx.clone(),
+                calls: calls.clone(),
+            },
+            None,
+        );
+
+        let mut processes = Vec::new();
+        let mut register = |client: &str| {
+            let (process_id, process) = fork_process_for_test();
+            sched_ctx
+                .lock()
+                .unwrap()
+                .set_process_state(process_id.into(), ProcessState::Normal)
+                .unwrap();
+            let is_new = registry.add_process(client, process_id);
+            processes.push(process);
+            (process_id, is_new)
+        };
+        let (process_id1, is_new1) = register(":1.1");
+        let (process_id2, is_new2) = register(":1.1");
+        let (other_process_id, is_new3) = register(":1.2");
+        assert!(is_new1);
+        assert!(!is_new2);
+        assert!(is_new3);
+        assert!(!registry.set_browser_processes_owner(":1.1", BrowserType::Ash));
+        assert!(!registry.set_memory_margins_owner(":1.1"));
+        assert!(registry.set_browser_processes_owner(":1.3", BrowserType::Lacros));
+
+        registry.on_client_disconnected(":1.1");
+
+        let mut removed = calls.take();
+        removed.sort();
+        let mut expected = vec![
+            Call::RemoveProcess(process_id1),
+            Call::RemoveProcess(process_id2),
+            Call::ClearBrowserProcesses(BrowserType::Ash),
+            Call::ResetMemoryMargins,
+        ];
+        expected.sort();
+        assert_eq!(removed, expected);
+        // The registrations of the other clients are kept.
+        assert_eq!(registered_process_ids(&sched_ctx), vec![other_process_id]);
+
+        // Disconnection is handled only once.
+        registry.on_client_disconnected(":1.1");
+        assert!(calls.take().is_empty());
+
+        // The memory margins set by another client are not reverted.
+        registry.set_memory_margins_owner(":1.2");
+        registry.on_client_disconnected(":1.3");
+        assert_eq!(
+            calls.take(),
+            vec![Call::ClearBrowserProcesses(BrowserType::Lacros)]
+        );
+    }
+
+    // sched_getattr(2) is not supported on qemu-user which CQ uses to run tests for non-x86_64
+    // boards.
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_save_owners() {
+        let dir = tempfile::tempdir().unwrap();
+        let sched_ctx = Arc::new(Mutex::new(
+            SchedQosContext::new_file(create_config_for_test(), &dir.path().join("states"))
+                .unwrap(),
+        ));
+        let owners_file = dir.path().join("owners");
+        let mut registry = ClientRegistry::new(
+            FakeResources {
+                sched_ctx: sched_ctx.clone(),
+                calls: Rc::new(RefCell::new(Vec::new())),
+            },
+            Some(&owners_file),
+        );
+        let mut processes = Vec::new();
+        let mut register = |registry: &mut ClientRegistry<FakeResources>| {
+            let (process_id, process) = fork_process_for_test();
+            sched_ctx
+                .lock()
+                .unwrap()
+                .set_process_state(process_id.into(), ProcessState::Normal)
+                .unwrap();
+            registry.add_process(":1.1", process_id);
+            processes.push(process);
+            process_id
+        };
+
+        // A burst of registrations is saved once.
+        let process_id1 = register(&mut registry);
+        assert!(registry.schedule_save());
+        let process_id2 = register(&mut registry);
+        assert!(!registry.schedule_save());
+        assert!(!owners_file.exists());
+        registry.save_owners();
+        assert_eq!(
+            load_owners(&owners_file).unwrap(),
+            HashMap::from([
+                (process_id1, ":1.1".to_string()),
+                (process_id2, ":1.1".to_string())
+            ])
+        );
+
+        // Registering the same process again does not change the owners.
+        registry.add_process(":1.1", process_id1);
+        assert!(!registry.schedule_save());
+
+        register(&mut registry);
+        assert!(registry.schedule_save());
+    }
+
+    // sched_getattr(2) is not supported on qemu-user which CQ uses to run tests for non-x86_64
+    // boards.
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_exited_processes_are_pruned() {
+        let dir = tempfile::tempdir().unwrap();
+        let sched_ctx = Arc::new(Mutex::new(
+            SchedQosContext::new_file(create_config_for_test(), &dir.path().join("states"))
+                .unwrap(),
+        ));
+        let owners_file = dir.path().join("owners");
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        let mut registry = ClientRegistry::new(
+            FakeResources {
+                sched_ctx: sched_ctx.clone(),
+                calls: calls.clone(),
+            },
+            Some(&owners_file),
+        );
+        let mut register = |client: &str| {
+            let (process_id, process) = fork_process_for_test();
+            let process_key = sched_ctx
+                .lock()
+                .unwrap()
+                .set_process_state(process_id.into(), ProcessState::Normal)
+                .unwrap()
+                .unwrap();
+            registry.add_process(client, process_id);
+            (process_id, process_key, process)
+        };
+        let (_, exited_process_key, exited_process) = register(":1.1");
+        let (process_id, _, _process) = register(":1.1");
+        let (other_process_id, _, _other_process) = register(":1.2");
+
+        // The process exits and is removed from the schedqos context.
+        drop(exited_process);
+        sched_ctx.lock().unwrap().remove_process(exited_process_key);
+
+        // The exited process is pruned when a client disconnects.
+        registry.on_client_disconnected(":1.2");
+        assert_eq!(calls.take(), vec![Call::RemoveProcess(other_process_id)]);
+        assert_eq!(
+            load_owners(&owners_file).unwrap(),
+            HashMap::from([(process_id, ":1.1".to_string())])
+        );
+    }
+
+    // pidfd_open(2) and sched_getattr(2) are not supported on qemu-user which CQ uses to run tests
+    // for non-x86_64 boards.
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_restore() {
+        let dir = tempfile::tempdir().unwrap();
+        let state_file = dir.path().join("states");
+        let owners_file = dir.path().join("owners");
+        let sched_ctx = Arc::new(Mutex::new(
+            SchedQosContext::new_file(create_config_for_test(), &state_file).unwrap(),
+        ));
+        let mut registry = ClientRegistry::new(
+            FakeResources {
+                sched_ctx: sched_ctx.clone(),
+                calls: Rc::new(RefCell::new(Vec::new())),
+            },
+            Some(&owners_file),
+        );
+
+        let mut processes = Vec::new();
+        let mut register = |client: &str| {
+            let (process_id, process) = fork_process_for_test();
+            sched_ctx
+                .lock()
+                .unwrap()
+                .set_process_state(process_id.into(), ProcessState::Normal)
+                .unwrap();
+            registry.add_process(client, process_id);
+            processes.push(process);
+            process_id
+        };
+        let process_id1 = register(":1.1");
+        let process_id2 = register(":1.1");
+        let other_process_id = register(":1.2");
+        // Registered without an owner, e.g. by resourced itself.
+        let (unowned_process_id, _unowned_process) = fork_process_for_test();
+        sched_ctx
+            .lock()
+            .unwrap()
+            .set_process_state(unowned_process_id.into(), ProcessState::Normal)
+            .unwrap();
+
+        registry.save_owners();
+
+        // Simulate a restart of resourced.
+        drop(registry);
+        drop(sched_ctx);
+
+        let (ctx, result) =
+            SchedQosContext::restore_from_file(create_config_for_test(), &state_file).unwrap();
+        let sched_ctx = Arc::new(Mutex::new(ctx));
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        let (mut registry, mut clients) = ClientRegistry::restore(
+            FakeResources {
+                sched_ctx: sched_ctx.clone(),
+                calls: calls.clone(),
+            },
+            &owners_file,
+            result
+                .processes
+                .iter()
+                .map(|process_key| process_key.process_id().into()),
+        );
+        clients.sort();
+        assert_eq!(clients, vec![":1.1".to_string(), ":1.2".to_string()]);
+
+        registry.on_client_disconnected(":1.1");
+        let mut removed = calls.take();
+        removed.sort();
+        let mut expected = vec![
+            Call::RemoveProcess(process_id1),
+            Call::RemoveProcess(process_id2),
+        ];
+        expected.sort();
+        assert_eq!(removed, expected);
+
+        let mut expected = vec![other_process_id, unowned_process_id];
+        expected.sort_unstable();
+        assert_eq!(registered_process_ids(&sched_ctx), expected);
+        assert_eq!(
+            load_owners(&owners_file).unwrap(),
+            HashMap::from([(other_process_id, ":1.2".to_string())])
+        );
+    }
+
+    #[test]
+    fn test_load_owners_missing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(load_owners(&dir.path().join("owners")).unwrap().is_empty());
+    }
+}
@@ -138,3 +138,106 @@ pub async fn monitor_dbus_service<T: DbusOwnershipChangeCallback + 'static>(
 
     Ok(())
 }
+
+/// Invoke the given callback once the client with the unique bus name (e.g. ":1.42") disconnects
+/// from the bus. The new owner passed to the callback is empty. If the client has disconnected
+/// already when this function is called, the callback is invoked right away.
+///
+/// The connection must deliver signals to all matching receivers (see
+/// [SyncConnection::set_signal_match_mode]) so that this does not interfere with
+/// [monitor_dbus_service].
+pub async fn monitor_dbus_client<T: DbusOwnershipChangeCallback + 'static>(
+    conn: &Arc<SyncConnection>,
+    client_name: String,
+    cb: T,
+) -> Result<()> {
+    let name_owner_match_string = [
+        "interface=org.freedesktop.DBus".to_string(),
+        "member=NameOwnerChanged".to_string(),
+        format!("arg0={client_name}"),
+    ]
+    .join(",");
+
+    let name_owner_change_signal =
+        MatchRule::new_signal("org.freedesktop.DBus", "NameOwnerChanged");
+
+    conn.add_match_no_cb(&name_owner_match_string)
+        .await
+        .context("failed to add match")?;
+
+    let (sender, mut receiver) = unbounded_channel::<NameOwnerChangeInfo>();
+
+    let cb_conn = conn.clone();
+    tokio::spawn(async move {
+        // Unique names are never reused, so the client is gone for good after the first message.
+        let Some(msg) = receiver.recv().await else {
+            return;
+        };
+        if let Err(e) = cb_conn.remove_match_no_cb(&name_owner_match_string).await {
+            error!("failed to remove match: {:?}", e);
+        }
+        if let Err(e) = cb.on_ownership_change(msg.old, msg.new).await {
+            error!("Error handling client disconnection: {:?}", e);
+        }
+    });
+
+    let sender = Arc::new(sender);
+    let cb_sender = sender.clone();
+    let name = client_name.clone();
+    let token = conn.start_receive(
+        name_owner_change_signal,
+        Box::new(move |msg, _| {
+            let (changed_name, old, new): (String, String, String) = match msg.read3() {
+                Ok(res) => res,
+                Err(e) => {
+                    error!("Malformed signal: {:?}", e);
+                    return true;
+                }
+            };
+
+            if changed_name != name || !new.is_empty() {
+                return true;
+            }
+            let msg = NameOwnerChangeInfo {
+                old,
+                new,
+                from_signal: true,
+            };
+            if let Err(e) = cb_sender.send(msg) {
+                error!("error dispatching client disconnection: {:?}", e)
+            }
+            // Stop receiving the signals for the client.
+            false
+        }),
+    );
+
+    let proxy = Proxy::new(
+        "org.freedesktop.DBus",
+        "/org/freedesktop/DBus",
+        DEFAULT_DBUS_TIMEOUT,
+        conn.clone(),
+    );
+
+    // The client may have disconnected before the match was added. A failure is treated as if
+    // the client is still connected so that its registrations are not dropped by mistake.
+    let (has_owner,): (bool,) = proxy
+        .method_call(
+            "org.freedesktop.DBus",
+            "NameHasOwner",
+            (client_name.as_str(),),
+        )
+        .await
+        .unwrap_or((true,));
+    if !has_owner {
+        conn.stop_receive(token);
+        let msg = NameOwnerChangeInfo {
+            old: client_name,
+            new: String::new(),
+            from_signal: false,
+        };
+        // The receiver has been dropped if the signal was dispatched first.
+        let _ = sender.send(msg);
+    }
+
+    Ok(())
+}
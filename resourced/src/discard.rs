@@ -0,0 +1,585 @@
+// Copyright 2024 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Recommends which browser tabs Chrome should discard first.
+//!
+//! Chrome reports its discardable tabs with their renderer pids and the time they were last
+//! active. Each tab is scored by how long it has been inactive, how much memory discarding it
+//! would free and how much of its resident memory is idle. The idle estimate comes from the
+//! Referenced field of /proc/pid/smaps_rollup, which is expensive to read for large processes,
+//! so it is rate-limited and the latest estimate of each tab is reused in between. Under critical
+//! memory pressure, or without the feature, the tabs are ordered by their memory usage.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use log::error;
+use once_cell::sync::Lazy;
+
+use crate::common::Clock;
+use crate::common::SystemClock;
+use crate::feature;
+use crate::memory;
+
+pub const DISCARD_HINT_FEATURE_NAME: &str = "CrOSLateBootResourcedDiscardHint";
+
+// Feature params of DISCARD_HINT_FEATURE_NAME.
+const AGE_WEIGHT_PARAM: &str = "age_weight";
+const SIZE_WEIGHT_PARAM: &str = "size_weight";
+const IDLE_WEIGHT_PARAM: &str = "idle_weight";
+const SAMPLING_INTERVAL_PARAM: &str = "sampling_interval_secs";
+
+const DEFAULT_SAMPLING_INTERVAL: Duration = Duration::from_secs(30);
+
+// Tabs inactive for longer than this get the full age score.
+const MAX_SCORED_AGE_MS: i64 = 30 * 60 * 1000;
+
+/// A discardable tab reported by Chrome.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DiscardCandidate {
+    // The id Chrome uses to identify the tab.
+    pub id: i64,
+    pub pid: i32,
+    // CLOCK_MONOTONIC timestamp in milliseconds of the last time the tab was active.
+    pub last_active_ms: i64,
+}
+
+/// A candidate in the recommended discard order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DiscardHint {
+    pub id: i64,
+    pub pid: i32,
+    // The estimated memory freed by discarding the tab.
+    pub reclaimable_kb: u64,
+}
+
+// Memory usage of a candidate process.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct ProcessMemory {
+    // Sum of anonymous RSS and swap.
+    reclaimable_kb: u64,
+    rss_kb: u64,
+    // Resident memory not referenced recently. None if the process was never sampled.
+    idle_kb: Option<u64>,
+}
+
+/// Weights of the score terms. Each term is normalized to [0, 1].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScoringWeights {
+    // Time since the tab was last active.
+    pub age: f64,
+    // Reclaimable memory relative to the largest candidate.
+    pub size: f64,
+    // Fraction of the resident memory that is idle.
+    pub idle: f64,
+}
+
+impl Default for ScoringWeights {
+    fn default() -> Self {
+        ScoringWeights {
+            age: 1.0,
+            size: 1.0,
+            idle: 1.0,
+        }
+    }
+}
+
+// Orders the candidates by reclaimable memory, used under critical pressure and when the feature
+// is disabled.
+const RECLAIMABLE_ORDER: ScoringWeights = ScoringWeights {
+    age: 0.0,
+    size: 1.0,
+    idle: 0.0,
+};
+
+// Returns the feature param parsed as T, or the default if it is not set or invalid.
+fn get_param_or<T: std::str::FromStr>(key: &str, default: T) -> T {
+    match feature::get_feature_param(DISCARD_HINT_FEATURE_NAME, key) {
+        Ok(Some(value)) => value.parse().unwrap_or_else(|_| {
+            error!(
+                "Invalid {} param {}: {}",
+                DISCARD_HINT_FEATURE_NAME, key, value
+            );
+            default
+        }),
+        Ok(None) => default,
+        Err(e) => {
+            error!(
+                "Failed to get {} param {}: {}",
+                DISCARD_HINT_FEATURE_NAME, key, e
+            );
+            default
+        }
+    }
+}
+
+impl ScoringWeights {
+    fn from_feature_params() -> Self {
+        let default = ScoringWeights::default();
+        ScoringWeights {
+            age: get_param_or(AGE_WEIGHT_PARAM, default.age),
+            size: get_param_or(SIZE_WEIGHT_PARAM, default.size),
+            idle: get_param_or(IDLE_WEIGHT_PARAM, default.idle),
+        }
+    }
+}
+
+/// Allows the expensive sampling at most once per interval.
+pub struct RateLimiter<C: Clock> {
+    clock: C,
+    last_sample_ms: Option<i64>,
+}
+
+impl<C: Clock> RateLimiter<C> {
+    pub const fn new(clock: C) -> Self {
+        RateLimiter {
+            clock,
+            last_sample_ms: None,
+        }
+    }
+
+    /// Returns true and starts a new interval if the last interval has elapsed.
+    pub fn try_acquire(&mut self, interval: Duration) -> bool {
+        let now_ms = self.clock.now_ms();
+        if let Some(last_sample_ms) = self.last_sample_ms {
+            if now_ms.saturating_sub(last_sample_ms) < interval.as_millis() as i64 {
+                return false;
+            }
+        }
+        self.last_sample_ms = Some(now_ms);
+        true
+    }
+}
+
+/// Samples the idle memory of the candidates at most once per interval and keeps the latest
+/// estimate of each tab, so that the order does not depend on whether a call sampled.
+pub struct IdleSampler<C: Clock> {
+    limiter: RateLimiter<C>,
+    // The latest idle estimate keyed by the tab id and its renderer pid.
+    estimates: HashMap<(i64, i32), u64>,
+}
+
+impl<C: Clock> IdleSampler<C> {
+    pub fn new(clock: C) -> Self {
+        IdleSampler {
+            limiter: RateLimiter::new(clock),
+            estimates: HashMap::new(),
+        }
+    }
+
+    // Sets the idle estimates of `usages`, sampling them with `read_idle_kb` if the interval has
+    // elapsed. Estimates of the tabs which are not candidates anymore are dropped.
+    fn estimate(
+        &mut self,
+        usages: &mut [(DiscardCandidate, ProcessMemory)],
+        interval: Duration,
+        read_idle_kb: impl Fn(i32) -> Result<u64>,
+    ) {
+        self.estimates.retain(|key, _| {
+            usages
+                .iter()
+                .any(|(candidate, _)| (candidate.id, candidate.pid) == *key)
+        });
+        if self.limiter.try_acquire(interval) {
+            for (candidate, _) in usages.iter() {
+                let key = (candidate.id, candidate.pid);
+                match read_idle_kb(candidate.pid) {
+                    Ok(idle_kb) => {
+                        self.estimates.insert(key, idle_kb);
+                    }
+                    Err(e) => {
+                        error!("Failed to sample idle memory of {}: {:#}", candidate.pid, e);
+                        self.estimates.remove(&key);
+                    }
+                }
+            }
+        }
+        for (candidate, usage) in usages.iter_mut() {
+            usage.idle_kb = self.estimates.get(&(candidate.id, candidate.pid)).copied();
+        }
+    }
+}
+
+static IDLE_SAMPLER: Lazy<Mutex<IdleSampler<SystemClock>>> =
+    Lazy::new(|| Mutex::new(IdleSampler::new(SystemClock)));
+
+// Reads the anonymous RSS and swap of the process from /proc/pid/status.
+fn read_process_memory(pid: i32) -> Result<ProcessMemory> {
+    let status = procfs::process::Process::new(pid)?.status()?;
+    let (Some(rss_kb), Some(swap_kb)) = (status.rssanon, status.vmswap) else {
+        bail!("Couldn't get RssAnon or VmSwap in /proc/{}/status", pid);
+    };
+    Ok(ProcessMemory {
+        reclaimable_kb: rss_kb + swap_kb,
+        rss_kb,
+        idle_kb: None,
+    })
+}
+
+// Estimates the idle resident memory of the process from /proc/pid/smaps_rollup. Reading it
+// requires CAP_SYS_PTRACE since the renderers run as another user.
+fn read_idle_kb(pid: i32) -> Result<u64> {
+    let process = procfs::process::Process::new(pid)?;
+    let rollup = process.smaps_rollup()?;
+    let map = &rollup
+        .memory_map_rollup
+        .memory_maps
+        .first()
+        .context("Empty smaps_rollup")?
+        .extension
+        .map;
+    // The values are in bytes.
+    let rss = map.get("Rss").context("No Rss in smaps_rollup")?;
+    let referenced = map
+        .get("Referenced")
+        .context("No Referenced in smaps_rollup")?;
+    Ok(rss.saturating_sub(*referenced) / 1024)
+}
+
+// Orders the candidates from the best to the worst to discard.
+fn rank_candidates(
+    candidates: &[(DiscardCandidate, ProcessMemory)],
+    now_ms: i64,
+    weights: &ScoringWeights,
+) -> Vec<DiscardHint> {
+    let max_reclaimable_kb = candidates
+        .iter()
+        .map(|(_, usage)| usage.reclaimable_kb)
+        .max()
+        .unwrap_or(0);
+    let mut ranked: Vec<(f64, &DiscardCandidate, &ProcessMemory)> = candidates
+        .iter()
+        .map(|(candidate, usage)| {
+            let age_ms = now_ms.saturating_sub(candidate.last_active_ms);
+            let age = age_ms.clamp(0, MAX_SCORED_AGE_MS) as f64 / MAX_SCORED_AGE_MS as f64;
+            let size = if max_reclaimable_kb > 0 {
+                usage.reclaimable_kb as f64 / max_reclaimable_kb as f64
+            } else {
+                0.0
+            };
+            let idle = match usage.idle_kb {
+                Some(idle_kb) if usage.rss_kb > 0 => {
+                    (idle_kb as f64 / usage.rss_kb as f64).min(1.0)
+                }
+                _ => 0.0,
+            };
+            let score = weights.age * age + weights.size * size + weights.idle * idle;
+            (score, candidate, usage)
+        })
+        .collect();
+    // Ties go to the least recently active tab.
+    ranked.sort_by(|(score_a, candidate_a, _), (score_b, candidate_b, _)| {
+        score_b
+            .total_cmp(score_a)
+            .then(candidate_a.last_active_ms.cmp(&candidate_b.last_active_ms))
+    });
+    ranked
+        .into_iter()
+        .map(|(_, candidate, usage)| DiscardHint {
+            id: candidate.id,
+            pid: candidate.pid,
+            reclaimable_kb: usage.reclaimable_kb,
+        })
+        .collect()
+}
+
+// Scores the candidates with `weights`, or orders them by reclaimable memory without sampling
+// them when the feature is disabled or the memory pressure is critical.
+fn rank_with_sampler<C: Clock>(
+    usages: &mut [(DiscardCandidate, ProcessMemory)],
+    now_ms: i64,
+    weights: Option<&ScoringWeights>,
+    critical: bool,
+    sampler: &mut IdleSampler<C>,
+    interval: Duration,
+    read_idle_kb: impl Fn(i32) -> Result<u64>,
+) -> Vec<DiscardHint> {
+    match weights {
+        Some(weights) if !critical => {
+            sampler.estimate(usages, interval, read_idle_kb);
+            rank_candidates(usages, now_ms, weights)
+        }
+        _ => rank_candidates(usages, now_ms, &RECLAIMABLE_ORDER),
+    }
+}
+
+/// Returns the candidates in the recommended discard order. Candidates whose process is gone are
+/// dropped.
+pub fn get_discard_candidates_hint(candidates: &[DiscardCandidate]) -> Vec<DiscardHint> {
+    let critical = memory::get_last_pressure_reading().is_some_and(|reading| {
+        reading.status.chrome_level == memory::PressureLevelChrome::Critical
+    });
+    let enabled = feature::is_feature_enabled(DISCARD_HINT_FEATURE_NAME).unwrap_or_else(|e| {
+        error!("Failed to get feature {}: {}", DISCARD_HINT_FEATURE_NAME, e);
+        false
+    });
+
+    let mut usages = Vec::with_capacity(candidates.len());
+    for candidate in candidates {
+        let usage = match read_process_memory(candidate.pid) {
+            Ok(usage) => usage,
+            Err(e) => {
+                error!(
+                    "Failed to get memory usage, pid: {}, error: {}",
+                    candidate.pid, e
+                );
+                continue;
+            }
+        };
+        usages.push((*candidate, usage));
+    }
+
+    let weights = enabled.then(ScoringWeights::from_feature_params);
+    let interval = Duration::from_secs(get_param_or(
+        SAMPLING_INTERVAL_PARAM,
+        DEFAULT_SAMPLING_INTERVAL.as_secs(),
+    ));
+    let mut sampler = match IDLE_SAMPLER.lock() {
+        Ok(sampler) => sampler,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    rank_with_sampler(
+        &mut usages,
+        SystemClock.now_ms(),
+        weights.as_ref(),
+        critical,
+        &mut sampler,
+        interval,
+        read_idle_kb,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+    use crate::test_utils::FakeClock;
+
+    const MINUTE_MS: i64 = 60 * 1000;
+
+    fn candidate(
+        id: i64,
+        last_active_ms: i64,
+        reclaimable_kb: u64,
+        idle_kb: Option<u64>,
+    ) -> (DiscardCandidate, ProcessMemory) {
+        (
+            DiscardCandidate {
+                id,
+                pid: id as i32 + 1000,
+                last_active_ms,
+            },
+            ProcessMemory {
+                reclaimable_kb,
+                rss_kb: reclaimable_kb,
+                idle_kb,
+            },
+        )
+    }
+
+    fn ranked_ids(
+        candidates: &[(DiscardCandidate, ProcessMemory)],
+        now_ms: i64,
+        weights: &ScoringWeights,
+    ) -> Vec<i64> {
+        rank_candidates(candidates, now_ms, weights)
+            .iter()
+            .map(|hint| hint.id)
+            .collect()
+    }
+
+    #[test]
+    fn test_rank_by_reclaimable() {
+        let now_ms = 100 * MINUTE_MS;
+        let candidates = [
+            candidate(1, 0, 100_000, None),
+            candidate(2, 90 * MINUTE_MS, 300_000, Some(0)),
+            candidate(3, 50 * MINUTE_MS, 200_000, None),
+            // Same size as 3 but less recently active.
+            candidate(4, 10 * MINUTE_MS, 200_000, Some(200_000)),
+        ];
+        assert_eq!(
+            ranked_ids(&candidates, now_ms, &RECLAIMABLE_ORDER),
+            vec![2, 4, 3, 1]
+        );
+
+        let hints = rank_candidates(&candidates, now_ms, &RECLAIMABLE_ORDER);
+        assert_eq!(
+            hints[0],
+            DiscardHint {
+                id: 2,
+                pid: 1002,
+                reclaimable_kb: 300_000
+            }
+        );
+    }
+
+    #[test]
+    fn test_rank_with_weights() {
+        let now_ms = 100 * MINUTE_MS;
+        let candidates = [
+            // Recently active, large and busy.
+            candidate(1, 99 * MINUTE_MS, 400_000, Some(0)),
+            // Inactive for an hour, small and mostly idle.
+            candidate(2, 40 * MINUTE_MS, 100_000, Some(90_000)),
+            // Inactive for 15 minutes, medium and half idle.
+            candidate(3, 85 * MINUTE_MS, 200_000, Some(100_000)),
+            // The process was never sampled.
+            candidate(4, 75 * MINUTE_MS, 200_000, None),
+        ];
+        // Scores: 1: 0.03 + 1.0 + 0.0, 2: 1.0 + 0.25 + 0.9, 3: 0.5 + 0.5 + 0.5,
+        // 4: 0.83 + 0.5 + 0.0.
+        assert_eq!(
+            ranked_ids(&candidates, now_ms, &ScoringWeights::default()),
+            vec![2, 3, 4, 1]
+        );
+
+        let age_only = ScoringWeights {
+            age: 1.0,
+            size: 0.0,
+            idle: 0.0,
+        };
+        assert_eq!(ranked_ids(&candidates, now_ms, &age_only), vec![2, 4, 3, 1]);
+
+        let idle_only = ScoringWeights {
+            age: 0.0,
+            size: 0.0,
+            idle: 1.0,
+        };
+        assert_eq!(
+            ranked_ids(&candidates, now_ms, &idle_only),
+            vec![2, 3, 4, 1]
+        );
+    }
+
+    #[test]
+    fn test_rank_empty() {
+        assert!(rank_candidates(&[], 0, &ScoringWeights::default()).is_empty());
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let clock = FakeClock::new(1000);
+        let mut limiter = RateLimiter::new(clock.clone());
+        let interval = Duration::from_secs(10);
+
+        assert!(limiter.try_acquire(interval));
+        assert!(!limiter.try_acquire(interval));
+
+        clock.set_ms(10_999);
+        assert!(!limiter.try_acquire(interval));
+
+        clock.set_ms(11_000);
+        assert!(limiter.try_acquire(interval));
+        assert!(!limiter.try_acquire(interval));
+
+        // A shorter interval takes effect immediately.
+        clock.set_ms(12_000);
+        assert!(limiter.try_acquire(Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn test_sampler_reuses_estimates() {
+        let clock = FakeClock::new(0);
+        let mut sampler = IdleSampler::new(clock.clone());
+        let interval = Duration::from_secs(30);
+        let sampled = RefCell::new(Vec::new());
+        let read_idle_kb = |pid: i32| {
+            sampled.borrow_mut().push(pid);
+            if pid == 1003 {
+                bail!("no smaps_rollup");
+            }
+            Ok(pid as u64)
+        };
+
+        let mut usages = [
+            candidate(1, 0, 100, None),
+            candidate(2, 0, 100, None),
+            candidate(3, 0, 100, None),
+        ];
+        sampler.estimate(&mut usages, interval, read_idle_kb);
+        assert_eq!(*sampled.borrow(), vec![1001, 1002, 1003]);
+        let idle: Vec<Option<u64>> = usages.iter().map(|(_, usage)| usage.idle_kb).collect();
+        assert_eq!(idle, vec![Some(1001), Some(1002), None]);
+
+        // Within the interval the processes are not sampled again, but the estimates of the
+        // remaining tabs are kept.
+        clock.set_ms(29_999);
+        sampled.borrow_mut().clear();
+        let mut usages = [candidate(2, 0, 100, None), candidate(4, 0, 100, None)];
+        sampler.estimate(&mut usages, interval, read_idle_kb);
+        assert!(sampled.borrow().is_empty());
+        let idle: Vec<Option<u64>> = usages.iter().map(|(_, usage)| usage.idle_kb).collect();
+        assert_eq!(idle, vec![Some(1002), None]);
+
+        // The tab 1 is dropped from the estimates since it was not a candidate last time.
+        clock.set_ms(30_000);
+        let mut usages = [candidate(1, 0, 100, None)];
+        sampler.estimate(&mut usages, Duration::from_secs(3600), read_idle_kb);
+        assert!(sampled.borrow().is_empty());
+        assert_eq!(usages[0].1.idle_kb, None);
+    }
+
+    #[test]
+    fn test_skip_sampling_under_critical_pressure() {
+        let clock = FakeClock::new(0);
+        let mut sampler = IdleSampler::new(clock);
+        let interval = Duration::from_secs(30);
+        let sampled = RefCell::new(0);
+        let read_idle_kb = |_| {
+            *sampled.borrow_mut() += 1;
+            Ok(90_000)
+        };
+        let now_ms = 100 * MINUTE_MS;
+        // The small idle tab is the best candidate with the weights, the large one by size.
+        let mut usages = [
+            candidate(1, 40 * MINUTE_MS, 100_000, None),
+            candidate(2, 99 * MINUTE_MS, 400_000, None),
+        ];
+        let weights = ScoringWeights::default();
+
+        let hints = rank_with_sampler(
+            &mut usages,
+            now_ms,
+            Some(&weights),
+            true,
+            &mut sampler,
+            interval,
+            read_idle_kb,
+        );
+        assert_eq!(*sampled.borrow(), 0);
+        assert_eq!(hints[0].id, 2);
+
+        // The skipped sampling did not start an interval.
+        let hints = rank_with_sampler(
+            &mut usages,
+            now_ms,
+            Some(&weights),
+            false,
+            &mut sampler,
+            interval,
+            read_idle_kb,
+        );
+        assert_eq!(*sampled.borrow(), 2);
+        assert_eq!(hints[0].id, 1);
+
+        // Without the feature the tabs are not sampled either.
+        let hints = rank_with_sampler(
+            &mut usages,
+            now_ms,
+            None,
+            false,
+            &mut sampler,
+            Duration::ZERO,
+            read_idle_kb,
+        );
+        assert_eq!(*sampled.borrow(), 2);
+        assert_eq!(hints[0].id, 2);
+    }
+}
@@ -0,0 +1,657 @@
+// Copyright 2024 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Collects a snapshot of resourced's view of the system for field debugging.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use log::error;
+use once_cell::sync::Lazy;
+use schedqos::ProcessState;
+use schedqos::ThreadState;
+use serde::Serialize;
+use serde::Serializer;
+use system_api::vm_memory_management::BalloonSize;
+
+use crate::config::ConfigProvider;
+use crate::config::PowerPreferencesType;
+use crate::config::PowerSourceType;
+use crate::feature;
+use crate::memory;
+use crate::memory::ComponentMarginsKb;
+use crate::memory::PressureLevelArcContainer;
+use crate::memory::PressureLevelArcvm;
+use crate::memory::PressureLevelChrome;
+use crate::memory::PressureReading;
+use crate::qos::SchedQosContext;
+use crate::thermal::ThermalState;
+
+// The number of the latest decisions kept in the journal.
+const DECISION_JOURNAL_CAPACITY: usize = 64;
+
+const POWER_SOURCE_TYPES: [PowerSourceType; 2] = [PowerSourceType::AC, PowerSourceType::DC];
+
+const POWER_PREFERENCES_TYPES: [PowerPreferencesType; 7] = [
+    PowerPreferencesType::Default,
+    PowerPreferencesType::WebRTC,
+    PowerPreferencesType::Fullscreen,
+    PowerPreferencesType::VmBoot,
+    PowerPreferencesType::BorealisGaming,
+    PowerPreferencesType::ArcvmGaming,
+    PowerPreferencesType::BatterySaver,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DecisionSource {
+    Memory,
+    Qos,
+    Thermal,
+}
+
+/// A decision taken by the pressure, the qos or the thermal path. The decisions are only
+/// formatted when a dump is requested.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Decision {
+    ChromePressure {
+        from: PressureLevelChrome,
+        to: PressureLevelChrome,
+        available_kb: u64,
+        reclaim_target_kb: u64,
+    },
+    ArcvmPressure {
+        from: PressureLevelArcvm,
+        to: PressureLevelArcvm,
+        reclaim_target_kb: u64,
+    },
+    ArcContainerPressure {
+        from: PressureLevelArcContainer,
+        to: PressureLevelArcContainer,
+        reclaim_target_kb: u64,
+    },
+    QosRestored {
+        restored: usize,
+        pruned: usize,
+    },
+    ProcessState {
+        process_id: u32,
+        state: ProcessState,
+    },
+    ProcessExited {
+        process_id: u32,
+    },
+    // The client which set the process state disconnected.
+    ProcessReleased {
+        process_id: u32,
+    },
+    ThreadState {
+        process_id: u32,
+        thread_id: u32,
+        state: ThreadState,
+    },
+    UrgentBurstyUclampMin(u32),
+    ThermalState(ThermalState),
+}
+
+impl Decision {
+    fn source(&self) -> DecisionSource {
+        match self {
+            Decision::ChromePressure { .. }
+            | Decision::ArcvmPressure { .. }
+            | Decision::ArcContainerPressure { .. } => DecisionSource::Memory,
+            Decision::QosRestored { .. }
+            | Decision::ProcessState { .. }
+            | Decision::ProcessExited { .. }
+            | Decision::ProcessReleased { .. }
+            | Decision::ThreadState { .. }
+            | Decision::UrgentBurstyUclampMin(_) => DecisionSource::Qos,
+            Decision::ThermalState(_) => DecisionSource::Thermal,
+        }
+    }
+}
+
+impl fmt::Display for Decision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Decision::ChromePressure {
+                from,
+                to,
+                available_kb,
+                reclaim_target_kb,
+            } => write!(
+                f,
+                "chrome pressure {:?} -> {:?}, available {} KB, reclaim target {} KB",
+                from, to, available_kb, reclaim_target_kb
+            ),
+            Decision::ArcvmPressure {
+                from,
+                to,
+                reclaim_target_kb,
+            } => write!(
+                f,
+                "arcvm pressure {:?} -> {:?}, reclaim target {} KB",
+                from, to, reclaim_target_kb
+            ),
+            Decision::ArcContainerPressure {
+                from,
+                to,
+                reclaim_target_kb,
+            } => write!(
+                f,
+                "arc container pressure {:?} -> {:?}, reclaim target {} KB",
+                from, to, reclaim_target_kb
+            ),
+            Decision::QosRestored { restored, pruned } => {
+                write!(f, "restored {} entries, pruned {}", restored, pruned)
+            }
+            Decision::ProcessState { process_id, state } => {
+                write!(f, "process {} -> {:?}", process_id, state)
+            }
+            Decision::ProcessExited { process_id } => write!(f, "process {} exited", process_id),
+            Decision::ProcessReleased { process_id } => {
+                write!(f, "process {} released by disconnected client", process_id)
+            }
+            Decision::ThreadState {
+                process_id,
+                thread_id,
+                state,
+            } => write!(f, "thread {}/{} -> {:?}", process_id, thread_id, state),
+            Decision::UrgentBurstyUclampMin(uclamp_min) => {
+                write!(f, "UrgentBursty uclamp_min -> {}", uclamp_min)
+            }
+            Decision::ThermalState(state) => write!(f, "{:?}", state),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct JournalEntry {
+    // Milliseconds since the UNIX epoch.
+    pub timestamp_ms: u64,
+    pub decision: Decision,
+}
+
+impl Serialize for JournalEntry {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct Entry {
+            timestamp_ms: u64,
+            source: DecisionSource,
+            message: String,
+        }
+        Entry {
+            timestamp_ms: self.timestamp_ms,
+            source: self.decision.source(),
+            message: self.decision.to_string(),
+        }
+        .serialize(serializer)
+    }
+}
+
+// A ring buffer of the latest decisions. The oldest decision is dropped when the journal is full.
+pub struct DecisionJournal {
+    capacity: usize,
+    entries: VecDeque<JournalEntry>,
+}
+
+impl DecisionJournal {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub fn record(&mut self, entry: JournalEntry) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    // Returns the entries from the oldest to the newest.
+    pub fn entries(&self) -> Vec<JournalEntry> {
+        self.entries.iter().copied().collect()
+    }
+}
+
+static DECISION_JOURNAL: Lazy<Mutex<DecisionJournal>> =
+    Lazy::new(|| Mutex::new(DecisionJournal::new(DECISION_JOURNAL_CAPACITY)));
+
+// Records a decision taken by the pressure, the qos or the thermal path.
+pub fn record_decision(decision: Decision) {
+    let timestamp_ms = match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(duration) => duration.as_millis() as u64,
+        Err(_) => 0,
+    };
+    let entry = JournalEntry {
+        timestamp_ms,
+        decision,
+    };
+    match DECISION_JOURNAL.lock() {
+        Ok(mut journal) => journal.record(entry),
+        Err(poisoned) => poisoned.into_inner().record(entry),
+    }
+}
+
+fn get_decisions() -> Vec<JournalEntry> {
+    match DECISION_JOURNAL.lock() {
+        Ok(journal) => journal.entries(),
+        Err(poisoned) => poisoned.into_inner().entries(),
+    }
+}
+
+// Serializes the pairs as a JSON object, keeping their order.
+fn serialize_pairs<K: Serialize, V: Serialize, S: Serializer>(
+    pairs: &[(K, V)],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(pairs.iter().map(|(key, value)| (key, value)))
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct QosThreadDump {
+    pub thread_id: u32,
+    pub state: ThreadState,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct QosProcessDump {
+    pub process_id: u32,
+    // The command line of the process. Only the first token is kept when redacted.
+    pub name: String,
+    pub state: ProcessState,
+    pub threads: Vec<QosThreadDump>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct VmBalloonDump {
+    pub cid: i64,
+    pub target_size_kb: u64,
+}
+
+#[derive(Serialize)]
+pub struct DebugDump {
+    // Power preferences keyed by "<power source>/<power preferences type>". None if the board
+    // does not configure the preferences.
+    #[serde(serialize_with = "serialize_pairs")]
+    pub power_preferences: Vec<(String, Option<String>)>,
+    #[serde(serialize_with = "serialize_pairs")]
+    pub features: Vec<(String, bool)>,
+    #[serde(rename = "memory_margins_kb")]
+    pub memory_margins: ComponentMarginsKb,
+    pub last_pressure_reading: Option<PressureReading>,
+    pub thermal_state: ThermalState,
+    // None if the schedqos context failed to initialize.
+    pub qos_processes: Option<Vec<QosProcessDump>>,
+    // None if the VM memory management service is not available.
+    pub vm_balloons: Option<Vec<VmBalloonDump>>,
+    pub decisions: Vec<JournalEntry>,
+}
+
+// Collects the debug dump. When `redact` is set, process command lines are truncated to the
+// first token.
+pub fn collect_debug_dump(
+    config_provider: &ConfigProvider,
+    scheduler_context: Option<&Mutex<SchedQosContext>>,
+    thermal_state: ThermalState,
+    balloon_sizes: Option<Vec<BalloonSize>>,
+    redact: bool,
+) -> DebugDump {
+    let mut power_preferences = Vec::new();
+    for power_source_type in POWER_SOURCE_TYPES {
+        for power_preferences_type in POWER_PREFERENCES_TYPES {
+            let key = format!(
+                "{}/{}",
+                power_source_type.dir_name(),
+                power_preferences_type.dir_name()
+            );
+            let value = match config_provider
+                .read_power_preferences(power_source_type, power_preferences_type)
+            {
+                Ok(preferences) => preferences.map(|preferences| format!("{:?}", preferences)),
+                Err(e) => Some(format!("error: {:#}", e)),
+            };
+            power_preferences.push((key, value));
+        }
+    }
+
+    let features = match feature::get_feature_states() {
+        Ok(features) => features,
+        Err(e) => {
+            error!("Failed to get feature states: {:#}", e);
+            Vec::new()
+        }
+    };
+
+    let qos_processes = scheduler_context.map(|scheduler_context| {
+        // The dump is for debugging, so a panic in another thread should not hide the state.
+        let registrations = match scheduler_context.lock() {
+            Ok(mut ctx) => ctx.registrations(),
+            Err(poisoned) => poisoned.into_inner().registrations(),
+        };
+        registrations
+            .into_iter()
+            .map(|registration| {
+                let process_id = registration.process_id.into();
+                QosProcessDump {
+                    process_id,
+                    name: read_process_name(process_id, redact),
+                    state: registration.state,
+                    threads: registration
+                        .threads
+                        .into_iter()
+                        .map(|(thread_id, state)| QosThreadDump {
+                            thread_id: thread_id.into(),
+                            state,
+                        })
+                        .collect(),
+                }
+            })
+            .collect()
+    });
+
+    let vm_balloons = balloon_sizes.map(|balloons| {
+        balloons
+            .into_iter()
+            .map(|balloon| VmBalloonDump {
+                cid: balloon.cid,
+                target_size_kb: balloon.target_size_kb,
+            })
+            .collect()
+    });
+
+    DebugDump {
+        power_preferences,
+        features,
+        memory_margins: memory::get_component_margins_kb(),
+        last_pressure_reading: memory::get_last_pressure_reading(),
+        thermal_state,
+        qos_processes,
+        vm_balloons,
+        decisions: get_decisions(),
+    }
+}
+
+fn read_process_name(process_id: u32, redact: bool) -> String {
+    match std::fs::read(format!("/proc/{}/cmdline", process_id)) {
+        Ok(cmdline) => format_cmdline(&cmdline, redact),
+        // The process may have exited since the registrations were collected.
+        Err(_) => String::new(),
+    }
+}
+
+// Converts the NUL separated /proc/pid/cmdline into a space separated string. Some processes
+// (e.g. Chrome) rewrite their arguments into argv[0], so the redaction splits on whitespace too.
+fn format_cmdline(cmdline: &[u8], redact: bool) -> String {
+    let cmdline = cmdline
+        .split(|b| *b == 0)
+        .filter(|token| !token.is_empty())
+        .map(String::from_utf8_lossy)
+        .collect::<Vec<_>>()
+        .join(" ");
+    if redact {
+        cmdline
+            .split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .to_string()
+    } else {
+        cmdline
+    }
+}
+
+impl DebugDump {
+    pub fn to_json(&self) -> String {
+        match serde_json::to_string(self) {
+            Ok(json) => json,
+            Err(e) => {
+                error!("Failed to serialize the debug dump: {}", e);
+                String::new()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::FakeConfig;
+    use crate::config::Governor;
+    use crate::config::PowerPreferences;
+    use crate::memory::ArcMarginsKb;
+    use crate::memory::PressureStatus;
+
+    fn entry(timestamp_ms: u64, process_id: u32) -> JournalEntry {
+        JournalEntry {
+            timestamp_ms,
+            decision: Decision::ProcessExited { process_id },
+        }
+    }
+
+    #[test]
+    fn test_decision_journal_record() {
+        let mut journal = DecisionJournal::new(3);
+        assert!(journal.entries().is_empty());
+
+        journal.record(entry(1, 10));
+        journal.record(entry(2, 20));
+
+        assert_eq!(journal.entries(), vec![entry(1, 10), entry(2, 20)]);
+    }
+
+    #[test]
+    fn test_decision_journal_truncate() {
+        let mut journal = DecisionJournal::new(3);
+        for i in 0..5 {
+            journal.record(entry(i, i as u32));
+        }
+
+        // Only the newest decisions are kept, from the oldest to the newest.
+        assert_eq!(
+            journal.entries(),
+            vec![entry(2, 2), entry(3, 3), entry(4, 4)]
+        );
+    }
+
+    #[test]
+    fn test_decision_journal_zero_capacity() {
+        let mut journal = DecisionJournal::new(0);
+        journal.record(entry(1, 10));
+        assert!(journal.entries().is_empty());
+    }
+
+    #[test]
+    fn test_format_cmdline() {
+        let cmdline = b"/opt/google/chrome/chrome\0--type=renderer\0--user-data-dir=/home\0";
+        assert_eq!(
+            format_cmdline(cmdline, false),
+            "/opt/google/chrome/chrome --type=renderer --user-data-dir=/home"
+        );
+        assert_eq!(format_cmdline(cmdline, true), "/opt/google/chrome/chrome");
+
+        // Arguments rewritten into argv[0].
+        let cmdline = b"/opt/google/chrome/chrome --type=renderer\0";
+        assert_eq!(format_cmdline(cmdline, true), "/opt/google/chrome/chrome");
+
+        assert_eq!(format_cmdline(b"", true), "");
+    }
+
+    #[test]
+    fn test_decision_format() {
+        let tests = [
+            (
+                Decision::ChromePressure {
+                    from: PressureLevelChrome::None,
+                    to: PressureLevelChrome::Moderate,
+                    available_kb: 300,
+                    reclaim_target_kb: 100,
+                },
+                DecisionSource::Memory,
+                "chrome pressure None -> Moderate, available 300 KB, reclaim target 100 KB",
+            ),
+            (
+                Decision::ThreadState {
+                    process_id: 10,
+                    thread_id: 11,
+                    state: ThreadState::Eco,
+                },
+                DecisionSource::Qos,
+                "thread 10/11 -> Eco",
+            ),
+            (
+                Decision::ThermalState(ThermalState::Fair),
+                DecisionSource::Thermal,
+                "Fair",
+            ),
+        ];
+        for (decision, source, message) in tests {
+            assert_eq!(decision.source(), source);
+            assert_eq!(decision.to_string(), message);
+        }
+    }
+
+    #[test]
+    fn test_debug_dump_to_json() {
+        let dump = DebugDump {
+            power_preferences: vec![
+                (
+                    "ac/default-power-preferences".to_string(),
+                    Some("governor".to_string()),
+                ),
+                ("dc/default-power-preferences".to_string(), None),
+            ],
+            features: vec![("FeatureA".to_string(), true)],
+            memory_margins: ComponentMarginsKb {
+                chrome_critical: 100,
+                chrome_moderate: 400,
+                arcvm: ArcMarginsKb {
+                    foreground: 75,
+                    perceptible: 100,
+                    cached: 200,
+                },
+                arc_container: ArcMarginsKb {
+                    foreground: 0,
+                    perceptible: 100,
+                    cached: 200,
+                },
+            },
+            last_pressure_reading: Some(PressureReading {
+                available_kb: 90,
+                background_memory_kb: 5,
+                balloon_reclaim_kb: None,
+                status: PressureStatus {
+                    chrome_level: PressureLevelChrome::Critical,
+                    chrome_reclaim_target_kb: 10,
+                    arcvm_level: PressureLevelArcvm::Cached,
+                    arcvm_reclaim_target_kb: 110,
+                    arc_container_level: PressureLevelArcContainer::None,
+                    arc_container_reclaim_target_kb: 0,
+                },
+            }),
+            thermal_state: ThermalState::Serious,
+            qos_processes: Some(vec![QosProcessDump {
+                process_id: 10,
+                name: "chrome".to_string(),
+                state: ProcessState::Background,
+                threads: vec![QosThreadDump {
+                    thread_id: 11,
+                    state: ThreadState::Eco,
+                }],
+            }]),
+            vm_balloons: Some(vec![VmBalloonDump {
+                cid: 5,
+                target_size_kb: 1024,
+            }]),
+            decisions: vec![JournalEntry {
+                timestamp_ms: 1234,
+                decision: Decision::ProcessState {
+                    process_id: 10,
+                    state: ProcessState::Background,
+                },
+            }],
+        };
+
+        assert_eq!(
+            dump.to_json(),
+            concat!(
+                r#"{"power_preferences":{"ac/default-power-preferences":"governor","#,
+                r#""dc/default-power-preferences":null},"#,
+                r#""features":{"FeatureA":true},"#,
+                r#""memory_margins_kb":{"chrome_critical":100,"chrome_moderate":400,"#,
+                r#""arcvm":{"foreground":75,"perceptible":100,"cached":200},"#,
+                r#""arc_container":{"foreground":0,"perceptible":100,"cached":200}},"#,
+                r#""last_pressure_reading":{"available_kb":90,"background_memory_kb":5,"#,
+                r#""balloon_reclaim_kb":null,"chrome_level":"Critical","#,
+                r#""chrome_reclaim_target_kb":10,"arcvm_level":"Cached","#,
+                r#""arcvm_reclaim_target_kb":110,"arc_container_level":"None","#,
+                r#""arc_container_reclaim_target_kb":0},"thermal_state":"Serious","#,
+                r#""qos_processes":[{"process_id":10,"name":"chrome","state":"Background","#,
+                r#""threads":[{"thread_id":11,"state":"Eco"}]}],"#,
+                r#""vm_balloons":[{"cid":5,"target_size_kb":1024}],"#,
+                r#""decisions":[{"timestamp_ms":1234,"source":"qos","#,
+                r#""message":"process 10 -> Background"}]}"#,
+            )
+        );
+    }
+
+    #[test]
+    fn test_collect_debug_dump_power_preferences() {
+        let mut fake = FakeConfig::new();
+        fake.write_power_preference(
+            PowerSourceType::AC,
+            PowerPreferencesType::Default,
+            &PowerPreferences {
+                governor: Some(Governor::Performance),
+                epp: None,
+                cpu_offline: None,
+            },
+        );
+
+        let mut balloon = BalloonSize::new();
+        balloon.cid = 5;
+        balloon.target_size_kb = 1024;
+
+        let dump = collect_debug_dump(
+            &fake.provider(),
+            None,
+            ThermalState::Nominal,
+            Some(vec![balloon]),
+            true,
+        );
+
+        assert_eq!(
+            dump.power_preferences.len(),
+            POWER_SOURCE_TYPES.len() * POWER_PREFERENCES_TYPES.len()
+        );
+        assert_eq!(
+            dump.power_preferences[0],
+            (
+                "ac/default-power-preferences".to_string(),
+                Some(
+                    "PowerPreferences { governor: Some(Performance), epp: None, cpu_offline: None }"
+                        .to_string()
+                )
+            )
+        );
+        assert!(dump.power_preferences[1..]
+            .iter()
+            .all(|(_, value)| value.is_none()));
+        assert!(dump.qos_processes.is_none());
+        assert!(dump.to_json().contains(r#""qos_processes":null"#));
+        assert_eq!(
+            dump.vm_balloons,
+            Some(vec![VmBalloonDump {
+                cid: 5,
+                target_size_kb: 1024,
+            }])
+        );
+    }
+}
@@ -3,23 +3,39 @@
 // found in the LICENSE file.
 
 use std::collections::hash_map::Entry;
+use std::collections::BTreeMap;
 use std::collections::HashMap;
+use std::path::Path;
+use std::path::PathBuf;
 use std::sync::Mutex;
 
 use anyhow::bail;
 use anyhow::Context;
 use anyhow::Result;
+use async_trait::async_trait;
 use dbus::nonblock::SyncConnection;
 #[cfg(feature = "chromeos")]
-use featured::CheckFeature;
-#[cfg(feature = "chromeos")]
+use featured::CheckFeature; // Trait CheckFeature is for get_params_and_enabled
 use log::error;
-use once_cell::sync::OnceCell; // Trait CheckFeature is for is_feature_enabled_blocking
+use log::info;
+use log::warn;
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+use tokio::signal::unix::signal;
+use tokio::signal::unix::Signal;
+use tokio::signal::unix::SignalKind;
+
+// The local developer override file. /usr/local is not covered by rootfs verification, so the file
+// can be edited on a test device. It is only honored in developer mode.
+const DEV_OVERRIDES_PATH: &str = "usr/local/etc/resourced_features.json";
 
 struct Feature {
     // The cached results of feature query.
     enabled: bool,
 
+    // The cached parameters of the feature. Empty if the feature is disabled.
+    params: HashMap<String, String>,
+
     // There must only ever be one struct instance for a given feature name.
     //
     // Reference: https://chromium.googlesource.com/chromiumos/platform2/+/79195b9779a292e50cef56b609ea089bd92f2175/featured/c_feature_library.h#25
@@ -27,28 +43,145 @@ struct Feature {
     raw: featured::Feature,
 }
 
+// A developer override of a feature. The params replace the featured-provided params with the
+// same keys.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct FeatureOverride {
+    pub enabled: bool,
+    #[serde(default)]
+    pub params: HashMap<String, String>,
+}
+
+// The developer overrides loaded from DEV_OVERRIDES_PATH.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DevOverrides {
+    // Whether developer mode allowed the override file to be honored.
+    pub allowed: bool,
+    pub overrides: BTreeMap<String, FeatureOverride>,
+}
+
+// Loads the developer override file under root. The file maps feature names to overrides:
+//
+//   { "FeatureName": { "enabled": true, "params": { "key": "value" } } }
+//
+// The file is ignored unless is_dev_mode returns true. Errors are logged and result in no
+// overrides so that a broken file never prevents resourced from starting.
+fn load_dev_overrides(root: &Path, is_dev_mode: impl FnOnce() -> bool) -> DevOverrides {
+    let path = root.join(DEV_OVERRIDES_PATH);
+    let allowed = is_dev_mode();
+    if !path.exists() {
+        return DevOverrides {
+            allowed,
+            overrides: BTreeMap::new(),
+        };
+    }
+    if !allowed {
+        warn!(
+            "Ignoring feature overrides in {} outside developer mode",
+            path.display()
+        );
+        return DevOverrides {
+            allowed,
+            overrides: BTreeMap::new(),
+        };
+    }
+
+    let overrides = match std::fs::read_to_string(&path)
+        .context("failed to read feature overrides")
+        .and_then(|content| {
+            serde_json::from_str::<BTreeMap<String, FeatureOverride>>(&content)
+                .context("failed to parse feature overrides")
+        }) {
+        Ok(overrides) => overrides,
+        Err(e) => {
+            error!("Ignoring {}: {:#}", path.display(), e);
+            BTreeMap::new()
+        }
+    };
+    for (name, feature_override) in &overrides {
+        info!(
+            "Feature override: {} enabled={} params={:?}",
+            name, feature_override.enabled, feature_override.params
+        );
+    }
+    DevOverrides { allowed, overrides }
+}
+
+pub fn is_dev_mode() -> bool {
+    match libchromeos::chromeos::is_dev_mode() {
+        Ok(dev_mode) => dev_mode,
+        Err(e) => {
+            error!("Failed to check developer mode: {}", e);
+            false
+        }
+    }
+}
+
 // Only use featured in ebuild as using featured makes "cargo build" fail.
 //
 // Reference: https://chromium.googlesource.com/chromiumos/platform2/+/main/featured/README.md
 struct FeatureManager {
     features: HashMap<String, Feature>,
+
+    // The developer overrides merged over the cached feature query results.
+    dev_overrides: DevOverrides,
 }
 
 impl FeatureManager {
     fn new() -> FeatureManager {
         FeatureManager {
             features: HashMap::new(),
+            dev_overrides: DevOverrides::default(),
         }
     }
 
-    // Returns the cached feature query result.
+    // Returns the cached feature query result, or the developer override if any.
     fn is_feature_enabled(&self, feature_name: &str) -> Result<bool> {
+        if let Some(feature_override) = self.dev_overrides.overrides.get(feature_name) {
+            return Ok(feature_override.enabled);
+        }
         match self.features.get(feature_name) {
             Some(feature) => Ok(feature.enabled),
             None => Ok(false),
         }
     }
 
+    // Returns the cached parameter of the feature. None if the feature is disabled or the
+    // parameter is not set. A developer override disabling the feature drops all the parameters,
+    // and one enabling it layers its parameters over the cached ones.
+    fn get_feature_param(&self, feature_name: &str, key: &str) -> Option<String> {
+        if let Some(feature_override) = self.dev_overrides.overrides.get(feature_name) {
+            if !feature_override.enabled {
+                return None;
+            }
+            if let Some(value) = feature_override.params.get(key) {
+                return Some(value.clone());
+            }
+        }
+        self.features
+            .get(feature_name)
+            .and_then(|feature| feature.params.get(key))
+            .cloned()
+    }
+
+    // Returns the feature states with the developer overrides applied, sorted by feature name.
+    fn feature_states(&self) -> Vec<(String, bool)> {
+        let mut states: BTreeMap<String, bool> = self
+            .features
+            .iter()
+            .map(|(name, feature)| (name.clone(), feature.enabled))
+            .collect();
+        for (name, feature_override) in &self.dev_overrides.overrides {
+            states.insert(name.clone(), feature_override.enabled);
+        }
+        states.into_iter().collect()
+    }
+
+    fn set_dev_overrides(&mut self, dev_overrides: DevOverrides) {
+        self.dev_overrides = dev_overrides;
+    }
+
     // Adds a feature to the hashmap if it's not present and caches the feature query.
     fn initialize_feature(&mut self, feature_name: &str, enabled_by_default: bool) -> Result<()> {
         let Entry::Vacant(vacant_entry) = self.features.entry(feature_name.to_string()) else {
@@ -58,11 +191,17 @@ impl FeatureManager {
         cfg_if::cfg_if! {
             if #[cfg(feature = "chromeos")] {
                 let feature = featured::Feature::new(feature_name, enabled_by_default)?;
-                let enabled =
-                    featured::PlatformFeatures::get()?.is_feature_enabled_blocking(&feature);
-                vacant_entry.insert(Feature { enabled, raw: feature });
+                let resp = featured::PlatformFeatures::get()?
+                    .get_params_and_enabled(&[&feature])
+                    .context("failed to query feature")?;
+                let enabled = resp.is_enabled(&feature);
+                let params = resp.get_params(&feature).cloned().unwrap_or_default();
+                vacant_entry.insert(Feature { enabled, params, raw: feature });
             } else {
-                vacant_entry.insert(Feature { enabled: enabled_by_default });
+                vacant_entry.insert(Feature {
+                    enabled: enabled_by_default,
+                    params: HashMap::new(),
+                });
             }
         }
 
@@ -77,6 +216,7 @@ impl FeatureManager {
             .context("failed to query features")?;
         for feature in self.features.values_mut() {
             feature.enabled = resp.is_enabled(&feature.raw);
+            feature.params = resp.get_params(&feature.raw).cloned().unwrap_or_default();
         }
         Ok(())
     }
@@ -118,6 +258,67 @@ pub async fn start_feature_monitoring(conn: &SyncConnection) -> Result<()> {
     Ok(())
 }
 
+// A source of reload requests for the developer overrides, i.e. SIGHUP in production.
+#[async_trait]
+trait ReloadSource {
+    // Waits for the next reload request. Returns None when there will be no more requests.
+    async fn recv(&mut self) -> Option<()>;
+}
+
+#[async_trait]
+impl ReloadSource for Signal {
+    async fn recv(&mut self) -> Option<()> {
+        Signal::recv(self).await
+    }
+}
+
+async fn reload_dev_overrides_on_request(
+    feature_manager: &Mutex<FeatureManager>,
+    root: &Path,
+    reload_source: &mut (impl ReloadSource + Send),
+    is_dev_mode: impl Fn() -> bool + Send,
+) {
+    while reload_source.recv().await.is_some() {
+        info!("Reloading feature overrides");
+        let dev_overrides = load_dev_overrides(root, &is_dev_mode);
+        feature_manager
+            .lock()
+            .expect("Lock failed")
+            .set_dev_overrides(dev_overrides);
+    }
+}
+
+// Loads the developer feature overrides under root and reloads them on SIGHUP. Must be called
+// inside the tokio runtime.
+pub fn start_dev_overrides_monitoring(root: &Path) -> Result<()> {
+    let feature_manager = FEATURE_MANAGER
+        .get()
+        .context("FEATURE_MANAGER is not initialized")?;
+    let dev_overrides = load_dev_overrides(root, is_dev_mode);
+    feature_manager
+        .lock()
+        .expect("Lock failed")
+        .set_dev_overrides(dev_overrides);
+
+    let mut sighup = signal(SignalKind::hangup()).context("failed to listen to SIGHUP")?;
+    let root: PathBuf = root.to_path_buf();
+    tokio::spawn(async move {
+        reload_dev_overrides_on_request(feature_manager, &root, &mut sighup, is_dev_mode).await;
+    });
+    Ok(())
+}
+
+pub fn get_dev_overrides() -> Result<DevOverrides> {
+    let feature_manager = FEATURE_MANAGER
+        .get()
+        .context("FEATURE_MANAGER is not initialized")?;
+    if let Ok(feature_manager_lock) = feature_manager.lock() {
+        Ok(feature_manager_lock.dev_overrides.clone())
+    } else {
+        bail!("Failed to lock FEATURE_MANAGER");
+    }
+}
+
 #[cfg(test)]
 pub fn init_for_test() {
     let _ = init();
@@ -134,6 +335,17 @@ pub fn is_feature_enabled(feature_name: &str) -> Result<bool> {
     }
 }
 
+pub fn get_feature_states() -> Result<Vec<(String, bool)>> {
+    let feature_manager = FEATURE_MANAGER
+        .get()
+        .context("FEATURE_MANAGER is not initialized")?;
+    if let Ok(feature_manager_lock) = feature_manager.lock() {
+        Ok(feature_manager_lock.feature_states())
+    } else {
+        bail!("Failed to lock FEATURE_MANAGER");
+    }
+}
+
 pub fn initialize_feature(feature_name: &str, enabled_by_default: bool) -> Result<()> {
     let feature_manager = FEATURE_MANAGER
         .get()
@@ -144,3 +356,198 @@ pub fn initialize_feature(feature_name: &str, enabled_by_default: bool) -> Resul
         bail!("Failed to lock FEATURE_MANAGER");
     }
 }
+
+pub fn get_feature_param(feature_name: &str, key: &str) -> Result<Option<String>> {
+    let feature_manager = FEATURE_MANAGER
+        .get()
+        .context("FEATURE_MANAGER is not initialized")?;
+    if let Ok(feature_manager_lock) = feature_manager.lock() {
+        Ok(feature_manager_lock.get_feature_param(feature_name, key))
+    } else {
+        bail!("Failed to lock FEATURE_MANAGER");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokio::sync::mpsc;
+
+    struct ChannelReloadSource(mpsc::Receiver<()>);
+
+    #[async_trait]
+    impl ReloadSource for ChannelReloadSource {
+        async fn recv(&mut self) -> Option<()> {
+            self.0.recv().await
+        }
+    }
+
+    fn write_dev_overrides(root: &Path, content: &str) {
+        let path = root.join(DEV_OVERRIDES_PATH);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+
+    fn feature_override(enabled: bool, params: &[(&str, &str)]) -> FeatureOverride {
+        FeatureOverride {
+            enabled,
+            params: params
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_load_dev_overrides_dev_mode_gating() {
+        let root = tempfile::tempdir().unwrap();
+        write_dev_overrides(
+            root.path(),
+            r#"{"FeatureA": {"enabled": true, "params": {"key": "value"}}, "FeatureB": {"enabled": false}}"#,
+        );
+
+        let dev_overrides = load_dev_overrides(root.path(), || true);
+        assert!(dev_overrides.allowed);
+        assert_eq!(
+            dev_overrides.overrides,
+            BTreeMap::from([
+                (
+                    "FeatureA".to_string(),
+                    feature_override(true, &[("key", "value")])
+                ),
+                ("FeatureB".to_string(), feature_override(false, &[])),
+            ])
+        );
+
+        let dev_overrides = load_dev_overrides(root.path(), || false);
+        assert!(!dev_overrides.allowed);
+        assert!(dev_overrides.overrides.is_empty());
+    }
+
+    #[test]
+    fn test_load_dev_overrides_missing_or_broken() {
+        let root = tempfile::tempdir().unwrap();
+        assert_eq!(
+            load_dev_overrides(root.path(), || true),
+            DevOverrides {
+                allowed: true,
+                overrides: BTreeMap::new(),
+            }
+        );
+
+        write_dev_overrides(root.path(), r#"{"FeatureA": {"enabled": "yes"}}"#);
+        let dev_overrides = load_dev_overrides(root.path(), || true);
+        assert!(dev_overrides.allowed);
+        assert!(dev_overrides.overrides.is_empty());
+    }
+
+    #[test]
+    fn test_dev_overrides_merge() {
+        let mut feature_manager = FeatureManager::new();
+        feature_manager.initialize_feature("Enabled", true).unwrap();
+        feature_manager
+            .initialize_feature("Disabled", false)
+            .unwrap();
+        feature_manager
+            .features
+            .get_mut("Enabled")
+            .unwrap()
+            .params
+            .extend([
+                ("kept".to_string(), "featured".to_string()),
+                ("replaced".to_string(), "featured".to_string()),
+            ]);
+
+        feature_manager.set_dev_overrides(DevOverrides {
+            allowed: true,
+            overrides: BTreeMap::from([
+                (
+                    "Enabled".to_string(),
+                    feature_override(true, &[("replaced", "override"), ("added", "override")]),
+                ),
+                ("Disabled".to_string(), feature_override(true, &[])),
+                ("Unknown".to_string(), feature_override(true, &[])),
+            ]),
+        });
+        assert!(feature_manager.is_feature_enabled("Enabled").unwrap());
+        assert!(feature_manager.is_feature_enabled("Disabled").unwrap());
+        assert!(feature_manager.is_feature_enabled("Unknown").unwrap());
+        assert_eq!(
+            feature_manager.get_feature_param("Enabled", "kept"),
+            Some("featured".to_string())
+        );
+        assert_eq!(
+            feature_manager.get_feature_param("Enabled", "replaced"),
+            Some("override".to_string())
+        );
+        assert_eq!(
+            feature_manager.get_feature_param("Enabled", "added"),
+            Some("override".to_string())
+        );
+        assert_eq!(
+            feature_manager.feature_states(),
+            vec![
+                ("Disabled".to_string(), true),
+                ("Enabled".to_string(), true),
+                ("Unknown".to_string(), true),
+            ]
+        );
+
+        // Disabling a feature drops its parameters.
+        feature_manager.set_dev_overrides(DevOverrides {
+            allowed: true,
+            overrides: BTreeMap::from([(
+                "Enabled".to_string(),
+                feature_override(false, &[("replaced", "override")]),
+            )]),
+        });
+        assert!(!feature_manager.is_feature_enabled("Enabled").unwrap());
+        assert_eq!(feature_manager.get_feature_param("Enabled", "kept"), None);
+        assert_eq!(
+            feature_manager.get_feature_param("Enabled", "replaced"),
+            None
+        );
+
+        // Removing the overrides restores the featured-provided state.
+        feature_manager.set_dev_overrides(DevOverrides::default());
+        assert!(feature_manager.is_feature_enabled("Enabled").unwrap());
+        assert!(!feature_manager.is_feature_enabled("Disabled").unwrap());
+        assert_eq!(
+            feature_manager.get_feature_param("Enabled", "replaced"),
+            Some("featured".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reload_dev_overrides_on_request() {
+        let root = tempfile::tempdir().unwrap();
+        let feature_manager = Mutex::new(FeatureManager::new());
+        let (sender, receiver) = mpsc::channel(1);
+        let mut reload_source = ChannelReloadSource(receiver);
+
+        write_dev_overrides(root.path(), r#"{"FeatureA": {"enabled": true}}"#);
+        sender.send(()).await.unwrap();
+        drop(sender);
+        reload_dev_overrides_on_request(&feature_manager, root.path(), &mut reload_source, || true)
+            .await;
+        assert!(feature_manager
+            .lock()
+            .unwrap()
+            .is_feature_enabled("FeatureA")
+            .unwrap());
+
+        // Reloading outside developer mode drops the overrides.
+        let (sender, receiver) = mpsc::channel(1);
+        let mut reload_source = ChannelReloadSource(receiver);
+        sender.send(()).await.unwrap();
+        drop(sender);
+        reload_dev_overrides_on_request(&feature_manager, root.path(), &mut reload_source, || {
+            false
+        })
+        .await;
+        let feature_manager = feature_manager.lock().unwrap();
+        assert!(!feature_manager.is_feature_enabled("FeatureA").unwrap());
+        assert!(!feature_manager.dev_overrides.allowed);
+    }
+}
@@ -0,0 +1,720 @@
+// Copyright 2024 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Switches the QoS profile while a game is running in the Borealis VM.
+//!
+//! The game session is reported by the Borealis lifecycle via D-Bus. If it is not, a Borealis VM
+//! started according to the concierge signals is treated as a game session instead. A reported
+//! session is only accepted for the Borealis VM reported by concierge, and its vcpu threads must
+//! belong to the VM. The profile is reverted when the session ends or the VM stops. The VM is also
+//! checked periodically so that the profile is reverted even if the end of the session is missed.
+
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use log::info;
+use log::warn;
+use schedqos::SavedSchedAttr;
+
+use crate::memory;
+use crate::memory::MarginPolicy;
+use crate::qos;
+use crate::qos::SchedQosContext;
+use crate::qos::ThreadState;
+
+/// The interval of checking that the VM of the game session is still running.
+pub const WATCHDOG_INTERVAL: Duration = Duration::from_secs(10);
+
+// cpu.shares of the background cgroup during a game session, the minimum allowed by the kernel.
+const GAME_BACKGROUND_CPU_SHARES: u16 = 2;
+
+// The vcpu threads of crosvm are named "crosvm_vcpu<index>".
+const VCPU_THREAD_NAME_PREFIX: &str = "crosvm_vcpu";
+
+/// The VM process identified by its pid and start time, so that a reused pid is not mistaken for
+/// the VM.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VmProcess {
+    pub pid: u32,
+    /// The start time of the process in clock ticks after the system boot.
+    pub start_time: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GameSession {
+    /// The VM process.
+    pub vm: VmProcess,
+    /// The vcpu threads of the VM process to be boosted.
+    pub vcpu_thread_ids: Vec<u32>,
+}
+
+/// The subsystems changed for a game session.
+pub trait GameModeSubsystems {
+    fn boost_vcpu_threads(&mut self, session: &GameSession) -> Result<()>;
+    fn restore_vcpu_threads(&mut self, session: &GameSession) -> Result<()>;
+    fn set_background_cpu_shares(&mut self, cpu_shares: u16) -> Result<()>;
+    fn set_margin_policy(&mut self, policy: MarginPolicy);
+    fn load_vm_process(&self, vm_pid: u32) -> Result<VmProcess>;
+    fn is_vm_running(&self, vm: &VmProcess) -> bool;
+    fn is_vm_thread(&self, vm: &VmProcess, thread_id: u32) -> bool;
+    fn find_vcpu_threads(&self, vm: &VmProcess) -> Result<Vec<u32>>;
+}
+
+/// Tracks the game session and applies the profile to the subsystems.
+pub struct GameModeController<S> {
+    subsystems: S,
+    // The Borealis VM reported by concierge.
+    borealis_vm: Option<VmProcess>,
+    session: Option<GameSession>,
+}
+
+impl<S: GameModeSubsystems> GameModeController<S> {
+    pub fn new(subsystems: S) -> Self {
+        Self {
+            subsystems,
+            borealis_vm: None,
+            session: None,
+        }
+    }
+
+    /// Starts the game session reported by the Borealis lifecycle. A running session is replaced.
+    ///
+    /// The session is rejected unless `vm_pid` is the running Borealis VM reported by concierge
+    /// and all the `vcpu_thread_ids` are threads of it.
+    pub fn on_session_start(&mut self, vm_pid: u32, vcpu_thread_ids: Vec<u32>) -> Result<()> {
+        let Some(vm) = self
+            .borealis_vm
+            .filter(|vm| vm.pid == vm_pid && self.subsystems.is_vm_running(vm))
+        else {
+            bail!("VM {} is not the running Borealis VM", vm_pid);
+        };
+        if let Some(thread_id) = vcpu_thread_ids
+            .iter()
+            .find(|thread_id| !self.subsystems.is_vm_thread(&vm, **thread_id))
+        {
+            bail!("thread {} is not a thread of VM {}", thread_id, vm_pid);
+        }
+        let end_result = self.end_session();
+        let start_result = self.start_session(GameSession {
+            vm,
+            vcpu_thread_ids,
+        });
+        end_result.and(start_result)
+    }
+
+    pub fn on_session_end(&mut self) -> Result<()> {
+        self.end_session()
+    }
+
+    /// Starts a session for the Borealis VM unless a session of a running VM exists already.
+    pub fn on_vm_started(&mut self, vm_pid: u32) -> Result<()> {
+        let vm = self
+            .subsystems
+            .load_vm_process(vm_pid)
+            .context("load VM process")?;
+        self.borealis_vm = Some(vm);
+        if let Some(session) = &self.session {
+            if self.subsystems.is_vm_running(&session.vm) {
+                return Ok(());
+            }
+        }
+        let end_result = self.end_session();
+        let vcpu_thread_ids = self
+            .subsystems
+            .find_vcpu_threads(&vm)
+            .context("find vcpu threads")?;
+        let start_result = self.start_session(GameSession {
+            vm,
+            vcpu_thread_ids,
+        });
+        end_result.and(start_result)
+    }
+
+    pub fn on_vm_stopped(&mut self) -> Result<()> {
+        self.borealis_vm = None;
+        self.end_session()
+    }
+
+    /// Ends the session if its VM is not running anymore. This is called every
+    /// [WATCHDOG_INTERVAL].
+    pub fn check_vm(&mut self) -> Result<()> {
+        match &self.session {
+            Some(session) if !self.subsystems.is_vm_running(&session.vm) => {
+                warn!(
+                    "VM {} of the game session exited without ending the session",
+                    session.vm.pid
+                );
+                self.end_session()
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn start_session(&mut self, session: GameSession) -> Result<()> {
+        info!("Start game session for VM {}", session.vm.pid);
+        let boost_result = self.subsystems.boost_vcpu_threads(&session);
+        let cpu_shares_result = self
+            .subsystems
+            .set_background_cpu_shares(GAME_BACKGROUND_CPU_SHARES);
+        self.subsystems.set_margin_policy(MarginPolicy::Relaxed);
+        // The session is recorded even if some subsystems failed, to revert the others on the end.
+        self.session = Some(session);
+        boost_result.and(cpu_shares_result)
+    }
+
+    fn end_session(&mut self) -> Result<()> {
+        let Some(session) = self.session.take() else {
+            return Ok(());
+        };
+        info!("End game session for VM {}", session.vm.pid);
+        let restore_result = self.subsystems.restore_vcpu_threads(&session);
+        let cpu_shares_result = self
+            .subsystems
+            .set_background_cpu_shares(qos::BACKGROUND_CPU_SHARES);
+        self.subsystems.set_margin_policy(MarginPolicy::Default);
+        restore_result.and(cpu_shares_result)
+    }
+}
+
+/// Applies the game session to the system.
+pub struct SystemGameModeSubsystems {
+    root: PathBuf,
+    sched_ctx: Option<Arc<Mutex<SchedQosContext>>>,
+    // The scheduler settings of the boosted vcpu threads before the session.
+    saved_sched_attrs: Vec<(u32, SavedSchedAttr)>,
+}
+
+impl SystemGameModeSubsystems {
+    pub fn new(root: &Path, sched_ctx: Option<Arc<Mutex<SchedQosContext>>>) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            sched_ctx,
+            saved_sched_attrs: Vec::new(),
+        }
+    }
+}
+
+impl GameModeSubsystems for SystemGameModeSubsystems {
+    fn boost_vcpu_threads(&mut self, session: &GameSession) -> Result<()> {
+        let Some(sched_ctx) = &self.sched_ctx else {
+            bail!("no schedqos context");
+        };
+        // Only the scheduler settings of the vcpu threads are changed. The VM process is not
+        // registered to schedqos, which would move it and all its threads out of the cgroups set
+        // up by concierge.
+        let ctx = sched_ctx.lock().expect("lock schedqos context");
+        for thread_id in &session.vcpu_thread_ids {
+            let saved = ctx
+                .set_unmanaged_thread_sched_attr(
+                    session.vm.pid.into(),
+                    (*thread_id).into(),
+                    ThreadState::Urgent,
+                )
+                .with_context(|| format!("boost vcpu thread {}", thread_id))?;
+            self.saved_sched_attrs.push((*thread_id, saved));
+        }
+        Ok(())
+    }
+
+    fn restore_vcpu_threads(&mut self, session: &GameSession) -> Result<()> {
+        let saved_sched_attrs = std::mem::take(&mut self.saved_sched_attrs);
+        let Some(sched_ctx) = &self.sched_ctx else {
+            bail!("no schedqos context");
+        };
+        let ctx = sched_ctx.lock().expect("lock schedqos context");
+        let mut result = Ok(());
+        for (thread_id, saved) in saved_sched_attrs {
+            // The VM or the vcpu thread has exited. The thread id may be reused by another process.
+            if !self.is_vm_running(&session.vm) || !self.is_vm_thread(&session.vm, thread_id) {
+                continue;
+            }
+            match ctx.restore_unmanaged_thread_sched_attr(thread_id.into(), &saved) {
+                Ok(()) => {}
+                Err(schedqos::Error::SchedAttr(e)) if e.raw_os_error() == Some(libc::ESRCH) => {}
+                Err(e) => {
+                    result = Err(e).with_context(|| format!("restore vcpu thread {}", thread_id));
+                }
+            }
+        }
+        result
+    }
+
+    fn set_background_cpu_shares(&mut self, cpu_shares: u16) -> Result<()> {
+        qos::set_background_cpu_shares(&self.root, cpu_shares).context("set background cpu.shares")
+    }
+
+    fn set_margin_policy(&mut self, policy: MarginPolicy) {
+        memory::set_margin_policy(policy);
+    }
+
+    fn load_vm_process(&self, vm_pid: u32) -> Result<VmProcess> {
+        let stat = procfs::process::Process::new_with_root(
+            self.root.join("proc").join(vm_pid.to_string()),
+        )
+        .and_then(|process| process.stat())
+        .with_context(|| format!("read stat of VM {}", vm_pid))?;
+        Ok(VmProcess {
+            pid: vm_pid,
+            start_time: stat.starttime,
+        })
+    }
+
+    fn is_vm_running(&self, vm: &VmProcess) -> bool {
+        // The pid may have been reused by another process.
+        matches!(self.load_vm_process(vm.pid), Ok(current) if current == *vm)
+    }
+
+    fn is_vm_thread(&self, vm: &VmProcess, thread_id: u32) -> bool {
+        self.root
+            .join(format!("proc/{}/task/{}", vm.pid, thread_id))
+            .exists()
+    }
+
+    fn find_vcpu_threads(&self, vm: &VmProcess) -> Result<Vec<u32>> {
+        let task_dir = self.root.join(format!("proc/{}/task", vm.pid));
+        let mut thread_ids = Vec::new();
+        for entry in
+            std::fs::read_dir(&task_dir).with_context(|| format!("read {}", task_dir.display()))?
+        {
+            let entry = entry?;
+            let Ok(thread_id) = entry.file_name().to_string_lossy().parse::<u32>() else {
+                continue;
+            };
+            // The thread may exit while scanning.
+            let Ok(name) = std::fs::read_to_string(entry.path().join("comm")) else {
+                continue;
+            };
+            if name.starts_with(VCPU_THREAD_NAME_PREFIX) {
+                thread_ids.push(thread_id);
+            }
+        }
+        thread_ids.sort_unstable();
+        Ok(thread_ids)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::test_utils::create_schedqos_context_for_test;
+
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    enum Call {
+        Boost(u32, Vec<u32>),
+        Restore(u32, Vec<u32>),
+        CpuShares(u16),
+        MarginPolicy(MarginPolicy),
+    }
+
+    #[derive(Clone, Default)]
+    struct FakeSubsystems {
+        calls: Rc<RefCell<Vec<Call>>>,
+        // The start time of the running VMs by pid.
+        running_vms: Rc<RefCell<HashMap<u32, u64>>>,
+        fail_boost: bool,
+    }
+
+    impl FakeSubsystems {
+        fn take_calls(&self) -> Vec<Call> {
+            self.calls.take()
+        }
+    }
+
+    impl GameModeSubsystems for FakeSubsystems {
+        fn boost_vcpu_threads(&mut self, session: &GameSession) -> Result<()> {
+            self.calls
+                .borrow_mut()
+                .push(Call::Boost(session.vm.pid, session.vcpu_thread_ids.clone()));
+            if self.fail_boost {
+                bail!("boost failed");
+            }
+            Ok(())
+        }
+
+        fn restore_vcpu_threads(&mut self, session: &GameSession) -> Result<()> {
+            self.calls.borrow_mut().push(Call::Restore(
+                session.vm.pid,
+                session.vcpu_thread_ids.clone(),
+            ));
+            Ok(())
+        }
+
+        fn set_background_cpu_shares(&mut self, cpu_shares: u16) -> Result<()> {
+            self.calls.borrow_mut().push(Call::CpuShares(cpu_shares));
+            Ok(())
+        }
+
+        fn set_margin_policy(&mut self, policy: MarginPolicy) {
+            self.calls.borrow_mut().push(Call::MarginPolicy(policy));
+        }
+
+        fn load_vm_process(&self, vm_pid: u32) -> Result<VmProcess> {
+            match self.running_vms.borrow().get(&vm_pid) {
+                Some(start_time) => Ok(VmProcess {
+                    pid: vm_pid,
+                    start_time: *start_time,
+                }),
+                None => bail!("VM {} not found", vm_pid),
+            }
+        }
+
+        fn is_vm_running(&self, vm: &VmProcess) -> bool {
+            self.running_vms.borrow().get(&vm.pid) == Some(&vm.start_time)
+        }
+
+        fn is_vm_thread(&self, vm: &VmProcess, thread_id: u32) -> bool {
+            thread_id > vm.pid && thread_id < vm.pid + 10
+        }
+
+        fn find_vcpu_threads(&self, vm: &VmProcess) -> Result<Vec<u32>> {
+            Ok(vec![vm.pid + 1, vm.pid + 2])
+        }
+    }
+
+    fn start_calls(vm_pid: u32, vcpu_thread_ids: Vec<u32>) -> Vec<Call> {
+        vec![
+            Call::Boost(vm_pid, vcpu_thread_ids),
+            Call::CpuShares(GAME_BACKGROUND_CPU_SHARES),
+            Call::MarginPolicy(MarginPolicy::Relaxed),
+        ]
+    }
+
+    fn end_calls(vm_pid: u32, vcpu_thread_ids: Vec<u32>) -> Vec<Call> {
+        vec![
+            Call::Restore(vm_pid, vcpu_thread_ids),
+            Call::CpuShares(qos::BACKGROUND_CPU_SHARES),
+            Call::MarginPolicy(MarginPolicy::Default),
+        ]
+    }
+
+    // The fake start time of the VM is its pid.
+    fn vm(vm_pid: u32) -> VmProcess {
+        VmProcess {
+            pid: vm_pid,
+            start_time: vm_pid as u64,
+        }
+    }
+
+    fn session(vm_pid: u32, vcpu_thread_ids: &[u32]) -> GameSession {
+        GameSession {
+            vm: vm(vm_pid),
+            vcpu_thread_ids: vcpu_thread_ids.to_vec(),
+        }
+    }
+
+    // Creates a controller with the running Borealis VM reported by concierge.
+    fn controller_with_vm(
+        subsystems: &FakeSubsystems,
+        vm_pid: u32,
+    ) -> GameModeController<FakeSubsystems> {
+        subsystems
+            .running_vms
+            .borrow_mut()
+            .insert(vm_pid, vm_pid as u64);
+        let mut controller = GameModeController::new(subsystems.clone());
+        controller.borealis_vm = Some(vm(vm_pid));
+        controller
+    }
+
+    #[test]
+    fn test_session_start_and_end() {
+        let subsystems = FakeSubsystems::default();
+        let mut controller = controller_with_vm(&subsystems, 100);
+
+        controller.on_session_start(100, vec![101, 102]).unwrap();
+        assert_eq!(
+            controller.session.as_ref(),
+            Some(&session(100, &[101, 102]))
+        );
+        assert_eq!(subsystems.take_calls(), start_calls(100, vec![101, 102]));
+
+        // The VM is running.
+        controller.check_vm().unwrap();
+        assert!(subsystems.take_calls().is_empty());
+
+        controller.on_session_end().unwrap();
+        assert_eq!(controller.session.as_ref(), None);
+        assert_eq!(subsystems.take_calls(), end_calls(100, vec![101, 102]));
+
+        // Ending twice is no-op.
+        controller.on_session_end().unwrap();
+        controller.on_vm_stopped().unwrap();
+        assert!(subsystems.take_calls().is_empty());
+    }
+
+    #[test]
+    fn test_session_restart() {
+        let subsystems = FakeSubsystems::default();
+        let mut controller = controller_with_vm(&subsystems, 100);
+
+        controller.on_session_start(100, vec![101]).unwrap();
+        subsystems.take_calls();
+        controller.on_session_start(100, vec![102]).unwrap();
+        let mut expected = end_calls(100, vec![101]);
+        expected.extend(start_calls(100, vec![102]));
+        assert_eq!(subsystems.take_calls(), expected);
+        assert_eq!(controller.session.as_ref(), Some(&session(100, &[102])));
+    }
+
+    #[test]
+    fn test_session_rejected() {
+        let subsystems = FakeSubsystems::default();
+        let mut controller = GameModeController::new(subsystems.clone());
+
+        // No Borealis VM is reported.
+        subsystems.running_vms.borrow_mut().insert(100, 100);
+        assert!(controller.on_session_start(100, vec![101]).is_err());
+
+        let mut controller = controller_with_vm(&subsystems, 100);
+        // Not the Borealis VM.
+        subsystems.running_vms.borrow_mut().insert(200, 200);
+        assert!(controller.on_session_start(200, vec![201]).is_err());
+        // Not a thread of the VM.
+        assert!(controller.on_session_start(100, vec![101, 201]).is_err());
+        // The pid of the VM is reused by another process.
+        subsystems.running_vms.borrow_mut().insert(100, 1000);
+        assert!(controller.on_session_start(100, vec![101]).is_err());
+
+        assert_eq!(controller.session.as_ref(), None);
+        assert!(subsystems.take_calls().is_empty());
+    }
+
+    #[test]
+    fn test_missed_session_end() {
+        let subsystems = FakeSubsystems::default();
+        let mut controller = controller_with_vm(&subsystems, 100);
+
+        controller.on_session_start(100, vec![101]).unwrap();
+        subsystems.take_calls();
+
+        // Neither the session end nor the VM stop is notified.
+        subsystems.running_vms.borrow_mut().remove(&100);
+        controller.check_vm().unwrap();
+        assert_eq!(controller.session.as_ref(), None);
+        assert_eq!(subsystems.take_calls(), end_calls(100, vec![101]));
+
+        controller.check_vm().unwrap();
+        assert!(subsystems.take_calls().is_empty());
+    }
+
+    #[test]
+    fn test_vm_pid_reused() {
+        let subsystems = FakeSubsystems::default();
+        let mut controller = controller_with_vm(&subsystems, 100);
+
+        controller.on_session_start(100, vec![101]).unwrap();
+        subsystems.take_calls();
+
+        // The VM exited and another process got its pid.
+        subsystems.running_vms.borrow_mut().insert(100, 1000);
+        controller.check_vm().unwrap();
+        assert_eq!(controller.session.as_ref(), None);
+        assert_eq!(subsystems.take_calls(), end_calls(100, vec![101]));
+    }
+
+    #[test]
+    fn test_revert_on_vm_stopped() {
+        let subsystems = FakeSubsystems::default();
+        let mut controller = controller_with_vm(&subsystems, 100);
+
+        controller.on_session_start(100, vec![101]).unwrap();
+        subsystems.take_calls();
+
+        subsystems.running_vms.borrow_mut().remove(&100);
+        controller.on_vm_stopped().unwrap();
+        assert_eq!(controller.session.as_ref(), None);
+        assert_eq!(subsystems.take_calls(), end_calls(100, vec![101]));
+
+        // The stopped VM is not the Borealis VM anymore.
+        subsystems.running_vms.borrow_mut().insert(100, 100);
+        assert!(controller.on_session_start(100, vec![101]).is_err());
+    }
+
+    #[test]
+    fn test_vm_started_fallback() {
+        let subsystems = FakeSubsystems::default();
+        subsystems.running_vms.borrow_mut().insert(100, 100);
+        let mut controller = GameModeController::new(subsystems.clone());
+
+        controller.on_vm_started(100).unwrap();
+        assert_eq!(
+            controller.session.as_ref(),
+            Some(&session(100, &[101, 102]))
+        );
+        assert_eq!(subsystems.take_calls(), start_calls(100, vec![101, 102]));
+
+        // The reported session replaces the detected one.
+        controller.on_session_start(100, vec![103]).unwrap();
+        subsystems.take_calls();
+
+        // A session of a running VM is kept.
+        subsystems.running_vms.borrow_mut().insert(200, 200);
+        controller.on_vm_started(200).unwrap();
+        assert_eq!(controller.session.as_ref(), Some(&session(100, &[103])));
+        assert!(subsystems.take_calls().is_empty());
+
+        // A session of an exited VM is replaced.
+        subsystems.running_vms.borrow_mut().remove(&100);
+        controller.on_vm_started(200).unwrap();
+        assert_eq!(
+            controller.session.as_ref(),
+            Some(&session(200, &[201, 202]))
+        );
+        let mut expected = end_calls(100, vec![103]);
+        expected.extend(start_calls(200, vec![201, 202]));
+        assert_eq!(subsystems.take_calls(), expected);
+    }
+
+    #[test]
+    fn test_revert_after_partial_failure() {
+        let subsystems = FakeSubsystems {
+            fail_boost: true,
+            ..FakeSubsystems::default()
+        };
+        let mut controller = controller_with_vm(&subsystems, 100);
+
+        assert!(controller.on_session_start(100, vec![101]).is_err());
+        // The other subsystems are still applied.
+        assert_eq!(subsystems.take_calls(), start_calls(100, vec![101]));
+
+        subsystems.running_vms.borrow_mut().remove(&100);
+        controller.check_vm().unwrap();
+        assert_eq!(controller.session.as_ref(), None);
+        assert_eq!(subsystems.take_calls(), end_calls(100, vec![101]));
+    }
+
+    // Writes /proc/<pid>/stat of a process with the start time.
+    fn write_fake_stat(root: &Path, pid: u32, start_time: u64) {
+        let process_dir = root.join(format!("proc/{}", pid));
+        std::fs::create_dir_all(&process_dir).unwrap();
+        // The start time is the 22nd of the 52 fields.
+        let mut fields = vec![pid.to_string(), "(crosvm)".to_string(), "S".to_string()];
+        fields.extend((4..=52).map(|i| {
+            if i == 22 {
+                start_time.to_string()
+            } else {
+                "0".to_string()
+            }
+        }));
+        std::fs::write(process_dir.join("stat"), fields.join(" ") + "\n").unwrap();
+    }
+
+    #[test]
+    fn test_find_vcpu_threads() {
+        let root = tempfile::tempdir().unwrap();
+        write_fake_stat(root.path(), 100, 12345);
+        let task_dir = root.path().join("proc/100/task");
+        for (thread_id, name) in [
+            (100, "crosvm"),
+            (101, "crosvm_vcpu 0"),
+            (102, "v_balloon"),
+            (103, "crosvm_vcpu 1"),
+        ] {
+            let thread_dir = task_dir.join(thread_id.to_string());
+            std::fs::create_dir_all(&thread_dir).unwrap();
+            std::fs::write(thread_dir.join("comm"), format!("{}\n", name)).unwrap();
+        }
+        let subsystems = SystemGameModeSubsystems::new(root.path(), None);
+
+        let vm = subsystems.load_vm_process(100).unwrap();
+        assert_eq!(
+            vm,
+            VmProcess {
+                pid: 100,
+                start_time: 12345
+            }
+        );
+        assert!(subsystems.is_vm_running(&vm));
+        assert!(!subsystems.is_vm_running(&VmProcess {
+            start_time: 1,
+            ..vm
+        }));
+        assert!(subsystems.load_vm_process(200).is_err());
+        assert!(subsystems.is_vm_thread(&vm, 101));
+        assert!(!subsystems.is_vm_thread(&vm, 201));
+        assert_eq!(subsystems.find_vcpu_threads(&vm).unwrap(), vec![101, 103]);
+        assert!(subsystems
+            .find_vcpu_threads(&VmProcess { pid: 200, ..vm })
+            .is_err());
+    }
+
+    fn thread_nice(thread_id: u32) -> i32 {
+        // SAFETY: getpriority does not access memory.
+        unsafe { libc::getpriority(libc::PRIO_PROCESS, thread_id) }
+    }
+
+    // sched_getattr(2) is not supported on qemu-user which CQ uses to run tests for non-x86_64
+    // boards.
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_boost_and_restore_vcpu_threads() {
+        let root = tempfile::tempdir().unwrap();
+        let sched_ctx = create_schedqos_context_for_test();
+        let mut subsystems = SystemGameModeSubsystems::new(root.path(), Some(sched_ctx.clone()));
+
+        let (thread_id_sender, thread_id_receiver) = std::sync::mpsc::channel();
+        let (exit_sender, exit_receiver) = std::sync::mpsc::channel::<()>();
+        let thread = std::thread::spawn(move || {
+            // SAFETY: gettid does not access memory.
+            thread_id_sender
+                .send(unsafe { libc::gettid() } as u32)
+                .unwrap();
+            let _ = exit_receiver.recv();
+        });
+        let thread_id = thread_id_receiver.recv().unwrap();
+        let vm_pid = std::process::id();
+        write_fake_stat(root.path(), vm_pid, vm_pid as u64);
+        std::fs::create_dir_all(
+            root.path()
+                .join(format!("proc/{}/task/{}", vm_pid, thread_id)),
+        )
+        .unwrap();
+        let original_nice = thread_nice(thread_id);
+        let session = session(vm_pid, &[thread_id]);
+
+        subsystems.boost_vcpu_threads(&session).unwrap();
+        assert_eq!(
+            thread_nice(thread_id),
+            sched_ctx
+                .lock()
+                .unwrap()
+                .thread_config(ThreadState::Urgent)
+                .nice
+        );
+        // The VM process is not registered, which would move it out of the cgroups of concierge.
+        assert!(sched_ctx.lock().unwrap().registrations().is_empty());
+
+        subsystems.restore_vcpu_threads(&session).unwrap();
+        assert_eq!(thread_nice(thread_id), original_nice);
+
+        drop(exit_sender);
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn test_set_background_cpu_shares() {
+        let root = tempfile::tempdir().unwrap();
+        let cgroup_dir = root.path().join("sys/fs/cgroup/cpu/resourced/background");
+        std::fs::create_dir_all(&cgroup_dir).unwrap();
+        let mut subsystems = SystemGameModeSubsystems::new(root.path(), None);
+
+        subsystems
+            .set_background_cpu_shares(GAME_BACKGROUND_CPU_SHARES)
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(cgroup_dir.join("cpu.shares")).unwrap(),
+            "2"
+        );
+    }
+}
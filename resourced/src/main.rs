@@ -6,13 +6,19 @@ mod common;
 mod config;
 mod cpu_utils;
 mod dbus;
+mod dbus_clients;
 mod dbus_ownership_listener;
+mod discard;
+mod dump;
 mod feature;
+mod game_mode;
 mod memory;
+mod metrics;
 mod power;
 mod proc;
 mod psi;
 mod qos;
+mod thermal;
 mod vm_concierge_client;
 mod vm_memory_management_client;
 
@@ -44,6 +50,14 @@ const IDENT: &str = "resourced";
 fn main() -> Result<()> {
     install_memfd_handler();
 
+    // `resourced --dump [--redact]` prints the debug dump of the running resourced.
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "--dump") {
+        let redact = args.iter().any(|arg| arg == "--redact");
+        println!("{}", dbus::get_debug_dump_blocking(redact)?);
+        return Ok(());
+    }
+
     // Initialize syslog. The default log level is info (debug! and trace! are ignored).
     // You can change the log level with log::set_max_level().
     if let Err(e) = syslog::init(IDENT.to_string(), false /* log_to_stderr */) {
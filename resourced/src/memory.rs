@@ -10,6 +10,7 @@ use std::io::BufReader;
 use std::io::Write;
 use std::path::Path;
 use std::sync::Mutex;
+use std::sync::PoisonError;
 use std::time::Duration;
 use std::time::Instant;
 use std::time::SystemTime;
@@ -19,9 +20,11 @@ use anyhow::Context;
 use anyhow::Result;
 use log::error;
 use once_cell::sync::Lazy;
+use serde::Serialize;
 use system_api::vm_memory_management::ResizePriority;
 
 use crate::common;
+use crate::dump;
 use crate::vm_memory_management_client::VmMemoryManagementClient;
 
 // Critical margin is 5.2% of total memory, moderate margin is 40% of total
@@ -368,14 +371,48 @@ fn get_default_memory_margins_kb_impl() -> MemoryMarginsKb {
     get_memory_margins_kb_from_bps(DEFAULT_CRITICAL_MARGIN_BPS, DEFAULT_MODERATE_MARGIN_BPS)
 }
 
+/// How the memory margins are applied.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MarginPolicy {
+    /// The configured margins.
+    Default,
+    /// Lowered margins while a game is running, so that the game is not interrupted by memory
+    /// reclaim as early.
+    Relaxed,
+}
+
+// The percentage of the configured margins used with MarginPolicy::Relaxed.
+const RELAXED_MARGIN_PERCENT: u64 = 75;
+
+static MARGIN_POLICY: Mutex<MarginPolicy> = Mutex::new(MarginPolicy::Default);
+
+pub fn set_margin_policy(policy: MarginPolicy) {
+    *MARGIN_POLICY.lock().unwrap_or_else(PoisonError::into_inner) = policy;
+}
+
+pub fn get_margin_policy() -> MarginPolicy {
+    *MARGIN_POLICY.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn apply_margin_policy(critical: u64, moderate: u64, policy: MarginPolicy) -> (u64, u64) {
+    match policy {
+        MarginPolicy::Default => (critical, moderate),
+        MarginPolicy::Relaxed => (
+            critical * RELAXED_MARGIN_PERCENT / 100,
+            moderate * RELAXED_MARGIN_PERCENT / 100,
+        ),
+    }
+}
+
 pub fn get_memory_margins_kb() -> (u64, u64) {
-    match MEMORY_MARGINS.lock() {
+    let (critical, moderate) = match MEMORY_MARGINS.lock() {
         Ok(data) => (data.critical, data.moderate),
         Err(poisoned) => {
             let data = poisoned.into_inner();
             (data.critical, data.moderate)
         }
-    }
+    };
+    apply_margin_policy(critical, moderate, get_margin_policy())
 }
 
 pub fn set_memory_margins_bps(critical: u32, moderate: u32) -> Result<()> {
@@ -389,12 +426,22 @@ pub fn set_memory_margins_bps(critical: u32, moderate: u32) -> Result<()> {
     }
 }
 
+/// Reverts the memory margins set by [set_memory_margins_bps] to the configured ones.
+pub fn reset_memory_margins() {
+    let margins = get_default_memory_margins_kb_impl();
+    *MEMORY_MARGINS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner) = margins;
+}
+
+#[derive(Serialize)]
 pub struct ArcMarginsKb {
     pub foreground: u64,
     pub perceptible: u64,
     pub cached: u64,
 }
 
+#[derive(Serialize)]
 pub struct ComponentMarginsKb {
     pub chrome_critical: u64,
     pub chrome_moderate: u64,
@@ -449,7 +496,7 @@ pub fn get_component_margins_kb() -> ComponentMarginsKb {
     }
 }
 
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
 pub enum PressureLevelChrome {
     // There is enough memory to use.
     None = 0,
@@ -460,7 +507,7 @@ pub enum PressureLevelChrome {
     Critical = 2,
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, PartialOrd)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Serialize)]
 pub enum PressureLevelArcvm {
     // There is enough memory to use.
     None = 0,
@@ -472,7 +519,7 @@ pub enum PressureLevelArcvm {
     Foreground = 3,
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, PartialOrd)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Serialize)]
 pub enum PressureLevelArcContainer {
     // There is enough memory to use.
     None = 0,
@@ -484,7 +531,7 @@ pub enum PressureLevelArcContainer {
     Foreground = 3,
 }
 
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
 pub struct PressureStatus {
     pub chrome_level: PressureLevelChrome,
     pub chrome_reclaim_target_kb: u64,
@@ -494,6 +541,66 @@ pub struct PressureStatus {
     pub arc_container_reclaim_target_kb: u64,
 }
 
+// The inputs and the result of the last memory pressure check, kept for debug dumps.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct PressureReading {
+    pub available_kb: u64,
+    pub background_memory_kb: u64,
+    // Memory reclaimed from the VM balloons. None when the VM memory management service is not
+    // active.
+    pub balloon_reclaim_kb: Option<u64>,
+    #[serde(flatten)]
+    pub status: PressureStatus,
+}
+
+static LAST_PRESSURE_READING: Mutex<Option<PressureReading>> = Mutex::new(None);
+
+pub fn get_last_pressure_reading() -> Option<PressureReading> {
+    match LAST_PRESSURE_READING.lock() {
+        Ok(data) => *data,
+        Err(poisoned) => *poisoned.into_inner(),
+    }
+}
+
+// Stores the reading and records the pressure level transitions in the decision journal.
+fn update_last_pressure_reading(reading: PressureReading) {
+    let mut last_reading = match LAST_PRESSURE_READING.lock() {
+        Ok(data) => data,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    let last_status = last_reading.map(|reading| reading.status);
+    let status = reading.status;
+
+    let last_chrome_level = last_status.map_or(PressureLevelChrome::None, |s| s.chrome_level);
+    if status.chrome_level != last_chrome_level {
+        dump::record_decision(dump::Decision::ChromePressure {
+            from: last_chrome_level,
+            to: status.chrome_level,
+            available_kb: reading.available_kb,
+            reclaim_target_kb: status.chrome_reclaim_target_kb,
+        });
+    }
+    let last_arcvm_level = last_status.map_or(PressureLevelArcvm::None, |s| s.arcvm_level);
+    if status.arcvm_level != last_arcvm_level {
+        dump::record_decision(dump::Decision::ArcvmPressure {
+            from: last_arcvm_level,
+            to: status.arcvm_level,
+            reclaim_target_kb: status.arcvm_reclaim_target_kb,
+        });
+    }
+    let last_arc_container_level =
+        last_status.map_or(PressureLevelArcContainer::None, |s| s.arc_container_level);
+    if status.arc_container_level != last_arc_container_level {
+        dump::record_decision(dump::Decision::ArcContainerPressure {
+            from: last_arc_container_level,
+            to: status.arc_container_level,
+            reclaim_target_kb: status.arc_container_reclaim_target_kb,
+        });
+    }
+
+    *last_reading = Some(reading);
+}
+
 macro_rules! get_arc_level {
     ($fn:ident, $ret_type:ty) => {
         fn $fn(margins: &ArcMarginsKb, available: u64, background_memory: u64) -> ($ret_type, u64) {
@@ -613,6 +720,7 @@ pub async fn get_memory_pressure_status(
         .max(arc_container_perceptible_target)
         .max(raw_chrome_reclaim_target_kb);
     let background_memory_kb = get_chrome_memory_kb(ChromeProcessType::Background, max_target);
+    let mut balloon_reclaim = None;
     let (chrome_level, chrome_reclaim_target_kb, arcvm_level, arcvm_reclaim_target_kb) =
         if vmms_client.is_active() {
             let now = Instant::now();
@@ -627,6 +735,7 @@ pub async fn get_memory_pressure_status(
             if let Err(e) = report_vmms_reclaim_memory_duration(now.elapsed()) {
                 error!("Failed to report try_vmms_reclaim_memory duration {:?}", e);
             }
+            balloon_reclaim = Some(balloon_reclaim_kb);
             let (chrome_level, chrome_reclaim_target_kb) =
                 margins.compute_chrome_pressure(available + balloon_reclaim_kb);
             (
@@ -661,14 +770,22 @@ pub async fn get_memory_pressure_status(
     let (arc_container_level, arc_container_reclaim_target_kb) =
         get_arc_container_level(&margins.arc_container, available, background_memory_kb);
 
-    Ok(PressureStatus {
+    let status = PressureStatus {
         chrome_level,
         chrome_reclaim_target_kb,
         arcvm_level,
         arcvm_reclaim_target_kb,
         arc_container_level,
         arc_container_reclaim_target_kb,
-    })
+    };
+    update_last_pressure_reading(PressureReading {
+        available_kb: available,
+        background_memory_kb,
+        balloon_reclaim_kb: balloon_reclaim,
+        status,
+    });
+
+    Ok(status)
 }
 
 pub fn init_memory_configs() -> Result<()> {
@@ -728,7 +845,7 @@ fn init_memory_configs_impl(root: &Path) -> Result<()> {
 }
 
 // The browser type of the process list.
-#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub enum BrowserType {
     // Ash Chrome.
     Ash = 0,
@@ -824,6 +941,13 @@ pub fn set_browser_processes(
     );
 }
 
+/// Forgets the processes reported for the browser, e.g. when the browser has disconnected.
+pub fn clear_browser_processes(browser_type: BrowserType) {
+    let mut chrome_pids = CHROME_PIDS.lock().expect("Lock chrome_pids failed");
+    chrome_pids.remove(&(browser_type, ChromeProcessType::Background));
+    chrome_pids.remove(&(browser_type, ChromeProcessType::Protected));
+}
+
 // Returns the amount of memory in KiB of the given chrome process type. To reduce
 // the work when there are a lot of chrome processes, it would stop counting if the
 // memory exceeds |threshold_kb|. When |threshold_kb| is 0, it returns 0.
@@ -1059,6 +1183,18 @@ full avg10=29.29 avg60=19.01 avg300=5.44 total=17589167"#;
         assert_eq!(margins[1], 456);
     }
 
+    #[test]
+    fn test_apply_margin_policy() {
+        assert_eq!(
+            apply_margin_policy(1000, 4000, MarginPolicy::Default),
+            (1000, 4000)
+        );
+        assert_eq!(
+            apply_margin_policy(1000, 4000, MarginPolicy::Relaxed),
+            (750, 3000)
+        );
+    }
+
     #[test]
     fn test_bps_to_margins_bps() {
         let (critical, moderate) = total_mem_to_margins_bps(
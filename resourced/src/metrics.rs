@@ -0,0 +1,619 @@
+// Copyright 2024 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Daily aggregated resource usage reporting.
+//!
+//! CPU time per top-level cgroup and the memory PSI "full" total are sampled periodically. The
+//! deltas between samples are accumulated into daily counters which are persisted, so restarting
+//! resourced does not lose them, and flushed to UMA once the local calendar day changes.
+
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use log::error;
+use log::info;
+use schedqos::cgroups::CgroupVersion;
+
+use crate::common::Clock;
+use crate::common::SystemClock;
+use crate::common::SECONDS_PER_DAY;
+use crate::psi;
+use crate::qos;
+
+const STATE_FILE_PATH: &str = "var/lib/resourced/daily_resource_usage";
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+const CGROUP_ROOT: &str = "sys/fs/cgroup";
+const CPUACCT_ROOT: &str = "sys/fs/cgroup/cpuacct";
+
+/// Top-level cgroups CPU time is reported for. Everything outside of them is reported as "Other".
+const CPU_CATEGORIES: [CpuCategory; 3] = [
+    CpuCategory {
+        name: "Browser",
+        cgroup: "chrome",
+    },
+    CpuCategory {
+        name: "Arc",
+        cgroup: "session_manager_containers",
+    },
+    CpuCategory {
+        name: "Vms",
+        cgroup: "vms",
+    },
+];
+const NUM_CPU_CATEGORIES: usize = CPU_CATEGORIES.len();
+
+struct CpuCategory {
+    // Name used in the state file and the UMA histogram.
+    name: &'static str,
+    // Path of the cgroup relative to the cpuacct (v1) or unified (v2) hierarchy root.
+    cgroup: &'static str,
+}
+
+/// Resource usage counters. Used both for cumulative samples and for the accumulated daily usage.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ResourceUsage {
+    /// CPU time in microseconds per entry in [CPU_CATEGORIES]. None if the cgroup does not exist,
+    /// so that the category is not reported.
+    pub cpu_us: [Option<u64>; NUM_CPU_CATEGORIES],
+    /// CPU time in microseconds of all tasks.
+    pub total_cpu_us: u64,
+    /// Time in microseconds all non-idle tasks were stalled on memory.
+    pub memory_full_us: u64,
+}
+
+impl ResourceUsage {
+    // Adds the usage between the cumulative samples `prev` and `next`. A counter that went
+    // backwards was reset (e.g. its cgroup was recreated) and counts from 0, as does the counter of
+    // a cgroup that appeared. A missing cgroup adds nothing.
+    fn add_delta(&mut self, prev: &ResourceUsage, next: &ResourceUsage) {
+        fn delta(prev: u64, next: u64) -> u64 {
+            if next >= prev {
+                next - prev
+            } else {
+                next
+            }
+        }
+
+        for i in 0..NUM_CPU_CATEGORIES {
+            if let Some(next_cpu_us) = next.cpu_us[i] {
+                let cpu_us = delta(prev.cpu_us[i].unwrap_or(0), next_cpu_us);
+                self.cpu_us[i] = Some(self.cpu_us[i].unwrap_or(0) + cpu_us);
+            }
+        }
+        self.total_cpu_us += delta(prev.total_cpu_us, next.total_cpu_us);
+        self.memory_full_us += delta(prev.memory_full_us, next.memory_full_us);
+    }
+
+    // Percentage of the total CPU time spent in each reported category, followed by the
+    // remainder as "Other".
+    fn cpu_percentages(&self) -> Vec<(&'static str, i32)> {
+        if self.total_cpu_us == 0 {
+            return Vec::new();
+        }
+        let percent =
+            |cpu_us: u64| (cpu_us.min(self.total_cpu_us) * 100 / self.total_cpu_us) as i32;
+
+        let mut result: Vec<_> = CPU_CATEGORIES
+            .iter()
+            .zip(self.cpu_us)
+            .filter_map(|(category, cpu_us)| Some((category.name, percent(cpu_us?))))
+            .collect();
+        let categorized: u64 = self.cpu_us.iter().flatten().sum();
+        result.push((
+            "Other",
+            percent(self.total_cpu_us.saturating_sub(categorized)),
+        ));
+        result
+    }
+}
+
+/// Reads the CPU usage of a cgroup in microseconds from `cpu.stat` on cgroup v2 and from
+/// `cpuacct.usage` on cgroup v1. Returns None if the cgroup does not exist.
+fn read_cgroup_cpu_us(version: CgroupVersion, cgroup: &Path) -> Result<Option<u64>> {
+    let file_name = match version {
+        CgroupVersion::V1 => "cpuacct.usage",
+        CgroupVersion::V2 => "cpu.stat",
+    };
+    let content = match fs::read_to_string(cgroup.join(file_name)) {
+        Ok(content) => content,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", file_name)),
+    };
+
+    match version {
+        CgroupVersion::V1 => {
+            let usage_ns: u64 = content
+                .trim()
+                .parse()
+                .with_context(|| format!("Couldn't parse \"{}\" as u64", content.trim()))?;
+            Ok(Some(usage_ns / 1000))
+        }
+        CgroupVersion::V2 => {
+            let usage = content
+                .lines()
+                .find_map(|line| line.strip_prefix("usage_usec "))
+                .context("No usage_usec in cpu.stat")?;
+            let usage_us = usage
+                .trim()
+                .parse()
+                .with_context(|| format!("Couldn't parse \"{}\" as u64", usage))?;
+            Ok(Some(usage_us))
+        }
+    }
+}
+
+fn read_resource_usage(root: &Path) -> Result<ResourceUsage> {
+    let version = qos::detect_cgroup_hierarchy(root).version();
+    let cpu_root = match version {
+        CgroupVersion::V1 => root.join(CPUACCT_ROOT),
+        CgroupVersion::V2 => root.join(CGROUP_ROOT),
+    };
+    let mut usage = ResourceUsage::default();
+    for (i, category) in CPU_CATEGORIES.iter().enumerate() {
+        usage.cpu_us[i] = read_cgroup_cpu_us(version, &cpu_root.join(category.cgroup))
+            .with_context(|| format!("Failed to read CPU usage of {}", category.cgroup))?;
+    }
+    usage.total_cpu_us = read_cgroup_cpu_us(version, &cpu_root)
+        .context("Failed to read total CPU usage")?
+        .context("No CPU usage of the root cgroup")?;
+    usage.memory_full_us = psi::get_memory_full_total_us(root)?;
+    Ok(usage)
+}
+
+/// The persisted part of [DailyAggregator].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct DailyState {
+    // Wall clock time of the last report, in seconds since the unix epoch.
+    last_report: i64,
+    // Usage accumulated since the last report.
+    usage: ResourceUsage,
+}
+
+impl DailyState {
+    fn serialize(&self) -> String {
+        let mut content = format!("last_report={}\n", self.last_report);
+        for (category, cpu_us) in CPU_CATEGORIES.iter().zip(self.usage.cpu_us) {
+            if let Some(cpu_us) = cpu_us {
+                content += &format!("cpu_{}_us={}\n", category.name, cpu_us);
+            }
+        }
+        content += &format!("total_cpu_us={}\n", self.usage.total_cpu_us);
+        content += &format!("memory_full_us={}\n", self.usage.memory_full_us);
+        content
+    }
+
+    fn deserialize(content: &str) -> Result<Self> {
+        let mut state = DailyState::default();
+        let mut has_last_report = false;
+        for line in content.lines() {
+            let (key, value) = line
+                .split_once('=')
+                .with_context(|| format!("Malformed line \"{}\"", line))?;
+            if key == "last_report" {
+                state.last_report = value
+                    .parse()
+                    .with_context(|| format!("Couldn't parse \"{}\" as i64", value))?;
+                has_last_report = true;
+                continue;
+            }
+
+            let value: u64 = value
+                .parse()
+                .with_context(|| format!("Couldn't parse \"{}\" as u64", value))?;
+            if let Some(i) = CPU_CATEGORIES
+                .iter()
+                .position(|category| key == format!("cpu_{}_us", category.name))
+            {
+                state.usage.cpu_us[i] = Some(value);
+            } else if key == "total_cpu_us" {
+                state.usage.total_cpu_us = value;
+            } else if key == "memory_full_us" {
+                state.usage.memory_full_us = value;
+            }
+            // Unknown keys are ignored so that categories can be removed.
+        }
+        if !has_last_report {
+            bail!("No last_report");
+        }
+        Ok(state)
+    }
+}
+
+/// Accumulates resource usage and decides when to report it.
+pub struct DailyAggregator<C: Clock> {
+    root: PathBuf,
+    clock: C,
+    state: DailyState,
+    // The cumulative sample of the previous tick. Not persisted since the counters start over
+    // on reboot.
+    last_sample: Option<ResourceUsage>,
+}
+
+impl<C: Clock> DailyAggregator<C> {
+    /// Creates an aggregator resuming from the persisted state under `root`, if any.
+    pub fn new(root: &Path, clock: C) -> Self {
+        let state_path = root.join(STATE_FILE_PATH);
+        let state = match fs::read_to_string(&state_path) {
+            Ok(content) => match DailyState::deserialize(&content) {
+                Ok(state) => Some(state),
+                Err(e) => {
+                    error!("Discarding corrupted daily resource usage: {:#}", e);
+                    None
+                }
+            },
+            Err(e) if e.kind() == ErrorKind::NotFound => None,
+            Err(e) => {
+                error!("Failed to read {}: {}", state_path.display(), e);
+                None
+            }
+        };
+        let state = state.unwrap_or_else(|| DailyState {
+            last_report: clock.now(),
+            usage: ResourceUsage::default(),
+        });
+
+        DailyAggregator {
+            root: root.to_path_buf(),
+            clock,
+            state,
+            last_sample: None,
+        }
+    }
+
+    /// Samples the resource usage, accumulates it and persists the result.
+    ///
+    /// Returns the accumulated usage to report when the local day changed since the last report.
+    /// A report is only made when the current day is later than the day of the last report, so
+    /// the clock jumping backwards delays the next report instead of reporting the same day twice
+    /// and jumping forward several days reports once.
+    pub fn tick(&mut self) -> Result<Option<ResourceUsage>> {
+        let sample = read_resource_usage(&self.root)?;
+        if let Some(last_sample) = &self.last_sample {
+            self.state.usage.add_delta(last_sample, &sample);
+        }
+        self.last_sample = Some(sample);
+
+        let now = self.clock.now();
+        let report = if self.clock.local_day(now)? > self.clock.local_day(self.state.last_report)? {
+            self.state.last_report = now;
+            Some(std::mem::take(&mut self.state.usage))
+        } else {
+            None
+        };
+
+        // Persist before the caller reports so a crash cannot lead to reporting twice.
+        let state_path = self.root.join(STATE_FILE_PATH);
+        fs::write(&state_path, self.state.serialize())
+            .with_context(|| format!("Failed to write {}", state_path.display()))?;
+
+        Ok(report)
+    }
+}
+
+fn report_daily_usage(usage: &ResourceUsage) -> Result<()> {
+    let metrics = metrics_rs::MetricsLibrary::get().context("MetricsLibrary::get() failed")?;
+
+    // Shall panic on poisoned mutex.
+    let mut metrics = metrics.lock().expect("Lock MetricsLibrary object failed");
+    let memory_full_secs = (usage.memory_full_us / 1_000_000).min(i32::MAX as u64) as i32;
+    metrics.send_to_uma(
+        "Platform.Resourced.DailyMemoryPressureFullSeconds", // Metric name
+        memory_full_secs,                                    // Sample
+        1,                                                   // Min
+        SECONDS_PER_DAY as i32,                              // Max
+        50,                                                  // Number of buckets
+    )?;
+
+    for (name, percent) in usage.cpu_percentages() {
+        metrics.send_percentage_to_uma(
+            &format!("Platform.Resourced.DailyCpuPercent.{}", name),
+            percent,
+        )?;
+    }
+    Ok(())
+}
+
+/// Starts sampling resource usage periodically and reporting it to UMA once per day.
+pub fn start_daily_resource_usage_reporting(root: &Path) {
+    let mut aggregator = DailyAggregator::new(root, SystemClock);
+    tokio::spawn(async move {
+        loop {
+            match aggregator.tick() {
+                Ok(Some(usage)) => {
+                    info!("Reporting daily resource usage: {:?}", usage);
+                    if let Err(e) = report_daily_usage(&usage) {
+                        error!("Failed to report daily resource usage: {:#}", e);
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => error!("Failed to aggregate resource usage: {:#}", e),
+            }
+            tokio::time::sleep(SAMPLE_INTERVAL).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::test_create_parent_dir;
+    use crate::test_utils::FakeClock;
+
+    const HOUR: i64 = 60 * 60;
+    // Midnight UTC.
+    const DAY_START: i64 = 19_000 * SECONDS_PER_DAY;
+
+    struct FakeSystem {
+        root: tempfile::TempDir,
+        version: CgroupVersion,
+    }
+
+    impl FakeSystem {
+        fn new() -> Self {
+            Self::with_cgroup_version(CgroupVersion::V1)
+        }
+
+        fn with_cgroup_version(version: CgroupVersion) -> Self {
+            let fake = FakeSystem {
+                root: tempfile::tempdir().unwrap(),
+                version,
+            };
+            test_create_parent_dir(&fake.root.path().join(STATE_FILE_PATH));
+            if version == CgroupVersion::V2 {
+                let cgroup_root = fake.root().join(CGROUP_ROOT);
+                fs::create_dir_all(&cgroup_root).unwrap();
+                fs::write(cgroup_root.join("cgroup.controllers"), "cpu cpuset memory").unwrap();
+            }
+            fake.set_usage(&ResourceUsage::default());
+            fake
+        }
+
+        fn root(&self) -> &Path {
+            self.root.path()
+        }
+
+        fn write_cpu_us(&self, cgroup: &Path, cpu_us: u64) {
+            fs::create_dir_all(cgroup).unwrap();
+            match self.version {
+                CgroupVersion::V1 => {
+                    fs::write(cgroup.join("cpuacct.usage"), format!("{}\n", cpu_us * 1000))
+                }
+                CgroupVersion::V2 => fs::write(
+                    cgroup.join("cpu.stat"),
+                    format!("usage_usec {}\nuser_usec 0\nsystem_usec 0\n", cpu_us),
+                ),
+            }
+            .unwrap();
+        }
+
+        // Writes the cumulative counters. The cgroups of unreported categories are removed.
+        fn set_usage(&self, usage: &ResourceUsage) {
+            let cpu_root = self.root().join(match self.version {
+                CgroupVersion::V1 => CPUACCT_ROOT,
+                CgroupVersion::V2 => CGROUP_ROOT,
+            });
+            for (category, cpu_us) in CPU_CATEGORIES.iter().zip(usage.cpu_us) {
+                let cgroup = cpu_root.join(category.cgroup);
+                match cpu_us {
+                    Some(cpu_us) => self.write_cpu_us(&cgroup, cpu_us),
+                    None => {
+                        if cgroup.exists() {
+                            fs::remove_dir_all(&cgroup).unwrap();
+                        }
+                    }
+                }
+            }
+            self.write_cpu_us(&cpu_root, usage.total_cpu_us);
+
+            let psi_path = self.root().join("proc/pressure/memory");
+            test_create_parent_dir(&psi_path);
+            fs::write(
+                psi_path,
+                format!(
+                    "some avg10=0.00 avg60=0.00 avg300=0.00 total={}\n\
+                     full avg10=0.00 avg60=0.00 avg300=0.00 total={}\n",
+                    usage.memory_full_us * 2,
+                    usage.memory_full_us
+                ),
+            )
+            .unwrap();
+        }
+    }
+
+    fn usage(
+        cpu_us: [u64; NUM_CPU_CATEGORIES],
+        total_cpu_us: u64,
+        memory_full_us: u64,
+    ) -> ResourceUsage {
+        ResourceUsage {
+            cpu_us: cpu_us.map(Some),
+            total_cpu_us,
+            memory_full_us,
+        }
+    }
+
+    #[test]
+    fn test_read_resource_usage() {
+        for version in [CgroupVersion::V1, CgroupVersion::V2] {
+            let fake = FakeSystem::with_cgroup_version(version);
+            let expected = usage([10, 20, 30], 100, 5);
+            fake.set_usage(&expected);
+            assert_eq!(read_resource_usage(fake.root()).unwrap(), expected);
+
+            // Missing cgroups are not reported.
+            let mut expected = usage([10, 20, 30], 100, 5);
+            expected.cpu_us[2] = None;
+            fake.set_usage(&expected);
+            assert_eq!(read_resource_usage(fake.root()).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_missing_cgroup_not_reported() {
+        let fake = FakeSystem::new();
+        let clock = FakeClock::from_secs(DAY_START + 23 * HOUR);
+        let mut without_vms = usage([100, 100, 0], 1000, 0);
+        without_vms.cpu_us[2] = None;
+        fake.set_usage(&without_vms);
+        let mut aggregator = DailyAggregator::new(fake.root(), clock.clone());
+        aggregator.tick().unwrap();
+
+        without_vms.cpu_us[0] = Some(150);
+        without_vms.total_cpu_us = 1100;
+        fake.set_usage(&without_vms);
+        clock.set_secs(DAY_START + SECONDS_PER_DAY);
+        let report = aggregator.tick().unwrap().unwrap();
+        assert_eq!(report.cpu_us, [Some(50), Some(0), None]);
+        assert_eq!(
+            report.cpu_percentages(),
+            vec![("Browser", 50), ("Arc", 0), ("Other", 50)]
+        );
+
+        // A cgroup created since the last sample counts from 0.
+        fake.set_usage(&usage([150, 100, 30], 1200, 0));
+        aggregator.tick().unwrap();
+        assert_eq!(aggregator.state.usage.cpu_us, [Some(0), Some(0), Some(30)]);
+    }
+
+    #[test]
+    fn test_accumulates_deltas() {
+        let fake = FakeSystem::new();
+        let clock = FakeClock::from_secs(DAY_START + HOUR);
+        fake.set_usage(&usage([100, 100, 100], 1000, 50));
+        let mut aggregator = DailyAggregator::new(fake.root(), clock.clone());
+
+        // The first sample is only the baseline.
+        assert_eq!(aggregator.tick().unwrap(), None);
+        assert_eq!(aggregator.state.usage, ResourceUsage::default());
+
+        fake.set_usage(&usage([150, 110, 100], 1200, 80));
+        clock.set_secs(DAY_START + 2 * HOUR);
+        assert_eq!(aggregator.tick().unwrap(), None);
+        assert_eq!(aggregator.state.usage, usage([50, 10, 0], 200, 30));
+
+        // The ARC cgroup was recreated and its counter restarted.
+        fake.set_usage(&usage([160, 5, 100], 1300, 80));
+        clock.set_secs(DAY_START + 3 * HOUR);
+        assert_eq!(aggregator.tick().unwrap(), None);
+        assert_eq!(aggregator.state.usage, usage([60, 15, 0], 300, 30));
+    }
+
+    #[test]
+    fn test_persists_across_restarts() {
+        let fake = FakeSystem::new();
+        let clock = FakeClock::from_secs(DAY_START + HOUR);
+        fake.set_usage(&usage([100, 100, 100], 1000, 50));
+        let mut aggregator = DailyAggregator::new(fake.root(), clock.clone());
+        aggregator.tick().unwrap();
+        fake.set_usage(&usage([150, 100, 100], 1100, 60));
+        aggregator.tick().unwrap();
+        drop(aggregator);
+
+        // The restarted aggregator resumes the counters and takes a new baseline.
+        let mut aggregator = DailyAggregator::new(fake.root(), clock.clone());
+        assert_eq!(aggregator.state.last_report, DAY_START + HOUR);
+        assert_eq!(aggregator.state.usage, usage([50, 0, 0], 100, 10));
+        fake.set_usage(&usage([200, 100, 100], 1300, 60));
+        aggregator.tick().unwrap();
+        fake.set_usage(&usage([210, 100, 100], 1400, 60));
+        aggregator.tick().unwrap();
+        assert_eq!(aggregator.state.usage, usage([60, 0, 0], 200, 10));
+    }
+
+    #[test]
+    fn test_discards_corrupted_state() {
+        let fake = FakeSystem::new();
+        fs::write(fake.root().join(STATE_FILE_PATH), "total_cpu_us=abc\n").unwrap();
+        let aggregator = DailyAggregator::new(fake.root(), FakeClock::from_secs(DAY_START));
+        assert_eq!(aggregator.state.last_report, DAY_START);
+        assert_eq!(aggregator.state.usage, ResourceUsage::default());
+    }
+
+    #[test]
+    fn test_state_serialization() {
+        let state = DailyState {
+            last_report: DAY_START,
+            usage: usage([1, 2, 3], 10, 4),
+        };
+        assert_eq!(DailyState::deserialize(&state.serialize()).unwrap(), state);
+        assert!(DailyState::deserialize("total_cpu_us=1\n").is_err());
+        assert!(DailyState::deserialize("last_report=1\ngarbage\n").is_err());
+    }
+
+    #[test]
+    fn test_day_rollover() {
+        let fake = FakeSystem::new();
+        let clock = FakeClock::from_secs(DAY_START + 23 * HOUR);
+        let mut aggregator = DailyAggregator::new(fake.root(), clock.clone());
+        aggregator.tick().unwrap();
+
+        fake.set_usage(&usage([10, 20, 30], 100, 3_000_000));
+        clock.set_secs(DAY_START + SECONDS_PER_DAY + HOUR);
+        assert_eq!(
+            aggregator.tick().unwrap(),
+            Some(usage([10, 20, 30], 100, 3_000_000))
+        );
+        assert_eq!(
+            aggregator.state.last_report,
+            DAY_START + SECONDS_PER_DAY + HOUR
+        );
+        assert_eq!(aggregator.state.usage, ResourceUsage::default());
+
+        // The reset counters are persisted.
+        let restarted = DailyAggregator::new(fake.root(), clock.clone());
+        assert_eq!(restarted.state, aggregator.state);
+
+        // No second report on the same day.
+        fake.set_usage(&usage([20, 20, 30], 200, 3_000_000));
+        clock.set_secs(DAY_START + SECONDS_PER_DAY + 23 * HOUR);
+        assert_eq!(aggregator.tick().unwrap(), None);
+    }
+
+    #[test]
+    fn test_clock_jumps() {
+        let fake = FakeSystem::new();
+        let clock = FakeClock::from_secs(DAY_START + HOUR);
+        let mut aggregator = DailyAggregator::new(fake.root(), clock.clone());
+        aggregator.tick().unwrap();
+
+        // Jumping forward several days reports once.
+        clock.set_secs(DAY_START + 5 * SECONDS_PER_DAY);
+        assert!(aggregator.tick().unwrap().is_some());
+        clock.set_secs(DAY_START + 5 * SECONDS_PER_DAY + HOUR);
+        assert_eq!(aggregator.tick().unwrap(), None);
+
+        // Jumping back to a day that was already reported does not report it again.
+        clock.set_secs(DAY_START + 3 * SECONDS_PER_DAY);
+        assert_eq!(aggregator.tick().unwrap(), None);
+        clock.set_secs(DAY_START + 4 * SECONDS_PER_DAY + HOUR);
+        assert_eq!(aggregator.tick().unwrap(), None);
+        clock.set_secs(DAY_START + 5 * SECONDS_PER_DAY + 2 * HOUR);
+        assert_eq!(aggregator.tick().unwrap(), None);
+
+        // The day after the last report is reported.
+        clock.set_secs(DAY_START + 6 * SECONDS_PER_DAY);
+        assert!(aggregator.tick().unwrap().is_some());
+    }
+
+    #[test]
+    fn test_cpu_percentages() {
+        assert_eq!(ResourceUsage::default().cpu_percentages(), vec![]);
+        assert_eq!(
+            usage([50, 20, 5], 100, 0).cpu_percentages(),
+            vec![("Browser", 50), ("Arc", 20), ("Vms", 5), ("Other", 25)]
+        );
+        // Counters sampled at slightly different times may exceed the total.
+        assert_eq!(
+            usage([90, 20, 0], 100, 0).cpu_percentages(),
+            vec![("Browser", 90), ("Arc", 20), ("Vms", 0), ("Other", 0)]
+        );
+    }
+}
@@ -6,6 +6,8 @@ use std::fs::read_to_string;
 use std::path::Path;
 use std::path::PathBuf;
 use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::Mutex;
 use std::vec::Vec;
 
 use anyhow::bail;
@@ -28,13 +30,18 @@ use crate::config::Governor;
 use crate::config::PowerPreferences;
 use crate::config::PowerPreferencesType;
 use crate::config::PowerSourceType;
+#[cfg(target_arch = "x86_64")]
+use crate::cpu_scaling;
 use crate::cpu_utils::hotplug_cpus;
 use crate::cpu_utils::HotplugCpuAction;
+use crate::thermal::ThermalHook;
 
 const POWER_SUPPLY_PATH: &str = "sys/class/power_supply";
 const POWER_SUPPLY_ONLINE: &str = "online";
 const POWER_SUPPLY_STATUS: &str = "status";
 const GLOBAL_ONDEMAND_PATH: &str = "sys/devices/system/cpu/cpufreq/ondemand";
+const CPU_BOOST_PATH: &str = "sys/devices/system/cpu/cpufreq/boost";
+const INTEL_PSTATE_NO_TURBO_PATH: &str = "sys/devices/system/cpu/intel_pstate/no_turbo";
 
 pub trait PowerSourceProvider {
     /// Returns the current power source of the system.
@@ -175,6 +182,9 @@ pub trait PowerPreferencesManager {
         vmboot: common::VmBootMode,
         batterysaver: common::BatterySaverMode,
     ) -> Result<()>;
+    /// Suppresses the CPU frequency boost (turbo) while `throttled` is true. The values before
+    /// throttling are restored when `throttled` is false.
+    fn set_cpu_boost_throttled(&self, throttled: bool) -> Result<()>;
     fn get_root(&self) -> &Path;
 }
 
@@ -234,6 +244,8 @@ pub struct DirectoryPowerPreferencesManager<P: PowerSourceProvider> {
     root: PathBuf,
     config_provider: ConfigProvider,
     power_source_provider: P,
+    // The cpu boost attributes changed by thermal throttling and their original values.
+    saved_cpu_boost: Arc<Mutex<Vec<(PathBuf, String)>>>,
 }
 
 impl<P: PowerSourceProvider> DirectoryPowerPreferencesManager<P> {
@@ -442,20 +454,75 @@ impl<P: PowerSourceProvider> PowerPreferencesManager for DirectoryPowerPreferenc
             self.apply_power_preferences(preferences)?
         }
 
-        if power_source == PowerSourceType::DC
+        // WebRTC and fullscreen video on battery lower the EPP, otherwise the EPP of the cpu
+        // scaling profile of the power source applies.
+        let epp = if power_source == PowerSourceType::DC
             && (rtc == RTCAudioActive::Active || fullscreen == FullscreenVideo::Active)
         {
-            if let Err(err) = self.set_epp(EnergyPerformancePreference::BalancePower) {
-                error!("Failed to set energy performance preference: {:#}", err);
-            }
+            Some(EnergyPerformancePreference::BalancePower)
         } else {
-            self.set_epp(EnergyPerformancePreference::BalancePerformance)?;
+            None
+        };
+
+        #[cfg(target_arch = "x86_64")]
+        match cpu_scaling::apply_power_source_profile(
+            &cpu_scaling::SysfsCpuScalingBackend::new(&self.root),
+            power_source,
+            epp,
+        ) {
+            Ok(true) => return Ok(()),
+            Ok(false) => {}
+            Err(err) => error!("Failed to apply cpu scaling profile: {:#}", err),
+        }
+
+        match epp {
+            Some(epp) => {
+                if let Err(err) = self.set_epp(epp) {
+                    error!("Failed to set energy performance preference: {:#}", err);
+                }
+            }
             // Default EPP
+            None => self.set_epp(EnergyPerformancePreference::BalancePerformance)?,
         }
 
         Ok(())
     }
 
+    fn set_cpu_boost_throttled(&self, throttled: bool) -> Result<()> {
+        // Both the cpufreq `boost` and the intel_pstate `no_turbo` attributes are supported.
+        let mut saved = self
+            .saved_cpu_boost
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if !throttled {
+            let mut result = Ok(());
+            for (path, value) in saved.drain(..) {
+                if let Err(e) = std::fs::write(&path, &value) {
+                    result = Err(e).with_context(|| {
+                        format!("Failed to restore {} to {}", path.display(), value)
+                    });
+                }
+            }
+            return result;
+        }
+
+        for (attr, disabled) in [(CPU_BOOST_PATH, "0"), (INTEL_PSTATE_NO_TURBO_PATH, "1")] {
+            let path = self.root.join(attr);
+            // The attribute only exists if the cpufreq driver supports it.
+            let Ok(value) = read_to_string(&path) else {
+                continue;
+            };
+            let value = value.trim_end_matches('\n');
+            if value != disabled {
+                std::fs::write(&path, disabled)
+                    .with_context(|| format!("Failed to write {}", path.display()))?;
+                info!("Thermal throttling, set {} to {}", attr, disabled);
+                saved.push((path, value.to_string()));
+            }
+        }
+        Ok(())
+    }
+
     fn get_root(&self) -> &Path {
         self.root.as_path()
     }
@@ -470,6 +537,33 @@ pub fn new_directory_power_preferences_manager(
         root: root.clone(),
         config_provider,
         power_source_provider: DirectoryPowerSourceProvider::new(root),
+        saved_cpu_boost: Arc::new(Mutex::new(Vec::new())),
+    }
+}
+
+/// Suppresses the CPU frequency boost (turbo) while the system is thermally throttled.
+///
+/// The cpufreq attributes are owned by the [PowerPreferencesManager], which applies the change.
+pub struct CpuBoostThermalHook {
+    power_preferences_manager: Arc<dyn PowerPreferencesManager + Send + Sync>,
+}
+
+impl CpuBoostThermalHook {
+    pub fn new(power_preferences_manager: Arc<dyn PowerPreferencesManager + Send + Sync>) -> Self {
+        CpuBoostThermalHook {
+            power_preferences_manager,
+        }
+    }
+}
+
+impl ThermalHook for CpuBoostThermalHook {
+    fn name(&self) -> &'static str {
+        "cpu boost"
+    }
+
+    fn set_throttled(&mut self, throttled: bool) -> Result<()> {
+        self.power_preferences_manager
+            .set_cpu_boost_throttled(throttled)
     }
 }
 
@@ -770,6 +864,7 @@ mod tests {
             root: root.path().to_path_buf(),
             config_provider,
             power_source_provider,
+            saved_cpu_boost: Default::default(),
         };
 
         manager.update_power_preferences(
@@ -806,6 +901,7 @@ mod tests {
             root: root.path().to_path_buf(),
             config_provider,
             power_source_provider,
+            saved_cpu_boost: Default::default(),
         };
 
         manager.update_power_preferences(
@@ -856,6 +952,7 @@ mod tests {
             root: root.path().to_path_buf(),
             config_provider,
             power_source_provider,
+            saved_cpu_boost: Default::default(),
         };
 
         manager.update_power_preferences(
@@ -906,6 +1003,7 @@ mod tests {
             root: root.path().to_path_buf(),
             config_provider,
             power_source_provider,
+            saved_cpu_boost: Default::default(),
         };
 
         manager.update_power_preferences(
@@ -956,6 +1054,7 @@ mod tests {
             root: root.path().to_path_buf(),
             config_provider,
             power_source_provider,
+            saved_cpu_boost: Default::default(),
         };
 
         manager.update_power_preferences(
@@ -1006,6 +1105,7 @@ mod tests {
             root: root.path().to_path_buf(),
             config_provider,
             power_source_provider,
+            saved_cpu_boost: Default::default(),
         };
 
         manager.update_power_preferences(
@@ -1071,6 +1171,7 @@ mod tests {
             root: root.to_path_buf(),
             config_provider,
             power_source_provider,
+            saved_cpu_boost: Default::default(),
         };
 
         let tests = [
@@ -1264,6 +1365,7 @@ mod tests {
                 power_source_provider: FakePowerSourceProvider {
                     power_source: PowerSourceType::DC,
                 },
+                saved_cpu_boost: Default::default(),
             };
             test_write_cpuset_root_cpus(root, test.cpus);
             test_write_smt_control(root, test.smt_orig_state);
@@ -1404,6 +1506,7 @@ mod tests {
                 root: root.path().to_path_buf(),
                 config_provider,
                 power_source_provider: test.0,
+                saved_cpu_boost: Default::default(),
             };
 
             manager.update_power_preferences(
@@ -1422,6 +1525,68 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_power_update_power_preferences_cpu_scaling_profile() -> Result<()> {
+        let root = tempdir()?;
+
+        write_epp(root.path(), "balance_performance", AFFECTED_CPU0)?;
+        test_write_cpuset_root_cpus(root.path(), "0-3");
+        let policy0 = root.path().join("sys/devices/system/cpu/cpufreq/policy0");
+        fs::write(policy0.join("scaling_driver"), "intel_pstate\n")?;
+        let intel_pstate = root.path().join("sys/devices/system/cpu/intel_pstate");
+        fs::create_dir_all(&intel_pstate)?;
+        fs::write(intel_pstate.join("min_perf_pct"), "20\n")?;
+        fs::write(intel_pstate.join("max_perf_pct"), "80\n")?;
+
+        // The profile of the power source sets the EPP, WebRTC and fullscreen video on battery
+        // only lower it.
+        let tests = [
+            (
+                PowerSourceType::DC,
+                RTCAudioActive::Inactive,
+                "balance_power",
+            ),
+            (PowerSourceType::DC, RTCAudioActive::Active, "balance_power"),
+            (
+                PowerSourceType::AC,
+                RTCAudioActive::Inactive,
+                "balance_performance",
+            ),
+            (
+                PowerSourceType::AC,
+                RTCAudioActive::Active,
+                "balance_performance",
+            ),
+        ];
+
+        for (power_source, rtc, expected_epp) in tests {
+            let manager = DirectoryPowerPreferencesManager {
+                root: root.path().to_path_buf(),
+                config_provider: FakeConfig::new().provider(),
+                power_source_provider: FakePowerSourceProvider { power_source },
+                saved_cpu_boost: Default::default(),
+            };
+
+            manager.update_power_preferences(
+                rtc,
+                FullscreenVideo::Inactive,
+                common::GameMode::Off,
+                common::VmBootMode::Inactive,
+                common::BatterySaverMode::Inactive,
+            )?;
+
+            assert_eq!(read_epp(root.path())?, expected_epp);
+            assert_eq!(fs::read_to_string(intel_pstate.join("min_perf_pct"))?, "0");
+            assert_eq!(
+                fs::read_to_string(intel_pstate.join("max_perf_pct"))?,
+                "100"
+            );
+        }
+
+        Ok(())
+    }
+
     #[test]
     fn test_power_update_power_preferences_fullscreen_active() -> Result<()> {
         let root = tempdir()?;
@@ -1453,6 +1618,7 @@ mod tests {
             root: root.path().to_path_buf(),
             config_provider,
             power_source_provider,
+            saved_cpu_boost: Default::default(),
         };
 
         manager.update_power_preferences(
@@ -1503,6 +1669,7 @@ mod tests {
             root: root.path().to_path_buf(),
             config_provider,
             power_source_provider,
+            saved_cpu_boost: Default::default(),
         };
 
         manager.update_power_preferences(
@@ -1553,6 +1720,7 @@ mod tests {
             root: root.path().to_path_buf(),
             config_provider,
             power_source_provider,
+            saved_cpu_boost: Default::default(),
         };
         manager.update_power_preferences(
             common::RTCAudioActive::Inactive,
@@ -1624,6 +1792,7 @@ mod tests {
             root: root.to_path_buf(),
             config_provider,
             power_source_provider,
+            saved_cpu_boost: Default::default(),
         };
         manager.update_power_preferences(
             common::RTCAudioActive::Inactive,
@@ -1691,6 +1860,7 @@ mod tests {
                 root: root.to_path_buf(),
                 config_provider,
                 power_source_provider,
+                saved_cpu_boost: Default::default(),
             };
 
             manager.update_power_preferences(
@@ -1706,4 +1876,39 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_cpu_boost_thermal_hook() -> Result<()> {
+        let root = tempdir()?;
+        let root = root.path();
+        let boost_path = root.join(CPU_BOOST_PATH);
+        let no_turbo_path = root.join(INTEL_PSTATE_NO_TURBO_PATH);
+        test_create_parent_dir(&boost_path);
+        test_create_parent_dir(&no_turbo_path);
+        fs::write(&boost_path, "1\n")?;
+        // Turbo is already disabled.
+        fs::write(&no_turbo_path, "1\n")?;
+
+        let mut hook = CpuBoostThermalHook::new(Arc::new(new_directory_power_preferences_manager(
+            root,
+            FakeConfig::new().provider(),
+        )));
+        hook.set_throttled(true)?;
+        assert_eq!(fs::read_to_string(&boost_path)?, "0");
+        assert_eq!(fs::read_to_string(&no_turbo_path)?, "1\n");
+
+        hook.set_throttled(false)?;
+        assert_eq!(fs::read_to_string(&boost_path)?, "1");
+        assert_eq!(fs::read_to_string(&no_turbo_path)?, "1\n");
+
+        // Missing attributes are skipped.
+        let mut hook = CpuBoostThermalHook::new(Arc::new(new_directory_power_preferences_manager(
+            &root.join("missing"),
+            FakeConfig::new().provider(),
+        )));
+        hook.set_throttled(true)?;
+        hook.set_throttled(false)?;
+
+        Ok(())
+    }
 }
@@ -9,6 +9,8 @@ use std::fs::OpenOptions;
 use std::io::Write;
 use std::os::unix::fs::OpenOptionsExt;
 use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::path::PathBuf;
 use std::time::Duration;
 
 use anyhow::bail;
@@ -18,6 +20,18 @@ use tokio::io::unix::AsyncFd;
 use tokio::io::Interest;
 use tokio::time::timeout;
 
+use crate::common::Clock;
+use crate::common::SystemClock;
+
+const MEMORY_PRESSURE_PATH: &str = "proc/pressure/memory";
+
+// The polling interval of the userspace fallback. It is doubled after each sample far from the
+// threshold and reset to the minimum once the pressure gets close.
+const MIN_POLL_INTERVAL_MS: i64 = 100;
+const MAX_POLL_INTERVAL_MS: i64 = 3200;
+// The pressure is close to the threshold once it reaches this fraction of it.
+const NEAR_THRESHOLD_RATIO: f64 = 0.5;
+
 /// Wait for PSI monitor event that memory stall time exceeded a certain threshold in recent time
 /// window. Returns Ok(true) if the PSI monitor event is triggered. Returns Ok(false) when waiting
 /// time exceeded `max_waiting_ms`.
@@ -75,9 +89,328 @@ pub async fn wait_psi_monitor_memory_event(
     }
 }
 
+/// How [PsiMemoryWatcher] detects that the stall threshold is exceeded. The values are
+/// reported to UMA, so they must not be renumbered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PsiMechanism {
+    /// The kernel notifies about the threshold via a PSI trigger.
+    Trigger = 0,
+    /// The kernel doesn't support PSI triggers. The averages in the pressure file are sampled
+    /// instead.
+    Polling = 1,
+}
+
+impl PsiMechanism {
+    /// The exclusive max of the UMA enum histogram.
+    pub const UMA_MAX: i32 = PsiMechanism::Polling as i32 + 1;
+}
+
+/// Waits for the memory "some" stall time to exceed a threshold within a time window, like
+/// [wait_psi_monitor_memory_event()], also on kernels without support for PSI triggers.
+pub struct PsiMemoryWatcher {
+    root: PathBuf,
+    stall_ms: u64,
+    window_ms: u64,
+    poller: Option<PsiPoller<SystemClock>>,
+}
+
+impl PsiMemoryWatcher {
+    /// Creates a watcher for `stall_ms` of stall time in `window_ms`. Whether the kernel supports
+    /// PSI triggers is detected by registering one.
+    pub fn new(root: &Path, stall_ms: u64, window_ms: u64) -> Result<Self> {
+        if stall_ms > window_ms {
+            bail!("The stall time couldn't be larger than the time window.");
+        }
+        let poller = if supports_triggers(root, stall_ms, window_ms)? {
+            None
+        } else {
+            Some(PsiPoller::new(stall_ms, window_ms, SystemClock))
+        };
+        Ok(Self {
+            root: root.to_path_buf(),
+            stall_ms,
+            window_ms,
+            poller,
+        })
+    }
+
+    /// The mechanism in use, e.g. for metrics.
+    pub fn mechanism(&self) -> PsiMechanism {
+        match self.poller {
+            Some(_) => PsiMechanism::Polling,
+            None => PsiMechanism::Trigger,
+        }
+    }
+
+    /// Waits for the stall threshold to be exceeded, with the same arguments and results as
+    /// [wait_psi_monitor_memory_event()].
+    pub async fn wait(&mut self, min_waiting_ms: u64, max_waiting_ms: u64) -> Result<bool> {
+        let Some(poller) = &mut self.poller else {
+            return wait_psi_monitor_memory_event(
+                self.stall_ms,
+                self.window_ms,
+                min_waiting_ms,
+                max_waiting_ms,
+            )
+            .await;
+        };
+        if min_waiting_ms > max_waiting_ms {
+            bail!("The minimal waiting time couldn't be larger than the maximal waiting time.");
+        }
+
+        tokio::time::sleep(Duration::from_millis(min_waiting_ms)).await;
+        let deadline_ms = poller.clock.now_ms() + (max_waiting_ms - min_waiting_ms) as i64;
+        let path = self.root.join(MEMORY_PRESSURE_PATH);
+        loop {
+            let content = std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            if poller.sample(&content)? {
+                return Ok(true);
+            }
+            let remaining_ms = deadline_ms - poller.clock.now_ms();
+            if remaining_ms <= 0 {
+                return Ok(false);
+            }
+            let sleep_ms = poller.interval_ms().min(remaining_ms);
+            tokio::time::sleep(Duration::from_millis(sleep_ms as u64)).await;
+        }
+    }
+}
+
+/// Returns whether the kernel accepts a PSI trigger for memory. Kernels without trigger support
+/// reject the write with EINVAL.
+fn supports_triggers(root: &Path, stall_ms: u64, window_ms: u64) -> Result<bool> {
+    let path = root.join(MEMORY_PRESSURE_PATH);
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    let trigger = format!("some {} {}\0", stall_ms * 1000, window_ms * 1000);
+    match file.write(trigger.as_bytes()) {
+        Ok(_) => Ok(true),
+        Err(e) if e.raw_os_error() == Some(libc::EINVAL) => Ok(false),
+        Err(e) => Err(e).with_context(|| format!("Failed to write {}", path.display())),
+    }
+}
+
+/// The userspace fallback for PSI triggers.
+///
+/// A trigger fires when the stall time within the window exceeds the threshold, at most once per
+/// window. The poller approximates this with the average stall percentage of the pressure file,
+/// avg10 for windows up to 10 seconds (which covers all windows the kernel accepts for triggers)
+/// and avg60 for longer windows.
+struct PsiPoller<C: Clock> {
+    clock: C,
+    /// The stall threshold in percent of the window.
+    threshold: f64,
+    window_ms: i64,
+    use_avg10: bool,
+    interval_ms: i64,
+    last_event_ms: Option<i64>,
+}
+
+impl<C: Clock> PsiPoller<C> {
+    fn new(stall_ms: u64, window_ms: u64, clock: C) -> Self {
+        Self {
+            clock,
+            threshold: stall_ms as f64 * 100.0 / window_ms as f64,
+            window_ms: window_ms as i64,
+            use_avg10: window_ms <= 10_000,
+            interval_ms: MIN_POLL_INTERVAL_MS,
+            last_event_ms: None,
+        }
+    }
+
+    /// How long to wait before the next sample.
+    fn interval_ms(&self) -> i64 {
+        self.interval_ms
+    }
+
+    /// Processes the content of the pressure file. Returns true if a trigger would have fired.
+    fn sample(&mut self, content: &str) -> Result<bool> {
+        let avg = parse_some_avg(content, if self.use_avg10 { "avg10" } else { "avg60" })?;
+
+        if avg >= self.threshold * NEAR_THRESHOLD_RATIO {
+            self.interval_ms = MIN_POLL_INTERVAL_MS;
+        } else {
+            self.interval_ms = (self.interval_ms * 2).min(MAX_POLL_INTERVAL_MS);
+        }
+
+        if avg < self.threshold {
+            return Ok(false);
+        }
+        let now_ms = self.clock.now_ms();
+        if matches!(self.last_event_ms, Some(last) if now_ms - last < self.window_ms) {
+            return Ok(false);
+        }
+        self.last_event_ms = Some(now_ms);
+        Ok(true)
+    }
+}
+
+fn parse_some_avg(content: &str, field: &str) -> Result<f64> {
+    let line = content
+        .lines()
+        .find(|line| line.starts_with("some "))
+        .context("No some line in PSI content")?;
+    let avg = line
+        .split_whitespace()
+        .find_map(|entry| entry.strip_prefix(field)?.strip_prefix('='))
+        .with_context(|| format!("No {} field in PSI some line", field))?;
+    avg.parse()
+        .with_context(|| format!("Couldn't parse PSI {} \"{}\" as f64", field, avg))
+}
+
+/// Returns the cumulative time in microseconds during which all non-idle tasks were stalled on
+/// memory at the same time, i.e. the `total` of the "full" line in /proc/pressure/memory.
+pub fn get_memory_full_total_us(root: &Path) -> Result<u64> {
+    let path = root.join(MEMORY_PRESSURE_PATH);
+    let content = std::fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    parse_full_total_us(&content)
+}
+
+fn parse_full_total_us(content: &str) -> Result<u64> {
+    let line = content
+        .lines()
+        .find(|line| line.starts_with("full "))
+        .context("No full line in PSI content")?;
+    let total = line
+        .split_whitespace()
+        .find_map(|field| field.strip_prefix("total="))
+        .context("No total field in PSI full line")?;
+    total
+        .parse()
+        .with_context(|| format!("Couldn't parse PSI total \"{}\" as u64", total))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_utils::FakeClock;
+
+    fn pressure_content(avg10: f64, avg60: f64) -> String {
+        format!(
+            "some avg10={:.2} avg60={:.2} avg300=0.00 total=100\n\
+             full avg10=0.00 avg60=0.00 avg300=0.00 total=50\n",
+            avg10, avg60
+        )
+    }
+
+    #[test]
+    fn test_parse_some_avg() {
+        let content = pressure_content(12.5, 3.25);
+        assert_eq!(parse_some_avg(&content, "avg10").unwrap(), 12.5);
+        assert_eq!(parse_some_avg(&content, "avg60").unwrap(), 3.25);
+        assert!(parse_some_avg(&content, "avg1").is_err());
+        assert!(parse_some_avg("full avg10=1.00\n", "avg10").is_err());
+        assert!(parse_some_avg("some avg10=abc\n", "avg10").is_err());
+    }
+
+    #[test]
+    fn test_poller_threshold_crossing() {
+        let clock = FakeClock::default();
+        // 150 ms in 1000 ms is 15 %.
+        let mut poller = PsiPoller::new(150, 1000, clock.clone());
+
+        assert!(!poller.sample(&pressure_content(14.99, 50.0)).unwrap());
+        clock.advance_ms(100);
+        assert!(poller.sample(&pressure_content(15.0, 0.0)).unwrap());
+
+        // Like a trigger, the poller fires at most once per window.
+        clock.advance_ms(500);
+        assert!(!poller.sample(&pressure_content(30.0, 0.0)).unwrap());
+        clock.advance_ms(500);
+        assert!(poller.sample(&pressure_content(30.0, 0.0)).unwrap());
+
+        // No event below the threshold, however long it has been.
+        clock.advance_ms(10_000);
+        assert!(!poller.sample(&pressure_content(1.0, 0.0)).unwrap());
+        clock.advance_ms(100);
+        assert!(poller.sample(&pressure_content(20.0, 0.0)).unwrap());
+    }
+
+    #[test]
+    fn test_poller_long_window_uses_avg60() {
+        let clock = FakeClock::default();
+        // 6 s in 60 s is 10 %.
+        let mut poller = PsiPoller::new(6_000, 60_000, clock);
+        assert!(!poller.sample(&pressure_content(90.0, 9.0)).unwrap());
+        assert!(poller.sample(&pressure_content(0.0, 10.0)).unwrap());
+    }
+
+    #[test]
+    fn test_poller_adaptive_interval() {
+        let mut poller = PsiPoller::new(100, 1000, FakeClock::default());
+        assert_eq!(poller.interval_ms(), MIN_POLL_INTERVAL_MS);
+
+        // Far from the 10 % threshold, back off up to the maximum.
+        let mut expected = MIN_POLL_INTERVAL_MS;
+        while expected < MAX_POLL_INTERVAL_MS {
+            poller.sample(&pressure_content(1.0, 0.0)).unwrap();
+            expected *= 2;
+            assert_eq!(poller.interval_ms(), expected.min(MAX_POLL_INTERVAL_MS));
+        }
+        poller.sample(&pressure_content(0.0, 0.0)).unwrap();
+        assert_eq!(poller.interval_ms(), MAX_POLL_INTERVAL_MS);
+
+        // Close to the threshold, sample at the minimum interval.
+        poller.sample(&pressure_content(5.0, 0.0)).unwrap();
+        assert_eq!(poller.interval_ms(), MIN_POLL_INTERVAL_MS);
+        poller.sample(&pressure_content(4.0, 0.0)).unwrap();
+        assert_eq!(poller.interval_ms(), MIN_POLL_INTERVAL_MS * 2);
+        poller.sample(&pressure_content(50.0, 0.0)).unwrap();
+        assert_eq!(poller.interval_ms(), MIN_POLL_INTERVAL_MS);
+    }
+
+    #[test]
+    fn test_supports_triggers() {
+        let root = tempfile::tempdir().unwrap();
+        // Missing PSI support is an error rather than a reason to fall back.
+        assert!(supports_triggers(root.path(), 150, 1000).is_err());
+
+        // Regular files accept the trigger write.
+        let path = root.path().join(MEMORY_PRESSURE_PATH);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, pressure_content(0.0, 0.0)).unwrap();
+        assert!(supports_triggers(root.path(), 150, 1000).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_watcher_polling() {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join(MEMORY_PRESSURE_PATH);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let mut watcher = PsiMemoryWatcher {
+            root: root.path().to_path_buf(),
+            stall_ms: 150,
+            window_ms: 1000,
+            poller: Some(PsiPoller::new(150, 1000, SystemClock)),
+        };
+        assert_eq!(watcher.mechanism(), PsiMechanism::Polling);
+        assert!(watcher.wait(10, 0).await.is_err());
+
+        std::fs::write(&path, pressure_content(20.0, 0.0)).unwrap();
+        assert!(watcher.wait(0, 1000).await.unwrap());
+
+        std::fs::write(&path, pressure_content(1.0, 0.0)).unwrap();
+        assert!(!watcher.wait(0, 300).await.unwrap());
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(watcher.wait(0, 300).await.is_err());
+    }
+
+    #[test]
+    fn test_parse_full_total_us() {
+        let content = "some avg10=0.00 avg60=0.12 avg300=0.35 total=2419741\n\
+                       full avg10=0.00 avg60=0.05 avg300=0.15 total=1047332\n";
+        assert_eq!(parse_full_total_us(content).unwrap(), 1047332);
+
+        assert!(parse_full_total_us("some avg10=0.00 avg60=0.00 avg300=0.00 total=1\n").is_err());
+        assert!(parse_full_total_us("full avg10=0.00 avg60=0.00 avg300=0.00\n").is_err());
+        assert!(parse_full_total_us("full avg10=0.00 total=abc\n").is_err());
+    }
 
     #[tokio::test]
     async fn test_wait_psi_monitor_memory_event() {
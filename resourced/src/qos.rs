@@ -13,23 +13,43 @@ use std::sync::Mutex;
 use dbus::MethodErr;
 use log::error;
 use log::info;
-use schedqos::cgroups::open_cpuset_cgroup;
-use schedqos::cgroups::setup_cpu_cgroup;
+use log::warn;
+use schedqos::cgroups::CgroupHierarchy;
+use schedqos::cgroups::CgroupSetupError;
+use schedqos::cgroups::CgroupVersion;
 use schedqos::CgroupContext;
 use schedqos::Config;
-use schedqos::ProcessKey;
-use schedqos::ProcessState;
-use schedqos::ThreadState;
+use schedqos::MemCgroup;
+pub use schedqos::ProcessKey;
+pub use schedqos::ProcessState;
+use schedqos::RestoreResult;
+use schedqos::RtPriorityPolicy;
+pub use schedqos::ThreadState;
+use schedqos::ThreadStateConfig;
+use schedqos::UCLAMP_MAX;
 use tokio::io::unix::AsyncFd;
 use tokio::io::Interest;
 use tokio::task::JoinHandle;
 
+use crate::cpu_utils;
+use crate::dump;
 use crate::proc::load_ruid;
+use crate::thermal::ThermalHook;
 
 pub type SchedQosContext = schedqos::RestorableSchedQosContext;
 
 const STATE_FILE_PATH: &str = "/run/resourced/schedqos_states";
 
+const CGROUP_ROOT_PATH: &str = "sys/fs/cgroup";
+const NORMAL_CPU_CGROUP: &str = "resourced/normal";
+const BACKGROUND_CPU_CGROUP: &str = "resourced/background";
+/// The default cpu.shares of the cgroup of [ProcessState::Background] processes.
+pub const BACKGROUND_CPU_SHARES: u16 = 10;
+
+/// The cap of uclamp_min for [ThreadState::UrgentBursty] while thermally throttled, 20% of
+/// [UCLAMP_MAX].
+const THROTTLED_UCLAMP_MIN: u32 = UCLAMP_MAX / 5;
+
 /// Error of parsing /proc/pid/status
 #[derive(Debug)]
 pub enum Error {
@@ -106,12 +126,67 @@ impl Display for Error {
 
 pub type Result<T> = std::result::Result<T, Error>;
 
-pub fn create_schedqos_context() -> anyhow::Result<SchedQosContext> {
-    let cpu_normal = setup_cpu_cgroup("resourced/normal", 1024)?;
-    let cpu_background = setup_cpu_cgroup("resourced/background", 10)?;
-    // Note these might be changed to resourced specific folders in the futre
-    let cpuset_all = open_cpuset_cgroup("chrome/urgent")?;
-    let cpuset_efficient = open_cpuset_cgroup("chrome/non-urgent")?;
+pub fn create_schedqos_context() -> anyhow::Result<(SchedQosContext, Option<RestoreResult>)> {
+    let hierarchy = detect_cgroup_hierarchy(Path::new("/"));
+    info!("Using cgroup {:?} hierarchy", hierarchy.version());
+    let cpu_normal = hierarchy.setup_cpu_cgroup(NORMAL_CPU_CGROUP, 1024)?;
+    let cpu_background =
+        hierarchy.setup_cpu_cgroup(BACKGROUND_CPU_CGROUP, BACKGROUND_CPU_SHARES)?;
+    let (cpuset_all, cpuset_efficient, cpuset_background_all, cpuset_background_efficient) =
+        match hierarchy.version() {
+            // Note these might be changed to resourced specific folders in the futre
+            CgroupVersion::V1 => (
+                hierarchy.open_cpuset_cgroup("chrome/urgent")?,
+                hierarchy.open_cpuset_cgroup("chrome/non-urgent")?,
+                None,
+                None,
+            ),
+            // Threads can only move within the threaded subtree of their process, so the cpuset
+            // cgroups are threaded children of each cpu cgroup. The upstart job creates them and
+            // enables the cpu and cpuset controllers.
+            CgroupVersion::V2 => {
+                let root = Path::new("/");
+                // The efficient cpusets use the little cores. Without big/little cores all
+                // cpusets keep inheriting all CPUs.
+                if cpu_utils::is_big_little_supported(root).unwrap_or(false) {
+                    let little_cores = cpu_utils::get_little_cores(root)?;
+                    for name in [
+                        "resourced/normal/efficient",
+                        "resourced/background/efficient",
+                    ] {
+                        hierarchy.set_cpuset_cpus(name, &little_cores)?;
+                    }
+                }
+                (
+                    hierarchy.open_cpuset_cgroup("resourced/normal/all")?,
+                    hierarchy.open_cpuset_cgroup("resourced/normal/efficient")?,
+                    Some(hierarchy.open_cpuset_cgroup("resourced/background/all")?),
+                    Some(hierarchy.open_cpuset_cgroup("resourced/background/efficient")?),
+                )
+            }
+        };
+    let mut process_configs = Config::default_process_config();
+    let (memory_normal, memory_background) = match hierarchy.version() {
+        // The memory cgroups are created and configured by the cgroups init script. They are only
+        // used if they exist.
+        CgroupVersion::V1 => match (
+            hierarchy.open_memory_cgroup(NORMAL_CPU_CGROUP),
+            hierarchy.open_memory_cgroup(BACKGROUND_CPU_CGROUP),
+        ) {
+            (Ok(memory_normal), Ok(memory_background)) => {
+                process_configs[ProcessState::Normal as usize].memcg = Some(MemCgroup::Normal);
+                process_configs[ProcessState::Background as usize].memcg =
+                    Some(MemCgroup::Background);
+                (Some(memory_normal), Some(memory_background))
+            }
+            (Err(e), _) | (_, Err(e)) => {
+                warn!("Memory cgroups are not used: {}", e);
+                (None, None)
+            }
+        },
+        // The cpu cgroups are the memory cgroups as well in the unified hierarchy.
+        CgroupVersion::V2 => (None, None),
+    };
 
     let config = Config {
         cgroup_context: CgroupContext {
@@ -119,20 +194,92 @@ pub fn create_schedqos_context() -> anyhow::Result<SchedQosContext> {
             cpu_background,
             cpuset_all,
             cpuset_efficient,
+            cpuset_background_all,
+            cpuset_background_efficient,
+            memory_normal,
+            memory_background,
+            verify_writes: cfg!(debug_assertions),
         },
-        process_configs: Config::default_process_config(),
+        process_configs,
         thread_configs: Config::default_thread_config(),
+        rt_priority_policy: RtPriorityPolicy::Reject,
     };
 
-    let file_path = Path::new(STATE_FILE_PATH);
-    let ctx = if file_path.exists() {
-        info!("Loading schedqos state from {:?}", file_path);
-        SchedQosContext::load_from_file(config, file_path)?
+    restore_or_create_context(config, Path::new(STATE_FILE_PATH))
+}
+
+/// Restores the states saved by the previous resourced instance if the state file exists.
+///
+/// [RestoreResult] is returned if the states are restored.
+fn restore_or_create_context(
+    config: Config,
+    file_path: &Path,
+) -> anyhow::Result<(SchedQosContext, Option<RestoreResult>)> {
+    if file_path.exists() {
+        info!("Restoring schedqos state from {:?}", file_path);
+        let (ctx, result) = SchedQosContext::restore_from_file(config, file_path)?;
+        info!(
+            "Restored {} processes and {} threads, pruned {} entries",
+            result.processes.len(),
+            result.n_threads,
+            result.n_pruned
+        );
+        Ok((ctx, Some(result)))
     } else {
         info!("Initialize schedqos state at {:?}", file_path);
-        SchedQosContext::new_file(config, file_path)?
+        Ok((SchedQosContext::new_file(config, file_path)?, None))
+    }
+}
+
+/// Numbers of QoS registrations restored from the previous resourced instance.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RestoreStats {
+    /// Processes and threads whose states are re-applied.
+    pub restored: u32,
+    /// Processes and threads dropped because they are dead or failed to be re-applied.
+    pub pruned: u32,
+}
+
+/// Monitors the restored processes to stop managing them when they exit.
+///
+/// The returned [JoinHandle]s are used for testing purpose.
+pub fn monitor_restored_processes(
+    sched_ctx: Arc<Mutex<SchedQosContext>>,
+    result: RestoreResult,
+) -> (RestoreStats, Vec<JoinHandle<()>>) {
+    let mut restored = result.n_threads;
+    let mut pruned = result.n_pruned;
+    let mut join_handles = Vec::new();
+    for process_key in result.processes {
+        let process_id: u32 = process_key.process_id().into();
+        match create_async_pidfd(process_id) {
+            Ok(pidfd) => {
+                join_handles.push(monitor_process(
+                    sched_ctx.clone(),
+                    pidfd,
+                    process_id,
+                    process_key,
+                ));
+                restored += 1;
+            }
+            Err(e) => {
+                error!("failed to monitor restored process {}: {}", process_id, e);
+                sched_ctx
+                    .lock()
+                    .expect("lock schedqos context")
+                    .remove_process(process_key);
+                pruned += 1;
+            }
+        }
+    }
+
+    dump::record_decision(dump::Decision::QosRestored { restored, pruned });
+
+    let stats = RestoreStats {
+        restored: restored as u32,
+        pruned: pruned as u32,
     };
-    Ok(ctx)
+    (stats, join_handles)
 }
 
 /// Validate the ruid of process_id is the same as the sender euid.
@@ -161,10 +308,29 @@ pub fn set_thread_state(
 
     validate_pid(process_id, sender_euid)?;
 
+    apply_thread_state(&sched_ctx, process_id, thread_id, state)
+}
+
+/// Sets the state of a thread on behalf of resourced itself, without validating the sender.
+///
+/// This is the core of the SetThreadState D-Bus method and depends on neither D-Bus nor the tokio
+/// runtime.
+pub fn apply_thread_state(
+    sched_ctx: &Mutex<SchedQosContext>,
+    process_id: u32,
+    thread_id: u32,
+    state: ThreadState,
+) -> Result<()> {
     let mut ctx = sched_ctx.lock().expect("lock schedqos context");
 
     ctx.set_thread_state(process_id.into(), thread_id.into(), state)?;
 
+    dump::record_decision(dump::Decision::ThreadState {
+        process_id,
+        thread_id,
+        state,
+    });
+
     Ok(())
 }
 
@@ -179,13 +345,33 @@ pub fn set_process_state(
 
     validate_pid(process_id, sender_euid)?;
 
-    let mut ctx = sched_ctx.lock().expect("lock schedqos context");
+    apply_process_state_and_monitor(sched_ctx, process_id, state)
+}
 
-    if let Some(process_key) = ctx.set_process_state(process_id.into(), state)? {
+/// Sets the state of a process like [apply_process_state] and stops managing the process when it
+/// exits. This needs the tokio runtime.
+///
+/// The returned [JoinHandle] is used for testing purpose.
+pub fn apply_process_state_and_monitor(
+    sched_ctx: Arc<Mutex<SchedQosContext>>,
+    process_id: u32,
+    state: ProcessState,
+) -> Result<Option<JoinHandle<()>>> {
+    let process_key = apply_process_state(&sched_ctx, process_id, state)?;
+
+    if let Some(process_key) = process_key {
         match create_async_pidfd(process_id) {
-            Ok(pidfd) => Ok(Some(monitor_process(sched_ctx.clone(), pidfd, process_key))),
+            Ok(pidfd) => Ok(Some(monitor_process(
+                sched_ctx.clone(),
+                pidfd,
+                process_id,
+                process_key,
+            ))),
             Err(e) => {
-                ctx.remove_process(process_key);
+                sched_ctx
+                    .lock()
+                    .expect("lock schedqos context")
+                    .remove_process(process_key);
                 if e.raw_os_error() == Some(libc::ESRCH) {
                     Err(Error::ProcessNotFound)
                 } else {
@@ -198,6 +384,95 @@ pub fn set_process_state(
     }
 }
 
+/// Sets the state of a process on behalf of resourced itself, without validating the sender.
+///
+/// This is the core of the SetProcessState D-Bus method and depends on neither D-Bus nor the
+/// tokio runtime. [ProcessKey] is returned if the process is newly registered, and the caller is
+/// responsible for removing it with [SchedQosContext::remove_process] when the process exits.
+pub fn apply_process_state(
+    sched_ctx: &Mutex<SchedQosContext>,
+    process_id: u32,
+    state: ProcessState,
+) -> Result<Option<ProcessKey>> {
+    let mut ctx = sched_ctx.lock().expect("lock schedqos context");
+
+    let process_key = ctx.set_process_state(process_id.into(), state)?;
+
+    dump::record_decision(dump::Decision::ProcessState { process_id, state });
+
+    Ok(process_key)
+}
+
+/// Sets cpu.shares, or the equivalent cpu.weight on cgroup v2, of the cgroup of
+/// [ProcessState::Background] processes.
+pub fn set_background_cpu_shares(
+    root: &Path,
+    cpu_shares: u16,
+) -> std::result::Result<(), CgroupSetupError> {
+    detect_cgroup_hierarchy(root).set_cpu_shares(BACKGROUND_CPU_CGROUP, cpu_shares)
+}
+
+/// Returns the cgroup hierarchy mounted at /sys/fs/cgroup under `root`.
+///
+/// The unified cgroup v2 hierarchy is used if it is mounted there, which is the case if
+/// cgroup.controllers exists and there is no v1 cpu hierarchy.
+pub fn detect_cgroup_hierarchy(root: &Path) -> CgroupHierarchy {
+    let cgroup_root = root.join(CGROUP_ROOT_PATH);
+    let version =
+        if cgroup_root.join("cgroup.controllers").exists() && !cgroup_root.join("cpu").exists() {
+            CgroupVersion::V2
+        } else {
+            CgroupVersion::V1
+        };
+    CgroupHierarchy::with_root(version, cgroup_root)
+}
+
+/// Caps uclamp_min of [ThreadState::UrgentBursty] while the system is thermally throttled.
+pub struct UclampThermalHook {
+    sched_ctx: Arc<Mutex<SchedQosContext>>,
+    // The config before throttling.
+    saved_config: Option<ThreadStateConfig>,
+}
+
+impl UclampThermalHook {
+    pub fn new(sched_ctx: Arc<Mutex<SchedQosContext>>) -> Self {
+        UclampThermalHook {
+            sched_ctx,
+            saved_config: None,
+        }
+    }
+}
+
+impl ThermalHook for UclampThermalHook {
+    fn name(&self) -> &'static str {
+        "qos uclamp_min"
+    }
+
+    fn set_throttled(&mut self, throttled: bool) -> anyhow::Result<()> {
+        let mut ctx = self.sched_ctx.lock().expect("lock schedqos context");
+        let thread_config = if throttled {
+            let thread_config = ctx.thread_config(ThreadState::UrgentBursty).clone();
+            let capped = ThreadStateConfig {
+                uclamp_min: thread_config.uclamp_min.min(THROTTLED_UCLAMP_MIN),
+                ..thread_config.clone()
+            };
+            self.saved_config = Some(thread_config);
+            capped
+        } else {
+            let Some(thread_config) = self.saved_config.take() else {
+                return Ok(());
+            };
+            thread_config
+        };
+        let uclamp_min = thread_config.uclamp_min;
+        ctx.set_thread_config(ThreadState::UrgentBursty, thread_config)?;
+
+        dump::record_decision(dump::Decision::UrgentBurstyUclampMin(uclamp_min));
+
+        Ok(())
+    }
+}
+
 fn create_async_pidfd(pid: u32) -> std::io::Result<AsyncFd<OwnedFd>> {
     // SAFETY: pidfd_open(2) does not modify userspace memory.
     let res = unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) } as libc::c_int;
@@ -215,6 +490,7 @@ fn create_async_pidfd(pid: u32) -> std::io::Result<AsyncFd<OwnedFd>> {
 fn monitor_process(
     sched_ctx: Arc<Mutex<SchedQosContext>>,
     pidfd: AsyncFd<OwnedFd>,
+    process_id: u32,
     process: ProcessKey,
 ) -> JoinHandle<()> {
     tokio::spawn(async move {
@@ -228,32 +504,110 @@ fn monitor_process(
             .lock()
             .expect("lock schedqos context")
             .remove_process(process);
+        dump::record_decision(dump::Decision::ProcessExited { process_id });
     })
 }
 
 #[cfg(test)]
 mod tests {
+    use std::fs::File;
+    use std::io::Read;
+    use std::os::unix::net::UnixDatagram;
     use std::time::Duration;
 
     use super::*;
     use crate::test_utils::*;
 
-    fn create_schedqos_context_for_test() -> Arc<Mutex<SchedQosContext>> {
-        let dir = tempfile::tempdir().unwrap();
-        let file_path = dir.path().join("states");
+    // Creates a pair of connected files. Each id written to the first file is read from the second
+    // file as a separate datagram.
+    fn create_fake_cgroup_file_pair() -> (File, File) {
+        let (s1, s2) = UnixDatagram::pair().unwrap();
+        s2.set_nonblocking(true).unwrap();
+        (OwnedFd::from(s1).into(), OwnedFd::from(s2).into())
+    }
+
+    fn read_ids(file: &mut File) -> Vec<u32> {
+        let mut ids = Vec::new();
+        let mut buf = [0; 32];
+        while let Ok(n) = file.read(&mut buf) {
+            ids.push(std::str::from_utf8(&buf[..n]).unwrap().parse().unwrap());
+        }
+        ids
+    }
+
+    struct FakeCgroupFiles {
+        cpu_background: File,
+        cpuset_efficient: File,
+        memory_background: File,
+    }
+
+    fn create_config_with_fake_cgroups() -> (Config, FakeCgroupFiles, Vec<File>) {
+        let cpu_background = create_fake_cgroup_file_pair();
+        let cpuset_efficient = create_fake_cgroup_file_pair();
+        let memory_background = create_fake_cgroup_file_pair();
+        let cpu_normal = create_fake_cgroup_file_pair();
+        let cpuset_all = create_fake_cgroup_file_pair();
+        let memory_normal = create_fake_cgroup_file_pair();
+        let mut process_configs = Config::default_process_config();
+        process_configs[ProcessState::Normal as usize].memcg = Some(MemCgroup::Normal);
+        process_configs[ProcessState::Background as usize].memcg = Some(MemCgroup::Background);
         let config = Config {
             cgroup_context: CgroupContext {
-                cpu_normal: tempfile::tempfile().unwrap(),
-                cpu_background: tempfile::tempfile().unwrap(),
-                cpuset_all: tempfile::tempfile().unwrap(),
-                cpuset_efficient: tempfile::tempfile().unwrap(),
+                cpu_normal: cpu_normal.0,
+                cpu_background: cpu_background.0,
+                cpuset_all: cpuset_all.0,
+                cpuset_efficient: cpuset_efficient.0,
+                cpuset_background_all: None,
+                cpuset_background_efficient: None,
+                memory_normal: Some(memory_normal.0),
+                memory_background: Some(memory_background.0),
+                verify_writes: false,
             },
-            process_configs: Config::default_process_config(),
+            process_configs,
             thread_configs: Config::default_thread_config(),
+            rt_priority_policy: RtPriorityPolicy::Reject,
+        };
+        let files = FakeCgroupFiles {
+            cpu_background: cpu_background.1,
+            cpuset_efficient: cpuset_efficient.1,
+            memory_background: memory_background.1,
         };
-        Arc::new(Mutex::new(
-            SchedQosContext::new_file(config, &file_path).unwrap(),
-        ))
+        // The peers of the other cgroup files must be alive for writes to succeed.
+        (
+            config,
+            files,
+            vec![cpu_normal.1, cpuset_all.1, memory_normal.1],
+        )
+    }
+
+    #[test]
+    fn test_detect_cgroup_hierarchy() {
+        let root = tempfile::tempdir().unwrap();
+        let cgroup_root = root.path().join(CGROUP_ROOT_PATH);
+        std::fs::create_dir_all(cgroup_root.join("cpu")).unwrap();
+        assert_eq!(
+            detect_cgroup_hierarchy(root.path()).version(),
+            CgroupVersion::V1
+        );
+
+        // v1 controllers and the v2 hierarchy can coexist, e.g. with a hybrid layout.
+        std::fs::write(cgroup_root.join("cgroup.controllers"), "cpu cpuset memory").unwrap();
+        assert_eq!(
+            detect_cgroup_hierarchy(root.path()).version(),
+            CgroupVersion::V1
+        );
+
+        std::fs::remove_dir(cgroup_root.join("cpu")).unwrap();
+        let hierarchy = detect_cgroup_hierarchy(root.path());
+        assert_eq!(hierarchy.version(), CgroupVersion::V2);
+
+        std::fs::create_dir_all(cgroup_root.join(BACKGROUND_CPU_CGROUP)).unwrap();
+        set_background_cpu_shares(root.path(), 2048).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(cgroup_root.join(BACKGROUND_CPU_CGROUP).join("cpu.weight"))
+                .unwrap(),
+            "200"
+        );
     }
 
     // sched_getattr(2) is not supported on qemu-user which CQ uses to run tests for non-x86_64
@@ -410,6 +764,71 @@ mod tests {
         assert!(matches!(result.err().unwrap(), Error::ProcessNotFound));
     }
 
+    // sched_getattr(2) is not supported on qemu-user which CQ uses to run tests for non-x86_64
+    // boards.
+    #[cfg(target_arch = "x86_64")]
+    #[tokio::test]
+    async fn test_apply_state_same_as_dbus_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let (config, mut files, _peers) = create_config_with_fake_cgroups();
+        let sched_ctx = Arc::new(Mutex::new(
+            SchedQosContext::new_file(config, &dir.path().join("states")).unwrap(),
+        ));
+        let mut read_writes = || {
+            (
+                read_ids(&mut files.cpu_background),
+                read_ids(&mut files.memory_background),
+                read_ids(&mut files.cpuset_efficient),
+            )
+        };
+
+        let (dbus_process_id, _dbus_process) = fork_process_for_test();
+        let uid = load_ruid(dbus_process_id).unwrap();
+        set_process_state(
+            sched_ctx.clone(),
+            dbus_process_id,
+            ProcessState::Background as u8,
+            uid,
+        )
+        .unwrap();
+        set_thread_state(
+            sched_ctx.clone(),
+            dbus_process_id,
+            dbus_process_id,
+            ThreadState::Urgent as u8,
+            uid,
+        )
+        .unwrap();
+        let dbus_writes = read_writes();
+
+        let (process_id, _process) = fork_process_for_test();
+        let process_key =
+            apply_process_state(&sched_ctx, process_id, ProcessState::Background).unwrap();
+        assert!(process_key.is_some());
+        apply_thread_state(&sched_ctx, process_id, process_id, ThreadState::Urgent).unwrap();
+        let writes = read_writes();
+
+        let expected = |process_id| (vec![process_id], vec![process_id], vec![process_id]);
+        assert_eq!(dbus_writes, expected(dbus_process_id));
+        assert_eq!(writes, expected(process_id));
+
+        let registrations = sched_ctx
+            .lock()
+            .expect("lock schedqos context")
+            .registrations();
+        for process_id in [dbus_process_id, process_id] {
+            let registration = registrations
+                .iter()
+                .find(|registration| u32::from(registration.process_id) == process_id)
+                .unwrap();
+            assert_eq!(registration.state, ProcessState::Background);
+            assert_eq!(
+                registration.threads,
+                vec![(process_id.into(), ThreadState::Urgent)]
+            );
+        }
+    }
+
     // pidfd_open(2) is not supported on qemu-user which CQ uses to run tests for non-x86_64
     // boards.
     #[cfg(target_arch = "x86_64")]
@@ -431,4 +850,81 @@ mod tests {
             Some(libc::EINVAL)
         );
     }
+
+    // pidfd_open(2) and sched_getattr(2) are not supported on qemu-user which CQ uses to run tests
+    // for non-x86_64 boards.
+    #[cfg(target_arch = "x86_64")]
+    #[tokio::test]
+    async fn test_restore_schedqos_context() {
+        let dir = tempfile::tempdir().unwrap();
+        let file_path = dir.path().join("states");
+
+        let (config, _files, _peers) = create_config_with_fake_cgroups();
+        let (mut ctx, result) = restore_or_create_context(config, &file_path).unwrap();
+        assert!(result.is_none());
+
+        let (process_id, process) = fork_process_for_test();
+        ctx.set_process_state(process_id.into(), ProcessState::Background)
+            .unwrap();
+        ctx.set_thread_state(process_id.into(), process_id.into(), ThreadState::Urgent)
+            .unwrap();
+        let (dead_process_id, dead_process) = fork_process_for_test();
+        ctx.set_process_state(dead_process_id.into(), ProcessState::Normal)
+            .unwrap();
+        drop(dead_process);
+        // Simulate a restart of resourced.
+        drop(ctx);
+
+        let (config, mut files, _peers) = create_config_with_fake_cgroups();
+        let (ctx, result) = restore_or_create_context(config, &file_path).unwrap();
+        let result = result.unwrap();
+
+        // The recorded states are re-applied to the living process.
+        assert_eq!(read_ids(&mut files.cpu_background), vec![process_id]);
+        assert_eq!(read_ids(&mut files.memory_background), vec![process_id]);
+        assert_eq!(read_ids(&mut files.cpuset_efficient), vec![process_id]);
+
+        let sched_ctx = Arc::new(Mutex::new(ctx));
+        let (stats, join_handles) = monitor_restored_processes(sched_ctx.clone(), result);
+        assert_eq!(
+            stats,
+            RestoreStats {
+                restored: 2,
+                pruned: 1,
+            }
+        );
+        assert_eq!(join_handles.len(), 1);
+
+        // The restored process is removed from the context when it exits.
+        drop(process);
+        for join_handle in join_handles {
+            join_handle.await.unwrap();
+        }
+        assert!(sched_ctx
+            .lock()
+            .expect("lock schedqos context")
+            .registrations()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_uclamp_thermal_hook() {
+        let sched_ctx = create_schedqos_context_for_test();
+        let uclamp_min = || {
+            sched_ctx
+                .lock()
+                .expect("lock schedqos context")
+                .thread_config(ThreadState::UrgentBursty)
+                .uclamp_min
+        };
+        let original = uclamp_min();
+        assert!(original > THROTTLED_UCLAMP_MIN);
+
+        let mut hook = UclampThermalHook::new(sched_ctx.clone());
+        hook.set_throttled(true).unwrap();
+        assert_eq!(uclamp_min(), THROTTLED_UCLAMP_MIN);
+
+        hook.set_throttled(false).unwrap();
+        assert_eq!(uclamp_min(), original);
+    }
 }
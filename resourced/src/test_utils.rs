@@ -2,21 +2,31 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
+use std::cell::Cell;
 use std::fs;
 use std::path::Path;
 use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::Mutex;
 use std::time::Duration;
 
 use anyhow::Result;
+use schedqos::CgroupContext;
+use schedqos::Config;
+use schedqos::RtPriorityPolicy;
 
 use crate::common::BatterySaverMode;
+use crate::common::Clock;
 use crate::common::FullscreenVideo;
 use crate::common::GameMode;
 use crate::common::RTCAudioActive;
 use crate::common::VmBootMode;
+use crate::common::SECONDS_PER_DAY;
 pub use crate::config::FakeConfig;
 use crate::cpu_utils::SMT_CONTROL_PATH;
 use crate::power;
+use crate::qos::SchedQosContext;
 
 const MOCK_NUM_CPU: i32 = 16;
 
@@ -47,11 +57,59 @@ impl power::PowerPreferencesManager for MockPowerPreferencesManager {
     ) -> Result<()> {
         Ok(())
     }
+    fn set_cpu_boost_throttled(&self, _throttled: bool) -> Result<()> {
+        Ok(())
+    }
     fn get_root(&self) -> &Path {
         self.root.as_path()
     }
 }
 
+/// A clock whose local timezone is UTC. The monotonic and the wall clock share the same time,
+/// which only moves when a test sets or advances it. Clones share the time.
+#[derive(Clone, Default)]
+pub struct FakeClock {
+    now_ms: Rc<Cell<i64>>,
+}
+
+impl FakeClock {
+    pub fn new(now_ms: i64) -> Self {
+        FakeClock {
+            now_ms: Rc::new(Cell::new(now_ms)),
+        }
+    }
+
+    pub fn from_secs(now: i64) -> Self {
+        Self::new(now * 1000)
+    }
+
+    pub fn set_ms(&self, now_ms: i64) {
+        self.now_ms.set(now_ms);
+    }
+
+    pub fn set_secs(&self, now: i64) {
+        self.set_ms(now * 1000);
+    }
+
+    pub fn advance_ms(&self, ms: i64) {
+        self.now_ms.set(self.now_ms.get() + ms);
+    }
+}
+
+impl Clock for FakeClock {
+    fn now_ms(&self) -> i64 {
+        self.now_ms.get()
+    }
+
+    fn now(&self) -> i64 {
+        self.now_ms.get().div_euclid(1000)
+    }
+
+    fn local_day(&self, time: i64) -> Result<i64> {
+        Ok(time.div_euclid(SECONDS_PER_DAY))
+    }
+}
+
 pub fn test_create_parent_dir(path: &Path) {
     fs::create_dir_all(path.parent().unwrap()).unwrap();
 }
@@ -336,3 +394,27 @@ pub fn fork_process_for_test() -> (u32, ProcessForTest) {
         },
     )
 }
+
+pub fn create_schedqos_context_for_test() -> Arc<Mutex<SchedQosContext>> {
+    let dir = tempfile::tempdir().unwrap();
+    let file_path = dir.path().join("states");
+    let config = Config {
+        cgroup_context: CgroupContext {
+            cpu_normal: tempfile::tempfile().unwrap(),
+            cpu_background: tempfile::tempfile().unwrap(),
+            cpuset_all: tempfile::tempfile().unwrap(),
+            cpuset_efficient: tempfile::tempfile().unwrap(),
+            cpuset_background_all: None,
+            cpuset_background_efficient: None,
+            memory_normal: None,
+            memory_background: None,
+            verify_writes: false,
+        },
+        process_configs: Config::default_process_config(),
+        thread_configs: Config::default_thread_config(),
+        rt_priority_policy: RtPriorityPolicy::Reject,
+    };
+    Arc::new(Mutex::new(
+        SchedQosContext::new_file(config, &file_path).unwrap(),
+    ))
+}
@@ -0,0 +1,531 @@
+// Copyright 2024 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Thermal state monitoring.
+//!
+//! The temperatures and trip points of the thermal zones in /sys/class/thermal are sampled
+//! periodically and mapped to a debounced [ThermalState]. While the system is thermally limited,
+//! the registered [ThermalHook]s throttle the power and QoS policies which would otherwise keep
+//! boosting the CPUs for nothing.
+
+use std::fs;
+use std::fs::read_to_string;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use log::error;
+use log::info;
+use serde::Serialize;
+use tokio::sync::watch;
+
+use crate::dump;
+
+const THERMAL_PATH: &str = "sys/class/thermal";
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Zone types which do not report a real temperature. The DPTF INT3400 zone only carries the
+/// platform policy.
+const IGNORED_ZONE_TYPES: [&str; 1] = ["INT3400 Thermal"];
+
+/// Number of consecutive samples required to raise the state.
+const RAISE_SAMPLES: u32 = 2;
+/// Number of consecutive samples required to lower the state. Cooling down is debounced longer
+/// than heating up to avoid flapping around a trip point.
+const LOWER_SAMPLES: u32 = 6;
+
+/// Thermal state of the system, ordered by severity.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub enum ThermalState {
+    /// No trip point is reached.
+    Nominal,
+    /// An active trip point is reached, i.e. fans are spinning up.
+    Fair,
+    /// A passive trip point is reached, i.e. the kernel is throttling the SoC.
+    Serious,
+    /// A hot or critical trip point is reached.
+    Critical,
+}
+
+impl ThermalState {
+    fn from_trip_type(trip_type: &str) -> Option<Self> {
+        match trip_type {
+            "active" => Some(Self::Fair),
+            "passive" => Some(Self::Serious),
+            "hot" | "critical" => Some(Self::Critical),
+            _ => None,
+        }
+    }
+}
+
+/// A policy applied while the system is thermally throttled.
+pub trait ThermalHook: Send {
+    /// Name used in logs.
+    fn name(&self) -> &'static str;
+
+    /// Called with true when throttling starts and with false when it ends.
+    fn set_throttled(&mut self, throttled: bool) -> Result<()>;
+}
+
+struct ThermalZone {
+    path: PathBuf,
+    zone_type: String,
+    // Whether the last read succeeded. Read errors are only logged when the zone starts failing.
+    readable: bool,
+}
+
+impl ThermalZone {
+    /// Returns the state of the zone from its current temperature and trip points.
+    fn read_state(&self) -> Result<ThermalState> {
+        let temp = read_millidegree(&self.path.join("temp"))?;
+        let mut state = ThermalState::Nominal;
+        for trip in 0.. {
+            let type_path = self.path.join(format!("trip_point_{}_type", trip));
+            if !type_path.exists() {
+                break;
+            }
+            let trip_type = read_to_string(&type_path)
+                .with_context(|| format!("Failed to read {}", type_path.display()))?;
+            let Some(trip_state) = ThermalState::from_trip_type(trip_type.trim()) else {
+                continue;
+            };
+            let trip_temp = read_millidegree(&self.path.join(format!("trip_point_{}_temp", trip)))?;
+            // Disabled trip points report a non-positive temperature.
+            if trip_temp > 0 && temp >= trip_temp {
+                state = state.max(trip_state);
+            }
+        }
+        Ok(state)
+    }
+}
+
+fn read_millidegree(path: &Path) -> Result<i64> {
+    let content =
+        read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    content
+        .trim()
+        .parse()
+        .with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+/// Lists the thermal zones having at least one trip point.
+fn find_thermal_zones(root: &Path) -> Result<Vec<ThermalZone>> {
+    let mut zones = Vec::new();
+    for entry in fs::read_dir(root.join(THERMAL_PATH))? {
+        let path = entry?.path();
+        let is_zone = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with("thermal_zone"));
+        if !is_zone || !path.join("trip_point_0_type").exists() {
+            continue;
+        }
+        let zone_type = read_to_string(path.join("type"))
+            .unwrap_or_default()
+            .trim()
+            .to_string();
+        if IGNORED_ZONE_TYPES.contains(&zone_type.as_str()) {
+            continue;
+        }
+        zones.push(ThermalZone {
+            path,
+            zone_type,
+            readable: true,
+        });
+    }
+    zones.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(zones)
+}
+
+/// Debounces the raw states sampled from the thermal zones.
+struct Debouncer {
+    state: ThermalState,
+    // The state the samples are converging to and the number of consecutive samples.
+    pending: Option<(ThermalState, u32)>,
+}
+
+impl Debouncer {
+    fn new() -> Self {
+        Debouncer {
+            state: ThermalState::Nominal,
+            pending: None,
+        }
+    }
+
+    /// Returns the new state if it changed.
+    ///
+    /// When the samples keep moving in the same direction, the least severe of them is used for
+    /// raising and the most severe for lowering.
+    fn update(&mut self, sample: ThermalState) -> Option<ThermalState> {
+        if sample == self.state {
+            self.pending = None;
+            return None;
+        }
+        let raising = sample > self.state;
+        let (target, count) = match self.pending {
+            Some((target, count)) if (target > self.state) == raising => {
+                let target = if raising {
+                    target.min(sample)
+                } else {
+                    target.max(sample)
+                };
+                (target, count + 1)
+            }
+            _ => (sample, 1),
+        };
+        let required = if raising {
+            RAISE_SAMPLES
+        } else {
+            LOWER_SAMPLES
+        };
+        if count < required {
+            self.pending = Some((target, count));
+            return None;
+        }
+        self.pending = None;
+        self.state = target;
+        Some(target)
+    }
+}
+
+/// Runs the registered hooks. Throttling starts at [ThermalState::Serious] and only ends when the
+/// state goes back to [ThermalState::Nominal].
+struct ThermalPolicy {
+    hooks: Vec<Box<dyn ThermalHook>>,
+    throttled: bool,
+}
+
+impl ThermalPolicy {
+    fn new() -> Self {
+        ThermalPolicy {
+            hooks: Vec::new(),
+            throttled: false,
+        }
+    }
+
+    /// Hooks are invoked in registration order when throttling starts and in reverse order when
+    /// it ends. A failing hook does not stop the others.
+    fn on_state_change(&mut self, state: ThermalState) {
+        let throttled = match state {
+            ThermalState::Nominal => false,
+            ThermalState::Fair => self.throttled,
+            ThermalState::Serious | ThermalState::Critical => true,
+        };
+        if throttled == self.throttled {
+            return;
+        }
+        self.throttled = throttled;
+
+        let hooks: Box<dyn Iterator<Item = &mut Box<dyn ThermalHook>>> = if throttled {
+            Box::new(self.hooks.iter_mut())
+        } else {
+            Box::new(self.hooks.iter_mut().rev())
+        };
+        for hook in hooks {
+            if let Err(e) = hook.set_throttled(throttled) {
+                error!("Thermal hook {} failed: {:#}", hook.name(), e);
+            }
+        }
+    }
+}
+
+/// Samples the thermal zones and publishes the debounced [ThermalState].
+pub struct ThermalMonitor {
+    root: PathBuf,
+    debouncer: Debouncer,
+    policy: ThermalPolicy,
+    sender: watch::Sender<ThermalState>,
+}
+
+impl ThermalMonitor {
+    pub fn new(root: &Path) -> Self {
+        ThermalMonitor {
+            root: root.to_path_buf(),
+            debouncer: Debouncer::new(),
+            policy: ThermalPolicy::new(),
+            sender: watch::channel(ThermalState::Nominal).0,
+        }
+    }
+
+    /// Registers a hook to run when throttling starts or ends.
+    pub fn register_hook(&mut self, hook: Box<dyn ThermalHook>) {
+        self.policy.hooks.push(hook);
+    }
+
+    /// Returns a receiver of the debounced thermal state.
+    pub fn subscribe(&self) -> watch::Receiver<ThermalState> {
+        self.sender.subscribe()
+    }
+
+    fn sample(&mut self, zones: &mut [ThermalZone]) -> Result<()> {
+        let mut sample = None;
+        for zone in zones {
+            match zone.read_state() {
+                Ok(state) => {
+                    if !zone.readable {
+                        info!("Thermal zone {} can be read again", zone.zone_type);
+                        zone.readable = true;
+                    }
+                    sample = sample.max(Some(state));
+                }
+                Err(e) => {
+                    if zone.readable {
+                        error!("Failed to read thermal zone {}: {:#}", zone.zone_type, e);
+                        zone.readable = false;
+                    }
+                }
+            }
+        }
+        let Some(sample) = sample else {
+            bail!("No thermal zone could be read");
+        };
+
+        if let Some(state) = self.debouncer.update(sample) {
+            info!("Thermal state changed to {:?}", state);
+            dump::record_decision(dump::Decision::ThermalState(state));
+            self.sender.send_replace(state);
+            self.policy.on_state_change(state);
+        }
+        Ok(())
+    }
+
+    /// Starts sampling the thermal zones periodically.
+    ///
+    /// If the system has no thermal zone with trip points, the state stays
+    /// [ThermalState::Nominal] forever.
+    pub fn start(mut self) {
+        let mut zones = match find_thermal_zones(&self.root) {
+            Ok(zones) if !zones.is_empty() => zones,
+            Ok(_) => {
+                info!("No thermal zone with trip points, thermal state stays nominal");
+                return;
+            }
+            Err(e) => {
+                info!(
+                    "Failed to list thermal zones, thermal state stays nominal: {:#}",
+                    e
+                );
+                return;
+            }
+        };
+        tokio::spawn(async move {
+            // Sampling keeps failing while no zone can be read. Log it only when it starts failing.
+            let mut failing = false;
+            loop {
+                match self.sample(&mut zones) {
+                    Ok(()) => failing = false,
+                    Err(e) if !failing => {
+                        error!("Failed to sample thermal state: {:#}", e);
+                        failing = true;
+                    }
+                    Err(_) => {}
+                }
+                tokio::time::sleep(SAMPLE_INTERVAL).await;
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    use super::*;
+
+    fn write_zone(root: &Path, zone: u32, zone_type: &str, temp: i64, trips: &[(&str, i64)]) {
+        let zone_path = root
+            .join(THERMAL_PATH)
+            .join(format!("thermal_zone{}", zone));
+        fs::create_dir_all(&zone_path).unwrap();
+        fs::write(zone_path.join("type"), format!("{}\n", zone_type)).unwrap();
+        fs::write(zone_path.join("temp"), format!("{}\n", temp)).unwrap();
+        for (i, (trip_type, trip_temp)) in trips.iter().enumerate() {
+            fs::write(
+                zone_path.join(format!("trip_point_{}_type", i)),
+                format!("{}\n", trip_type),
+            )
+            .unwrap();
+            fs::write(
+                zone_path.join(format!("trip_point_{}_temp", i)),
+                format!("{}\n", trip_temp),
+            )
+            .unwrap();
+        }
+    }
+
+    const TRIPS: [(&str, i64); 3] = [("active", 60000), ("passive", 80000), ("critical", 100000)];
+
+    #[test]
+    fn test_read_zone_state() {
+        let root = tempfile::tempdir().unwrap();
+        for (temp, expected) in [
+            (40000, ThermalState::Nominal),
+            (60000, ThermalState::Fair),
+            (85000, ThermalState::Serious),
+            (105000, ThermalState::Critical),
+        ] {
+            write_zone(root.path(), 0, "x86_pkg_temp", temp, &TRIPS);
+            let zones = find_thermal_zones(root.path()).unwrap();
+            assert_eq!(zones.len(), 1);
+            assert_eq!(zones[0].read_state().unwrap(), expected);
+        }
+
+        // Disabled trip points are ignored.
+        write_zone(root.path(), 0, "x86_pkg_temp", 1000, &[("passive", 0)]);
+        let zones = find_thermal_zones(root.path()).unwrap();
+        assert_eq!(zones[0].read_state().unwrap(), ThermalState::Nominal);
+    }
+
+    #[test]
+    fn test_find_thermal_zones() {
+        let root = tempfile::tempdir().unwrap();
+        // No thermal class directory at all.
+        assert!(find_thermal_zones(root.path()).is_err());
+
+        write_zone(root.path(), 0, "INT3400 Thermal", 20000, &TRIPS);
+        write_zone(root.path(), 1, "acpitz", 20000, &[]);
+        assert!(find_thermal_zones(root.path()).unwrap().is_empty());
+
+        write_zone(root.path(), 2, "TSR0", 20000, &TRIPS);
+        fs::create_dir_all(root.path().join(THERMAL_PATH).join("cooling_device0")).unwrap();
+        let zones = find_thermal_zones(root.path()).unwrap();
+        assert_eq!(zones.len(), 1);
+        assert_eq!(zones[0].zone_type, "TSR0");
+    }
+
+    #[test]
+    fn test_debouncer_raise() {
+        let mut debouncer = Debouncer::new();
+        assert_eq!(debouncer.update(ThermalState::Serious), None);
+        // A single sample back to nominal resets the debounce.
+        assert_eq!(debouncer.update(ThermalState::Nominal), None);
+        assert_eq!(debouncer.update(ThermalState::Critical), None);
+        // The least severe of the consecutive samples is used.
+        assert_eq!(
+            debouncer.update(ThermalState::Serious),
+            Some(ThermalState::Serious)
+        );
+        assert_eq!(debouncer.update(ThermalState::Serious), None);
+    }
+
+    #[test]
+    fn test_debouncer_lower() {
+        let mut debouncer = Debouncer::new();
+        debouncer.update(ThermalState::Critical);
+        debouncer.update(ThermalState::Critical);
+        assert_eq!(debouncer.state, ThermalState::Critical);
+
+        for _ in 0..LOWER_SAMPLES - 1 {
+            assert_eq!(debouncer.update(ThermalState::Nominal), None);
+        }
+        // Heating up again resets the debounce.
+        assert_eq!(debouncer.update(ThermalState::Critical), None);
+        for _ in 0..LOWER_SAMPLES - 2 {
+            assert_eq!(debouncer.update(ThermalState::Nominal), None);
+        }
+        // The most severe of the consecutive samples is used.
+        assert_eq!(debouncer.update(ThermalState::Fair), None);
+        assert_eq!(
+            debouncer.update(ThermalState::Nominal),
+            Some(ThermalState::Fair)
+        );
+    }
+
+    struct RecordingHook {
+        name: &'static str,
+        calls: Arc<Mutex<Vec<(&'static str, bool)>>>,
+        fail: bool,
+    }
+
+    impl ThermalHook for RecordingHook {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn set_throttled(&mut self, throttled: bool) -> Result<()> {
+            self.calls.lock().unwrap().push((self.name, throttled));
+            if self.fail {
+                bail!("failure");
+            }
+            Ok(())
+        }
+    }
+
+    fn new_policy_with_hooks(calls: &Arc<Mutex<Vec<(&'static str, bool)>>>) -> ThermalPolicy {
+        let mut policy = ThermalPolicy::new();
+        for (name, fail) in [("power", true), ("qos", false)] {
+            policy.hooks.push(Box::new(RecordingHook {
+                name,
+                calls: calls.clone(),
+                fail,
+            }));
+        }
+        policy
+    }
+
+    #[test]
+    fn test_policy_hysteresis_and_ordering() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let mut policy = new_policy_with_hooks(&calls);
+
+        policy.on_state_change(ThermalState::Fair);
+        assert!(calls.lock().unwrap().is_empty());
+
+        // Hooks run in registration order even if one of them fails.
+        policy.on_state_change(ThermalState::Serious);
+        assert_eq!(
+            calls.lock().unwrap().drain(..).collect::<Vec<_>>(),
+            vec![("power", true), ("qos", true)]
+        );
+
+        policy.on_state_change(ThermalState::Critical);
+        policy.on_state_change(ThermalState::Fair);
+        assert!(calls.lock().unwrap().is_empty());
+
+        // Restored in reverse order only once back to nominal.
+        policy.on_state_change(ThermalState::Nominal);
+        assert_eq!(
+            calls.lock().unwrap().drain(..).collect::<Vec<_>>(),
+            vec![("qos", false), ("power", false)]
+        );
+    }
+
+    #[test]
+    fn test_monitor_sample() {
+        let root = tempfile::tempdir().unwrap();
+        write_zone(root.path(), 0, "TCPU", 40000, &TRIPS);
+        write_zone(root.path(), 1, "TSR0", 40000, &TRIPS);
+        let mut zones = find_thermal_zones(root.path()).unwrap();
+
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let mut monitor = ThermalMonitor::new(root.path());
+        monitor.policy = new_policy_with_hooks(&calls);
+        let receiver = monitor.subscribe();
+
+        // The hottest zone decides.
+        write_zone(root.path(), 1, "TSR0", 90000, &TRIPS);
+        for _ in 0..RAISE_SAMPLES {
+            monitor.sample(&mut zones).unwrap();
+        }
+        assert_eq!(*receiver.borrow(), ThermalState::Serious);
+        assert_eq!(calls.lock().unwrap().len(), 2);
+
+        // Sampling fails only if no zone can be read.
+        fs::remove_file(zones[1].path.join("temp")).unwrap();
+        assert!(monitor.sample(&mut zones).is_ok());
+        assert!(zones[0].readable);
+        assert!(!zones[1].readable);
+        fs::remove_file(zones[0].path.join("temp")).unwrap();
+        assert!(monitor.sample(&mut zones).is_err());
+
+        // A zone is readable again once its read succeeds.
+        write_zone(root.path(), 1, "TSR0", 90000, &TRIPS);
+        assert!(monitor.sample(&mut zones).is_ok());
+        assert!(zones[1].readable);
+    }
+}
@@ -18,6 +18,7 @@ use log::error;
 use log::info;
 use log::warn;
 use protobuf::Message as ProtoMessage;
+use system_api::vm_memory_management::BalloonSize;
 use system_api::vm_memory_management::ConnectionType;
 use system_api::vm_memory_management::PacketType;
 use system_api::vm_memory_management::ResizePriority;
@@ -41,7 +42,8 @@ const VM_CONCIERGE_SERVICE_NAME: &str = "org.chromium.VmConcierge";
 /// The client internally manages the connection state, and will automatically
 /// connect/disconnect as the process providing the VMMS changes.
 ///
-/// This struct should only be used by one tokio task at a time.
+/// This struct should only be used by one tokio task at a time, except for
+/// get_balloon_sizes which gives up while another task borrows the connection.
 ///
 /// TODO(b/306377872): Move VM memory coordination into resourced
 ///
@@ -180,6 +182,28 @@ impl VmMemoryManagementClient {
         self.acquire_state().put_borrowed_connection(conn);
     }
 
+    /// Query the target balloon size of each VM from the VMMS. Returns None if
+    /// the client is not active, if the connection is in use by a reclaim
+    /// request, or if the request failed.
+    pub async fn get_balloon_sizes(&self) -> Option<Vec<BalloonSize>> {
+        let mut conn = {
+            let mut state = self.acquire_state();
+            if matches!(*state, VmMMConnectionState::Borrowed) {
+                return None;
+            }
+            state.borrow_connection()?
+        };
+        let ret = conn.get_balloon_sizes().await;
+        self.acquire_state().put_borrowed_connection(conn);
+        match ret {
+            Ok(balloons) => Some(balloons),
+            Err(e) => {
+                error!("error getting balloon sizes {:?}", e);
+                None
+            }
+        }
+    }
+
     /// Returns true if there is an active connection to the VMMS. This will
     /// return false if the VMMS hasn't started yet, if the feature is not enabled
     /// on this device, or if some other error occurred while initializing the
@@ -284,9 +308,45 @@ impl VmMMConnection {
         }
     }
 
+    async fn get_balloon_sizes(&mut self) -> Result<Vec<BalloonSize>> {
+        let mut packet = VmMemoryManagementPacket::new();
+        packet.type_ = PacketType::PACKET_TYPE_BALLOON_SIZES_REQUEST.into();
+
+        self.write_message(packet)
+            .await
+            .context("write balloon sizes request")?;
+
+        let timeout = self.reclaim_request_timeout;
+        tokio::select! {
+            biased;
+            balloons = self.read_balloon_sizes_response() => balloons,
+            () = sleep(timeout) => bail!("timeout waiting for balloon sizes"),
+        }
+    }
+
+    async fn read_balloon_sizes_response(&mut self) -> Result<Vec<BalloonSize>> {
+        loop {
+            let packet = self.read_message().await?;
+            match packet.type_.enum_value_or_default() {
+                PacketType::PACKET_TYPE_BALLOON_SIZES_RESPONSE => {
+                    return Ok(packet.balloon_sizes_response().balloons.clone());
+                }
+                // The response to a reclaim request which timed out.
+                PacketType::PACKET_TYPE_KILL_DECISION => {}
+                _ => bail!("Recieved unexpected message: type={:?}", packet.type_),
+            }
+        }
+    }
+
     async fn read_reclaim_response(&mut self, seq_num: u32) -> Result<u64> {
         loop {
             let packet = self.read_message().await?;
+            // The response to a balloon sizes request which timed out.
+            if packet.type_.enum_value_or_default()
+                == PacketType::PACKET_TYPE_BALLOON_SIZES_RESPONSE
+            {
+                continue;
+            }
             if packet.type_.enum_value_or_default() != PacketType::PACKET_TYPE_KILL_DECISION
                 || !packet.has_kill_decision_response()
             {
@@ -542,6 +602,38 @@ mod tests {
         join.await.unwrap();
     }
 
+    #[tokio::test]
+    async fn test_get_balloon_sizes() {
+        let (mut conn, mut server) = new_connection();
+
+        let join = tokio::task::spawn_blocking(move || {
+            let packet = read_server(&mut server);
+            assert_eq!(
+                packet.type_,
+                PacketType::PACKET_TYPE_BALLOON_SIZES_REQUEST.into()
+            );
+
+            // A late reply to a timed out reclaim request is skipped.
+            let mut stale = VmMemoryManagementPacket::new();
+            stale.type_ = PacketType::PACKET_TYPE_KILL_DECISION.into();
+            write_server(&mut server, stale);
+
+            let mut resp = VmMemoryManagementPacket::new();
+            resp.type_ = PacketType::PACKET_TYPE_BALLOON_SIZES_RESPONSE.into();
+            let mut balloon = BalloonSize::new();
+            balloon.cid = 5;
+            balloon.target_size_kb = 1024;
+            resp.mut_balloon_sizes_response().balloons.push(balloon);
+            write_server(&mut server, resp);
+        });
+
+        let balloons = conn.get_balloon_sizes().await.unwrap();
+        assert_eq!(balloons.len(), 1);
+        assert_eq!(balloons[0].cid, 5);
+        assert_eq!(balloons[0].target_size_kb, 1024);
+        join.await.unwrap();
+    }
+
     #[tokio::test(flavor = "current_thread")]
     async fn test_try_reclaim_memory_timeout() {
         let (mut conn, mut server) = new_connection();
@@ -13,6 +13,13 @@ pub const UNMOUNTER_INTERVAL: Duration = Duration::from_millis(1000);
 #[cfg(test)]
 pub const UNMOUNTER_INTERVAL: Duration = Duration::from_millis(10);
 
+// Time to keep a shader cache mounted after its last game session ended, so
+// that relaunching the game right away does not remount it.
+#[cfg(not(test))]
+pub const UNMOUNT_GRACE_PERIOD: Duration = Duration::from_secs(5);
+#[cfg(test)]
+pub const UNMOUNT_GRACE_PERIOD: Duration = Duration::from_millis(20);
+
 pub const DLC_HANDLER_INTERVAL: Duration = Duration::from_millis(1000);
 pub const MAX_CONCURRENT_DLC_INSTALLS: usize = 1;
 // Limit the number of DLC installation queue to:
@@ -29,6 +36,7 @@ pub const PRECOMPILED_CACHE_DIR: &str = "precompiled_cache";
 lazy_static! {
     pub static ref IMAGE_LOADER: PathBuf = tempfile::tempdir().unwrap().into_path();
     pub static ref CRYPTO_HOME: PathBuf = tempfile::tempdir().unwrap().into_path();
+    pub static ref MOUNT_REFS_DIR: PathBuf = tempfile::tempdir().unwrap().into_path();
     pub static ref GPU_DEVICE_ID: u16 = 0x9a40;
 }
 
@@ -37,6 +45,9 @@ lazy_static! {
     pub static ref IMAGE_LOADER: PathBuf = std::path::Path::new("/run/imageloader").to_path_buf();
     pub static ref CRYPTO_HOME: PathBuf =
         std::path::Path::new("/run/daemon-store/shadercached").to_path_buf();
+    // Game session counts of each VM, kept across shadercached restarts.
+    pub static ref MOUNT_REFS_DIR: PathBuf =
+        std::path::Path::new("/run/shadercached/mount_refs").to_path_buf();
     pub static ref GPU_DEVICE_ID: u16 = get_gpu_device_id().unwrap_or(0);
 }
 
@@ -90,6 +90,7 @@ async fn dequeue_mount_for_failed_dlc<D: DbusConnectionTrait>(
         }
 
         shader_cache_mount.dequeue_mount(&steam_app_id);
+        shader_cache_mount.drop_mount_refs(steam_app_id);
         let mut mount_status = ShaderCacheMountStatus::new();
         mount_status.mounted = false;
         mount_status.vm_name = vm_id.vm_name.clone();
@@ -240,6 +241,8 @@ pub async fn unmount_dlc(
                 "Processing DLC {} unmount for VM {:?}",
                 steam_app_id_to_unmount, vm_id
             );
+            // The DLC is going away, unmount regardless of game sessions.
+            shader_cache_mount.drop_mount_refs(steam_app_id_to_unmount);
             shader_cache_mount.remove_game_from_db_list(steam_app_id_to_unmount)?;
         }
     }
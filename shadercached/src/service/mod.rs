@@ -94,9 +94,21 @@ pub async fn handle_install<D: DbusConnectionTrait>(
         response.mounted = is_mounted;
     }
 
-    if request.mount && !response.mounted {
-        // Queue mount if not mounted already
-        shader_cache_mount.enqueue_mount(request.steam_app_id);
+    if request.mount {
+        // Each mount request is a game session using the cache until the
+        // matching unmount request.
+        shader_cache_mount.acquire_mount(request.steam_app_id);
+        if !response.mounted {
+            // Queue mount if not mounted already
+            shader_cache_mount.enqueue_mount(request.steam_app_id);
+        } else if let Err(e) = shader_cache_mount.add_game_to_db_list(request.steam_app_id) {
+            // The previous session may have removed the game from the list
+            // while the cache waits to be unmounted.
+            warn!(
+                "Failed to add {} to foz db list: {}",
+                request.steam_app_id, e
+            );
+        }
     }
 
     if !is_dlc_installed(request.steam_app_id) {
@@ -108,6 +120,7 @@ pub async fn handle_install<D: DbusConnectionTrait>(
         let mut dlc_queue = dlc_queue.write().await;
         if let Some(dequeued_game) = dlc_queue.queue_install(&request.steam_app_id) {
             shader_cache_mount.dequeue_mount(&dequeued_game);
+            shader_cache_mount.drop_mount_refs(dequeued_game);
         }
         return Ok(response.write_to_bytes()?);
     }
@@ -236,7 +249,9 @@ pub async fn handle_unmount(raw_bytes: Vec<u8>, mount_map: ShaderCacheMountMapPt
         // with success code.
         // (note: --wait flag probably only needed for tasts and manual
         // debugging).
-        shader_cache_mount.remove_game_from_db_list(request.steam_app_id)?;
+        // If other game sessions still use the cache, it stays mounted until
+        // the last of them is unmounted.
+        shader_cache_mount.release_mount(request.steam_app_id)?;
     } else {
         return Err(anyhow!("VM had never mounted shader cache"));
     }
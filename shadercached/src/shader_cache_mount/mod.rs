@@ -22,21 +22,39 @@ use mesa_path_constants::*;
 
 use anyhow::{anyhow, Result};
 use log::{debug, error};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::ffi::OsString;
 use std::fs;
 use std::os::unix::prelude::PermissionsExt;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Instant;
 
 const UNINITIALIZED_ERROR: &str = "Mesa cache path not initialized";
 
+// Game sessions using a Steam app's shader cache.
+#[derive(Debug, Clone, Default)]
+struct MountRefs {
+    // Number of mount requests not matched by an unmount request yet.
+    count: u32,
+    // When |count| dropped to zero. The cache is unmounted once
+    // UNMOUNT_GRACE_PERIOD has passed since then.
+    released_at: Option<Instant>,
+}
+
 #[derive(Debug, Clone)]
 pub struct ShaderCacheMount {
     // The Steam application that we want to mount to this directory.
     mount_queue: HashSet<SteamAppId>,
     // Steam app ids to unmount in periodic unmount loop
     unmount_queue: HashSet<SteamAppId>,
+    // Reference counts of the mounted or mount-pending Steam apps. Several
+    // games, or several sessions of a game, can use the caches of one VM at
+    // the same time. Entries are dropped once the cache is unmounted.
+    mount_refs: HashMap<SteamAppId, MountRefs>,
+    // File persisting the counts of |mount_refs|, so that sessions started
+    // before shadercached restarted keep the caches mounted.
+    mount_refs_path: PathBuf,
     // crosvm render server cache path
     render_server_path: PathBuf,
     // Precompiled cache path
@@ -62,9 +80,12 @@ impl ShaderCacheMount {
             .join(&vm_id.vm_owner_id)
             .join(PRECOMPILED_CACHE_DIR)
             .join(vm_name_encoded);
+        let mount_refs_path = get_mount_refs_path(vm_id);
         Ok(ShaderCacheMount {
             mount_queue: HashSet::new(),
             unmount_queue: HashSet::new(),
+            mount_refs: queue_ops::load_mount_refs(&mount_refs_path),
+            mount_refs_path,
             render_server_path: render_server_path.clone(),
             precompiled_cache_path,
             foz_blob_db_list_path: render_server_path.join(FOZ_DB_LIST_FILE),
@@ -158,6 +179,14 @@ impl ShaderCacheMount {
     }
 }
 
+fn get_mount_refs_path(vm_id: &VmId) -> PathBuf {
+    let vm_id_encoded = base64::encode_config(
+        format!("{}/{}", vm_id.vm_owner_id, vm_id.vm_name),
+        base64::URL_SAFE,
+    );
+    MOUNT_REFS_DIR.join(vm_id_encoded)
+}
+
 fn get_mesa_cache_relative_path(render_server_path: &Path) -> Result<PathBuf> {
     // Within gpu cache directory, mesa creates a nested sub directory to store
     // shader cache.
@@ -240,6 +269,11 @@ impl ShaderCacheMount {
     pub fn get_unmount_queue(&self) -> &HashSet<SteamAppId> {
         &self.unmount_queue
     }
+    pub fn get_mount_ref_count(&self, steam_app_id: SteamAppId) -> u32 {
+        self.mount_refs
+            .get(&steam_app_id)
+            .map_or(0, |mount_refs| mount_refs.count)
+    }
 }
 
 #[cfg(test)]
@@ -6,14 +6,16 @@
 // which are linked to foz db list operations.
 
 use super::mesa_path_constants::*;
-use super::{ShaderCacheMount, ShaderCacheMountMap, VmId};
+use super::{MountRefs, ShaderCacheMount, ShaderCacheMountMap, VmId};
 use crate::common::*;
 
 use anyhow::{anyhow, Result};
 use log::{debug, error, warn};
 use regex::Regex;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fs;
+use std::path::Path;
+use std::time::Instant;
 use system_api::shadercached::ShaderCacheMountStatus;
 
 impl ShaderCacheMount {
@@ -99,6 +101,10 @@ impl ShaderCacheMount {
         let mut mount_statuses: Vec<ShaderCacheMountStatus> = vec![];
 
         for &steam_app_id in &self.unmount_queue {
+            if !self.is_unmount_due(steam_app_id) {
+                debug!("Unmount of {} is not due yet", steam_app_id);
+                continue;
+            }
             debug!("Attempting to unmount {}", steam_app_id);
 
             let unmount_result = self.unmount(steam_app_id);
@@ -118,11 +124,113 @@ impl ShaderCacheMount {
             mount_statuses.push(status);
         }
 
+        for steam_app_id in &to_dequeue {
+            self.mount_refs.remove(steam_app_id);
+        }
+        if !to_dequeue.is_empty() {
+            self.save_mount_refs();
+        }
         self.dequeue_unmount_multi(&to_dequeue);
 
         mount_statuses
     }
 
+    pub fn acquire_mount(&mut self, steam_app_id: SteamAppId) -> u32 {
+        // A game session starts using the cache. Cancel the unmount if the
+        // previous session just ended.
+        let mount_refs = self.mount_refs.entry(steam_app_id).or_default();
+        mount_refs.count += 1;
+        mount_refs.released_at = None;
+        let count = mount_refs.count;
+        debug!("Acquired mount {}, ref count {}", steam_app_id, count);
+        self.unmount_queue.remove(&steam_app_id);
+        self.save_mount_refs();
+        count
+    }
+
+    pub fn release_mount(&mut self, steam_app_id: SteamAppId) -> Result<bool> {
+        // A game session stops using the cache. The last session removes the
+        // game from the foz db list and queues unmount, which happens after
+        // the grace period. If the cache was mounted without acquiring it
+        // (ex. before shadercached restarted), unmount is queued right away.
+        if let Some(mount_refs) = self.mount_refs.get_mut(&steam_app_id) {
+            mount_refs.count = mount_refs.count.saturating_sub(1);
+            let count = mount_refs.count;
+            if count == 0 {
+                mount_refs.released_at = Some(Instant::now());
+            }
+            self.save_mount_refs();
+            if count > 0 {
+                debug!(
+                    "Released mount {}, still used by {} sessions",
+                    steam_app_id, count
+                );
+                return Ok(false);
+            }
+        }
+        self.remove_game_from_db_list(steam_app_id)
+    }
+
+    pub fn drop_mount_refs(&mut self, steam_app_id: SteamAppId) {
+        // Forget the sessions of the game so that it can be unmounted
+        // regardless of them. Used when the cache must go away (ex. DLC
+        // uninstallation) or will never be mounted.
+        if let Some(mount_refs) = self.mount_refs.remove(&steam_app_id) {
+            if mount_refs.count > 0 {
+                warn!(
+                    "Dropping {} sessions still using the cache of {}",
+                    mount_refs.count, steam_app_id
+                );
+            }
+            self.save_mount_refs();
+        }
+    }
+
+    fn save_mount_refs(&self) {
+        // Persist the session counts so that a restarted shadercached does
+        // not unmount caches still in use. Only games with sessions are
+        // stored, pending grace periods are not kept across restarts.
+        // Example contents:
+        // 620 2
+        // 570 1
+        let mut contents = String::new();
+        for (steam_app_id, mount_refs) in &self.mount_refs {
+            if mount_refs.count > 0 {
+                contents += &format!("{} {}\n", steam_app_id, mount_refs.count);
+            }
+        }
+        let result = if contents.is_empty() {
+            match fs::remove_file(&self.mount_refs_path) {
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+                result => result,
+            }
+        } else {
+            // Write to a temporary file first so that a crash never leaves a
+            // partial file behind.
+            let temp_path = self.mount_refs_path.with_extension("tmp");
+            fs::write(&temp_path, contents)
+                .and_then(|_| fs::rename(&temp_path, &self.mount_refs_path))
+        };
+        if let Err(e) = result {
+            warn!(
+                "Failed to save mount ref counts to {:?}: {}",
+                self.mount_refs_path, e
+            );
+        }
+    }
+
+    fn is_unmount_due(&self, steam_app_id: SteamAppId) -> bool {
+        match self.mount_refs.get(&steam_app_id) {
+            None => true,
+            Some(MountRefs { count, .. }) if *count > 0 => false,
+            Some(MountRefs {
+                released_at: Some(released_at),
+                ..
+            }) => released_at.elapsed() >= UNMOUNT_GRACE_PERIOD,
+            Some(_) => true,
+        }
+    }
+
     pub fn is_pending_mount(&self, steam_app_id: &SteamAppId) -> bool {
         self.mount_queue.contains(steam_app_id)
     }
@@ -192,6 +300,13 @@ impl ShaderCacheMount {
         self.mount_queue.clear()
     }
 
+    fn clear_mount_refs(&mut self) {
+        let steam_app_ids: Vec<SteamAppId> = self.mount_refs.keys().copied().collect();
+        for steam_app_id in steam_app_ids {
+            self.drop_mount_refs(steam_app_id);
+        }
+    }
+
     fn dequeue_unmount_multi(&mut self, to_remove: &[SteamAppId]) {
         debug!("Dequeue unmount {:?}: {:?}", to_remove, self.unmount_queue);
         self.unmount_queue
@@ -211,17 +326,54 @@ impl ShaderCacheMount {
     }
 }
 
+pub(super) fn load_mount_refs(path: &Path) -> HashMap<SteamAppId, MountRefs> {
+    let mut mount_refs = HashMap::new();
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) => {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                warn!("Failed to load mount ref counts from {:?}: {}", path, e);
+            }
+            return mount_refs;
+        }
+    };
+    for line in contents.lines() {
+        let parsed = line.split_once(' ').and_then(|(steam_app_id, count)| {
+            Some((
+                steam_app_id.parse::<SteamAppId>().ok()?,
+                count.parse::<u32>().ok()?,
+            ))
+        });
+        match parsed {
+            Some((steam_app_id, count)) if count > 0 => {
+                debug!("Restored mount {}, ref count {}", steam_app_id, count);
+                mount_refs.insert(
+                    steam_app_id,
+                    MountRefs {
+                        count,
+                        released_at: None,
+                    },
+                );
+            }
+            _ => warn!("Unexpected mount ref count entry, ignoring: {}", line),
+        }
+    }
+    mount_refs
+}
+
 impl ShaderCacheMountMap {
     pub async fn clear_all_mounts(self: &ShaderCacheMountMap, vm_id: Option<VmId>) -> Result<()> {
         // Queue unmount-everything and clear queued mounts.
         // This function is called on Purge (vm_id is None) and on Borealis exit
-        // (vm_id is set).
+        // (vm_id is set). The caches are unmounted regardless of the game
+        // sessions still using them.
         let mut mount_map = self.write().await;
         let mut failed_unmounts: HashSet<VmId> = HashSet::new();
 
         if let Some(vm_id) = vm_id {
             if let Some(shader_cache_mount) = mount_map.get_mut(&vm_id) {
                 shader_cache_mount.clear_mount_queue();
+                shader_cache_mount.clear_mount_refs();
                 if let Err(e) = shader_cache_mount.reset_foz_db_list() {
                     error!("Failed to queue unmount all for {:?}: {}", vm_id, e);
                     failed_unmounts.insert(vm_id);
@@ -232,6 +384,7 @@ impl ShaderCacheMountMap {
         } else {
             for (vm_id, shader_cache_mount) in mount_map.iter_mut() {
                 shader_cache_mount.clear_mount_queue();
+                shader_cache_mount.clear_mount_refs();
                 if let Err(e) = shader_cache_mount.reset_foz_db_list() {
                     error!("Failed to queue unmount all for {:?}: {}", vm_id, e);
                     failed_unmounts.insert(vm_id.clone());
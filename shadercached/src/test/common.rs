@@ -11,7 +11,7 @@ use tempfile::TempDir;
 
 use crate::{
     common::{
-        steam_app_id_to_dlc, SteamAppId, CRYPTO_HOME, GPU_DEVICE_ID, IMAGE_LOADER,
+        steam_app_id_to_dlc, SteamAppId, CRYPTO_HOME, GPU_DEVICE_ID, IMAGE_LOADER, MOUNT_REFS_DIR,
         PRECOMPILED_CACHE_DIR,
     },
     dbus_wrapper::MockDbusConnectionTrait,
@@ -68,6 +68,11 @@ pub fn mock_gpucache() -> Result<TempDir> {
 
     create_mock_mesa_shader_cache_sf(&render_server_path)?;
 
+    // Start without the game sessions persisted by the previous tests.
+    for entry in std::fs::read_dir(&*MOUNT_REFS_DIR)? {
+        std::fs::remove_file(entry?.path())?;
+    }
+
     Ok(mock_gpu_cache)
 }
 
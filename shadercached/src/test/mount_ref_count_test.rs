@@ -0,0 +1,266 @@
+// Copyright 2024 The ChromiumOS Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use serial_test::serial;
+use system_api::shadercached::UnmountRequest;
+use tempfile::TempDir;
+
+use crate::common::{SteamAppId, UNMOUNT_GRACE_PERIOD};
+use crate::service::handle_unmount;
+use crate::shader_cache_mount::{mount_ops, new_mount_map, ShaderCacheMountMap, VmId};
+use crate::test::common::{
+    add_shader_cache_mount, foz_db_list_contains, foz_db_list_empty, generate_mount_list,
+    get_unmount_queue, mock_gpucache, simulate_mounted,
+};
+
+// Mount list lines of the games currently mounted, keyed by game
+type MountedGames = Arc<Mutex<HashMap<SteamAppId, String>>>;
+
+fn mock_unmount_request(vm_id: &VmId, game_id: SteamAppId) -> Result<Vec<u8>> {
+    let mut unmount_request = UnmountRequest::new();
+    unmount_request.vm_name = vm_id.vm_name.clone();
+    unmount_request.vm_owner_id = vm_id.vm_owner_id.clone();
+    unmount_request.steam_app_id = game_id;
+    Ok(protobuf::Message::write_to_bytes(&unmount_request)?)
+}
+
+async fn mount_game(
+    mock_gpu_cache: &TempDir,
+    mounted_games: &MountedGames,
+    game_id: SteamAppId,
+) -> Result<()> {
+    simulate_mounted(mock_gpu_cache, game_id).await?;
+    mounted_games
+        .lock()
+        .unwrap()
+        .insert(game_id, generate_mount_list(mock_gpu_cache, game_id));
+    Ok(())
+}
+
+async fn acquire_mount(
+    mount_map: Arc<ShaderCacheMountMap>,
+    vm_id: &VmId,
+    game_id: SteamAppId,
+) -> Result<u32> {
+    let mut mount_map_write = mount_map.write().await;
+    let shader_cache_mount = mount_map_write.get_mut(vm_id).unwrap();
+    Ok(shader_cache_mount.acquire_mount(game_id))
+}
+
+async fn get_mount_ref_count(
+    mount_map: Arc<ShaderCacheMountMap>,
+    vm_id: &VmId,
+    game_id: SteamAppId,
+) -> Result<u32> {
+    let mount_map_read = mount_map.read().await;
+    let shader_cache_mount = mount_map_read.get(vm_id).unwrap();
+    Ok(shader_cache_mount.get_mount_ref_count(game_id))
+}
+
+async fn process_unmount_queue(mount_map: Arc<ShaderCacheMountMap>, vm_id: &VmId) -> Result<()> {
+    let mut mount_map_write = mount_map.write().await;
+    let shader_cache_mount = mount_map_write.get_mut(vm_id).unwrap();
+    shader_cache_mount.process_unmount_queue();
+    Ok(())
+}
+
+fn is_mounted(mounted_games: &MountedGames, game_id: SteamAppId) -> bool {
+    mounted_games.lock().unwrap().contains_key(&game_id)
+}
+
+#[tokio::test]
+#[serial]
+async fn unmount_after_last_session() -> Result<()> {
+    let mock_gpu_cache = mock_gpucache()?;
+    let vm_id = VmId::new("vm", "owner");
+    let mount_map = new_mount_map();
+    add_shader_cache_mount(&mock_gpu_cache, mount_map.clone(), &vm_id).await?;
+
+    let mounted_games: MountedGames = Arc::new(Mutex::new(HashMap::new()));
+    let get_mount_list_context = mount_ops::helpers::mock_privileged_ops::get_mount_list_context();
+    let mounted_games_clone = mounted_games.clone();
+    get_mount_list_context.expect().returning(move || {
+        Ok(mounted_games_clone
+            .lock()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect())
+    });
+    let unmount_context = mount_ops::helpers::mock_privileged_ops::unmount_context();
+    let mounted_games_clone = mounted_games.clone();
+    unmount_context.expect().returning(move |path| {
+        mounted_games_clone
+            .lock()
+            .unwrap()
+            .retain(|_, mount_line| !mount_line.contains(path));
+        Ok(())
+    });
+
+    // Two overlapping sessions of game 42 and one session of game 1337
+    mount_game(&mock_gpu_cache, &mounted_games, 42).await?;
+    mount_game(&mock_gpu_cache, &mounted_games, 1337).await?;
+    assert_eq!(acquire_mount(mount_map.clone(), &vm_id, 42).await?, 1);
+    assert_eq!(acquire_mount(mount_map.clone(), &vm_id, 1337).await?, 1);
+    assert_eq!(acquire_mount(mount_map.clone(), &vm_id, 42).await?, 2);
+
+    // First session of 42 exits, the cache is still in use
+    handle_unmount(mock_unmount_request(&vm_id, 42)?, mount_map.clone()).await?;
+    assert_eq!(get_mount_ref_count(mount_map.clone(), &vm_id, 42).await?, 1);
+    assert!(get_unmount_queue(mount_map.clone(), &vm_id)
+        .await?
+        .is_empty());
+    assert!(foz_db_list_contains(&mock_gpu_cache, 42)?);
+
+    // 1337 exits, unmount waits for the grace period
+    handle_unmount(mock_unmount_request(&vm_id, 1337)?, mount_map.clone()).await?;
+    assert!(!foz_db_list_contains(&mock_gpu_cache, 1337)?);
+    process_unmount_queue(mount_map.clone(), &vm_id).await?;
+    assert!(is_mounted(&mounted_games, 1337));
+
+    tokio::time::sleep(UNMOUNT_GRACE_PERIOD * 2).await;
+    process_unmount_queue(mount_map.clone(), &vm_id).await?;
+    assert!(!is_mounted(&mounted_games, 1337));
+    assert!(is_mounted(&mounted_games, 42));
+
+    // Last session of 42 exits
+    handle_unmount(mock_unmount_request(&vm_id, 42)?, mount_map.clone()).await?;
+    assert_eq!(get_mount_ref_count(mount_map.clone(), &vm_id, 42).await?, 0);
+    tokio::time::sleep(UNMOUNT_GRACE_PERIOD * 2).await;
+    process_unmount_queue(mount_map.clone(), &vm_id).await?;
+    assert!(!is_mounted(&mounted_games, 42));
+
+    assert!(foz_db_list_empty(&mock_gpu_cache)?);
+    assert!(get_unmount_queue(mount_map.clone(), &vm_id)
+        .await?
+        .is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn remount_during_grace_period() -> Result<()> {
+    let mock_gpu_cache = mock_gpucache()?;
+    let vm_id = VmId::new("vm", "owner");
+    let mount_map = new_mount_map();
+    add_shader_cache_mount(&mock_gpu_cache, mount_map.clone(), &vm_id).await?;
+
+    simulate_mounted(&mock_gpu_cache, 42).await?;
+    acquire_mount(mount_map.clone(), &vm_id, 42).await?;
+
+    handle_unmount(mock_unmount_request(&vm_id, 42)?, mount_map.clone()).await?;
+    assert!(get_unmount_queue(mount_map.clone(), &vm_id)
+        .await?
+        .contains(&42));
+
+    // The game starts again before the cache is unmounted
+    acquire_mount(mount_map.clone(), &vm_id, 42).await?;
+    assert!(get_unmount_queue(mount_map.clone(), &vm_id)
+        .await?
+        .is_empty());
+    assert_eq!(get_mount_ref_count(mount_map.clone(), &vm_id, 42).await?, 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn clear_all_mounts_ignores_sessions() -> Result<()> {
+    let mock_gpu_cache = mock_gpucache()?;
+    let vm_id = VmId::new("vm", "owner");
+    let mount_map = new_mount_map();
+    add_shader_cache_mount(&mock_gpu_cache, mount_map.clone(), &vm_id).await?;
+
+    let mounted_games: MountedGames = Arc::new(Mutex::new(HashMap::new()));
+    let get_mount_list_context = mount_ops::helpers::mock_privileged_ops::get_mount_list_context();
+    let mounted_games_clone = mounted_games.clone();
+    get_mount_list_context.expect().returning(move || {
+        Ok(mounted_games_clone
+            .lock()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect())
+    });
+    let unmount_context = mount_ops::helpers::mock_privileged_ops::unmount_context();
+    let mounted_games_clone = mounted_games.clone();
+    unmount_context.expect().returning(move |path| {
+        mounted_games_clone
+            .lock()
+            .unwrap()
+            .retain(|_, mount_line| !mount_line.contains(path));
+        Ok(())
+    });
+
+    mount_game(&mock_gpu_cache, &mounted_games, 42).await?;
+    acquire_mount(mount_map.clone(), &vm_id, 42).await?;
+    acquire_mount(mount_map.clone(), &vm_id, 42).await?;
+
+    // Purge unmounts without grace period even though the game is running
+    mount_map.clear_all_mounts(None).await?;
+    assert!(foz_db_list_empty(&mock_gpu_cache)?);
+    assert_eq!(get_mount_ref_count(mount_map.clone(), &vm_id, 42).await?, 0);
+
+    process_unmount_queue(mount_map.clone(), &vm_id).await?;
+    assert!(!is_mounted(&mounted_games, 42));
+    assert!(get_unmount_queue(mount_map.clone(), &vm_id)
+        .await?
+        .is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn ref_counts_survive_restart() -> Result<()> {
+    let mock_gpu_cache = mock_gpucache()?;
+    let vm_id = VmId::new("vm", "owner");
+    let mount_map = new_mount_map();
+    add_shader_cache_mount(&mock_gpu_cache, mount_map.clone(), &vm_id).await?;
+
+    simulate_mounted(&mock_gpu_cache, 42).await?;
+    acquire_mount(mount_map.clone(), &vm_id, 42).await?;
+    acquire_mount(mount_map.clone(), &vm_id, 42).await?;
+    acquire_mount(mount_map.clone(), &vm_id, 1337).await?;
+    handle_unmount(mock_unmount_request(&vm_id, 1337)?, mount_map.clone()).await?;
+
+    // shadercached restarts with both sessions of 42 still running
+    let mount_map = new_mount_map();
+    add_shader_cache_mount(&mock_gpu_cache, mount_map.clone(), &vm_id).await?;
+    assert_eq!(get_mount_ref_count(mount_map.clone(), &vm_id, 42).await?, 2);
+    assert_eq!(
+        get_mount_ref_count(mount_map.clone(), &vm_id, 1337).await?,
+        0
+    );
+
+    // The first session exiting after the restart keeps the cache mounted
+    handle_unmount(mock_unmount_request(&vm_id, 42)?, mount_map.clone()).await?;
+    assert_eq!(get_mount_ref_count(mount_map.clone(), &vm_id, 42).await?, 1);
+    assert!(foz_db_list_contains(&mock_gpu_cache, 42)?);
+    assert!(get_unmount_queue(mount_map.clone(), &vm_id)
+        .await?
+        .is_empty());
+
+    // The last session exiting queues unmount, also after another restart
+    let mount_map = new_mount_map();
+    add_shader_cache_mount(&mock_gpu_cache, mount_map.clone(), &vm_id).await?;
+    handle_unmount(mock_unmount_request(&vm_id, 42)?, mount_map.clone()).await?;
+    assert_eq!(get_mount_ref_count(mount_map.clone(), &vm_id, 42).await?, 0);
+    assert!(!foz_db_list_contains(&mock_gpu_cache, 42)?);
+    assert!(get_unmount_queue(mount_map.clone(), &vm_id)
+        .await?
+        .contains(&42));
+
+    // Nothing is restored once no session is left
+    let mount_map = new_mount_map();
+    add_shader_cache_mount(&mock_gpu_cache, mount_map.clone(), &vm_id).await?;
+    assert_eq!(get_mount_ref_count(mount_map.clone(), &vm_id, 42).await?, 0);
+
+    Ok(())
+}
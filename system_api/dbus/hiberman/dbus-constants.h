@@ -17,6 +17,18 @@ const char kHibernateServiceName[] = "org.chromium.Hibernate";
 const char kResumeFromHibernateASMethod[] = "ResumeFromHibernateAS";
 const char kAbortResumeMethod[] = "AbortResume";
 
+// hiberman service, which checks and requests hibernate.
+const char kHibernateManagerInterface[] = "org.chromium.HibernateInterface";
+const char kHibernateManagerServicePath[] = "/org/chromium/HibernateManager";
+const char kHibernateManagerServiceName[] = "org.chromium.HibernateManager";
+
+// Methods exposed by the hiberman service.
+const char kCheckHibernateReadinessMethod[] = "CheckHibernateReadiness";
+const char kRequestHibernateMethod[] = "RequestHibernate";
+
+// Signals emitted by the hiberman service.
+const char kHibernateProgressSignal[] = "HibernateProgress";
+
 // When this file exists at boot, a resume from hibernation is in progress.
 // It is deleted if the resume is aborted.
 const char kHibernateResumeInProgressFile[] =
@@ -65,6 +65,7 @@ const char kSetFullscreenVideoWithTimeout[] = "SetFullscreenVideoWithTimeout";
 const char kSetVmBootModeWithTimeoutMethod[] = "SetVmBootModeWithTimeout";
 const char kReportBackgroundProcessesMethod[] = "ReportBackgroundProcesses";
 const char kReportBrowserProcessesMethod[] = "ReportBrowserProcesses";
+const char kGetDiscardCandidatesHintMethod[] = "GetDiscardCandidatesHint";
 
 // Signals.
 
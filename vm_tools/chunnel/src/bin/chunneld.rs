@@ -9,7 +9,7 @@ use std::fs::File;
 use std::io;
 use std::net::{Ipv4Addr, Ipv6Addr, TcpListener};
 use std::os::raw::c_int;
-use std::os::unix::io::{AsRawFd, IntoRawFd};
+use std::os::unix::io::{AsRawFd, BorrowedFd, IntoRawFd};
 use std::result;
 use std::sync::{Arc, Mutex};
 use std::thread;
@@ -19,7 +19,7 @@ use chunnel::forwarder::ForwarderSession;
 use dbus::arg::OwnedFd;
 use dbus::blocking::LocalConnection as DBusConnection;
 use dbus::{self, Error as DBusError};
-use libchromeos::deprecated::{EventFd, PollContext, PollToken};
+use libchromeos::events::{EventFd, PollToken, Poller};
 use libchromeos::panic_handler::install_memfd_handler;
 use libchromeos::pipe;
 use libchromeos::signal::block_signal;
@@ -198,7 +198,7 @@ impl ForwarderSessions {
     }
 
     /// Adds or removes listeners based on the latest listening ports from the D-Bus thread.
-    fn process_update_queue(&mut self, poll_ctx: &PollContext<Token>) -> Result<()> {
+    fn process_update_queue(&mut self, poll_ctx: &Poller<Token>) -> Result<()> {
         // Unwrap of LockResult is customary.
         let mut update_queue = self.update_queue.lock().unwrap();
         let mut active_ports: BTreeSet<u16> = BTreeSet::new();
@@ -301,7 +301,7 @@ impl ForwarderSessions {
 
     fn accept_connection(
         &mut self,
-        poll_ctx: &PollContext<Token>,
+        poll_ctx: &Poller<Token>,
         port: u16,
         sock_family: SocketFamily,
     ) -> Result<()> {
@@ -315,7 +315,7 @@ impl ForwarderSessions {
             SocketFamily::Ipv6 => &port_listeners.tcp6_listener,
         };
 
-        // This session should be dropped if any of the PollContext setup fails. Since the only
+        // This session should be dropped if any of the Poller setup fails. Since the only
         // extant fds for the underlying sockets will be closed, they will be unregistered from
         // epoll set automatically.
         let session = create_forwarder_session(
@@ -338,7 +338,7 @@ impl ForwarderSessions {
         Ok(())
     }
 
-    fn forward_from_local(&mut self, poll_ctx: &PollContext<Token>, tag: SessionTag) -> Result<()> {
+    fn forward_from_local(&mut self, poll_ctx: &Poller<Token>, tag: SessionTag) -> Result<()> {
         let session = self
             .tcp4_forwarders
             .get_mut(&tag)
@@ -356,11 +356,7 @@ impl ForwarderSessions {
         Ok(())
     }
 
-    fn forward_from_remote(
-        &mut self,
-        poll_ctx: &PollContext<Token>,
-        tag: SessionTag,
-    ) -> Result<()> {
+    fn forward_from_remote(&mut self, poll_ctx: &Poller<Token>, tag: SessionTag) -> Result<()> {
         let session = self
             .tcp4_forwarders
             .get_mut(&tag)
@@ -379,9 +375,8 @@ impl ForwarderSessions {
     }
 
     fn run(&mut self) -> Result<()> {
-        let poll_ctx: PollContext<Token> =
-            PollContext::build_with(&[(&self.update_evt, Token::UpdatePorts)])
-                .map_err(Error::PollContextNew)?;
+        let poll_ctx: Poller<Token> = Poller::build_with(&[(&self.update_evt, Token::UpdatePorts)])
+            .map_err(Error::PollContextNew)?;
 
         loop {
             let events = poll_ctx.wait().map_err(Error::PollWait)?;
@@ -490,9 +485,10 @@ fn create_forwarder_session(
         VsockAccept,
     }
 
-    let poll_ctx: PollContext<Token> =
-        PollContext::build_with(&[(&vsock_listener, Token::VsockAccept)])
-            .map_err(Error::PollContextNew)?;
+    // Safe because vsock_listener outlives the borrow.
+    let vsock_fd = unsafe { BorrowedFd::borrow_raw(vsock_listener.as_raw_fd()) };
+    let poll_ctx: Poller<Token> =
+        Poller::build_with(&[(&vsock_fd, Token::VsockAccept)]).map_err(Error::PollContextNew)?;
 
     // Wait a few seconds for the guest to connect.
     let events = poll_ctx
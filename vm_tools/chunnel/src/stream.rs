@@ -5,7 +5,7 @@
 use std::fmt;
 use std::io;
 use std::net::TcpStream;
-use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, RawFd};
 use std::os::unix::net::UnixStream;
 use std::result;
 
@@ -119,6 +119,13 @@ impl io::Write for StreamSocket {
     }
 }
 
+impl AsFd for StreamSocket {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        // Safe because the fd is owned by self and stays open until self is dropped.
+        unsafe { BorrowedFd::borrow_raw(self.fd) }
+    }
+}
+
 impl AsRawFd for StreamSocket {
     fn as_raw_fd(&self) -> RawFd {
         self.fd
@@ -148,6 +148,11 @@ apps::VmType BalloonBlocker::GetVmType() const {
   return metrics_->GetVmType();
 }
 
+int64_t BalloonBlocker::GetTargetSize() {
+  DCHECK_CALLED_ON_VALID_SEQUENCE(sequence_checker_);
+  return balloon_->GetTargetSize();
+}
+
 int BalloonBlocker::GetCid() {
   return vm_cid_;
 }
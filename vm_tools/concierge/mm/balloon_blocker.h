@@ -87,6 +87,9 @@ class BalloonBlocker {
   // Returns the type of VM this blocker is for. Used for logging and metrics.
   apps::VmType GetVmType() const;
 
+  // Returns the size in bytes the balloon is inflating or deflating to.
+  virtual int64_t GetTargetSize();
+
   // Sets whether the "BalloonTrace" logs should be printed.
   void SetShouldLogBalloonTrace(bool do_log);
 
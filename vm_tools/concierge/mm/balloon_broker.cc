@@ -70,6 +70,8 @@ BalloonBroker::BalloonBroker(
       &BalloonBroker::HandleNoKillCandidates, base::Unretained(this)));
   kills_server_->SetDecisionLatencyNotification(base::BindRepeating(
       &BalloonBroker::HandleDecisionLatency, base::Unretained(this)));
+  kills_server_->SetBalloonSizesHandler(base::BindRepeating(
+      &BalloonBroker::HandleBalloonSizesRequest, base::Unretained(this)));
 
   // Add the local context. Local context does not have a balloon.
   contexts_[VMADDR_CID_LOCAL] = {};
@@ -426,6 +428,19 @@ void BalloonBroker::HandleDecisionLatency(Client client,
   }
 }
 
+base::flat_map<int, int64_t> BalloonBroker::HandleBalloonSizesRequest() {
+  DCHECK_CALLED_ON_VALID_SEQUENCE(sequence_checker_);
+
+  base::flat_map<int, int64_t> sizes;
+  for (const auto& [cid, context] : contexts_) {
+    // The host has no balloon.
+    if (context.balloon) {
+      sizes[cid] = context.balloon->GetTargetSize();
+    }
+  }
+  return sizes;
+}
+
 int64_t BalloonBroker::EvenlyAdjustBalloons(const base::flat_set<int>& targets,
                                             int64_t total_adjustment,
                                             ResizePriority priority) {
@@ -158,6 +158,10 @@ class BalloonBroker {
   // Callback to be run when a decision latency packet is received.
   void HandleDecisionLatency(Client client, const DecisionLatency& latency);
 
+  // Callback to be run when the balloon sizes are requested. Returns the
+  // target balloon size in bytes of each VM, keyed by CID.
+  base::flat_map<int, int64_t> HandleBalloonSizesRequest();
+
   // END: Server Callbacks.
 
   // Attempts to evenly adjust the target balloons at the target priority.
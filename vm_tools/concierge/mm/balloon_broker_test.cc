@@ -69,6 +69,8 @@ class BalloonBrokerTest : public ::testing::Test {
     no_kill_candidate_handler_ = fake_kills_server_->NoKillCandidateCallback();
 
     decision_latency_handler_ = fake_kills_server_->DecisionLatencyCallback();
+
+    balloon_sizes_handler_ = fake_kills_server_->BalloonSizesHandler();
   }
 
   void TearDown() override {
@@ -485,5 +487,20 @@ TEST_F(BalloonBrokerTest, HandleDecisionLatencyMetrics) {
   }
 }
 
+TEST_F(BalloonBrokerTest, TestHandleBalloonSizesRequest) {
+  balloon_broker_->RegisterVm(apps::VmType::UNKNOWN, 5, kTestSocket);
+  balloon_broker_->RegisterVm(apps::VmType::UNKNOWN, 6, kTestSocket);
+
+  FakeBalloonBlocker::fake_balloon_blockers_[5]->target_size_ = MiB(512);
+  FakeBalloonBlocker::fake_balloon_blockers_[6]->target_size_ = MiB(128);
+
+  base::flat_map<int, int64_t> sizes = balloon_sizes_handler_.Run();
+
+  // The host has no balloon, so only the VMs are reported.
+  ASSERT_EQ(sizes.size(), 2);
+  ASSERT_EQ(sizes[5], MiB(512));
+  ASSERT_EQ(sizes[6], MiB(128));
+}
+
 }  // namespace
 }  // namespace vm_tools::concierge::mm
@@ -37,6 +37,10 @@ int64_t FakeBalloonBlocker::TryResize(ResizeRequest request) {
   return result;
 }
 
+int64_t FakeBalloonBlocker::GetTargetSize() {
+  return target_size_;
+}
+
 void FakeBalloonBlocker::BlockAt(ResizeDirection direction,
                                  ResizePriority priority) {
   if (!blocks_.contains(direction)) {
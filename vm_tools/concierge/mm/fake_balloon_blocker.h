@@ -26,12 +26,15 @@ class FakeBalloonBlocker : public BalloonBlocker {
   ResizePriority LowestUnblockedPriority(
       ResizeDirection direction, base::TimeTicks check_time) const override;
 
+  int64_t GetTargetSize() override;
+
   int Cid();
 
   void BlockAt(ResizeDirection direction, ResizePriority priority);
 
   std::vector<ResizeRequest> resize_requests_;
   std::vector<int64_t> try_resize_results_;
+  int64_t target_size_ = 0;
   base::flat_map<ResizeDirection, base::flat_map<ResizePriority, bool>>
       blocks_{};
 };
@@ -32,4 +32,8 @@ FakeKillsServer::NoKillCandidateCallback() {
   return KillsServer::GetNoKillCandidateCallback();
 }
 
+const KillsServer::BalloonSizesHandler& FakeKillsServer::BalloonSizesHandler() {
+  return KillsServer::GetBalloonSizesHandler();
+}
+
 }  // namespace vm_tools::concierge::mm
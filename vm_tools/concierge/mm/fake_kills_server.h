@@ -22,6 +22,8 @@ class FakeKillsServer : public KillsServer {
   const KillRequestHandler& KillRequestHandler();
 
   const NoKillCandidateNotification& NoKillCandidateCallback();
+
+  const BalloonSizesHandler& BalloonSizesHandler();
 };
 
 }  // namespace vm_tools::concierge::mm
@@ -4,6 +4,11 @@
 
 #include "vm_tools/concierge/mm/kills_server.h"
 
+#include <sys/socket.h>
+
+// Needs to be included after sys/socket.h
+#include <linux/vm_sockets.h>
+
 #include <memory>
 #include <utility>
 
@@ -11,6 +16,8 @@
 
 #include "vm_tools/concierge/byte_unit.h"
 
+using vm_tools::vm_memory_management::BalloonSize;
+using vm_tools::vm_memory_management::BalloonSizesResponse;
 using vm_tools::vm_memory_management::DecisionLatency;
 using vm_tools::vm_memory_management::KillDecisionRequest;
 using vm_tools::vm_memory_management::KillDecisionResponse;
@@ -37,6 +44,11 @@ void KillsServer::SetDecisionLatencyNotification(
   decision_latency_callback_ = callback;
 }
 
+void KillsServer::SetBalloonSizesHandler(BalloonSizesHandler callback) {
+  DCHECK_CALLED_ON_VALID_SEQUENCE(sequence_checker_);
+  balloon_sizes_handler_ = callback;
+}
+
 const KillsServer::DecisionLatencyNotification&
 KillsServer::GetDecisionLatencyCallback() {
   return decision_latency_callback_;
@@ -51,6 +63,10 @@ KillsServer::GetNoKillCandidateCallback() {
   return no_kill_candiate_callback_;
 }
 
+const KillsServer::BalloonSizesHandler& KillsServer::GetBalloonSizesHandler() {
+  return balloon_sizes_handler_;
+}
+
 void KillsServer::HandlePacket(
     const Connection& connection,
     const VmMemoryManagementPacket& received_packet) {
@@ -62,6 +78,8 @@ void KillsServer::HandlePacket(
       return HandleNoKillCandidates(connection, received_packet);
     case PacketType::PACKET_TYPE_DECISION_LATENCY:
       return HandleDecisionLatency(connection, received_packet);
+    case PacketType::PACKET_TYPE_BALLOON_SIZES_REQUEST:
+      return HandleBalloonSizesRequest(connection);
     default:
       LOG(ERROR) << "Unknown command received from client: "
                  << connection.client.cid
@@ -126,4 +144,33 @@ void KillsServer::HandleDecisionLatency(
   decision_latency_callback_.Run(connection.client, packet.decision_latency());
 }
 
+void KillsServer::HandleBalloonSizesRequest(const Connection& connection) {
+  DCHECK_CALLED_ON_VALID_SEQUENCE(sequence_checker_);
+  // A VM must not learn about the balloons of other VMs.
+  if (connection.client.cid != VMADDR_CID_LOCAL) {
+    LOG(ERROR) << "Received balloon sizes request from VM CID: "
+               << connection.client.cid;
+    return;
+  }
+
+  VmMemoryManagementPacket reply_packet;
+  reply_packet.set_type(PacketType::PACKET_TYPE_BALLOON_SIZES_RESPONSE);
+
+  BalloonSizesResponse* balloon_sizes =
+      reply_packet.mutable_balloon_sizes_response();
+  if (balloon_sizes_handler_) {
+    for (const auto& [cid, target_size] : balloon_sizes_handler_.Run()) {
+      BalloonSize* balloon = balloon_sizes->add_balloons();
+      balloon->set_cid(cid);
+      // Client expects the sizes in KB units.
+      balloon->set_target_size_kb(target_size / KiB(1));
+    }
+  }
+
+  if (!connection.socket->WritePacket(reply_packet)) {
+    LOG(ERROR) << "Failed to write balloon sizes response.";
+    RemoveConnection(connection.client.connection_id);
+  }
+}
+
 }  // namespace vm_tools::concierge::mm
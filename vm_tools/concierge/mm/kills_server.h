@@ -8,6 +8,8 @@
 #include <memory>
 #include <vector>
 
+#include <base/containers/flat_map.h>
+
 #include "vm_tools/concierge/mm/server.h"
 
 using vm_tools::vm_memory_management::DecisionLatency;
@@ -44,6 +46,12 @@ class KillsServer : public Server {
       base::RepeatingCallback<void(Client, const DecisionLatency&)>;
   void SetDecisionLatencyNotification(DecisionLatencyNotification callback);
 
+  // Sets the callback that returns the target balloon size in bytes of each
+  // VM, keyed by CID.
+  using BalloonSizesHandler =
+      base::RepeatingCallback<base::flat_map<int, int64_t>()>;
+  void SetBalloonSizesHandler(BalloonSizesHandler callback);
+
   // END: Event Callbacks.
  protected:
   // Gets the decision latency handler for this server.
@@ -55,6 +63,9 @@ class KillsServer : public Server {
   // Gets the no kill candidates callback for this server.
   const NoKillCandidateNotification& GetNoKillCandidateCallback();
 
+  // Gets the balloon sizes handler for this server.
+  const BalloonSizesHandler& GetBalloonSizesHandler();
+
  private:
   // Performs implementation specific actions based on the received packet.
   void HandlePacket(const Connection& connection,
@@ -72,6 +83,9 @@ class KillsServer : public Server {
   void HandleDecisionLatency(const Connection& connection,
                              const VmMemoryManagementPacket& packet) const;
 
+  // Handles a balloon sizes request from a client.
+  void HandleBalloonSizesRequest(const Connection& connection);
+
   // Event Callbacks.
   KillRequestHandler kill_request_handler_
       GUARDED_BY_CONTEXT(sequence_checker_){};
@@ -79,6 +93,8 @@ class KillsServer : public Server {
       GUARDED_BY_CONTEXT(sequence_checker_) = base::DoNothing();
   DecisionLatencyNotification decision_latency_callback_
       GUARDED_BY_CONTEXT(sequence_checker_) = base::DoNothing();
+  BalloonSizesHandler balloon_sizes_handler_
+      GUARDED_BY_CONTEXT(sequence_checker_){};
 };
 
 }  // namespace vm_tools::concierge::mm
@@ -3,8 +3,12 @@
 // found in the LICENSE file.
 
 #include <fcntl.h>
+#include <sys/socket.h>
 #include <sys/stat.h>
 
+// Needs to be included after sys/socket.h
+#include <linux/vm_sockets.h>
+
 #include <utility>
 
 #include <gtest/gtest.h>
@@ -516,6 +520,8 @@ class KillsServerTest : public ServerTest {
         &KillsServerTest::OnNoKillCandidates, base::Unretained(this)));
     kills_server_->SetDecisionLatencyNotification(base::BindRepeating(
         &KillsServerTest::OnDecisionLatency, base::Unretained(this)));
+    kills_server_->SetBalloonSizesHandler(base::BindRepeating(
+        &KillsServerTest::HandleBalloonSizesRequest, base::Unretained(this)));
   }
 
  protected:
@@ -539,6 +545,10 @@ class KillsServerTest : public ServerTest {
     decision_latency_ = latency;
   }
 
+  base::flat_map<int, int64_t> HandleBalloonSizesRequest() {
+    return balloon_sizes_;
+  }
+
   std::unique_ptr<KillsServer> kills_server_{};
 
   int handle_kill_request_count_ = 0;
@@ -552,6 +562,8 @@ class KillsServerTest : public ServerTest {
   Client decision_latency_client_{};
   DecisionLatency decision_latency_{};
   int decision_latency_count_ = 0;
+
+  base::flat_map<int, int64_t> balloon_sizes_{};
 };
 
 TEST_F(KillsServerTest, TestMissingKillRequestFieldFails) {
@@ -690,5 +702,51 @@ TEST_F(KillsServerTest, TestDecisionLatencyIsReceivedProperly) {
   ASSERT_EQ(decision_latency_.latency_ms(), 44);
 }
 
+TEST_F(KillsServerTest, TestBalloonSizesRequestSendsCorrectResponse) {
+  AssertListeningSucceeds(*kills_server_);
+  ConnectNewClient(*kills_server_, VMADDR_CID_LOCAL,
+                   ConnectionType::CONNECTION_TYPE_KILLS);
+
+  FakeVmSocket* client_socket = leaked_client_sockets_.back();
+
+  VmMemoryManagementPacket request_packet;
+  request_packet.set_type(PacketType::PACKET_TYPE_BALLOON_SIZES_REQUEST);
+  client_socket->packet_to_read_ = request_packet;
+
+  balloon_sizes_[5] = MiB(512);
+  balloon_sizes_[6] = 0;
+
+  client_socket->on_readable_.Run();
+
+  ASSERT_EQ(client_socket->written_packet_.type(),
+            PacketType::PACKET_TYPE_BALLOON_SIZES_RESPONSE);
+  ASSERT_TRUE(client_socket->written_packet_.has_balloon_sizes_response());
+  const auto& balloons =
+      client_socket->written_packet_.balloon_sizes_response().balloons();
+  ASSERT_EQ(balloons.size(), 2);
+  ASSERT_EQ(balloons[0].cid(), 5);
+  ASSERT_EQ(balloons[0].target_size_kb(), 512 * 1024);
+  ASSERT_EQ(balloons[1].cid(), 6);
+  ASSERT_EQ(balloons[1].target_size_kb(), 0);
+}
+
+TEST_F(KillsServerTest, TestBalloonSizesRequestFromVmIsIgnored) {
+  AssertListeningSucceeds(*kills_server_);
+  ConnectNewClient(*kills_server_, 10, ConnectionType::CONNECTION_TYPE_KILLS);
+
+  FakeVmSocket* client_socket = leaked_client_sockets_.back();
+
+  VmMemoryManagementPacket request_packet;
+  request_packet.set_type(PacketType::PACKET_TYPE_BALLOON_SIZES_REQUEST);
+  client_socket->packet_to_read_ = request_packet;
+
+  balloon_sizes_[5] = MiB(512);
+
+  client_socket->on_readable_.Run();
+
+  ASSERT_FALSE(client_socket->written_packet_.has_balloon_sizes_response());
+  ASSERT_EQ(disconnected_count_, 0);
+}
+
 }  // namespace
 }  // namespace vm_tools::concierge::mm